@@ -0,0 +1,76 @@
+//! Calls to functions with many overloads, dispatched through compiled
+//! dispatch tables and by the linear scan with the dispatch cache.
+//!
+//! Run with `cargo bench --bench dispatch`. The overloads of `area` have no
+//! guards, so after the first call the scan's cache answers as directly as
+//! a table does, and both should stay flat as overloads are added. Those of
+//! `bucket` have guards, which the cache can't answer for: the scan
+//! evaluates every overload's guard on each call, where the table knows
+//! the guards exclude each other and stops at the first that holds.
+
+use relic::dispatch::Dispatch;
+use relic::evaluator::EvalValue;
+use relic::Engine;
+use std::time::{Duration, Instant};
+
+const CALLS: usize = 20_000;
+
+// `shapes` value types with an `area` overload each, and a `bucket`
+// function with `shapes` guarded overloads on Int
+fn declarations(shapes: usize) -> String {
+    let mut source = String::new();
+    for i in 0..shapes {
+        source.push_str(&format!("value Shape{}(size: Int) {{}}\n", i));
+        source.push_str(&format!("fn area(s: Shape{}) -> Int {{ s.size * {} }}\n", i, i + 1));
+        source.push_str(&format!(
+            "fn bucket(n: Int where n >= {} && n < {}) -> Int {{ {} }}\n",
+            i * 10,
+            (i + 1) * 10,
+            i
+        ));
+    }
+    source
+}
+
+// Make CALLS calls to `function` with `arguments` in turn
+fn run(engine: &Engine, function: &str, arguments: &[EvalValue]) -> Duration {
+    let start = Instant::now();
+    for argument in arguments.iter().cycle().take(CALLS) {
+        engine.call::<i64>(function, (argument.clone(),)).expect("benchmark calls dispatch");
+    }
+    start.elapsed()
+}
+
+// The time CALLS calls take, and what one call with each argument returns
+fn time(dispatch: Dispatch, shapes: usize, function: &str) -> (Duration, Vec<i64>) {
+    let mut engine = Engine::new().with_dispatch(dispatch);
+    engine.load(&declarations(shapes)).expect("benchmark declarations load");
+    let arguments: Vec<EvalValue> = match function {
+        "area" => (0..shapes).map(|i| engine.eval(&format!("Shape{}(2)", i)).expect("shapes construct")).collect(),
+        _ => (0..shapes as i64).map(|i| EvalValue::Integer(i * 10 + 5)).collect(),
+    };
+    let results = arguments
+        .iter()
+        .map(|argument| engine.call::<i64>(function, (argument.clone(),)).expect("benchmark calls dispatch"))
+        .collect();
+    // Best of a few runs, to keep noise out of the comparison
+    ((0..5).map(|_| run(&engine, function, &arguments)).min().unwrap(), results)
+}
+
+fn main() {
+    for function in ["area", "bucket"] {
+        for shapes in [4, 16, 64] {
+            let (table, expected) = time(Dispatch::Table, shapes, function);
+            let (scan, results) = time(Dispatch::Scan, shapes, function);
+            assert_eq!(results, expected, "both dispatch to the same overloads");
+            println!(
+                "{:>6} with {:>2} overloads: table {:>9.2?}, scan {:>9.2?} ({:.2}x)",
+                function,
+                shapes,
+                table,
+                scan,
+                scan.as_secs_f64() / table.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Construction throughput from several threads at once.
+//!
+//! Run with `cargo bench --bench instance_pool`. Each value type's instance
+//! pool has its own lock, so threads constructing different types should
+//! get through more constructions per second than threads that all
+//! construct the same type.
+
+use relic::Engine;
+use std::any::Any;
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 8;
+const PER_THREAD: i64 = 20_000;
+
+fn declarations() -> String {
+    (0..THREADS).map(|i| format!("value Count{}(n: Int) {{ validate: n >= 0 }}\n", i)).collect()
+}
+
+// Construct PER_THREAD values on each thread; with `shared`, all of them
+// of one type, otherwise each thread its own type
+fn run(shared: bool) -> Duration {
+    let mut engine = Engine::new();
+    engine.load(&declarations()).expect("benchmark declarations load");
+    let registry = engine.registry();
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for thread in 0..THREADS {
+            let type_name = if shared { "Count0".to_string() } else { format!("Count{}", thread) };
+            scope.spawn(move || {
+                for n in 0..PER_THREAD {
+                    let input: Box<dyn Any + Send + Sync> = Box::new(n);
+                    registry.construct(&type_name, input).expect("benchmark values construct");
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn time(shared: bool) -> Duration {
+    // Best of a few runs, to keep noise out of the comparison
+    (0..5).map(|_| run(shared)).min().unwrap()
+}
+
+fn main() {
+    let total = THREADS as f64 * PER_THREAD as f64;
+    for (label, shared) in [("one type", true), ("one type per thread", false)] {
+        let elapsed = time(shared);
+        println!(
+            "{:>20}: {} constructions on {} threads in {:>8.2?} ({:.0}/s)",
+            label,
+            total,
+            THREADS,
+            elapsed,
+            total / elapsed.as_secs_f64()
+        );
+    }
+}
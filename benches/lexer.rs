@@ -0,0 +1,60 @@
+//! Lexing throughput on a large generated source file.
+//!
+//! Run with `cargo bench --bench lexer`. Lexing should scale linearly, so
+//! the time per megabyte should stay flat as the file grows.
+
+use relic::lexer::{Lexer, Token};
+use std::time::{Duration, Instant};
+
+const DECLARATION: &str = "\
+// Email addresses are normalized to lower case — ünïcödé included
+value EmailAddress(raw: String) {
+    validate: raw contains \"@\" && raw.length() > 3
+    normalize: raw.toLowerCase()
+}
+
+fn score(x: Int, y: Int) -> Int {
+    let total = x * 2 + y % 7;
+    total |> abs
+}
+
+";
+
+fn source_of_size(bytes: usize) -> String {
+    DECLARATION.repeat(bytes / DECLARATION.len() + 1)
+}
+
+fn lex(source: &str) -> usize {
+    let mut lexer = Lexer::new(source.to_string());
+    let mut count = 0;
+    while lexer.next_token().expect("benchmark source lexes") != Token::Eof {
+        count += 1;
+    }
+    count
+}
+
+fn time(source: &str) -> (usize, Duration) {
+    // Best of a few runs, to keep noise out of the comparison
+    (0..5)
+        .map(|_| {
+            let start = Instant::now();
+            let tokens = lex(source);
+            (tokens, start.elapsed())
+        })
+        .min_by_key(|(_, elapsed)| *elapsed)
+        .unwrap()
+}
+
+fn main() {
+    for megabytes in [1, 4, 16] {
+        let source = source_of_size(megabytes << 20);
+        let (tokens, elapsed) = time(&source);
+        println!(
+            "{:>3} MB: {:>9} tokens in {:>8.2?} ({:.2?}/MB)",
+            megabytes,
+            tokens,
+            elapsed,
+            elapsed / megabytes as u32
+        );
+    }
+}
@@ -0,0 +1,43 @@
+/* C interface to the Relic engine; see src/ffi.rs.
+ *
+ * Functions that can fail return 0 or a pointer on success, and -1 or NULL
+ * on failure, after which relic_last_error and relic_last_error_code
+ * describe what went wrong. Strings are NUL-terminated UTF-8.
+ */
+#ifndef RELIC_H
+#define RELIC_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RelicEngine RelicEngine;
+
+/* Create an engine; free it with relic_engine_free. */
+RelicEngine *relic_engine_new(void);
+void relic_engine_free(RelicEngine *engine);
+
+/* Define the declarations in source and run its expressions. */
+int relic_load(RelicEngine *engine, const char *source);
+
+/* Evaluate an expression, returning its value as text, or NULL.
+ * Free the text with relic_string_free. */
+char *relic_eval(RelicEngine *engine, const char *source);
+
+/* Construct a value of type_name from its parameter as text ("42", "true",
+ * "12.50", "2024-01-31", "ada@example.com"), running the type's validation. */
+int relic_construct(RelicEngine *engine, const char *type_name, const char *value);
+
+/* The message and code (such as "E0301", or "E0601" for an evaluation
+ * cancelled through the engine's token) of the last call's error, or NULL
+ * if it succeeded. Valid until the next call on the engine. */
+const char *relic_last_error(const RelicEngine *engine);
+const char *relic_last_error_code(const RelicEngine *engine);
+
+void relic_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* RELIC_H */
@@ -0,0 +1,477 @@
+//! Questions asked of every instance of a value type at once:
+//! `exists(User, u => u.age > 65)` and `forall(...)`, and the aggregates
+//! `User.count(u => ...)`, `Order.sum(o => o.amount)`, `Order.min(...)` and
+//! `Order.max(...)`. Each visits the instance pool one instance at a time,
+//! oldest first, without collecting it into a list.
+//!
+//! For exploring data, `Order.mean(o => o.amount)`, `median` and `stddev`
+//! describe a number over the instances, `sample(User, 100)` picks some
+//! rows at random and `histogram(Order, o => o.amount, 10)` counts them in
+//! buckets. These keep the numbers, or the rows picked, as they go.
+
+use crate::ast::Expression;
+use crate::decimal::Decimal;
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::{evaluate_expression, instance_value, EvalValue};
+use crate::query::{Query, Source};
+use crate::relation::{Relation, Schema};
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// A method of a value type that folds a function of each instance into
+/// one result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    /// `T.count(x => p)`: the number of instances `p` holds for
+    Count,
+    /// `T.sum(x => n)`: the total of an Int over the instances
+    Sum,
+    /// `T.min(x => k)`: the least Int or String over the instances, or
+    /// None if there are none
+    Min,
+    /// `T.max(x => k)`: the greatest, likewise
+    Max,
+    /// `T.mean(x => n)`: the mean of an Int or Decimal over the instances,
+    /// as a Decimal, or None if there are none
+    Mean,
+    /// `T.median(x => n)`: the middle value, or the mean of the two middle
+    /// values, likewise
+    Median,
+    /// `T.stddev(x => n)`: the population standard deviation, likewise
+    Stddev,
+}
+
+impl Aggregate {
+    /// The aggregate a type method called `method` computes, if any.
+    pub fn of_method(method: &str) -> Option<Self> {
+        match method {
+            "count" => Some(Aggregate::Count),
+            "sum" => Some(Aggregate::Sum),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            "mean" => Some(Aggregate::Mean),
+            "median" => Some(Aggregate::Median),
+            "stddev" => Some(Aggregate::Stddev),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aggregate::Count => "count",
+            Aggregate::Sum => "sum",
+            Aggregate::Min => "min",
+            Aggregate::Max => "max",
+            Aggregate::Mean => "mean",
+            Aggregate::Median => "median",
+            Aggregate::Stddev => "stddev",
+        }
+    }
+
+    /// The types the function passed to the aggregate may return.
+    pub fn accepts(self) -> &'static [Type] {
+        match self {
+            Aggregate::Count => &[Type::Bool],
+            Aggregate::Sum => &[Type::Int],
+            Aggregate::Min | Aggregate::Max => &[Type::Int, Type::String],
+            Aggregate::Mean | Aggregate::Median | Aggregate::Stddev => &[Type::Int, Type::Decimal],
+        }
+    }
+
+    /// The type of the aggregate, given the type its function returns.
+    pub fn result_type(self, function: &Type) -> Type {
+        match self {
+            Aggregate::Count | Aggregate::Sum => Type::Int,
+            Aggregate::Min | Aggregate::Max => Type::Option(Box::new(function.clone())),
+            Aggregate::Mean | Aggregate::Median | Aggregate::Stddev => Type::Option(Box::new(Type::Decimal)),
+        }
+    }
+
+    /// Evaluate `T.aggregate(parameter => body)` over the instances of the
+    /// value type `type_name`.
+    pub fn evaluate(
+        self,
+        type_name: &str,
+        parameter: &str,
+        body: &Expression,
+        context: &HashMap<String, EvalValue>,
+        registry: &ValueRegistry,
+    ) -> Result<EvalValue> {
+        let mut count = 0i64;
+        let mut sum = 0i64;
+        let mut best: Option<EvalValue> = None;
+        let mut numbers = Vec::new();
+        each_instance(type_name, parameter, body, context, registry, |value| {
+            match (self, value) {
+                (Aggregate::Count, EvalValue::Boolean(holds)) => count += i64::from(holds),
+                (Aggregate::Sum, EvalValue::Integer(n)) => {
+                    sum = sum.checked_add(n).ok_or_else(|| {
+                        failure(format!("The sum over {} overflows an Int", type_name), type_name)
+                    })?;
+                }
+                (Aggregate::Min | Aggregate::Max, value) => {
+                    let wanted = if self == Aggregate::Min { Ordering::Less } else { Ordering::Greater };
+                    let replace = match &best {
+                        None => true,
+                        Some(current) => compare(&value, current, type_name)? == wanted,
+                    };
+                    if replace {
+                        best = Some(value);
+                    }
+                }
+                (Aggregate::Mean | Aggregate::Median | Aggregate::Stddev, EvalValue::Integer(n)) => {
+                    numbers.push(Decimal::from_int(n))
+                }
+                (Aggregate::Mean | Aggregate::Median | Aggregate::Stddev, EvalValue::Decimal(n)) => numbers.push(n),
+                (_, value) => {
+                    return Err(failure(
+                        format!("The function passed to {}.{} returned {}", type_name, self.name(), value),
+                        type_name,
+                    ))
+                }
+            }
+            Ok(true)
+        })?;
+
+        Ok(match self {
+            Aggregate::Count => EvalValue::Integer(count),
+            Aggregate::Sum => EvalValue::Integer(sum),
+            Aggregate::Min | Aggregate::Max => EvalValue::Option(best.map(Box::new)),
+            Aggregate::Mean | Aggregate::Median | Aggregate::Stddev => {
+                let statistic = if numbers.is_empty() {
+                    None
+                } else {
+                    let statistic = self.describe(numbers).ok_or_else(|| {
+                        failure(format!("The {} over {} overflows a Decimal", self.name(), type_name), type_name)
+                    })?;
+                    Some(Box::new(EvalValue::Decimal(statistic)))
+                };
+                EvalValue::Option(statistic)
+            }
+        })
+    }
+
+    // The mean, median or standard deviation of `numbers`, which aren't
+    // empty, or None if it overflows
+    fn describe(self, mut numbers: Vec<Decimal>) -> Option<Decimal> {
+        match self {
+            Aggregate::Median => {
+                numbers.sort();
+                let middle = numbers.len() / 2;
+                if numbers.len() % 2 == 1 {
+                    return Some(numbers[middle]);
+                }
+                numbers[middle - 1].checked_add(&numbers[middle])?.checked_div(&Decimal::from_int(2))
+            }
+            Aggregate::Stddev => {
+                let average = mean(&numbers)?;
+                let squares = numbers
+                    .iter()
+                    .map(|n| {
+                        let deviation = n.checked_sub(&average)?;
+                        deviation.checked_mul(&deviation)
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                mean(&squares)?.checked_sqrt()
+            }
+            _ => mean(&numbers),
+        }
+    }
+}
+
+fn mean(numbers: &[Decimal]) -> Option<Decimal> {
+    let sum = numbers.iter().try_fold(Decimal::from_int(0), |sum, n| sum.checked_add(n))?;
+    sum.checked_div(&Decimal::from_int(numbers.len() as i64))
+}
+
+/// `exists(T, x => p)`, or `forall(T, x => p)` if `exists` is false: whether
+/// `p` holds for some, or every, instance of the value type `T`, or row of
+/// a list or query. The scan stops at the first row that decides the
+/// answer. The pool has no index to consult, so a predicate that never
+/// decides it visits every instance.
+pub fn quantify(
+    exists: bool,
+    pool: &Expression,
+    parameter: &str,
+    predicate: &Expression,
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    let name = if exists { "exists" } else { "forall" };
+    let rows = Query::of(evaluate_expression(pool, context, registry)?, name)?;
+    let value_type = match rows.source() {
+        Source::Type(type_name) => type_name.as_str(),
+        Source::List(_) => "query",
+    };
+
+    let mut scope = context.clone();
+    let mut decided = false;
+    rows.for_each(registry, |row| {
+        scope.insert(parameter.to_string(), row);
+        match evaluate_expression(predicate, &scope, registry)? {
+            // `exists` is decided by the first row the predicate holds for,
+            // `forall` by the first it doesn't
+            EvalValue::Boolean(holds) if holds == exists => {
+                decided = true;
+                Ok(false)
+            }
+            EvalValue::Boolean(_) => Ok(true),
+            other => Err(failure(
+                format!("The predicate of {} evaluated to {}, not a Bool", name, other),
+                value_type,
+            )),
+        }
+    })?;
+    Ok(EvalValue::Boolean(decided == exists))
+}
+
+/// `sample(rows, size)`: `size` of the rows of a value type, list or query,
+/// picked at random, each set of them as likely as any other, in the order
+/// they came; or every row, if there are no more than `size`.
+pub fn sample(rows: EvalValue, size: EvalValue, registry: &ValueRegistry) -> Result<EvalValue> {
+    let rows = Query::of(rows, "sample")?;
+    let size = match size {
+        EvalValue::Integer(n) if n >= 0 => n as usize,
+        other => return Err(failure(format!("The size of a sample must be an Int of at least 0, not {}", other), "sample")),
+    };
+    // Reservoir sampling: the nth row replaces a kept one with chance
+    // size / n, so the rows are visited once without being collected
+    let mut random = Random::seeded();
+    let mut kept: Vec<(usize, EvalValue)> = Vec::new();
+    let mut seen = 0;
+    rows.for_each(registry, |row| {
+        if kept.len() < size {
+            kept.push((seen, row));
+        } else if let Some(slot) = kept.get_mut(random.below(seen + 1)) {
+            *slot = (seen, row);
+        }
+        seen += 1;
+        Ok(true)
+    })?;
+    kept.sort_by_key(|(index, _)| *index);
+    Ok(EvalValue::List(kept.into_iter().map(|(_, row)| row).collect()))
+}
+
+/// The most buckets `histogram` will count into.
+pub const MAX_BUCKETS: i64 = 1000;
+
+/// `histogram(rows, x => n, buckets)`: a relation of `from`, `to` and
+/// `count`, counting the rows whose Int `n` is in each of up to `buckets`
+/// ranges of equal width, from the least `n` to the greatest. Both ends of
+/// a range are in it. A range with no rows has a count of 0, and there are
+/// no ranges if there are no rows.
+pub fn histogram(
+    rows: &Expression,
+    parameter: &str,
+    body: &Expression,
+    buckets: &Expression,
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    let rows = Query::of(evaluate_expression(rows, context, registry)?, "histogram")?;
+    let buckets = match evaluate_expression(buckets, context, registry)? {
+        EvalValue::Integer(n) if (1..=MAX_BUCKETS).contains(&n) => i128::from(n),
+        other => {
+            return Err(failure(
+                format!("The buckets of a histogram must be an Int from 1 to {}, not {}", MAX_BUCKETS, other),
+                "histogram",
+            ))
+        }
+    };
+
+    let mut numbers = Vec::new();
+    let mut scope = context.clone();
+    rows.for_each(registry, |row| {
+        scope.insert(parameter.to_string(), row);
+        match evaluate_expression(body, &scope, registry)? {
+            EvalValue::Integer(n) => numbers.push(i128::from(n)),
+            other => {
+                return Err(failure(
+                    format!("The function passed to histogram returned {}, not an Int", other),
+                    "histogram",
+                ))
+            }
+        }
+        Ok(true)
+    })?;
+
+    let schema = Schema {
+        fields: ["from", "to", "count"].iter().map(|field| (field.to_string(), "Int".to_string())).collect(),
+    };
+    let mut relation = Relation::new(schema);
+    if let (Some(&least), Some(&greatest)) = (numbers.iter().min(), numbers.iter().max()) {
+        let span = greatest - least + 1;
+        // Rounded up, so that `buckets` ranges cover the span
+        let width = (span + buckets - 1) / buckets;
+        let mut counts = vec![0i64; ((span + width - 1) / width) as usize];
+        for n in numbers {
+            counts[((n - least) / width) as usize] += 1;
+        }
+        for (bucket, count) in counts.into_iter().enumerate() {
+            let from = least + bucket as i128 * width;
+            let to = (from + width - 1).min(greatest);
+            // Both ends are between two Ints, so they fit in one
+            relation = relation.add_values(vec![
+                EvalValue::Integer(from as i64),
+                EvalValue::Integer(to as i64),
+                EvalValue::Integer(count),
+            ])?;
+        }
+    }
+    Ok(EvalValue::Relation(relation))
+}
+
+// splitmix64, seeded from the operating system's randomness, which is
+// plenty for picking a sample to look at
+struct Random(u64);
+
+impl Random {
+    fn seeded() -> Random {
+        Random(uuid::Uuid::new_v4().as_u64_pair().0)
+    }
+
+    // A number below `n`, which must be positive
+    fn below(&mut self, n: usize) -> usize {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        ((u128::from(z) * n as u128) >> 64) as usize
+    }
+}
+
+/// Evaluate `body`, with `parameter` bound to each instance of `type_name`
+/// in turn, and pass the result to `visit`, until it returns false.
+fn each_instance(
+    type_name: &str,
+    parameter: &str,
+    body: &Expression,
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+    mut visit: impl FnMut(EvalValue) -> Result<bool>,
+) -> Result<()> {
+    let mut scope = context.clone();
+    for instance in registry.snapshot(type_name).iter() {
+        let instance = instance_value(type_name, instance.as_ref(), registry);
+        if !registry.is_visible(type_name, &instance) {
+            continue;
+        }
+        scope.insert(parameter.to_string(), instance);
+        if !visit(evaluate_expression(body, &scope, registry)?)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn compare(left: &EvalValue, right: &EvalValue, type_name: &str) -> Result<Ordering> {
+    match (left, right) {
+        (EvalValue::Integer(l), EvalValue::Integer(r)) => Ok(l.cmp(r)),
+        (EvalValue::String(l), EvalValue::String(r)) => Ok(l.cmp(r)),
+        _ => Err(failure(format!("Cannot compare {} with {}", left, right), type_name)),
+    }
+}
+
+fn failure(message: String, value_type: &str) -> Error {
+    Error::Validation(ValidationError {
+        message,
+        value_type: value_type.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::evaluator::EvalValue;
+
+    #[test]
+    fn test_quantifiers() {
+        let mut engine = Engine::new();
+        engine.load("value User(age: Int) {}").unwrap();
+        assert_eq!(engine.eval("exists(User, u => u.age > 65)").unwrap(), EvalValue::Boolean(false));
+        assert_eq!(engine.eval("forall(User, u => u.age > 65)").unwrap(), EvalValue::Boolean(true));
+
+        engine.load("User(70)\nUser(0)").unwrap();
+        assert_eq!(engine.eval("exists(User, u => u.age > 65)").unwrap(), EvalValue::Boolean(true));
+        assert_eq!(engine.eval("forall(User, u => u.age > 65)").unwrap(), EvalValue::Boolean(false));
+        // Both stop at User(70), before dividing by User(0)'s age
+        assert_eq!(engine.eval("exists(User, u => 100 / u.age < 5)").unwrap(), EvalValue::Boolean(true));
+        assert_eq!(engine.eval("forall(User, u => 100 / u.age > 5)").unwrap(), EvalValue::Boolean(false));
+
+        let error = engine.eval("exists(User, u => u.age)").unwrap_err();
+        assert!(error.to_string().contains("predicate of exists: expected `Bool`, found `Int`"), "{}", error);
+        assert!(engine.eval("forall(Int, n => true)").is_err());
+        assert!(engine.eval("exists(User, u => u.name == 1)").is_err());
+        assert!(engine.eval("let f = u => true in 1").is_err());
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let mut engine = Engine::new();
+        engine.load("value Order(amount: Int) {}\nvalue Sku(code: String) {}").unwrap();
+        assert_eq!(engine.eval("Order.sum(o => o.amount)").unwrap(), EvalValue::Integer(0));
+        assert_eq!(engine.eval("Order.max(o => o.amount)").unwrap(), EvalValue::Option(None));
+
+        engine.load("Order(30)\nOrder(70)\nOrder(5)\nSku(\"b\")\nSku(\"a\")").unwrap();
+        assert_eq!(engine.eval("Order.count(o => o.amount > 10)").unwrap(), EvalValue::Integer(2));
+        assert_eq!(engine.eval("Order.sum(o => o.amount * 2)").unwrap(), EvalValue::Integer(210));
+        assert_eq!(
+            engine.eval("Order.min(o => o.amount)").unwrap(),
+            EvalValue::Option(Some(Box::new(EvalValue::Integer(5))))
+        );
+        assert_eq!(engine.eval("Order.max(o => o.amount).getOrElse(0)").unwrap(), EvalValue::Integer(70));
+        assert_eq!(
+            engine.eval("Sku.min(s => s.code)").unwrap(),
+            EvalValue::Option(Some(Box::new(EvalValue::String("a".to_string()))))
+        );
+
+        let error = engine.eval("Order.sum(o => o.amount > 1)").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Type error: The function passed to Order.sum must return `Int`, found `Bool`"
+        );
+        let error = engine.eval("Order.max(o => o.amount > 1)").unwrap_err();
+        assert!(error.to_string().contains("must return `Int` or `String`"), "{}", error);
+        assert!(engine.eval("Order.count(o => o.amount)").is_err());
+        assert!(engine.eval("Order.sum(o => o.missing)").is_err());
+    }
+
+    #[test]
+    fn test_statistics() {
+        let mut engine = Engine::new();
+        engine.load("value Order(amount: Int) {}\nvalue Price(amount: Decimal) {}").unwrap();
+        assert_eq!(engine.eval("Order.mean(o => o.amount)").unwrap(), EvalValue::Option(None));
+        assert_eq!(engine.eval("histogram(Order, o => o.amount, 3).rows()").unwrap(), EvalValue::List(vec![]));
+
+        engine.load("Order(2)\nOrder(4)\nOrder(4)\nOrder(4)\nOrder(5)\nOrder(5)\nOrder(7)\nOrder(9)").unwrap();
+        let statistic = |method: &str| engine.eval(&format!("Order.{}(o => o.amount)", method)).unwrap().to_string();
+        assert_eq!(statistic("mean"), "Some(5)");
+        assert_eq!(statistic("median"), "Some(4.5)");
+        assert_eq!(statistic("stddev"), "Some(2)");
+        engine.load("Price(1.50)\nPrice(2.25)").unwrap();
+        assert_eq!(engine.eval("Price.mean(p => p.amount)").unwrap().to_string(), "Some(1.875)");
+        assert!(engine.eval("Order.mean(o => o.amount > 1)").unwrap_err().to_string().contains("must return `Int` or `Decimal`"));
+
+        // Ranges of width 3 from 2 to 9, both ends included
+        let histogram = engine.eval("histogram(Order, o => o.amount, 3)").unwrap();
+        let EvalValue::Relation(histogram) = histogram else { panic!("{}", histogram) };
+        let cells: Vec<Vec<String>> =
+            histogram.cells().iter().map(|row| row.iter().map(|cell| cell.to_string()).collect()).collect();
+        assert_eq!(cells, [["2", "4", "4"], ["5", "7", "3"], ["8", "9", "1"]]);
+        assert!(engine.eval("histogram(Order, o => o.amount, 0)").is_err());
+        assert!(engine.eval("histogram(Order, o => o.amount > 1, 3)").is_err());
+
+        // A sample keeps the order the rows came in
+        let EvalValue::List(picked) = engine.eval("sample(Order |> select(o => o.amount), 3)").unwrap() else { panic!() };
+        assert_eq!(picked.len(), 3);
+        let amounts: Vec<i64> = picked.iter().map(|n| if let EvalValue::Integer(n) = n { *n } else { panic!() }).collect();
+        assert!(amounts.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", amounts);
+        assert_eq!(engine.eval("sample(Order, 20)").unwrap(), engine.eval("all(Order)").unwrap());
+        assert_eq!(engine.eval("sample(Order, 0)").unwrap(), EvalValue::List(vec![]));
+        assert!(engine.eval("sample(Order, \"3\")").is_err());
+        assert!(engine.eval("sample(Order, 0 - 1)").is_err());
+    }
+}
@@ -0,0 +1,721 @@
+//! Compiled programs saved to disk, so that an embedder with a large
+//! schema can load it into a fresh `Engine` without parsing and type
+//! checking the source again.
+//!
+//! The format is a compact binary encoding of the declarations, the type
+//! environment they define and the specializations found for them. It
+//! starts with `RELIC` and a version number, and is only read back by the
+//! same version of the crate that wrote it.
+
+use crate::ast::*;
+use crate::error::{Error, ModuleError, Result};
+use crate::lexer::Span;
+use crate::specialization::SpecializedCall;
+use crate::decimal::Decimal;
+use crate::temporal::Temporal;
+use crate::types::{Constraints, FunctionType, Type, ValueType};
+use std::path::Path;
+
+const MAGIC: &[u8] = b"RELIC";
+const VERSION: u8 = 7;
+
+/// What compiling a program defines: its declarations, with the type
+/// environment they make up and the calls to them that dispatch statically.
+#[derive(Debug, Clone, Default)]
+pub struct CompiledProgram {
+    pub declarations: Vec<Declaration>,
+    /// Value types, as the type checker knows them
+    pub value_types: Vec<ValueType>,
+    /// Function and method signatures, as the type checker knows them
+    pub functions: Vec<FunctionType>,
+    pub specializations: Vec<SpecializedCall>,
+}
+
+impl CompiledProgram {
+    /// Add what `other` defines after what this defines, as for a module
+    /// and the program that imports it.
+    pub fn append(&mut self, mut other: CompiledProgram) {
+        self.declarations.append(&mut other.declarations);
+        self.value_types.append(&mut other.value_types);
+        self.functions.append(&mut other.functions);
+        self.specializations.append(&mut other.specializations);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer { bytes: MAGIC.to_vec() };
+        writer.bytes.push(VERSION);
+        self.declarations.encode(&mut writer);
+        self.value_types.encode(&mut writer);
+        self.functions.encode(&mut writer);
+        self.specializations.encode(&mut writer);
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            return Err(corrupt("not a compiled Relic program"));
+        };
+        match rest.first() {
+            Some(&VERSION) => {}
+            Some(version) => {
+                return Err(corrupt(&format!(
+                    "compiled by format version {}, but this is version {}",
+                    version, VERSION
+                )))
+            }
+            None => return Err(corrupt("truncated")),
+        }
+
+        let mut reader = Reader { bytes: &rest[1..] };
+        let program = Self {
+            declarations: Decode::decode(&mut reader)?,
+            value_types: Decode::decode(&mut reader)?,
+            functions: Decode::decode(&mut reader)?,
+            specializations: Decode::decode(&mut reader)?,
+        };
+        if !reader.bytes.is_empty() {
+            return Err(corrupt("unexpected data at the end"));
+        }
+        Ok(program)
+    }
+
+    /// Write the program to the file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_bytes()).map_err(|e| {
+            Error::Module(ModuleError::new(format!("Cannot write '{}': {}", path.display(), e)).with_source(e))
+        })
+    }
+
+    /// Read a program written by `save`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path).map_err(|e| {
+            Error::Module(ModuleError::new(format!("Cannot read '{}': {}", path.display(), e)).with_source(e))
+        })?;
+        Self::from_bytes(&bytes).map_err(|e| match e {
+            Error::Module(mut error) => {
+                error.message = format!("{} in '{}'", error.message, path.display());
+                Error::Module(error)
+            }
+            e => e,
+        })
+    }
+}
+
+fn corrupt(problem: &str) -> Error {
+    Error::Module(ModuleError::new(format!("Invalid compiled program: {}", problem)))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn tag(&mut self, tag: u8) {
+        self.bytes.push(tag);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        if self.bytes.len() < n {
+            return Err(corrupt("truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn tag(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+}
+
+fn unknown_tag<T>(what: &str, tag: u8) -> Result<T> {
+    Err(corrupt(&format!("unknown {} tag {}", what, tag)))
+}
+
+trait Encode {
+    fn encode(&self, w: &mut Writer);
+}
+
+trait Decode: Sized {
+    fn decode(r: &mut Reader) -> Result<Self>;
+}
+
+impl Encode for u64 {
+    fn encode(&self, w: &mut Writer) {
+        w.bytes.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u64 {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(u64::from_le_bytes(r.take(8)?.try_into().expect("8 bytes")))
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, w: &mut Writer) {
+        (*self as u64).encode(w)
+    }
+}
+
+impl Decode for usize {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        usize::try_from(u64::decode(r)?).map_err(|_| corrupt("length out of range"))
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, w: &mut Writer) {
+        (*self as u64).encode(w)
+    }
+}
+
+impl Decode for i64 {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(u64::decode(r)? as i64)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, w: &mut Writer) {
+        w.tag(*self as u8)
+    }
+}
+
+impl Decode for bool {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        match r.tag()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => unknown_tag("Bool", tag),
+        }
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, w: &mut Writer) {
+        self.len().encode(w);
+        w.bytes.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        let len = usize::decode(r)?;
+        let bytes = r.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| corrupt("string is not UTF-8"))
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, w: &mut Writer) {
+        self.len().encode(w);
+        for item in self {
+            item.encode(w);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        let len = usize::decode(r)?;
+        // Every item takes at least a byte, so a corrupt length can't make
+        // us allocate more than the input
+        if len > r.bytes.len() {
+            return Err(corrupt("truncated"));
+        }
+        (0..len).map(|_| T::decode(r)).collect()
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            None => w.tag(0),
+            Some(value) => {
+                w.tag(1);
+                value.encode(w);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        match r.tag()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(r)?)),
+            tag => unknown_tag("Option", tag),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Box<T> {
+    fn encode(&self, w: &mut Writer) {
+        (**self).encode(w)
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        T::decode(r).map(Box::new)
+    }
+}
+
+/// Implement `Encode` and `Decode` for a struct, field by field.
+macro_rules! record {
+    ($ty:ident { $($field:ident),* }) => {
+        impl Encode for $ty {
+            fn encode(&self, w: &mut Writer) {
+                $(self.$field.encode(w);)*
+            }
+        }
+
+        impl Decode for $ty {
+            fn decode(r: &mut Reader) -> Result<Self> {
+                Ok($ty { $($field: Decode::decode(r)?),* })
+            }
+        }
+    };
+}
+
+/// Implement `Encode` and `Decode` for an enum whose variants have no
+/// fields, with each variant's position as its tag.
+macro_rules! tags {
+    ($ty:ident { $($variant:ident),* }) => {
+        impl Encode for $ty {
+            fn encode(&self, w: &mut Writer) {
+                let variants = [$($ty::$variant),*];
+                let tag = variants.iter().position(|v| v == self).expect("every variant is listed");
+                w.tag(tag as u8)
+            }
+        }
+
+        impl Decode for $ty {
+            fn decode(r: &mut Reader) -> Result<Self> {
+                let variants = [$($ty::$variant),*];
+                let tag = r.tag()?;
+                match variants.into_iter().nth(tag as usize) {
+                    Some(variant) => Ok(variant),
+                    None => unknown_tag(stringify!($ty), tag),
+                }
+            }
+        }
+    };
+}
+
+record!(Span { offset, line, column, length });
+record!(ValueDeclaration { name, parameter, body, span });
+record!(FunctionDeclaration { name, parameters, return_type, body, span });
+record!(MethodDeclaration { name, parameters, return_type, body, span });
+record!(Parameter { name, ty, unit });
+record!(ParameterWithGuard { name, ty, guard });
+record!(ValueBody { validate, normalize, unique, display, sensitive, coerce, convert });
+record!(Argument { label, value });
+record!(MatchArm { pattern, body });
+record!(ValueType { name, parameter_name, parameter_type, unit, conversions, constraints, coerce });
+record!(Constraints { validate, normalize, unique });
+record!(FunctionType { name, parameter_names, parameter_types, return_type });
+record!(SpecializedCall { function_name, arg_types, target_function });
+
+tags!(BinaryOp { And, Or, Add, Subtract, Multiply, Divide, Modulo });
+tags!(UnaryOp { Not, Minus });
+tags!(ComparisonOp { Equal, NotEqual, Less, Greater, LessEqual, GreaterEqual, Contains });
+
+impl Encode for Declaration {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Declaration::Value(v) => {
+                w.tag(0);
+                v.encode(w);
+            }
+            Declaration::Function(f) => {
+                w.tag(1);
+                f.encode(w);
+            }
+            Declaration::Method(m) => {
+                w.tag(2);
+                m.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Declaration {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        match r.tag()? {
+            0 => Ok(Declaration::Value(Decode::decode(r)?)),
+            1 => Ok(Declaration::Function(Decode::decode(r)?)),
+            2 => Ok(Declaration::Method(Decode::decode(r)?)),
+            tag => unknown_tag("declaration", tag),
+        }
+    }
+}
+
+impl Encode for Type {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Type::String => w.tag(0),
+            Type::Int => w.tag(1),
+            Type::Bool => w.tag(2),
+            Type::Value(name) => {
+                w.tag(3);
+                name.encode(w);
+            }
+            Type::Any => w.tag(4),
+            Type::Unknown => w.tag(5),
+            Type::Type => w.tag(6),
+            Type::List(element) => {
+                w.tag(7);
+                element.encode(w);
+            }
+            Type::Option(inner) => {
+                w.tag(8);
+                inner.encode(w);
+            }
+            Type::Relation => w.tag(9),
+            Type::Date => w.tag(10),
+            Type::Time => w.tag(11),
+            Type::Duration => w.tag(12),
+            Type::Decimal => w.tag(13),
+            Type::Query(element) => {
+                w.tag(14);
+                element.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Type {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        match r.tag()? {
+            0 => Ok(Type::String),
+            1 => Ok(Type::Int),
+            2 => Ok(Type::Bool),
+            3 => Ok(Type::Value(Decode::decode(r)?)),
+            4 => Ok(Type::Any),
+            5 => Ok(Type::Unknown),
+            6 => Ok(Type::Type),
+            7 => Ok(Type::List(Decode::decode(r)?)),
+            8 => Ok(Type::Option(Decode::decode(r)?)),
+            9 => Ok(Type::Relation),
+            10 => Ok(Type::Date),
+            11 => Ok(Type::Time),
+            12 => Ok(Type::Duration),
+            13 => Ok(Type::Decimal),
+            14 => Ok(Type::Query(Decode::decode(r)?)),
+            tag => unknown_tag("type", tag),
+        }
+    }
+}
+
+impl Encode for Literal {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Literal::String(s) => {
+                w.tag(0);
+                s.encode(w);
+            }
+            Literal::Integer(n) => {
+                w.tag(1);
+                n.encode(w);
+            }
+            Literal::Boolean(b) => {
+                w.tag(2);
+                b.encode(w);
+            }
+            // As its ISO-8601 text
+            Literal::Temporal(t) => {
+                w.tag(3);
+                t.to_string().encode(w);
+            }
+            // As its digits, which keep its places
+            Literal::Decimal(d) => {
+                w.tag(4);
+                d.to_string().encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Literal {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        match r.tag()? {
+            0 => Ok(Literal::String(Decode::decode(r)?)),
+            1 => Ok(Literal::Integer(Decode::decode(r)?)),
+            2 => Ok(Literal::Boolean(Decode::decode(r)?)),
+            3 => {
+                let text: String = Decode::decode(r)?;
+                let literal = Temporal::parse(&text).map(Literal::Temporal);
+                literal.ok_or_else(|| corrupt(&format!("invalid date, time or duration {}", text)))
+            }
+            4 => {
+                let text: String = Decode::decode(r)?;
+                let literal = Decimal::parse(&text).map(Literal::Decimal);
+                literal.ok_or_else(|| corrupt(&format!("invalid decimal {}", text)))
+            }
+            tag => unknown_tag("literal", tag),
+        }
+    }
+}
+
+impl Encode for Pattern {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Pattern::Constructor(type_name, binding) => {
+                w.tag(0);
+                type_name.encode(w);
+                binding.encode(w);
+            }
+            Pattern::List(elements, rest) => {
+                w.tag(1);
+                elements.encode(w);
+                rest.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Pattern {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        match r.tag()? {
+            0 => Ok(Pattern::Constructor(Decode::decode(r)?, Decode::decode(r)?)),
+            1 => Ok(Pattern::List(Decode::decode(r)?, Decode::decode(r)?)),
+            tag => unknown_tag("pattern", tag),
+        }
+    }
+}
+
+impl Encode for Statement {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Statement::Let(name, value) => {
+                w.tag(0);
+                name.encode(w);
+                value.encode(w);
+            }
+            Statement::Expression(expr) => {
+                w.tag(1);
+                expr.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Statement {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        match r.tag()? {
+            0 => Ok(Statement::Let(Decode::decode(r)?, Decode::decode(r)?)),
+            1 => Ok(Statement::Expression(Decode::decode(r)?)),
+            tag => unknown_tag("statement", tag),
+        }
+    }
+}
+
+impl Encode for Expression {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Expression::Binary(op, left, right) => {
+                w.tag(0);
+                op.encode(w);
+                left.encode(w);
+                right.encode(w);
+            }
+            Expression::Unary(op, operand) => {
+                w.tag(1);
+                op.encode(w);
+                operand.encode(w);
+            }
+            Expression::Literal(literal) => {
+                w.tag(2);
+                literal.encode(w);
+            }
+            Expression::Identifier(name) => {
+                w.tag(3);
+                name.encode(w);
+            }
+            Expression::FunctionCall(name, args) => {
+                w.tag(4);
+                name.encode(w);
+                args.encode(w);
+            }
+            Expression::NamedCall(name, args) => {
+                w.tag(5);
+                name.encode(w);
+                args.encode(w);
+            }
+            Expression::MemberAccess(object, member) => {
+                w.tag(6);
+                object.encode(w);
+                member.encode(w);
+            }
+            Expression::MethodCall(object, name, args) => {
+                w.tag(7);
+                object.encode(w);
+                name.encode(w);
+                args.encode(w);
+            }
+            Expression::Comparison(op, left, right) => {
+                w.tag(8);
+                op.encode(w);
+                left.encode(w);
+                right.encode(w);
+            }
+            Expression::Pipeline(value, function) => {
+                w.tag(9);
+                value.encode(w);
+                function.encode(w);
+            }
+            Expression::Let(name, value, body) => {
+                w.tag(10);
+                name.encode(w);
+                value.encode(w);
+                body.encode(w);
+            }
+            Expression::Match(value, arms) => {
+                w.tag(11);
+                value.encode(w);
+                arms.encode(w);
+            }
+            Expression::TypeLiteral(name) => {
+                w.tag(12);
+                name.encode(w);
+            }
+            Expression::Block(statements, result) => {
+                w.tag(13);
+                statements.encode(w);
+                result.encode(w);
+            }
+            Expression::Lambda(parameter, body) => {
+                w.tag(14);
+                parameter.encode(w);
+                body.encode(w);
+            }
+            Expression::Relation(fields, rows) => {
+                w.tag(15);
+                fields.encode(w);
+                rows.encode(w);
+            }
+            Expression::OptionalAccess(object, member, args) => {
+                w.tag(16);
+                object.encode(w);
+                member.encode(w);
+                args.encode(w);
+            }
+            Expression::Coalesce(option, default) => {
+                w.tag(17);
+                option.encode(w);
+                default.encode(w);
+            }
+            Expression::Unwrap(option) => {
+                w.tag(18);
+                option.encode(w);
+            }
+            Expression::Comprehension(element, name, source, condition) => {
+                w.tag(19);
+                element.encode(w);
+                name.encode(w);
+                source.encode(w);
+                condition.encode(w);
+            }
+        }
+    }
+}
+
+impl Decode for Expression {
+    fn decode(r: &mut Reader) -> Result<Self> {
+        Ok(match r.tag()? {
+            0 => Expression::Binary(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?),
+            1 => Expression::Unary(Decode::decode(r)?, Decode::decode(r)?),
+            2 => Expression::Literal(Decode::decode(r)?),
+            3 => Expression::Identifier(Decode::decode(r)?),
+            4 => Expression::FunctionCall(Decode::decode(r)?, Decode::decode(r)?),
+            5 => Expression::NamedCall(Decode::decode(r)?, Decode::decode(r)?),
+            6 => Expression::MemberAccess(Decode::decode(r)?, Decode::decode(r)?),
+            7 => Expression::MethodCall(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?),
+            8 => Expression::Comparison(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?),
+            9 => Expression::Pipeline(Decode::decode(r)?, Decode::decode(r)?),
+            10 => Expression::Let(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?),
+            11 => Expression::Match(Decode::decode(r)?, Decode::decode(r)?),
+            12 => Expression::TypeLiteral(Decode::decode(r)?),
+            13 => Expression::Block(Decode::decode(r)?, Decode::decode(r)?),
+            14 => Expression::Lambda(Decode::decode(r)?, Decode::decode(r)?),
+            15 => Expression::Relation(Decode::decode(r)?, Decode::decode(r)?),
+            16 => Expression::OptionalAccess(Decode::decode(r)?, Decode::decode(r)?, Decode::decode(r)?),
+            17 => Expression::Coalesce(Decode::decode(r)?, Decode::decode(r)?),
+            18 => Expression::Unwrap(Decode::decode(r)?),
+            19 => Expression::Comprehension(
+                Decode::decode(r)?,
+                Decode::decode(r)?,
+                Decode::decode(r)?,
+                Decode::decode(r)?,
+            ),
+            tag => return unknown_tag("expression", tag),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    const SOURCE: &str = r#"
+value Email(raw: String) {
+    validate: raw contains "@" && !(raw == "@")
+    normalize: raw.toLowerCase()
+    unique: true
+}
+
+fn describe(n: Int where n < 0) -> String { "negative" }
+fn describe(n: Int) -> String {
+    let doubled = n * 2;
+    match Email("a@b.c") { Email(e) => "count: " }
+}
+fn emails(t: Type) -> List[Any] { all(t) }
+method shout(s: String) -> String { s.toUpperCase() }
+"#;
+
+    #[test]
+    fn test_round_trip() {
+        let compiled = Engine::new().compile(SOURCE).unwrap();
+        assert_eq!(compiled.declarations.len(), 5);
+        assert_eq!(compiled.functions.len(), 4);
+
+        let bytes = compiled.to_bytes();
+        let loaded = CompiledProgram::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.declarations, compiled.declarations);
+        assert_eq!(loaded.to_bytes(), bytes);
+
+        let mut engine = Engine::new();
+        engine.load_compiled(&loaded).unwrap();
+        assert_eq!(engine.type_of("describe(1)").unwrap(), Type::String);
+        assert_eq!(engine.eval_as::<String>("describe(-1)").unwrap(), "negative");
+        assert_eq!(engine.eval_as::<String>("shout(\"hi\")").unwrap(), "HI");
+        assert!(engine.eval("Email(\"nope\")").is_err());
+        assert!(engine.load_compiled(&loaded).is_err(), "Email is already defined");
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        let message = |bytes: &[u8]| CompiledProgram::from_bytes(bytes).unwrap_err().to_string();
+        assert!(message(b"not relic").contains("not a compiled Relic program"));
+        assert!(message(b"RELIC\x09").contains("format version 9"));
+
+        let bytes = Engine::new().compile(SOURCE).unwrap().to_bytes();
+        for len in [6, bytes.len() / 2, bytes.len() - 1] {
+            assert!(message(&bytes[..len]).contains("Invalid compiled program"));
+        }
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert!(message(&extra).contains("unexpected data at the end"));
+    }
+}
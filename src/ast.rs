@@ -1,8 +1,58 @@
+use crate::lexer::Span;
+use crate::decimal::Decimal;
+use crate::temporal::Temporal;
 use crate::types::Type;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
+    pub imports: Vec<Import>,
     pub declarations: Vec<Declaration>,
+    /// Expressions written at the top level of a script, in source order.
+    /// They run after every declaration in the file has been compiled.
+    pub expressions: Vec<TopLevelExpression>,
+    /// `test` declarations, which only `relic test` runs.
+    pub tests: Vec<TestDeclaration>,
+    /// `data` blocks, whose instances are constructed when the program is
+    /// compiled.
+    pub data: Vec<DataDeclaration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopLevelExpression {
+    pub expression: Expression,
+    pub span: Span,
+    /// `let name = expression`, without `in`: the name later script
+    /// expressions, and later REPL inputs, see the value by. Functions
+    /// don't see it.
+    pub binding: Option<String>,
+}
+
+/// `test "name" { body }`: a check that the program behaves as intended.
+/// It passes if its body evaluates without an error, such as a failed
+/// `assert`, and to anything but `false`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestDeclaration {
+    pub name: String,
+    pub body: Expression,
+    pub span: Span,
+}
+
+/// `data Type { Type(...), ... }`: instances of a value type to construct,
+/// with full validation, when the program is loaded. Each entry keeps its
+/// own span so that a failing one can be pointed at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataDeclaration {
+    pub type_name: String,
+    pub entries: Vec<TopLevelExpression>,
+    pub span: Span,
+}
+
+/// `import "path"`: bring the declarations of another source file into
+/// scope. The path is interpreted by the compiler's `ModuleResolver`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub path: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -12,11 +62,39 @@ pub enum Declaration {
     Method(MethodDeclaration),
 }
 
+impl Declaration {
+    pub fn name(&self) -> &str {
+        match self {
+            Declaration::Value(v) => &v.name,
+            Declaration::Function(f) => &f.name,
+            Declaration::Method(m) => &m.name,
+        }
+    }
+
+    /// Source range covered by the declaration, from its keyword to its closing brace.
+    pub fn span(&self) -> Span {
+        match self {
+            Declaration::Value(v) => v.span,
+            Declaration::Function(f) => f.span,
+            Declaration::Method(m) => m.span,
+        }
+    }
+
+    pub fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Declaration::Value(v) => &mut v.span,
+            Declaration::Function(f) => &mut f.span,
+            Declaration::Method(m) => &mut m.span,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ValueDeclaration {
     pub name: String,
     pub parameter: Parameter,
     pub body: ValueBody,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -25,6 +103,7 @@ pub struct FunctionDeclaration {
     pub parameters: Vec<ParameterWithGuard>,
     pub return_type: Type,
     pub body: Expression,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -33,6 +112,20 @@ pub struct MethodDeclaration {
     pub parameters: Vec<ParameterWithGuard>,
     pub return_type: Type,
     pub body: Expression,
+    pub span: Span,
+}
+
+/// A method is checked, stored and dispatched as the function it is.
+impl From<MethodDeclaration> for FunctionDeclaration {
+    fn from(method: MethodDeclaration) -> Self {
+        FunctionDeclaration {
+            name: method.name,
+            parameters: method.parameters,
+            return_type: method.return_type,
+            body: method.body,
+            span: method.span,
+        }
+    }
 }
 
 
@@ -40,6 +133,9 @@ pub struct MethodDeclaration {
 pub struct Parameter {
     pub name: String,
     pub ty: Type,
+    /// The unit of a value type's `Int` or `Decimal` parameter, as in
+    /// `x: Decimal unit "km"`; see `TypeChecker::unit_of`
+    pub unit: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -54,6 +150,18 @@ pub struct ValueBody {
     pub validate: Option<Expression>,
     pub normalize: Option<Expression>,
     pub unique: Option<bool>,
+    /// The `display:` template, which instances are shown with; see
+    /// `template::Template`
+    pub display: Option<String>,
+    /// `sensitive: true` keeps the parameter out of how instances are
+    /// shown, and out of error messages
+    pub sensitive: Option<bool>,
+    /// `coerce: true` lets a bare parameter stand for an instance where the
+    /// type is expected: `f(30)` for `f(a: Age)` constructs `Age(30)`
+    pub coerce: Option<bool>,
+    /// `convert: fromMiles` names the functions that may construct the
+    /// type from a measure in another unit; see `TypeChecker::unit_of`
+    pub convert: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -63,6 +171,7 @@ pub enum Expression {
     Literal(Literal),
     Identifier(String),
     FunctionCall(String, Vec<Expression>),
+    NamedCall(String, Vec<Argument>), // call with at least one labelled argument
     MemberAccess(Box<Expression>, String),
     MethodCall(Box<Expression>, String, Vec<Expression>),
     Comparison(ComparisonOp, Box<Expression>, Box<Expression>),
@@ -70,6 +179,172 @@ pub enum Expression {
     Let(String, Box<Expression>, Box<Expression>), // let name = value in body
     Match(Box<Expression>, Vec<MatchArm>),
     TypeLiteral(String), // Type literals for Type-as-Relation (e.g., User as a Type value)
+    Block(Vec<Statement>, Box<Expression>), // { statement; ...; result }
+    Lambda(String, Box<Expression>), // x => body, only as an argument of a builtin that takes one
+    Relation(Vec<Parameter>, Vec<Vec<Expression>>), // Relation(name: String, ...) { ("bob", ...), ... }
+    OptionalAccess(Box<Expression>, String, Option<Vec<Expression>>), // option?.member, or option?.method(args)
+    Coalesce(Box<Expression>, Box<Expression>), // option ?? default
+    Unwrap(Box<Expression>), // option!
+    // [element for name in source if condition]
+    Comprehension(Box<Expression>, String, Box<Expression>, Option<Box<Expression>>),
+}
+
+impl Expression {
+    /// Whether the tree is more than `limit` levels deep. It looks no more
+    /// than `limit` levels down, so it can't itself exhaust the stack on a
+    /// tree too deep to check or evaluate.
+    pub fn deeper_than(&self, limit: usize) -> bool {
+        let Some(limit) = limit.checked_sub(1) else {
+            return true;
+        };
+        let deeper = |expr: &Expression| expr.deeper_than(limit);
+        match self {
+            Expression::Literal(_) | Expression::Identifier(_) | Expression::TypeLiteral(_) => false,
+            Expression::Binary(_, left, right)
+            | Expression::Comparison(_, left, right)
+            | Expression::Pipeline(left, right)
+            | Expression::Coalesce(left, right)
+            | Expression::Let(_, left, right) => deeper(left) || deeper(right),
+            Expression::Unary(_, operand)
+            | Expression::MemberAccess(operand, _)
+            | Expression::Unwrap(operand)
+            | Expression::Lambda(_, operand) => deeper(operand),
+            Expression::FunctionCall(_, args) => args.iter().any(deeper),
+            Expression::NamedCall(_, args) => args.iter().any(|arg| deeper(&arg.value)),
+            Expression::MethodCall(object, _, args) => deeper(object) || args.iter().any(deeper),
+            Expression::OptionalAccess(object, _, args) => deeper(object) || args.iter().flatten().any(deeper),
+            Expression::Match(scrutinee, arms) => deeper(scrutinee) || arms.iter().any(|arm| deeper(&arm.body)),
+            Expression::Block(statements, result) => {
+                statements.iter().any(|statement| match statement {
+                    Statement::Let(_, expr) | Statement::Expression(expr) => deeper(expr),
+                }) || deeper(result)
+            }
+            Expression::Relation(_, rows) => rows.iter().flatten().any(deeper),
+            Expression::Comprehension(element, _, source, condition) => {
+                deeper(element) || deeper(source) || condition.as_deref().is_some_and(deeper)
+            }
+        }
+    }
+}
+
+/// The name `option?.member` binds the option's value to while the member
+/// is read. It can't be written in source, so it never shadows a variable.
+pub const UNWRAPPED: &str = "?";
+
+/// The access of `option?.member`, or `option?.method(args)`, made on the
+/// option's value, bound to `UNWRAPPED`.
+pub fn unwrapped_access(member: &str, args: &Option<Vec<Expression>>) -> Expression {
+    let value = Box::new(Expression::Identifier(UNWRAPPED.to_string()));
+    match args {
+        Some(args) => Expression::MethodCall(value, member.to_string(), args.clone()),
+        None => Expression::MemberAccess(value, member.to_string()),
+    }
+}
+
+/// The name the left side of `left |> f(...)` is bound to while `f` is
+/// called. Like `UNWRAPPED`, it can't be written in source.
+pub const PIPED: &str = "|>";
+
+/// The call `left |> right` makes: `right`, a function name or call, with
+/// `left` as its first argument. None if `right` isn't one.
+pub fn piped(left: Expression, right: &Expression) -> Option<Expression> {
+    match right {
+        Expression::Identifier(name) => Some(Expression::FunctionCall(name.clone(), vec![left])),
+        Expression::FunctionCall(name, args) => {
+            let mut args = args.clone();
+            args.insert(0, left);
+            Some(Expression::FunctionCall(name.clone(), args))
+        }
+        Expression::NamedCall(name, args) => {
+            let mut args = args.clone();
+            args.insert(0, Argument { label: None, value: left });
+            Some(Expression::NamedCall(name.clone(), args))
+        }
+        _ => None,
+    }
+}
+
+/// The query `[element for name in source if condition]` stands for:
+/// `source |> where(name => condition) |> select(name => element)`,
+/// materialized into a list.
+pub fn comprehension(element: &Expression, name: &str, source: &Expression, condition: Option<&Expression>) -> Expression {
+    let lambda = |body: &Expression| Expression::Lambda(name.to_string(), Box::new(body.clone()));
+    let rows = match condition {
+        Some(condition) => Expression::FunctionCall("where".to_string(), vec![source.clone(), lambda(condition)]),
+        None => source.clone(),
+    };
+    let selected = Expression::FunctionCall("select".to_string(), vec![rows, lambda(element)]);
+    Expression::FunctionCall("materialize".to_string(), vec![selected])
+}
+
+/// A statement in a block, run for its bindings or side effects.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Let(String, Expression), // let name = value;
+    Expression(Expression),  // expression;
+}
+
+/// An argument at a call site, optionally labelled with the name of the
+/// parameter it is for: `createUser(name: "bob", age: 30)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Argument {
+    pub label: Option<String>,
+    pub value: Expression,
+}
+
+/// Match call arguments to parameters: positional arguments fill parameters
+/// from the left, labelled arguments fill the parameter with that name.
+/// Returns, for each parameter, the index of the argument bound to it, or
+/// None if the arguments don't fit (wrong count, unknown label, or a
+/// parameter given twice).
+pub fn bind_arguments<S: AsRef<str>>(parameters: &[S], arguments: &[Argument]) -> Option<Vec<usize>> {
+    if parameters.len() != arguments.len() {
+        return None;
+    }
+
+    let mut bound = vec![None; parameters.len()];
+    for (i, argument) in arguments.iter().enumerate() {
+        let index = match &argument.label {
+            Some(label) => parameters.iter().position(|p| p.as_ref() == label)?,
+            None => i,
+        };
+        if bound[index].replace(i).is_some() {
+            return None;
+        }
+    }
+
+    bound.into_iter().collect()
+}
+
+/// If `name(args)` calls the `exists` or `forall` builtin, its pool, the
+/// parameter of its predicate and the predicate's body.
+pub fn quantifier<'a>(
+    name: &str,
+    args: &'a [Expression],
+) -> Option<(&'a Expression, &'a str, &'a Expression)> {
+    match (name, args) {
+        ("exists" | "forall", [pool, Expression::Lambda(parameter, predicate)]) => {
+            Some((pool, parameter, predicate))
+        }
+        _ => None,
+    }
+}
+
+/// The builtins that add a step to a query, each taking its rows and a
+/// function of a row.
+pub const QUERY_STEPS: &[&str] = &["where", "select", "sortBy", "running", "rank", "lag", "lead"];
+
+/// If `name(args)` is a step of a query, such as `where(rows, x => p)` or
+/// `select(rows, x => e)`, its rows, the parameter of its function and the
+/// function's body.
+pub fn query_step<'a>(
+    name: &str,
+    args: &'a [Expression],
+) -> Option<(&'a Expression, &'a str, &'a Expression)> {
+    match args {
+        [rows, Expression::Lambda(parameter, body)] if QUERY_STEPS.contains(&name) => Some((rows, parameter, body)),
+        _ => None,
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -81,6 +356,10 @@ pub struct MatchArm {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Pattern {
     Constructor(String, String), // ValueType(binding)
+    /// `[]`, `[a, b]` or `[head, ...rest]`: a list of exactly the bound
+    /// elements, or of at least them when there is a rest binding, which
+    /// takes the elements after them
+    List(Vec<String>, Option<String>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -116,4 +395,6 @@ pub enum Literal {
     String(String),
     Integer(i64),
     Boolean(bool),
+    Temporal(Temporal),
+    Decimal(Decimal),
 }
@@ -0,0 +1,532 @@
+//! JSON export of the syntax tree, for tools that analyze Relic code
+//! without linking the crate.
+//!
+//! Every node is an object with a `kind` field. Declarations and top-level
+//! script expressions carry a `span` (`offset`, `line`, `column` and
+//! `length`, in characters); operators and types are written as they appear
+//! in source.
+//!
+//! `program_to_outline` shows the same tree indented, one node per line,
+//! for people reading it: the REPL's `:ast`.
+
+use crate::ast::*;
+use crate::diagnostic::json_string;
+use crate::formatter::{binary_token, comparison_token, format_type};
+use crate::json::Json;
+use crate::lexer::{Span, Token};
+use crate::types::Type;
+
+/// Serialize a parsed program as a single line of JSON.
+pub fn program_to_json(program: &Program) -> String {
+    let mut out = String::new();
+    write(&program_tree(program), &mut out);
+    out
+}
+
+/// Serialize a single expression.
+pub fn expression_to_json(expr: &Expression) -> String {
+    let mut out = String::new();
+    write(&expression(expr), &mut out);
+    out
+}
+
+fn program_tree(program: &Program) -> Json {
+    let declarations = list(program.declarations.iter().map(declaration));
+    let expressions = list(program.expressions.iter().map(|e| {
+        object([
+            ("expression", expression(&e.expression)),
+            ("binding", e.binding.as_deref().map_or(Json::Null, string)),
+            ("span", span(e.span)),
+        ])
+    }));
+
+    let imports = list(program.imports.iter().map(|i| {
+        object([("path", string(&i.path)), ("span", span(i.span))])
+    }));
+
+    let tests = list(program.tests.iter().map(|t| {
+        object([
+            ("name", string(&t.name)),
+            ("body", expression(&t.body)),
+            ("span", span(t.span)),
+        ])
+    }));
+
+    let data = list(program.data.iter().map(|d| {
+        let entries = list(d.entries.iter().map(|e| {
+            object([
+                ("expression", expression(&e.expression)),
+                ("span", span(e.span)),
+            ])
+        }));
+        object([
+            ("type", string(&d.type_name)),
+            ("entries", entries),
+            ("span", span(d.span)),
+        ])
+    }));
+
+    object([
+        ("imports", imports),
+        ("declarations", declarations),
+        ("expressions", expressions),
+        ("tests", tests),
+        ("data", data),
+    ])
+}
+
+fn expression(expr: &Expression) -> Json {
+    match expr {
+        Expression::Binary(op, left, right) => object([
+            ("kind", string("binary")),
+            ("op", string(&binary_token(op).to_string())),
+            ("left", expression(left)),
+            ("right", expression(right)),
+        ]),
+        Expression::Unary(op, operand) => {
+            let op = match op {
+                UnaryOp::Not => Token::Not,
+                UnaryOp::Minus => Token::Minus,
+            };
+            object([
+                ("kind", string("unary")),
+                ("op", string(&op.to_string())),
+                ("operand", expression(operand)),
+            ])
+        }
+        Expression::Literal(literal) => {
+            let (ty, value) = match literal {
+                Literal::String(s) => ("string", string(s)),
+                Literal::Integer(n) => ("integer", Json::Number(n.to_string())),
+                Literal::Boolean(b) => ("boolean", Json::Bool(*b)),
+                Literal::Temporal(t) => (t.type_name(), string(&t.to_string())),
+                // As a string, since a JSON number may lose digits
+                Literal::Decimal(d) => ("decimal", string(&d.to_string())),
+            };
+            object([("kind", string("literal")), ("type", string(ty)), ("value", value)])
+        }
+        Expression::Identifier(name) => {
+            object([("kind", string("identifier")), ("name", string(name))])
+        }
+        Expression::FunctionCall(name, args) => call(
+            name,
+            args.iter().map(|value| argument(None, value)),
+        ),
+        Expression::NamedCall(name, args) => call(
+            name,
+            args.iter().map(|a| argument(a.label.as_deref(), &a.value)),
+        ),
+        Expression::MemberAccess(object_expr, member) => object([
+            ("kind", string("member_access")),
+            ("object", expression(object_expr)),
+            ("member", string(member)),
+        ]),
+        Expression::MethodCall(receiver, method, args) => object([
+            ("kind", string("method_call")),
+            ("receiver", expression(receiver)),
+            ("method", string(method)),
+            ("arguments", list(args.iter().map(expression))),
+        ]),
+        Expression::Comparison(op, left, right) => object([
+            ("kind", string("comparison")),
+            ("op", string(&comparison_token(op).to_string())),
+            ("left", expression(left)),
+            ("right", expression(right)),
+        ]),
+        Expression::Pipeline(value, function) => object([
+            ("kind", string("pipeline")),
+            ("value", expression(value)),
+            ("function", expression(function)),
+        ]),
+        Expression::Let(name, value, body) => object([
+            ("kind", string("let")),
+            ("name", string(name)),
+            ("value", expression(value)),
+            ("body", expression(body)),
+        ]),
+        Expression::Match(scrutinee, arms) => object([
+            ("kind", string("match")),
+            ("scrutinee", expression(scrutinee)),
+            ("arms", list(arms.iter().map(match_arm))),
+        ]),
+        Expression::TypeLiteral(name) => {
+            object([("kind", string("type_literal")), ("name", string(name))])
+        }
+        Expression::Block(statements, result) => object([
+            ("kind", string("block")),
+            ("statements", list(statements.iter().map(statement))),
+            ("result", expression(result)),
+        ]),
+        Expression::Relation(fields, rows) => object([
+            ("kind", string("relation")),
+            (
+                "fields",
+                list(fields.iter().map(|f| object([("name", string(&f.name)), ("type", ty(&f.ty))]))),
+            ),
+            ("rows", list(rows.iter().map(|row| list(row.iter().map(expression))))),
+        ]),
+        Expression::Lambda(parameter, body) => object([
+            ("kind", string("lambda")),
+            ("parameter", string(parameter)),
+            ("body", expression(body)),
+        ]),
+        // A member access has no arguments, where a method call's may be empty
+        Expression::OptionalAccess(option, member, args) => object([
+            ("kind", string("optional_access")),
+            ("option", expression(option)),
+            ("member", string(member)),
+            (
+                "arguments",
+                args.as_ref().map_or(Json::Null, |args| list(args.iter().map(expression))),
+            ),
+        ]),
+        Expression::Coalesce(option, default) => object([
+            ("kind", string("coalesce")),
+            ("option", expression(option)),
+            ("default", expression(default)),
+        ]),
+        Expression::Unwrap(option) => object([
+            ("kind", string("unwrap")),
+            ("option", expression(option)),
+        ]),
+        Expression::Comprehension(element, name, source, condition) => object([
+            ("kind", string("comprehension")),
+            ("element", expression(element)),
+            ("name", string(name)),
+            ("source", expression(source)),
+            ("condition", optional(condition.as_deref())),
+        ]),
+    }
+}
+
+fn declaration(declaration: &Declaration) -> Json {
+    match declaration {
+        Declaration::Value(v) => object([
+            ("kind", string("value")),
+            ("name", string(&v.name)),
+            (
+                "parameter",
+                object([
+                    ("name", string(&v.parameter.name)),
+                    ("type", ty(&v.parameter.ty)),
+                    ("unit", v.parameter.unit.as_deref().map_or(Json::Null, string)),
+                ]),
+            ),
+            ("validate", optional(v.body.validate.as_ref())),
+            ("normalize", optional(v.body.normalize.as_ref())),
+            ("unique", v.body.unique.map_or(Json::Null, Json::Bool)),
+            ("display", v.body.display.as_deref().map_or(Json::Null, string)),
+            ("sensitive", v.body.sensitive.map_or(Json::Null, Json::Bool)),
+            ("coerce", v.body.coerce.map_or(Json::Null, Json::Bool)),
+            ("convert", list(v.body.convert.iter().map(|name| string(name)))),
+            ("span", span(v.span)),
+        ]),
+        Declaration::Function(f) => function(
+            "function",
+            &f.name,
+            &f.parameters,
+            &f.return_type,
+            &f.body,
+            f.span,
+        ),
+        Declaration::Method(m) => function(
+            "method",
+            &m.name,
+            &m.parameters,
+            &m.return_type,
+            &m.body,
+            m.span,
+        ),
+    }
+}
+
+fn function(
+    kind: &str,
+    name: &str,
+    parameters: &[ParameterWithGuard],
+    return_type: &Type,
+    body: &Expression,
+    declaration_span: Span,
+) -> Json {
+    let parameters = list(parameters.iter().map(|p| {
+        object([
+            ("name", string(&p.name)),
+            ("type", ty(&p.ty)),
+            ("guard", optional(p.guard.as_ref())),
+        ])
+    }));
+
+    object([
+        ("kind", string(kind)),
+        ("name", string(name)),
+        ("parameters", parameters),
+        ("return_type", ty(return_type)),
+        ("body", expression(body)),
+        ("span", span(declaration_span)),
+    ])
+}
+
+fn call(name: &str, arguments: impl Iterator<Item = Json>) -> Json {
+    object([
+        ("kind", string("call")),
+        ("function", string(name)),
+        ("arguments", list(arguments)),
+    ])
+}
+
+fn argument(label: Option<&str>, value: &Expression) -> Json {
+    object([
+        ("label", label.map_or(Json::Null, string)),
+        ("value", expression(value)),
+    ])
+}
+
+fn match_arm(arm: &MatchArm) -> Json {
+    let pattern = match &arm.pattern {
+        Pattern::Constructor(type_name, binding) => object([
+            ("kind", string("constructor")),
+            ("type", string(type_name)),
+            ("binding", string(binding)),
+        ]),
+        Pattern::List(elements, rest) => object([
+            ("kind", string("list")),
+            ("elements", list(elements.iter().map(|element| string(element)))),
+            ("rest", rest.as_deref().map_or(Json::Null, string)),
+        ]),
+    };
+    object([("pattern", pattern), ("body", expression(&arm.body))])
+}
+
+fn statement(statement: &Statement) -> Json {
+    match statement {
+        Statement::Let(name, value) => object([
+            ("kind", string("let")),
+            ("name", string(name)),
+            ("value", expression(value)),
+        ]),
+        Statement::Expression(expr) => object([
+            ("kind", string("expression")),
+            ("expression", expression(expr)),
+        ]),
+    }
+}
+
+/// The syntax tree of `program` as an indented outline: each node on a
+/// line of its own, with its kind and the fields that aren't nodes, and the
+/// nodes it contains indented below it under the name of their field.
+/// Spans, empty lists and absent parts are left out.
+pub fn program_to_outline(program: &Program) -> String {
+    let mut out = String::new();
+    outline(&program_tree(program), "program", 0, &mut out);
+    out
+}
+
+fn outline(node: &Json, label: &str, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    match node {
+        Json::Object(fields) => {
+            out.push_str(&format!("{}{}", indent, label));
+            let kind = fields.iter().find_map(|(name, value)| match value {
+                Json::String(kind) if name == "kind" => Some(kind),
+                _ => None,
+            });
+            match kind {
+                // An element of a list
+                Some(kind) if label == "-" => out.push_str(&format!(" {}", kind)),
+                Some(kind) => out.push_str(&format!(": {}", kind)),
+                None => {}
+            }
+            let shown = fields.iter().filter(|(name, value)| name != "kind" && name != "span" && !absent(value));
+            let (nodes, scalars): (Vec<_>, Vec<_>) =
+                shown.partition(|(_, value)| matches!(value, Json::Object(_) | Json::Array(_)));
+            for (name, value) in scalars {
+                out.push_str(&format!(" {}={}", name, scalar(value)));
+            }
+            out.push('\n');
+            for (name, value) in nodes {
+                outline(value, name, depth + 1, out);
+            }
+        }
+        Json::Array(items) => {
+            out.push_str(&format!("{}{}:\n", indent, label));
+            for item in items {
+                outline(item, "-", depth + 1, out);
+            }
+        }
+        value => out.push_str(&format!("{}{} {}\n", indent, label, scalar(value))),
+    }
+}
+
+// Whether a field has nothing to show
+fn absent(value: &Json) -> bool {
+    match value {
+        Json::Null => true,
+        Json::Array(items) => items.is_empty(),
+        _ => false,
+    }
+}
+
+fn scalar(value: &Json) -> String {
+    match value {
+        Json::String(s) => json_string(s),
+        Json::Number(n) => n.clone(),
+        Json::Bool(b) => b.to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+fn span(span: Span) -> Json {
+    object([
+        ("offset", Json::Number(span.offset.to_string())),
+        ("line", Json::Number(span.line.to_string())),
+        ("column", Json::Number(span.column.to_string())),
+        ("length", Json::Number(span.length.to_string())),
+    ])
+}
+
+fn ty(ty: &Type) -> Json {
+    string(&format_type(ty))
+}
+
+fn optional(expr: Option<&Expression>) -> Json {
+    expr.map_or(Json::Null, expression)
+}
+
+fn string(s: &str) -> Json {
+    Json::String(s.to_string())
+}
+
+fn object<const N: usize>(fields: [(&str, Json); N]) -> Json {
+    Json::Object(fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+}
+
+fn list(items: impl Iterator<Item = Json>) -> Json {
+    Json::Array(items.collect())
+}
+
+// Compactly, with no whitespace between tokens
+fn write(node: &Json, out: &mut String) {
+    match node {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(&b.to_string()),
+        Json::Number(n) => out.push_str(n),
+        Json::String(s) => out.push_str(&json_string(s)),
+        Json::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write(item, out);
+            }
+            out.push(']');
+        }
+        Json::Object(fields) => {
+            out.push('{');
+            for (i, (name, value)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&json_string(name));
+                out.push(':');
+                write(value, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source.to_string()))
+            .and_then(|mut parser| parser.parse_program())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_program_to_json() {
+        let program = parse("value Email(raw: String) {\n    validate: raw contains \"@\"\n}\nEmail(\"a@b\")");
+
+        assert_eq!(
+            program_to_json(&program),
+            concat!(
+                "{\"imports\":[],\"declarations\":[{\"kind\":\"value\",\"name\":\"Email\",",
+                "\"parameter\":{\"name\":\"raw\",\"type\":\"String\",\"unit\":null},",
+                "\"validate\":{\"kind\":\"comparison\",\"op\":\"contains\",",
+                "\"left\":{\"kind\":\"identifier\",\"name\":\"raw\"},",
+                "\"right\":{\"kind\":\"literal\",\"type\":\"string\",\"value\":\"@\"}},",
+                "\"normalize\":null,\"unique\":null,\"display\":null,\"sensitive\":null,\"coerce\":null,",
+                "\"convert\":[],",
+                "\"span\":{\"offset\":0,\"line\":1,\"column\":1,\"length\":59}}],",
+                "\"expressions\":[{\"expression\":{\"kind\":\"call\",\"function\":\"Email\",",
+                "\"arguments\":[{\"label\":null,\"value\":{\"kind\":\"literal\",\"type\":\"string\",\"value\":\"a@b\"}}]},",
+                "\"binding\":null,\"span\":{\"offset\":60,\"line\":4,\"column\":1,\"length\":12}}],\"tests\":[],\"data\":[]}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_program_to_outline() {
+        let program = parse("value Age(n: Int) { validate: n >= 0 }\nlet adults = [a for a in Age if a.n > 17]");
+        assert_eq!(
+            program_to_outline(&program),
+            concat!(
+                "program\n",
+                "  declarations:\n",
+                "    - value name=\"Age\"\n",
+                "      parameter name=\"n\" type=\"Int\"\n",
+                "      validate: comparison op=\">=\"\n",
+                "        left: identifier name=\"n\"\n",
+                "        right: literal type=\"integer\" value=0\n",
+                "  expressions:\n",
+                "    - binding=\"adults\"\n",
+                "      expression: comprehension name=\"a\"\n",
+                "        element: identifier name=\"a\"\n",
+                "        source: identifier name=\"Age\"\n",
+                "        condition: comparison op=\">\"\n",
+                "          left: member_access member=\"n\"\n",
+                "            object: identifier name=\"a\"\n",
+                "          right: literal type=\"integer\" value=17\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_outline_deeper_than_json_reads() {
+        // Deeper than `json::parse` allows, which the outline doesn't go through
+        let source = format!("1{}", " + 1".repeat(crate::json::MAX_DEPTH + 20));
+        let program = Parser::new(Lexer::new(source))
+            .unwrap()
+            .with_max_depth(1000)
+            .parse_program()
+            .unwrap();
+
+        let outline = program_to_outline(&program);
+        assert_eq!(outline.matches("binary op=\"+\"").count(), crate::json::MAX_DEPTH + 20);
+    }
+
+    #[test]
+    fn test_function_with_guards_and_labels() {
+        let program = parse("fn f(x: Int where x > 0, y: Email) -> Int { g(a: x, b: -1) }");
+        let json = program_to_json(&program);
+
+        assert!(json.contains("\"guard\":{\"kind\":\"comparison\",\"op\":\">\""));
+        assert!(json.contains("\"type\":\"Email\""));
+        assert!(json.contains("{\"label\":\"a\",\"value\":{\"kind\":\"identifier\",\"name\":\"x\"}}"));
+        assert!(json.contains("{\"kind\":\"unary\",\"op\":\"-\""));
+    }
+
+    #[test]
+    fn test_strings_are_escaped() {
+        let expr = Expression::Literal(Literal::String("say \"hi\"\n".to_string()));
+        assert_eq!(
+            expression_to_json(&expr),
+            "{\"kind\":\"literal\",\"type\":\"string\",\"value\":\"say \\\"hi\\\"\\n\"}"
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Methods built into Relic's own types: `s.trim()`, `d.year()`,
+//! `xs.length()`, `o.getOrElse(0)` and the rest. Each is an entry in a
+//! table, by the tag of its receiver's type (as dispatch tags arguments)
+//! and its name, with its parameters, its result type and its Rust body.
+//! The evaluator looks method calls up in the registry's table, the type
+//! checker and completion list what it holds, and an embedder adds methods
+//! with `Engine::register_builtin`.
+//!
+//! A function of the same name comes first for the receivers it takes; see
+//! `evaluator::call_method` for the order a method call resolves in.
+
+use crate::ast::Expression;
+use crate::decimal;
+use crate::dispatch;
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::{self, EvalValue};
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// The Rust side of a built-in method. It's given the receiver, whose type
+/// has the method's receiver tag, and the call's arguments, whose number
+/// the method takes.
+pub type BuiltinBody = Arc<dyn Fn(&EvalValue, &Arguments<'_>) -> Result<EvalValue> + Send + Sync>;
+
+/// The arguments of a method call, evaluated when the method asks for
+/// them, so that one it doesn't need is never evaluated.
+pub struct Arguments<'a> {
+    expressions: &'a [Expression],
+    context: &'a HashMap<String, EvalValue>,
+    registry: &'a ValueRegistry,
+}
+
+impl<'a> Arguments<'a> {
+    pub(crate) fn new(
+        expressions: &'a [Expression],
+        context: &'a HashMap<String, EvalValue>,
+        registry: &'a ValueRegistry,
+    ) -> Self {
+        Self { expressions, context, registry }
+    }
+
+    pub fn len(&self) -> usize {
+        self.expressions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expressions.is_empty()
+    }
+
+    /// The value of the argument at `index`.
+    pub fn get(&self, index: usize) -> Result<EvalValue> {
+        let expression = self.expressions.get(index).ok_or_else(|| {
+            Error::Validation(ValidationError {
+                message: format!("The method was given {} arguments, not {}", self.len(), index + 1),
+                value_type: "method".to_string(),
+            })
+        })?;
+        evaluator::evaluate_expression(expression, self.context, self.registry)
+    }
+
+    /// The values of every argument, in order.
+    pub fn values(&self) -> Result<Vec<EvalValue>> {
+        (0..self.len()).map(|index| self.get(index)).collect()
+    }
+
+    /// The registry the method was called from.
+    pub fn registry(&self) -> &ValueRegistry {
+        self.registry
+    }
+}
+
+#[derive(Clone)]
+pub struct BuiltinMethod {
+    name: String,
+    receiver: Type,
+    parameters: Vec<(String, Type)>,
+    // Whether the last parameter takes any number of arguments, none
+    // included
+    variadic: bool,
+    return_type: Type,
+    body: BuiltinBody,
+}
+
+impl fmt::Debug for BuiltinMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BuiltinMethod")
+            .field("receiver", &self.receiver)
+            .field("signature", &self.signature())
+            .finish_non_exhaustive()
+    }
+}
+
+impl BuiltinMethod {
+    /// The method `name` of values of type `receiver`, taking `parameters`,
+    /// as name and type pairs, and returning `return_type`.
+    pub fn new(
+        receiver: Type,
+        name: &str,
+        parameters: &[(&str, Type)],
+        return_type: Type,
+        body: impl Fn(&EvalValue, &Arguments<'_>) -> Result<EvalValue> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            receiver,
+            parameters: parameters.iter().map(|(name, ty)| (name.to_string(), ty.clone())).collect(),
+            variadic: false,
+            return_type,
+            body: Arc::new(body),
+        }
+    }
+
+    /// The same method, with its last parameter taking any number of
+    /// arguments.
+    pub fn variadic(mut self) -> Self {
+        self.variadic = true;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn receiver(&self) -> &Type {
+        &self.receiver
+    }
+
+    pub fn parameters(&self) -> &[(String, Type)] {
+        &self.parameters
+    }
+
+    pub fn is_variadic(&self) -> bool {
+        self.variadic
+    }
+
+    pub fn return_type(&self) -> &Type {
+        &self.return_type
+    }
+
+    /// Whether a call with `count` arguments is one this method takes.
+    pub fn takes(&self, count: usize) -> bool {
+        match self.variadic {
+            true => count + 1 >= self.parameters.len(),
+            false => count == self.parameters.len(),
+        }
+    }
+
+    /// The type the argument at `index` must have.
+    pub fn parameter_type(&self, index: usize) -> Option<&Type> {
+        match self.parameters.get(index) {
+            Some((_, ty)) => Some(ty),
+            None if self.variadic => self.parameters.last().map(|(_, ty)| ty),
+            None => None,
+        }
+    }
+
+    /// How the method is called: `round(places: Int) -> Decimal`.
+    pub fn signature(&self) -> String {
+        let mut parameters: Vec<String> =
+            self.parameters.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+        if let (true, Some(last)) = (self.variadic, parameters.last_mut()) {
+            last.push_str("...");
+        }
+        format!("{}({}) -> {}", self.name, parameters.join(", "), self.return_type)
+    }
+
+    pub fn call(&self, receiver: &EvalValue, arguments: &Arguments<'_>) -> Result<EvalValue> {
+        (self.body)(receiver, arguments)
+    }
+
+    // The tag of the values this method is called on
+    fn tag(&self) -> &str {
+        dispatch::parameter_tag(&self.receiver).unwrap_or_default()
+    }
+}
+
+/// The built-in methods, by receiver tag, in the order they were defined.
+#[derive(Clone, Debug, Default)]
+pub struct BuiltinTable {
+    methods: HashMap<String, Vec<BuiltinMethod>>,
+}
+
+impl BuiltinTable {
+    /// The methods every program has.
+    pub fn standard() -> Self {
+        let mut table = Self::default();
+        for method in standard_methods() {
+            table.define(method);
+        }
+        table
+    }
+
+    /// Add `method`, replacing the one of its receiver and name that takes
+    /// as many parameters.
+    pub fn define(&mut self, method: BuiltinMethod) {
+        let methods = self.methods.entry(method.tag().to_string()).or_default();
+        let same = |other: &BuiltinMethod| {
+            other.name == method.name
+                && other.parameters.len() == method.parameters.len()
+                && other.variadic == method.variadic
+        };
+        match methods.iter_mut().find(|other| same(other)) {
+            Some(other) => *other = method,
+            None => methods.push(method),
+        }
+    }
+
+    /// The method `name` of values tagged `receiver` that takes `arguments`
+    /// arguments.
+    pub fn find<'a>(&'a self, receiver: &str, name: &'a str, arguments: usize) -> Option<&'a BuiltinMethod> {
+        self.named(receiver, name).find(|method| method.takes(arguments))
+    }
+
+    /// Every method `name` of values tagged `receiver`.
+    pub fn named<'a>(&'a self, receiver: &str, name: &'a str) -> impl Iterator<Item = &'a BuiltinMethod> {
+        self.of(receiver).iter().filter(move |method| method.name == name)
+    }
+
+    /// The methods of values tagged `receiver`.
+    pub fn of(&self, receiver: &str) -> &[BuiltinMethod] {
+        self.methods.get(receiver).map_or(&[], Vec::as_slice)
+    }
+
+    /// The methods of values of type `ty`.
+    pub fn of_type(&self, ty: &Type) -> &[BuiltinMethod] {
+        dispatch::parameter_tag(ty).map_or(&[], |tag| self.of(tag))
+    }
+}
+
+// `name` on a receiver that isn't of the method's type, which the table's
+// lookup by tag rules out
+fn wrong_receiver(name: &str, receiver: &EvalValue) -> Error {
+    Error::Validation(ValidationError {
+        message: format!("Method {} can't be called on {}", name, receiver),
+        value_type: evaluator::type_tag(receiver).to_string(),
+    })
+}
+
+// A String method taking no arguments
+fn string_method(name: &'static str, f: fn(&str) -> String) -> BuiltinMethod {
+    BuiltinMethod::new(Type::String, name, &[], Type::String, move |receiver, _| match receiver {
+        EvalValue::String(s) => Ok(EvalValue::String(f(s))),
+        other => Err(wrong_receiver(name, other)),
+    })
+}
+
+// A part of a date, time or duration, such as `year()`
+fn accessor(receiver: Type, name: &'static str) -> BuiltinMethod {
+    BuiltinMethod::new(receiver, name, &[], Type::Int, move |receiver, _| match receiver {
+        EvalValue::Temporal(t) => t.accessor(name).map(EvalValue::Integer),
+        other => Err(wrong_receiver(name, other)),
+    })
+}
+
+// A Decimal method, given its arguments' values
+fn decimal_method(name: &'static str, parameters: &[(&str, Type)], return_type: Type) -> BuiltinMethod {
+    BuiltinMethod::new(Type::Decimal, name, parameters, return_type, move |receiver, arguments| match receiver {
+        EvalValue::Decimal(d) => decimal::method(d, name, &arguments.values()?),
+        other => Err(wrong_receiver(name, other)),
+    })
+}
+
+// Declared but not yet implemented: lists take lambdas through the query
+// steps, `xs.where(x => p)`
+fn unimplemented(name: &'static str) -> BuiltinMethod {
+    let any = Type::List(Box::new(Type::Any));
+    BuiltinMethod::new(any, name, &[("predicate", Type::Any)], Type::Any, move |_, _| {
+        Err(Error::Validation(ValidationError {
+            message: format!("List.{}() not yet implemented - requires lambda support", name),
+            value_type: "method".to_string(),
+        }))
+    })
+}
+
+fn standard_methods() -> Vec<BuiltinMethod> {
+    let any = || Box::new(Type::Any);
+    let mut methods = vec![
+        string_method("toLowerCase", str::to_lowercase),
+        string_method("toUpperCase", str::to_uppercase),
+        string_method("trim", |s| s.trim().to_string()),
+        string_method("collapseWhitespace", evaluator::collapse_whitespace),
+        string_method("titleCase", evaluator::title_case),
+    ];
+    for (receiver, names) in [
+        (Type::Date, &["year", "month", "day", "dayOfWeek"][..]),
+        (Type::Time, &["hour", "minute", "second"]),
+        (Type::Duration, &["days", "hours", "minutes", "seconds"]),
+    ] {
+        methods.extend(names.iter().map(|name| accessor(receiver.clone(), name)));
+    }
+    methods.extend([
+        decimal_method("round", &[("places", Type::Int)], Type::Decimal),
+        decimal_method("places", &[], Type::Int),
+        decimal_method("abs", &[], Type::Decimal),
+        BuiltinMethod::new(Type::List(any()), "length", &[], Type::Int, |receiver, _| match receiver {
+            EvalValue::List(items) => Ok(EvalValue::Integer(items.len() as i64)),
+            other => Err(wrong_receiver("length", other)),
+        }),
+        unimplemented("filter"),
+        unimplemented("find"),
+        BuiltinMethod::new(Type::Query(any()), "count", &[], Type::Int, |receiver, arguments| match receiver {
+            EvalValue::Query(query) => Ok(EvalValue::Integer(query.count(arguments.registry())? as i64)),
+            other => Err(wrong_receiver("count", other)),
+        }),
+        BuiltinMethod::new(Type::Query(any()), "materialize", &[], Type::List(any()), |receiver, arguments| {
+            match receiver {
+                EvalValue::Query(query) => Ok(EvalValue::List(query.materialize(arguments.registry())?)),
+                other => Err(wrong_receiver("materialize", other)),
+            }
+        }),
+        BuiltinMethod::new(Type::Option(any()), "isSome", &[], Type::Bool, |receiver, _| match receiver {
+            EvalValue::Option(value) => Ok(EvalValue::Boolean(value.is_some())),
+            other => Err(wrong_receiver("isSome", other)),
+        }),
+        BuiltinMethod::new(Type::Option(any()), "isNone", &[], Type::Bool, |receiver, _| match receiver {
+            EvalValue::Option(value) => Ok(EvalValue::Boolean(value.is_none())),
+            other => Err(wrong_receiver("isNone", other)),
+        }),
+        // The default is only evaluated when it's needed
+        BuiltinMethod::new(Type::Option(any()), "getOrElse", &[("default", Type::Any)], Type::Any, |receiver, args| {
+            match receiver {
+                EvalValue::Option(Some(value)) => Ok((**value).clone()),
+                EvalValue::Option(None) => args.get(0),
+                other => Err(wrong_receiver("getOrElse", other)),
+            }
+        }),
+        BuiltinMethod::new(Type::Relation, "rows", &[], Type::List(any()), |receiver, _| match receiver {
+            EvalValue::Relation(relation) => Ok(EvalValue::List(relation.row_values())),
+            other => Err(wrong_receiver("rows", other)),
+        }),
+        BuiltinMethod::new(Type::Relation, "schema", &[], Type::List(Box::new(Type::String)), |receiver, _| {
+            match receiver {
+                EvalValue::Relation(relation) => Ok(evaluator::schema_value(relation)),
+                other => Err(wrong_receiver("schema", other)),
+            }
+        }),
+        BuiltinMethod::new(Type::Relation, "addRow", &[("values", Type::Any)], Type::Relation, |receiver, arguments| {
+            match receiver {
+                EvalValue::Relation(relation) => Ok(EvalValue::Relation(relation.add_values(arguments.values()?)?)),
+                other => Err(wrong_receiver("addRow", other)),
+            }
+        })
+        .variadic(),
+    ]);
+    methods
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_builtin_table() {
+        let table = BuiltinTable::standard();
+        let round = table.find("Decimal", "round", 1).unwrap();
+        assert_eq!(round.signature(), "round(places: Int) -> Decimal");
+        assert!(table.find("Decimal", "round", 0).is_none());
+        let add_row = table.find("Relation", "addRow", 3).unwrap();
+        assert_eq!(add_row.signature(), "addRow(values: Any...) -> Relation");
+        assert!(add_row.takes(0));
+        let names: Vec<&str> = table.of_type(&Type::Time).iter().map(BuiltinMethod::name).collect();
+        assert_eq!(names, ["hour", "minute", "second"]);
+
+        // An embedder's method is type checked, evaluated and listed
+        let mut engine = Engine::new();
+        engine.register_builtin(BuiltinMethod::new(
+            Type::String,
+            "repeat",
+            &[("times", Type::Int)],
+            Type::String,
+            |receiver, arguments| match (receiver, arguments.get(0)?) {
+                (EvalValue::String(s), EvalValue::Integer(n)) => Ok(EvalValue::String(s.repeat(n.max(0) as usize))),
+                _ => unreachable!("the type checker rules out other arguments"),
+            },
+        ));
+        assert_eq!(engine.eval("\"ab\".repeat(3)").unwrap(), EvalValue::String("ababab".to_string()));
+        assert_eq!(engine.type_of("\"ab\".repeat(3).toUpperCase()").unwrap(), Type::String);
+        let error = engine.eval("\"ab\".repeat(\"x\")").unwrap_err().to_string();
+        assert!(error.contains("Mismatched types in argument 1 of `repeat`"), "{}", error);
+        assert!(engine.eval("\"ab\".repeat()").unwrap_err().to_string().contains("repeat takes 1 argument"));
+        assert!(engine.registry().builtins().find("String", "repeat", 1).is_some());
+
+        // A user's function of the same name still comes first
+        engine.load("fn repeat(s: String, n: Int) -> String { s }").unwrap();
+        assert_eq!(engine.eval("\"ab\".repeat(3)").unwrap(), EvalValue::String("ab".to_string()));
+    }
+}
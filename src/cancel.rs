@@ -0,0 +1,79 @@
+//! Stopping an evaluation from another thread. A service that runs Relic
+//! on a worker thread (or in `spawn_blocking`, from async code) makes a
+//! `CancellationToken` for each request, passes it to
+//! `Engine::eval_with_token`, and cancels it when the request times out or
+//! is abandoned; the evaluator checks it before evaluating each expression
+//! and gives up with an error. Other evaluations, on the same engine from
+//! other threads, carry on.
+//!
+//! An engine also has a token of its own, which every evaluation checks:
+//! cancelling it stops them all, until it is reset.
+
+use crate::error::{Error, Result};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+thread_local! {
+    // The tokens of the evaluations running on this thread, innermost last
+    static CURRENT: RefCell<Vec<CancellationToken>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A flag shared between an evaluation and whoever may cancel it. Clones
+/// share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the evaluations checking this token, including any started
+    /// later, until it is reset.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Let evaluations checking this token run again. Evaluations never
+    /// reset a token themselves; whoever cancelled it decides.
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::Relaxed);
+    }
+
+    /// Fail if the token has been cancelled; called at the evaluator's safe
+    /// points.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// Run `f`, an evaluation on this thread, stopping it when `token` is
+/// cancelled as well as when the engine's own token is.
+pub(crate) fn with_token<T>(token: &CancellationToken, f: impl FnOnce() -> T) -> T {
+    // Popped even if `f` unwinds
+    struct Scope;
+    impl Drop for Scope {
+        fn drop(&mut self) {
+            CURRENT.with(|current| current.borrow_mut().pop());
+        }
+    }
+    CURRENT.with(|current| current.borrow_mut().push(token.clone()));
+    let _scope = Scope;
+    f()
+}
+
+/// Fail if the token of any evaluation running on this thread has been
+/// cancelled.
+pub(crate) fn check_current() -> Result<()> {
+    CURRENT.with(|current| current.borrow().iter().try_for_each(CancellationToken::check))
+}
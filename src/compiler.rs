@@ -1,14 +1,37 @@
+use crate::artifact::CompiledProgram;
 use crate::ast::*;
+use crate::builtin::BuiltinMethod;
+use crate::dispatch::Dispatch;
+use crate::division::Division;
 use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::int_arithmetic;
+use crate::formatter::format_expression;
+use crate::limits::EvalLimits;
+use crate::module::{self, FileSystemResolver, Module, ModuleResolver};
+use crate::native::NativeFunction;
+use crate::provenance;
 use crate::specialization::SpecializationCache;
-use crate::stdlib;
-use crate::value::{ValueConstructor, ValueRegistry};
+use crate::stdlib::{self, ScriptEnvironment};
+use crate::template::Template;
+use crate::types::{FunctionType, ValueType};
+use crate::value::{ValueConstructor, ValueRegistry, Visibility};
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 pub struct Compiler {
     registry: ValueRegistry,
     specialization_cache: SpecializationCache,
+    resolver: Box<dyn ModuleResolver>,
+    // Names of the modules compiled so far, so each is only loaded once
+    modules: HashSet<String>,
+    // `data` blocks compiled so far, constructed again for each test
+    seeds: Vec<DataDeclaration>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
@@ -18,13 +41,130 @@ impl Compiler {
         Self {
             registry,
             specialization_cache: SpecializationCache::new(),
+            resolver: Box::new(FileSystemResolver::default()),
+            modules: HashSet::new(),
+            seeds: Vec::new(),
         }
     }
 
-    pub fn compile_program(&mut self, program: &Program) -> Result<()> {
+    /// Use `resolver` to find the modules named in `import` statements.
+    /// Defaults to a `FileSystemResolver` rooted at the working directory.
+    pub fn with_resolver(mut self, resolver: impl ModuleResolver + 'static) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    /// Give scripts `environment` through `args()` and `env()`. Scripts see
+    /// no arguments or variables by default.
+    pub fn with_script_environment(mut self, environment: ScriptEnvironment) -> Self {
+        self.registry.set_script_environment(environment);
+        self
+    }
+
+    /// Keep, with each instance constructed, the code that constructed it;
+    /// see `ValueRegistry::set_provenance`.
+    pub fn with_provenance(mut self) -> Self {
+        self.registry.set_provenance(true);
+        self
+    }
+
+    /// Choose how calls pick among overloads; see `Dispatch`.
+    pub fn with_dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.registry.set_dispatch(dispatch);
+        self
+    }
+
+    /// Choose how `/` and `%` round negative `Int`s, in expressions and in
+    /// the rules of value types compiled from now on; see `Division`.
+    pub fn with_division(mut self, division: Division) -> Self {
+        self.registry.set_division(division);
+        self
+    }
+
+    /// Bound what each evaluation may do; see `EvalLimits`.
+    pub fn with_limits(mut self, limits: EvalLimits) -> Self {
+        self.registry.set_limits(limits);
+        self
+    }
+
+    /// Start with the instances constructed by `previous`; see
+    /// `ValueRegistry::keep_instances`.
+    pub fn with_instances_from(mut self, previous: &ValueRegistry) -> Self {
+        self.registry.keep_instances(previous);
+        self
+    }
+
+    /// Names of the modules compiled so far. For a `FileSystemResolver`,
+    /// these are the paths of their files.
+    pub fn modules(&self) -> impl Iterator<Item = &str> {
+        self.modules.iter().map(String::as_str)
+    }
+
+    /// Load the modules `program` imports that haven't been compiled yet, in
+    /// dependency order. Type check and `compile_module` each of them before
+    /// compiling the program itself.
+    pub fn load_imports(&self, program: &Program) -> Result<Vec<Module>> {
+        module::load_imports(self.resolver.as_ref(), program, &|name| self.modules.contains(name))
+    }
+
+    pub fn compile_module(&mut self, module: &Module) -> Result<CompiledProgram> {
+        let compiled = self.compile_program(&module.program)?;
+        self.modules.insert(module.name.clone());
+        Ok(compiled)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "compile", level = "debug", skip_all, fields(declarations = program.declarations.len()))
+    )]
+    pub fn compile_program(&mut self, program: &Program) -> Result<CompiledProgram> {
+        for declaration in &program.declarations {
+            self.compile_declaration(declaration)?;
+        }
+        for data in &program.data {
+            self.seed(data)?;
+        }
+
+        let mut compiled = CompiledProgram {
+            declarations: program.declarations.clone(),
+            ..CompiledProgram::default()
+        };
         for declaration in &program.declarations {
+            match declaration {
+                Declaration::Value(v) => compiled.value_types.push(ValueType::of(v)),
+                Declaration::Function(FunctionDeclaration { name, parameters, return_type, .. })
+                | Declaration::Method(MethodDeclaration { name, parameters, return_type, .. }) => {
+                    compiled.functions.push(FunctionType::of(name, parameters, return_type))
+                }
+            }
+        }
+        let names: Vec<&str> = compiled.functions.iter().map(|f| f.name.as_str()).collect();
+        compiled.specializations = self.specialization_cache.specializations_of(&names);
+        Ok(compiled)
+    }
+
+    /// Define what a program compiled before defines, without compiling it
+    /// again. Its types must already be declared to the type checker; see
+    /// `TypeChecker::declare_compiled`.
+    pub fn load_compiled(&mut self, compiled: &CompiledProgram) -> Result<()> {
+        for declaration in &compiled.declarations {
             self.compile_declaration(declaration)?;
         }
+        for call in &compiled.specializations {
+            self.specialization_cache.insert(call.clone());
+        }
+        Ok(())
+    }
+
+    /// Construct the instances of a `data` block, stopping at the first that
+    /// fails validation. They are not part of the `CompiledProgram`.
+    fn seed(&mut self, data: &DataDeclaration) -> Result<()> {
+        for entry in &data.entries {
+            let describe = || format!("data {}: {}", data.type_name, format_expression(&entry.expression));
+            provenance::at(Some(entry.span.line), describe, || self.evaluate_expression(&entry.expression))
+                .map_err(|e| e.with_span(entry.span))?;
+        }
+        self.seeds.push(data.clone());
         Ok(())
     }
 
@@ -47,6 +187,7 @@ impl Compiler {
         let validator = if let Some(ref validate_expr) = decl.body.validate {
             let expr_clone = validate_expr.clone();
             let param_name = decl.parameter.name.clone();
+            let division = self.registry.division();
 
             Box::new(move |input: &(dyn Any + Send + Sync)| -> Result<()> {
                 // This is a simplified validator - in a real implementation,
@@ -54,7 +195,7 @@ impl Compiler {
                 match &decl_clone.parameter.ty {
                     crate::types::Type::String => {
                         if let Some(s) = input.downcast_ref::<String>() {
-                            if !evaluate_string_validation(s, &expr_clone, &param_name)? {
+                            if !evaluate_string_validation(s, &expr_clone, &param_name, division)? {
                                 return Err(Error::Validation(ValidationError {
                                     message: "Validation failed".to_string(),
                                     value_type: name.clone(),
@@ -64,7 +205,7 @@ impl Compiler {
                     }
                     crate::types::Type::Int => {
                         if let Some(n) = input.downcast_ref::<i64>() {
-                            if !evaluate_int_validation(*n, &expr_clone, &param_name)? {
+                            if !evaluate_int_validation(*n, &expr_clone, &param_name, division)? {
                                 return Err(Error::Validation(ValidationError {
                                     message: "Validation failed".to_string(),
                                     value_type: name.clone(),
@@ -97,6 +238,8 @@ impl Compiler {
             declaration: decl.clone(),
             validator,
             normalizer,
+            // The parser checked the template
+            display: decl.body.display.as_deref().and_then(|display| Template::parse(display).ok()),
         };
 
         self.registry.register(decl.name.clone(), constructor);
@@ -117,6 +260,56 @@ impl Compiler {
         Ok(())
     }
 
+    /// Make a function implemented in Rust callable from Relic code.
+    pub fn register_native(&mut self, native: NativeFunction) {
+        self.registry.register_native(native);
+    }
+
+    pub fn register_builtin(&mut self, method: BuiltinMethod) {
+        self.registry.register_builtin(method);
+    }
+
+    /// Decide which instances evaluation sees; see
+    /// `ValueRegistry::set_visibility`.
+    pub fn set_visibility(&mut self, visibility: Option<Visibility>) {
+        self.registry.set_visibility(visibility);
+    }
+
+    /// Remove the value type or function overloads called `name`; see
+    /// `ValueRegistry::remove`.
+    pub fn forget(&mut self, name: &str) -> bool {
+        self.specialization_cache.forget(name);
+        self.seeds.retain(|data| data.type_name != name);
+        self.registry.remove(name)
+    }
+
+    /// Run a `test` declaration with a fresh instance store, holding only
+    /// the instances of the `data` blocks compiled so far, so that it leaves
+    /// nothing behind. Fails if the test's body fails or evaluates to
+    /// `false`.
+    pub fn run_test(&mut self, test: &TestDeclaration) -> Result<()> {
+        let seeds = &self.seeds;
+        let result = self.registry.with_fresh_instances(|registry| {
+            for entry in seeds.iter().flat_map(|data| &data.entries) {
+                crate::evaluator::evaluate_expression(&entry.expression, &HashMap::new(), registry)?;
+            }
+            crate::evaluator::evaluate_expression(&test.body, &HashMap::new(), registry)
+        });
+        match result.map_err(|e| e.with_span(test.span))? {
+            crate::evaluator::EvalValue::Boolean(false) => Err(Error::Validation(ValidationError {
+                message: format!("Test '{}' evaluated to false", test.name),
+                value_type: "test".to_string(),
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    /// Bind `name` to `value` for later evaluations, as a top-level `let`
+    /// does; see `ValueRegistry::bind`.
+    pub fn bind(&mut self, name: &str, value: crate::evaluator::EvalValue) {
+        self.registry.bind(name, value);
+    }
+
     pub fn get_registry(&self) -> &ValueRegistry {
         &self.registry
     }
@@ -128,7 +321,12 @@ impl Compiler {
     pub fn evaluate_expression(&self, expr: &Expression) -> Result<crate::evaluator::EvalValue> {
         // Use optimized evaluator when we have type information available
         // For now, fall back to regular evaluation
-        crate::evaluator::evaluate_expression(expr, &HashMap::new(), &self.registry)
+        self.registry.begin_evaluation();
+        let value = crate::evaluator::evaluate_expression(expr, self.registry.bindings(), &self.registry)?;
+        // A query that leaves evaluation is run, so that what's returned is rows
+        let value = crate::query::materialized(value, &self.registry)?;
+        self.registry.limits().check_value(&value)?;
+        Ok(value)
     }
     
     pub fn evaluate_expression_with_optimization(&mut self, expr: &Expression) -> Result<crate::evaluator::EvalValue> {
@@ -162,8 +360,8 @@ enum EvalValue {
 }
 
 // Simplified expression evaluation functions
-fn evaluate_string_validation(value: &str, expr: &Expression, param_name: &str) -> Result<bool> {
-    evaluate_string_validation_with_context(value, expr, param_name, &HashMap::new())
+fn evaluate_string_validation(value: &str, expr: &Expression, param_name: &str, division: Division) -> Result<bool> {
+    evaluate_string_validation_with_context(value, expr, param_name, &HashMap::new(), division)
 }
 
 fn evaluate_string_validation_with_context(
@@ -171,15 +369,16 @@ fn evaluate_string_validation_with_context(
     expr: &Expression,
     param_name: &str,
     context: &HashMap<String, EvalValue>,
+    division: Division,
 ) -> Result<bool> {
     match expr {
         Expression::Binary(BinaryOp::And, left, right) => {
-            Ok(evaluate_string_validation_with_context(value, left, param_name, context)?
-                && evaluate_string_validation_with_context(value, right, param_name, context)?)
+            Ok(evaluate_string_validation_with_context(value, left, param_name, context, division)?
+                && evaluate_string_validation_with_context(value, right, param_name, context, division)?)
         }
         Expression::Binary(BinaryOp::Or, left, right) => {
-            Ok(evaluate_string_validation_with_context(value, left, param_name, context)?
-                || evaluate_string_validation_with_context(value, right, param_name, context)?)
+            Ok(evaluate_string_validation_with_context(value, left, param_name, context, division)?
+                || evaluate_string_validation_with_context(value, right, param_name, context, division)?)
         }
         Expression::Comparison(ComparisonOp::Contains, left, right) => {
             // Get the left side value
@@ -219,10 +418,10 @@ fn evaluate_string_validation_with_context(
             let left_val = if let Expression::MemberAccess(obj, member) = &**left {
                 if let Expression::Identifier(name) = &**obj {
                     if name == param_name && member == "length" {
-                        value.len() as i64
+                        value.chars().count() as i64
                     } else if let Some(val) = context.get(name) {
                         match val {
-                            EvalValue::String(s) if member == "length" => s.len() as i64,
+                            EvalValue::String(s) if member == "length" => s.chars().count() as i64,
                             EvalValue::Integer(n) => *n,
                             _ => return Ok(false),
                         }
@@ -258,18 +457,18 @@ fn evaluate_string_validation_with_context(
         Expression::Pipeline(left, _right) => {
             // For now, just evaluate the left side
             // Full implementation would need to apply right as a function to left
-            evaluate_string_validation_with_context(value, left, param_name, context)
+            evaluate_string_validation_with_context(value, left, param_name, context, division)
         }
         Expression::Let(name, binding_value, body) => {
             // Evaluate the binding value
-            let bound_value = evaluate_expression_to_value(binding_value, value, param_name, context)?;
+            let bound_value = evaluate_expression_to_value(binding_value, value, param_name, context, division)?;
             
             // Create new context with the bound value
             let mut new_context = context.clone();
             new_context.insert(name.clone(), bound_value);
             
             // Evaluate the body with the extended context
-            evaluate_string_validation_with_context(value, body, param_name, &new_context)
+            evaluate_string_validation_with_context(value, body, param_name, &new_context, division)
         }
         Expression::Match(expr, arms) => {
             // For now, we only support matching on identifiers
@@ -277,7 +476,7 @@ fn evaluate_string_validation_with_context(
                 if name == param_name || context.contains_key(name) {
                     // Evaluate the first arm (in a full implementation, we'd match patterns)
                     if let Some(arm) = arms.first() {
-                        evaluate_string_validation_with_context(value, &arm.body, param_name, context)
+                        evaluate_string_validation_with_context(value, &arm.body, param_name, context, division)
                     } else {
                         Ok(false)
                     }
@@ -292,8 +491,8 @@ fn evaluate_string_validation_with_context(
     }
 }
 
-fn evaluate_int_validation(value: i64, expr: &Expression, param_name: &str) -> Result<bool> {
-    evaluate_int_validation_with_context(value, expr, param_name, &HashMap::new())
+fn evaluate_int_validation(value: i64, expr: &Expression, param_name: &str, division: Division) -> Result<bool> {
+    evaluate_int_validation_with_context(value, expr, param_name, &HashMap::new(), division)
 }
 
 fn evaluate_int_validation_with_context(
@@ -301,61 +500,62 @@ fn evaluate_int_validation_with_context(
     expr: &Expression,
     param_name: &str,
     context: &HashMap<String, EvalValue>,
+    division: Division,
 ) -> Result<bool> {
     match expr {
         Expression::Binary(BinaryOp::And, left, right) => {
-            Ok(evaluate_int_validation_with_context(value, left, param_name, context)?
-                && evaluate_int_validation_with_context(value, right, param_name, context)?)
+            Ok(evaluate_int_validation_with_context(value, left, param_name, context, division)?
+                && evaluate_int_validation_with_context(value, right, param_name, context, division)?)
         }
         Expression::Binary(BinaryOp::Or, left, right) => {
-            Ok(evaluate_int_validation_with_context(value, left, param_name, context)?
-                || evaluate_int_validation_with_context(value, right, param_name, context)?)
+            Ok(evaluate_int_validation_with_context(value, left, param_name, context, division)?
+                || evaluate_int_validation_with_context(value, right, param_name, context, division)?)
         }
         Expression::Comparison(ComparisonOp::Equal, left, right) => {
-            let left_val = evaluate_int_expr(left, value, param_name, context)?;
-            let right_val = evaluate_int_expr(right, value, param_name, context)?;
+            let left_val = evaluate_int_expr(left, value, param_name, context, division)?;
+            let right_val = evaluate_int_expr(right, value, param_name, context, division)?;
             Ok(left_val == right_val)
         }
         Expression::Comparison(ComparisonOp::NotEqual, left, right) => {
-            let left_val = evaluate_int_expr(left, value, param_name, context)?;
-            let right_val = evaluate_int_expr(right, value, param_name, context)?;
+            let left_val = evaluate_int_expr(left, value, param_name, context, division)?;
+            let right_val = evaluate_int_expr(right, value, param_name, context, division)?;
             Ok(left_val != right_val)
         }
         Expression::Comparison(ComparisonOp::Greater, left, right) => {
-            let left_val = evaluate_int_expr(left, value, param_name, context)?;
-            let right_val = evaluate_int_expr(right, value, param_name, context)?;
+            let left_val = evaluate_int_expr(left, value, param_name, context, division)?;
+            let right_val = evaluate_int_expr(right, value, param_name, context, division)?;
             Ok(left_val > right_val)
         }
         Expression::Comparison(ComparisonOp::GreaterEqual, left, right) => {
-            let left_val = evaluate_int_expr(left, value, param_name, context)?;
-            let right_val = evaluate_int_expr(right, value, param_name, context)?;
+            let left_val = evaluate_int_expr(left, value, param_name, context, division)?;
+            let right_val = evaluate_int_expr(right, value, param_name, context, division)?;
             Ok(left_val >= right_val)
         }
         Expression::Comparison(ComparisonOp::Less, left, right) => {
-            let left_val = evaluate_int_expr(left, value, param_name, context)?;
-            let right_val = evaluate_int_expr(right, value, param_name, context)?;
+            let left_val = evaluate_int_expr(left, value, param_name, context, division)?;
+            let right_val = evaluate_int_expr(right, value, param_name, context, division)?;
             Ok(left_val < right_val)
         }
         Expression::Comparison(ComparisonOp::LessEqual, left, right) => {
-            let left_val = evaluate_int_expr(left, value, param_name, context)?;
-            let right_val = evaluate_int_expr(right, value, param_name, context)?;
+            let left_val = evaluate_int_expr(left, value, param_name, context, division)?;
+            let right_val = evaluate_int_expr(right, value, param_name, context, division)?;
             Ok(left_val <= right_val)
         }
         Expression::Pipeline(left, _right) => {
             // For now, just evaluate the left side
             // Full implementation would need to apply right as a function to left
-            evaluate_int_validation_with_context(value, left, param_name, context)
+            evaluate_int_validation_with_context(value, left, param_name, context, division)
         }
         Expression::Let(name, binding_value, body) => {
             // Evaluate the binding value with current parameter as integer
-            let bound_value = evaluate_expression_to_value_int(binding_value, value, param_name, context)?;
+            let bound_value = evaluate_expression_to_value_int(binding_value, value, param_name, context, division)?;
             
             // Create new context with the bound value
             let mut new_context = context.clone();
             new_context.insert(name.clone(), bound_value);
             
             // Evaluate the body with the extended context
-            evaluate_int_validation_with_context(value, body, param_name, &new_context)
+            evaluate_int_validation_with_context(value, body, param_name, &new_context, division)
         }
         Expression::Match(expr, arms) => {
             // For now, we only support matching on identifiers
@@ -363,12 +563,14 @@ fn evaluate_int_validation_with_context(
                 if name == param_name || context.contains_key(name) {
                     // Evaluate the first arm (in a full implementation, we'd match patterns)
                     if let Some(arm) = arms.first() {
-                        // Create a new context with the pattern binding
-                        // Create a new context with the pattern binding
-                        let Pattern::Constructor(_, binding) = &arm.pattern;
+                        // Create a new context with the pattern binding; an
+                        // Int is never a list
+                        let Pattern::Constructor(_, binding) = &arm.pattern else {
+                            return Ok(false);
+                        };
                         let mut new_context = context.clone();
                         new_context.insert(binding.clone(), EvalValue::Integer(value));
-                        evaluate_int_validation_with_context(value, &arm.body, param_name, &new_context)
+                        evaluate_int_validation_with_context(value, &arm.body, param_name, &new_context, division)
                     } else {
                         Ok(false)
                     }
@@ -389,6 +591,7 @@ fn evaluate_int_expr(
     param_value: i64,
     param_name: &str,
     context: &HashMap<String, EvalValue>,
+    division: Division,
 ) -> Result<i64> {
     match expr {
         Expression::Literal(Literal::Integer(n)) => Ok(*n),
@@ -412,7 +615,7 @@ fn evaluate_int_expr(
                     Ok(0) // placeholder
                 } else if let Some(val) = context.get(name) {
                     match val {
-                        EvalValue::String(s) if member == "length" => Ok(s.len() as i64),
+                        EvalValue::String(s) if member == "length" => Ok(s.chars().count() as i64),
                         _ => Err(Error::Validation(ValidationError {
                             message: format!("Cannot access {} on {}", member, name),
                             value_type: "".to_string(),
@@ -431,6 +634,20 @@ fn evaluate_int_expr(
                 }))
             }
         }
+        Expression::Unary(UnaryOp::Minus, operand) => {
+            let n = evaluate_int_expr(operand, param_value, param_name, context, division)?;
+            n.checked_neg().ok_or_else(|| {
+                Error::Validation(ValidationError {
+                    message: "Negation overflows Int".to_string(),
+                    value_type: "".to_string(),
+                })
+            })
+        }
+        Expression::Binary(op, left, right) => {
+            let left = evaluate_int_expr(left, param_value, param_name, context, division)?;
+            let right = evaluate_int_expr(right, param_value, param_name, context, division)?;
+            int_arithmetic(op, left, right, division)
+        }
         _ => Err(Error::Validation(ValidationError {
             message: "Cannot evaluate expression as integer".to_string(),
             value_type: "".to_string(),
@@ -461,14 +678,15 @@ fn evaluate_string_expr(
         }
         Expression::MethodCall(obj, method, _args) => {
             if let Expression::Identifier(name) = &**obj {
-                if name == param_name && method == "toLowerCase" {
-                    Ok(param_value.to_lowercase())
+                let receiver = if name == param_name {
+                    Some(param_value)
                 } else if let Some(EvalValue::String(s)) = context.get(name) {
-                    if method == "toLowerCase" {
-                        Ok(s.to_lowercase())
-                    } else {
-                        Ok(s.clone())
-                    }
+                    Some(s.as_str())
+                } else {
+                    None
+                };
+                if let Some(rewritten) = receiver.and_then(|s| string_method(s, method)) {
+                    Ok(rewritten)
                 } else {
                     Err(Error::Validation(ValidationError {
                         message: format!("Cannot call method {} on {}", method, name),
@@ -482,6 +700,10 @@ fn evaluate_string_expr(
                 }))
             }
         }
+        Expression::Binary(BinaryOp::Add, left, right) => {
+            let left = evaluate_string_expr(left, param_value, param_name, context)?;
+            Ok(left + &evaluate_string_expr(right, param_value, param_name, context)?)
+        }
         _ => Err(Error::Validation(ValidationError {
             message: "Cannot evaluate expression as string".to_string(),
             value_type: "".to_string(),
@@ -489,12 +711,25 @@ fn evaluate_string_expr(
     }
 }
 
+// The built-in string methods that take no arguments, applied to `s`
+fn string_method(s: &str, method: &str) -> Option<String> {
+    match method {
+        "toLowerCase" => Some(s.to_lowercase()),
+        "toUpperCase" => Some(s.to_uppercase()),
+        "trim" => Some(s.trim().to_string()),
+        "collapseWhitespace" => Some(crate::evaluator::collapse_whitespace(s)),
+        "titleCase" => Some(crate::evaluator::title_case(s)),
+        _ => None,
+    }
+}
+
 // Helper function to evaluate an expression to a value (for string context)
 fn evaluate_expression_to_value(
     expr: &Expression,
     param_value: &str,
     param_name: &str,
     context: &HashMap<String, EvalValue>,
+    division: Division,
 ) -> Result<EvalValue> {
     match expr {
         Expression::Literal(Literal::String(s)) => Ok(EvalValue::String(s.clone())),
@@ -515,7 +750,7 @@ fn evaluate_expression_to_value(
         Expression::MemberAccess(obj, member) => {
             if let Expression::Identifier(name) = &**obj {
                 if name == param_name && member == "length" {
-                    Ok(EvalValue::Integer(param_value.len() as i64))
+                    Ok(EvalValue::Integer(param_value.chars().count() as i64))
                 } else {
                     Err(Error::Validation(ValidationError {
                         message: format!("Unknown member access: {}.{}", name, member),
@@ -530,11 +765,11 @@ fn evaluate_expression_to_value(
             }
         }
         Expression::Binary(BinaryOp::Multiply, left, right) => {
-            let left_val = evaluate_expression_to_value(left, param_value, param_name, context)?;
-            let right_val = evaluate_expression_to_value(right, param_value, param_name, context)?;
+            let left_val = evaluate_expression_to_value(left, param_value, param_name, context, division)?;
+            let right_val = evaluate_expression_to_value(right, param_value, param_name, context, division)?;
             
             if let (EvalValue::Integer(l), EvalValue::Integer(r)) = (left_val, right_val) {
-                Ok(EvalValue::Integer(l * r))
+                Ok(EvalValue::Integer(int_arithmetic(&BinaryOp::Multiply, l, r, division)?))
             } else {
                 Err(Error::Validation(ValidationError {
                     message: "Multiplication requires integers".to_string(),
@@ -543,18 +778,11 @@ fn evaluate_expression_to_value(
             }
         }
         Expression::Binary(BinaryOp::Divide, left, right) => {
-            let left_val = evaluate_expression_to_value(left, param_value, param_name, context)?;
-            let right_val = evaluate_expression_to_value(right, param_value, param_name, context)?;
+            let left_val = evaluate_expression_to_value(left, param_value, param_name, context, division)?;
+            let right_val = evaluate_expression_to_value(right, param_value, param_name, context, division)?;
             
             if let (EvalValue::Integer(l), EvalValue::Integer(r)) = (left_val, right_val) {
-                if r != 0 {
-                    Ok(EvalValue::Integer(l / r))
-                } else {
-                    Err(Error::Validation(ValidationError {
-                        message: "Division by zero".to_string(),
-                        value_type: "".to_string(),
-                    }))
-                }
+                Ok(EvalValue::Integer(int_arithmetic(&BinaryOp::Divide, l, r, division)?))
             } else {
                 Err(Error::Validation(ValidationError {
                     message: "Division requires integers".to_string(),
@@ -563,11 +791,11 @@ fn evaluate_expression_to_value(
             }
         }
         Expression::Binary(BinaryOp::Add, left, right) => {
-            let left_val = evaluate_expression_to_value(left, param_value, param_name, context)?;
-            let right_val = evaluate_expression_to_value(right, param_value, param_name, context)?;
+            let left_val = evaluate_expression_to_value(left, param_value, param_name, context, division)?;
+            let right_val = evaluate_expression_to_value(right, param_value, param_name, context, division)?;
             
             if let (EvalValue::Integer(l), EvalValue::Integer(r)) = (left_val, right_val) {
-                Ok(EvalValue::Integer(l + r))
+                Ok(EvalValue::Integer(int_arithmetic(&BinaryOp::Add, l, r, division)?))
             } else {
                 Err(Error::Validation(ValidationError {
                     message: "Addition requires integers".to_string(),
@@ -588,6 +816,7 @@ fn evaluate_expression_to_value_int(
     param_value: i64,
     param_name: &str,
     context: &HashMap<String, EvalValue>,
+    division: Division,
 ) -> Result<EvalValue> {
     match expr {
         Expression::Literal(Literal::String(s)) => Ok(EvalValue::String(s.clone())),
@@ -606,11 +835,11 @@ fn evaluate_expression_to_value_int(
             }
         }
         Expression::Binary(BinaryOp::Multiply, left, right) => {
-            let left_val = evaluate_expression_to_value_int(left, param_value, param_name, context)?;
-            let right_val = evaluate_expression_to_value_int(right, param_value, param_name, context)?;
+            let left_val = evaluate_expression_to_value_int(left, param_value, param_name, context, division)?;
+            let right_val = evaluate_expression_to_value_int(right, param_value, param_name, context, division)?;
             
             if let (EvalValue::Integer(l), EvalValue::Integer(r)) = (left_val, right_val) {
-                Ok(EvalValue::Integer(l * r))
+                Ok(EvalValue::Integer(int_arithmetic(&BinaryOp::Multiply, l, r, division)?))
             } else {
                 Err(Error::Validation(ValidationError {
                     message: "Multiplication requires integers".to_string(),
@@ -619,18 +848,11 @@ fn evaluate_expression_to_value_int(
             }
         }
         Expression::Binary(BinaryOp::Divide, left, right) => {
-            let left_val = evaluate_expression_to_value_int(left, param_value, param_name, context)?;
-            let right_val = evaluate_expression_to_value_int(right, param_value, param_name, context)?;
+            let left_val = evaluate_expression_to_value_int(left, param_value, param_name, context, division)?;
+            let right_val = evaluate_expression_to_value_int(right, param_value, param_name, context, division)?;
             
             if let (EvalValue::Integer(l), EvalValue::Integer(r)) = (left_val, right_val) {
-                if r != 0 {
-                    Ok(EvalValue::Integer(l / r))
-                } else {
-                    Err(Error::Validation(ValidationError {
-                        message: "Division by zero".to_string(),
-                        value_type: "".to_string(),
-                    }))
-                }
+                Ok(EvalValue::Integer(int_arithmetic(&BinaryOp::Divide, l, r, division)?))
             } else {
                 Err(Error::Validation(ValidationError {
                     message: "Division requires integers".to_string(),
@@ -639,11 +861,11 @@ fn evaluate_expression_to_value_int(
             }
         }
         Expression::Binary(BinaryOp::Add, left, right) => {
-            let left_val = evaluate_expression_to_value_int(left, param_value, param_name, context)?;
-            let right_val = evaluate_expression_to_value_int(right, param_value, param_name, context)?;
+            let left_val = evaluate_expression_to_value_int(left, param_value, param_name, context, division)?;
+            let right_val = evaluate_expression_to_value_int(right, param_value, param_name, context, division)?;
             
             if let (EvalValue::Integer(l), EvalValue::Integer(r)) = (left_val, right_val) {
-                Ok(EvalValue::Integer(l + r))
+                Ok(EvalValue::Integer(int_arithmetic(&BinaryOp::Add, l, r, division)?))
             } else {
                 Err(Error::Validation(ValidationError {
                     message: "Addition requires integers".to_string(),
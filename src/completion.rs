@@ -0,0 +1,308 @@
+//! Tab completion for the REPL, from what the type checker knows: value
+//! types, function overloads with their signatures, built-in methods after
+//! `.`, and REPL commands after `:`. Also parameter help while typing the
+//! arguments of a call.
+
+use crate::ast::FunctionDeclaration;
+use crate::formatter::format_signature;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::typechecker::TypeChecker;
+use crate::types::{FunctionType, Type};
+use unicode_ident::is_xid_continue;
+
+/// A possible completion of the word before the cursor.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Candidate {
+    /// What is shown in the list of completions, e.g. a signature
+    pub display: String,
+    /// What replaces the word being completed
+    pub replacement: String,
+}
+
+/// Complete the word that ends at byte offset `pos` of `line`. Returns where
+/// the word starts and the candidates for it, sorted.
+pub fn complete(checker: &TypeChecker, commands: &[&str], line: &str, pos: usize) -> (usize, Vec<Candidate>) {
+    let prefix = &line[..pos];
+
+    // `:command`, at the start of the input
+    let trimmed = prefix.trim_start();
+    if trimmed.starts_with(':') && !trimmed.contains(char::is_whitespace) {
+        let start = pos - trimmed.len();
+        let candidates = commands
+            .iter()
+            .filter(|command| command.starts_with(trimmed))
+            .map(|command| candidate(command.to_string(), command.to_string()))
+            .collect();
+        return (start, candidates);
+    }
+
+    let start = prefix
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_xid_continue(*c))
+        .last()
+        .map_or(pos, |(i, _)| i);
+    let word = &prefix[start..];
+
+    let mut candidates: Vec<_> = if prefix[..start].ends_with('.') {
+        members(checker, receiver(&prefix[..start - 1]))
+    } else {
+        globals(checker)
+    };
+    candidates.retain(|c| c.replacement.starts_with(word));
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+/// Parameter help for the call whose arguments the cursor, at byte offset
+/// `pos` of `line`, is in: the signatures of the overloads in `functions`
+/// that take as many arguments as have been started, with their guards, or
+/// the parameter of a value type's constructor. None outside a call.
+pub fn signature_hint(
+    checker: &TypeChecker,
+    functions: &[FunctionDeclaration],
+    line: &str,
+    pos: usize,
+) -> Option<String> {
+    let (start, mut argument) = open_call(&line[..pos])?;
+    let name = &line[start..line[start..].find('(')? + start];
+    // `x.f(` passes `x` as the first argument
+    if line[..start].ends_with('.') {
+        argument += 1;
+    }
+
+    if let Some(value) = checker.get_environment().get_value(name) {
+        return Some(format!("{}({})", value.name, value.parameter_type));
+    }
+    let overloads: Vec<_> = functions
+        .iter()
+        .filter(|f| f.name == name && f.parameters.len() > argument)
+        .map(format_signature)
+        .collect();
+    if overloads.is_empty() {
+        None
+    } else {
+        Some(overloads.join(" | "))
+    }
+}
+
+/// Where the name of the innermost call left open at the end of `text`
+/// starts, and how many of its arguments come before the last.
+fn open_call(text: &str) -> Option<(usize, usize)> {
+    // Open brackets, with where each is and the commas seen inside it
+    let mut open: Vec<(char, usize, usize)> = Vec::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                // Skip the string, minding escapes
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '(' | '[' | '{' => open.push((c, i, 0)),
+            ')' | ']' | '}' => {
+                open.pop();
+            }
+            ',' => {
+                if let Some(last) = open.last_mut() {
+                    last.2 += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let &('(', paren, commas) = open.last()? else {
+        return None;
+    };
+    let before = &text[..paren];
+    let start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_xid_continue(*c))
+        .last()?
+        .0;
+    Some((start, commas))
+}
+
+/// Value types and functions.
+fn globals(checker: &TypeChecker) -> Vec<Candidate> {
+    let env = checker.get_environment();
+    let values = env.values().map(|value| {
+        candidate(value.name.clone(), format!("{}({})", value.name, value.parameter_type))
+    });
+    let functions = env
+        .functions()
+        .map(|function| candidate(function.name.clone(), signature(function, 0)));
+    values.chain(functions).collect()
+}
+
+/// Members and methods of `receiver`: the built-ins for its type, and the
+/// functions that can be called on it with `receiver.f()`. When its type
+/// can't be worked out, every built-in and function is offered.
+fn members(checker: &TypeChecker, receiver: &str) -> Vec<Candidate> {
+    let receiver_type = Parser::new(Lexer::new(receiver.to_string()))
+        .and_then(|mut parser| parser.parse_expression())
+        .and_then(|expr| checker.check_expression(&expr))
+        .ok();
+
+    let builtins: Vec<_> = match &receiver_type {
+        Some(ty) => checker.methods(ty),
+        None => [Type::String, Type::List(Box::new(Type::Any)), Type::Type]
+            .iter()
+            .flat_map(|ty| checker.methods(ty))
+            .collect(),
+    };
+    let functions = checker.get_environment().functions().filter(|function| {
+        match (function.parameter_types.first(), &receiver_type) {
+            (Some(first), Some(ty)) => first.accepts(ty),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    });
+
+    builtins
+        .into_iter()
+        .map(|(name, display)| candidate(name, display))
+        .chain(functions.map(|function| candidate(function.name.clone(), signature(function, 1))))
+        .collect()
+}
+
+/// The expression before a `.` at the end of `text`: a run of name
+/// characters, strings and bracketed groups, such as `f(1)[0]` or `"a b"`.
+fn receiver(text: &str) -> &str {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut start = text.len();
+    for (i, c) in text.char_indices().rev() {
+        match c {
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            ')' | ']' => depth += 1,
+            '(' | '[' if depth > 0 => depth -= 1,
+            _ if depth > 0 => {}
+            c if is_xid_continue(c) || c == '.' => {}
+            _ => break,
+        }
+        start = i;
+    }
+    &text[start..]
+}
+
+/// `name(a: Int, b: String) -> Bool`, leaving out the first `skip`
+/// parameters.
+fn signature(function: &FunctionType, skip: usize) -> String {
+    let parameters: Vec<_> = function
+        .parameter_names
+        .iter()
+        .zip(&function.parameter_types)
+        .skip(skip)
+        .map(|(name, ty)| format!("{}: {}", name, ty))
+        .collect();
+    format!("{}({}) -> {}", function.name, parameters.join(", "), function.return_type)
+}
+
+fn candidate(replacement: String, display: String) -> Candidate {
+    Candidate { display, replacement }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(source: &str) -> TypeChecker {
+        let program = Parser::new(Lexer::new(source.to_string()))
+            .and_then(|mut parser| parser.parse_program())
+            .unwrap();
+        let mut checker = TypeChecker::new();
+        checker.check_program(&program).unwrap();
+        checker
+    }
+
+    fn displays(checker: &TypeChecker, line: &str) -> (usize, Vec<String>) {
+        let (start, candidates) = complete(checker, &[":help", ":exit"], line, line.len());
+        (start, candidates.into_iter().map(|c| c.display).collect())
+    }
+
+    const SOURCE: &str = "value Email(raw: String) { validate: raw contains \"@\" }
+        fn domain(e: String) -> String { e }
+        fn double(x: Int) -> Int { x * 2 }
+        fn double(s: String) -> String { s }";
+
+    #[test]
+    fn test_names_and_overloads() {
+        let checker = checker(SOURCE);
+        assert_eq!(
+            displays(&checker, "1 + dou"),
+            (4, vec!["double(s: String) -> String".to_string(), "double(x: Int) -> Int".to_string()])
+        );
+        assert_eq!(displays(&checker, "Em"), (0, vec!["Email(String)".to_string()]));
+    }
+
+    #[test]
+    fn test_members_of_typed_receiver() {
+        let checker = checker(SOURCE);
+        assert_eq!(
+            displays(&checker, "\"a.b\".to"),
+            (6, vec!["toLowerCase() -> String".to_string(), "toUpperCase() -> String".to_string()])
+        );
+        let (_, members) = displays(&checker, "double(\"x\").");
+        assert!(members.contains(&"domain() -> String".to_string()));
+        assert!(members.contains(&"length: Int".to_string()));
+        assert!(!members.iter().any(|m| m.starts_with("double() -> Int")));
+
+        assert_eq!(displays(&checker, "Email.co"), (6, vec!["count() -> Int".to_string()]));
+    }
+
+    #[test]
+    fn test_signature_hints() {
+        let source = "fn size(n: Int) -> String { \"any\" }
+            fn size(n: Int where n > 100) -> String { \"big\" }
+            fn size(a: Int, b: Int) -> String { \"two\" }
+            value Email(raw: String) {}";
+        let checker = checker(source);
+        let functions: Vec<_> = Parser::new(Lexer::new(source.to_string()))
+            .and_then(|mut parser| parser.parse_program())
+            .unwrap()
+            .declarations
+            .into_iter()
+            .filter_map(|d| match d {
+                crate::ast::Declaration::Function(f) => Some(f),
+                _ => None,
+            })
+            .collect();
+        let hint = |line: &str| signature_hint(&checker, &functions, line, line.len());
+
+        assert_eq!(
+            hint("1 + size(").unwrap(),
+            "size(n: Int) -> String | size(n: Int where n > 100) -> String | size(a: Int, b: Int) -> String"
+        );
+        // Overloads with too few parameters are left out
+        assert_eq!(hint("size(1, ").unwrap(), "size(a: Int, b: Int) -> String");
+        assert_eq!(hint("1.size(").unwrap(), "size(a: Int, b: Int) -> String");
+        // Commas and brackets inside the arguments don't count
+        assert_eq!(hint("size(f(1, 2), \"a, (b\"").as_deref(), Some("size(a: Int, b: Int) -> String"));
+        assert_eq!(hint("size(f(1, 2").as_deref(), None);
+        assert_eq!(hint("Email(").unwrap(), "Email(String)");
+
+        assert_eq!(hint("size(1)"), None);
+        assert_eq!(hint("size(1, 2, "), None);
+        assert_eq!(hint("(1 + "), None);
+    }
+
+    #[test]
+    fn test_commands() {
+        let checker = checker("");
+        assert_eq!(displays(&checker, "  :h"), (2, vec![":help".to_string()]));
+        assert_eq!(displays(&checker, ":").1.len(), 2);
+    }
+}
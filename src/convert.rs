@@ -0,0 +1,431 @@
+//! Conversions between Rust values and the `EvalValue`s Relic code works
+//! with, so that native functions can take and return ordinary Rust types
+//! and hosts can read results without matching on `EvalValue`.
+//!
+//! Integers, `bool`, strings, decimals, dates, times, durations, `Option`,
+//! `Vec` and `HashMap` convert out of the box. A struct converts to and from a Relic
+//! value of the type with the same name once `impl_eval_value!` lists its
+//! fields:
+//!
+//! ```
+//! use relic::{convert::{FromEvalValue, IntoEvalValue}, impl_eval_value};
+//!
+//! #[derive(Debug, PartialEq)]
+//! struct Age {
+//!     years: i64,
+//! }
+//! impl_eval_value!(Age { years });
+//!
+//! let value = Age { years: 30 }.into_eval_value();
+//! assert_eq!(value.to_string(), "Age(30)");
+//! assert_eq!(Age::from_eval_value(value).unwrap(), Age { years: 30 });
+//! ```
+
+use crate::decimal::Decimal;
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::EvalValue;
+use crate::temporal::{self, Temporal};
+use crate::types::Type;
+use std::collections::HashMap;
+
+/// A Rust type with a Relic counterpart.
+pub trait RelicType {
+    /// The Relic type of values of this type, used in native function
+    /// signatures.
+    fn relic_type() -> Type;
+}
+
+/// A Rust type that can be made from a Relic value.
+pub trait FromEvalValue: RelicType + Sized {
+    /// Fails if `value` isn't of `Self::relic_type()`.
+    fn from_eval_value(value: EvalValue) -> Result<Self>;
+}
+
+/// A Rust type that can be given to Relic code as a value.
+pub trait IntoEvalValue: RelicType {
+    fn into_eval_value(self) -> EvalValue;
+}
+
+macro_rules! impl_integer {
+    ($($ty:ty),*) => {$(
+        impl RelicType for $ty {
+            fn relic_type() -> Type {
+                Type::Int
+            }
+        }
+
+        impl FromEvalValue for $ty {
+            fn from_eval_value(value: EvalValue) -> Result<Self> {
+                match value {
+                    EvalValue::Integer(n) => <$ty>::try_from(n).map_err(|_| {
+                        Error::Validation(ValidationError {
+                            message: format!("{} is out of range for {}", n, stringify!($ty)),
+                            value_type: "Int".to_string(),
+                        })
+                    }),
+                    other => Err(mismatch::<Self>(&other)),
+                }
+            }
+        }
+
+        impl IntoEvalValue for $ty {
+            /// Values beyond the range of Relic's `Int` are clamped to it.
+            fn into_eval_value(self) -> EvalValue {
+                EvalValue::Integer(i64::try_from(self).unwrap_or(i64::MAX))
+            }
+        }
+    )*};
+}
+
+impl_integer!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl RelicType for bool {
+    fn relic_type() -> Type {
+        Type::Bool
+    }
+}
+
+impl FromEvalValue for bool {
+    fn from_eval_value(value: EvalValue) -> Result<Self> {
+        match value {
+            EvalValue::Boolean(b) => Ok(b),
+            other => Err(mismatch::<Self>(&other)),
+        }
+    }
+}
+
+impl IntoEvalValue for bool {
+    fn into_eval_value(self) -> EvalValue {
+        EvalValue::Boolean(self)
+    }
+}
+
+impl RelicType for Decimal {
+    fn relic_type() -> Type {
+        Type::Decimal
+    }
+}
+
+impl FromEvalValue for Decimal {
+    fn from_eval_value(value: EvalValue) -> Result<Self> {
+        match value {
+            EvalValue::Decimal(d) => Ok(d),
+            other => Err(mismatch::<Self>(&other)),
+        }
+    }
+}
+
+impl IntoEvalValue for Decimal {
+    fn into_eval_value(self) -> EvalValue {
+        EvalValue::Decimal(self)
+    }
+}
+
+impl RelicType for String {
+    fn relic_type() -> Type {
+        Type::String
+    }
+}
+
+impl FromEvalValue for String {
+    fn from_eval_value(value: EvalValue) -> Result<Self> {
+        match value {
+            EvalValue::String(s) => Ok(s),
+            other => Err(mismatch::<Self>(&other)),
+        }
+    }
+}
+
+impl IntoEvalValue for String {
+    fn into_eval_value(self) -> EvalValue {
+        EvalValue::String(self)
+    }
+}
+
+impl RelicType for &str {
+    fn relic_type() -> Type {
+        Type::String
+    }
+}
+
+impl IntoEvalValue for &str {
+    fn into_eval_value(self) -> EvalValue {
+        EvalValue::String(self.to_string())
+    }
+}
+
+macro_rules! impl_temporal {
+    ($($ty:ident),*) => {$(
+        impl RelicType for temporal::$ty {
+            fn relic_type() -> Type {
+                Type::$ty
+            }
+        }
+
+        impl FromEvalValue for temporal::$ty {
+            fn from_eval_value(value: EvalValue) -> Result<Self> {
+                match value {
+                    EvalValue::Temporal(Temporal::$ty(t)) => Ok(t),
+                    other => Err(mismatch::<Self>(&other)),
+                }
+            }
+        }
+
+        impl IntoEvalValue for temporal::$ty {
+            fn into_eval_value(self) -> EvalValue {
+                EvalValue::Temporal(Temporal::$ty(self))
+            }
+        }
+    )*};
+}
+
+impl_temporal!(Date, Time, Duration);
+
+/// Any Relic value, unconverted.
+impl RelicType for EvalValue {
+    fn relic_type() -> Type {
+        Type::Any
+    }
+}
+
+impl FromEvalValue for EvalValue {
+    fn from_eval_value(value: EvalValue) -> Result<Self> {
+        Ok(value)
+    }
+}
+
+impl IntoEvalValue for EvalValue {
+    fn into_eval_value(self) -> EvalValue {
+        self
+    }
+}
+
+/// `Option[T]`
+impl<T: RelicType> RelicType for Option<T> {
+    fn relic_type() -> Type {
+        Type::Option(Box::new(T::relic_type()))
+    }
+}
+
+impl<T: FromEvalValue> FromEvalValue for Option<T> {
+    fn from_eval_value(value: EvalValue) -> Result<Self> {
+        match value {
+            EvalValue::Option(Some(value)) => T::from_eval_value(*value).map(Some),
+            EvalValue::Option(None) => Ok(None),
+            other => Err(mismatch::<Self>(&other)),
+        }
+    }
+}
+
+impl<T: IntoEvalValue> IntoEvalValue for Option<T> {
+    fn into_eval_value(self) -> EvalValue {
+        EvalValue::Option(self.map(|value| Box::new(value.into_eval_value())))
+    }
+}
+
+/// `List[T]`
+impl<T: RelicType> RelicType for Vec<T> {
+    fn relic_type() -> Type {
+        Type::List(Box::new(T::relic_type()))
+    }
+}
+
+impl<T: FromEvalValue> FromEvalValue for Vec<T> {
+    fn from_eval_value(value: EvalValue) -> Result<Self> {
+        match value {
+            EvalValue::List(items) => items.into_iter().map(T::from_eval_value).collect(),
+            other => Err(mismatch::<Self>(&other)),
+        }
+    }
+}
+
+impl<T: IntoEvalValue> IntoEvalValue for Vec<T> {
+    fn into_eval_value(self) -> EvalValue {
+        EvalValue::List(self.into_iter().map(IntoEvalValue::into_eval_value).collect())
+    }
+}
+
+/// The fields of a value, by name. Any value converts to a map of its
+/// fields; a map converts to a value of type `Map`.
+impl<T: RelicType> RelicType for HashMap<String, T> {
+    fn relic_type() -> Type {
+        Type::Any
+    }
+}
+
+impl<T: FromEvalValue> FromEvalValue for HashMap<String, T> {
+    fn from_eval_value(value: EvalValue) -> Result<Self> {
+        match value {
+            EvalValue::Value { fields, .. } => fields
+                .into_iter()
+                .map(|(name, value)| Ok((name, T::from_eval_value(value)?)))
+                .collect(),
+            other => Err(mismatch::<Self>(&other)),
+        }
+    }
+}
+
+impl<T: IntoEvalValue> IntoEvalValue for HashMap<String, T> {
+    fn into_eval_value(self) -> EvalValue {
+        EvalValue::Value {
+            type_name: "Map".to_string(),
+            fields: self.into_iter().map(|(name, value)| (name, value.into_eval_value())).collect(),
+            display: None,
+            sensitive: false,
+        }
+    }
+}
+
+/// Arguments for calling a Relic function from Rust: a tuple of values
+/// that convert to Relic, or the `EvalValue`s themselves.
+pub trait IntoArguments {
+    fn into_arguments(self) -> Vec<EvalValue>;
+}
+
+impl IntoArguments for Vec<EvalValue> {
+    fn into_arguments(self) -> Vec<EvalValue> {
+        self
+    }
+}
+
+macro_rules! impl_into_arguments {
+    ($($arg:ident),*) => {
+        impl<$($arg: IntoEvalValue),*> IntoArguments for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_arguments(self) -> Vec<EvalValue> {
+                let ($($arg,)*) = self;
+                vec![$($arg.into_eval_value()),*]
+            }
+        }
+    };
+}
+
+impl_into_arguments!();
+impl_into_arguments!(A);
+impl_into_arguments!(A, B);
+impl_into_arguments!(A, B, C);
+impl_into_arguments!(A, B, C, D);
+
+/// Implement `RelicType`, `FromEvalValue` and `IntoEvalValue` for a struct,
+/// converting it to and from a Relic value of the type with the same name.
+/// List the fields to convert; each must have a type that converts too.
+/// Converting to a value doesn't run its type's validation.
+#[macro_export]
+macro_rules! impl_eval_value {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::convert::RelicType for $name {
+            fn relic_type() -> $crate::types::Type {
+                $crate::types::Type::Value(stringify!($name).to_string())
+            }
+        }
+
+        impl $crate::convert::FromEvalValue for $name {
+            fn from_eval_value(value: $crate::evaluator::EvalValue) -> $crate::Result<Self> {
+                let mut fields = $crate::convert::fields_of(value, stringify!($name))?;
+                Ok($name {
+                    $($field: $crate::convert::take_field(&mut fields, stringify!($name), stringify!($field))?,)*
+                })
+            }
+        }
+
+        impl $crate::convert::IntoEvalValue for $name {
+            fn into_eval_value(self) -> $crate::evaluator::EvalValue {
+                let mut fields = ::std::collections::HashMap::new();
+                $(fields.insert(
+                    stringify!($field).to_string(),
+                    $crate::convert::IntoEvalValue::into_eval_value(self.$field),
+                );)*
+                $crate::evaluator::EvalValue::Value {
+                    type_name: stringify!($name).to_string(),
+                    fields,
+                    display: None,
+                    sensitive: false,
+                }
+            }
+        }
+    };
+}
+
+/// The fields of `value`, which must be a value of type `type_name`. Used
+/// by `impl_eval_value!`.
+#[doc(hidden)]
+pub fn fields_of(value: EvalValue, type_name: &str) -> Result<HashMap<String, EvalValue>> {
+    match value {
+        EvalValue::Value { type_name: actual, fields, .. } if actual == type_name => Ok(fields),
+        other => Err(Error::Validation(ValidationError {
+            message: format!("Expected a value of type {}, found {}", type_name, other),
+            value_type: type_name.to_string(),
+        })),
+    }
+}
+
+/// Remove and convert the field `field` of a value of type `type_name`.
+/// Used by `impl_eval_value!`.
+#[doc(hidden)]
+pub fn take_field<T: FromEvalValue>(
+    fields: &mut HashMap<String, EvalValue>,
+    type_name: &str,
+    field: &str,
+) -> Result<T> {
+    let value = fields.remove(field).ok_or_else(|| {
+        Error::Validation(ValidationError {
+            message: format!("Value of type {} has no field '{}'", type_name, field),
+            value_type: type_name.to_string(),
+        })
+    })?;
+    T::from_eval_value(value)
+}
+
+/// The error for a value that isn't of `T`'s Relic type.
+fn mismatch<T: RelicType>(found: &EvalValue) -> Error {
+    Error::Validation(ValidationError {
+        message: format!("Expected a value of type {}, found {}", T::relic_type(), found),
+        value_type: T::relic_type().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: i64,
+        label: Option<String>,
+    }
+    crate::impl_eval_value!(Point { x, label });
+
+    #[test]
+    fn test_collections_and_structs() {
+        let points = vec![
+            Point { x: 1, label: Some("a".to_string()) },
+            Point { x: 2, label: None },
+        ];
+        let value = points.into_eval_value();
+        assert_eq!(<Vec<Point>>::relic_type().to_string(), "List[Point]");
+        let points = <Vec<Point>>::from_eval_value(value).unwrap();
+        assert_eq!(points[1], Point { x: 2, label: None });
+
+        let fields = <HashMap<String, EvalValue>>::from_eval_value(Point { x: 3, label: None }.into_eval_value()).unwrap();
+        assert_eq!(fields["x"], EvalValue::Integer(3));
+
+        assert!(Point::from_eval_value(EvalValue::Integer(3)).is_err());
+        assert!(u8::from_eval_value(EvalValue::Integer(300)).is_err());
+        assert_eq!(u8::from_eval_value(EvalValue::Integer(255)).unwrap(), 255);
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(i64::from_eval_value(42i64.into_eval_value()).unwrap(), 42);
+        assert!(bool::from_eval_value(true.into_eval_value()).unwrap());
+        assert_eq!("hi".into_eval_value(), EvalValue::String("hi".to_string()));
+        let date = temporal::Date::from_ymd(2024, 1, 31).unwrap();
+        assert_eq!(temporal::Date::from_eval_value(date.into_eval_value()).unwrap(), date);
+        assert!(temporal::Time::from_eval_value(date.into_eval_value()).is_err());
+        let price = Decimal::parse("19.99").unwrap();
+        assert_eq!(Decimal::from_eval_value(price.into_eval_value()).unwrap(), price);
+
+        let error = String::from_eval_value(EvalValue::Integer(1)).unwrap_err();
+        assert_eq!(error.to_string(), "Validation error in String: Expected a value of type String, found 1");
+    }
+}
@@ -0,0 +1,454 @@
+//! Guard coverage: whether the guarded overloads of a function leave
+//! inputs that no overload takes, or share inputs that two of them take
+//! equally well. Either is an error only when a call hits it, as "no
+//! matching function" or an ambiguous call, so both are reported as
+//! warnings when the overloads are declared.
+//!
+//! Overloads are compared with the others taking the same parameter types.
+//! A guard is understood when it compares its parameter, an `Int`, with
+//! integer literals, combined with `&&`, `||` and `!`; an overload set
+//! with any other guard, such as `n % 2 == 0`, isn't analyzed, so that
+//! nothing is reported that might not be true.
+
+use crate::ast::{BinaryOp, ComparisonOp, Declaration, Expression, FunctionDeclaration, Literal, Program, UnaryOp};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::formatter::format_type;
+use crate::types::Type;
+use crate::value::ValueRegistry;
+
+// Inputs are split into at most this many regions before giving up, for
+// overloads with guards on many parameters
+const MAX_REGIONS: usize = 4096;
+
+// How many uncovered regions a warning lists
+const SHOWN: usize = 3;
+
+/// Warnings about the guards of the functions `program` declares, each
+/// compared with the overloads `registry` already holds.
+pub fn check(program: &Program, registry: &ValueRegistry) -> Vec<Diagnostic> {
+    let declared: Vec<FunctionDeclaration> = program
+        .declarations
+        .iter()
+        .filter_map(|declaration| match declaration {
+            Declaration::Function(function) => Some(function.clone()),
+            Declaration::Method(method) => Some(method.clone().into()),
+            Declaration::Value(_) => None,
+        })
+        .collect();
+    let mut names: Vec<&str> = Vec::new();
+    for function in &declared {
+        if !names.contains(&function.name.as_str()) {
+            names.push(&function.name);
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for name in names {
+        let mut overloads: Vec<FunctionDeclaration> = registry
+            .get_functions(name)
+            .map(|overloads| overloads.iter().filter(|overload| registry.native(overload).is_none()).cloned().collect())
+            .unwrap_or_default();
+        for function in declared.iter().filter(|function| function.name == name) {
+            if !overloads.contains(function) {
+                overloads.push(function.clone());
+            }
+        }
+        warnings.extend(analyze(&overloads, &declared));
+    }
+    warnings
+}
+
+/// Warnings about the guards of `overloads`, the overloads of one
+/// function, for each set taking the same parameter types that includes
+/// one of `declared`.
+pub fn analyze(overloads: &[FunctionDeclaration], declared: &[FunctionDeclaration]) -> Vec<Diagnostic> {
+    let mut groups: Vec<(Vec<&Type>, Vec<&FunctionDeclaration>)> = Vec::new();
+    for overload in overloads {
+        let types: Vec<&Type> = overload.parameters.iter().map(|p| &p.ty).collect();
+        match groups.iter_mut().find(|(group, _)| *group == types) {
+            Some((_, group)) => group.push(overload),
+            None => groups.push((types, vec![overload])),
+        }
+    }
+
+    let mut warnings = Vec::new();
+    for (types, group) in &groups {
+        let Some(reported) = group.iter().rev().find(|overload| declared.contains(overload)) else {
+            continue;
+        };
+        if group.iter().all(|overload| overload.parameters.iter().all(|p| p.guard.is_none())) {
+            continue;
+        }
+        let Some(regions) = group.iter().map(|overload| region(overload)).collect::<Option<Vec<_>>>() else {
+            continue;
+        };
+        let signature = format!(
+            "{}({})",
+            reported.name,
+            types.iter().map(|ty| format_type(ty)).collect::<Vec<_>>().join(", ")
+        );
+        let names: Vec<&str> = reported.parameters.iter().map(|p| p.name.as_str()).collect();
+
+        // Overloads equally specific, with the same number of guards, make
+        // a call both take ambiguous
+        for (i, first) in group.iter().enumerate() {
+            for (j, second) in group.iter().enumerate().skip(i + 1) {
+                if guards(first) != guards(second) || !(declared.contains(first) || declared.contains(second)) {
+                    continue;
+                }
+                let shared: Vec<Intervals> =
+                    regions[i].iter().zip(&regions[j]).map(|(a, b)| a.intersect(b)).collect();
+                if shared.iter().any(Intervals::is_empty) {
+                    continue;
+                }
+                let later = if declared.contains(second) { second } else { first };
+                warnings.push(warning(
+                    "W0202",
+                    format!(
+                        "Guards of {} on lines {} and {} both match {}; such calls fail as ambiguous",
+                        signature,
+                        first.span.line,
+                        second.span.line,
+                        describe(&names, &shared)
+                    ),
+                    later,
+                ));
+            }
+        }
+
+        // An overload without guards whose types take these arguments
+        // catches whatever the guards leave
+        let fallback = overloads.iter().any(|overload| {
+            overload.parameters.len() == types.len()
+                && overload.parameters.iter().all(|p| p.guard.is_none())
+                && overload.parameters.iter().zip(types).all(|(p, ty)| p.ty.accepts(ty))
+        });
+        if fallback {
+            continue;
+        }
+        if let Some(uncovered) = uncovered(&regions) {
+            if !uncovered.is_empty() {
+                let mut listed: Vec<String> =
+                    uncovered.iter().take(SHOWN).map(|cell| describe(&names, cell)).collect();
+                if uncovered.len() > SHOWN {
+                    listed.push(format!("{} more", uncovered.len() - SHOWN));
+                }
+                warnings.push(warning(
+                    "W0201",
+                    format!(
+                        "Guards of {} leave {} unmatched; such calls fail with no matching overload",
+                        signature,
+                        listed.join("; ")
+                    ),
+                    reported,
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/// Whether the guards of `first` and `second`, overloads with the same
+/// parameter types, can't both hold for any arguments. False when a guard
+/// isn't understood.
+pub(crate) fn disjoint(first: &FunctionDeclaration, second: &FunctionDeclaration) -> bool {
+    match (region(first), region(second)) {
+        (Some(first), Some(second)) => first.iter().zip(&second).any(|(a, b)| a.intersect(b).is_empty()),
+        _ => false,
+    }
+}
+
+fn warning(code: &'static str, message: String, overload: &FunctionDeclaration) -> Diagnostic {
+    let span = overload.span;
+    Diagnostic {
+        severity: Severity::Warning,
+        code,
+        message,
+        location: Some((span.line, span.column, span.length)),
+    }
+}
+
+fn guards(overload: &FunctionDeclaration) -> usize {
+    overload.parameters.iter().filter(|p| p.guard.is_some()).count()
+}
+
+// The arguments an overload's guards let through, one set per parameter,
+// if every guard is understood
+fn region(overload: &FunctionDeclaration) -> Option<Vec<Intervals>> {
+    overload
+        .parameters
+        .iter()
+        .map(|p| match &p.guard {
+            None => Some(Intervals::all()),
+            Some(guard) if p.ty == Type::Int => accepted(guard, &p.name),
+            Some(_) => None,
+        })
+        .collect()
+}
+
+// The values of the Int `name` for which `guard` holds
+fn accepted(guard: &Expression, name: &str) -> Option<Intervals> {
+    match guard {
+        Expression::Literal(Literal::Boolean(true)) => Some(Intervals::all()),
+        Expression::Literal(Literal::Boolean(false)) => Some(Intervals::none()),
+        Expression::Binary(BinaryOp::And, left, right) => {
+            Some(accepted(left, name)?.intersect(&accepted(right, name)?))
+        }
+        Expression::Binary(BinaryOp::Or, left, right) => Some(accepted(left, name)?.union(&accepted(right, name)?)),
+        Expression::Unary(UnaryOp::Not, operand) => Some(accepted(operand, name)?.complement()),
+        Expression::Comparison(op, left, right) => match (left.as_ref(), right.as_ref()) {
+            (Expression::Identifier(parameter), bound) if parameter == name => compared(op, integer(bound)?),
+            (bound, Expression::Identifier(parameter)) if parameter == name => {
+                let flipped = match op {
+                    ComparisonOp::Less => ComparisonOp::Greater,
+                    ComparisonOp::Greater => ComparisonOp::Less,
+                    ComparisonOp::LessEqual => ComparisonOp::GreaterEqual,
+                    ComparisonOp::GreaterEqual => ComparisonOp::LessEqual,
+                    other => other.clone(),
+                };
+                compared(&flipped, integer(bound)?)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn compared(op: &ComparisonOp, bound: i64) -> Option<Intervals> {
+    let interval = |low: Option<i64>, high: Option<i64>| match (low, high) {
+        (Some(low), Some(high)) => Intervals(vec![(low, high)]),
+        // Past the end of Int, nothing is left
+        _ => Intervals::none(),
+    };
+    Some(match op {
+        ComparisonOp::Equal => interval(Some(bound), Some(bound)),
+        ComparisonOp::NotEqual => interval(Some(bound), Some(bound)).complement(),
+        ComparisonOp::Less => interval(Some(i64::MIN), bound.checked_sub(1)),
+        ComparisonOp::LessEqual => interval(Some(i64::MIN), Some(bound)),
+        ComparisonOp::Greater => interval(bound.checked_add(1), Some(i64::MAX)),
+        ComparisonOp::GreaterEqual => interval(Some(bound), Some(i64::MAX)),
+        _ => return None,
+    })
+}
+
+fn integer(expression: &Expression) -> Option<i64> {
+    match expression {
+        Expression::Literal(Literal::Integer(n)) => Some(*n),
+        Expression::Unary(UnaryOp::Minus, operand) => integer(operand)?.checked_neg(),
+        _ => None,
+    }
+}
+
+// The combinations of arguments no region takes, each as one set per
+// parameter, or None if there are too many combinations to try
+fn uncovered(regions: &[Vec<Intervals>]) -> Option<Vec<Vec<Intervals>>> {
+    let arity = regions.first()?.len();
+    // Each parameter's values, split wherever a guard's bounds fall
+    let pieces: Vec<Vec<(i64, i64)>> = (0..arity)
+        .map(|index| {
+            let mut starts = vec![i64::MIN];
+            for region in regions {
+                for &(low, high) in &region[index].0 {
+                    starts.push(low);
+                    starts.extend(high.checked_add(1));
+                }
+            }
+            starts.sort_unstable();
+            starts.dedup();
+            let ends = starts.iter().skip(1).map(|start| start - 1).chain([i64::MAX]);
+            starts.iter().copied().zip(ends).collect()
+        })
+        .collect();
+    if pieces.iter().try_fold(1usize, |count, pieces| count.checked_mul(pieces.len()))? > MAX_REGIONS {
+        return None;
+    }
+
+    let mut cells: Vec<Vec<(i64, i64)>> = vec![Vec::new()];
+    for pieces in &pieces {
+        cells = cells
+            .into_iter()
+            .flat_map(|cell| {
+                pieces.iter().map(move |&piece| {
+                    let mut cell = cell.clone();
+                    cell.push(piece);
+                    cell
+                })
+            })
+            .collect();
+    }
+    let missing = cells.into_iter().filter(|cell| {
+        !regions.iter().any(|region| region.iter().zip(cell).all(|(values, &(low, _))| values.contains(low)))
+    });
+    let mut missing: Vec<Vec<Intervals>> =
+        missing.map(|cell| cell.into_iter().map(|piece| Intervals(vec![piece])).collect()).collect();
+    // Join combinations that differ in one parameter, until none do
+    while let Some((i, j, index)) = mergeable(&missing) {
+        let other = missing.remove(j);
+        missing[i][index] = missing[i][index].union(&other[index]);
+    }
+    Some(missing)
+}
+
+// Two combinations that differ only in the parameter `index`
+fn mergeable(cells: &[Vec<Intervals>]) -> Option<(usize, usize, usize)> {
+    for (i, first) in cells.iter().enumerate() {
+        for (j, second) in cells.iter().enumerate().skip(i + 1) {
+            let differing: Vec<usize> = (0..first.len()).filter(|&index| first[index] != second[index]).collect();
+            if let [index] = differing.as_slice() {
+                return Some((i, j, *index));
+            }
+        }
+    }
+    None
+}
+
+// `n == 0`, or `a >= 1, b <= -1` for several parameters
+fn describe(names: &[&str], values: &[Intervals]) -> String {
+    let constrained: Vec<String> = names
+        .iter()
+        .zip(values)
+        .filter(|(_, values)| **values != Intervals::all())
+        .map(|(name, values)| values.describe(name))
+        .collect();
+    if constrained.is_empty() {
+        "every argument".to_string()
+    } else {
+        constrained.join(", ")
+    }
+}
+
+/// Disjoint, sorted, inclusive ranges of `Int`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Intervals(Vec<(i64, i64)>);
+
+impl Intervals {
+    fn all() -> Self {
+        Intervals(vec![(i64::MIN, i64::MAX)])
+    }
+
+    fn none() -> Self {
+        Intervals(Vec::new())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn contains(&self, n: i64) -> bool {
+        self.0.iter().any(|&(low, high)| low <= n && n <= high)
+    }
+
+    fn union(&self, other: &Intervals) -> Intervals {
+        let mut ranges: Vec<(i64, i64)> = self.0.iter().chain(&other.0).copied().collect();
+        ranges.sort_unstable();
+        let mut merged: Vec<(i64, i64)> = Vec::new();
+        for (low, high) in ranges {
+            match merged.last_mut() {
+                Some(last) if last.1.checked_add(1).is_none_or(|next| low <= next) => last.1 = last.1.max(high),
+                _ => merged.push((low, high)),
+            }
+        }
+        Intervals(merged)
+    }
+
+    fn intersect(&self, other: &Intervals) -> Intervals {
+        let mut ranges = Vec::new();
+        for &(a_low, a_high) in &self.0 {
+            for &(b_low, b_high) in &other.0 {
+                let (low, high) = (a_low.max(b_low), a_high.min(b_high));
+                if low <= high {
+                    ranges.push((low, high));
+                }
+            }
+        }
+        Intervals(ranges).union(&Intervals::none())
+    }
+
+    fn complement(&self) -> Intervals {
+        let mut ranges = Vec::new();
+        let mut next = Some(i64::MIN);
+        for &(low, high) in &self.0 {
+            if let Some(start) = next {
+                if start < low {
+                    ranges.push((start, low - 1));
+                }
+            }
+            next = high.checked_add(1);
+        }
+        if let Some(start) = next {
+            ranges.push((start, i64::MAX));
+        }
+        Intervals(ranges)
+    }
+
+    fn describe(&self, name: &str) -> String {
+        let ranges: Vec<String> = self
+            .0
+            .iter()
+            .map(|&(low, high)| match (low, high) {
+                (low, high) if low == high => format!("{} == {}", name, low),
+                (i64::MIN, high) => format!("{} <= {}", name, high),
+                (low, i64::MAX) => format!("{} >= {}", name, low),
+                (low, high) => format!("{} <= {} <= {}", low, name, high),
+            })
+            .collect();
+        ranges.join(" or ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    fn warnings(source: &str) -> Vec<String> {
+        let mut engine = Engine::new();
+        engine.load(source).unwrap();
+        engine.take_warnings().into_iter().map(|w| format!("{} {}", w.code, w.message)).collect()
+    }
+
+    #[test]
+    fn test_guard_coverage() {
+        // Complete and disjoint
+        let complete = "fn classify(n: Int where n < 0) -> String { \"negative\" }
+                        fn classify(n: Int where n == 0) -> String { \"zero\" }
+                        fn classify(n: Int where n > 0) -> String { \"positive\" }";
+        assert_eq!(warnings(complete), Vec::<String>::new());
+
+        let gaps = "fn classify(n: Int where n < 0) -> String { \"negative\" }
+                    fn classify(n: Int where n > 0 && n != 5) -> String { \"positive\" }";
+        assert_eq!(
+            warnings(gaps),
+            ["W0201 Guards of classify(Int) leave n == 0 or n == 5 unmatched; \
+              such calls fail with no matching overload"]
+        );
+
+        let overlap = "fn size(n: Int where n <= 10) -> String { \"small\" }
+                       fn size(n: Int where 5 <= n) -> String { \"large\" }";
+        assert_eq!(
+            warnings(overlap),
+            ["W0202 Guards of size(Int) on lines 1 and 2 both match 5 <= n <= 10; such calls fail as ambiguous"]
+        );
+
+        // A fallback catches the gaps; an unguarded overload is less specific
+        let fallback = "fn sign(n: Int where n > 0) -> Int { 1 }
+                        fn sign(n: Int) -> Int { 0 }";
+        assert_eq!(warnings(fallback), Vec::<String>::new());
+
+        // Guards that aren't understood aren't analyzed
+        assert_eq!(warnings("fn parity(n: Int where n % 2 == 0) -> String { \"even\" }"), Vec::<String>::new());
+
+        let two = "fn quadrant(x: Int where x >= 0, y: Int where y >= 0) -> Int { 1 }
+                   fn quadrant(x: Int where x < 0, y: Int where y >= 0) -> Int { 2 }";
+        assert_eq!(
+            warnings(two),
+            ["W0201 Guards of quadrant(Int, Int) leave y <= -1 unmatched; such calls fail with no matching overload"]
+        );
+
+        // Overloads declared separately are compared at the later one
+        let mut engine = Engine::new();
+        engine.load("fn level(n: Int where n < 3) -> Int { 0 }").unwrap();
+        assert_eq!(engine.take_warnings().len(), 1);
+        engine.load("fn level(n: Int where n >= 3) -> Int { 1 }").unwrap();
+        assert!(engine.take_warnings().is_empty());
+    }
+}
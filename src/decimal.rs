@@ -0,0 +1,406 @@
+//! Fixed-point decimal numbers, for amounts of money and other quantities
+//! that must add up exactly. A literal with a decimal point, such as
+//! `19.99` or `0.050`, is a `Decimal`, and keeps the number of places it
+//! was written with: `1.50 + 2.25` is `3.75`, and `1.5 * 2.00` is `3.000`.
+//!
+//! Adding, subtracting, multiplying and taking remainders are exact; an
+//! `Int` on either side is taken as a decimal with no places. Division
+//! rounds half to even at `DIVISION_PLACES` places, then drops trailing
+//! zeros down to the places of its operands, so `10.00 / 4` is `2.50` and
+//! `1.00 / 3` is `0.3333333333333333`. `round(places)` rounds half to even,
+//! banker's rounding, which doesn't drift upwards when summing rounded
+//! amounts: `2.345.round(2)` is `2.34` and `2.355.round(2)` is `2.36`.
+
+use crate::ast::{BinaryOp, ComparisonOp};
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::EvalValue;
+use crate::types::Type;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// The most places a decimal can have.
+pub const MAX_PLACES: u32 = 28;
+
+/// The places a quotient is rounded to, before trailing zeros are dropped.
+pub const DIVISION_PLACES: u32 = 16;
+
+/// A decimal number, as a whole number of units of `10^-places`. Decimals
+/// that differ only in places, such as `1.5` and `1.50`, are equal.
+#[derive(Debug, Clone, Copy)]
+pub struct Decimal {
+    units: i128,
+    places: u32,
+}
+
+impl Decimal {
+    /// `units` of `10^-places`, or None if that has too many places.
+    pub fn new(units: i128, places: u32) -> Option<Decimal> {
+        (places <= MAX_PLACES).then_some(Decimal { units, places })
+    }
+
+    pub fn from_int(n: i64) -> Decimal {
+        Decimal { units: n as i128, places: 0 }
+    }
+
+    /// Digits with a decimal point between them, such as `12.50`,
+    /// optionally negated with a leading `-`.
+    pub fn parse(text: &str) -> Option<Decimal> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text),
+        };
+        let (whole, fraction) = digits.split_once('.')?;
+        let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+        if !is_digits(whole) || !is_digits(fraction) {
+            return None;
+        }
+        let places = u32::try_from(fraction.len()).ok()?;
+        let units: i128 = format!("{}{}", whole, fraction).parse().ok()?;
+        Decimal::new(if negative { -units } else { units }, places)
+    }
+
+    pub fn places(&self) -> u32 {
+        self.places
+    }
+
+    pub fn abs(&self) -> Decimal {
+        Decimal { units: self.units.abs(), places: self.places }
+    }
+
+    pub fn negate(&self) -> Decimal {
+        Decimal { units: -self.units, places: self.places }
+    }
+
+    /// The decimal with exactly `places` places, rounded half to even if
+    /// that drops digits.
+    pub fn round(&self, places: u32) -> Option<Decimal> {
+        if places > MAX_PLACES {
+            return None;
+        }
+        if places >= self.places {
+            let units = self.units.checked_mul(power_of_ten(places - self.places)?)?;
+            return Some(Decimal { units, places });
+        }
+        let units = divide_half_even(self.units, power_of_ten(self.places - places)?);
+        Some(Decimal { units, places })
+    }
+
+    /// The same number, with trailing zeros dropped down to `places`.
+    fn trimmed(mut self, places: u32) -> Decimal {
+        while self.places > places && self.units % 10 == 0 {
+            self.units /= 10;
+            self.places -= 1;
+        }
+        self
+    }
+
+    /// Both decimals' units at the places of whichever has more.
+    fn aligned(&self, other: &Decimal) -> Option<(i128, i128, u32)> {
+        let places = self.places.max(other.places);
+        let left = self.units.checked_mul(power_of_ten(places - self.places)?)?;
+        let right = other.units.checked_mul(power_of_ten(places - other.places)?)?;
+        Some((left, right, places))
+    }
+
+    pub fn checked_add(&self, other: &Decimal) -> Option<Decimal> {
+        let (left, right, places) = self.aligned(other)?;
+        Some(Decimal { units: left.checked_add(right)?, places })
+    }
+
+    pub fn checked_sub(&self, other: &Decimal) -> Option<Decimal> {
+        let (left, right, places) = self.aligned(other)?;
+        Some(Decimal { units: left.checked_sub(right)?, places })
+    }
+
+    pub fn checked_mul(&self, other: &Decimal) -> Option<Decimal> {
+        let units = self.units.checked_mul(other.units)?;
+        let places = self.places + other.places;
+        let product = Decimal { units, places }.trimmed(MAX_PLACES);
+        if product.places > MAX_PLACES {
+            return product.round(MAX_PLACES);
+        }
+        Some(product)
+    }
+
+    /// The quotient, rounded half to even at `DIVISION_PLACES` places, or
+    /// None if `other` is zero or the quotient is too large.
+    pub fn checked_div(&self, other: &Decimal) -> Option<Decimal> {
+        if other.units == 0 {
+            return None;
+        }
+        let (left, right, places) = self.aligned(other)?;
+        let scaled = left.checked_mul(power_of_ten(DIVISION_PLACES)?)?;
+        let units = divide_half_even(scaled, right);
+        Some(Decimal { units, places: DIVISION_PLACES }.trimmed(places.min(DIVISION_PLACES)))
+    }
+
+    /// The square root, rounded down at half of `DIVISION_PLACES` places,
+    /// with trailing zeros dropped, or None if the decimal is negative.
+    pub fn checked_sqrt(&self) -> Option<Decimal> {
+        if self.units < 0 {
+            return None;
+        }
+        let places = DIVISION_PLACES / 2;
+        let square = self.round(places * 2)?;
+        Some(Decimal { units: square.units.isqrt(), places }.trimmed(0))
+    }
+
+    /// The remainder of dividing by `other`, with the sign of `self`, or
+    /// None if `other` is zero.
+    pub fn checked_rem(&self, other: &Decimal) -> Option<Decimal> {
+        let (left, right, places) = self.aligned(other)?;
+        Some(Decimal { units: left.checked_rem(right)?, places })
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Decimal) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Decimal) -> Ordering {
+        match self.aligned(other) {
+            Some((left, right, _)) => left.cmp(&right),
+            // Aligning overflows only when the one with fewer places is
+            // larger in magnitude than any number the other can hold
+            None if self.places < other.places => self.units.signum().cmp(&0),
+            None => 0.cmp(&other.units.signum()),
+        }
+    }
+}
+
+impl Hash for Decimal {
+    // Equal decimals hash alike whatever their places
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let trimmed = self.trimmed(0);
+        trimmed.units.hash(state);
+        trimmed.places.hash(state);
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.units < 0 {
+            write!(f, "-")?;
+        }
+        let digits = self.units.unsigned_abs().to_string();
+        let places = self.places as usize;
+        if places == 0 {
+            return write!(f, "{}", digits);
+        }
+        let digits = format!("{:0>width$}", digits, width = places + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - places);
+        write!(f, "{}.{}", whole, fraction)
+    }
+}
+
+/// The type of `left op right` when either side is a decimal and the other
+/// a decimal or an `Int`, or None if the operator doesn't apply to them.
+pub fn operator_type(op: &BinaryOp, left: &Type, right: &Type) -> Option<Type> {
+    let arithmetic = matches!(
+        op,
+        BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo
+    );
+    match (left, right) {
+        (Type::Decimal, Type::Decimal | Type::Int) | (Type::Int, Type::Decimal) if arithmetic => Some(Type::Decimal),
+        _ => None,
+    }
+}
+
+/// Whether values of `left` and `right` can be compared as numbers.
+pub fn comparable(left: &Type, right: &Type) -> bool {
+    matches!((left, right), (Type::Decimal, Type::Decimal | Type::Int) | (Type::Int, Type::Decimal))
+}
+
+/// `value` as a decimal, if it is a decimal or an integer.
+fn as_decimal(value: &EvalValue) -> Option<Decimal> {
+    match value {
+        EvalValue::Decimal(d) => Some(*d),
+        EvalValue::Integer(n) => Some(Decimal::from_int(*n)),
+        _ => None,
+    }
+}
+
+/// `left op right`, where one side is a decimal and the other a decimal or
+/// an integer.
+pub fn compare(op: &ComparisonOp, left: &EvalValue, right: &EvalValue) -> Result<bool> {
+    let (Some(l), Some(r)) = (as_decimal(left), as_decimal(right)) else {
+        return Err(mismatch("comparison"));
+    };
+    match op {
+        ComparisonOp::Equal => Ok(l == r),
+        ComparisonOp::NotEqual => Ok(l != r),
+        ComparisonOp::Less => Ok(l < r),
+        ComparisonOp::Greater => Ok(l > r),
+        ComparisonOp::LessEqual => Ok(l <= r),
+        ComparisonOp::GreaterEqual => Ok(l >= r),
+        ComparisonOp::Contains => Err(mismatch("comparison")),
+    }
+}
+
+/// `left op right`, where one side is a decimal and the other a decimal or
+/// an integer.
+pub fn arithmetic(op: &BinaryOp, left: &EvalValue, right: &EvalValue) -> Result<EvalValue> {
+    let (Some(l), Some(r)) = (as_decimal(left), as_decimal(right)) else {
+        return Err(mismatch("binary operation"));
+    };
+    let result = match op {
+        BinaryOp::Add => l.checked_add(&r),
+        BinaryOp::Subtract => l.checked_sub(&r),
+        BinaryOp::Multiply => l.checked_mul(&r),
+        BinaryOp::Divide if r.units == 0 => return Err(by_zero("Division by zero")),
+        BinaryOp::Divide => l.checked_div(&r),
+        BinaryOp::Modulo if r.units == 0 => return Err(by_zero("Modulo by zero")),
+        BinaryOp::Modulo => l.checked_rem(&r),
+        BinaryOp::And | BinaryOp::Or => return Err(mismatch("binary operation")),
+    };
+    result.map(EvalValue::Decimal).ok_or_else(overflow)
+}
+
+/// The result of the built-in method `name` of a decimal.
+pub fn method(decimal: &Decimal, name: &str, args: &[EvalValue]) -> Result<EvalValue> {
+    match (name, args) {
+        ("round", [EvalValue::Integer(places)]) => u32::try_from(*places)
+            .ok()
+            .and_then(|places| decimal.round(places))
+            .map(EvalValue::Decimal)
+            .ok_or_else(|| {
+                Error::Validation(ValidationError {
+                    message: format!("Can't round to {} places: a decimal has 0 to {}", places, MAX_PLACES),
+                    value_type: "Decimal".to_string(),
+                })
+            }),
+        ("places", []) => Ok(EvalValue::Integer(decimal.places as i64)),
+        ("abs", []) => Ok(EvalValue::Decimal(decimal.abs())),
+        _ => Err(Error::Validation(ValidationError {
+            message: format!("Decimal has no method '{}' taking {} arguments", name, args.len()),
+            value_type: "Decimal".to_string(),
+        })),
+    }
+}
+
+/// `10^exponent`, if it fits.
+fn power_of_ten(exponent: u32) -> Option<i128> {
+    10i128.checked_pow(exponent)
+}
+
+/// `numerator / denominator`, rounded half to even.
+fn divide_half_even(numerator: i128, denominator: i128) -> i128 {
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    // Compare twice the remainder with the denominator, without overflowing
+    let half = denominator.unsigned_abs() - remainder.unsigned_abs();
+    let away = match remainder.unsigned_abs().cmp(&half) {
+        Ordering::Less => false,
+        Ordering::Greater => true,
+        Ordering::Equal => quotient % 2 != 0,
+    };
+    if !away {
+        quotient
+    } else if (numerator < 0) == (denominator < 0) {
+        quotient + 1
+    } else {
+        quotient - 1
+    }
+}
+
+// As the evaluator reports operands of the wrong types
+fn mismatch(operation: &str) -> Error {
+    Error::Validation(ValidationError {
+        message: format!("Type mismatch in {}", operation),
+        value_type: "".to_string(),
+    })
+}
+
+fn by_zero(message: &str) -> Error {
+    Error::Validation(ValidationError { message: message.to_string(), value_type: "".to_string() })
+}
+
+fn overflow() -> Error {
+    Error::Validation(ValidationError {
+        message: "The decimal is too large".to_string(),
+        value_type: "Decimal".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+
+    fn decimal(text: &str) -> Decimal {
+        Decimal::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        for text in ["0.5", "12.50", "-0.05", "100.000", "0.0000000000000000000000000001"] {
+            assert_eq!(decimal(text).to_string(), text);
+        }
+        for text in ["12", ".5", "5.", "1.2.3", "1e5", "--1.0", "0.00000000000000000000000000001"] {
+            assert_eq!(Decimal::parse(text), None, "{}", text);
+        }
+        assert_eq!(decimal("1.5"), decimal("1.50"));
+        assert!(decimal("-2.01") < decimal("-2.0"));
+    }
+
+    #[test]
+    fn test_arithmetic_and_rounding() {
+        let calc = |l: &str, op: BinaryOp, r: &str| {
+            let value = |text: &str| match Decimal::parse(text) {
+                Some(d) => EvalValue::Decimal(d),
+                None => EvalValue::Integer(text.parse().unwrap()),
+            };
+            arithmetic(&op, &value(l), &value(r)).unwrap().to_string()
+        };
+        assert_eq!(calc("0.10", BinaryOp::Add, "0.2"), "0.30");
+        assert_eq!(calc("1.5", BinaryOp::Multiply, "2.00"), "3.000");
+        assert_eq!(calc("19.99", BinaryOp::Multiply, "3"), "59.97");
+        assert_eq!(calc("10.00", BinaryOp::Divide, "4"), "2.50");
+        assert_eq!(calc("1.00", BinaryOp::Divide, "3"), "0.3333333333333333");
+        assert_eq!(calc("2.00", BinaryOp::Divide, "3"), "0.6666666666666667");
+        assert_eq!(calc("-7.5", BinaryOp::Modulo, "2"), "-1.5");
+        assert!(arithmetic(&BinaryOp::Divide, &EvalValue::Decimal(decimal("1.0")), &EvalValue::Integer(0)).is_err());
+
+        // Half to even: ties go to the even neighbour, either side of zero
+        for (text, rounded) in [("2.345", "2.34"), ("2.355", "2.36"), ("2.3451", "2.35"), ("-2.345", "-2.34"), ("-2.355", "-2.36")] {
+            assert_eq!(decimal(text).round(2).unwrap().to_string(), rounded);
+        }
+        assert_eq!(decimal("0.5").round(0).unwrap().to_string(), "0");
+        assert_eq!(decimal("1.5").round(3).unwrap().to_string(), "1.500");
+
+        assert_eq!(decimal("6.25").checked_sqrt().unwrap().to_string(), "2.5");
+        assert_eq!(Decimal::from_int(2).checked_sqrt().unwrap().to_string(), "1.41421356");
+        assert!(decimal("-1.0").checked_sqrt().is_none());
+    }
+
+    #[test]
+    fn test_decimals_in_relic() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value Price(amount: Decimal) { validate: amount >= 0 && amount.places() <= 2 }\n\
+                 fn withTax(p: Decimal) -> Decimal { (p * 1.2).round(2) }",
+            )
+            .unwrap();
+        let eval = |source: &str| engine.eval(source).map(|value| value.to_string());
+        assert_eq!(eval("withTax(9.99)").unwrap(), "11.99");
+        assert_eq!(eval("0.1 + 0.2 == 0.3").unwrap(), "true");
+        assert_eq!(eval("-1.25").unwrap(), "-1.25");
+        assert_eq!(eval("Price(4.50)").unwrap(), "Price(4.50)");
+        assert!(eval("Price(4.505)").is_err());
+        assert!(eval("Price(-1.00)").is_err());
+        assert!(eval("1.0 + true").is_err());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::error::Error;
+use std::io::IsTerminal;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+/// A single message about the source, ready to be rendered.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable code: `E....` for errors (see `Error::code`), `W0101` for
+    /// ambiguous comparison chains, `W0201` and `W0202` for guards that
+    /// leave inputs unmatched or overlap (see `coverage`)
+    pub code: &'static str,
+    pub message: String,
+    /// (line, column, length) of the offending source, 1-based
+    pub location: Option<(usize, usize, usize)>,
+}
+
+impl Diagnostic {
+    pub fn from_error(error: &Error) -> Self {
+        let mut message = match error {
+            Error::Lexer(e) => e.message.clone(),
+            Error::Parser(e) => e.message.clone(),
+            Error::Type(e) => e.message.clone(),
+            Error::Validation(e) if e.value_type.is_empty() => e.message.clone(),
+            Error::Validation(e) => format!("{} (in {})", e.message, e.value_type),
+            Error::Module(e) => e.message.clone(),
+            Error::Internal(e) => e.message.clone(),
+            Error::Cancelled => error.to_string(),
+        };
+        // Causes are not part of an error's Display; spell them out here
+        let mut source = std::error::Error::source(error);
+        while let Some(cause) = source {
+            message.push_str(&format!(": {}", cause));
+            source = cause.source();
+        }
+
+        Diagnostic {
+            severity: Severity::Error,
+            code: error.code(),
+            message,
+            location: error.location(),
+        }
+    }
+
+    /// Encode the diagnostic as a single-line JSON object for editors and CI.
+    pub fn to_json(&self, origin: Option<&str>) -> String {
+        let file = match origin {
+            Some(origin) => json_string(origin),
+            None => "null".to_string(),
+        };
+        let span = match self.location {
+            Some((line, column, length)) => format!(
+                "{{\"line\":{},\"column\":{},\"length\":{}}}",
+                line, column, length
+            ),
+            None => "null".to_string(),
+        };
+
+        format!(
+            "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\"span\":{}}}",
+            json_string(self.severity.label()),
+            json_string(self.code),
+            json_string(&self.message),
+            file,
+            span
+        )
+    }
+}
+
+/// `s` as a quoted, escaped JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// How diagnostics are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Code frames for people
+    Human,
+    /// One JSON object per line for tools
+    Json,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(error: &Error) -> Self {
+        Diagnostic::from_error(error)
+    }
+}
+
+/// Formats diagnostics with the offending source line and a caret underline.
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Self {
+        Self { color }
+    }
+
+    /// Colored output when stderr is a terminal, unless `NO_COLOR` is set.
+    pub fn from_env() -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        Self::new(!no_color && std::io::stderr().is_terminal())
+    }
+
+    pub fn render_error(&self, error: &Error, source: &str, origin: Option<&str>) -> String {
+        self.render(&Diagnostic::from_error(error), source, origin)
+    }
+
+    /// Render a diagnostic against `source`. `origin` names the input
+    /// (usually a file path) in the location line.
+    pub fn render(&self, diagnostic: &Diagnostic, source: &str, origin: Option<&str>) -> String {
+        let severity = diagnostic.severity;
+        let mut out = format!(
+            "{}{}[{}]{}{}: {}{}\n",
+            self.paint(severity.color()),
+            severity.label(),
+            diagnostic.code,
+            self.paint(RESET),
+            self.paint(BOLD),
+            self.highlight(&diagnostic.message),
+            self.paint(RESET),
+        );
+
+        let (line, column, length) = match diagnostic.location {
+            Some(location) => location,
+            None => return out,
+        };
+
+        let gutter = " ".repeat(line.to_string().len());
+        let location = match origin {
+            Some(origin) => format!("{}:{}:{}", origin, line, column),
+            None => format!("{}:{}", line, column),
+        };
+        out.push_str(&format!("{}{}-->{} {}\n", gutter, self.paint(BLUE), self.paint(RESET), location));
+
+        let source_line = match source.lines().nth(line.saturating_sub(1)) {
+            Some(source_line) => source_line,
+            None => return out,
+        };
+
+        // Keep tabs in the padding so the carets line up with the source
+        let chars: Vec<char> = source_line.chars().collect();
+        let start = column.saturating_sub(1).min(chars.len());
+        let padding: String = chars[..start]
+            .iter()
+            .map(|&c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = length.min(chars.len() - start).max(1);
+
+        out.push_str(&format!("{} {}|{}\n", gutter, self.paint(BLUE), self.paint(RESET)));
+        out.push_str(&format!(
+            "{}{} |{} {}\n",
+            self.paint(BLUE),
+            line,
+            self.paint(RESET),
+            source_line
+        ));
+        out.push_str(&format!(
+            "{} {}|{} {}{}{}{}\n",
+            gutter,
+            self.paint(BLUE),
+            self.paint(RESET),
+            padding,
+            self.paint(severity.color()),
+            "^".repeat(width),
+            self.paint(RESET),
+        ));
+
+        out
+    }
+
+    /// Color the `quoted` parts of a message, such as the expected and found
+    /// types of a mismatch, so they stand out from the bold message text.
+    fn highlight(&self, message: &str) -> String {
+        // An unpaired backtick is not a quote
+        if !self.color || message.matches('`').count() % 2 == 1 {
+            return message.to_string();
+        }
+        let mut out = String::new();
+        for (i, part) in message.split('`').enumerate() {
+            if i % 2 == 1 {
+                out.push_str(&format!("{}`{}`{}{}", CYAN, part, RESET, BOLD));
+            } else {
+                out.push_str(part);
+            }
+        }
+        out
+    }
+
+    fn paint(&self, code: &'static str) -> &'static str {
+        if self.color {
+            code
+        } else {
+            ""
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse_error(source: &str) -> Error {
+        let lexer = Lexer::new(source.to_string());
+        Parser::new(lexer)
+            .and_then(|mut parser| parser.parse_program())
+            .expect_err("expected a parse error")
+    }
+
+    #[test]
+    fn test_code_frame_underlines_token() {
+        let source = "fn ok(x: Int) -> Int { x }\nfn bad(a Int) -> Int { a }";
+        let error = parse_error(source);
+        let rendered = Renderer::new(false).render_error(&error, source, Some("bad.relic"));
+
+        assert_eq!(
+            rendered,
+            "error[E0101]: Expected Colon, found Identifier(\"Int\")\n \
+             --> bad.relic:2:10\n  \
+             |\n\
+             2 | fn bad(a Int) -> Int { a }\n  \
+             |          ^^^\n"
+        );
+    }
+
+    #[test]
+    fn test_error_without_location_has_no_frame() {
+        let error = Error::Validation(crate::error::ValidationError {
+            message: "Validation failed".to_string(),
+            value_type: "EmailAddress".to_string(),
+        });
+        let rendered = Renderer::new(false).render_error(&error, "", None);
+
+        assert_eq!(rendered, "error[E0301]: Validation failed (in EmailAddress)\n");
+    }
+
+    #[test]
+    fn test_caret_at_end_of_input() {
+        let source = "fn f(x: Int) -> Int { x +";
+        let error = parse_error(source);
+        let rendered = Renderer::new(false).render_error(&error, source, None);
+
+        assert!(rendered.contains(" --> 1:26\n"));
+        assert!(rendered.ends_with(&format!("| {}^\n", " ".repeat(25))));
+    }
+
+    #[test]
+    fn test_json_output() {
+        let source = "fn ok(x: Int) -> Int { x }\nfn bad(a Int) -> Int { a }";
+        let diagnostic = Diagnostic::from_error(&parse_error(source));
+
+        assert_eq!(
+            diagnostic.to_json(Some("bad.relic")),
+            "{\"severity\":\"error\",\"code\":\"E0101\",\
+             \"message\":\"Expected Colon, found Identifier(\\\"Int\\\")\",\
+             \"file\":\"bad.relic\",\"span\":{\"line\":2,\"column\":10,\"length\":3}}"
+        );
+    }
+
+    #[test]
+    fn test_json_output_without_location() {
+        let error = Error::Validation(crate::error::ValidationError {
+            message: "line one\nline two".to_string(),
+            value_type: String::new(),
+        });
+        let json = Diagnostic::from_error(&error).to_json(None);
+
+        assert_eq!(
+            json,
+            "{\"severity\":\"error\",\"code\":\"E0301\",\
+             \"message\":\"line one\\nline two\",\"file\":null,\"span\":null}"
+        );
+    }
+
+    #[test]
+    fn test_color_output() {
+        let source = "value V(x: Int) { bogus }";
+        let error = parse_error(source);
+
+        let plain = Renderer::new(false).render_error(&error, source, None);
+        let colored = Renderer::new(true).render_error(&error, source, None);
+
+        assert!(!plain.contains('\x1b'));
+        assert!(colored.starts_with(RED));
+        assert!(colored.contains(&format!("{}^^^^^{}", RED, RESET)));
+    }
+
+    #[test]
+    fn test_quoted_types_are_highlighted() {
+        let error = Error::Type(crate::error::TypeError {
+            message: "Mismatched types: expected `Int`, found `List[String]`".to_string(),
+            span: None,
+        });
+
+        let colored = Renderer::new(true).render_error(&error, "", None);
+        assert!(colored.contains(&format!(
+            "Mismatched types: expected {}`Int`{}{}, found {}`List[String]`{}{}",
+            CYAN, RESET, BOLD, CYAN, RESET, BOLD
+        )));
+        let plain = Renderer::new(false).render_error(&error, "", None);
+        assert!(plain.contains("expected `Int`, found `List[String]`"));
+    }
+}
@@ -0,0 +1,330 @@
+//! Dispatch tables: a function's overloads compiled into a decision tree,
+//! so that a call finds the overloads that can take its arguments by their
+//! type tags alone (`Int`, `Date`, `Circle`, `List`...) rather than
+//! matching and scoring every overload. The tree branches on each
+//! argument's tag in turn and ends in the overloads that take those tags,
+//! most specific first; only their guards are left to evaluate.
+//!
+//! Where the guards of a tier's overloads can't both hold, as far as
+//! `coverage` can tell, the first guard that holds decides; otherwise every
+//! guard is evaluated, to find ambiguous calls.
+//!
+//! A registry compiles an overload set's table at its first call, and drops
+//! it when an overload of the function is defined or removed. The linear
+//! scan it replaces is still there, with the registry's dispatch cache, for
+//! comparison (`benches/dispatch.rs`). The cache only answers for functions
+//! without guards.
+
+use crate::ast::FunctionDeclaration;
+use crate::coverage;
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::{self, EvalValue};
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// How a call chooses among a function's overloads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Look the overloads up in the function's dispatch table
+    #[default]
+    Table,
+    /// Score every overload, or take the one the registry's dispatch cache
+    /// holds for the argument types
+    Scan,
+}
+
+/// The overloads of one function, by the type tags of the arguments they
+/// take.
+#[derive(Debug)]
+pub struct DispatchTable {
+    arities: HashMap<usize, Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    /// The next argument's tag picks the branch; a tag no overload names
+    /// explicitly goes to `other`, where only `Any` parameters are left
+    Switch { arms: HashMap<String, Node>, other: Box<Node> },
+    /// The overloads that take the arguments' tags, most specific first
+    Leaf(Vec<Tier>),
+}
+
+/// Overloads equally specific for the arguments that reach them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tier {
+    /// Indexes into the function's overloads
+    pub overloads: Vec<usize>,
+    /// Whether no two of their guards can hold for the same arguments
+    pub exclusive: bool,
+}
+
+impl DispatchTable {
+    pub fn compile(overloads: &[FunctionDeclaration]) -> Self {
+        let mut by_arity: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, overload) in overloads.iter().enumerate() {
+            by_arity.entry(overload.parameters.len()).or_default().push(index);
+        }
+        let arities = by_arity
+            .into_iter()
+            .map(|(arity, candidates)| (arity, node(overloads, 0, arity, candidates)))
+            .collect();
+        DispatchTable { arities }
+    }
+
+    /// The overloads that take arguments of the types of `arguments`,
+    /// before their guards are evaluated, in tiers of equal specificity.
+    pub fn candidates(&self, arguments: &[EvalValue]) -> &[Tier] {
+        let Some(mut node) = self.arities.get(&arguments.len()) else {
+            return &[];
+        };
+        for argument in arguments {
+            node = match node {
+                Node::Switch { arms, other } => arms.get(evaluator::type_tag(argument)).unwrap_or(other),
+                Node::Leaf(_) => break,
+            };
+        }
+        match node {
+            Node::Leaf(tiers) => tiers,
+            Node::Switch { .. } => &[],
+        }
+    }
+
+    /// The index of the overload of `name` a call with `arguments` runs:
+    /// the most specific of those whose types and guards take them.
+    pub fn resolve(
+        &self,
+        name: &str,
+        overloads: &[FunctionDeclaration],
+        arguments: &[EvalValue],
+        registry: &ValueRegistry,
+    ) -> Result<usize> {
+        for tier in self.candidates(arguments) {
+            let mut applicable = tier
+                .overloads
+                .iter()
+                .filter(|&&index| evaluator::guards_hold(&overloads[index], arguments, registry));
+            let first = applicable.next();
+            if tier.exclusive {
+                if let Some(&index) = first {
+                    return Ok(index);
+                }
+                continue;
+            }
+            match (first, applicable.next()) {
+                (Some(&index), None) => return Ok(index),
+                (Some(_), Some(_)) => {
+                    return Err(Error::Validation(ValidationError {
+                        message: format!(
+                            "Ambiguous function call '{}' - multiple functions with same specificity",
+                            name
+                        ),
+                        value_type: "function".to_string(),
+                    }))
+                }
+                _ => {}
+            }
+        }
+        Err(Error::Validation(ValidationError {
+            message: format!("No matching function '{}' found for given arguments", name),
+            value_type: "function".to_string(),
+        }))
+    }
+}
+
+// The subtree for `candidates`, which take the tags of the arguments
+// before `position`
+fn node(overloads: &[FunctionDeclaration], position: usize, arity: usize, candidates: Vec<usize>) -> Node {
+    if position == arity {
+        let mut tiers: Vec<(u32, Vec<usize>)> = Vec::new();
+        for index in candidates {
+            let specificity = evaluator::calculate_function_specificity(&overloads[index]);
+            match tiers.iter_mut().find(|(tier, _)| *tier == specificity) {
+                Some((_, tier)) => tier.push(index),
+                None => tiers.push((specificity, vec![index])),
+            }
+        }
+        tiers.sort_by_key(|(specificity, _)| std::cmp::Reverse(*specificity));
+        let tiers = tiers.into_iter().map(|(_, members)| {
+            let exclusive = members.iter().enumerate().all(|(i, &first)| {
+                members[i + 1..].iter().all(|&second| coverage::disjoint(&overloads[first], &overloads[second]))
+            });
+            Tier { overloads: members, exclusive }
+        });
+        return Node::Leaf(tiers.collect());
+    }
+
+    let tag_of = |index: usize| parameter_tag(&overloads[index].parameters[position].ty);
+    let mut tags: Vec<&str> = candidates.iter().filter_map(|&index| tag_of(index)).collect();
+    tags.sort_unstable();
+    tags.dedup();
+    let arms = tags
+        .into_iter()
+        .map(|tag| {
+            let taking = candidates.iter().copied().filter(|&index| tag_of(index).is_none_or(|own| own == tag));
+            (tag.to_string(), node(overloads, position + 1, arity, taking.collect()))
+        })
+        .collect();
+    let any = candidates.iter().copied().filter(|&index| tag_of(index).is_none()).collect();
+    Node::Switch {
+        arms,
+        other: Box::new(node(overloads, position + 1, arity, any)),
+    }
+}
+
+// The tag of the arguments a parameter of type `ty` takes, or None for
+// `Any`, which takes every tag
+pub(crate) fn parameter_tag(ty: &Type) -> Option<&str> {
+    Some(match ty {
+        Type::Any => return None,
+        Type::Int => "Int",
+        Type::String => "String",
+        Type::Bool => "Bool",
+        Type::Decimal => "Decimal",
+        Type::Date => "Date",
+        Type::Time => "Time",
+        Type::Duration => "Duration",
+        Type::Type => "Type",
+        Type::List(_) => "List",
+        Type::Option(_) => "Option",
+        Type::Relation => "Relation",
+        Type::Query(_) => "Query",
+        Type::Value(name) => name,
+        // Takes no argument
+        Type::Unknown => "",
+    })
+}
+
+/// The overload each call resolved to by scanning, by function name and the
+/// type signatures of the arguments; a registry's own, like its tables.
+#[derive(Debug, Default)]
+pub(crate) struct DispatchCache {
+    overloads: RwLock<HashMap<(String, Vec<String>), usize>>,
+}
+
+impl DispatchCache {
+    pub(crate) fn get(&self, name: &str, signatures: &[String]) -> Option<usize> {
+        let overloads = self.overloads.read().ok()?;
+        overloads.get(&(name.to_string(), signatures.to_vec())).copied()
+    }
+
+    pub(crate) fn insert(&self, name: &str, signatures: Vec<String>, overload: usize) {
+        if let Ok(mut overloads) = self.overloads.write() {
+            overloads.insert((name.to_string(), signatures), overload);
+        }
+    }
+
+    /// Drop what was resolved for `name`, whose overloads changed.
+    pub(crate) fn forget(&mut self, name: &str) {
+        if let Ok(overloads) = self.overloads.get_mut() {
+            overloads.retain(|(function, _), _| function != name);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.overloads.read().map_or(0, |overloads| overloads.len())
+    }
+}
+
+/// A registry's compiled dispatch tables, by function name.
+#[derive(Debug, Default)]
+pub(crate) struct DispatchTables {
+    tables: RwLock<HashMap<String, Arc<DispatchTable>>>,
+}
+
+impl DispatchTables {
+    /// The table for `overloads`, the overloads of `name`, compiling it if
+    /// this is their first call; true if it was compiled before.
+    pub(crate) fn get(&self, name: &str, overloads: &[FunctionDeclaration]) -> (Arc<DispatchTable>, bool) {
+        if let Some(table) = self.tables.read().ok().and_then(|tables| tables.get(name).cloned()) {
+            return (table, true);
+        }
+        let table = Arc::new(DispatchTable::compile(overloads));
+        if let Ok(mut tables) = self.tables.write() {
+            tables.insert(name.to_string(), table.clone());
+        }
+        (table, false)
+    }
+
+    /// Drop the table of `name`, whose overloads changed.
+    pub(crate) fn forget(&mut self, name: &str) {
+        if let Ok(tables) = self.tables.get_mut() {
+            tables.remove(name);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.tables.read().map_or(0, |tables| tables.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_dispatch_table() {
+        let source = "value Circle(r: Int) {}
+                      value Square(side: Int) {}
+                      fn describe(c: Circle) -> String { \"circle\" }
+                      fn describe(s: Square) -> String { \"square\" }
+                      fn describe(n: Int where n > 0) -> String { \"positive\" }
+                      fn describe(n: Int) -> String { \"int\" }
+                      fn describe(x: Any) -> String { \"anything\" }
+                      fn describe(a: Int, b: Int) -> String { \"pair\" }";
+        for dispatch in [Dispatch::Table, Dispatch::Scan] {
+            let mut engine = Engine::new().with_dispatch(dispatch);
+            engine.load(source).unwrap();
+            let describe = |call: &str| engine.eval_as::<String>(call).unwrap();
+            assert_eq!(describe("describe(Circle(1))"), "circle");
+            assert_eq!(describe("describe(Square(1))"), "square");
+            assert_eq!(describe("describe(1, 2)"), "pair");
+            assert_eq!(describe("describe(3)"), "positive");
+            // A guard that fails falls through to the next tier, call after
+            // call
+            assert_eq!(describe("describe(-3)"), "int");
+        }
+
+        // Each engine's scan caches its own overloads' indexes
+        let mut first = Engine::new().with_dispatch(Dispatch::Scan);
+        first.load("fn f(s: String) -> String { \"string\" }\nfn f(n: Int) -> String { \"int\" }").unwrap();
+        let mut second = Engine::new().with_dispatch(Dispatch::Scan);
+        second.load("fn f(n: Int) -> String { \"int\" }\nfn f(s: String) -> String { \"string\" }").unwrap();
+        assert_eq!(first.eval_as::<String>("f(1)").unwrap(), "int");
+        assert_eq!(second.eval_as::<String>("f(1)").unwrap(), "int");
+        assert_eq!(second.eval_as::<String>("f(\"x\")").unwrap(), "string");
+        assert_eq!(first.registry().stats().dispatch_cache_entries, 1);
+
+        let mut engine = Engine::new();
+        engine.load(source).unwrap();
+        engine.eval("describe(1)").unwrap();
+        let registry = engine.registry();
+        let overloads = registry.get_functions("describe").unwrap();
+        let table = DispatchTable::compile(overloads);
+        let overloads_of = |tiers: &[Tier]| tiers.iter().map(|tier| tier.overloads.clone()).collect::<Vec<_>>();
+        assert_eq!(overloads_of(table.candidates(&[EvalValue::Integer(1)])), [vec![2], vec![3], vec![4]]);
+        assert_eq!(overloads_of(table.candidates(&[EvalValue::Boolean(true)])), [vec![4]]);
+        assert!(table.candidates(&[]).is_empty());
+        assert_eq!(registry.stats().dispatch_tables, 1);
+
+        // Defining an overload drops the compiled table
+        engine.load("fn describe(b: Bool) -> String { \"bool\" }").unwrap();
+        assert_eq!(engine.registry().stats().dispatch_tables, 0);
+        assert_eq!(engine.eval_as::<String>("describe(true)").unwrap(), "bool");
+
+        // Guards that can't both hold decide at the first that does
+        engine.load("fn sign(n: Int where n < 0) -> Int { -1 }\nfn sign(n: Int where n >= 0) -> Int { 1 }").unwrap();
+        assert_eq!(engine.eval("sign(-4)").unwrap(), EvalValue::Integer(-1));
+        assert_eq!(engine.eval("sign(4)").unwrap(), EvalValue::Integer(1));
+        let table = DispatchTable::compile(engine.registry().get_functions("sign").unwrap());
+        assert!(table.candidates(&[EvalValue::Integer(0)])[0].exclusive);
+
+        engine
+            .load("fn twice(n: Int where n > 0) -> Int { n * 2 }\nfn twice(n: Int where n < 10) -> Int { n }")
+            .unwrap();
+        assert!(engine.eval("twice(5)").unwrap_err().to_string().contains("Ambiguous"));
+        assert!(engine.eval("twice(5, 5)").is_err());
+    }
+}
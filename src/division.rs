@@ -0,0 +1,97 @@
+//! What `/` and `%` do with negative `Int`s. By default division truncates
+//! toward zero and the remainder takes the sign of the dividend, so that
+//! `a == (a / b) * b + a % b`: `-7 / 2` is `-3` and `-7 % 2` is `-1`. With
+//! `Division::Euclidean` the remainder is never negative instead, and the
+//! quotient rounds to match: `-7 / 2` is `-4` and `-7 % 2` is `1`, so a rule
+//! such as `n % 2 == 1` holds for every odd `n`.
+//!
+//! Either way dividing by zero, or `i64::MIN` by `-1`, fails the evaluation.
+//! `Decimal` division is exact, and its `%` always truncates.
+
+use crate::error::{Error, Result, ValidationError};
+
+/// How `/` and `%` round when an operand is negative.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Division {
+    /// The quotient truncates toward zero, and the remainder has the sign
+    /// of the dividend
+    #[default]
+    Truncate,
+    /// The remainder is never negative
+    Euclidean,
+}
+
+impl Division {
+    /// `left / right`.
+    pub fn divide(self, left: i64, right: i64) -> Result<i64> {
+        if right == 0 {
+            return Err(undefined("Division by zero"));
+        }
+        match self {
+            Division::Truncate => left.checked_div(right),
+            Division::Euclidean => left.checked_div_euclid(right),
+        }
+        .ok_or_else(|| undefined("Division overflows Int"))
+    }
+
+    /// `left % right`, the remainder of `divide`.
+    pub fn remainder(self, left: i64, right: i64) -> Result<i64> {
+        if right == 0 {
+            return Err(undefined("Modulo by zero"));
+        }
+        match self {
+            Division::Truncate => left.checked_rem(right),
+            Division::Euclidean => left.checked_rem_euclid(right),
+        }
+        .ok_or_else(|| undefined("Modulo overflows Int"))
+    }
+}
+
+fn undefined(message: &str) -> Error {
+    Error::Validation(ValidationError {
+        message: message.to_string(),
+        value_type: "".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::evaluator::EvalValue;
+
+    #[test]
+    fn test_division() {
+        let cases = [(7, 2), (-7, 2), (7, -2), (-7, -2), (6, -3)];
+        for division in [Division::Truncate, Division::Euclidean] {
+            for (a, b) in cases {
+                let (q, r) = (division.divide(a, b).unwrap(), division.remainder(a, b).unwrap());
+                assert_eq!(q * b + r, a, "{:?}: {} / {}", division, a, b);
+            }
+            assert!(division.divide(1, 0).is_err());
+            assert!(division.remainder(1, 0).is_err());
+            assert!(division.divide(i64::MIN, -1).is_err());
+        }
+        assert_eq!(Division::Truncate.divide(-7, 2).unwrap(), -3);
+        assert_eq!(Division::Truncate.remainder(-7, 2).unwrap(), -1);
+        assert_eq!(Division::Euclidean.divide(-7, 2).unwrap(), -4);
+        assert_eq!(Division::Euclidean.remainder(-7, 2).unwrap(), 1);
+        assert_eq!(Division::Euclidean.remainder(-7, -2).unwrap(), 1);
+
+        // Value types' rules follow the engine's semantics, as expressions do
+        let source = "value Odd(n: Int) { validate: n % 2 == 1 }\nvalue Half(n: Int) { validate: n / 2 >= -1 }";
+        let mut engine = Engine::new();
+        engine.load(source).unwrap();
+        assert_eq!(engine.eval("(0 - 7) % 2").unwrap(), EvalValue::Integer(-1));
+        assert!(engine.eval("Odd(3)").is_ok());
+        assert!(engine.eval("Odd(0 - 3)").is_err());
+        assert!(engine.eval("Half(0 - 3)").is_ok());
+
+        let mut engine = Engine::new().with_division(Division::Euclidean);
+        engine.load(source).unwrap();
+        assert_eq!(engine.eval("(0 - 7) % 2").unwrap(), EvalValue::Integer(1));
+        assert_eq!(engine.eval("(0 - 7) / 2").unwrap(), EvalValue::Integer(-4));
+        assert!(engine.eval("Odd(0 - 3)").is_ok());
+        assert!(engine.eval("Half(0 - 3)").is_err());
+    }
+}
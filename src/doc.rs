@@ -0,0 +1,369 @@
+//! Reference documentation for a program's declarations, as Markdown or
+//! HTML. Each value type is listed with its parameter and the rules that
+//! constrain it as a relation (validation, normalization, uniqueness), and
+//! each function with its overloads and their guards. `///` comments on the
+//! lines just above a declaration are its description.
+
+use crate::ast::{Declaration, Expression, ParameterWithGuard, Program, ValueDeclaration};
+use crate::formatter::{format_expression, format_type};
+use crate::lexer::{self, Token};
+use crate::types::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+/// Document the declarations of `program`, parsed from `source`, under the
+/// heading `title`. Value types come first, then functions, each in the
+/// order they're declared; the overloads of a function are listed
+/// together.
+pub fn generate(title: &str, source: &str, program: &Program, format: DocFormat) -> String {
+    let comments = DocComments::new(source);
+    let mut values = Vec::new();
+    let mut functions: Vec<Section> = Vec::new();
+    for declaration in &program.declarations {
+        let description = comments.before(declaration);
+        match declaration {
+            Declaration::Value(value) => values.push(value_section(value, description)),
+            Declaration::Function(f) => {
+                add_overload(&mut functions, "function", &f.name, &f.parameters, &f.return_type, description)
+            }
+            Declaration::Method(m) => {
+                add_overload(&mut functions, "method", &m.name, &m.parameters, &m.return_type, description)
+            }
+        }
+    }
+
+    let groups = [("Value types", values), ("Functions", functions)];
+    match format {
+        DocFormat::Markdown => markdown(title, &groups),
+        DocFormat::Html => html(title, &groups),
+    }
+}
+
+/// What is documented about one declaration, or about a function with all
+/// of its overloads.
+struct Section {
+    /// The name, or a signature
+    heading: String,
+    kind: &'static str,
+    description: Vec<String>,
+    /// Labelled Relic code, such as the validation rule
+    facts: Vec<(String, String)>,
+    overloads: Vec<Section>,
+}
+
+fn value_section(value: &ValueDeclaration, description: Vec<String>) -> Section {
+    let mut facts = Vec::new();
+    let mut rule = |label: &str, expr: &Option<Expression>| {
+        if let Some(expr) = expr {
+            facts.push((label.to_string(), format_expression(expr)));
+        }
+    };
+    rule("Validation", &value.body.validate);
+    rule("Normalization", &value.body.normalize);
+    if value.body.unique == Some(true) {
+        facts.push(("Unique".to_string(), "true".to_string()));
+    }
+    if let Some(display) = &value.body.display {
+        facts.push(("Display".to_string(), format!("{:?}", display)));
+    }
+    if value.body.sensitive == Some(true) {
+        facts.push(("Sensitive".to_string(), "true".to_string()));
+    }
+    if let Some(unit) = &value.parameter.unit {
+        facts.push(("Unit".to_string(), format!("{:?}", unit)));
+    }
+    if !value.body.convert.is_empty() {
+        facts.push(("Converted by".to_string(), value.body.convert.join(", ")));
+    }
+    if value.body.coerce == Some(true) {
+        facts.push(("Coerces".to_string(), format!("from {}", format_type(&value.parameter.ty))));
+    }
+    facts.push(("Instances".to_string(), format!("{}.all()", value.name)));
+    Section {
+        heading: format!("{}({}: {})", value.name, value.parameter.name, format_type(&value.parameter.ty)),
+        kind: "value type",
+        description,
+        facts,
+        overloads: Vec::new(),
+    }
+}
+
+fn add_overload(
+    functions: &mut Vec<Section>,
+    kind: &'static str,
+    name: &str,
+    parameters: &[ParameterWithGuard],
+    return_type: &Type,
+    description: Vec<String>,
+) {
+    let signature: Vec<String> = parameters
+        .iter()
+        .map(|p| format!("{}: {}", p.name, format_type(&p.ty)))
+        .collect();
+    let facts = parameters
+        .iter()
+        .filter_map(|p| {
+            let guard = p.guard.as_ref()?;
+            Some((format!("Guard on `{}`", p.name), format_expression(guard)))
+        })
+        .collect();
+    let overload = Section {
+        heading: format!("{}({}) -> {}", name, signature.join(", "), format_type(return_type)),
+        kind,
+        description,
+        facts,
+        overloads: Vec::new(),
+    };
+
+    match functions.iter_mut().find(|f| f.heading == name) {
+        Some(function) => function.overloads.push(overload),
+        None => functions.push(Section {
+            heading: name.to_string(),
+            kind,
+            description: Vec::new(),
+            facts: Vec::new(),
+            overloads: vec![overload],
+        }),
+    }
+}
+
+/// The `///` comments of a source file, to find the ones above each
+/// declaration.
+struct DocComments {
+    // (line, text without the `///`) of each doc comment, and the line of
+    // every other token, in source order
+    lines: Vec<(usize, Option<String>)>,
+    offsets: Vec<usize>,
+}
+
+impl DocComments {
+    fn new(source: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut offsets = Vec::new();
+        for (token, span, _) in lexer::tokenize(source) {
+            let text = match token {
+                Token::Comment(text) => text.strip_prefix("///").map(|doc| {
+                    doc.strip_prefix(' ').unwrap_or(doc).trim_end().to_string()
+                }),
+                _ => None,
+            };
+            lines.push((span.line, text));
+            offsets.push(span.offset);
+        }
+        Self { lines, offsets }
+    }
+
+    /// The description of `declaration`: the doc comments on the lines
+    /// directly above it, split into paragraphs at blank `///` lines.
+    fn before(&self, declaration: &Declaration) -> Vec<String> {
+        let span = declaration.span();
+        let Ok(start) = self.offsets.binary_search(&span.offset) else {
+            return Vec::new();
+        };
+        let mut lines = Vec::new();
+        let mut line = span.line;
+        for (comment_line, text) in self.lines[..start].iter().rev() {
+            match text {
+                Some(text) if *comment_line + 1 == line => {
+                    lines.push(text.as_str());
+                    line = *comment_line;
+                }
+                _ => break,
+            }
+        }
+        lines.reverse();
+
+        let mut paragraphs = Vec::new();
+        for paragraph in lines.split(|line| line.is_empty()) {
+            if !paragraph.is_empty() {
+                paragraphs.push(paragraph.join(" "));
+            }
+        }
+        paragraphs
+    }
+}
+
+fn markdown(title: &str, groups: &[(&str, Vec<Section>)]) -> String {
+    let mut out = format!("# {}\n", title);
+    for (group, sections) in groups {
+        if sections.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("\n## {}\n", group));
+        for section in sections {
+            markdown_section(&mut out, section, "###");
+        }
+    }
+    out
+}
+
+fn markdown_section(out: &mut String, section: &Section, level: &str) {
+    out.push_str(&format!("\n{} `{}`\n", level, section.heading));
+    if section.overloads.len() == 1 {
+        // A function with one overload is documented by its signature alone
+        let overload = &section.overloads[0];
+        out.push_str(&format!("\n`{}`\n", overload.heading));
+        markdown_body(out, overload);
+        return;
+    }
+    if section.overloads.is_empty() {
+        markdown_body(out, section);
+    } else {
+        out.push_str(&format!("\n{} overloads:\n", section.overloads.len()));
+        for overload in &section.overloads {
+            markdown_section(out, overload, "####");
+        }
+    }
+}
+
+fn markdown_body(out: &mut String, section: &Section) {
+    for paragraph in &section.description {
+        out.push_str(&format!("\n{}\n", paragraph));
+    }
+    if !section.facts.is_empty() {
+        out.push('\n');
+        for (label, code) in &section.facts {
+            out.push_str(&format!("- **{}:** `{}`\n", label, code));
+        }
+    }
+}
+
+fn html(title: &str, groups: &[(&str, Vec<Section>)]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n",
+        escape(title)
+    );
+    for (group, sections) in groups {
+        if sections.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("<h2>{}</h2>\n", group));
+        for section in sections {
+            html_section(&mut out, section, 3);
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn html_section(out: &mut String, section: &Section, level: usize) {
+    let id = section.heading.split('(').next().unwrap_or_default();
+    out.push_str(&format!(
+        "<h{0} id=\"{1}\"><code>{2}</code> <small>{3}</small></h{0}>\n",
+        level,
+        escape(id),
+        escape(&section.heading),
+        section.kind
+    ));
+    if section.overloads.len() == 1 {
+        let overload = &section.overloads[0];
+        out.push_str(&format!("<p><code>{}</code></p>\n", escape(&overload.heading)));
+        html_body(out, overload);
+        return;
+    }
+    html_body(out, section);
+    for overload in &section.overloads {
+        out.push_str(&format!("<h{0}><code>{1}</code></h{0}>\n", level + 1, escape(&overload.heading)));
+        html_body(out, overload);
+    }
+}
+
+fn html_body(out: &mut String, section: &Section) {
+    for paragraph in &section.description {
+        out.push_str(&format!("<p>{}</p>\n", escape(paragraph)));
+    }
+    if !section.facts.is_empty() {
+        out.push_str("<ul>\n");
+        for (label, code) in &section.facts {
+            out.push_str(&format!("<li><strong>{}:</strong> <code>{}</code></li>\n", escape(label), escape(code)));
+        }
+        out.push_str("</ul>\n");
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    const SOURCE: &str = "\
+/// An email address.
+///
+/// Stored in lower case.
+value Email(raw: String) {
+    validate: raw contains \"@\"
+    normalize: raw.toLowerCase()
+    unique: true
+}
+
+// Not documentation
+fn size(n: Int where n > 0) -> Int { n }
+
+/// The size of a string
+fn size(s: String) -> Int { s.length }
+";
+
+    fn document(format: DocFormat) -> String {
+        let program = Parser::new(Lexer::new(SOURCE.to_string()))
+            .and_then(|mut parser| parser.parse_program())
+            .unwrap();
+        generate("Example", SOURCE, &program, format)
+    }
+
+    #[test]
+    fn test_markdown() {
+        assert_eq!(
+            document(DocFormat::Markdown),
+            "\
+# Example
+
+## Value types
+
+### `Email(raw: String)`
+
+An email address.
+
+Stored in lower case.
+
+- **Validation:** `raw contains \"@\"`
+- **Normalization:** `raw.toLowerCase()`
+- **Unique:** `true`
+- **Instances:** `Email.all()`
+
+## Functions
+
+### `size`
+
+2 overloads:
+
+#### `size(n: Int) -> Int`
+
+- **Guard on `n`:** `n > 0`
+
+#### `size(s: String) -> Int`
+
+The size of a string
+"
+        );
+    }
+
+    #[test]
+    fn test_html_is_escaped() {
+        let html = document(DocFormat::Html);
+        assert!(html.contains("<li><strong>Validation:</strong> <code>raw contains &quot;@&quot;</code></li>"));
+        assert!(html.contains("<h3 id=\"size\"><code>size</code> <small>function</small></h3>"));
+        assert!(html.contains("<code>size(n: Int) -&gt; Int</code>"));
+    }
+}
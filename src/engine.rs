@@ -0,0 +1,1119 @@
+//! An embeddable Relic engine. `Engine` takes source text and does what
+//! `main.rs` does by hand: parse, type check, compile and evaluate, with
+//! the modules a program imports loaded first. A panic while evaluating,
+//! from a bug in Relic or in a registered Rust function, is returned as an
+//! `Error::Internal` instead of unwinding into the host program.
+//!
+//! Defining things takes `&mut Engine`, but evaluating expressions, calling
+//! functions and querying instances only take `&Engine`, and an `Engine` is
+//! `Send` and `Sync`: once a program is loaded, it can be shared between
+//! threads as an `Arc<Engine>`. Only the instance store changes under
+//! shared access, and each value type's pool has its own lock.
+//!
+//! ```
+//! use relic::Engine;
+//!
+//! let mut engine = Engine::new();
+//! engine.load("value Age(n: Int) { validate: n >= 0 }").unwrap();
+//! engine.eval("Age(30)").unwrap();
+//! assert!(engine.eval("Age(-1)").is_err());
+//! assert_eq!(engine.get_instances("Age").unwrap().len(), 1);
+//! ```
+
+use crate::artifact::CompiledProgram;
+use crate::ast::{Declaration, Expression, Program};
+use crate::builtin::BuiltinMethod;
+use crate::cancel::{self, CancellationToken};
+use crate::compiler::Compiler;
+use crate::convert::{FromEvalValue, IntoArguments, IntoEvalValue};
+use crate::coverage;
+use crate::diagnostic::Diagnostic;
+use crate::dispatch::Dispatch;
+use crate::division::Division;
+use crate::error::{catch_panic, Error, ParserError, Result, TypeError, ValidationError};
+use crate::evaluator::{self, EvalValue};
+use crate::explain::{self, DispatchExplanation};
+use crate::formatter::format_expression;
+use crate::json;
+use crate::json_schema;
+use crate::lexer::{Lexer, Token};
+use crate::limits::EvalLimits;
+use crate::migrate::{self, Migration};
+use crate::module::{self, ModuleResolver};
+use crate::native::{IntoNativeFunction, NativeFunction};
+use crate::parser::Parser;
+use crate::proto::{self, ProtoFile};
+use crate::provenance::{self, Provenance};
+use crate::query;
+use crate::reload::{self, Reload};
+use crate::replay::{Difference, Recorder, ReplayLog};
+use crate::rules::ValidationRules;
+use crate::stdlib::ScriptEnvironment;
+use crate::subscription::{Change, ChangeStream, SubscriptionId};
+use crate::typechecker::TypeChecker;
+use crate::types::Type;
+use crate::value::{BulkConstruction, ValueRegistry};
+use std::panic::Location;
+use std::sync::{Arc, Mutex};
+
+/// Declarations defined so far, with the instances constructed from them.
+/// Each `load` and `eval` builds on what came before, as a REPL session
+/// does.
+pub struct Engine {
+    compiler: Compiler,
+    typechecker: TypeChecker,
+    // Warnings from parsing, kept until `take_warnings`; expressions are
+    // parsed under shared access too
+    warnings: Mutex<Vec<Diagnostic>>,
+    // Every `load` and `eval` with its results, once `with_replay_log`
+    // starts recording
+    replay_log: Option<Mutex<ReplayLog>>,
+}
+
+// An engine is shared between threads as `Arc<Engine>`
+const _: fn() = || {
+    fn shared<T: Send + Sync>() {}
+    shared::<Engine>();
+    shared::<ValueRegistry>();
+    shared::<EvalValue>();
+};
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            compiler: Compiler::new(),
+            typechecker: TypeChecker::new(),
+            warnings: Mutex::new(Vec::new()),
+            replay_log: None,
+        }
+    }
+
+    /// Use `resolver` to find the modules named in `import` statements; see
+    /// `Compiler::with_resolver`.
+    pub fn with_resolver(mut self, resolver: impl ModuleResolver + 'static) -> Self {
+        self.compiler = self.compiler.with_resolver(resolver);
+        self
+    }
+
+    /// Bound what each evaluation may do, for running code the host doesn't
+    /// trust; see `EvalLimits`. Going over a limit fails the evaluation.
+    ///
+    /// ```
+    /// # use relic::{limits::EvalLimits, Engine};
+    /// let limits = EvalLimits { max_string_len: Some(5), ..EvalLimits::default() };
+    /// let engine = Engine::new().with_limits(limits);
+    /// assert!(engine.eval("\"abc\"").is_ok());
+    /// assert!(engine.eval("\"abcdef\"").is_err());
+    /// ```
+    pub fn with_limits(mut self, limits: EvalLimits) -> Self {
+        self.compiler = self.compiler.with_limits(limits);
+        self
+    }
+
+    /// Choose how calls pick among a function's overloads: through its
+    /// dispatch table, the default, or by scoring each; see `Dispatch`.
+    /// Either way what a call resolved to is kept by this engine alone, so
+    /// engines with overloads of the same name don't see each other's.
+    pub fn with_dispatch(mut self, dispatch: Dispatch) -> Self {
+        self.compiler = self.compiler.with_dispatch(dispatch);
+        self
+    }
+
+    /// Choose how `/` and `%` round negative `Int`s: toward zero, the
+    /// default, or so that `%` is never negative; see `Division`.
+    pub fn with_division(mut self, division: Division) -> Self {
+        self.compiler = self.compiler.with_division(division);
+        self
+    }
+
+    /// Give programs `environment` through `args()` and `env()`; see
+    /// `Compiler::with_script_environment`.
+    pub fn with_script_environment(mut self, environment: ScriptEnvironment) -> Self {
+        self.compiler = self.compiler.with_script_environment(environment);
+        self
+    }
+
+    /// Keep, with each instance constructed, the code that constructed it:
+    /// the line of Rust that called the engine, and the expression it ran.
+    /// `provenance(x)` and `inspect(x)` show it; see `provenance`.
+    pub fn with_provenance(mut self) -> Self {
+        self.compiler = self.compiler.with_provenance();
+        self
+    }
+
+    /// Record every `load` and `eval` from now on, with its results, for
+    /// `replay_log` to return; see `replay::ReplayLog`.
+    pub fn with_replay_log(mut self) -> Self {
+        self.replay_log = Some(Mutex::new(ReplayLog::new(Recorder::Engine)));
+        self
+    }
+
+    /// The inputs recorded since `with_replay_log`, or None without it.
+    pub fn replay_log(&self) -> Option<ReplayLog> {
+        self.replay_log.as_ref().map(|log| log.lock().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+
+    /// Load each input of `log`, recorded by another engine, into this one,
+    /// and return those whose results differ from the recorded ones.
+    pub fn replay(&mut self, log: &ReplayLog) -> Result<Vec<Difference>> {
+        if log.recorder != Recorder::Engine {
+            return Err(Error::Validation(ValidationError {
+                message: format!("This log was recorded by the {}, not an engine", log.recorder.name()),
+                value_type: "replay log".to_string(),
+            }));
+        }
+        let mut replayed = ReplayLog::new(Recorder::Engine);
+        for entry in &log.entries {
+            let results = self.load_unrecorded(&entry.input);
+            replayed.record(&entry.input, replay_results(results.as_deref()));
+        }
+        Ok(log.diff(&replayed))
+    }
+
+    /// Define the declarations in `source`, after the modules it imports,
+    /// then evaluate its script expressions in order. Returns their values,
+    /// or the first error; an expression that fails stops the rest from
+    /// running. `let name = value` among them binds the value for the
+    /// expressions after it, and for later loads and evaluations.
+    #[track_caller]
+    pub fn load(&mut self, source: &str) -> Result<Vec<EvalValue>> {
+        let caller = Location::caller();
+        let records = self.compiler.get_registry().records_provenance();
+        let values = attributed(records, || host_call("load", caller, String::new()), || self.load_unrecorded(source));
+        self.record(source, || replay_results(values.as_deref()));
+        values
+    }
+
+    fn load_unrecorded(&mut self, source: &str) -> Result<Vec<EvalValue>> {
+        let program = self.parse(source, |parser| parser.parse_program())?;
+        self.define(&program)?;
+        self.run(&program)
+    }
+
+    fn record(&self, input: &str, results: impl FnOnce() -> Vec<String>) {
+        if let Some(log) = &self.replay_log {
+            log.lock().unwrap_or_else(|e| e.into_inner()).record(input, results());
+        }
+    }
+
+    /// Define the declarations in `source` and the modules it imports, as
+    /// `load` does but without running its expressions, and return them
+    /// compiled. Save the result to load it into another engine with
+    /// `load_compiled`, without parsing or type checking it again.
+    ///
+    /// ```
+    /// # use relic::{artifact::CompiledProgram, Engine};
+    /// let compiled = Engine::new().compile("fn double(n: Int) -> Int { n * 2 }").unwrap();
+    /// let bytes = compiled.to_bytes();
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.load_compiled(&CompiledProgram::from_bytes(&bytes).unwrap()).unwrap();
+    /// assert_eq!(engine.eval_as::<i64>("double(21)").unwrap(), 42);
+    /// ```
+    pub fn compile(&mut self, source: &str) -> Result<CompiledProgram> {
+        let program = self.parse(source, |parser| parser.parse_program())?;
+        self.define(&program)
+    }
+
+    /// Define what `compiled` defines, as if its source had been loaded.
+    pub fn load_compiled(&mut self, compiled: &CompiledProgram) -> Result<()> {
+        self.typechecker.declare_compiled(compiled)?;
+        self.compiler.load_compiled(compiled)
+    }
+
+    /// Check and compile the declarations of `program`, after the modules
+    /// it imports.
+    fn define(&mut self, program: &Program) -> Result<CompiledProgram> {
+        let mut compiled = CompiledProgram::default();
+        // Imported modules come first, each after the modules it imports
+        for module in self.compiler.load_imports(program)? {
+            self.typechecker
+                .check_program(&module.program)
+                .map_err(|e| module::in_module(&module.name, e))?;
+            compiled.append(self.compiler.compile_module(&module)?);
+        }
+        self.typechecker.check_program(program)?;
+        compiled.append(self.compiler.compile_program(program)?);
+        let warnings = coverage::check(program, self.compiler.get_registry());
+        self.warnings.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).extend(warnings);
+        Ok(compiled)
+    }
+
+    /// Replace a value type with the declaration in `source`, keeping its
+    /// instances: each is constructed again under the new declaration,
+    /// after passing its parameter through the function `mapping` if one is
+    /// given. Instances that fail are dropped and listed in the result.
+    ///
+    /// ```
+    /// # use relic::Engine;
+    /// let mut engine = Engine::new();
+    /// engine.load("value Age(n: Int) {}\nAge(5)\nAge(50)").unwrap();
+    /// engine.load("fn months(years: Int) -> Int { years * 12 }").unwrap();
+    /// let migration = engine
+    ///     .migrate("value Age(months: Int) { validate: months < 120 }", Some("months"))
+    ///     .unwrap();
+    /// assert_eq!(migration.migrated.len(), 1);
+    /// assert_eq!(migration.failed.len(), 1);
+    /// ```
+    pub fn migrate(&mut self, source: &str, mapping: Option<&str>) -> Result<Migration> {
+        let program = self.parse(source, |parser| parser.parse_program())?;
+        let declaration = match program.declarations.as_slice() {
+            [Declaration::Value(declaration)] => declaration,
+            _ => {
+                return Err(Error::Type(TypeError {
+                    message: "A migration takes one value type declaration".to_string(),
+                    span: None,
+                }))
+            }
+        };
+        catch_panic(|| migrate::migrate(&mut self.typechecker, &mut self.compiler, declaration, mapping))
+    }
+
+    /// Replace the functions and value types `source` declares while the
+    /// engine runs, and define those it declares for the first time. A
+    /// redefinition must keep the parameter and return types of a
+    /// function's overloads, or a value type's parameter; otherwise the
+    /// error shows what changed and nothing is replaced. See
+    /// [`reload`](crate::reload).
+    ///
+    /// ```
+    /// # use relic::Engine;
+    /// let mut engine = Engine::new();
+    /// engine.load("fn greet(name: String) -> String { \"Hi \" + name }").unwrap();
+    /// engine.reload("fn greet(name: String) -> String { \"Hello \" + name }").unwrap();
+    /// assert_eq!(engine.eval_as::<String>("greet(\"Ada\")").unwrap(), "Hello Ada");
+    /// assert!(engine.reload("fn greet(name: String) -> Int { 1 }").is_err());
+    /// ```
+    pub fn reload(&mut self, source: &str) -> Result<Reload> {
+        let program = self.parse(source, |parser| parser.parse_program())?;
+        catch_panic(|| reload::reload(&mut self.typechecker, &mut self.compiler, &program))
+    }
+
+    /// Show how the call `source`, such as `area(Circle(2))`, is dispatched:
+    /// every overload of the function, whether it takes the arguments, how
+    /// specific it is and how its guards evaluated, and which one runs. The
+    /// arguments are evaluated, but the function isn't called.
+    pub fn explain_dispatch(&mut self, source: &str) -> Result<DispatchExplanation> {
+        let call = self.parse_expression(source)?;
+        catch_panic(|| explain::explain_call(&call, &mut self.typechecker, &self.compiler))
+    }
+
+    /// Type check and evaluate the expression `source`.
+    #[track_caller]
+    pub fn eval(&self, source: &str) -> Result<EvalValue> {
+        let caller = Location::caller();
+        let records = self.compiler.get_registry().records_provenance();
+        let value = self.parse_expression(source).and_then(|expr| {
+            self.typechecker.check_script_expression(&expr)?;
+            let evaluate = || self.evaluating(|| self.compiler.evaluate_expression(&expr));
+            attributed(records, || host_call("eval", caller, source.to_string()), evaluate)
+        });
+        self.record(source, || replay_results(value.as_ref().map(std::slice::from_ref)));
+        value
+    }
+
+    /// Evaluate the expression `source` as `eval` does, stopping with an
+    /// error if `token` is cancelled, from any thread. Only this evaluation
+    /// checks `token`, so one request can be cancelled while others on the
+    /// same engine carry on.
+    ///
+    /// ```
+    /// # use relic::{cancel::CancellationToken, Engine};
+    /// let engine = Engine::new();
+    /// let token = CancellationToken::new();
+    /// token.cancel();
+    /// assert!(engine.eval_with_token("1 + 1", &token).is_err());
+    /// assert!(engine.eval("1 + 1").is_ok());
+    /// ```
+    #[track_caller]
+    pub fn eval_with_token(&self, source: &str, token: &CancellationToken) -> Result<EvalValue> {
+        cancel::with_token(token, || self.eval(source))
+    }
+
+    /// Evaluate the expression `source` and convert its value to `T`, as in
+    /// `engine.eval_as::<Vec<i64>>("[1, 2]")`.
+    #[track_caller]
+    pub fn eval_as<T: FromEvalValue>(&self, source: &str) -> Result<T> {
+        T::from_eval_value(self.eval(source)?)
+    }
+
+    /// Call the Relic function `name` with `args`, a tuple of Rust values,
+    /// and convert its result to `R`. Overloads are chosen as for a call in
+    /// Relic code. Arguments that are values of a Relic value type must pass
+    /// its validation.
+    ///
+    /// ```
+    /// # use relic::Engine;
+    /// let mut engine = Engine::new();
+    /// engine.load("fn add(a: Int, b: Int) -> Int { a + b }").unwrap();
+    /// let sum: i64 = engine.call("add", (2, 3)).unwrap();
+    /// assert_eq!(sum, 5);
+    /// ```
+    #[track_caller]
+    pub fn call<R: FromEvalValue>(&self, name: &str, args: impl IntoArguments) -> Result<R> {
+        let caller = Location::caller();
+        let args = args.into_arguments();
+        for arg in &args {
+            self.validate(arg)?;
+        }
+        let registry = self.compiler.get_registry();
+        let call = || {
+            let args: Vec<String> = args.iter().map(EvalValue::to_string).collect();
+            host_call("call", caller, format!("{}({})", name, args.join(", ")))
+        };
+        let value = attributed(registry.records_provenance(), call, || {
+            self.evaluating(|| query::materialized(evaluator::call(name, &args, registry)?, registry))
+        })?;
+        R::from_eval_value(value)
+    }
+
+    /// The token every evaluation with this engine checks. Cancelling it,
+    /// from any thread, stops each evaluation in progress, on every thread,
+    /// with an error saying it was cancelled, and fails every evaluation
+    /// started after it until the token is reset. To cancel one evaluation
+    /// alone, give it a token of its own with `eval_with_token`.
+    ///
+    /// ```
+    /// # use relic::Engine;
+    /// let engine = Engine::new();
+    /// let token = engine.cancellation_token();
+    /// token.cancel();
+    /// assert!(engine.eval("1 + 1").is_err());
+    /// assert!(engine.eval("1 + 1").is_err());
+    /// token.reset();
+    /// assert!(engine.eval("1 + 1").is_ok());
+    /// ```
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.compiler.get_registry().cancellation_token().clone()
+    }
+
+    /// The type of the expression `source`, without evaluating it.
+    pub fn type_of(&self, source: &str) -> Result<Type> {
+        let expr = self.parse_expression(source)?;
+        self.typechecker.check_script_expression(&expr)
+    }
+
+    /// Make the Rust closure `f` callable from Relic as `name`. Its
+    /// signature comes from the types of its parameters and result, which
+    /// must convert to and from Relic values; see `NativeFunction::from_fn`.
+    /// It's an overload like any other, so Relic code can define more
+    /// functions called `name` with different parameter types.
+    ///
+    /// ```
+    /// # use relic::{Engine, evaluator::EvalValue};
+    /// let mut engine = Engine::new();
+    /// engine.register_fn("shout", |s: String| s.to_uppercase());
+    /// assert_eq!(engine.eval("shout(\"hi\")").unwrap(), EvalValue::String("HI".to_string()));
+    /// ```
+    pub fn register_fn<Args>(&mut self, name: &str, f: impl IntoNativeFunction<Args>) {
+        self.register_native(NativeFunction::from_fn(name, f));
+    }
+
+    /// Make a function implemented in Rust callable from Relic, for one
+    /// that needs the registry or takes values `register_fn` can't convert.
+    pub fn register_native(&mut self, native: NativeFunction) {
+        self.typechecker.declare_native(&native);
+        self.compiler.register_native(native);
+    }
+
+    /// Add a method to one of Relic's own types, called as
+    /// `receiver.name(args)`, or replace one of the same name and number of
+    /// parameters.
+    ///
+    /// ```
+    /// # use relic::builtin::BuiltinMethod;
+    /// # use relic::evaluator::EvalValue;
+    /// # use relic::types::Type;
+    /// # use relic::Engine;
+    /// let mut engine = Engine::new();
+    /// engine.register_builtin(BuiltinMethod::new(Type::Int, "isEven", &[], Type::Bool, |n, _| {
+    ///     Ok(EvalValue::Boolean(matches!(n, EvalValue::Integer(n) if n % 2 == 0)))
+    /// }));
+    /// assert_eq!(engine.eval("4.isEven()").unwrap(), EvalValue::Boolean(true));
+    /// ```
+    pub fn register_builtin(&mut self, method: BuiltinMethod) {
+        self.typechecker.declare_builtin(&method);
+        self.compiler.register_builtin(method);
+    }
+
+    /// Construct a value of the value type `type_name` from its parameter,
+    /// as `TypeName(value)` does in Relic: it must pass the type's
+    /// validation, and becomes one of the type's instances.
+    pub fn construct(&self, type_name: &str, value: impl IntoEvalValue) -> Result<EvalValue> {
+        let value = value.into_eval_value();
+        self.evaluating(|| evaluator::construct(type_name, &value, self.compiler.get_registry()))
+    }
+
+    /// Construct a value of `type_name` from each of `inputs`, for importing
+    /// many at once: every input is validated, and the result has the values
+    /// constructed from those that passed, in order, with the index and error
+    /// of each that didn't.
+    ///
+    /// ```
+    /// # use relic::Engine;
+    /// let mut engine = Engine::new();
+    /// engine.load("value Age(n: Int) { validate: n >= 0 }").unwrap();
+    /// let bulk = engine.construct_bulk("Age", [30, -1, 45]).unwrap();
+    /// assert_eq!(bulk.instances.len(), 2);
+    /// assert_eq!(bulk.errors[0].index, 1);
+    /// ```
+    pub fn construct_bulk<T: IntoEvalValue>(
+        &self,
+        type_name: &str,
+        inputs: impl IntoIterator<Item = T>,
+    ) -> Result<BulkConstruction<EvalValue>> {
+        let inputs: Vec<EvalValue> = inputs.into_iter().map(IntoEvalValue::into_eval_value).collect();
+        self.evaluating(|| evaluator::construct_bulk(type_name, &inputs, self.compiler.get_registry(), false))
+    }
+
+    /// `construct_bulk`, validating the inputs on several threads.
+    pub fn construct_bulk_parallel<T: IntoEvalValue>(
+        &self,
+        type_name: &str,
+        inputs: impl IntoIterator<Item = T>,
+    ) -> Result<BulkConstruction<EvalValue>> {
+        let inputs: Vec<EvalValue> = inputs.into_iter().map(IntoEvalValue::into_eval_value).collect();
+        self.evaluating(|| evaluator::construct_bulk(type_name, &inputs, self.compiler.get_registry(), true))
+    }
+
+    /// Only let evaluation see the instances `visible` returns true for,
+    /// given the name of the instance's type and the instance, as when
+    /// tenants share one engine and each may only query their own values.
+    /// It replaces any earlier predicate, and applies to `T.all()`,
+    /// `T.count()`, `all(t)`, aggregates and quantifiers; `get_instances`
+    /// still returns every instance.
+    ///
+    /// ```
+    /// # use relic::{Engine, evaluator::EvalValue};
+    /// let mut engine = Engine::new();
+    /// engine.load("value Doc(id: String) {}\nDoc(\"acme/1\")\nDoc(\"acme/2\")\nDoc(\"initech/1\")").unwrap();
+    /// engine.set_visibility(|_, doc| doc.to_string().starts_with("Doc(initech/"));
+    /// assert_eq!(engine.eval("Doc.count()").unwrap(), EvalValue::Integer(1));
+    /// engine.clear_visibility();
+    /// assert_eq!(engine.eval("Doc.count()").unwrap(), EvalValue::Integer(3));
+    /// ```
+    pub fn set_visibility(&mut self, visible: impl Fn(&str, &EvalValue) -> bool + Send + Sync + 'static) {
+        self.compiler.set_visibility(Some(Arc::new(visible)));
+    }
+
+    /// Let evaluation see every instance again.
+    pub fn clear_visibility(&mut self) {
+        self.compiler.set_visibility(None);
+    }
+
+    /// Call `subscriber` with each instance of the value type `type_name`
+    /// that is constructed or retracted from now on, for a host to keep
+    /// its own view of the instances up to date.
+    ///
+    /// ```
+    /// # use relic::{Engine, subscription::Change};
+    /// # use std::sync::{Arc, Mutex};
+    /// let mut engine = Engine::new();
+    /// let names = Arc::new(Mutex::new(Vec::new()));
+    /// let view = Arc::clone(&names);
+    /// engine.subscribe("User", move |change| {
+    ///     if let Change::Constructed(user) = change {
+    ///         view.lock().unwrap().push(user.to_string());
+    ///     }
+    /// });
+    /// engine.load("value User(name: String) {}\nUser(\"ann\")").unwrap();
+    /// assert_eq!(*names.lock().unwrap(), ["User(ann)"]);
+    /// ```
+    pub fn subscribe(&self, type_name: &str, subscriber: impl Fn(&Change) + Send + Sync + 'static) -> SubscriptionId {
+        self.compiler.get_registry().subscribe(type_name, Arc::new(subscriber))
+    }
+
+    /// The changes to the instances of `type_name` from now on, queued to
+    /// be taken with `try_recv` or awaited with `recv()`.
+    pub fn changes(&self, type_name: &str) -> ChangeStream {
+        self.compiler.get_registry().changes(type_name)
+    }
+
+    /// End a subscription made with `subscribe` or `changes`. Returns false
+    /// if it had already ended.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.compiler.get_registry().unsubscribe(id)
+    }
+
+    /// Every instance of the value type `type_name` constructed so far, in
+    /// the order they were constructed.
+    pub fn get_instances(&self, type_name: &str) -> Result<Vec<EvalValue>> {
+        let registry = self.compiler.get_registry();
+        if !registry.constructors.contains_key(type_name) {
+            return Err(Error::Type(TypeError {
+                message: format!("Unknown value type: {}", type_name),
+                span: None,
+            }));
+        }
+        Ok(evaluator::instance_values(type_name, registry))
+    }
+
+    /// The rules the validation of the value type `type_name` places on its
+    /// parameter, such as a least length, for a UI to check input with;
+    /// see `rules::ValidationRules`.
+    pub fn validation_rules(&self, type_name: &str) -> Result<ValidationRules> {
+        let registry = self.compiler.get_registry();
+        match registry.constructors.get(type_name) {
+            Some(constructor) => Ok(ValidationRules::of(&constructor.declaration, registry)),
+            None => Err(Error::Type(TypeError {
+                message: format!("Unknown value type: {}", type_name),
+                span: None,
+            })),
+        }
+    }
+
+    /// A JSON Schema document for payloads of the value types `type_names`,
+    /// with their validation rules as keywords; see `json_schema::generate`.
+    pub fn json_schema(&self, type_names: &[&str]) -> Result<String> {
+        json_schema::generate(self.compiler.get_registry(), type_names)
+    }
+
+    /// Protocol Buffers definitions for the value types `type_names`, in
+    /// `package`, with a report of what they can't express; see
+    /// `proto::generate`.
+    pub fn proto(&self, type_names: &[&str], package: &str) -> Result<ProtoFile> {
+        proto::generate(self.compiler.get_registry(), type_names, package)
+    }
+
+    /// The value of the value type `type_name` that `json` describes, with
+    /// every value inside it constructed and validated; see
+    /// `json::from_json`.
+    #[track_caller]
+    pub fn from_json(&self, type_name: &str, json: &str) -> Result<EvalValue> {
+        let caller = Location::caller();
+        let registry = self.compiler.get_registry();
+        // The JSON may hold sensitive values, so only its type is given
+        let call = || host_call("from_json", caller, format!("fromJson({}, ...)", type_name));
+        attributed(registry.records_provenance(), call, || {
+            self.evaluating(|| json::from_json(type_name, json, registry))
+        })
+    }
+
+    /// Remove the value type or function called `name`, so that it can be
+    /// defined again. Returns false if nothing had that name.
+    pub fn forget(&mut self, name: &str) -> bool {
+        let checked = self.typechecker.forget(name);
+        let compiled = self.compiler.forget(name);
+        checked || compiled
+    }
+
+    /// Warnings found while parsing since the last call.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(self.warnings.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    /// The type checker, for looking up what has been declared.
+    pub fn typechecker(&self) -> &TypeChecker {
+        &self.typechecker
+    }
+
+    /// The registry of compiled value types, functions and instances.
+    pub fn registry(&self) -> &ValueRegistry {
+        self.compiler.get_registry()
+    }
+
+    /// Run the validation of `value`'s type on it, if it's a value of a
+    /// Relic value type: values made in Rust haven't been through their
+    /// constructors.
+    fn validate(&self, value: &EvalValue) -> Result<()> {
+        let EvalValue::Value { type_name, fields, .. } = value else {
+            return Ok(());
+        };
+        let registry = self.compiler.get_registry();
+        let Some(constructor) = registry.constructors.get(type_name) else {
+            return Ok(());
+        };
+        let parameter = &constructor.declaration.parameter.name;
+        let Some(field) = fields.get(parameter) else {
+            return Err(Error::Validation(ValidationError {
+                message: format!("Value of type {} needs a field '{}'", type_name, parameter),
+                value_type: type_name.clone(),
+            }));
+        };
+        // As a constructor call would, whatever the parameter's type
+        evaluator::validate(type_name, field, registry)
+    }
+
+    fn run(&mut self, program: &Program) -> Result<Vec<EvalValue>> {
+        let mut values = Vec::new();
+        for expression in &program.expressions {
+            let value = provenance::at(
+                Some(expression.span.line),
+                || format_expression(&expression.expression),
+                || self.evaluating(|| self.compiler.evaluate_expression(&expression.expression)),
+            )
+            .map_err(|e| e.with_span(expression.span))?;
+            if let Some(name) = &expression.binding {
+                let ty = self.typechecker.check_script_expression(&expression.expression)?;
+                self.typechecker.declare_binding(name, ty);
+                self.compiler.bind(name, value.clone());
+            }
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    /// Run an evaluation, turning a panic into an error.
+    fn evaluating<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.compiler.get_registry().begin_evaluation();
+        catch_panic(f)
+    }
+
+    fn parse_expression(&self, source: &str) -> Result<Expression> {
+        self.parse(source, |parser| {
+            let expr = parser.parse_expression()?;
+            if parser.current_token != Token::Eof {
+                let span = parser.current_span();
+                return Err(Error::Parser(ParserError {
+                    message: format!("Unexpected token after expression: {:?}", parser.current_token),
+                    line: span.line,
+                    column: span.column,
+                    length: span.length,
+                }));
+            }
+            Ok(expr)
+        })
+    }
+
+    fn parse<T>(&self, source: &str, parse: impl FnOnce(&mut Parser) -> Result<T>) -> Result<T> {
+        let mut parser = Parser::new(Lexer::new(source.to_string()))?;
+        let result = parse(&mut parser);
+        let mut warnings = self.warnings.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        warnings.extend_from_slice(parser.warnings());
+        result
+    }
+}
+
+// Run `f`, with what it constructs attributed to `site` if provenance is
+// recorded
+fn attributed<T>(records: bool, site: impl FnOnce() -> Provenance, f: impl FnOnce() -> T) -> T {
+    if records {
+        provenance::within(site(), f)
+    } else {
+        f()
+    }
+}
+
+// A host's call of the engine's `method` at `caller`, running `expression`
+fn host_call(method: &str, caller: &Location, expression: String) -> Provenance {
+    Provenance {
+        origin: format!("{} at {}:{}", method, caller.file(), caller.line()),
+        line: None,
+        expression,
+    }
+}
+
+// What a replay log records of an input's results
+fn replay_results(results: std::result::Result<&[EvalValue], &Error>) -> Vec<String> {
+    match results {
+        Ok(values) => values.iter().map(EvalValue::to_string).collect(),
+        Err(error) => vec![format!("error: {}", error)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decimal::Decimal;
+    use crate::module::MemoryResolver;
+
+    #[test]
+    fn test_register_fn() {
+        let mut engine = Engine::new();
+        engine.register_fn("describe", |n: i64| format!("the number {}", n));
+        engine.load("fn describe(s: String) -> String { s.toUpperCase() }").unwrap();
+
+        assert_eq!(engine.eval("describe(7)").unwrap(), EvalValue::String("the number 7".to_string()));
+        assert_eq!(engine.eval("describe(\"x\")").unwrap(), EvalValue::String("X".to_string()));
+        assert_eq!(engine.type_of("describe(7)").unwrap(), Type::String);
+        assert!(matches!(engine.eval("describe(true)"), Err(Error::Type(_))));
+
+        engine.register_fn("answer", || 42i64);
+        assert_eq!(engine.eval("answer() + 1").unwrap(), EvalValue::Integer(43));
+    }
+
+    #[test]
+    fn test_panics_are_errors() {
+        let mut engine = Engine::new();
+        engine.register_fn("boom", |n: i64| -> i64 { panic!("boom {}", n) });
+        let error = engine.eval("boom(1)").unwrap_err();
+        assert_eq!(error.code(), "E0501");
+        assert!(error.to_string().starts_with("Internal error: boom 1 (this is a bug in Relic"));
+        assert!(matches!(engine.call::<i64>("boom", (2,)), Err(Error::Internal(_))));
+
+        // The engine is still usable
+        assert_eq!(engine.eval("1 + 1").unwrap(), EvalValue::Integer(2));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut engine = Engine::new();
+        engine.load("value Tag(label: String) { validate: nonEmpty(label) }\nvalue Unused(n: Int) {}").unwrap();
+        engine.eval("Tag(\"a\")").unwrap();
+        engine.eval("Tag(\"a much longer label than the first\")").unwrap();
+
+        let stats = engine.registry().stats();
+        let names: Vec<_> = stats.types.iter().map(|t| (t.name.as_str(), t.instances)).collect();
+        assert_eq!(names, [("Tag", 2), ("Unused", 0)]);
+        assert_eq!(stats.instances(), 2);
+        assert_eq!(stats.interned_strings, 2);
+        assert_eq!(stats.interned_bytes, "a".len() + "a much longer label than the first".len());
+        assert_eq!(stats.approximate_bytes(), stats.types[0].approximate_bytes + stats.interned_bytes);
+        assert_eq!(stats.types[1].approximate_bytes, 0);
+        assert!(stats.native_functions > 0);
+    }
+
+    #[test]
+    fn test_interned_strings() {
+        let mut engine = Engine::new();
+        engine.load("value Country(code: String) {}").unwrap();
+        let codes = (0..1000).map(|i| ["GB", "DE", "GB", "FR"][i % 4].to_string());
+        assert_eq!(engine.construct_bulk("Country", codes).unwrap().instances.len(), 1000);
+
+        // Each code is stored once, however many instances hold it
+        let stats = engine.registry().stats();
+        assert_eq!((stats.interned_strings, stats.interned_bytes), (3, 6));
+        assert_eq!(engine.eval("Country.count(c => c.code == \"GB\")").unwrap(), EvalValue::Integer(500));
+
+        // Strings no instance holds any more are let go
+        engine.reload("value Country(code: String) { validate: code != \"FR\" }").unwrap();
+        assert_eq!(engine.registry().stats().interned_strings, 2);
+        assert_eq!(engine.get_instances("Country").unwrap()[0].to_string(), "Country(GB)");
+    }
+
+    #[test]
+    fn test_data_blocks() {
+        let mut engine = Engine::new();
+        engine
+            .load("value Tag(label: String) { validate: label.length > 0 }\ndata Tag { Tag(\"a\"), Tag(\"b\") }")
+            .unwrap();
+        assert_eq!(engine.get_instances("Tag").unwrap().len(), 2);
+
+        // Entries are checked against the block's type, and validated
+        let error = engine.load("value Other(n: Int) {}\ndata Tag { Other(1) }").unwrap_err();
+        assert!(error.to_string().contains("Mismatched types in data block for 'Tag'"), "{}", error);
+        assert!(engine.load("data Missing { Tag(\"c\") }").is_err());
+        assert!(matches!(engine.load("data Tag { Tag(\"c\"), Tag(\"\") }"), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn test_visibility() {
+        let mut engine = Engine::new();
+        engine
+            .load("value Order(ref: String) {}\ndata Order { Order(\"a-1\"), Order(\"b-1\"), Order(\"a-2\") }")
+            .unwrap();
+        let tenant = Arc::new(std::sync::Mutex::new("a-".to_string()));
+        let current = Arc::clone(&tenant);
+        engine.set_visibility(move |type_name, order| {
+            type_name == "Order" && order.to_string().starts_with(&format!("Order({}", current.lock().unwrap()))
+        });
+
+        assert_eq!(engine.eval("Order.count()").unwrap(), EvalValue::Integer(2));
+        assert_eq!(engine.eval("Order.all().length()").unwrap(), EvalValue::Integer(2));
+        assert_eq!(engine.eval("Order.count(o => true)").unwrap(), EvalValue::Integer(2));
+        assert_eq!(engine.eval("exists(Order, o => o.ref == \"b-1\")").unwrap(), EvalValue::Boolean(false));
+        *tenant.lock().unwrap() = "b-".to_string();
+        assert_eq!(engine.eval("exists(Order, o => o.ref == \"b-1\")").unwrap(), EvalValue::Boolean(true));
+        assert_eq!(engine.eval("Order.min(o => o.ref)").unwrap().to_string(), "Some(b-1)");
+
+        // The host still sees every instance
+        assert_eq!(engine.get_instances("Order").unwrap().len(), 3);
+        engine.clear_visibility();
+        assert_eq!(engine.eval("Order.count()").unwrap(), EvalValue::Integer(3));
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let mut engine = Engine::new();
+        let token = engine.cancellation_token();
+        // Cancelled partway through, as by another thread
+        engine.register_fn("stop", move || {
+            token.cancel();
+            1i64
+        });
+        let error = engine.eval("stop() + 1").unwrap_err();
+        assert!(matches!(error, Error::Cancelled));
+        assert_eq!(error.code(), "E0601");
+        // The engine's token stays cancelled until the host resets it, even
+        // after an evaluation fails for another reason
+        assert!(engine.eval("1 + 1").is_err());
+        assert!(engine.eval("1 / 0").is_err());
+        assert!(engine.eval("1 + 1").is_err());
+        engine.cancellation_token().reset();
+        assert_eq!(engine.eval("1 + 1").unwrap(), EvalValue::Integer(2));
+
+        // A token of its own stops one evaluation, and no other
+        let (started, wait_started) = std::sync::mpsc::channel();
+        let (resume, wait_resume) = std::sync::mpsc::channel::<()>();
+        let (started, wait_resume) = (Mutex::new(started), Mutex::new(wait_resume));
+        let mut engine = Engine::new();
+        engine.register_fn("pause", move || {
+            started.lock().unwrap().send(()).unwrap();
+            wait_resume.lock().unwrap().recv().unwrap();
+            1i64
+        });
+        let engine = Arc::new(engine);
+        let token = CancellationToken::new();
+        let request = {
+            let (engine, token) = (Arc::clone(&engine), token.clone());
+            std::thread::spawn(move || engine.eval_with_token("pause() + 1", &token))
+        };
+        wait_started.recv().unwrap();
+        token.cancel();
+        // Evaluated while the cancelled request is still in progress
+        assert_eq!(engine.eval("2 + 2").unwrap(), EvalValue::Integer(4));
+        resume.send(()).unwrap();
+        let error = request.join().unwrap().unwrap_err();
+        assert!(matches!(error, Error::Cancelled), "{}", error);
+        assert_eq!(engine.eval("2 + 2").unwrap(), EvalValue::Integer(4));
+    }
+
+    #[test]
+    fn test_prelude() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.eval("between(3, 1, 5) && nonEmpty(lowercase(\"A\"))").unwrap(), EvalValue::Boolean(true));
+
+        // A program's own function replaces the prelude's with its signature
+        engine.load("fn isPositive(n: Int) -> Int { n }").unwrap();
+        assert_eq!(engine.type_of("isPositive(0)").unwrap(), Type::Int);
+        assert_eq!(engine.eval("isPositive(0)").unwrap(), EvalValue::Integer(0));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Age {
+        n: i64,
+    }
+    crate::impl_eval_value!(Age { n });
+
+    #[test]
+    fn test_host_values() {
+        let mut engine = Engine::new();
+        engine
+            .load("value Age(n: Int) { validate: n >= 0 }\nfn adult(a: Age) -> Bool { true }\nAge(20)\nAge(5)")
+            .unwrap();
+
+        let ages: Vec<Age> = engine.eval_as("all(Age)").unwrap();
+        assert_eq!(ages, vec![Age { n: 20 }, Age { n: 5 }]);
+
+        assert!(engine.call::<bool>("adult", (Age { n: 30 },)).unwrap());
+        assert!(matches!(engine.call::<bool>("adult", (Age { n: -1 },)), Err(Error::Validation(_))));
+        assert!(engine.call::<bool>("adult", (30,)).is_err());
+        assert!(engine.call::<bool>("missing", ()).is_err());
+
+        assert!(engine.construct("Age", 40).is_ok());
+        assert!(matches!(engine.construct("Age", -40), Err(Error::Validation(_))));
+        assert!(engine.construct("Age", "forty").is_err());
+        assert_eq!(engine.get_instances("Age").unwrap().len(), 3);
+    }
+
+    struct Price {
+        amount: Decimal,
+    }
+    crate::impl_eval_value!(Price { amount });
+
+    #[test]
+    fn test_host_values_of_any_parameter_type() {
+        let mut engine = Engine::new();
+        engine
+            .load("value Price(amount: Decimal) { validate: amount > 0.00 }\nfn cents(p: Price) -> Decimal { p.amount }")
+            .unwrap();
+
+        let price = Price { amount: Decimal::parse("12.50").unwrap() };
+        assert_eq!(engine.call::<Decimal>("cents", (price,)).unwrap(), Decimal::parse("12.50").unwrap());
+        let free = Price { amount: Decimal::parse("0.00").unwrap() };
+        assert!(matches!(engine.call::<Decimal>("cents", (free,)), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn test_construct_bulk() {
+        let mut engine = Engine::new();
+        engine.load("value Age(n: Int) { validate: n >= 0 }").unwrap();
+
+        let inputs = vec![
+            EvalValue::Integer(3),
+            EvalValue::Integer(-1),
+            EvalValue::String("x".to_string()),
+            EvalValue::Integer(7),
+        ];
+        let bulk = engine.construct_bulk("Age", inputs).unwrap();
+        let ages: Vec<String> = bulk.instances.iter().map(|age| age.to_string()).collect();
+        assert_eq!(ages, ["Age(3)", "Age(7)"]);
+        let rejected: Vec<usize> = bulk.errors.iter().map(|error| error.index).collect();
+        assert_eq!(rejected, [1, 2]);
+        assert_eq!(bulk.errors[0].message, "Validation error in Age: Validation failed");
+
+        // In parallel, the instances are still recorded in input order
+        let bulk = engine.construct_bulk_parallel("Age", (-50..50).rev()).unwrap();
+        assert_eq!(bulk.instances.len(), 50);
+        assert_eq!(bulk.errors.first().map(|error| error.index), Some(50));
+        assert_eq!(bulk.errors.len(), 50);
+        let ages = engine.get_instances("Age").unwrap();
+        assert_eq!(ages.len(), 52);
+        assert_eq!(ages[2].to_string(), "Age(49)");
+        assert_eq!(ages[51].to_string(), "Age(0)");
+
+        assert!(engine.construct_bulk("Missing", [1]).is_err());
+        assert!(engine.construct_bulk("Missing", Vec::<i64>::new()).is_err());
+    }
+
+    #[test]
+    fn test_concurrent_construction() {
+        let mut engine = Engine::new();
+        engine
+            .load("value Age(n: Int) { validate: n >= 0 }\nvalue Score(n: Int) {}\nvalue Tag(label: String) {}")
+            .unwrap();
+        let registry = engine.registry();
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                scope.spawn(move || {
+                    for n in 0..500_i64 {
+                        let (type_name, input): (&str, Box<dyn std::any::Any + Send + Sync>) = match thread % 3 {
+                            0 => ("Age", Box::new(n)),
+                            1 => ("Score", Box::new(n)),
+                            _ => ("Tag", Box::new(n.to_string())),
+                        };
+                        registry.construct(type_name, input).unwrap();
+                    }
+                });
+            }
+        });
+        // Threads 0, 3 and 6 made Ages; 1, 4 and 7 Scores; 2 and 5 Tags
+        assert_eq!(registry.count_instances("Age"), 1500);
+        assert_eq!(registry.count_instances("Score"), 1500);
+        assert_eq!(registry.count_instances("Tag"), 1000);
+        assert_eq!(registry.stats().instances(), 4000);
+    }
+
+    #[test]
+    fn test_shared_engine() {
+        let limits = EvalLimits { max_depth: Some(3), max_instances: Some(2), ..EvalLimits::default() };
+        let mut engine = Engine::new().with_limits(limits);
+        engine
+            .load(
+                "value Age(n: Int) { validate: n >= 0 }\n\
+                 fn one(n: Int) -> Int { n + 1 }\n\
+                 fn two(n: Int) -> Int { one(n) + 1 }\n\
+                 fn three(n: Int) -> Int { two(n) + 1 }\n\
+                 fn four(n: Int) -> Int { three(n) + 1 }\n\
+                 fn ages(n: Int) -> Int { let a = Age(n); let b = Age(n + 1); n }",
+            )
+            .unwrap();
+        let engine = Arc::new(engine);
+        let handles: Vec<_> = (0..8_i64)
+            .map(|thread| {
+                let engine = Arc::clone(&engine);
+                std::thread::spawn(move || {
+                    for n in 0..50 {
+                        // Each thread's calls and instances count against its
+                        // own evaluation's limits
+                        assert_eq!(engine.eval_as::<i64>("three(0)").unwrap(), 3);
+                        assert_eq!(engine.call::<i64>("ages", (thread * 100 + n,)).unwrap(), thread * 100 + n);
+                        assert!(engine.eval("Age(-1)").is_err());
+                    }
+                    assert_eq!(engine.type_of("Age(1)").unwrap(), Type::Value("Age".to_string()));
+                    engine.get_instances("Age").unwrap().len()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap() >= 100);
+        }
+        assert_eq!(engine.get_instances("Age").unwrap().len(), 800);
+        assert!(engine.eval("four(0)").unwrap_err().to_string().contains("nested more than 3 deep"));
+        assert!(engine.eval("ages(1) + ages(2)").unwrap_err().to_string().contains("limit of 2 instances"));
+    }
+
+    #[test]
+    fn test_snapshots() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::mpsc;
+
+        let (started, wait_started) = mpsc::channel();
+        let (resume, wait_resume) = mpsc::channel::<()>();
+        let (started, wait_resume) = (Mutex::new(started), Mutex::new(wait_resume));
+        let visited = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&visited);
+        let mut engine = Engine::new();
+        // Holds up the query at its first instance until told to go on
+        engine.register_fn("pause", move |n: i64| {
+            if seen.fetch_add(1, Ordering::SeqCst) == 0 {
+                started.lock().unwrap().send(()).unwrap();
+                wait_resume.lock().unwrap().recv().unwrap();
+            }
+            n
+        });
+        engine.load("value Age(n: Int) {}\nAge(1)\nAge(2)\nAge(3)").unwrap();
+        let before = engine.registry().snapshot("Age");
+        assert!(Arc::ptr_eq(&before, &engine.registry().snapshot("Age")));
+
+        let engine = Arc::new(engine);
+        let query = {
+            let engine = Arc::clone(&engine);
+            std::thread::spawn(move || engine.eval("forall(Age, a => pause(a.n) > 0)"))
+        };
+        wait_started.recv().unwrap();
+        // The query is in progress, and constructions don't wait for it
+        for n in 4..=100 {
+            engine.construct("Age", n).unwrap();
+        }
+        resume.send(()).unwrap();
+        assert_eq!(query.join().unwrap().unwrap(), EvalValue::Boolean(true));
+        // It saw the instances there were when it began
+        assert_eq!(visited.load(Ordering::SeqCst), 3);
+        assert_eq!(engine.registry().count_instances("Age"), 100);
+        assert_eq!(before.len(), 3);
+    }
+
+    #[test]
+    fn test_engine() {
+        let mut engine = Engine::new().with_resolver(
+            MemoryResolver::new().with_module("ages", "value Age(n: Int) { validate: n >= 0 }"),
+        );
+        let values = engine
+            .load("import \"ages\"\nfn double(n: Int) -> Int { n * 2 }\nAge(41)\ndouble(21)")
+            .unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[1], EvalValue::Integer(42));
+
+        assert_eq!(engine.eval("double(2)").unwrap(), EvalValue::Integer(4));
+        assert_eq!(engine.type_of("Age(1)").unwrap(), Type::Value("Age".to_string()));
+        assert!(matches!(engine.eval("Age(-1)"), Err(Error::Validation(_))));
+        assert!(matches!(engine.eval("double(\"2\")"), Err(Error::Type(_))));
+        assert!(matches!(engine.eval("1 2"), Err(Error::Parser(_))));
+
+        let ages: Vec<String> = engine.get_instances("Age").unwrap().iter().map(|a| a.to_string()).collect();
+        assert_eq!(ages, vec!["Age(41)"]);
+        assert!(engine.get_instances("Missing").is_err());
+
+        assert!(engine.forget("double"));
+        engine.load("fn double(n: Int) -> Int { n + n + 0 }").unwrap();
+        assert_eq!(engine.eval("double(5)").unwrap(), EvalValue::Integer(10));
+    }
+
+    #[test]
+    fn test_top_level_bindings() {
+        let mut engine = Engine::new();
+        let values = engine
+            .load("value Age(n: Int) {}\nAge(3)\nAge(40)\nlet adults = [a.n for a in Age if a.n > 17]\nlet x = 2\nx * 10")
+            .unwrap();
+        assert_eq!(values.last(), Some(&EvalValue::Integer(20)));
+
+        // Later loads and evaluations see them, functions don't
+        assert_eq!(engine.eval("adults").unwrap().to_string(), "[40]");
+        assert_eq!(engine.type_of("adults").unwrap(), Type::List(Box::new(Type::Int)));
+        assert!(matches!(engine.load("fn f() -> Int { x }"), Err(Error::Type(_))));
+        engine.load("let x = \"two\"").unwrap();
+        assert_eq!(engine.type_of("x").unwrap(), Type::String);
+        assert_eq!(engine.eval("let x = 1 in x").unwrap(), EvalValue::Integer(1));
+
+        // A binding whose value fails isn't made
+        assert!(engine.load("let y = Age(\"old\")").is_err());
+        assert!(engine.eval("y").is_err());
+    }
+}
@@ -1,4 +1,6 @@
+use crate::lexer::Span;
 use std::fmt;
+use std::sync::Arc;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -8,6 +10,10 @@ pub enum Error {
     Parser(ParserError),
     Type(TypeError),
     Validation(ValidationError),
+    Module(ModuleError),
+    Internal(InternalError),
+    /// The evaluation's `CancellationToken` was cancelled.
+    Cancelled,
 }
 
 #[derive(Debug, Clone)]
@@ -15,6 +21,7 @@ pub struct LexerError {
     pub message: String,
     pub line: usize,
     pub column: usize,
+    pub length: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -22,11 +29,14 @@ pub struct ParserError {
     pub message: String,
     pub line: usize,
     pub column: usize,
+    pub length: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct TypeError {
     pub message: String,
+    // Declaration being checked when the error occurred, if known
+    pub span: Option<Span>,
 }
 
 #[derive(Debug, Clone)]
@@ -35,6 +45,87 @@ pub struct ValidationError {
     pub value_type: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct ModuleError {
+    pub message: String,
+    // The import statement that failed, in the file being compiled
+    pub span: Option<Span>,
+    /// What went wrong underneath, such as the I/O error from reading the
+    /// module or an error inside it. Returned by `Error::source`.
+    pub source: Option<Arc<dyn std::error::Error + Send + Sync>>,
+}
+
+/// A bug in Relic itself, such as a panic in the evaluator, caught before
+/// it could take down the REPL session or host process.
+#[derive(Debug, Clone)]
+pub struct InternalError {
+    pub message: String,
+}
+
+impl ModuleError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            span: None,
+            source: None,
+        }
+    }
+
+    pub fn with_source(mut self, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+}
+
+impl Error {
+    /// Diagnostic code identifying the class of error. Codes are stable
+    /// across releases so tools can match on them:
+    ///
+    /// - `E0001` lexical error
+    /// - `E0101` syntax error
+    /// - `E0201` type error
+    /// - `E0301` validation or runtime error
+    /// - `E0401` import that could not be resolved or loaded
+    /// - `E0501` internal error: a bug in Relic, not in the program
+    /// - `E0601` evaluation cancelled by the host
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Lexer(_) => "E0001",
+            Error::Parser(_) => "E0101",
+            Error::Type(_) => "E0201",
+            Error::Validation(_) => "E0301",
+            Error::Module(_) => "E0401",
+            Error::Internal(_) => "E0501",
+            Error::Cancelled => "E0601",
+        }
+    }
+
+    /// Source location of the error as (line, column, length), if known.
+    pub fn location(&self) -> Option<(usize, usize, usize)> {
+        match self {
+            Error::Lexer(e) => Some((e.line, e.column, e.length)),
+            Error::Parser(e) => Some((e.line, e.column, e.length)),
+            Error::Type(e) => e.span.map(|s| (s.line, s.column, s.length)),
+            Error::Validation(_) | Error::Internal(_) | Error::Cancelled => None,
+            Error::Module(e) => e.span.map(|s| (s.line, s.column, s.length)),
+        }
+    }
+
+    /// Attach a location to an error that does not have one yet.
+    pub fn with_span(mut self, span: Span) -> Self {
+        match self {
+            Error::Type(ref mut e) => {
+                e.span.get_or_insert(span);
+            }
+            Error::Module(ref mut e) => {
+                e.span.get_or_insert(span);
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -44,8 +135,102 @@ impl fmt::Display for Error {
             Error::Validation(e) => {
                 write!(f, "Validation error in {}: {}", e.value_type, e.message)
             }
+            Error::Module(e) => write!(f, "Module error: {}", e.message),
+            Error::Internal(e) => write!(f, "Internal error: {}", e.message),
+            Error::Cancelled => write!(f, "Evaluation was cancelled"),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Module(e) => e.source.as_deref().map(|source| source as _),
+            _ => None,
+        }
+    }
+}
+
+impl From<LexerError> for Error {
+    fn from(e: LexerError) -> Self {
+        Error::Lexer(e)
+    }
+}
+
+impl From<ParserError> for Error {
+    fn from(e: ParserError) -> Self {
+        Error::Parser(e)
+    }
+}
+
+impl From<TypeError> for Error {
+    fn from(e: TypeError) -> Self {
+        Error::Type(e)
+    }
+}
+
+impl From<ValidationError> for Error {
+    fn from(e: ValidationError) -> Self {
+        Error::Validation(e)
+    }
+}
+
+impl From<ModuleError> for Error {
+    fn from(e: ModuleError) -> Self {
+        Error::Module(e)
+    }
+}
+
+/// Run `f`, turning a panic inside it into an `Error::Internal`, so that a
+/// bug in the evaluator fails one evaluation rather than the REPL session
+/// or the host process. The panic hook still runs first, so the payload
+/// and where it happened are printed to stderr for a bug report.
+pub fn catch_panic<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let payload = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        #[cfg(feature = "tracing")]
+        tracing::error!(payload = %payload, "caught a panic");
+        Err(Error::Internal(InternalError {
+            message: format!("{} (this is a bug in Relic; please report it with the input that caused it)", payload),
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::module::MemoryResolver;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_sources() {
+        // The I/O error from reading a module is kept, not flattened
+        let error = Engine::new().load("import \"no/such/module.relic\"").unwrap_err();
+        let io = error.source().and_then(|source| source.downcast_ref::<std::io::Error>()).unwrap();
+        assert_eq!(io.kind(), std::io::ErrorKind::NotFound);
+
+        // An error inside a module is the source of the error importing it
+        let resolver = MemoryResolver::new().with_module("bad", "fn f() -> Int { \"s\" }");
+        let mut engine = Engine::new().with_resolver(resolver);
+        let error = engine.load("import \"bad\"").unwrap_err();
+        assert!(error.to_string().contains("In module 'bad'"), "{}", error);
+        let inner = error.source().and_then(|source| source.downcast_ref::<Error>()).unwrap();
+        assert_eq!(inner.code(), "E0201");
+        assert!(inner.source().is_none());
+    }
+
+    #[test]
+    fn test_from_conversions() {
+        fn check() -> Result<()> {
+            Err(TypeError { message: "no".to_string(), span: None })?
+        }
+        assert!(matches!(check(), Err(Error::Type(_))));
+        let error: Error = ModuleError::new("Cannot read module 'm'").into();
+        assert!(matches!(error, Error::Module(_)));
+    }
+}
@@ -1,28 +1,20 @@
+use crate::aggregate::{self, Aggregate};
 use crate::ast::*;
+use crate::builtin::Arguments;
+use crate::decimal::{self, Decimal};
+use crate::dispatch::Dispatch;
+use crate::division::Division;
 use crate::error::{Error, Result, ValidationError};
-// use crate::relation::{Relation, Schema}; // Unused for now
-use crate::value::ValueRegistry;
-use std::collections::HashMap;
-use std::sync::RwLock;
+use crate::json;
+use crate::relation::{Relation, Schema};
+use crate::query::{self, Query};
+use crate::temporal::{self, Temporal};
+use crate::value::{BulkConstruction, BulkError, ValueObject, ValueRegistry};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::any::Any;
 
-// Cache key for dispatch decisions
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-struct DispatchKey {
-    function_name: String,
-    arg_type_signatures: Vec<String>,
-}
-
-// Cache entry storing the resolved function
-type DispatchCache = Arc<RwLock<HashMap<DispatchKey, usize>>>; // Stores index into function list
-
-// Create a thread-safe dispatch cache
-lazy_static::lazy_static! {
-    static ref DISPATCH_CACHE: DispatchCache = Arc::new(RwLock::new(HashMap::new()));
-}
-
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub enum EvalValue {
     String(String),
     Integer(i64),
@@ -30,11 +22,109 @@ pub enum EvalValue {
     Value {
         type_name: String,
         fields: HashMap<String, EvalValue>,
+        // How the value is shown, from its type's `display:` template
+        display: Option<Arc<str>>,
+        // Whether its type is `sensitive`, so that its fields aren't shown
+        sensitive: bool,
     },
     // First-class Type value for Type-as-Relation
     Type(String), // Type name
     // List value for relational operations
     List(Vec<EvalValue>),
+    // Option value: Some(value) or None
+    Option(Option<Box<EvalValue>>),
+    // Rows with named fields, under a schema
+    Relation(Relation),
+    // A date, a time of day or a duration
+    Temporal(Temporal),
+    // A fixed-point decimal number
+    Decimal(Decimal),
+    // Rows not yet visited, from `where` and `select`
+    Query(Query),
+}
+
+/// What is shown in place of the fields of a value of a `sensitive` type.
+pub const REDACTED: &str = "<redacted>";
+
+// Written out so that the fields of sensitive values stay out of logs and
+// panic messages
+impl std::fmt::Debug for EvalValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalValue::String(s) => f.debug_tuple("String").field(s).finish(),
+            EvalValue::Integer(n) => f.debug_tuple("Integer").field(n).finish(),
+            EvalValue::Boolean(b) => f.debug_tuple("Boolean").field(b).finish(),
+            EvalValue::Value { type_name, sensitive: true, .. } => f
+                .debug_struct("Value")
+                .field("type_name", type_name)
+                .field("fields", &format_args!("{}", REDACTED))
+                .finish_non_exhaustive(),
+            EvalValue::Value { type_name, fields, display, .. } => f
+                .debug_struct("Value")
+                .field("type_name", type_name)
+                .field("fields", fields)
+                .field("display", display)
+                .finish(),
+            EvalValue::Type(name) => f.debug_tuple("Type").field(name).finish(),
+            EvalValue::List(items) => f.debug_tuple("List").field(items).finish(),
+            EvalValue::Option(inner) => f.debug_tuple("Option").field(inner).finish(),
+            EvalValue::Relation(relation) => f.debug_tuple("Relation").field(relation).finish(),
+            EvalValue::Temporal(t) => f.debug_tuple("Temporal").field(t).finish(),
+            EvalValue::Decimal(d) => f.debug_tuple("Decimal").field(d).finish(),
+            EvalValue::Query(query) => f.debug_tuple("Query").field(query).finish(),
+        }
+    }
+}
+
+// Values are equal by their type and fields; how they are shown doesn't
+// take part
+impl PartialEq for EvalValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (EvalValue::String(a), EvalValue::String(b)) => a == b,
+            (EvalValue::Integer(a), EvalValue::Integer(b)) => a == b,
+            (EvalValue::Boolean(a), EvalValue::Boolean(b)) => a == b,
+            (
+                EvalValue::Value { type_name: a, fields: a_fields, .. },
+                EvalValue::Value { type_name: b, fields: b_fields, .. },
+            ) => a == b && a_fields == b_fields,
+            (EvalValue::Type(a), EvalValue::Type(b)) => a == b,
+            (EvalValue::List(a), EvalValue::List(b)) => a == b,
+            (EvalValue::Option(a), EvalValue::Option(b)) => a == b,
+            (EvalValue::Relation(a), EvalValue::Relation(b)) => a == b,
+            (EvalValue::Temporal(a), EvalValue::Temporal(b)) => a == b,
+            (EvalValue::Decimal(a), EvalValue::Decimal(b)) => a == b,
+            (EvalValue::Query(a), EvalValue::Query(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+// What `pattern` binds if `value` matches it. A constructor pattern
+// matches the values of its type, and anything that isn't a value
+// instance, as validation rules match on their parameter; a list pattern
+// matches lists of exactly its elements, or of at least them when it has
+// a rest binding.
+fn match_pattern(pattern: &Pattern, value: &EvalValue) -> Option<Vec<(String, EvalValue)>> {
+    match (pattern, value) {
+        (Pattern::Constructor(constructor, _), EvalValue::Value { type_name, .. }) if type_name != constructor => None,
+        (Pattern::Constructor(_, binding), _) => Some(vec![(binding.clone(), value.clone())]),
+        (Pattern::List(elements, rest), EvalValue::List(items)) => {
+            let matches = match rest {
+                Some(_) => items.len() >= elements.len(),
+                None => items.len() == elements.len(),
+            };
+            if !matches {
+                return None;
+            }
+            let mut bindings: Vec<_> = elements.iter().cloned().zip(items.iter().cloned()).collect();
+            if let Some(rest) = rest {
+                bindings.push((rest.clone(), EvalValue::List(items[elements.len()..].to_vec())));
+            }
+            Some(bindings)
+        }
+        (Pattern::List(..), _) => None,
+    }
 }
 
 // General expression evaluator that can handle all expression types including function calls
@@ -42,11 +132,26 @@ pub fn evaluate_expression(
     expr: &Expression,
     context: &HashMap<String, EvalValue>,
     registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    // Every expression is a safe point to stop at
+    registry.cancellation_token().check()?;
+    crate::cancel::check_current()?;
+    let value = evaluate_node(expr, context, registry)?;
+    registry.limits().check_value(&value)?;
+    Ok(value)
+}
+
+fn evaluate_node(
+    expr: &Expression,
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
 ) -> Result<EvalValue> {
     match expr {
         Expression::Literal(Literal::String(s)) => Ok(EvalValue::String(s.clone())),
         Expression::Literal(Literal::Integer(n)) => Ok(EvalValue::Integer(*n)),
         Expression::Literal(Literal::Boolean(b)) => Ok(EvalValue::Boolean(*b)),
+        Expression::Literal(Literal::Temporal(t)) => Ok(EvalValue::Temporal(*t)),
+        Expression::Literal(Literal::Decimal(d)) => Ok(EvalValue::Decimal(*d)),
         
         Expression::Identifier(name) => {
             // First check if it's in the context
@@ -67,54 +172,25 @@ pub fn evaluate_expression(
             let left_val = evaluate_expression(left, context, registry)?;
             let right_val = evaluate_expression(right, context, registry)?;
             
-            match (op, left_val, right_val) {
-                (BinaryOp::Add, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-                    Ok(EvalValue::Integer(l + r))
-                }
-                (BinaryOp::Subtract, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-                    Ok(EvalValue::Integer(l - r))
-                }
-                (BinaryOp::Multiply, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-                    Ok(EvalValue::Integer(l * r))
-                }
-                (BinaryOp::Divide, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-                    if r != 0 {
-                        Ok(EvalValue::Integer(l / r))
-                    } else {
-                        Err(Error::Validation(ValidationError {
-                            message: "Division by zero".to_string(),
-                            value_type: "".to_string(),
-                        }))
-                    }
-                }
-                (BinaryOp::Modulo, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-                    if r != 0 {
-                        Ok(EvalValue::Integer(l % r))
-                    } else {
-                        Err(Error::Validation(ValidationError {
-                            message: "Modulo by zero".to_string(),
-                            value_type: "".to_string(),
-                        }))
-                    }
-                }
-                (BinaryOp::And, EvalValue::Boolean(l), EvalValue::Boolean(r)) => {
-                    Ok(EvalValue::Boolean(l && r))
-                }
-                (BinaryOp::Or, EvalValue::Boolean(l), EvalValue::Boolean(r)) => {
-                    Ok(EvalValue::Boolean(l || r))
-                }
-                _ => Err(Error::Validation(ValidationError {
-                    message: "Type mismatch in binary operation".to_string(),
-                    value_type: "".to_string(),
-                })),
-            }
+            binary_operation(op, left_val, right_val, registry)
         }
         
         Expression::Unary(op, expr) => {
             let val = evaluate_expression(expr, context, registry)?;
             match (op, val) {
                 (UnaryOp::Not, EvalValue::Boolean(b)) => Ok(EvalValue::Boolean(!b)),
-                (UnaryOp::Minus, EvalValue::Integer(n)) => Ok(EvalValue::Integer(-n)),
+                (UnaryOp::Minus, EvalValue::Integer(n)) => {
+                    n.checked_neg().map(EvalValue::Integer).ok_or_else(|| {
+                        Error::Validation(ValidationError {
+                            message: "Negation overflows Int".to_string(),
+                            value_type: "".to_string(),
+                        })
+                    })
+                }
+                (UnaryOp::Minus, EvalValue::Temporal(Temporal::Duration(d))) => Ok(EvalValue::Temporal(
+                    Temporal::Duration(temporal::Duration::from_seconds(-d.seconds())),
+                )),
+                (UnaryOp::Minus, EvalValue::Decimal(d)) => Ok(EvalValue::Decimal(d.negate())),
                 _ => Err(Error::Validation(ValidationError {
                     message: "Type mismatch in unary operation".to_string(),
                     value_type: "".to_string(),
@@ -126,91 +202,42 @@ pub fn evaluate_expression(
             let left_val = evaluate_expression(left, context, registry)?;
             let right_val = evaluate_expression(right, context, registry)?;
             
-            let result = match (op, left_val, right_val) {
-                (ComparisonOp::Equal, EvalValue::Integer(l), EvalValue::Integer(r)) => l == r,
-                (ComparisonOp::NotEqual, EvalValue::Integer(l), EvalValue::Integer(r)) => l != r,
-                (ComparisonOp::Less, EvalValue::Integer(l), EvalValue::Integer(r)) => l < r,
-                (ComparisonOp::Greater, EvalValue::Integer(l), EvalValue::Integer(r)) => l > r,
-                (ComparisonOp::LessEqual, EvalValue::Integer(l), EvalValue::Integer(r)) => l <= r,
-                (ComparisonOp::GreaterEqual, EvalValue::Integer(l), EvalValue::Integer(r)) => l >= r,
-                (ComparisonOp::Equal, EvalValue::String(ref l), EvalValue::String(ref r)) => l == r,
-                (ComparisonOp::NotEqual, EvalValue::String(ref l), EvalValue::String(ref r)) => l != r,
-                (ComparisonOp::Contains, EvalValue::String(ref l), EvalValue::String(ref r)) => l.contains(r),
-                (ComparisonOp::Equal, EvalValue::Boolean(l), EvalValue::Boolean(r)) => l == r,
-                (ComparisonOp::NotEqual, EvalValue::Boolean(l), EvalValue::Boolean(r)) => l != r,
-                _ => return Err(Error::Validation(ValidationError {
-                    message: "Type mismatch in comparison".to_string(),
-                    value_type: "".to_string(),
-                })),
-            };
-            
-            Ok(EvalValue::Boolean(result))
+            Ok(EvalValue::Boolean(compare(op, left_val, right_val)?))
         }
         
         Expression::FunctionCall(name, args) => {
+            if let Some((pool, parameter, predicate)) = quantifier(name, args) {
+                return aggregate::quantify(name == "exists", pool, parameter, predicate, context, registry);
+            }
+            if let Some((rows, parameter, body)) = query_step(name, args) {
+                return query::evaluate_step(name, rows, parameter, body, context, registry);
+            }
+            if let ("materialize", [rows]) = (name.as_str(), args.as_slice()) {
+                return query::materialized(evaluate_expression(rows, context, registry)?, registry);
+            }
+            if let ("sample", [rows, size]) = (name.as_str(), args.as_slice()) {
+                let rows = evaluate_expression(rows, context, registry)?;
+                return aggregate::sample(rows, evaluate_expression(size, context, registry)?, registry);
+            }
+            if let ("histogram", [rows, Expression::Lambda(parameter, body), buckets]) = (name.as_str(), args.as_slice()) {
+                return aggregate::histogram(rows, parameter, body, buckets, context, registry);
+            }
+            if let ("fromJson", [type_name, text]) = (name.as_str(), args.as_slice()) {
+                return match (evaluate_expression(type_name, context, registry)?, evaluate_expression(text, context, registry)?) {
+                    (EvalValue::Type(type_name), EvalValue::String(text)) => json::from_json(&type_name, &text, registry),
+                    _ => Err(Error::Validation(ValidationError {
+                        message: "fromJson expects a value type and a String".to_string(),
+                        value_type: "".to_string(),
+                    })),
+                };
+            }
+
             // Evaluate arguments first
             let mut arg_values = Vec::new();
             for arg in args {
                 arg_values.push(evaluate_expression(arg, context, registry)?);
             }
             
-            // Handle built-in functions first
-            if name == "all" && arg_values.len() == 1 {
-                if let EvalValue::Type(type_name) = &arg_values[0] {
-                    // Get all instances of the type and return as List
-                    let instances = registry.get_all_instances(type_name);
-                    let eval_instances: Vec<EvalValue> = instances
-                        .into_iter()
-                        .map(|instance| {
-                            let mut fields = HashMap::new();
-                            
-                            // Try to extract field value based on the constructor definition
-                            if let Some(constructor) = registry.constructors.get(type_name) {
-                                let param_name = &constructor.declaration.parameter.name;
-                                
-                                // Try to downcast and extract the value
-                                let any_ref = instance.as_any();
-                                if let Some(generic_obj) = any_ref.downcast_ref::<crate::value::GenericValueObject>() {
-                                    // Access the data field directly
-                                    let data_ref = &*generic_obj.data;
-                                    
-                                    // Try to extract based on parameter type
-                                    match &constructor.declaration.parameter.ty {
-                                        crate::types::Type::String => {
-                                            if let Some(s) = data_ref.downcast_ref::<String>() {
-                                                fields.insert(param_name.clone(), EvalValue::String(s.clone()));
-                                            }
-                                        }
-                                        crate::types::Type::Int => {
-                                            if let Some(n) = data_ref.downcast_ref::<i64>() {
-                                                fields.insert(param_name.clone(), EvalValue::Integer(*n));
-                                            }
-                                        }
-                                        crate::types::Type::Bool => {
-                                            if let Some(b) = data_ref.downcast_ref::<bool>() {
-                                                fields.insert(param_name.clone(), EvalValue::Boolean(*b));
-                                            }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                            }
-                            
-                            EvalValue::Value {
-                                type_name: instance.type_name().to_string(),
-                                fields,
-                            }
-                        })
-                        .collect();
-                    return Ok(EvalValue::List(eval_instances));
-                } else {
-                    return Err(Error::Validation(ValidationError {
-                        message: "all() expects a Type argument".to_string(),
-                        value_type: "function".to_string(),
-                    }));
-                }
-            }
-            
             // First check if it's a value constructor
             if registry.constructors.contains_key(name) {
                 // Handle value construction
@@ -225,138 +252,11 @@ pub fn evaluate_expression(
                     }));
                 }
                 
-                // Convert the argument to a form the constructor can use
-                let arg = &arg_values[0];
-                let input: Box<dyn Any + Send + Sync> = match arg {
-                    EvalValue::String(s) => Box::new(s.clone()),
-                    EvalValue::Integer(n) => Box::new(*n),
-                    EvalValue::Boolean(b) => Box::new(*b),
-                    _ => return Err(Error::Validation(ValidationError {
-                        message: format!("Invalid argument type for value constructor {}", name),
-                        value_type: "constructor".to_string(),
-                    })),
-                };
-                
-                // Construct the value
-                let _value_obj = registry.construct(name, input)?;
-                
-                // Extract the field value for the EvalValue
-                let mut fields = HashMap::new();
-                if let Some(constructor) = registry.constructors.get(name) {
-                    let param_name = &constructor.declaration.parameter.name;
-                    fields.insert(param_name.clone(), arg.clone());
-                }
-                
-                Ok(EvalValue::Value {
-                    type_name: name.clone(),
-                    fields,
-                })
+                construct(name, &arg_values[0], registry)
             }
             // With unified syntax, all functions can have multiple implementations
             else if let Some(functions) = registry.get_functions(name) {
-                // If only one function, execute it directly
-                if functions.len() == 1 {
-                    let func_decl = &functions[0];
-                    // Check argument count
-                    if arg_values.len() != func_decl.parameters.len() {
-                        return Err(Error::Validation(ValidationError {
-                            message: format!(
-                                "Function {} expects {} arguments, got {}",
-                                name,
-                                func_decl.parameters.len(),
-                                arg_values.len()
-                            ),
-                            value_type: "function".to_string(),
-                        }));
-                    }
-                    
-                    // Create new context with function parameters
-                    let mut func_context = HashMap::new();
-                    for (param, value) in func_decl.parameters.iter().zip(arg_values.iter()) {
-                        func_context.insert(param.name.clone(), value.clone());
-                    }
-                    
-                    // Evaluate function body
-                    evaluate_expression(&func_decl.body, &func_context, registry)
-                } else {
-                    // Multiple implementations - use dispatch
-                    dispatch_function(name, functions, &arg_values, context, registry)
-                }
-            } else if let Some(methods) = registry.get_methods(name) {
-                // Handle as a method call with multiple dispatch
-                // Find the best matching method based on argument types and specificity
-                let mut candidates = Vec::new();
-                
-                for method in methods {
-                    if method.parameters.len() != arg_values.len() {
-                        continue;
-                    }
-                    
-                    // Check if all parameters match
-                    let matches = method.parameters.iter()
-                        .zip(&arg_values)
-                        .all(|(param, value)| {
-                            matches_type(&param.ty, value)
-                        });
-                        
-                    if matches {
-                        // Create context for guard evaluation
-                        let mut guard_context = HashMap::new();
-                        for (param, value) in method.parameters.iter().zip(arg_values.iter()) {
-                            guard_context.insert(param.name.clone(), value.clone());
-                        }
-                        
-                        // Check if all guards are satisfied
-                        let guards_satisfied = method.parameters.iter()
-                            .all(|param| {
-                                match &param.guard {
-                                    Some(guard_expr) => {
-                                        // Evaluate the guard expression
-                                        match evaluate_expression(guard_expr, &guard_context, registry) {
-                                            Ok(EvalValue::Boolean(true)) => true,
-                                            _ => false,
-                                        }
-                                    }
-                                    None => true, // No guard means it's satisfied
-                                }
-                            });
-                            
-                        if guards_satisfied {
-                            // Calculate specificity score for this method
-                            let specificity = calculate_method_specificity(method, &arg_values);
-                            candidates.push((method, specificity));
-                        }
-                    }
-                }
-                
-                // Sort by specificity (higher is more specific)
-                candidates.sort_by(|a, b| b.1.cmp(&a.1));
-                
-                // Check for ambiguity - if top two have same specificity
-                if candidates.len() >= 2 && candidates[0].1 == candidates[1].1 {
-                    return Err(Error::Validation(ValidationError {
-                        message: format!("Ambiguous method call '{}' - multiple methods with same specificity", name),
-                        value_type: "method".to_string(),
-                    }));
-                }
-                
-                let best_match = candidates.first().map(|(method, _)| *method);
-                
-                if let Some(method) = best_match {
-                    // Create new context with method parameters
-                    let mut method_context = HashMap::new();
-                    for (param, value) in method.parameters.iter().zip(arg_values.iter()) {
-                        method_context.insert(param.name.clone(), value.clone());
-                    }
-                    
-                    // Evaluate method body
-                    evaluate_expression(&method.body, &method_context, registry)
-                } else {
-                    Err(Error::Validation(ValidationError {
-                        message: format!("No matching method '{}' found for given arguments", name),
-                        value_type: "method".to_string(),
-                    }))
-                }
+                call_overloads(name, functions, &arg_values, context, registry)
             } else if name == "relationOf" {
                 // Special handling for relationOf
                 // TODO: Implement relationOf for Type-as-Relation
@@ -372,6 +272,24 @@ pub fn evaluate_expression(
             }
         }
         
+        Expression::NamedCall(name, args) => evaluate_named_call(name, args, context, registry),
+
+        Expression::Block(statements, result) => {
+            let mut block_context = context.clone();
+            for statement in statements {
+                match statement {
+                    Statement::Let(name, value) => {
+                        let bound_value = evaluate_expression(value, &block_context, registry)?;
+                        block_context.insert(name.clone(), bound_value);
+                    }
+                    Statement::Expression(expr) => {
+                        evaluate_expression(expr, &block_context, registry)?;
+                    }
+                }
+            }
+            evaluate_expression(result, &block_context, registry)
+        }
+
         Expression::Let(name, binding, body) => {
             let bound_value = evaluate_expression(binding, context, registry)?;
             let mut new_context = context.clone();
@@ -379,45 +297,40 @@ pub fn evaluate_expression(
             evaluate_expression(body, &new_context, registry)
         }
         
+        Expression::Comprehension(element, name, source, condition) => {
+            evaluate_expression(&comprehension(element, name, source, condition.as_deref()), context, registry)
+        }
+
         Expression::Pipeline(left, right) => {
-            // Evaluate the left expression
-            let left_val = evaluate_expression(left, context, registry)?;
-            
-            // The right side should be a function call or identifier
-            match &**right {
-                Expression::Identifier(func_name) => {
-                    // Transform into a function call with left_val as argument
-                    let func_call = Expression::FunctionCall(
-                        func_name.clone(),
-                        vec![value_to_expression(left_val)?],
-                    );
-                    evaluate_expression(&func_call, context, registry)
-                }
-                Expression::FunctionCall(func_name, args) => {
-                    // Prepend left_val to the arguments
-                    let mut new_args = vec![value_to_expression(left_val)?];
-                    new_args.extend(args.clone());
-                    let func_call = Expression::FunctionCall(func_name.clone(), new_args);
-                    evaluate_expression(&func_call, context, registry)
-                }
-                _ => Err(Error::Validation(ValidationError {
+            // A variable or type is passed as it is; anything else is
+            // evaluated once and passed by a name source can't use
+            let simple = matches!(**left, Expression::Identifier(_) | Expression::TypeLiteral(_));
+            let argument = if simple { (**left).clone() } else { Expression::Identifier(PIPED.to_string()) };
+            let call = piped(argument, right).ok_or_else(|| {
+                Error::Validation(ValidationError {
                     message: "Pipeline right side must be a function".to_string(),
                     value_type: "".to_string(),
-                })),
+                })
+            })?;
+            if simple {
+                return evaluate_expression(&call, context, registry);
             }
+            let mut piped_context = context.clone();
+            piped_context.insert(PIPED.to_string(), evaluate_expression(left, context, registry)?);
+            evaluate_expression(&call, &piped_context, registry)
         }
-        
+
         Expression::MemberAccess(obj, member) => {
             let obj_val = evaluate_expression(obj, context, registry)?;
             match &obj_val {
                 EvalValue::String(s) => match member.as_str() {
-                    "length" => Ok(EvalValue::Integer(s.len() as i64)),
+                    "length" => Ok(EvalValue::Integer(s.chars().count() as i64)),
                     _ => Err(Error::Validation(ValidationError {
                         message: format!("String has no member '{}'", member),
                         value_type: "String".to_string(),
                     })),
                 },
-                EvalValue::Value { type_name, fields } => {
+                EvalValue::Value { type_name, fields, .. } => {
                     fields.get(member).cloned().ok_or_else(|| {
                         Error::Validation(ValidationError {
                             message: format!("Value type '{}' has no member '{}'", type_name, member),
@@ -432,355 +345,558 @@ pub fn evaluate_expression(
             }
         }
         
-        Expression::MethodCall(obj, method, args) => {
-            // Check if this is a Type method call (e.g., User.all())
-            if let Expression::Identifier(type_name) = &**obj {
-                // Check if this identifier is a type name in the registry
-                if registry.constructors.contains_key(type_name) {
-                    // Handle Type-as-Relation methods by delegating to built-in functions
-                    match method.as_str() {
-                        "all" if args.is_empty() => {
-                            // Delegate to the built-in all() function
-                            let instances = registry.get_all_instances(type_name);
-                            let eval_instances: Vec<EvalValue> = instances
-                                .into_iter()
-                                .map(|instance| {
-                                    let mut fields = HashMap::new();
-                                    
-                                    // Try to extract field value based on the constructor definition
-                                    if let Some(constructor) = registry.constructors.get(type_name) {
-                                        let param_name = &constructor.declaration.parameter.name;
-                                        
-                                        // Try to downcast and extract the value
-                                        let any_ref = instance.as_any();
-                                        if let Some(generic_obj) = any_ref.downcast_ref::<crate::value::GenericValueObject>() {
-                                            // Access the data field directly
-                                            let data_ref = &*generic_obj.data;
-                                            
-                                            // Try to extract based on parameter type
-                                            match &constructor.declaration.parameter.ty {
-                                                crate::types::Type::String => {
-                                                    if let Some(s) = data_ref.downcast_ref::<String>() {
-                                                        fields.insert(param_name.clone(), EvalValue::String(s.clone()));
-                                                    }
-                                                }
-                                                crate::types::Type::Int => {
-                                                    if let Some(n) = data_ref.downcast_ref::<i64>() {
-                                                        fields.insert(param_name.clone(), EvalValue::Integer(*n));
-                                                    }
-                                                }
-                                                crate::types::Type::Bool => {
-                                                    if let Some(b) = data_ref.downcast_ref::<bool>() {
-                                                        fields.insert(param_name.clone(), EvalValue::Boolean(*b));
-                                                    }
-                                                }
-                                                _ => {}
-                                            }
-                                        }
-                                    }
-                                    
-                                    EvalValue::Value {
-                                        type_name: instance.type_name().to_string(),
-                                        fields,
-                                    }
-                                })
-                                .collect();
-                            Ok(EvalValue::List(eval_instances))
-                        }
-                        "count" if args.is_empty() => {
-                            // For now, keep count as special case until we implement pure Relic functions
-                            let count = registry.count_instances(type_name);
-                            Ok(EvalValue::Integer(count as i64))
-                        }
-                        _ => Err(Error::Validation(ValidationError {
-                            message: format!("Unknown type method {} or wrong arguments", method),
-                            value_type: type_name.to_string(),
-                        })),
-                    }
-                } else {
-                    // Not a type, check if it's in the context
-                    if context.contains_key(type_name) {
-                        // Continue with normal method evaluation
-                        // With unified syntax, check if this is a user-defined function (UFC syntax)
-                        if let Some(_functions) = registry.get_functions(method) {
-                            // Transform x.f(y, z) into f(x, y, z)
-                            let mut full_args = vec![obj.as_ref().clone()];
-                            full_args.extend(args.clone());
-                            return evaluate_expression(
-                                &Expression::FunctionCall(method.clone(), full_args),
-                                context,
-                                registry,
-                            );
-                        }
-                        
-                        // Otherwise, handle built-in methods
-                        let obj_val = evaluate_expression(obj, context, registry)?;
-                        match (&obj_val, method.as_str()) {
-                            (EvalValue::String(s), "toLowerCase") if args.is_empty() => {
-                                Ok(EvalValue::String(s.to_lowercase()))
-                            }
-                            (EvalValue::String(s), "toUpperCase") if args.is_empty() => {
-                                Ok(EvalValue::String(s.to_uppercase()))
-                            }
-                            (EvalValue::List(items), "length") if args.is_empty() => {
-                                Ok(EvalValue::Integer(items.len() as i64))
-                            }
-                            (EvalValue::List(_items), "filter") if args.len() == 1 => {
-                                // For now, filter is not implemented
-                                // We need function values/lambdas for this
-                                Err(Error::Validation(ValidationError {
-                                    message: "List.filter() not yet implemented - requires lambda support".to_string(),
-                                    value_type: "method".to_string(),
-                                }))
-                            }
-                            (EvalValue::List(_items), "find") if args.len() == 1 => {
-                                // For now, find is not implemented
-                                // We need function values/lambdas for this
-                                Err(Error::Validation(ValidationError {
-                                    message: "List.find() not yet implemented - requires lambda support".to_string(),
-                                    value_type: "method".to_string(),
-                                }))
-                            }
-                            _ => Err(Error::Validation(ValidationError {
-                                message: format!("Unknown method {} or wrong arguments", method),
-                                value_type: "".to_string(),
-                            })),
-                        }
-                    } else {
-                        Err(Error::Validation(ValidationError {
-                            message: format!("Unknown identifier: {}", type_name),
-                            value_type: "".to_string(),
-                        }))
-                    }
-                }
-            } else {
-                // With unified syntax, check if this is a user-defined function (UFC syntax)
-                if let Some(_functions) = registry.get_functions(method) {
-                    // Transform x.f(y, z) into f(x, y, z)
-                    let mut full_args = vec![obj.as_ref().clone()];
-                    full_args.extend(args.clone());
-                    return evaluate_expression(
-                        &Expression::FunctionCall(method.clone(), full_args),
-                        context,
-                        registry,
-                    );
-                }
-                
-                // For backward compatibility, check if this is a method
-                if let Some(_methods) = registry.get_methods(method) {
-                    // Transform x.f(y, z) into f(x, y, z) for method dispatch
-                    let mut full_args = vec![obj.as_ref().clone()];
-                    full_args.extend(args.clone());
-                    return evaluate_expression(
-                        &Expression::FunctionCall(method.clone(), full_args),
-                        context,
-                        registry,
-                    );
-                }
-                
-                // Otherwise, handle built-in methods
-                let obj_val = evaluate_expression(obj, context, registry)?;
-                match (&obj_val, method.as_str()) {
-                    (EvalValue::String(s), "toLowerCase") if args.is_empty() => {
-                        Ok(EvalValue::String(s.to_lowercase()))
-                    }
-                    (EvalValue::String(s), "toUpperCase") if args.is_empty() => {
-                        Ok(EvalValue::String(s.to_uppercase()))
-                    }
-                    (EvalValue::List(items), "length") if args.is_empty() => {
-                        Ok(EvalValue::Integer(items.len() as i64))
-                    }
-                    (EvalValue::List(_items), "filter") if args.len() == 1 => {
-                        // For now, filter is not implemented
-                        // We need function values/lambdas for this
-                        Err(Error::Validation(ValidationError {
-                            message: "List.filter() not yet implemented - requires lambda support".to_string(),
-                            value_type: "method".to_string(),
-                        }))
-                    }
-                    (EvalValue::List(_items), "find") if args.len() == 1 => {
-                        // For now, find is not implemented
-                        // We need function values/lambdas for this
-                        Err(Error::Validation(ValidationError {
-                            message: "List.find() not yet implemented - requires lambda support".to_string(),
-                            value_type: "method".to_string(),
-                        }))
-                    }
-                    _ => Err(Error::Validation(ValidationError {
-                        message: format!("Unknown method {} or wrong arguments", method),
-                        value_type: "".to_string(),
-                    })),
-                }
-            }
-        }
-        
+        Expression::MethodCall(obj, method, args) => call_method(obj, method, args, context, registry),
+
         Expression::Match(expr, arms) => {
             let val = evaluate_expression(expr, context, registry)?;
-            
-            // For now, just evaluate the first arm's body
-            // Full pattern matching would be more complex
-            if let Some(arm) = arms.first() {
-                // Add pattern binding to context if needed
-                let mut new_context = context.clone();
-                match &arm.pattern {
-                    Pattern::Constructor(_, binding) => {
-                        new_context.insert(binding.clone(), val);
-                    }
+
+            // The first arm whose pattern matches decides
+            for arm in arms {
+                if let Some(bindings) = match_pattern(&arm.pattern, &val) {
+                    let mut new_context = context.clone();
+                    new_context.extend(bindings);
+                    return evaluate_expression(&arm.body, &new_context, registry);
                 }
-                evaluate_expression(&arm.body, &new_context, registry)
-            } else {
-                Err(Error::Validation(ValidationError {
-                    message: "No match arms".to_string(),
-                    value_type: "".to_string(),
-                }))
             }
+            Err(Error::Validation(ValidationError {
+                message: format!("No match arm matches {}", val),
+                value_type: type_tag(&val).to_string(),
+            }))
         }
 
         Expression::TypeLiteral(type_name) => {
             // Return a Type value for Type-as-Relation
             Ok(EvalValue::Type(type_name.clone()))
         }
+
+        Expression::Relation(fields, rows) => {
+            let schema = Schema {
+                fields: fields.iter().map(|field| (field.name.clone(), field.ty.to_string())).collect(),
+            };
+            let mut relation = Relation::new(schema);
+            for row in rows {
+                let values = row
+                    .iter()
+                    .map(|cell| evaluate_expression(cell, context, registry))
+                    .collect::<Result<Vec<_>>>()?;
+                relation = relation.add_values(values)?;
+            }
+            Ok(EvalValue::Relation(relation))
+        }
+
+        Expression::Lambda(parameter, _) => Err(Error::Validation(ValidationError {
+            message: format!("The function `{} => ...` can only be passed to exists, forall or an aggregate such as User.count", parameter),
+            value_type: "function".to_string(),
+        })),
+
+        Expression::OptionalAccess(option, member, args) => {
+            let Some(value) = option_operand("?.", option, context, registry)? else {
+                return Ok(EvalValue::Option(None));
+            };
+            let mut access_context = context.clone();
+            access_context.insert(UNWRAPPED.to_string(), value);
+            match evaluate_expression(&unwrapped_access(member, args), &access_context, registry)? {
+                option @ EvalValue::Option(_) => Ok(option),
+                value => Ok(EvalValue::Option(Some(Box::new(value)))),
+            }
+        }
+
+        // The default is only evaluated if it's needed
+        Expression::Coalesce(option, default) => match option_operand("??", option, context, registry)? {
+            Some(value) => Ok(value),
+            None => evaluate_expression(default, context, registry),
+        },
+
+        Expression::Unwrap(option) => option_operand("!", option, context, registry)?.ok_or_else(|| {
+            Error::Validation(ValidationError {
+                message: format!("`{}!` unwrapped None", crate::formatter::format_expression(option)),
+                value_type: "Option".to_string(),
+            })
+        }),
     }
 }
 
-// Helper to convert EvalValue back to Expression for pipeline operations
-fn value_to_expression(val: EvalValue) -> Result<Expression> {
-    match val {
-        EvalValue::String(s) => Ok(Expression::Literal(Literal::String(s))),
-        EvalValue::Integer(n) => Ok(Expression::Literal(Literal::Integer(n))),
-        EvalValue::Boolean(b) => Ok(Expression::Literal(Literal::Boolean(b))),
-        EvalValue::Value { type_name, .. } => {
-            // For now, we can't convert value objects back to expressions
-            Err(Error::Validation(ValidationError {
-                message: format!("Cannot convert value type '{}' to expression", type_name),
-                value_type: type_name,
+/// The value in `option`, the operand of `operator`, or None if it has none.
+fn option_operand(
+    operator: &str,
+    option: &Expression,
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+) -> Result<Option<EvalValue>> {
+    match evaluate_expression(option, context, registry)? {
+        EvalValue::Option(value) => Ok(value.map(|value| *value)),
+        other => Err(Error::Validation(ValidationError {
+            message: format!("`{}` needs an Option, found {}", operator, other),
+            value_type: "Option".to_string(),
+        })),
+    }
+}
+
+/// `left op right` for two `Int`s, failing rather than wrapping when the
+/// result doesn't fit in one. `/` and `%` round as `division` says.
+pub(crate) fn int_arithmetic(op: &BinaryOp, left: i64, right: i64, division: Division) -> Result<i64> {
+    let (result, operation) = match op {
+        BinaryOp::Add => (left.checked_add(right), "Addition"),
+        BinaryOp::Subtract => (left.checked_sub(right), "Subtraction"),
+        BinaryOp::Multiply => (left.checked_mul(right), "Multiplication"),
+        BinaryOp::Divide => return division.divide(left, right),
+        BinaryOp::Modulo => return division.remainder(left, right),
+        BinaryOp::And | BinaryOp::Or => {
+            return Err(Error::Validation(ValidationError {
+                message: "Cannot evaluate expression as integer".to_string(),
+                value_type: "".to_string(),
             }))
         }
-        EvalValue::Type(type_name) => Ok(Expression::TypeLiteral(type_name)),
-        EvalValue::List(_items) => {
-            // For now, create a placeholder - in a full implementation we'd need list literals
-            Err(Error::Validation(ValidationError {
-                message: "Cannot convert List to expression".to_string(),
-                value_type: "List".to_string(),
-            }))
+    };
+    result.ok_or_else(|| {
+        Error::Validation(ValidationError {
+            message: format!("{} overflows Int", operation),
+            value_type: "".to_string(),
+        })
+    })
+}
+
+/// `left op right`, for the operators that take two evaluated operands.
+pub(crate) fn binary_operation(
+    op: &BinaryOp,
+    left: EvalValue,
+    right: EvalValue,
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    match (op, left, right) {
+        (
+            op @ (BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo),
+            EvalValue::Integer(l),
+            EvalValue::Integer(r),
+        ) => Ok(EvalValue::Integer(int_arithmetic(op, l, r, registry.division())?)),
+        (BinaryOp::And, EvalValue::Boolean(l), EvalValue::Boolean(r)) => {
+            Ok(EvalValue::Boolean(l && r))
         }
+        (BinaryOp::Or, EvalValue::Boolean(l), EvalValue::Boolean(r)) => {
+            Ok(EvalValue::Boolean(l || r))
+        }
+        (BinaryOp::Add, EvalValue::String(l), EvalValue::String(r)) => Ok(EvalValue::String(l + &r)),
+        (BinaryOp::Multiply, EvalValue::String(s), EvalValue::Integer(n))
+        | (BinaryOp::Multiply, EvalValue::Integer(n), EvalValue::String(s)) => repeat(&s, n, registry),
+        (op, l @ EvalValue::Temporal(_), r) | (op, l, r @ EvalValue::Temporal(_)) => {
+            temporal::arithmetic(op, &l, &r)
+        }
+        (op, l @ EvalValue::Decimal(_), r) | (op, l, r @ EvalValue::Decimal(_)) => {
+            decimal::arithmetic(op, &l, &r)
+        }
+        _ => Err(Error::Validation(ValidationError {
+            message: "Type mismatch in binary operation".to_string(),
+            value_type: "".to_string(),
+        })),
     }
 }
 
-// Check if a runtime value matches a type
-fn matches_type(ty: &crate::types::Type, value: &EvalValue) -> bool {
-    match (ty, value) {
-        (crate::types::Type::Int, EvalValue::Integer(_)) => true,
-        (crate::types::Type::String, EvalValue::String(_)) => true,
-        (crate::types::Type::Bool, EvalValue::Boolean(_)) => true,
-        (crate::types::Type::Value(type_name), EvalValue::Value { type_name: val_type, .. }) => {
-            type_name == val_type
-        },
-        (crate::types::Type::Type, EvalValue::Type(_)) => true,
-        (crate::types::Type::List(_), EvalValue::List(_)) => true, // TODO: Check element types
-        (crate::types::Type::Any, _) => true, // Any matches everything
-        _ => false,
+/// The longest string, in bytes, that `*` builds even without a
+/// `max_string_len` limit, so that a mistyped count fails the evaluation
+/// rather than aborting the process when memory runs out.
+const MAX_REPEATED_BYTES: usize = 1 << 30;
+
+/// `s` repeated `times` times, checked against the string length limit
+/// before the string is built.
+fn repeat(s: &str, times: i64, registry: &ValueRegistry) -> Result<EvalValue> {
+    let cannot_repeat = |reason: &str| {
+        Error::Validation(ValidationError {
+            message: format!("Can't repeat a string {} times{}", times, reason),
+            value_type: "String".to_string(),
+        })
+    };
+    let Ok(times) = usize::try_from(times) else {
+        return Err(cannot_repeat(""));
+    };
+    let length = s.chars().count().saturating_mul(times);
+    registry.limits().check_string_len(length)?;
+    let bytes = s.len().checked_mul(times).filter(|&bytes| bytes <= MAX_REPEATED_BYTES);
+    let mut repeated = String::new();
+    if bytes.is_none_or(|bytes| repeated.try_reserve_exact(bytes).is_err()) {
+        return Err(cannot_repeat(&format!(": the result would be over {} bytes", MAX_REPEATED_BYTES)));
     }
+    for _ in 0..times {
+        repeated.push_str(s);
+    }
+    Ok(EvalValue::String(repeated))
 }
 
-// Get type signature for an EvalValue (used for cache keys)
-fn get_value_type_signature(value: &EvalValue) -> String {
-    match value {
-        EvalValue::String(_) => "String".to_string(),
-        EvalValue::Integer(_) => "Int".to_string(),
-        EvalValue::Boolean(_) => "Bool".to_string(),
-        EvalValue::Value { type_name, .. } => type_name.clone(),
-        EvalValue::Type(_) => "Type".to_string(),
-        EvalValue::List(_) => "List".to_string(),
+/// `left op right`, as a comparison in Relic evaluates it.
+pub(crate) fn compare(op: &ComparisonOp, left: EvalValue, right: EvalValue) -> Result<bool> {
+    Ok(match (op, left, right) {
+        (ComparisonOp::Equal, EvalValue::Integer(l), EvalValue::Integer(r)) => l == r,
+        (ComparisonOp::NotEqual, EvalValue::Integer(l), EvalValue::Integer(r)) => l != r,
+        (ComparisonOp::Less, EvalValue::Integer(l), EvalValue::Integer(r)) => l < r,
+        (ComparisonOp::Greater, EvalValue::Integer(l), EvalValue::Integer(r)) => l > r,
+        (ComparisonOp::LessEqual, EvalValue::Integer(l), EvalValue::Integer(r)) => l <= r,
+        (ComparisonOp::GreaterEqual, EvalValue::Integer(l), EvalValue::Integer(r)) => l >= r,
+        (ComparisonOp::Equal, EvalValue::String(ref l), EvalValue::String(ref r)) => l == r,
+        (ComparisonOp::NotEqual, EvalValue::String(ref l), EvalValue::String(ref r)) => l != r,
+        (ComparisonOp::Contains, EvalValue::String(ref l), EvalValue::String(ref r)) => l.contains(r),
+        (ComparisonOp::Contains, EvalValue::List(ref items), ref item) => items.contains(item),
+        (ComparisonOp::Equal, EvalValue::Boolean(l), EvalValue::Boolean(r)) => l == r,
+        (ComparisonOp::NotEqual, EvalValue::Boolean(l), EvalValue::Boolean(r)) => l != r,
+        (op, EvalValue::Temporal(l), EvalValue::Temporal(r)) => temporal::compare(op, &l, &r)?,
+        (op, l @ EvalValue::Decimal(_), r) | (op, l, r @ EvalValue::Decimal(_)) => decimal::compare(op, &l, &r)?,
+        _ => return Err(Error::Validation(ValidationError {
+            message: "Type mismatch in comparison".to_string(),
+            value_type: "".to_string(),
+        })),
+    })
+}
+
+/// Every instance of the value type `type_name` constructed so far, as
+/// values with the type's parameter as their one field.
+pub fn instance_values(type_name: &str, registry: &ValueRegistry) -> Vec<EvalValue> {
+    registry
+        .snapshot(type_name)
+        .iter()
+        .map(|instance| instance_value(type_name, instance.as_ref(), registry))
+        .collect()
+}
+
+/// The instances of the value type `type_name` that evaluation can see;
+/// see `ValueRegistry::set_visibility`.
+pub fn visible_instances(type_name: &str, registry: &ValueRegistry) -> Vec<EvalValue> {
+    let mut instances = instance_values(type_name, registry);
+    instances.retain(|instance| registry.is_visible(type_name, instance));
+    instances
+}
+
+/// One instance of the value type `type_name`, as a value with the type's
+/// parameter as its one field.
+pub(crate) fn instance_value(type_name: &str, instance: &dyn ValueObject, registry: &ValueRegistry) -> EvalValue {
+    let mut fields = HashMap::new();
+
+    // Try to extract field value based on the constructor definition
+    if let Some(constructor) = registry.constructors.get(type_name) {
+        let param_name = &constructor.declaration.parameter.name;
+
+        // Try to downcast and extract the value
+        let any_ref = instance.as_any();
+        if let Some(generic_obj) = any_ref.downcast_ref::<crate::value::GenericValueObject>() {
+            // Access the data field directly
+            let data_ref = &*generic_obj.data;
+
+            // Try to extract based on parameter type
+            match &constructor.declaration.parameter.ty {
+                crate::types::Type::String => {
+                    if let Some(s) = crate::value::text(data_ref) {
+                        fields.insert(param_name.clone(), EvalValue::String(s.to_string()));
+                    }
+                }
+                crate::types::Type::Int => {
+                    if let Some(n) = data_ref.downcast_ref::<i64>() {
+                        fields.insert(param_name.clone(), EvalValue::Integer(*n));
+                    }
+                }
+                crate::types::Type::Bool => {
+                    if let Some(b) = data_ref.downcast_ref::<bool>() {
+                        fields.insert(param_name.clone(), EvalValue::Boolean(*b));
+                    }
+                }
+                crate::types::Type::Date | crate::types::Type::Time | crate::types::Type::Duration => {
+                    if let Some(t) = data_ref.downcast_ref::<Temporal>() {
+                        fields.insert(param_name.clone(), EvalValue::Temporal(*t));
+                    }
+                }
+                crate::types::Type::Decimal => {
+                    if let Some(d) = data_ref.downcast_ref::<Decimal>() {
+                        fields.insert(param_name.clone(), EvalValue::Decimal(*d));
+                    }
+                }
+                _ => {
+                    if let Some(value) = data_ref.downcast_ref::<EvalValue>() {
+                        fields.insert(param_name.clone(), value.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    let type_name = instance.type_name().to_string();
+    let display = registry
+        .constructors
+        .get(&type_name)
+        .and_then(|constructor| fields.get(&constructor.declaration.parameter.name))
+        .and_then(|arg| displayed(&type_name, arg, registry));
+    EvalValue::Value {
+        sensitive: is_sensitive(&type_name, registry),
+        type_name,
+        fields,
+        display,
     }
 }
 
-// Create a cache key from function name and argument types
-fn create_dispatch_key(name: &str, arg_values: &[EvalValue]) -> DispatchKey {
-    DispatchKey {
-        function_name: name.to_string(),
-        arg_type_signatures: arg_values.iter()
-            .map(get_value_type_signature)
-            .collect(),
+/// `s` with each run of whitespace replaced by one space.
+pub(crate) fn collapse_whitespace(s: &str) -> String {
+    let mut collapsed = String::with_capacity(s.len());
+    let mut in_whitespace = false;
+    for c in s.chars() {
+        if c.is_whitespace() {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(c);
+            in_whitespace = false;
+        }
     }
+    collapsed
 }
 
-// Calculate specificity score for a method based on parameter types
-// Higher score means more specific
-fn dispatch_function(
-    name: &str,
-    functions: &[crate::ast::FunctionDeclaration],
-    arg_values: &[EvalValue],
-    _context: &HashMap<String, EvalValue>,
+/// `s` with the first letter of each word in upper case and the rest in
+/// lower case. Words are separated by whitespace, which is kept as it is.
+pub(crate) fn title_case(s: &str) -> String {
+    let mut titled = String::with_capacity(s.len());
+    let mut word_start = true;
+    for c in s.chars() {
+        if word_start {
+            titled.extend(c.to_uppercase());
+        } else {
+            titled.extend(c.to_lowercase());
+        }
+        word_start = c.is_whitespace();
+    }
+    titled
+}
+
+/// `receiver.method(args)`, which is, in this order:
+///
+/// 1. With a lambda for a query step, as in `rows.where(x => p)`, the step
+///    `where(rows, x => p)`.
+/// 2. On the name of a value type, as in `Person.count()`, an aggregate or
+///    relation method of the type's instances.
+/// 3. If an overload of a function `method` takes the receiver's type as
+///    its first parameter (or a value type declared `coerce: true` that
+///    wraps it), and as many more as there are arguments, the call
+///    `method(receiver, args)`, dispatched among all its overloads as
+///    usual. Functions declared with `method`, natives and the prelude's
+///    functions count. Guards don't: a guard that rejects the receiver
+///    makes the call fail rather than fall through to a built-in method.
+/// 4. A built-in method of the receiver's type that takes the arguments,
+///    from the registry's builtin table.
+///
+/// Anything else is an error. A function therefore shadows a built-in
+/// method only for the receivers it takes: `fn length(p: Person)` leaves
+/// `Person.all().length()` to the built-in. The type checker resolves
+/// method calls in the same order.
+fn call_method(
+    receiver: &Expression,
+    method: &str,
+    args: &[Expression],
+    context: &HashMap<String, EvalValue>,
     registry: &ValueRegistry,
 ) -> Result<EvalValue> {
-    // Create cache key
-    let cache_key = create_dispatch_key(name, arg_values);
-    
-    // Check cache first
-    {
-        let cache = DISPATCH_CACHE.read().unwrap();
-        if let Some(&func_index) = cache.get(&cache_key) {
-            if func_index < functions.len() {
-                let func = &functions[func_index];
-                // Create new context with function parameters
-                let mut func_context = HashMap::new();
-                for (param, value) in func.parameters.iter().zip(arg_values.iter()) {
-                    func_context.insert(param.name.clone(), value.clone());
-                }
-                
-                // Evaluate function body (cached path)
-                return evaluate_expression(&func.body, &func_context, registry);
-            }
+    if let [lambda @ Expression::Lambda(..)] = args {
+        if QUERY_STEPS.contains(&method) {
+            let call = Expression::FunctionCall(method.to_string(), vec![receiver.clone(), lambda.clone()]);
+            return evaluate_expression(&call, context, registry);
         }
     }
-    
-    // Cache miss - perform full dispatch resolution
+
+    if let Expression::Identifier(type_name) = receiver {
+        if registry.constructors.contains_key(type_name) {
+            return call_type_method(type_name, method, args, context, registry);
+        }
+    }
+
+    let receiver = evaluate_expression(receiver, context, registry)?;
+    if let Some(functions) = registry.get_functions(method) {
+        let takes_receiver = functions.iter().any(|function| {
+            function.parameters.len() == args.len() + 1 && {
+                let first = &function.parameters[0].ty;
+                matches_type(first, &receiver) || coercion(first, &receiver, registry).is_some()
+            }
+        });
+        if takes_receiver {
+            let mut arg_values = vec![receiver];
+            for arg in args {
+                arg_values.push(evaluate_expression(arg, context, registry)?);
+            }
+            return call_overloads(method, functions, &arg_values, context, registry);
+        }
+    }
+
+    match registry.builtins().find(type_tag(&receiver), method, args.len()) {
+        Some(builtin) => builtin.call(&receiver, &Arguments::new(args, context, registry)),
+        None => Err(Error::Validation(ValidationError {
+            message: format!(
+                "No function or method '{}' takes a receiver of type {} and {} arguments",
+                method,
+                type_tag(&receiver),
+                args.len()
+            ),
+            value_type: "".to_string(),
+        })),
+    }
+}
+
+/// `type_name.method(args)`, such as `Person.all()` or
+/// `Order.sum(o => o.amount)`.
+fn call_type_method(
+    type_name: &str,
+    method: &str,
+    args: &[Expression],
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    if let (Some(aggregate), [Expression::Lambda(parameter, body)]) = (Aggregate::of_method(method), args) {
+        return aggregate.evaluate(type_name, parameter, body, context, registry);
+    }
+
+    // Handle Type-as-Relation methods by delegating to built-in functions
+    match method {
+        "all" if args.is_empty() => {
+            // Delegate to the built-in all() function
+            let eval_instances = visible_instances(type_name, registry);
+            Ok(EvalValue::List(eval_instances))
+        }
+        "count" if args.is_empty() => {
+            // For now, keep count as special case until we implement pure Relic functions
+            let count = if registry.has_visibility() {
+                visible_instances(type_name, registry).len()
+            } else {
+                registry.count_instances(type_name)
+            };
+            Ok(EvalValue::Integer(count as i64))
+        }
+        _ => Err(Error::Validation(ValidationError {
+            message: format!("Unknown type method {} or wrong arguments", method),
+            value_type: type_name.to_string(),
+        })),
+    }
+}
+
+/// The fields of `relation`, as `["name: String", ...]`.
+pub(crate) fn schema_value(relation: &Relation) -> EvalValue {
+    EvalValue::List(
+        relation
+            .schema()
+            .fields
+            .iter()
+            .map(|(name, type_name)| EvalValue::String(format!("{}: {}", name, type_name)))
+            .collect(),
+    )
+}
+
+// Check if a runtime value matches a type
+pub(crate) fn matches_type(ty: &crate::types::Type, value: &EvalValue) -> bool {
+    match (ty, value) {
+        (crate::types::Type::Int, EvalValue::Integer(_)) => true,
+        (crate::types::Type::String, EvalValue::String(_)) => true,
+        (crate::types::Type::Bool, EvalValue::Boolean(_)) => true,
+        (crate::types::Type::Value(type_name), EvalValue::Value { type_name: val_type, .. }) => {
+            type_name == val_type
+        },
+        (crate::types::Type::Type, EvalValue::Type(_)) => true,
+        (crate::types::Type::List(_), EvalValue::List(_)) => true, // TODO: Check element types
+        (crate::types::Type::Option(_), EvalValue::Option(_)) => true,
+        (crate::types::Type::Relation, EvalValue::Relation(_)) => true,
+        (ty, EvalValue::Temporal(t)) => *ty == t.type_of() || *ty == crate::types::Type::Any,
+        (crate::types::Type::Decimal, EvalValue::Decimal(_)) => true,
+        (crate::types::Type::Query(_), EvalValue::Query(_)) => true,
+        (crate::types::Type::Any, _) => true, // Any matches everything
+        _ => false,
+    }
+}
+
+/// The tag of a value's type that dispatch goes by: its type's name, with
+/// the element type of a list or option left out.
+pub(crate) fn type_tag(value: &EvalValue) -> &str {
+    match value {
+        EvalValue::String(_) => "String",
+        EvalValue::Integer(_) => "Int",
+        EvalValue::Boolean(_) => "Bool",
+        EvalValue::Value { type_name, .. } => type_name,
+        EvalValue::Type(_) => "Type",
+        EvalValue::List(_) => "List",
+        EvalValue::Option(_) => "Option",
+        EvalValue::Relation(_) => "Relation",
+        EvalValue::Temporal(t) => t.type_name(),
+        EvalValue::Decimal(_) => "Decimal",
+        EvalValue::Query(_) => "Query",
+    }
+}
+
+// Get type signature for an EvalValue (used for cache keys)
+fn get_value_type_signature(value: &EvalValue) -> String {
+    type_tag(value).to_string()
+}
+
+// The type signatures of a call's arguments, which the dispatch cache is
+// keyed by with the function's name
+fn argument_signatures(arg_values: &[EvalValue]) -> Vec<String> {
+    arg_values.iter().map(get_value_type_signature).collect()
+}
+
+/// The overload of `name` the dispatch cache holds for the types of
+/// `arg_values`, if it has resolved such a call before and `registry`
+/// dispatches with it.
+pub(crate) fn cached_overload(name: &str, arg_values: &[EvalValue], registry: &ValueRegistry) -> Option<usize> {
+    if registry.dispatch() != Dispatch::Scan {
+        return None;
+    }
+    registry.dispatch_cache().get(name, &argument_signatures(arg_values))
+}
+
+// Calculate specificity score for a method based on parameter types
+// Higher score means more specific
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "dispatch", level = "trace", skip_all, fields(function = name, overloads = functions.len()))
+)]
+fn dispatch_function(
+    name: &str,
+    functions: &[crate::ast::FunctionDeclaration],
+    arg_values: &[EvalValue],
+    _context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    if registry.dispatch() == Dispatch::Table {
+        let (table, compiled) = registry.dispatch_table(name, functions);
+        registry.counters().dispatch(compiled);
+        let index = table.resolve(name, functions, arg_values, registry)?;
+        #[cfg(feature = "tracing")]
+        tracing::trace!(overload = index, compiled, "dispatch table");
+        return call_function(&functions[index], arg_values, registry);
+    }
+
+    let signatures = argument_signatures(arg_values);
+    // Guards decide each call anew, so a function with any isn't cached
+    let cacheable = functions.iter().all(|func| func.parameters.iter().all(|param| param.guard.is_none()));
+    
+    // Check cache first
+    if cacheable {
+        if let Some(func_index) = registry.dispatch_cache().get(name, &signatures) {
+            if func_index < functions.len() {
+                // Cached path
+                registry.counters().dispatch(true);
+                #[cfg(feature = "tracing")]
+                tracing::trace!(arg_types = ?signatures, overload = func_index, "dispatch cache hit");
+                return call_function(&functions[func_index], arg_values, registry);
+            }
+        }
+    }
+    
+    // Cache miss - perform full dispatch resolution
+    registry.counters().dispatch(false);
+    #[cfg(feature = "tracing")]
+    tracing::trace!(arg_types = ?signatures, "dispatch cache miss");
     // Find the best matching function based on argument types and specificity
     let mut candidates = Vec::new();
     
     for (index, func) in functions.iter().enumerate() {
-        if func.parameters.len() != arg_values.len() {
-            continue;
-        }
-        
-        // Check if all parameters match
-        let matches = func.parameters.iter()
-            .zip(arg_values)
-            .all(|(param, value)| matches_type(&param.ty, value));
-            
-        if matches {
-            // Create context for guard evaluation
-            let mut guard_context = HashMap::new();
-            for (param, value) in func.parameters.iter().zip(arg_values.iter()) {
-                guard_context.insert(param.name.clone(), value.clone());
-            }
-            
-            // Check if all guards are satisfied
-            let guards_satisfied = func.parameters.iter()
-                .all(|param| {
-                    match &param.guard {
-                        Some(guard_expr) => {
-                            // Evaluate the guard expression
-                            match evaluate_expression(guard_expr, &guard_context, registry) {
-                                Ok(EvalValue::Boolean(true)) => true,
-                                _ => false,
-                            }
-                        }
-                        None => true, // No guard means it's satisfied
-                    }
-                });
-                
-            if guards_satisfied {
-                // Calculate specificity score for this function
-                let specificity = calculate_function_specificity(func, arg_values);
-                candidates.push((index, func, specificity));
-            }
+        if function_applies(func, arg_values, registry) {
+            // Calculate specificity score for this function
+            let specificity = calculate_function_specificity(func);
+            candidates.push((index, func, specificity));
         }
     }
     
     // Sort by specificity (higher is more specific)
-    candidates.sort_by(|a, b| b.2.cmp(&a.2));
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.2));
     
     // Check for ambiguity - if top two have same specificity
     if candidates.len() >= 2 && candidates[0].2 == candidates[1].2 {
@@ -793,20 +909,14 @@ fn dispatch_function(
     let best_match = candidates.first().map(|(index, func, _)| (*index, *func));
     
     if let Some((func_index, func)) = best_match {
+        #[cfg(feature = "tracing")]
+        tracing::trace!(overload = func_index, candidates = candidates.len(), "dispatch resolved");
         // Store in cache for future lookups
-        {
-            let mut cache = DISPATCH_CACHE.write().unwrap();
-            cache.insert(cache_key, func_index);
-        }
-        
-        // Create new context with function parameters
-        let mut func_context = HashMap::new();
-        for (param, value) in func.parameters.iter().zip(arg_values.iter()) {
-            func_context.insert(param.name.clone(), value.clone());
+        if cacheable {
+            registry.dispatch_cache().insert(name, signatures, func_index);
         }
         
-        // Evaluate function body
-        evaluate_expression(&func.body, &func_context, registry)
+        call_function(func, arg_values, registry)
     } else {
         Err(Error::Validation(ValidationError {
             message: format!("No matching function '{}' found for given arguments", name),
@@ -815,34 +925,486 @@ fn dispatch_function(
     }
 }
 
-fn calculate_function_specificity(func: &crate::ast::FunctionDeclaration, arg_values: &[EvalValue]) -> u32 {
-    let mut score = 0;
-    
-    for (param, _value) in func.parameters.iter().zip(arg_values) {
-        score += match &param.ty {
-            crate::types::Type::Int => 3,     // Specific types get higher scores
-            crate::types::Type::String => 3,
-            crate::types::Type::Bool => 3,
-            crate::types::Type::Value(_) => 3,
-            crate::types::Type::Type => 3,
-            crate::types::Type::List(_) => 3,
-            crate::types::Type::Any => 1,     // Any is least specific
-            crate::types::Type::Unknown => 0,
+/// Construct a value of the value type `name` from its parameter, running
+/// the type's validation and recording the instance.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "construct", level = "trace", skip_all, fields(value_type = name))
+)]
+pub fn construct(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> Result<EvalValue> {
+    let (arg, input) = prepare(name, arg, registry)?;
+    registry.construct(name, input)?;
+    Ok(constructed(name, &arg, registry))
+}
+
+/// Construct a value of the value type `name` from each of `args`, as
+/// `ValueRegistry::construct_bulk` does: an argument that doesn't fit the
+/// type's parameter, or can't be normalized, is rejected with the others
+/// that fail validation.
+pub fn construct_bulk(
+    name: &str,
+    args: &[EvalValue],
+    registry: &ValueRegistry,
+    parallel: bool,
+) -> Result<BulkConstruction<EvalValue>> {
+    let mut errors = Vec::new();
+    let mut inputs = Vec::new();
+    // The normalized arguments of `inputs`, with where each came from
+    let mut accepted = Vec::new();
+    for (index, arg) in args.iter().enumerate() {
+        match prepare(name, arg, registry) {
+            Ok((arg, input)) => {
+                inputs.push(input);
+                accepted.push((index, arg));
+            }
+            Err(error) => errors.push(BulkError { index, message: error.to_string() }),
+        }
+    }
+
+    // Which fails if there is no such type
+    let bulk = if parallel {
+        registry.construct_bulk_parallel(name, inputs)?
+    } else {
+        registry.construct_bulk(name, inputs)?
+    };
+    let invalid: HashSet<usize> = bulk.errors.iter().map(|error| error.index).collect();
+    errors.extend(bulk.errors.into_iter().map(|error| BulkError { index: accepted[error.index].0, ..error }));
+    errors.sort_by_key(|error| error.index);
+
+    let instances = accepted
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| !invalid.contains(position))
+        .map(|(_, (_, arg))| constructed(name, arg, registry))
+        .collect();
+    Ok(BulkConstruction { instances, errors })
+}
+
+/// `arg`, normalized, and the input for the constructor of the value type
+/// `name` made from it.
+fn prepare(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> Result<(EvalValue, Box<dyn Any + Send + Sync>)> {
+    let arg = normalize(name, arg, registry)?;
+    let input = constructor_input(name, &arg, registry)?;
+    validate_by_evaluation(name, &arg, registry)?;
+    Ok((arg, input))
+}
+
+/// Run the `validate:` clause of the value type `name` against `arg` if the
+/// compiled validator can't: it only interprets `String` and `Int`
+/// parameters.
+fn validate_by_evaluation(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> Result<()> {
+    let declaration = &registry.constructors[name].declaration;
+    let parameter = &declaration.parameter;
+    let Some(validate) = &declaration.body.validate else {
+        return Ok(());
+    };
+    if matches!(parameter.ty, crate::types::Type::String | crate::types::Type::Int) {
+        return Ok(());
+    }
+
+    let mut context = HashMap::new();
+    context.insert(parameter.name.clone(), arg.clone());
+    match evaluate_expression(validate, &context, registry)? {
+        EvalValue::Boolean(true) => Ok(()),
+        _ => Err(Error::Validation(ValidationError {
+            message: "Validation failed".to_string(),
+            value_type: name.to_string(),
+        })),
+    }
+}
+
+/// `arg` as the `normalize:` clause of the value type `name` rewrites it,
+/// or unchanged if the type has none. Normalizing comes before validating,
+/// so that it is the canonical form that must be valid.
+fn normalize(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> Result<EvalValue> {
+    let Some(declaration) = registry.constructors.get(name).map(|constructor| &constructor.declaration) else {
+        return Ok(arg.clone());
+    };
+    let parameter = &declaration.parameter;
+    // An argument of the wrong type is left for `constructor_input` to reject
+    let (Some(normalize), true) = (&declaration.body.normalize, matches_type(&parameter.ty, arg)) else {
+        return Ok(arg.clone());
+    };
+
+    let mut context = HashMap::new();
+    context.insert(parameter.name.clone(), arg.clone());
+    let normalized = evaluate_expression(normalize, &context, registry)?;
+    if !matches_type(&parameter.ty, &normalized) {
+        return Err(Error::Validation(ValidationError {
+            message: format!(
+                "Normalizing {} gave {}, which is not {}",
+                name,
+                argument_text(name, &normalized, registry),
+                parameter.ty
+            ),
+            value_type: name.to_string(),
+        }));
+    }
+    Ok(normalized)
+}
+
+/// The value constructed from `arg`: the parameter is its one field.
+fn constructed(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> EvalValue {
+    let parameter = &registry.constructors[name].declaration.parameter;
+    let mut fields = HashMap::new();
+    fields.insert(parameter.name.clone(), arg.clone());
+    EvalValue::Value {
+        type_name: name.to_string(),
+        fields,
+        display: displayed(name, arg, registry),
+        sensitive: is_sensitive(name, registry),
+    }
+}
+
+/// `arg`, an argument for the value type `name`, as error messages show
+/// it: not at all if the type is sensitive.
+fn argument_text(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> String {
+    if is_sensitive(name, registry) {
+        REDACTED.to_string()
+    } else {
+        arg.to_string()
+    }
+}
+
+/// Whether the value type `name` is declared `sensitive: true`.
+pub(crate) fn is_sensitive(name: &str, registry: &ValueRegistry) -> bool {
+    registry
+        .constructors
+        .get(name)
+        .is_some_and(|constructor| constructor.declaration.body.sensitive == Some(true))
+}
+
+/// The value of type `name` made from `arg`, as the type's `display:`
+/// template shows it. None if the type has no template, or if filling it in
+/// fails, so that the value is shown as usual instead.
+fn displayed(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> Option<Arc<str>> {
+    let constructor = registry.constructors.get(name)?;
+    let template = constructor.display.as_ref()?;
+    template
+        .render(&constructor.declaration.parameter.name, arg, registry)
+        .ok()
+        .map(Arc::from)
+}
+
+/// Check that `arg`, once normalized, would construct a valid value of the
+/// value type `name`, without constructing it.
+pub fn validate(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> Result<()> {
+    let (_, input) = prepare(name, arg, registry)?;
+    (registry.constructors[name].validator)(&*input)
+}
+
+/// `arg` in the form the constructor of the value type `name` takes.
+fn constructor_input(name: &str, arg: &EvalValue, registry: &ValueRegistry) -> Result<Box<dyn Any + Send + Sync>> {
+    let constructor = registry.constructors.get(name).ok_or_else(|| {
+        Error::Validation(ValidationError {
+            message: format!("Unknown value type: {}", name),
+            value_type: name.to_string(),
+        })
+    })?;
+    let parameter = &constructor.declaration.parameter;
+    // Calls from Relic code are type checked, but not those from Rust
+    if !matches_type(&parameter.ty, arg) {
+        return Err(Error::Validation(ValidationError {
+            message: format!(
+                "Value constructor {} expects {}, found {}",
+                name,
+                parameter.ty,
+                argument_text(name, arg, registry)
+            ),
+            value_type: name.to_string(),
+        }));
+    }
+
+    match arg {
+        EvalValue::String(s) => Ok(Box::new(s.clone())),
+        EvalValue::Integer(n) => Ok(Box::new(*n)),
+        EvalValue::Boolean(b) => Ok(Box::new(*b)),
+        EvalValue::Temporal(t) => Ok(Box::new(*t)),
+        EvalValue::Decimal(d) => Ok(Box::new(*d)),
+        // Another value, a list or an option is kept as it is
+        EvalValue::Value { .. } | EvalValue::List(_) | EvalValue::Option(_) => Ok(Box::new(arg.clone())),
+        _ => Err(Error::Validation(ValidationError {
+            message: format!("Invalid argument type for value constructor {}", name),
+            value_type: "constructor".to_string(),
+        })),
+    }
+}
+
+/// Call the function `name` with already evaluated arguments, choosing
+/// among its overloads as a call in Relic code would.
+pub fn call(name: &str, arg_values: &[EvalValue], registry: &ValueRegistry) -> Result<EvalValue> {
+    let functions = registry.get_functions(name).ok_or_else(|| {
+        Error::Validation(ValidationError {
+            message: format!("Unknown function: {}", name),
+            value_type: "function".to_string(),
+        })
+    })?;
+    // The arguments haven't been type checked, so make sure some overload
+    // takes them before running one
+    let applies = |func: &crate::ast::FunctionDeclaration| {
+        func.parameters.len() == arg_values.len()
+            && func.parameters.iter().zip(arg_values).all(|(param, value)| matches_type(&param.ty, value))
+    };
+    if !functions.iter().any(applies) {
+        return Err(Error::Validation(ValidationError {
+            message: format!("No matching function '{}' found for given arguments", name),
+            value_type: "function".to_string(),
+        }));
+    }
+    call_overloads(name, functions, arg_values, &HashMap::new(), registry)
+}
+
+fn call_overloads(
+    name: &str,
+    functions: &[crate::ast::FunctionDeclaration],
+    arg_values: &[EvalValue],
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    // If only one function, execute it directly
+    if functions.len() == 1 {
+        let func_decl = &functions[0];
+        // Check argument count
+        if arg_values.len() != func_decl.parameters.len() {
+            return Err(Error::Validation(ValidationError {
+                message: format!(
+                    "Function {} expects {} arguments, got {}",
+                    name,
+                    func_decl.parameters.len(),
+                    arg_values.len()
+                ),
+                value_type: "function".to_string(),
+            }));
+        }
+
+        if takes_coerced(func_decl, arg_values, registry) {
+            return call_function(func_decl, &coerce_arguments(func_decl, arg_values, registry)?, registry);
+        }
+        call_function(func_decl, arg_values, registry)
+    } else {
+        // Multiple implementations - use dispatch, and coerce arguments
+        // only if no overload takes them as they are
+        match dispatch_function(name, functions, arg_values, context, registry) {
+            Err(error) if !functions.iter().any(|func| takes_types(func, arg_values)) => {
+                call_coerced(name, functions, arg_values, registry).unwrap_or(Err(error))
+            }
+            result => result,
+        }
+    }
+}
+
+/// Call `func` with arguments that match its parameters: run its Rust body
+/// if it's a native function, or else evaluate its Relic body.
+pub fn call_function(
+    func: &crate::ast::FunctionDeclaration,
+    arg_values: &[EvalValue],
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    registry.usage().enter(registry.limits())?;
+    let result = if let Some(native) = registry.native(func) {
+        native.call(arg_values, registry)
+    } else {
+        let mut func_context = HashMap::new();
+        for (param, value) in func.parameters.iter().zip(arg_values) {
+            func_context.insert(param.name.clone(), value.clone());
+        }
+        evaluate_expression(&func.body, &func_context, registry)
+    };
+    registry.usage().leave();
+    result
+}
+
+// Check whether a function accepts the arguments: the count and types
+// must match and every parameter guard must hold
+fn function_applies(
+    func: &crate::ast::FunctionDeclaration,
+    arg_values: &[EvalValue],
+    registry: &ValueRegistry,
+) -> bool {
+    takes_types(func, arg_values) && guards_hold(func, arg_values, registry)
+}
+
+/// Whether `func` takes arguments of the types of `arg_values`, as they are.
+fn takes_types(func: &crate::ast::FunctionDeclaration, arg_values: &[EvalValue]) -> bool {
+    func.parameters.len() == arg_values.len()
+        && func.parameters.iter().zip(arg_values).all(|(param, value)| matches_type(&param.ty, value))
+}
+
+/// The value type a parameter of type `ty` coerces `value` to: `ty` names
+/// a value type declared `coerce: true`, and `value` has the type of its
+/// parameter.
+fn coercion<'a>(ty: &'a crate::types::Type, value: &EvalValue, registry: &ValueRegistry) -> Option<&'a str> {
+    let crate::types::Type::Value(name) = ty else {
+        return None;
+    };
+    let declaration = &registry.constructors.get(name)?.declaration;
+    (declaration.body.coerce == Some(true) && matches_type(&declaration.parameter.ty, value)).then_some(name)
+}
+
+/// Whether `func` takes `arg_values` once some are coerced to the value
+/// types of their parameters.
+fn takes_coerced(func: &crate::ast::FunctionDeclaration, arg_values: &[EvalValue], registry: &ValueRegistry) -> bool {
+    func.parameters.len() == arg_values.len()
+        && !takes_types(func, arg_values)
+        && func.parameters.iter().zip(arg_values).all(|(param, value)| {
+            matches_type(&param.ty, value) || coercion(&param.ty, value, registry).is_some()
+        })
+}
+
+/// `arg_values` with those `func` takes by coercion constructed into the
+/// value types of their parameters, which validates them.
+fn coerce_arguments(
+    func: &crate::ast::FunctionDeclaration,
+    arg_values: &[EvalValue],
+    registry: &ValueRegistry,
+) -> Result<Vec<EvalValue>> {
+    func.parameters
+        .iter()
+        .zip(arg_values)
+        .map(|(param, value)| match matches_type(&param.ty, value) {
+            true => Ok(value.clone()),
+            false => match coercion(&param.ty, value, registry) {
+                Some(type_name) => construct(type_name, value, registry),
+                None => Ok(value.clone()),
+            },
+        })
+        .collect()
+}
+
+/// The call to the only overload of `name` that takes `arg_values` once
+/// some are coerced, when none takes them as they are; None if there is
+/// no such overload, or its guards don't hold.
+fn call_coerced(
+    name: &str,
+    functions: &[crate::ast::FunctionDeclaration],
+    arg_values: &[EvalValue],
+    registry: &ValueRegistry,
+) -> Option<Result<EvalValue>> {
+    let mut coerced = functions.iter().filter(|func| takes_coerced(func, arg_values, registry));
+    let func = coerced.next()?;
+    if coerced.next().is_some() {
+        return Some(Err(Error::Validation(ValidationError {
+            message: format!("Ambiguous function call '{}' - the arguments can be coerced for several overloads", name),
+            value_type: "function".to_string(),
+        })));
+    }
+    let arguments = match coerce_arguments(func, arg_values, registry) {
+        Ok(arguments) => arguments,
+        Err(error) => return Some(Err(error)),
+    };
+    guards_hold(func, &arguments, registry).then(|| call_function(func, &arguments, registry))
+}
+
+/// Whether the guards of `func` hold for arguments of its parameter types.
+pub(crate) fn guards_hold(
+    func: &crate::ast::FunctionDeclaration,
+    arg_values: &[EvalValue],
+    registry: &ValueRegistry,
+) -> bool {
+    if func.parameters.iter().all(|param| param.guard.is_none()) {
+        return true;
+    }
+
+    // Create context for guard evaluation
+    let mut guard_context = HashMap::new();
+    for (param, value) in func.parameters.iter().zip(arg_values.iter()) {
+        guard_context.insert(param.name.clone(), value.clone());
+    }
+
+    // Check if all guards are satisfied
+    func.parameters.iter().all(|param| match &param.guard {
+        Some(guard_expr) => matches!(
+            evaluate_expression(guard_expr, &guard_context, registry),
+            Ok(EvalValue::Boolean(true))
+        ),
+        None => true, // No guard means it's satisfied
+    })
+}
+
+// Evaluate a call with labelled arguments. Labels narrow the overloads to
+// those with matching parameter names; dispatch then picks among them as
+// usual. Named calls bypass the dispatch cache, since the same argument
+// types can resolve differently depending on the labels.
+fn evaluate_named_call(
+    name: &str,
+    args: &[Argument],
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    // Value constructors take their single parameter by name
+    if let Some(constructor) = registry.constructors.get(name) {
+        let parameter = &constructor.declaration.parameter.name;
+        let order = bind_arguments(&[parameter], args).ok_or_else(|| {
+            Error::Validation(ValidationError {
+                message: format!(
+                    "Value constructor {} takes one argument named '{}'",
+                    name, parameter
+                ),
+                value_type: "constructor".to_string(),
+            })
+        })?;
+        let args = order.iter().map(|&i| args[i].value.clone()).collect();
+        return evaluate_expression(&Expression::FunctionCall(name.to_string(), args), context, registry);
+    }
+
+    let functions = registry.get_functions(name).ok_or_else(|| {
+        Error::Validation(ValidationError {
+            message: format!("Unknown function or method: {}", name),
+            value_type: "function".to_string(),
+        })
+    })?;
+
+    // Evaluate each argument once, then line the values up with each
+    // overload's parameters
+    let mut values = Vec::new();
+    for arg in args {
+        values.push(evaluate_expression(&arg.value, context, registry)?);
+    }
+
+    let mut labels_match = false;
+    let mut candidates = Vec::new();
+    for func in functions {
+        let names: Vec<&str> = func.parameters.iter().map(|p| p.name.as_str()).collect();
+        let Some(order) = bind_arguments(&names, args) else {
+            continue;
         };
-        
-        // Add bonus for having a guard (more specific)
-        if param.guard.is_some() {
-            score += 2;
+        labels_match = true;
+
+        let ordered: Vec<EvalValue> = order.iter().map(|&i| values[i].clone()).collect();
+        if function_applies(func, &ordered, registry) {
+            let specificity = calculate_function_specificity(func);
+            candidates.push((func, ordered, specificity));
         }
     }
-    
-    score
+
+    if !labels_match {
+        return Err(Error::Validation(ValidationError {
+            message: format!("No overload of '{}' has parameters matching the argument labels", name),
+            value_type: "function".to_string(),
+        }));
+    }
+
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.2));
+    if candidates.len() >= 2 && candidates[0].2 == candidates[1].2 {
+        return Err(Error::Validation(ValidationError {
+            message: format!("Ambiguous function call '{}' - multiple functions with same specificity", name),
+            value_type: "function".to_string(),
+        }));
+    }
+
+    match candidates.into_iter().next() {
+        Some((func, ordered, _)) => call_function(func, &ordered, registry),
+        None => Err(Error::Validation(ValidationError {
+            message: format!("No matching function '{}' found for given arguments", name),
+            value_type: "function".to_string(),
+        })),
+    }
 }
 
-fn calculate_method_specificity(method: &crate::ast::MethodDeclaration, arg_values: &[EvalValue]) -> u32 {
+pub(crate) fn calculate_function_specificity(func: &crate::ast::FunctionDeclaration) -> u32 {
     let mut score = 0;
     
-    for (param, _value) in method.parameters.iter().zip(arg_values) {
+    for param in &func.parameters {
         score += match &param.ty {
             crate::types::Type::Int => 3,     // Specific types get higher scores
             crate::types::Type::String => 3,
@@ -850,6 +1412,11 @@ fn calculate_method_specificity(method: &crate::ast::MethodDeclaration, arg_valu
             crate::types::Type::Value(_) => 3,
             crate::types::Type::Type => 3,
             crate::types::Type::List(_) => 3,
+            crate::types::Type::Option(_) => 3,
+            crate::types::Type::Relation => 3,
+            crate::types::Type::Date | crate::types::Type::Time | crate::types::Type::Duration => 3,
+            crate::types::Type::Decimal => 3,
+            crate::types::Type::Query(_) => 3,
             crate::types::Type::Any => 1,     // Any is least specific
             crate::types::Type::Unknown => 0,
         };
@@ -869,14 +1436,16 @@ impl std::fmt::Display for EvalValue {
             EvalValue::String(s) => write!(f, "{}", s),
             EvalValue::Integer(n) => write!(f, "{}", n),
             EvalValue::Boolean(b) => write!(f, "{}", b),
-            EvalValue::Value { type_name, fields } => {
+            EvalValue::Value { display: Some(display), .. } => write!(f, "{}", display),
+            EvalValue::Value { type_name, sensitive: true, .. } => write!(f, "{}({})", type_name, REDACTED),
+            EvalValue::Value { type_name, fields, .. } => {
                 write!(f, "{}", type_name)?;
                 if !fields.is_empty() {
                     write!(f, "(")?;
                     let mut first = true;
                     // For single-parameter values, just show the value
                     if fields.len() == 1 {
-                        for (_name, value) in fields {
+                        for value in fields.values() {
                             write!(f, "{}", value)?;
                         }
                     } else {
@@ -906,6 +1475,430 @@ impl std::fmt::Display for EvalValue {
                 }
                 write!(f, "]")
             },
+            EvalValue::Option(Some(value)) => write!(f, "Some({})", value),
+            EvalValue::Option(None) => write!(f, "None"),
+            EvalValue::Temporal(t) => write!(f, "{}", t),
+            EvalValue::Decimal(d) => write!(f, "{}", d),
+            // As the literal that makes it, with strings quoted
+            EvalValue::Relation(relation) => {
+                let fields: Vec<_> = relation
+                    .schema()
+                    .fields
+                    .iter()
+                    .map(|(name, type_name)| format!("{}: {}", name, type_name))
+                    .collect();
+                write!(f, "Relation({})", fields.join(", "))?;
+                let rows: Vec<_> = relation
+                    .cells()
+                    .iter()
+                    .map(|row| {
+                        let cells: Vec<_> = row
+                            .iter()
+                            .map(|cell| match cell {
+                                EvalValue::String(s) => format!("{:?}", s),
+                                other => other.to_string(),
+                            })
+                            .collect();
+                        format!("({})", cells.join(", "))
+                    })
+                    .collect();
+                if !rows.is_empty() {
+                    write!(f, " {{ {} }}", rows.join(", "))?;
+                }
+                Ok(())
+            }
+            EvalValue::Query(query) => write!(f, "Query({})", query),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::Compiler;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn eval(source: &str, expr: &str) -> crate::Result<String> {
+        let program = Parser::new(Lexer::new(source.to_string()))?.parse_program()?;
+        let mut compiler = Compiler::new();
+        compiler.compile_program(&program)?;
+        let expr = Parser::new(Lexer::new(expr.to_string()))?.parse_expression()?;
+        compiler.evaluate_expression(&expr).map(|v| v.to_string())
+    }
+
+    #[test]
+    fn test_methods_dispatch_like_functions() {
+        use crate::ast::{Declaration, MethodDeclaration};
+        use crate::typechecker::TypeChecker;
+
+        let source = |keyword: &str, name: &str| {
+            format!(
+                "value Tag(label: String) {{}}\n\
+                 {k} {n}(n: Int where n > 100) -> String {{ \"big\" }}\n\
+                 {k} {n}(s: String) -> String {{ s.toUpperCase() }}\n\
+                 {k} {n}(t: Tag) -> String {{ \"tag\" }}\n\
+                 {k} {n}(a: Int, b: Int) -> String {{ \"pair\" }}",
+                k = keyword,
+                n = name
+            )
+        };
+        let calls = |name: &str| {
+            ["NAME(500)", "NAME(\"x\")", "NAME(Tag(\"a\"))", "NAME(1, 2)", "500.NAME()", "1.NAME(2)", "NAME(5)", "NAME(true)"]
+                .map(|call| call.replace("NAME", name))
+        };
+        let run = |program: &crate::ast::Program, name: &str| {
+            let mut checker = TypeChecker::new();
+            checker.check_program(program).unwrap();
+            let mut compiler = Compiler::new();
+            compiler.compile_program(program).unwrap();
+            calls(name).map(|call| {
+                let expr = Parser::new(Lexer::new(call)).unwrap().parse_expression().unwrap();
+                let checked = checker.check_expression(&expr).map(|ty| ty.to_string());
+                let value = compiler.evaluate_expression(&expr).map(|v| v.to_string());
+                format!("{:?} {:?}", checked, value)
+            })
+        };
+        let parse = |source: String| Parser::new(Lexer::new(source)).unwrap().parse_program().unwrap();
+
+        let functions = run(&parse(source("fn", "describe")), "describe");
+        let methods = run(&parse(source("method", "describe")), "describe");
+        assert_eq!(functions, methods);
+        assert!(functions[0].contains("Ok(\"big\")"), "{}", functions[0]);
+        assert!(functions[7].contains("Err"), "{}", functions[7]);
+
+        // As do methods built as such, rather than parsed
+        let mut program = parse(source("fn", "describe"));
+        for declaration in &mut program.declarations {
+            if let Declaration::Function(f) = declaration {
+                *declaration = Declaration::Method(MethodDeclaration {
+                    name: f.name.clone(),
+                    parameters: f.parameters.clone(),
+                    return_type: f.return_type.clone(),
+                    body: f.body.clone(),
+                    span: f.span,
+                });
+            }
+        }
+        assert_eq!(functions, run(&program, "describe"));
+    }
+
+    #[test]
+    fn test_labelled_arguments_reorder() {
+        let source = "fn sub(a: Int, b: Int) -> Int { a - b }";
+        assert_eq!(eval(source, "sub(b: 1, a: 10)").unwrap(), "9");
+        assert_eq!(eval(source, "sub(10, b: 1)").unwrap(), "9");
+        assert!(eval(source, "sub(10, c: 1)").is_err());
+    }
+
+    #[test]
+    fn test_labels_select_overload() {
+        let source = "fn size(width: Int) -> Int { width }\n\
+                      fn size(radius: Int) -> Int { radius * radius }";
+        assert_eq!(eval(source, "size(width: 3)").unwrap(), "3");
+        assert_eq!(eval(source, "size(radius: 3)").unwrap(), "9");
+    }
+
+    #[test]
+    fn test_block_body() {
+        let source = "fn f(x: Int) -> Int { let a = x + 1; let b = a * 2; a + b }\n\
+                      fn g(x: Int) -> Int { f(x); let x = x * 10; x }";
+        assert_eq!(eval(source, "f(1)").unwrap(), "6");
+        assert_eq!(eval(source, "g(2)").unwrap(), "20");
+    }
+
+    #[test]
+    fn test_labelled_constructor_argument() {
+        let source = "value Age(years: Int) { validate: years >= 0 }";
+        assert_eq!(eval(source, "Age(years: 30)").unwrap(), "Age(30)");
+        assert!(eval(source, "Age(months: 30)").is_err());
+    }
+
+    #[test]
+    fn test_normalization() {
+        let source = "value Name(raw: String) {\n\
+                          validate: raw != \"\"\n\
+                          normalize: raw.trim().collapseWhitespace().titleCase()\n\
+                      }";
+        assert_eq!(eval(source, "Name(\"  ada \t LOVELACE \")").unwrap(), "Name(Ada Lovelace)");
+        assert_eq!(eval(source, "Name(\"ÉMILE  zola\")").unwrap(), "Name(Émile Zola)");
+        // It is the normalized value that must be valid
+        assert!(eval(source, "Name(\" \n \")").is_err());
+    }
+
+    #[test]
+    fn test_string_operators() {
+        let source = "value Slug(raw: String) { normalize: \"#\" + raw.toLowerCase() }\n\
+                      fn banner(title: String) -> String { let rule = \"=\" * title.length; rule + \" \" + title }";
+        assert_eq!(eval(source, "Slug(\"Rust\")").unwrap(), "Slug(#rust)");
+        assert_eq!(eval(source, "banner(\"Ünïcode\")").unwrap(), "======= Ünïcode");
+        assert_eq!(eval(source, "3 * \"ab\" + \"\" * 0").unwrap(), "ababab");
+        assert!(eval(source, "\"ab\" * -1").is_err());
+        // Without a limit, a huge count fails the evaluation instead of
+        // exhausting memory
+        let error = crate::Engine::new().eval("\"ab\" * 100000000000").unwrap_err();
+        assert_eq!(error.code(), "E0301");
+        assert!(error.to_string().contains("would be over"), "{}", error);
+
+        // Repetition is refused before it builds a string over the limit
+        let limits = crate::limits::EvalLimits { max_string_len: Some(100), ..Default::default() };
+        let engine = crate::Engine::new().with_limits(limits);
+        let error = engine.eval("\"ab\" * 1000000000000").unwrap_err();
+        assert!(error.to_string().contains("more than the limit of 100"), "{}", error);
+    }
+
+    #[test]
+    fn test_list_contains() {
+        let mut engine = crate::Engine::new();
+        engine.load("value Email(raw: String) {}").unwrap();
+        engine.eval("Email(\"a@b\")").unwrap();
+        engine.eval("Email(\"c@d\")").unwrap();
+        let eval = |expr: &str| engine.eval(expr).unwrap().to_string();
+        assert_eq!(eval("Email.all() contains Email(\"a@b\")"), "true");
+        assert_eq!(eval("[e.raw for e in Email] contains \"c@d\""), "true");
+        assert_eq!(eval("[e.raw for e in Email] contains \"c\""), "false");
+        assert_eq!(eval("[e for e in Email if e.raw != \"a@b\"] contains Email(\"a@b\")"), "false");
+    }
+
+    #[test]
+    fn test_int_overflow() {
+        let source = "value Doubled(n: Int) { validate: n * 2 > 0 }";
+        for (expr, message) in [
+            ("9223372036854775807 + 1", "Addition overflows Int"),
+            ("(0 - 9223372036854775807) - 2", "Subtraction overflows Int"),
+            ("4611686018427387904 * 2", "Multiplication overflows Int"),
+            ("-(0 - 9223372036854775807 - 1)", "Negation overflows Int"),
+            ("Doubled(9223372036854775807)", "Multiplication overflows Int"),
+        ] {
+            let error = eval(source, expr).unwrap_err();
+            assert_eq!(error.code(), "E0301", "{}", expr);
+            assert!(error.to_string().contains(message), "{}: {}", expr, error);
+        }
+        assert_eq!(eval(source, "9223372036854775806 + 1").unwrap(), "9223372036854775807");
+        assert_eq!(eval(source, "Doubled(3)").unwrap(), "Doubled(3)");
+
+        // Also inside a `let` in a validate clause
+        let source = "value A(n: Int) { validate: let m = n * n in m > 0 }\n\
+                      value B(n: Int) { validate: let m = n + n in m > 0 }";
+        for expr in ["A(9223372036854775807)", "B(9223372036854775807)"] {
+            let error = eval(source, expr).unwrap_err();
+            assert_eq!(error.code(), "E0301", "{}: {}", expr, error);
+            assert!(error.to_string().contains("overflows Int"), "{}: {}", expr, error);
         }
+        assert_eq!(eval(source, "A(3)").unwrap(), "A(3)");
+    }
+
+    #[test]
+    fn test_unicode_names() {
+        let source = "value Straße(name: String) { validate: name.length > 0 }\n\
+                      fn länge(s: Straße) -> Int { s.name.length }";
+        assert_eq!(eval(source, "länge(Straße(\"Hauptstraße\"))").unwrap(), "11");
+    }
+
+    #[test]
+    fn test_script_environment() {
+        use crate::stdlib::ScriptEnvironment;
+
+        let environment = ScriptEnvironment {
+            arguments: vec!["data.csv".to_string()],
+            variables: [("LIMIT".to_string(), "10".to_string())].into_iter().collect(),
+        };
+        let compiler = Compiler::new().with_script_environment(environment);
+        let eval = |expr: &str| {
+            let expr = Parser::new(Lexer::new(expr.to_string())).unwrap().parse_expression().unwrap();
+            compiler.evaluate_expression(&expr).unwrap().to_string()
+        };
+
+        assert_eq!(eval("args()"), "[data.csv]");
+        assert_eq!(eval("env(\"LIMIT\")"), "Some(10)");
+        assert_eq!(eval("env(\"HOME\").getOrElse(\"none\")"), "none");
+        assert_eq!(eval("env(\"LIMIT\").isSome()"), "true");
+        assert_eq!(eval("args() contains \"data.csv\""), "true");
+        assert_eq!(eval("args() contains \"data\""), "false");
+
+        // Nothing from the process unless the host passes it on
+        let registry = Compiler::new().into_registry();
+        assert_eq!(registry.script_environment(), &ScriptEnvironment::default());
+    }
+
+    #[test]
+    fn test_option_operators() {
+        let mut engine = crate::Engine::new();
+        engine.load("value Order(id: String) {}").unwrap();
+        let eval = |expr: &str| engine.eval(expr).map(|value| value.to_string());
+        let latest = "Order.max(o => o.id)";
+
+        assert_eq!(eval(&format!("{}?.toUpperCase()", latest)).unwrap(), "None");
+        assert_eq!(eval(&format!("{}?.length ?? 0", latest)).unwrap(), "0");
+        let error = eval(&format!("{}!", latest)).unwrap_err();
+        assert!(error.to_string().contains("Order.max(o => o.id)!` unwrapped None"), "{}", error);
+
+        eval("Order(\"a-7\")").unwrap();
+        assert_eq!(eval(&format!("{}?.toUpperCase()", latest)).unwrap(), "Some(A-7)");
+        assert_eq!(eval(&format!("{}?.length ?? 0", latest)).unwrap(), "3");
+        assert_eq!(eval(&format!("{}!.length * 2", latest)).unwrap(), "6");
+        // The default isn't evaluated when there is a value
+        assert_eq!(eval("Order.max(o => o.id.length) ?? 1 / 0").unwrap(), "3");
+
+        // Each operator needs an option, and `??` a default of its type
+        assert!(eval("\"a\"?.length").unwrap_err().to_string().contains("`?.` needs an Option"));
+        assert!(eval("1!").is_err());
+        assert!(eval(&format!("{} ?? 0", latest)).unwrap_err().to_string().contains("default of `??`"));
+    }
+
+    #[test]
+    fn test_sensitive_values() {
+        let mut engine = crate::Engine::new();
+        engine
+            .load(
+                "value Password(secret: String) { validate: secret.length > 3\n sensitive: true }\n\
+                 value Card(number: String) { display: \"Card(#{number.length} digits)\"\n sensitive: true }",
+            )
+            .unwrap();
+        let password = engine.eval("Password(\"hunter2\")").unwrap();
+        assert_eq!(password.to_string(), "Password(<redacted>)");
+        assert!(!format!("{:?}", password).contains("hunter2"));
+        assert_eq!(engine.eval("all(Password)").unwrap().to_string(), "[Password(<redacted>)]");
+        let table = crate::table::render(&engine.get_instances("Password").unwrap(), 10).unwrap();
+        assert!(table.contains("<redacted>") && !table.contains("hunter2"), "{}", table);
+        assert_eq!(engine.registry().recent_instances("Password", 1)[0].to_string(), "Password(<redacted>)");
+
+        // The parameter is still there for code that asks for it
+        assert_eq!(engine.eval("Password(\"hunter2\").secret").unwrap().to_string(), "hunter2");
+        // A display template shows what it chooses to
+        assert_eq!(engine.eval("Card(\"4111111111111111\")").unwrap().to_string(), "Card(16 digits)");
+        // Errors don't repeat the argument
+        let error = engine.construct("Password", 4).unwrap_err().to_string();
+        assert!(error.contains("found <redacted>"), "{}", error);
+        assert!(!error.contains('4'), "{}", error);
+    }
+
+    #[test]
+    fn test_method_resolution() {
+        let mut engine = crate::Engine::new();
+        engine
+            .load(
+                "value Person(name: String) {}
+                 fn length(p: Person) -> Int { 99 }
+                 fn trim(s: String) -> String { \"mine\" }
+                 fn shout(s: String where s.length > 3) -> String { s.toUpperCase() }
+                 fn shout(n: Int) -> String { \"number\" }
+                 fn toUpperCase(n: Int where n > 0) -> Int { n }
+                 fn toUpperCase(b: Bool) -> Bool { b }",
+            )
+            .unwrap();
+        let eval = |source: &str| engine.eval(source).map(|value| value.to_string());
+
+        // A function shadows a built-in method for the receivers it takes
+        assert_eq!(eval("Person(\"a\").length()").unwrap(), "99");
+        assert_eq!(eval("Person.all().length()").unwrap(), "1");
+        assert_eq!(engine.type_of("Person.all().length()").unwrap(), crate::types::Type::Int);
+        assert_eq!(eval("\" x \".trim()").unwrap(), "mine");
+        assert_eq!(eval("\"abc\".toUpperCase()").unwrap(), "ABC");
+
+        // Its guards don't make a call fall through to the built-in
+        assert_eq!(eval("\"loud\".shout()").unwrap(), "LOUD");
+        assert!(eval("\"no\".shout()").unwrap_err().to_string().contains("No matching function 'shout'"));
+        assert_eq!(eval("(5).toUpperCase()").unwrap(), "5");
+        assert!(eval("(-5).toUpperCase()").is_err());
+
+        // Neither a function nor a built-in method
+        let error = eval("(1).trim()").unwrap_err().to_string();
+        assert!(error.contains("Mismatched types in receiver of 'trim'"), "{}", error);
+        let error = eval("true.titleCase()").unwrap_err().to_string();
+        assert!(error.contains("Type `Bool` has no method 'titleCase'"), "{}", error);
+
+        // The evaluator agrees with the type checker without it
+        let registry = engine.registry();
+        let call = |source: &str| {
+            let mut parser = Parser::new(Lexer::new(source.to_string())).unwrap();
+            let expression = parser.parse_expression().unwrap();
+            super::evaluate_expression(&expression, &Default::default(), registry).map(|value| value.to_string())
+        };
+        assert_eq!(call("Person.all().length()").unwrap(), "1");
+        assert_eq!(call("\" x \".trim()").unwrap(), "mine");
+        let error = call("true.titleCase()").unwrap_err().to_string();
+        assert!(error.contains("No function or method 'titleCase' takes a receiver of type Bool and 0 arguments"));
+    }
+
+    #[test]
+    fn test_coercion() {
+        let mut engine = crate::Engine::new();
+        engine
+            .load(
+                "value Age(n: Int) { validate: n >= 0\n coerce: true }
+                 value Score(n: Int) { coerce: true }
+                 value Label(text: String) {}
+                 fn years(a: Age) -> Int { a.n }
+                 fn kind(a: Age) -> String { \"age\" }
+                 fn kind(s: String) -> String { \"string\" }
+                 fn size(n: Int) -> String { \"int\" }
+                 fn size(a: Age) -> String { \"age\" }
+                 fn rate(a: Age) -> Int { 1 }
+                 fn rate(s: Score) -> Int { 2 }
+                 fn tag(l: Label) -> String { l.text }",
+            )
+            .unwrap();
+        let eval = |source: &str| engine.eval(source).map(|value| value.to_string());
+
+        // An Int stands for an Age, which is constructed and validated
+        assert_eq!(eval("years(30)").unwrap(), "30");
+        assert_eq!(eval("(30).years()").unwrap(), "30");
+        assert_eq!(engine.type_of("years(30)").unwrap(), crate::types::Type::Int);
+        assert!(matches!(engine.eval("years(-1)"), Err(crate::Error::Validation(_))));
+        assert_eq!(eval("Age.count()").unwrap(), "2");
+        assert_eq!(eval("kind(3)").unwrap(), "age");
+        assert_eq!(eval("kind(\"x\")").unwrap(), "string");
+
+        // An overload that takes the argument as it is comes first
+        assert_eq!(eval("size(3)").unwrap(), "int");
+        assert_eq!(eval("size(Age(3))").unwrap(), "age");
+
+        // Coercion is opt-in, and must pick one overload
+        let error = eval("tag(\"x\")").unwrap_err().to_string();
+        assert!(error.contains("Mismatched types in argument 1 of 'tag'"), "{}", error);
+        let error = eval("rate(3)").unwrap_err().to_string();
+        assert!(error.contains("Ambiguous call to 'rate'"), "{}", error);
+        assert_eq!(eval("rate(Score(3))").unwrap(), "2");
+    }
+
+    #[test]
+    fn test_list_patterns() {
+        let mut engine = crate::Engine::new();
+        engine
+            .load(
+                "value Person(name: String) {}
+                 value User(name: String) {}
+                 fn first(people: List[Person]) -> String { match people { [] => \"nobody\", [p, ...rest] => p.name } }
+                 fn second(people: List[Person]) -> String { match people { [a, b, ...rest] => b.name, [...all] => \"\" } }
+                 fn pair(people: List[Person]) -> Bool { match people { [a, b] => true, [...all] => false } }
+                 fn others(people: List[Person]) -> List[Person] { match people { [p, ...rest] => rest, [] => people } }",
+            )
+            .unwrap();
+        let eval = |source: &str| engine.eval(source).map(|value| value.to_string());
+
+        assert_eq!(eval("first(Person.all())").unwrap(), "nobody");
+        assert_eq!(eval("second(Person.all())").unwrap(), "");
+        engine.eval("Person(\"Ada\")").unwrap();
+        engine.eval("Person(\"Grace\")").unwrap();
+        assert_eq!(eval("first(Person.all())").unwrap(), "Ada");
+        assert_eq!(eval("second(Person.all())").unwrap(), "Grace");
+        assert_eq!(eval("pair(Person.all())").unwrap(), "true");
+        assert_eq!(eval("first(others(Person.all()))").unwrap(), "Grace");
+        engine.eval("Person(\"Edsger\")").unwrap();
+        assert_eq!(eval("pair(Person.all())").unwrap(), "false");
+        assert_eq!(engine.type_of("others(Person.all())").unwrap().to_string(), "List[Person]");
+
+        // The README's example, matching a type's instances directly
+        let example = "match User.all() { [] => \"nobody\", [first, ...rest] => first.name }";
+        assert_eq!(engine.type_of(example).unwrap(), crate::types::Type::String);
+        assert_eq!(eval(example).unwrap(), "nobody");
+        engine.eval("User(\"Barbara\")").unwrap();
+        assert_eq!(eval(example).unwrap(), "Barbara");
+
+        // A list pattern only matches lists, and some arm must match
+        let error = engine.type_of("match Person(\"Ada\") { [] => 0 }").unwrap_err().to_string();
+        assert!(error.contains("List patterns can only match lists"), "{}", error);
+        let error = eval("match Person.all() { [] => 0, [a] => 1 }").unwrap_err().to_string();
+        assert!(error.contains("No match arm matches"), "{}", error);
     }
-}
\ No newline at end of file
+}
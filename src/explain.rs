@@ -0,0 +1,309 @@
+//! Explaining how a call is dispatched. For each overload of the function,
+//! an explanation says whether it takes the arguments (and if not, why
+//! not), how specific it is and how its guards evaluated, and then which
+//! overload the call would run.
+
+use crate::ast::{Expression, FunctionDeclaration};
+use crate::compiler::Compiler;
+use crate::error::{Error, Result, TypeError};
+use crate::evaluator::{self, EvalValue};
+use crate::formatter::{format_signature, format_type};
+use crate::typechecker::TypeChecker;
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::collections::HashMap;
+use std::fmt;
+
+/// How a call to `function` with `arguments` is dispatched.
+#[derive(Debug, Clone)]
+pub struct DispatchExplanation {
+    pub function: String,
+    pub arguments: Vec<EvalValue>,
+    /// Every overload of the function, in the order they were defined
+    pub candidates: Vec<Candidate>,
+    pub selection: Selection,
+    /// The overload the dispatch cache holds for these argument types, if
+    /// any. A call uses it instead of dispatching again.
+    pub cached: Option<usize>,
+}
+
+/// One overload, and how it fares against the arguments.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub overload: FunctionDeclaration,
+    /// Why the overload doesn't take the arguments, if it doesn't
+    pub rejection: Option<Rejection>,
+    /// Higher is preferred; each typed parameter and each guard adds to it
+    pub specificity: u32,
+    /// The parameters with guards, and how each guard evaluated
+    pub guards: Vec<(String, GuardResult)>,
+}
+
+impl Candidate {
+    pub fn applies(&self) -> bool {
+        self.rejection.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    Arity { expected: usize, found: usize },
+    Type { parameter: String, expected: Type, found: EvalValue },
+    Guard { parameter: String },
+}
+
+#[derive(Debug, Clone)]
+pub enum GuardResult {
+    Passed,
+    Failed,
+    /// The guard didn't evaluate to a Bool; it counts as failed
+    Error(Error),
+}
+
+/// Which overload a call runs, by index into `candidates`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    /// The function's only overload, which is called without dispatching,
+    /// so its guards aren't checked
+    Only(usize),
+    /// The most specific overload that applies
+    Best(usize),
+    /// The most specific overloads that apply are equally specific, so the
+    /// call fails
+    Ambiguous(Vec<usize>),
+    /// No overload applies, so the call fails
+    NoMatch,
+}
+
+impl DispatchExplanation {
+    /// The overload the call would run, if any.
+    pub fn selected(&self) -> Option<usize> {
+        match self.selection {
+            Selection::Only(index) => Some(index),
+            Selection::Best(index) => Some(self.cached.unwrap_or(index)),
+            Selection::Ambiguous(_) | Selection::NoMatch => self.cached,
+        }
+    }
+}
+
+/// Explain how a call of the function `name` with `arguments` is dispatched.
+pub fn explain(name: &str, arguments: &[EvalValue], registry: &ValueRegistry) -> Result<DispatchExplanation> {
+    let functions = registry.get_functions(name).ok_or_else(|| {
+        let message = if registry.constructors.contains_key(name) {
+            format!("{} is a value type; constructing it doesn't dispatch", name)
+        } else {
+            format!("Unknown function: {}", name)
+        };
+        Error::Type(TypeError { message, span: None })
+    })?;
+
+    let candidates: Vec<_> = functions.iter().map(|func| candidate(func, arguments, registry)).collect();
+    let selection = if let [only] = candidates.as_slice() {
+        match only.rejection {
+            Some(Rejection::Arity { .. }) => Selection::NoMatch,
+            _ => Selection::Only(0),
+        }
+    } else {
+        let best = candidates.iter().filter(|c| c.applies()).map(|c| c.specificity).max();
+        let most_specific: Vec<_> = (0..candidates.len())
+            .filter(|&i| candidates[i].applies() && Some(candidates[i].specificity) == best)
+            .collect();
+        match most_specific.as_slice() {
+            [] => Selection::NoMatch,
+            [index] => Selection::Best(*index),
+            _ => Selection::Ambiguous(most_specific),
+        }
+    };
+    let cached = match selection {
+        Selection::Only(_) => None,
+        _ => evaluator::cached_overload(name, arguments, registry).filter(|&i| i < candidates.len()),
+    };
+
+    Ok(DispatchExplanation {
+        function: name.to_string(),
+        arguments: arguments.to_vec(),
+        candidates,
+        selection,
+        cached,
+    })
+}
+
+/// Explain the dispatch of `call`, a call written in Relic such as
+/// `area(Circle(2))`. Its arguments are type checked and evaluated first.
+pub fn explain_call(
+    call: &Expression,
+    typechecker: &mut TypeChecker,
+    compiler: &Compiler,
+) -> Result<DispatchExplanation> {
+    let Expression::FunctionCall(name, arguments) = call else {
+        return Err(Error::Type(TypeError {
+            message: "Expected a function call with positional arguments, as in f(1, \"x\")".to_string(),
+            span: None,
+        }));
+    };
+    let mut values = Vec::new();
+    for argument in arguments {
+        typechecker.check_expression(argument)?;
+        values.push(compiler.evaluate_expression(argument)?);
+    }
+    explain(name, &values, compiler.get_registry())
+}
+
+fn candidate(func: &FunctionDeclaration, arguments: &[EvalValue], registry: &ValueRegistry) -> Candidate {
+    let mut candidate = Candidate {
+        overload: func.clone(),
+        rejection: None,
+        specificity: evaluator::calculate_function_specificity(func),
+        guards: Vec::new(),
+    };
+    if func.parameters.len() != arguments.len() {
+        candidate.rejection = Some(Rejection::Arity {
+            expected: func.parameters.len(),
+            found: arguments.len(),
+        });
+        return candidate;
+    }
+    let mismatch = func.parameters.iter().zip(arguments).find(|(p, value)| !evaluator::matches_type(&p.ty, value));
+    if let Some((parameter, value)) = mismatch {
+        candidate.rejection = Some(Rejection::Type {
+            parameter: parameter.name.clone(),
+            expected: parameter.ty.clone(),
+            found: value.clone(),
+        });
+        return candidate;
+    }
+
+    // Guards see every parameter, as when the overload is dispatched to
+    let context: HashMap<_, _> = func
+        .parameters
+        .iter()
+        .zip(arguments)
+        .map(|(p, value)| (p.name.clone(), value.clone()))
+        .collect();
+    for parameter in &func.parameters {
+        let Some(guard) = &parameter.guard else { continue };
+        let result = match evaluator::evaluate_expression(guard, &context, registry) {
+            Ok(EvalValue::Boolean(true)) => GuardResult::Passed,
+            Ok(EvalValue::Boolean(false)) => GuardResult::Failed,
+            Ok(other) => GuardResult::Error(Error::Type(TypeError {
+                message: format!("Guard evaluated to {}, not a Bool", other),
+                span: None,
+            })),
+            Err(e) => GuardResult::Error(e),
+        };
+        if candidate.rejection.is_none() && !matches!(result, GuardResult::Passed) {
+            candidate.rejection = Some(Rejection::Guard {
+                parameter: parameter.name.clone(),
+            });
+        }
+        candidate.guards.push((parameter.name.clone(), result));
+    }
+    candidate
+}
+
+impl fmt::Display for DispatchExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arguments: Vec<_> = self.arguments.iter().map(|a| a.to_string()).collect();
+        writeln!(f, "Overloads of {} for ({}):", self.function, arguments.join(", "))?;
+        for (i, candidate) in self.candidates.iter().enumerate() {
+            writeln!(f, "  {}. {}", i + 1, format_signature(&candidate.overload))?;
+            match &candidate.rejection {
+                Some(Rejection::Arity { expected, found }) => {
+                    writeln!(f, "     doesn't apply: has {} parameters for {} arguments", expected, found)?
+                }
+                Some(Rejection::Type { parameter, expected, found }) => {
+                    writeln!(f, "     doesn't apply: {} must be {}, not {}", parameter, format_type(expected), found)?
+                }
+                Some(Rejection::Guard { .. }) | None => {
+                    let verdict = if candidate.applies() { "applies" } else { "doesn't apply" };
+                    writeln!(f, "     {}, specificity {}", verdict, candidate.specificity)?
+                }
+            }
+            for (parameter, result) in &candidate.guards {
+                match result {
+                    GuardResult::Passed => writeln!(f, "     guard on {} passed", parameter)?,
+                    GuardResult::Failed => writeln!(f, "     guard on {} failed", parameter)?,
+                    GuardResult::Error(e) => writeln!(f, "     guard on {} failed: {}", parameter, e)?,
+                }
+            }
+        }
+
+        match &self.selection {
+            Selection::Only(i) => write!(f, "Calls {}, the only overload; its guards aren't checked", i + 1)?,
+            Selection::Best(i) => write!(f, "Calls {}, the most specific overload that applies", i + 1)?,
+            Selection::Ambiguous(indices) => {
+                let numbers: Vec<_> = indices.iter().map(|i| (i + 1).to_string()).collect();
+                write!(f, "Ambiguous: {} apply with the same specificity", numbers.join(" and "))?
+            }
+            Selection::NoMatch => write!(f, "No overload applies")?,
+        }
+        if let Some(cached) = self.cached {
+            if self.selection != Selection::Best(cached) {
+                write!(
+                    f,
+                    "\nBut the dispatch cache already resolved these argument types to {}, so that is what runs",
+                    cached + 1
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_explain_dispatch() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "fn size(n: Int) -> String { \"any\" }\n\
+                 fn size(n: Int where n > 100) -> String { \"big\" }\n\
+                 fn size(s: String) -> String { s }\n\
+                 fn size(a: Int, b: Int) -> String { \"two\" }",
+            )
+            .unwrap();
+
+        let explanation = engine.explain_dispatch("size(500)").unwrap();
+        assert_eq!(explanation.selection, Selection::Best(1));
+        let rejections: Vec<_> = explanation.candidates.iter().map(|c| c.rejection.clone()).collect();
+        assert_eq!(
+            rejections,
+            [
+                None,
+                None,
+                Some(Rejection::Type {
+                    parameter: "s".to_string(),
+                    expected: Type::String,
+                    found: EvalValue::Integer(500),
+                }),
+                Some(Rejection::Arity { expected: 2, found: 1 }),
+            ]
+        );
+        assert!(explanation.candidates[1].specificity > explanation.candidates[0].specificity);
+        assert!(explanation.to_string().contains("     guard on n passed\n"));
+
+        let explanation = engine.explain_dispatch("size(5)").unwrap();
+        assert_eq!(explanation.candidates[1].rejection, Some(Rejection::Guard { parameter: "n".to_string() }));
+        assert_eq!(explanation.selection, Selection::Best(0));
+        assert!(explanation.to_string().ends_with("Calls 1, the most specific overload that applies"));
+
+        assert!(engine.explain_dispatch("missing(1)").is_err());
+        assert!(engine.explain_dispatch("1 + 2").is_err());
+    }
+
+    #[test]
+    fn test_single_overload_skips_guards() {
+        let mut engine = Engine::new();
+        engine.load("fn positive(n: Int where n > 0) -> Int { n }").unwrap();
+
+        let explanation = engine.explain_dispatch("positive(0 - 1)").unwrap();
+        assert_eq!(explanation.selection, Selection::Only(0));
+        assert_eq!(explanation.selected(), Some(0));
+        assert!(!explanation.candidates[0].applies());
+        assert_eq!(explanation.cached, None);
+    }
+}
@@ -0,0 +1,271 @@
+//! A C interface to `Engine`, for embedding Relic in programs written in
+//! other languages. The library builds as a `cdylib`, and `include/relic.h`
+//! declares these functions.
+//!
+//! Every function takes the engine it works on. Those that can fail return
+//! 0 or a pointer on success, and -1 or NULL on failure, after which
+//! `relic_last_error` and `relic_last_error_code` describe what went wrong.
+//! Strings passed in must be NUL-terminated UTF-8.
+
+use crate::engine::Engine;
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::EvalValue;
+use crate::json;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+/// An engine, with the last error it reported.
+pub struct RelicEngine {
+    engine: Engine,
+    // Kept as C strings so that the pointers handed out stay valid until
+    // the next call
+    error_message: Option<CString>,
+    error_code: Option<CString>,
+}
+
+impl RelicEngine {
+    /// Record the outcome of a call: clear the last error, or keep `error`.
+    fn report<T>(&mut self, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.error_message = None;
+                self.error_code = None;
+                Some(value)
+            }
+            Err(error) => {
+                self.error_message = Some(c_string(error.to_string()));
+                self.error_code = Some(c_string(error.code().to_string()));
+                None
+            }
+        }
+    }
+}
+
+/// Create an engine. Free it with `relic_engine_free`.
+#[no_mangle]
+pub extern "C" fn relic_engine_new() -> *mut RelicEngine {
+    Box::into_raw(Box::new(RelicEngine {
+        engine: Engine::new(),
+        error_message: None,
+        error_code: None,
+    }))
+}
+
+/// Free an engine made by `relic_engine_new`. Does nothing given NULL.
+///
+/// # Safety
+///
+/// `engine` must be NULL or come from `relic_engine_new`, and not be used
+/// again.
+#[no_mangle]
+pub unsafe extern "C" fn relic_engine_free(engine: *mut RelicEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Define the declarations in `source` and run its expressions; see
+/// `Engine::load`. Returns 0, or -1 on failure.
+///
+/// # Safety
+///
+/// `engine` must come from `relic_engine_new`, and `source` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn relic_load(engine: *mut RelicEngine, source: *const c_char) -> c_int {
+    let Some(engine) = engine.as_mut() else {
+        return -1;
+    };
+    let result = str_argument(source, "source").and_then(|source| engine.engine.load(source));
+    match engine.report(result) {
+        Some(_) => 0,
+        None => -1,
+    }
+}
+
+/// Evaluate the expression `source`, returning its value as text, or NULL
+/// on failure. Free the text with `relic_string_free`.
+///
+/// # Safety
+///
+/// `engine` must come from `relic_engine_new`, and `source` must be a
+/// NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn relic_eval(engine: *mut RelicEngine, source: *const c_char) -> *mut c_char {
+    let Some(engine) = engine.as_mut() else {
+        return ptr::null_mut();
+    };
+    let result = str_argument(source, "source").and_then(|source| engine.engine.eval(source));
+    match engine.report(result) {
+        Some(value) => c_string(value.to_string()).into_raw(),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Construct a value of the value type `type_name`, as `TypeName(value)`
+/// would: it must pass the type's validation, and is recorded as one of
+/// the type's instances. `value` is the parameter as text, such as `42`
+/// for an `Int`, `true` for a `Bool` or `12.50` for a `Decimal`. Returns 0,
+/// or -1 on failure.
+///
+/// # Safety
+///
+/// `engine` must come from `relic_engine_new`, and `type_name` and `value`
+/// must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn relic_construct(
+    engine: *mut RelicEngine,
+    type_name: *const c_char,
+    value: *const c_char,
+) -> c_int {
+    let Some(engine) = engine.as_mut() else {
+        return -1;
+    };
+    let result = str_argument(type_name, "type_name").and_then(|type_name| {
+        let value = parameter(&engine.engine, type_name, str_argument(value, "value")?)?;
+        engine.engine.construct(type_name, value)
+    });
+    match engine.report(result) {
+        Some(_) => 0,
+        None => -1,
+    }
+}
+
+/// The message of the error from the last call on `engine`, or NULL if it
+/// succeeded. Valid until the next call on `engine`.
+///
+/// # Safety
+///
+/// `engine` must come from `relic_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn relic_last_error(engine: *const RelicEngine) -> *const c_char {
+    match engine.as_ref().and_then(|engine| engine.error_message.as_ref()) {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// The code of the error from the last call on `engine`, such as `E0301`
+/// for failed validation, or NULL if it succeeded. Valid until the next
+/// call on `engine`.
+///
+/// # Safety
+///
+/// `engine` must come from `relic_engine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn relic_last_error_code(engine: *const RelicEngine) -> *const c_char {
+    match engine.as_ref().and_then(|engine| engine.error_code.as_ref()) {
+        Some(code) => code.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// Free a string returned by `relic_eval`. Does nothing given NULL.
+///
+/// # Safety
+///
+/// `s` must be NULL or come from `relic_eval`, and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn relic_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// The string `s` points to, or an error naming the argument if it's NULL
+/// or not UTF-8.
+unsafe fn str_argument<'a>(s: *const c_char, name: &str) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(argument_error(format!("{} is NULL", name)));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| argument_error(format!("{} is not valid UTF-8", name)))
+}
+
+/// The parameter of a `type_name` value, read from `text` according to the
+/// parameter's type.
+fn parameter(engine: &Engine, type_name: &str, text: &str) -> Result<EvalValue> {
+    let Some(constructor) = engine.registry().constructors.get(type_name) else {
+        return Err(argument_error(format!("Unknown value type: {}", type_name)));
+    };
+    let ty = &constructor.declaration.parameter.ty;
+    json::from_text(ty, text, engine.registry())
+        .map_err(|_| argument_error(format!("'{}' is not a valid {} for {}", text, ty, type_name)))
+}
+
+fn argument_error(message: String) -> Error {
+    Error::Validation(ValidationError {
+        message,
+        value_type: "argument".to_string(),
+    })
+}
+
+/// `s` as a C string, with any NUL bytes in it replaced.
+fn c_string(s: String) -> CString {
+    CString::new(s.replace('\0', "\u{fffd}")).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: *const c_char) -> Option<String> {
+        (!s.is_null()).then(|| unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn test_c_interface() {
+        let source = c"value Age(n: Int) { validate: n >= 0 }";
+        let age = c"Age";
+        unsafe {
+            let engine = relic_engine_new();
+            assert_eq!(relic_load(engine, source.as_ptr()), 0);
+            assert_eq!(text(relic_last_error(engine)), None);
+
+            assert_eq!(relic_construct(engine, age.as_ptr(), c"30".as_ptr()), 0);
+            assert_eq!(relic_construct(engine, age.as_ptr(), c"-1".as_ptr()), -1);
+            assert_eq!(text(relic_last_error_code(engine)).as_deref(), Some("E0301"));
+            assert_eq!(relic_construct(engine, age.as_ptr(), c"thirty".as_ptr()), -1);
+            assert!(text(relic_last_error(engine)).unwrap().contains("'thirty' is not a valid Int"));
+
+            let count = relic_eval(engine, c"Age.count()".as_ptr());
+            assert_eq!(text(count).as_deref(), Some("1"));
+            relic_string_free(count);
+
+            assert!(relic_eval(engine, c"Age(".as_ptr()).is_null());
+            assert_eq!(text(relic_last_error_code(engine)).as_deref(), Some("E0101"));
+            assert_eq!(relic_load(engine, ptr::null()), -1);
+
+            (*engine).engine.cancellation_token().cancel();
+            assert!(relic_eval(engine, c"1 + 1".as_ptr()).is_null());
+            assert_eq!(text(relic_last_error_code(engine)).as_deref(), Some("E0601"));
+            (*engine).engine.cancellation_token().reset();
+
+            relic_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_parameters_of_other_types() {
+        let source = c"value Price(amount: Decimal) { validate: amount > 0.00 }\nvalue Due(on: Date) {}";
+        let price = c"Price";
+        unsafe {
+            let engine = relic_engine_new();
+            assert_eq!(relic_load(engine, source.as_ptr()), 0);
+
+            assert_eq!(relic_construct(engine, price.as_ptr(), c"12.50".as_ptr()), 0);
+            assert_eq!(relic_construct(engine, price.as_ptr(), c"0.00".as_ptr()), -1);
+            assert_eq!(text(relic_last_error_code(engine)).as_deref(), Some("E0301"));
+            assert_eq!(relic_construct(engine, price.as_ptr(), c"twelve".as_ptr()), -1);
+            assert!(text(relic_last_error(engine)).unwrap().contains("'twelve' is not a valid Decimal"));
+            assert_eq!(relic_construct(engine, c"Due".as_ptr(), c"2024-01-31".as_ptr()), 0);
+
+            let count = relic_eval(engine, c"Price.count() + Due.count()".as_ptr());
+            assert_eq!(text(count).as_deref(), Some("2"));
+            relic_string_free(count);
+
+            relic_engine_free(engine);
+        }
+    }
+}
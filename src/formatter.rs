@@ -0,0 +1,1224 @@
+use crate::ast::*;
+use crate::error::Error;
+use crate::lexer::{Comment, Lexer, Span, Token};
+use crate::parser::Parser;
+use crate::types::Type;
+
+const INDENT: usize = 4;
+// Pipelines longer than this are broken one stage per line
+const MAX_WIDTH: usize = 100;
+
+/// Format a source file canonically, keeping its comments.
+///
+/// Fails with every syntax error in the file if it does not parse.
+pub fn format_source(source: &str) -> Result<String, Vec<Error>> {
+    let lexer = Lexer::new(source.to_string());
+    let mut parser = Parser::new(lexer).map_err(|e| vec![e])?;
+    let (program, errors) = parser.parse_program_recovering();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let trivia = Trivia::collect(source).map_err(|e| vec![e])?;
+    let mut printer = Printer::new(Some(trivia));
+    printer.program(&program);
+    Ok(printer.finish())
+}
+
+/// Format a program without any comments.
+pub fn format_program(program: &Program) -> String {
+    let mut printer = Printer::new(None);
+    printer.program(program);
+    printer.finish()
+}
+
+/// Format an expression on a single line.
+pub fn format_expression(expr: &Expression) -> String {
+    let mut printer = Printer::new(None);
+    printer.flat = true;
+    printer.expr(expr, 0);
+    printer.out
+}
+
+/// Format a type as it is written in source.
+pub fn format_type(ty: &Type) -> String {
+    let mut printer = Printer::new(None);
+    printer.ty(ty);
+    printer.out
+}
+
+/// Format a function's signature, with its guards, as in
+/// `name(p: T where guard, ...) -> R`.
+pub fn format_signature(func: &FunctionDeclaration) -> String {
+    let parameters: Vec<_> = func
+        .parameters
+        .iter()
+        .map(|p| match &p.guard {
+            Some(guard) => format!("{}: {} where {}", p.name, format_type(&p.ty), format_expression(guard)),
+            None => format!("{}: {}", p.name, format_type(&p.ty)),
+        })
+        .collect();
+    format!("{}({}) -> {}", func.name, parameters.join(", "), format_type(&func.return_type))
+}
+
+/// Tokens and comments of the original source. The printer walks the source
+/// tokens in step with the tokens it prints, and writes out each comment
+/// just before the first printed token that came after it.
+struct Trivia {
+    chars: Vec<char>,
+    tokens: Vec<(Token, Span)>,
+    comments: Vec<Comment>,
+    next_token: usize,
+    next_comment: usize,
+    // End of the last source token or comment written out
+    last_end: usize,
+}
+
+impl Trivia {
+    fn collect(source: &str) -> crate::Result<Self> {
+        let mut lexer = Lexer::new(source.to_string());
+        let mut tokens = Vec::new();
+        loop {
+            let (token, span) = lexer.next_spanned_token()?;
+            if token == Token::Eof {
+                break;
+            }
+            tokens.push((token, span));
+        }
+
+        Ok(Trivia {
+            chars: source.chars().collect(),
+            tokens,
+            comments: lexer.comments().to_vec(),
+            next_token: 0,
+            next_comment: 0,
+            last_end: 0,
+        })
+    }
+
+    /// Match a printed token against the source, skipping parentheses,
+    /// commas and semicolons the printer dropped. Returns the source span on a match;
+    /// tokens the printer added (such as parentheses) match nothing.
+    fn consume(&mut self, token: &Token) -> Option<Span> {
+        for i in self.next_token..self.tokens.len() {
+            let (source_token, span) = &self.tokens[i];
+            if source_token == token {
+                self.next_token = i + 1;
+                return Some(*span);
+            }
+            if !matches!(
+                source_token,
+                Token::LeftParen | Token::RightParen | Token::Comma | Token::Semicolon
+            ) {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Next comment starting before `offset`, with whether it sat on a line
+    /// of its own and whether a blank line preceded it.
+    fn next_comment_before(&mut self, offset: usize) -> Option<(Comment, bool, bool)> {
+        let comment = self.comments.get(self.next_comment)?.clone();
+        if comment.span.offset >= offset {
+            return None;
+        }
+        self.next_comment += 1;
+
+        let own_line = self.chars[..comment.span.offset]
+            .iter()
+            .rev()
+            .take_while(|&&c| c != '\n')
+            .all(|c| c.is_whitespace());
+        let blank_before = self.blank_line_before(comment.span.offset);
+        self.last_end = comment.span.end();
+        Some((comment, own_line, blank_before))
+    }
+
+    fn blank_line_before(&self, offset: usize) -> bool {
+        let start = self.last_end.min(offset);
+        self.chars[start..offset].iter().filter(|&&c| c == '\n').count() >= 2
+    }
+
+    fn token_at(&self, offset: usize) -> Option<&Token> {
+        let index = self
+            .tokens
+            .binary_search_by_key(&offset, |(_, span)| span.offset)
+            .ok()?;
+        Some(&self.tokens[index].0)
+    }
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+    // Print everything on one line, for measuring and inline use
+    flat: bool,
+    trivia: Option<Trivia>,
+}
+
+impl Printer {
+    fn new(trivia: Option<Trivia>) -> Self {
+        Self {
+            out: String::new(),
+            indent: 0,
+            flat: false,
+            trivia,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush_comments(usize::MAX);
+        let end = self.out.trim_end().len();
+        self.out.truncate(end);
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    fn program(&mut self, program: &Program) {
+        enum Item<'a> {
+            Import(&'a Import),
+            Declaration(&'a Declaration),
+            Expression(&'a TopLevelExpression),
+            Test(&'a TestDeclaration),
+            Data(&'a DataDeclaration),
+        }
+
+        // Imports and script expressions can sit between declarations; keep
+        // source order
+        let mut items: Vec<_> = program
+            .imports
+            .iter()
+            .map(|i| (i.span.offset, Item::Import(i)))
+            .chain(program.declarations.iter().map(|d| (d.span().offset, Item::Declaration(d))))
+            .chain(program.expressions.iter().map(|e| (e.span.offset, Item::Expression(e))))
+            .chain(program.tests.iter().map(|t| (t.span.offset, Item::Test(t))))
+            .chain(program.data.iter().map(|d| (d.span.offset, Item::Data(d))))
+            .collect();
+        items.sort_by_key(|(offset, _)| *offset);
+
+        let mut previous = None;
+        for (_, item) in items {
+            match item {
+                Item::Import(import) => {
+                    match &previous {
+                        Some(Item::Import(_)) => self.newline(),
+                        Some(_) => self.blank_line(),
+                        None => {}
+                    }
+                    self.token(Token::Import);
+                    self.space();
+                    self.token(Token::String(import.path.clone()));
+                }
+                Item::Declaration(declaration) => {
+                    if previous.is_some() {
+                        self.blank_line();
+                    }
+                    self.declaration(declaration);
+                }
+                Item::Expression(expression) => {
+                    match &previous {
+                        // Without it the next expression could continue this one
+                        Some(Item::Expression(_)) => {
+                            self.token(Token::Semicolon);
+                            self.newline();
+                        }
+                        Some(_) => self.blank_line(),
+                        None => {}
+                    }
+                    if let Some(name) = &expression.binding {
+                        self.token(Token::Let);
+                        self.space();
+                        self.token(Token::Identifier(name.clone()));
+                        self.space();
+                        self.token(Token::Assign);
+                        self.space();
+                    }
+                    self.expr(&expression.expression, 0);
+                }
+                Item::Test(test) => {
+                    if previous.is_some() {
+                        self.blank_line();
+                    }
+                    self.token(Token::Test);
+                    self.space();
+                    self.token(Token::String(test.name.clone()));
+                    self.space();
+                    self.token(Token::LeftBrace);
+                    self.indent += INDENT;
+                    self.newline();
+                    self.block(&test.body);
+                    self.indent -= INDENT;
+                    self.newline();
+                    self.token(Token::RightBrace);
+                }
+                Item::Data(data) => {
+                    if previous.is_some() {
+                        self.blank_line();
+                    }
+                    self.token(Token::Data);
+                    self.space();
+                    self.token(Token::Identifier(data.type_name.clone()));
+                    self.space();
+                    self.token(Token::LeftBrace);
+                    self.indent += INDENT;
+                    for entry in &data.entries {
+                        self.newline();
+                        self.expr(&entry.expression, 0);
+                        self.token(Token::Comma);
+                    }
+                    self.indent -= INDENT;
+                    self.newline();
+                    self.token(Token::RightBrace);
+                }
+            }
+            previous = Some(item);
+        }
+    }
+
+    fn declaration(&mut self, declaration: &Declaration) {
+        match declaration {
+            Declaration::Value(v) => self.value_declaration(v),
+            Declaration::Function(f) => {
+                // The parser reads `method` as `fn`; keep whichever was written
+                let keyword = match self.trivia.as_ref().and_then(|t| t.token_at(f.span.offset)) {
+                    Some(Token::Method) => Token::Method,
+                    _ => Token::Fn,
+                };
+                self.function(keyword, &f.name, &f.parameters, &f.return_type, &f.body);
+            }
+            Declaration::Method(m) => {
+                self.function(Token::Method, &m.name, &m.parameters, &m.return_type, &m.body)
+            }
+        }
+    }
+
+    fn value_declaration(&mut self, value: &ValueDeclaration) {
+        self.token(Token::Value);
+        self.space();
+        self.token(Token::Identifier(value.name.clone()));
+        self.token(Token::LeftParen);
+        self.token(Token::Identifier(value.parameter.name.clone()));
+        self.token(Token::Colon);
+        self.space();
+        self.ty(&value.parameter.ty);
+        if let Some(unit) = &value.parameter.unit {
+            self.space();
+            self.token(Token::Identifier("unit".to_string()));
+            self.space();
+            self.token(Token::String(unit.clone()));
+        }
+        self.token(Token::RightParen);
+        self.space();
+        self.token(Token::LeftBrace);
+
+        let body = &value.body;
+        if body.validate.is_none()
+            && body.normalize.is_none()
+            && body.unique.is_none()
+            && body.display.is_none()
+            && body.sensitive.is_none()
+            && body.coerce.is_none()
+            && body.convert.is_empty()
+        {
+            self.token(Token::RightBrace);
+            return;
+        }
+
+        self.indent += INDENT;
+        if let Some(validate) = &body.validate {
+            self.newline();
+            self.token(Token::Validate);
+            self.token(Token::Colon);
+            self.space();
+            self.block(validate);
+        }
+        if let Some(normalize) = &body.normalize {
+            self.newline();
+            self.token(Token::Normalize);
+            self.token(Token::Colon);
+            self.space();
+            self.block(normalize);
+        }
+        if let Some(unique) = body.unique {
+            self.newline();
+            self.token(Token::Unique);
+            self.token(Token::Colon);
+            self.space();
+            self.token(if unique { Token::True } else { Token::False });
+        }
+        if let Some(display) = &body.display {
+            self.newline();
+            self.token(Token::Identifier("display".to_string()));
+            self.token(Token::Colon);
+            self.space();
+            self.token(Token::String(display.clone()));
+        }
+        if let Some(sensitive) = body.sensitive {
+            self.newline();
+            self.token(Token::Identifier("sensitive".to_string()));
+            self.token(Token::Colon);
+            self.space();
+            self.token(if sensitive { Token::True } else { Token::False });
+        }
+        if let Some(coerce) = body.coerce {
+            self.newline();
+            self.token(Token::Identifier("coerce".to_string()));
+            self.token(Token::Colon);
+            self.space();
+            self.token(if coerce { Token::True } else { Token::False });
+        }
+        if !body.convert.is_empty() {
+            self.newline();
+            self.token(Token::Identifier("convert".to_string()));
+            self.token(Token::Colon);
+            for (i, function) in body.convert.iter().enumerate() {
+                if i > 0 {
+                    self.token(Token::Comma);
+                }
+                self.space();
+                self.token(Token::Identifier(function.clone()));
+            }
+        }
+        self.indent -= INDENT;
+        self.newline();
+        self.token(Token::RightBrace);
+    }
+
+    fn function(
+        &mut self,
+        keyword: Token,
+        name: &str,
+        parameters: &[ParameterWithGuard],
+        return_type: &Type,
+        body: &Expression,
+    ) {
+        self.token(keyword);
+        self.space();
+        self.token(Token::Identifier(name.to_string()));
+        self.token(Token::LeftParen);
+        for (i, parameter) in parameters.iter().enumerate() {
+            if i > 0 {
+                self.token(Token::Comma);
+                self.space();
+            }
+            self.token(Token::Identifier(parameter.name.clone()));
+            self.token(Token::Colon);
+            self.space();
+            self.ty(&parameter.ty);
+            if let Some(guard) = &parameter.guard {
+                self.space();
+                self.token(Token::Where);
+                self.space();
+                self.expr(guard, 0);
+            }
+        }
+        self.token(Token::RightParen);
+        self.space();
+        self.token(Token::ReturnArrow);
+        self.space();
+        self.ty(return_type);
+        self.space();
+        self.token(Token::LeftBrace);
+        self.indent += INDENT;
+        self.newline();
+        self.block(body);
+        self.indent -= INDENT;
+        self.newline();
+        self.token(Token::RightBrace);
+    }
+
+    fn ty(&mut self, ty: &Type) {
+        let name = match ty {
+            Type::String => "String".to_string(),
+            Type::Int => "Int".to_string(),
+            Type::Bool => "Bool".to_string(),
+            Type::Any => "Any".to_string(),
+            Type::Type => "Type".to_string(),
+            Type::Relation => "Relation".to_string(),
+            Type::Date => "Date".to_string(),
+            Type::Time => "Time".to_string(),
+            Type::Duration => "Duration".to_string(),
+            Type::Decimal => "Decimal".to_string(),
+            Type::Unknown => "Unknown".to_string(),
+            Type::Value(name) => name.clone(),
+            Type::List(element) | Type::Option(element) | Type::Query(element) => {
+                let name = match ty {
+                    Type::List(_) => "List",
+                    Type::Option(_) => "Option",
+                    _ => "Query",
+                };
+                self.token(Token::Identifier(name.to_string()));
+                self.token(Token::LeftBracket);
+                self.ty(element);
+                self.token(Token::RightBracket);
+                return;
+            }
+        };
+        self.token(Token::Identifier(name));
+    }
+
+    /// An expression in statement position, where `let` chains and block
+    /// statements are laid out one per line.
+    fn block(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Block(statements, result) if !self.flat => {
+                for statement in statements {
+                    self.statement(statement);
+                    self.newline();
+                }
+                self.block(result);
+            }
+            Expression::Let(name, value, body) if !self.flat => {
+                self.let_binding(name, value);
+                self.newline();
+                self.block(body);
+            }
+            _ => self.expr(expr, 0),
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(name, value) => {
+                self.token(Token::Let);
+                self.space();
+                self.token(Token::Identifier(name.clone()));
+                self.space();
+                self.token(Token::Assign);
+                self.space();
+                self.expr(value, 0);
+            }
+            Statement::Expression(expr) => self.expr(expr, 0),
+        }
+        self.token(Token::Semicolon);
+    }
+
+    fn let_binding(&mut self, name: &str, value: &Expression) {
+        self.token(Token::Let);
+        self.space();
+        self.token(Token::Identifier(name.to_string()));
+        self.space();
+        self.token(Token::Assign);
+        self.space();
+        if matches!(value, Expression::Let(..)) {
+            self.parenthesized(value);
+        } else {
+            self.expr(value, 0);
+        }
+        self.space();
+        self.token(Token::In);
+    }
+
+    /// Print `expr`, parenthesized if it binds less tightly than `min_precedence`.
+    fn expr(&mut self, expr: &Expression, min_precedence: u8) {
+        // `let` extends as far right as possible, so it needs parentheses
+        // anywhere but in a tail position
+        let needs_parens = match expr {
+            Expression::Let(..) => min_precedence > 0,
+            _ => precedence(expr) < min_precedence,
+        };
+        if needs_parens {
+            self.parenthesized(expr);
+            return;
+        }
+
+        match expr {
+            Expression::Pipeline(..) => self.pipeline(expr),
+            Expression::Binary(op, left, right) => {
+                let p = precedence(expr);
+                self.expr(left, p);
+                self.space();
+                self.token(binary_token(op));
+                self.space();
+                self.expr(right, p + 1);
+            }
+            Expression::Comparison(op, left, right) => {
+                let p = precedence(expr);
+                // Spell out the grouping of comparison chains, which the
+                // parser warns about
+                if matches!(**left, Expression::Comparison(..)) {
+                    self.parenthesized(left);
+                } else {
+                    self.expr(left, p);
+                }
+                self.space();
+                self.token(comparison_token(op));
+                self.space();
+                self.expr(right, p + 1);
+            }
+            Expression::Unary(op, operand) => {
+                self.token(match op {
+                    UnaryOp::Not => Token::Not,
+                    UnaryOp::Minus => Token::Minus,
+                });
+                self.expr(operand, precedence(expr));
+            }
+            Expression::Literal(literal) => self.token(match literal {
+                Literal::String(s) => Token::String(s.clone()),
+                Literal::Integer(n) => Token::Integer(*n),
+                Literal::Boolean(true) => Token::True,
+                Literal::Boolean(false) => Token::False,
+                Literal::Temporal(t) => Token::Temporal(*t),
+                Literal::Decimal(d) => Token::Decimal(*d),
+            }),
+            Expression::Identifier(name) | Expression::TypeLiteral(name) => {
+                self.token(Token::Identifier(name.clone()))
+            }
+            Expression::FunctionCall(name, args) => {
+                self.token(Token::Identifier(name.clone()));
+                self.arguments(args);
+            }
+            Expression::NamedCall(name, args) => {
+                self.token(Token::Identifier(name.clone()));
+                self.token(Token::LeftParen);
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.token(Token::Comma);
+                        self.space();
+                    }
+                    if let Some(label) = &arg.label {
+                        self.token(Token::Identifier(label.clone()));
+                        self.token(Token::Colon);
+                        self.space();
+                    }
+                    self.expr(&arg.value, 0);
+                }
+                self.token(Token::RightParen);
+            }
+            Expression::MemberAccess(receiver, member) => {
+                self.expr(receiver, precedence(expr));
+                self.token(Token::Dot);
+                self.member(member);
+            }
+            Expression::MethodCall(receiver, method, args) => {
+                self.expr(receiver, precedence(expr));
+                self.token(Token::Dot);
+                self.member(method);
+                self.arguments(args);
+            }
+            Expression::OptionalAccess(option, member, args) => {
+                self.expr(option, precedence(expr));
+                self.token(Token::QuestionDot);
+                self.member(member);
+                if let Some(args) = args {
+                    self.arguments(args);
+                }
+            }
+            Expression::Unwrap(option) => {
+                self.expr(option, precedence(expr));
+                self.token(Token::Not);
+            }
+            Expression::Coalesce(option, default) => {
+                // `??` groups to the right
+                let p = precedence(expr);
+                self.expr(option, p + 1);
+                self.space();
+                self.token(Token::Coalesce);
+                self.space();
+                self.expr(default, p);
+            }
+            Expression::Let(name, value, body) => {
+                self.let_binding(name, value);
+                self.space();
+                self.expr(body, 0);
+            }
+            Expression::Match(scrutinee, arms) => self.match_expression(scrutinee, arms),
+            Expression::Block(statements, result) => {
+                self.token(Token::LeftBrace);
+                for statement in statements {
+                    self.space();
+                    self.statement(statement);
+                }
+                self.space();
+                self.expr(result, 0);
+                self.space();
+                self.token(Token::RightBrace);
+            }
+            Expression::Relation(fields, rows) => self.relation(fields, rows),
+            Expression::Lambda(parameter, body) => {
+                self.token(Token::Identifier(parameter.clone()));
+                self.space();
+                self.token(Token::Arrow);
+                self.space();
+                self.expr(body, 0);
+            }
+            Expression::Comprehension(element, name, source, condition) => {
+                self.token(Token::LeftBracket);
+                self.expr(element, 0);
+                self.space();
+                self.token(Token::Identifier("for".to_string()));
+                self.space();
+                self.token(Token::Identifier(name.clone()));
+                self.space();
+                self.token(Token::In);
+                self.space();
+                self.expr(source, 0);
+                if let Some(condition) = condition {
+                    self.space();
+                    self.token(Token::Identifier("if".to_string()));
+                    self.space();
+                    self.expr(condition, 0);
+                }
+                self.token(Token::RightBracket);
+            }
+        }
+    }
+
+    fn pipeline(&mut self, expr: &Expression) {
+        let mut stages = Vec::new();
+        let mut current = expr;
+        while let Expression::Pipeline(left, right) = current {
+            stages.push(right.as_ref());
+            current = left;
+        }
+        stages.reverse();
+
+        let broken = !self.flat && self.column() + flat_width(expr) > MAX_WIDTH;
+        if matches!(current, Expression::Let(..)) {
+            self.parenthesized(current);
+        } else {
+            self.expr(current, 0);
+        }
+
+        if broken {
+            self.indent += INDENT;
+        }
+        for stage in stages {
+            if broken {
+                self.newline();
+            } else {
+                self.space();
+            }
+            self.token(Token::Pipeline);
+            self.space();
+            self.expr(stage, 1);
+        }
+        if broken {
+            self.indent -= INDENT;
+        }
+    }
+
+    fn match_expression(&mut self, scrutinee: &Expression, arms: &[MatchArm]) {
+        self.token(Token::Match);
+        self.space();
+        self.expr(scrutinee, 0);
+        self.space();
+        self.token(Token::LeftBrace);
+        if arms.is_empty() {
+            self.token(Token::RightBrace);
+            return;
+        }
+
+        self.indent += INDENT;
+        for (i, arm) in arms.iter().enumerate() {
+            if self.flat {
+                self.space();
+            } else {
+                self.newline();
+            }
+            self.pattern(&arm.pattern);
+            self.space();
+            self.token(Token::Arrow);
+            self.space();
+            self.expr(&arm.body, 0);
+            if !self.flat || i + 1 < arms.len() {
+                self.token(Token::Comma);
+            }
+        }
+        self.indent -= INDENT;
+        if self.flat {
+            self.space();
+        } else {
+            self.newline();
+        }
+        self.token(Token::RightBrace);
+    }
+
+    /// `Relation(name: String) { ("bob"), ... }`, one row per line.
+    fn relation(&mut self, fields: &[Parameter], rows: &[Vec<Expression>]) {
+        self.token(Token::Identifier("Relation".to_string()));
+        self.token(Token::LeftParen);
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                self.token(Token::Comma);
+                self.space();
+            }
+            self.token(Token::Identifier(field.name.clone()));
+            self.token(Token::Colon);
+            self.space();
+            self.ty(&field.ty);
+        }
+        self.token(Token::RightParen);
+        if rows.is_empty() {
+            return;
+        }
+
+        self.space();
+        self.token(Token::LeftBrace);
+        self.indent += INDENT;
+        for (i, row) in rows.iter().enumerate() {
+            if self.flat {
+                self.space();
+            } else {
+                self.newline();
+            }
+            self.arguments(row);
+            if !self.flat || i + 1 < rows.len() {
+                self.token(Token::Comma);
+            }
+        }
+        self.indent -= INDENT;
+        if self.flat {
+            self.space();
+        } else {
+            self.newline();
+        }
+        self.token(Token::RightBrace);
+    }
+
+    fn arguments(&mut self, args: &[Expression]) {
+        self.token(Token::LeftParen);
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.token(Token::Comma);
+                self.space();
+            }
+            self.expr(arg, 0);
+        }
+        self.token(Token::RightParen);
+    }
+
+    fn member(&mut self, name: &str) {
+        // `where` is a keyword but allowed as a method name
+        if name == "where" {
+            self.token(Token::Where);
+        } else {
+            self.token(Token::Identifier(name.to_string()));
+        }
+    }
+
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Constructor(constructor, binding) => {
+                self.token(Token::Identifier(constructor.clone()));
+                self.token(Token::LeftParen);
+                self.token(Token::Identifier(binding.clone()));
+                self.token(Token::RightParen);
+            }
+            Pattern::List(elements, rest) => {
+                self.token(Token::LeftBracket);
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        self.token(Token::Comma);
+                        self.space();
+                    }
+                    self.token(Token::Identifier(element.clone()));
+                }
+                if let Some(rest) = rest {
+                    if !elements.is_empty() {
+                        self.token(Token::Comma);
+                        self.space();
+                    }
+                    self.token(Token::Ellipsis);
+                    self.token(Token::Identifier(rest.clone()));
+                }
+                self.token(Token::RightBracket);
+            }
+        }
+    }
+
+    fn parenthesized(&mut self, expr: &Expression) {
+        self.token(Token::LeftParen);
+        self.expr(expr, 0);
+        self.token(Token::RightParen);
+    }
+
+    fn token(&mut self, token: Token) {
+        let span = self.trivia.as_mut().and_then(|t| t.consume(&token));
+        if let Some(span) = span {
+            self.flush_comments(span.offset);
+            let blank_before = self
+                .trivia
+                .as_ref()
+                .is_some_and(|t| t.blank_line_before(span.offset));
+            if blank_before && self.at_line_start() && !self.after_open_brace() {
+                self.blank_line();
+            }
+            if let Some(trivia) = self.trivia.as_mut() {
+                trivia.last_end = span.end();
+            }
+        }
+
+        if self.out.ends_with("*/")
+            && !matches!(token, Token::RightParen | Token::RightBracket | Token::Comma | Token::Dot)
+        {
+            self.out.push(' ');
+        }
+        self.out.push_str(&token.to_string());
+    }
+
+    fn flush_comments(&mut self, offset: usize) {
+        while let Some((comment, own_line, blank_before)) = self
+            .trivia
+            .as_mut()
+            .and_then(|t| t.next_comment_before(offset))
+        {
+            self.comment(&comment.text, own_line, blank_before);
+        }
+    }
+
+    fn comment(&mut self, text: &str, own_line: bool, blank_before: bool) {
+        if own_line || self.out.trim().is_empty() {
+            if blank_before && !self.after_open_brace() {
+                self.blank_line();
+            } else if !self.at_line_start() {
+                self.newline();
+            }
+            self.out.push_str(text);
+            self.newline();
+        } else if self.at_line_start() {
+            // A trailing comment stays at the end of the line it followed
+            let end = self.out.trim_end().len();
+            let rest = self.out.split_off(end);
+            self.out.push(' ');
+            self.out.push_str(text);
+            self.out.push_str(&rest);
+        } else {
+            if !self.out.ends_with([' ', '(', '[']) {
+                self.out.push(' ');
+            }
+            self.out.push_str(text);
+            if text.starts_with("//") {
+                self.newline();
+            }
+        }
+    }
+
+    fn space(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with([' ', '\n']) {
+            self.out.push(' ');
+        }
+    }
+
+    fn newline(&mut self) {
+        if self.flat {
+            self.space();
+            return;
+        }
+        let end = self.out.trim_end_matches(' ').len();
+        self.out.truncate(end);
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(self.indent));
+    }
+
+    fn blank_line(&mut self) {
+        let end = self.out.trim_end().len();
+        if end == 0 {
+            self.out.clear();
+            return;
+        }
+        self.out.truncate(end);
+        self.out.push_str("\n\n");
+        self.out.push_str(&" ".repeat(self.indent));
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.current_line().chars().all(|c| c == ' ')
+    }
+
+    fn after_open_brace(&self) -> bool {
+        self.out.trim_end().ends_with('{')
+    }
+
+    fn column(&self) -> usize {
+        self.current_line().chars().count()
+    }
+
+    fn current_line(&self) -> &str {
+        match self.out.rfind('\n') {
+            Some(i) => &self.out[i + 1..],
+            None => &self.out,
+        }
+    }
+}
+
+fn flat_width(expr: &Expression) -> usize {
+    format_expression(expr).chars().count()
+}
+
+fn precedence(expr: &Expression) -> u8 {
+    match expr {
+        Expression::Pipeline(..) | Expression::Lambda(..) => 0,
+        Expression::Binary(BinaryOp::Or, ..) => 1,
+        Expression::Binary(BinaryOp::And, ..) => 2,
+        Expression::Comparison(..) => 3,
+        Expression::Coalesce(..) => 4,
+        Expression::Binary(BinaryOp::Add | BinaryOp::Subtract, ..) => 5,
+        Expression::Binary(..) => 6,
+        Expression::Unary(..) => 7,
+        Expression::MemberAccess(..)
+        | Expression::MethodCall(..)
+        | Expression::OptionalAccess(..)
+        | Expression::Unwrap(..) => 8,
+        _ => 9,
+    }
+}
+
+pub(crate) fn binary_token(op: &BinaryOp) -> Token {
+    match op {
+        BinaryOp::And => Token::And,
+        BinaryOp::Or => Token::Or,
+        BinaryOp::Add => Token::Plus,
+        BinaryOp::Subtract => Token::Minus,
+        BinaryOp::Multiply => Token::Star,
+        BinaryOp::Divide => Token::Slash,
+        BinaryOp::Modulo => Token::Percent,
+    }
+}
+
+pub(crate) fn comparison_token(op: &ComparisonOp) -> Token {
+    match op {
+        ComparisonOp::Equal => Token::Equal,
+        ComparisonOp::NotEqual => Token::NotEqual,
+        ComparisonOp::Less => Token::Less,
+        ComparisonOp::Greater => Token::Greater,
+        ComparisonOp::LessEqual => Token::LessEqual,
+        ComparisonOp::GreaterEqual => Token::GreaterEqual,
+        ComparisonOp::Contains => Token::Contains,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        format_source(source).expect("source should parse")
+    }
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source.to_string()))
+            .and_then(|mut parser| parser.parse_program())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_canonical_layout() {
+        let source = "value  Email(raw:String){validate:raw contains \"@\"&&raw.length>3 unique:true display:\"<#{raw}>\"}\n\
+                      fn   add(x:Int,y:Int where y>0)->Int{x+y*2}";
+
+        assert_eq!(
+            format(source),
+            "value Email(raw: String) {\n    \
+                 validate: raw contains \"@\" && raw.length > 3\n    \
+                 unique: true\n    \
+                 display: \"<#{raw}>\"\n\
+             }\n\
+             \n\
+             fn add(x: Int, y: Int where y > 0) -> Int {\n    \
+                 x + y * 2\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_parentheses_follow_precedence() {
+        let source = "fn f(a: Int, b: Int) -> Int { ((a + b)) * (a - (b - 1)) + (a * b) }";
+        assert!(format(source).contains("    (a + b) * (a - (b - 1)) + a * b\n"));
+
+        let source = "fn g(a: Int, b: Int, c: Bool) -> Bool { a < b == c }";
+        assert!(format(source).contains("    (a < b) == c\n"));
+
+        let source = "fn h(a: Option[String], b: Option[String]) -> Int { (a ?? b)!.length + (a?.length ?? (0)) }";
+        assert!(format(source).contains("    (a ?? b)!.length + (a?.length ?? 0)\n"));
+    }
+
+    #[test]
+    fn test_let_chain_one_binding_per_line() {
+        let source = "fn f(x: Int) -> Int { let a = x in let b = (let c = a in c) in a + b }";
+        assert_eq!(
+            format(source),
+            "fn f(x: Int) -> Int {\n    \
+                 let a = x in\n    \
+                 let b = (let c = a in c) in\n    \
+                 a + b\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_block_statements_one_per_line() {
+        let source = "fn f(x: Int) -> Int { let a = x+1;let b=a*2; g(b); a + b }";
+        assert_eq!(
+            format(source),
+            "fn f(x: Int) -> Int {\n    \
+                 let a = x + 1;\n    \
+                 let b = a * 2;\n    \
+                 g(b);\n    \
+                 a + b\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_long_pipeline_is_broken() {
+        let source = "fn f(x: String) -> String { x |> g |> h }\n\
+                      fn g(text: String) -> String { text |> someVeryLongFunctionName |> anotherVeryLongFunctionName |> yetAnotherFunction |> finalStep }";
+        let formatted = format(source);
+
+        assert!(formatted.contains("    x |> g |> h\n"));
+        assert!(formatted.contains(
+            "    text\n        |> someVeryLongFunctionName\n        |> anotherVeryLongFunctionName\n"
+        ));
+    }
+
+    #[test]
+    fn test_comments_are_preserved() {
+        let source = "// Header\n\n\
+                      // Adds one\n\
+                      fn inc(x: Int /* any */) -> Int {\n\
+                      let y = x in   // bind\n\
+                      \n\
+                      y+1 // result\n\
+                      }\n\
+                      // trailing";
+
+        assert_eq!(
+            format(source),
+            "// Header\n\
+             \n\
+             // Adds one\n\
+             fn inc(x: Int /* any */) -> Int {\n    \
+                 let y = x in // bind\n\
+             \n    \
+                 y + 1 // result\n\
+             }\n\
+             // trailing\n"
+        );
+    }
+
+    #[test]
+    fn test_method_keyword_is_kept() {
+        let source = "method area(w: Int) -> Int { w }";
+        assert!(format(source).starts_with("method area(w: Int) -> Int {"));
+    }
+
+    #[test]
+    fn test_script_expressions_keep_their_place() {
+        let source = "#!/usr/bin/env relic\nf(1)\nfn f(x: Int) -> Int { x }\nf(2); -1;";
+        assert_eq!(
+            format(source),
+            "#!/usr/bin/env relic\nf(1)\n\nfn f(x: Int) -> Int {\n    x\n}\n\nf(2);\n-1\n"
+        );
+        assert_eq!(format("let a=f(1)\nlet b = let c = a in c\nb"), "let a = f(1);\nlet b = let c = a in c;\nb\n");
+    }
+
+    #[test]
+    fn test_imports_are_grouped() {
+        let source = "import \"a.relic\"; import \"b.relic\"\nfn f(x: Int) -> Int { x }\nimport \"c.relic\"\nf(1)";
+        assert_eq!(
+            format(source),
+            "import \"a.relic\"\nimport \"b.relic\"\n\nfn f(x: Int) -> Int {\n    x\n}\n\nimport \"c.relic\"\n\nf(1)\n"
+        );
+    }
+
+    #[test]
+    fn test_tests_keep_their_place() {
+        let source = "fn f(x: Int) -> Int { x }\ntest \"f\" { assertEq(f(1), 1) }\nf(2)";
+        assert_eq!(
+            format(source),
+            "fn f(x: Int) -> Int {\n    x\n}\n\ntest \"f\" {\n    assertEq(f(1), 1)\n}\n\nf(2)\n"
+        );
+    }
+
+    #[test]
+    fn test_data_blocks_list_one_entry_per_line() {
+        let source = "data User { User(\"alice\") User(\"bob\") }";
+        assert_eq!(format(source), "data User {\n    User(\"alice\"),\n    User(\"bob\"),\n}\n");
+    }
+
+    #[test]
+    fn test_lambdas() {
+        assert_eq!(
+            format("fn anyOld() -> Bool { exists( User,u=>u.age>65 ) }"),
+            "fn anyOld() -> Bool {\n    exists(User, u => u.age > 65)\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_list_patterns() {
+        assert_eq!(
+            format("fn f(xs: List[Int]) -> Int { match xs { []=>0, [x,...rest]=>x, [ ...all ] => 1 } }"),
+            "fn f(xs: List[Int]) -> Int {\n    match xs {\n        [] => 0,\n        [x, ...rest] => x,\n        [...all] => 1,\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_units() {
+        assert_eq!(
+            format("value Km(distance:Decimal unit \"km\"){validate:distance>=0.0}"),
+            "value Km(distance: Decimal unit \"km\") {\n    validate: distance >= 0.0\n}\n"
+        );
+        assert_eq!(
+            format("value Km(distance: Decimal unit \"km\") {convert:fromMiles,fromFeet}"),
+            "value Km(distance: Decimal unit \"km\") {\n    convert: fromMiles, fromFeet\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_comprehensions() {
+        assert_eq!(
+            format("fn f() -> List[String] { [ u.email for u in User if u.email.length>3] }"),
+            "fn f() -> List[String] {\n    [u.email for u in User if u.email.length > 3]\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_relation_rows_one_per_line() {
+        assert_eq!(
+            format("fn people() -> Relation { Relation(name:String,age:Int){(\"bob\",30) (\"amy\",41)} }"),
+            "fn people() -> Relation {\n    Relation(name: String, age: Int) {\n        (\"bob\", 30),\n        (\"amy\", 41),\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_generic_types() {
+        let source = "fn f(xs: List[ /* people */ List[Person]]) -> List[Int] { 1 }";
+        assert_eq!(
+            format(source),
+            "fn f(xs: List[/* people */ List[Person]]) -> List[Int] {\n    1\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_syntax_errors_are_reported() {
+        let errors = format_source("fn f(x Int) -> Int { x }\nfn g(y: Int) Int { y }").unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_examples_format_idempotently() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/examples");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|e| e.to_str()) != Some("relic") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            let Ok(formatted) = format_source(&source) else {
+                continue;
+            };
+
+            assert_eq!(format(&formatted), formatted, "{} is not idempotent", path.display());
+            assert_eq!(
+                format_program(&parse(&formatted)),
+                format_program(&parse(&source)),
+                "{} changed meaning",
+                path.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Inspecting a value, for debugging: its type, and for an instance of a
+//! value type, its fields, the clauses of its declaration and where it sits
+//! in the type's instance pool, with where it was constructed if that is
+//! recorded. `inspect(x)` returns the report as a String, and the REPL's
+//! `:inspect expr` prints it.
+
+use crate::ast::ValueDeclaration;
+use crate::evaluator::{instance_values, EvalValue, REDACTED};
+use crate::formatter::{format_expression, format_type};
+use crate::provenance::Provenance;
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::fmt;
+use std::sync::Arc;
+
+/// What there is to know about a value.
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    pub value: EvalValue,
+    pub ty: Type,
+    /// For an instance of a value type, what the registry knows of it
+    pub instance: Option<InstanceInfo>,
+}
+
+/// An instance of a value type, as the registry sees it.
+#[derive(Debug, Clone)]
+pub struct InstanceInfo {
+    /// The type's declaration, if it is still defined
+    pub declaration: Option<ValueDeclaration>,
+    /// Each field, with its declared type, in declaration order
+    pub fields: Vec<(String, Type, EvalValue)>,
+    /// The instance's ID: its position in the type's pool, from 1, if an
+    /// equal instance is there
+    pub id: Option<usize>,
+    /// How many instances of the type are in the pool
+    pub pool_size: usize,
+    /// How many instances in the pool are equal to this one
+    pub equal_instances: usize,
+    /// Where each equal instance was constructed, if the registry records
+    /// provenance
+    pub provenance: Vec<Arc<Provenance>>,
+}
+
+/// Inspect `value`, looking up value types in `registry`.
+pub fn inspect(value: &EvalValue, registry: &ValueRegistry) -> Inspection {
+    let instance = match value {
+        EvalValue::Value { type_name, fields, .. } => {
+            let declaration = registry.constructors.get(type_name).map(|constructor| constructor.declaration.clone());
+            let mut fields: Vec<_> = fields
+                .iter()
+                .map(|(name, field)| {
+                    let ty = declaration
+                        .as_ref()
+                        .filter(|declaration| declaration.parameter.name == *name)
+                        .map_or_else(|| type_of(field), |declaration| declaration.parameter.ty.clone());
+                    (name.clone(), ty, field.clone())
+                })
+                .collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            let pool = instance_values(type_name, registry);
+            Some(InstanceInfo {
+                declaration,
+                fields,
+                id: pool.iter().position(|instance| instance == value).map(|index| index + 1),
+                pool_size: pool.len(),
+                equal_instances: pool.iter().filter(|instance| *instance == value).count(),
+                provenance: registry.provenance(value),
+            })
+        }
+        _ => None,
+    };
+    Inspection {
+        value: value.clone(),
+        ty: type_of(value),
+        instance,
+    }
+}
+
+/// The type of a value, taking a list's element type from its first element.
+pub fn type_of(value: &EvalValue) -> Type {
+    match value {
+        EvalValue::String(_) => Type::String,
+        EvalValue::Integer(_) => Type::Int,
+        EvalValue::Boolean(_) => Type::Bool,
+        EvalValue::Value { type_name, .. } => Type::Value(type_name.clone()),
+        EvalValue::Type(_) => Type::Type,
+        EvalValue::List(items) => Type::List(Box::new(items.first().map_or(Type::Any, type_of))),
+        EvalValue::Option(inner) => Type::Option(Box::new(inner.as_deref().map_or(Type::Any, type_of))),
+        EvalValue::Relation(_) => Type::Relation,
+        EvalValue::Temporal(t) => t.type_of(),
+        EvalValue::Decimal(_) => Type::Decimal,
+        EvalValue::Query(_) => Type::Query(Box::new(Type::Any)),
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+        let Some(instance) = &self.instance else {
+            write!(f, "\n  type: {}", format_type(&self.ty))?;
+            return match &self.value {
+                EvalValue::List(items) => write!(f, "\n  length: {}", items.len()),
+                EvalValue::Relation(relation) => write!(f, "\n  rows: {}", relation.rows().len()),
+                _ => Ok(()),
+            };
+        };
+        let type_name = format_type(&self.ty);
+        match &instance.declaration {
+            Some(_) => write!(f, "\n  type: {} (value type)", type_name)?,
+            None => write!(f, "\n  type: {} (no longer defined)", type_name)?,
+        }
+        let sensitive = matches!(self.value, EvalValue::Value { sensitive: true, .. });
+        for (name, ty, value) in &instance.fields {
+            let shown = if sensitive { REDACTED.to_string() } else { field_literal(value) };
+            write!(f, "\n  field {}: {} = {}", name, format_type(ty), shown)?;
+        }
+        let body = instance.declaration.as_ref().map(|declaration| &declaration.body);
+        if let Some(validate) = body.and_then(|body| body.validate.as_ref()) {
+            write!(f, "\n  validate: {}", format_expression(validate))?;
+        }
+        match body.and_then(|body| body.normalize.as_ref()) {
+            Some(normalize) => write!(f, "\n  normalize: {}", format_expression(normalize))?,
+            None => write!(f, "\n  normalize: none")?,
+        }
+        if let Some(display) = body.and_then(|body| body.display.as_ref()) {
+            write!(f, "\n  display: {:?}", display)?;
+        }
+        if sensitive {
+            write!(f, "\n  sensitive: true")?;
+        }
+        let unique = body.and_then(|body| body.unique).unwrap_or(false);
+        let others = instance.equal_instances.saturating_sub(1);
+        let noun = if others == 1 { "instance" } else { "instances" };
+        write!(f, "\n  unique: {}, {} other equal {}", unique, others, noun)?;
+        match instance.id {
+            Some(id) => write!(f, "\n  instance: #{} of {} in the {} pool", id, instance.pool_size, type_name)?,
+            None => write!(f, "\n  instance: not in the {} pool ({} instances)", type_name, instance.pool_size)?,
+        }
+        for site in &instance.provenance {
+            write!(f, "\n  created: {}", site)?;
+        }
+        Ok(())
+    }
+}
+
+// Strings are quoted, so that "" and " " can be told apart
+fn field_literal(value: &EvalValue) -> String {
+    match value {
+        EvalValue::String(s) => format!("{:?}", s),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Engine;
+
+    #[test]
+    fn test_inspect() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value Email(raw: String) { validate: raw contains \"@\"\n normalize: raw.toLowerCase()\n unique: true }\n\
+                 value Count(n: Int) {}",
+            )
+            .unwrap();
+        engine.eval("Email(\"ann@x.com\")").unwrap();
+        let report = engine.eval("inspect(Email(\"Bob@X.com\"))").unwrap().to_string();
+        assert_eq!(
+            report,
+            "Email(bob@x.com)\n  type: Email (value type)\n  field raw: String = \"bob@x.com\"\n  \
+             validate: raw contains \"@\"\n  normalize: raw.toLowerCase()\n  unique: true, 0 other equal instances\n  \
+             instance: #2 of 2 in the Email pool"
+        );
+
+        engine.eval("Count(3)").unwrap();
+        let report = engine.eval("inspect(Count(3))").unwrap().to_string();
+        assert!(report.contains("normalize: none"));
+        assert!(report.contains("unique: false, 1 other equal instance\n"));
+        assert!(report.ends_with("instance: #1 of 2 in the Count pool"));
+
+        let report = engine.eval("inspect(all(Count))").unwrap().to_string();
+        assert_eq!(report, "[Count(3), Count(3)]\n  type: List[Count]\n  length: 2");
+        assert_eq!(engine.eval("inspect(42)").unwrap().to_string(), "42\n  type: Int");
+    }
+}
@@ -0,0 +1,474 @@
+//! Reading JSON: a parser for JSON text, and `fromJson(User, text)`, which
+//! constructs a value from it the way `relic schema` describes. A value is
+//! an object with its type's parameter as its one field, so `value
+//! User(email: Email)` reads `{"email": {"raw": "ada@example.com"}}`,
+//! constructing the Email and then the User, each validated as if
+//! constructed in Relic.
+//!
+//! An error says where in the JSON it is, starting from the type's name:
+//! `user.email: Validation failed (in Email)`, or `user.tags[2]: expected
+//! a String, found a number`. Values in the JSON aren't repeated in errors,
+//! so those of `sensitive` types stay out of them.
+
+use crate::decimal::Decimal;
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::{construct, EvalValue};
+use crate::formatter::format_type;
+use crate::temporal::{Date, Duration, Temporal, Time};
+use crate::types::Type;
+use crate::value::ValueRegistry;
+
+/// How deeply arrays and objects may nest.
+pub const MAX_DEPTH: usize = 128;
+
+/// A JSON value. Numbers keep their text, so that a Decimal read from one
+/// keeps every digit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    /// The fields in the order written
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// What kind of JSON value this is, as errors name it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Number(_) => "a number",
+            Json::String(_) => "a string",
+            Json::Array(_) => "an array",
+            Json::Object(_) => "an object",
+        }
+    }
+}
+
+/// Parse `text` as one JSON value, with nothing but whitespace around it.
+pub fn parse(text: &str) -> Result<Json> {
+    let mut parser = JsonParser { text, position: 0, depth: 0 };
+    let value = parser.value()?;
+    parser.whitespace();
+    if parser.position < text.len() {
+        return Err(parser.error("expected the end of the text"));
+    }
+    Ok(value)
+}
+
+/// `fromJson(T, text)`: the value of the value type `type_name` that the
+/// JSON `text` describes, constructed with every value inside it.
+pub fn from_json(type_name: &str, text: &str, registry: &ValueRegistry) -> Result<EvalValue> {
+    if !registry.constructors.contains_key(type_name) {
+        return Err(failure(format!("fromJson needs a value type, and {} isn't one", type_name), type_name));
+    }
+    let json = parse(text)?;
+    value_of(&Type::Value(type_name.to_string()), &json, &root(type_name), registry)
+}
+
+/// The value of type `ty` written as plain `text`, as its JSON would be
+/// without the quotes: `42`, `true`, `12.50` or `2024-01-31`, for values
+/// passed in as text, such as through the C interface.
+pub(crate) fn from_text(ty: &Type, text: &str, registry: &ValueRegistry) -> Result<EvalValue> {
+    let json = match (ty, text.trim()) {
+        (Type::Int, number) => Json::Number(number.to_string()),
+        (Type::Bool, "true") => Json::Bool(true),
+        (Type::Bool, "false") => Json::Bool(false),
+        (Type::Decimal | Type::Date | Type::Time | Type::Duration, trimmed) => Json::String(trimmed.to_string()),
+        _ => Json::String(text.to_string()),
+    };
+    value_of(ty, &json, "value", registry)
+}
+
+// The value of type `ty` that `json`, at `path`, describes
+fn value_of(ty: &Type, json: &Json, path: &str, registry: &ValueRegistry) -> Result<EvalValue> {
+    let wrong_kind = || failure(format!("{}: expected {}, found {}", path, described(ty), json.kind()), "JSON");
+    let text = |parse: fn(&str) -> Option<EvalValue>| match json {
+        Json::String(text) => parse(text).ok_or_else(|| {
+            failure(format!("{}: expected {}, found a string that isn't one", path, described(ty)), "JSON")
+        }),
+        _ => Err(wrong_kind()),
+    };
+    match ty {
+        Type::Value(name) => {
+            let Some(constructor) = registry.constructors.get(name) else {
+                return Err(wrong_kind());
+            };
+            let parameter = &constructor.declaration.parameter;
+            let Json::Object(fields) = json else {
+                return Err(failure(
+                    format!("{}: expected an object with the field '{}', found {}", path, parameter.name, json.kind()),
+                    name,
+                ));
+            };
+            if let Some((unknown, _)) = fields.iter().find(|(field, _)| *field != parameter.name) {
+                return Err(failure(format!("{}: {} has no field '{}'", path, name, unknown), name));
+            }
+            let field = match fields.as_slice() {
+                [(_, field)] => field,
+                [] => return Err(failure(format!("{}: missing the field '{}'", path, parameter.name), name)),
+                _ => return Err(failure(format!("{}: the field '{}' is given twice", path, parameter.name), name)),
+            };
+            let arg = value_of(&parameter.ty, field, &format!("{}.{}", path, parameter.name), registry)?;
+            construct(name, &arg, registry).map_err(|error| match error {
+                Error::Validation(error) => failure(format!("{}: {}", path, error.message), &error.value_type),
+                other => other,
+            })
+        }
+        Type::String => match json {
+            Json::String(s) => Ok(EvalValue::String(s.clone())),
+            _ => Err(wrong_kind()),
+        },
+        Type::Int => match json {
+            Json::Number(number) => number.parse().map(EvalValue::Integer).map_err(|_| {
+                failure(format!("{}: expected an Int, found a number that isn't one", path), "JSON")
+            }),
+            _ => Err(wrong_kind()),
+        },
+        Type::Bool => match json {
+            Json::Bool(b) => Ok(EvalValue::Boolean(*b)),
+            _ => Err(wrong_kind()),
+        },
+        // From a string, as the schema has it, or from a number's digits
+        Type::Decimal => match json {
+            Json::String(number) | Json::Number(number) => decimal(number).map(EvalValue::Decimal).ok_or_else(|| {
+                failure(format!("{}: expected a Decimal, found {} that isn't one", path, json.kind()), "JSON")
+            }),
+            _ => Err(wrong_kind()),
+        },
+        Type::Date => text(|t| Date::parse(t).map(|d| EvalValue::Temporal(Temporal::Date(d)))),
+        Type::Time => text(|t| Time::parse(t).map(|t| EvalValue::Temporal(Temporal::Time(t)))),
+        Type::Duration => text(|t| Duration::parse(t).map(|d| EvalValue::Temporal(Temporal::Duration(d)))),
+        Type::List(element) => match json {
+            Json::Array(items) => items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| value_of(element, item, &format!("{}[{}]", path, index), registry))
+                .collect::<Result<Vec<_>>>()
+                .map(EvalValue::List),
+            _ => Err(wrong_kind()),
+        },
+        Type::Option(element) => match json {
+            Json::Null => Ok(EvalValue::Option(None)),
+            _ => Ok(EvalValue::Option(Some(Box::new(value_of(element, json, path, registry)?)))),
+        },
+        _ => Err(wrong_kind()),
+    }
+}
+
+// A Decimal written with or without a decimal point
+fn decimal(text: &str) -> Option<Decimal> {
+    Decimal::parse(text).or_else(|| text.parse().ok().map(Decimal::from_int))
+}
+
+// `Int`, as errors name it: "an Int", "a List[String]"
+fn described(ty: &Type) -> String {
+    let name = format_type(ty);
+    let article = if name.starts_with(['A', 'E', 'I', 'O', 'U']) { "an" } else { "a" };
+    format!("{} {}", article, name)
+}
+
+// Where errors about a value of `type_name` start: `user` for User
+fn root(type_name: &str) -> String {
+    let mut chars = type_name.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn failure(message: String, value_type: &str) -> Error {
+    Error::Validation(ValidationError {
+        message,
+        value_type: value_type.to_string(),
+    })
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    // A byte offset into `text`
+    position: usize,
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn value(&mut self) -> Result<Json> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'{') => self.nested(|parser| parser.object()),
+            Some(b'[') => self.nested(|parser| parser.array()),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("expected a value, found the end of the text")),
+        }
+    }
+
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Result<Json>) -> Result<Json> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("arrays and objects are nested too deeply"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Json> {
+        self.position += 1;
+        let mut fields = Vec::new();
+        self.whitespace();
+        if self.eat(b'}') {
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a field name in quotes"));
+            }
+            let name = self.string()?;
+            self.whitespace();
+            if !self.eat(b':') {
+                return Err(self.error("expected ':' after a field name"));
+            }
+            fields.push((name, self.value()?));
+            self.whitespace();
+            if self.eat(b'}') {
+                return Ok(Json::Object(fields));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or '}' after a field"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json> {
+        self.position += 1;
+        let mut items = Vec::new();
+        self.whitespace();
+        if self.eat(b']') {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.whitespace();
+            if self.eat(b']') {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or ']' after an item"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.position += 1;
+        let mut value = String::new();
+        loop {
+            let rest = &self.text[self.position..];
+            let Some(c) = rest.chars().next() else {
+                return Err(self.error("expected '\"' to end the string"));
+            };
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(value),
+                '\\' => value.push(self.escape()?),
+                c if (c as u32) < 0x20 => return Err(self.error("control characters must be escaped in a string")),
+                c => value.push(c),
+            }
+        }
+    }
+
+    // The character an escape, after its `\`, stands for
+    fn escape(&mut self) -> Result<char> {
+        let c = self.peek().ok_or_else(|| self.error("expected an escape after '\\'"))?;
+        self.position += 1;
+        Ok(match c {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let unit = self.hex()?;
+                // A character outside the Basic Multilingual Plane is a
+                // surrogate pair of escapes
+                let code = if (0xD800..0xDC00).contains(&unit) && self.text[self.position..].starts_with("\\u") {
+                    self.position += 2;
+                    let low = self.hex()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(self.error("expected the second half of a surrogate pair"));
+                    }
+                    0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    unit
+                };
+                char::from_u32(code).ok_or_else(|| self.error("expected a character, found half a surrogate pair"))?
+            }
+            _ => return Err(self.error("expected one of \\\" \\\\ \\/ \\b \\f \\n \\r \\t \\u")),
+        })
+    }
+
+    fn hex(&mut self) -> Result<u32> {
+        let digits = self.text.get(self.position..self.position + 4);
+        let digits = digits.filter(|d| d.bytes().all(|b| b.is_ascii_hexdigit())).ok_or_else(|| self.error("expected four hex digits after \\u"))?;
+        self.position += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap_or_default())
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.position;
+        self.eat(b'-');
+        if !self.eat(b'0') && !self.digits() {
+            return Err(self.error("expected a digit"));
+        }
+        if self.eat(b'.') && !self.digits() {
+            return Err(self.error("expected a digit after '.'"));
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            if !self.eat(b'+') {
+                self.eat(b'-');
+            }
+            if !self.digits() {
+                return Err(self.error("expected a digit in the exponent"));
+            }
+        }
+        Ok(Json::Number(self.text[start..self.position].to_string()))
+    }
+
+    // Whether there were any digits to skip
+    fn digits(&mut self) -> bool {
+        let start = self.position;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.position += 1;
+        }
+        self.position > start
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json> {
+        if !self.text[self.position..].starts_with(word) {
+            return Err(self.error("expected a value"));
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.peek() == Some(byte);
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn error(&self, message: &str) -> Error {
+        let before = &self.text[..self.position];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map_or(0, |line| line.chars().count()) + 1;
+        failure(format!("Invalid JSON at line {}, column {}: {}", line, column, message), "JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_parse() {
+        let json = parse(r#" {"a": [1, -2.5e3, "x\"\u00e9\ud83d\ude00"], "b": {"c": null, "d": true}} "#).unwrap();
+        assert_eq!(
+            json,
+            Json::Object(vec![
+                (
+                    "a".to_string(),
+                    Json::Array(vec![
+                        Json::Number("1".to_string()),
+                        Json::Number("-2.5e3".to_string()),
+                        Json::String("x\"é😀".to_string()),
+                    ])
+                ),
+                (
+                    "b".to_string(),
+                    Json::Object(vec![("c".to_string(), Json::Null), ("d".to_string(), Json::Bool(true))])
+                ),
+            ])
+        );
+
+        for (text, error) in [
+            ("{\"a\": 1,}", "line 1, column 9: expected a field name"),
+            ("[1 2]", "column 4: expected ',' or ']'"),
+            ("\n 01", "line 2, column 3: expected the end"),
+            ("\"\\x\"", "expected one of"),
+            ("tru", "expected a value"),
+            ("", "found the end of the text"),
+        ] {
+            let message = parse(text).unwrap_err().to_string();
+            assert!(message.contains(error), "{}: {}", text, message);
+        }
+        assert!(parse(&"[".repeat(MAX_DEPTH + 1)).unwrap_err().to_string().contains("nested too deeply"));
+    }
+
+    #[test]
+    fn test_from_json() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value Email(raw: String) { validate: raw contains \"@\" normalize: raw.toLowerCase() }
+                 value Price(amount: Decimal) { validate: amount >= 0.00 }
+                 value User(email: Email) {}
+                 value Team(members: List[User]) {}
+                 fn readUser(text: String) -> User { fromJson(User, text) }",
+            )
+            .unwrap();
+
+        let user = engine.from_json("User", r#"{"email": {"raw": "Ada@Example.com"}}"#).unwrap();
+        assert_eq!(user.to_string(), "User(Email(ada@example.com))");
+        assert_eq!(engine.get_instances("Email").unwrap().len(), 1);
+        assert_eq!(engine.from_json("Price", r#"{"amount": "19.990"}"#).unwrap().to_string(), "Price(19.990)");
+        assert_eq!(engine.from_json("Price", r#"{"amount": 20}"#).unwrap().to_string(), "Price(20)");
+        // The same from Relic
+        let user: EvalValue = engine.call("readUser", (r#"{"email": {"raw": "bo@example.com"}}"#,)).unwrap();
+        assert_eq!(user.to_string(), "User(Email(bo@example.com))");
+
+        // Errors say where in the JSON they are
+        let error = |type_name: &str, json: &str| engine.from_json(type_name, json).unwrap_err().to_string();
+        assert_eq!(
+            error("User", r#"{"email": {"raw": "nope"}}"#),
+            "Validation error in Email: user.email: Validation failed"
+        );
+        assert_eq!(
+            error("Team", r#"{"members": [{"email": {"raw": "a@b"}}, {"email": "c@d"}]}"#),
+            "Validation error in Email: team.members[1].email: expected an object with the field 'raw', found a string"
+        );
+        assert_eq!(error("User", r#"{"mail": {}}"#), "Validation error in User: user: User has no field 'mail'");
+        assert_eq!(error("User", "{}"), "Validation error in User: user: missing the field 'email'");
+        assert_eq!(
+            error("Price", r#"{"amount": true}"#),
+            "Validation error in JSON: price.amount: expected a Decimal, found a boolean"
+        );
+        assert!(error("User", "{").contains("Invalid JSON"));
+        assert!(error("Missing", "{}").contains("isn't one"));
+        assert!(engine.eval("fromJson(Email, 5)").is_err());
+        assert!(engine.eval("fromJson(5, \"{}\")").is_err());
+    }
+}
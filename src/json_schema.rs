@@ -0,0 +1,190 @@
+//! JSON Schema (draft 2020-12) for value types, so that other systems can
+//! check a payload before sending it to Relic. A value is an object with
+//! its type's parameter as its one, required property: `value Age(n: Int)
+//! { validate: n >= 0 }` is `{"n": 30}`, and a parameter of another value
+//! type holds that type's object, by `$ref`.
+//!
+//! The validation rules that `rules::ValidationRules` reads off a type
+//! become keywords: `minLength`, `maxLength`, `minimum`, `maximum` and
+//! `pattern`. The rest of the validation is put in the parameter's
+//! `description`, as is every rule of a type with a `normalize:`, whose
+//! rules apply to the normalized value rather than to what is sent.
+
+use crate::diagnostic::json_string;
+use crate::error::{Error, Result, TypeError};
+use crate::formatter::format_type;
+use crate::rules::{escape_pattern, ValidationRules};
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::collections::BTreeMap;
+
+/// The dialect the schemas are written in.
+pub const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// A schema document for the value types `type_names`, each under `$defs`
+/// with the value types their parameters refer to. Given one type, the
+/// document is that type's schema; given more, it only defines them.
+pub fn generate(registry: &ValueRegistry, type_names: &[&str]) -> Result<String> {
+    let mut definitions = BTreeMap::new();
+    let mut pending: Vec<String> = type_names.iter().map(|name| name.to_string()).collect();
+    while let Some(type_name) = pending.pop() {
+        if definitions.contains_key(&type_name) {
+            continue;
+        }
+        let constructor = registry.constructors.get(&type_name).ok_or_else(|| {
+            Error::Type(TypeError {
+                message: format!("Unknown value type: {}", type_name),
+                span: None,
+            })
+        })?;
+        let rules = ValidationRules::of(&constructor.declaration, registry);
+        pending.extend(referenced(&rules.parameter_type));
+        definitions.insert(type_name, value_schema(&rules));
+    }
+
+    let definitions: Vec<String> =
+        definitions.iter().map(|(name, schema)| format!("{}:{}", json_string(name), schema)).collect();
+    let root = match type_names {
+        [one] => format!(",\"$ref\":{}", json_string(&format!("#/$defs/{}", one))),
+        _ => String::new(),
+    };
+    Ok(format!("{{\"$schema\":{}{},\"$defs\":{{{}}}}}", json_string(DIALECT), root, definitions.join(",")))
+}
+
+/// The schema of one value type's object, with `$ref`s to the schemas of
+/// other value types under `#/$defs`.
+pub fn value_schema(rules: &ValidationRules) -> String {
+    let mut parameter = type_keywords(&rules.parameter_type);
+    let mut notes = Vec::new();
+    if rules.normalized {
+        if !rules.rules.is_empty() || !rules.unchecked.is_empty() {
+            notes.push(format!(
+                "{} is normalized before it is validated, so its validation is left to Relic",
+                rules.type_name
+            ));
+        }
+    } else {
+        parameter.extend(rule_keywords(rules));
+        if !rules.unchecked.is_empty() {
+            notes.push(format!("Relic also checks: {}", rules.unchecked.join(" && ")));
+        }
+    }
+    if !notes.is_empty() {
+        parameter.push(("description".to_string(), json_string(&notes.join(". "))));
+    }
+
+    format!(
+        "{{\"title\":{},\"type\":\"object\",\"properties\":{{{}:{}}},\"required\":[{}],\"additionalProperties\":false}}",
+        json_string(&rules.type_name),
+        json_string(&rules.parameter),
+        object(&parameter),
+        json_string(&rules.parameter)
+    )
+}
+
+// The keywords that say what type of JSON a Relic type is
+fn type_keywords(ty: &Type) -> Vec<(String, String)> {
+    let keyword = |name: &str, value: &str| (name.to_string(), json_string(value));
+    match ty {
+        Type::String => vec![keyword("type", "string")],
+        Type::Int => vec![keyword("type", "integer")],
+        Type::Bool => vec![keyword("type", "boolean")],
+        // A string, so that no digits are lost to floating point
+        Type::Decimal => vec![keyword("type", "string"), keyword("pattern", "^-?[0-9]+\\.[0-9]+$")],
+        Type::Date => vec![keyword("type", "string"), keyword("format", "date")],
+        Type::Time => vec![keyword("type", "string"), keyword("pattern", "^[0-9]{2}:[0-9]{2}(:[0-9]{2})?$")],
+        Type::Duration => vec![keyword("type", "string"), keyword("format", "duration")],
+        Type::Value(name) => vec![keyword("$ref", &format!("#/$defs/{}", name))],
+        Type::List(element) => vec![keyword("type", "array"), ("items".to_string(), object(&type_keywords(element)))],
+        Type::Option(element) => vec![(
+            "anyOf".to_string(),
+            format!("[{},{{\"type\":\"null\"}}]", object(&type_keywords(element))),
+        )],
+        // Anything else can't be sent as JSON, so the schema only names it
+        other => vec![keyword("description", &format!("A Relic {}", format_type(other)))],
+    }
+}
+
+// The keywords the rules amount to
+fn rule_keywords(rules: &ValidationRules) -> Vec<(String, String)> {
+    let mut keywords = Vec::new();
+    let (least, greatest) = rules.bounds();
+    let (low, high) = match rules.parameter_type {
+        Type::Int => ("minimum", "maximum"),
+        _ => ("minLength", "maxLength"),
+    };
+    if let Some(least) = least {
+        keywords.push((low.to_string(), least.to_string()));
+    }
+    if let Some(greatest) = greatest {
+        keywords.push((high.to_string(), greatest.to_string()));
+    }
+    // A pattern matches anywhere in the string, so each substring is a
+    // pattern of its own
+    let patterns: Vec<String> = rules.required_substrings().map(|text| json_string(&escape_pattern(text))).collect();
+    match patterns.as_slice() {
+        [] => {}
+        [one] => keywords.push(("pattern".to_string(), one.clone())),
+        many => {
+            let all: Vec<String> = many.iter().map(|pattern| format!("{{\"pattern\":{}}}", pattern)).collect();
+            keywords.push(("allOf".to_string(), format!("[{}]", all.join(","))));
+        }
+    }
+    keywords
+}
+
+// The value types `ty` refers to
+fn referenced(ty: &Type) -> Vec<String> {
+    match ty {
+        Type::Value(name) => vec![name.clone()],
+        Type::List(element) | Type::Option(element) => referenced(element),
+        _ => Vec::new(),
+    }
+}
+
+fn object(keywords: &[(String, String)]) -> String {
+    let keywords: Vec<String> = keywords.iter().map(|(name, value)| format!("{}:{}", json_string(name), value)).collect();
+    format!("{{{}}}", keywords.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_json_schema() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value Email(raw: String) {
+                     validate: raw contains \"@\" && raw.length <= 254
+                     normalize: raw.toLowerCase()
+                 }
+                 value Age(n: Int) { validate: n >= 0 && n < 150 && n != 13 }
+                 value Code(raw: String) { validate: raw contains \"a.b\" && raw contains \"-\" }
+                 value Member(email: Email) {}",
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.json_schema(&["Age"]).unwrap(),
+            "{\"$schema\":\"https://json-schema.org/draft/2020-12/schema\",\"$ref\":\"#/$defs/Age\",\"$defs\":{\
+             \"Age\":{\"title\":\"Age\",\"type\":\"object\",\"properties\":{\"n\":{\"type\":\"integer\",\
+             \"minimum\":0,\"maximum\":149,\"description\":\"Relic also checks: n != 13\"}},\"required\":[\"n\"],\
+             \"additionalProperties\":false}}}"
+        );
+
+        // A type's schema brings the types it refers to
+        let member = engine.json_schema(&["Member"]).unwrap();
+        assert!(member.contains("\"properties\":{\"email\":{\"$ref\":\"#/$defs/Email\"}}"), "{}", member);
+        assert!(member.contains("\"Email\":{\"title\":\"Email\""), "{}", member);
+        // Email is normalized, so its rules are only described
+        assert!(member.contains("\"raw\":{\"type\":\"string\",\"description\":\"Email is normalized"), "{}", member);
+
+        let code = engine.json_schema(&["Code", "Age"]).unwrap();
+        assert!(!code.contains("\"$ref\":\"#/$defs/Code\""), "{}", code);
+        assert!(code.contains("\"allOf\":[{\"pattern\":\"a\\\\.b\"},{\"pattern\":\"-\"}]"), "{}", code);
+
+        assert!(engine.json_schema(&["Missing"]).is_err());
+    }
+}
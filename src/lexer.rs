@@ -1,4 +1,9 @@
 use crate::error::{Error, LexerError, Result};
+use crate::decimal::Decimal;
+use crate::temporal::Temporal;
+use std::fmt;
+use unicode_ident::{is_xid_continue, is_xid_start};
+use unicode_normalization::UnicodeNormalization;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -16,6 +21,9 @@ pub enum Token {
     In,
     Match,
     Where,
+    Import,
+    Test,
+    Data,
     Arrow,        // => for match arms
     ReturnArrow,  // -> for function return types
 
@@ -23,15 +31,21 @@ pub enum Token {
     Identifier(String),
     String(String),
     Integer(i64),
+    Temporal(Temporal), // @2024-01-31, @14:30, @P90D
+    Decimal(Decimal),   // 19.99
 
     // Operators
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Colon,
     Dot,
+    Ellipsis, // ... before the rest of a list pattern
     Comma,
+    Semicolon,
 
     // Comparison operators
     Equal,
@@ -59,16 +73,275 @@ pub enum Token {
     // Pipeline operator
     Pipeline,
 
+    // Option operators; `!` after an expression unwraps it
+    QuestionDot, // ?.
+    Coalesce,    // ??
+
     // Special
     Eof,
+    Comment(String), // only in `tokenize` output; the parser never sees comments
+}
+
+impl fmt::Display for Token {
+    /// Source text of the token
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Value => "value",
+            Token::Fn => "fn",
+            Token::Method => "method",
+            Token::Validate => "validate",
+            Token::Normalize => "normalize",
+            Token::Unique => "unique",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Contains => "contains",
+            Token::Let => "let",
+            Token::In => "in",
+            Token::Match => "match",
+            Token::Where => "where",
+            Token::Import => "import",
+            Token::Test => "test",
+            Token::Data => "data",
+            Token::Arrow => "=>",
+            Token::ReturnArrow => "->",
+            Token::Identifier(name) => return write!(f, "{}", name),
+            Token::String(s) => return write!(f, "\"{}\"", s),
+            Token::Integer(n) => return write!(f, "{}", n),
+            Token::Temporal(t) => return write!(f, "@{}", t),
+            Token::Decimal(d) => return write!(f, "{}", d),
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::Colon => ":",
+            Token::Dot => ".",
+            Token::Ellipsis => "...",
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::Equal => "==",
+            Token::NotEqual => "!=",
+            Token::Less => "<",
+            Token::Greater => ">",
+            Token::LessEqual => "<=",
+            Token::GreaterEqual => ">=",
+            Token::Assign => "=",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Not => "!",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Pipeline => "|>",
+            Token::QuestionDot => "?.",
+            Token::Coalesce => "??",
+            Token::Eof => "",
+            Token::Comment(text) => text,
+        };
+        f.write_str(text)
+    }
+}
+
+/// Source location: character offset and 1-based line/column of the first
+/// character, plus the length in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub length: usize,
+}
+
+impl Span {
+    pub fn new(offset: usize, line: usize, column: usize, length: usize) -> Self {
+        Self {
+            offset,
+            line,
+            column,
+            length,
+        }
+    }
+
+    /// Character offset just past the end of the span.
+    pub fn end(&self) -> usize {
+        self.offset + self.length
+    }
+
+    /// Span starting where `self` starts and ending where `other` ends.
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            length: other.end().saturating_sub(self.offset),
+            ..*self
+        }
+    }
+}
+
+/// Highlighting class of a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Keyword,
+    /// A built-in type name, or any capitalized identifier, which by
+    /// convention names a value type
+    Type,
+    Identifier,
+    /// String, integer and boolean literals
+    Literal,
+    Operator,
+    /// Parentheses, brackets, braces, `,`, `:`, `;` and `.`
+    Punctuation,
+    Comment,
+}
+
+impl Category {
+    pub fn of(token: &Token) -> Category {
+        match token {
+            Token::True
+            | Token::False
+            | Token::String(_)
+            | Token::Integer(_)
+            | Token::Temporal(_)
+            | Token::Decimal(_) => Category::Literal,
+            Token::Value
+            | Token::Fn
+            | Token::Method
+            | Token::Validate
+            | Token::Normalize
+            | Token::Unique
+            | Token::Let
+            | Token::In
+            | Token::Match
+            | Token::Where
+            | Token::Import
+            | Token::Test
+            | Token::Data => Category::Keyword,
+            Token::Identifier(name) => {
+                if name.starts_with(char::is_uppercase) {
+                    Category::Type
+                } else {
+                    Category::Identifier
+                }
+            }
+            Token::LeftParen
+            | Token::RightParen
+            | Token::LeftBrace
+            | Token::RightBrace
+            | Token::LeftBracket
+            | Token::RightBracket
+            | Token::Colon
+            | Token::Dot
+            | Token::Ellipsis
+            | Token::Comma
+            | Token::Semicolon
+            | Token::Eof => Category::Punctuation,
+            Token::Comment(_) => Category::Comment,
+            Token::Contains
+            | Token::Arrow
+            | Token::ReturnArrow
+            | Token::Equal
+            | Token::NotEqual
+            | Token::Less
+            | Token::Greater
+            | Token::LessEqual
+            | Token::GreaterEqual
+            | Token::Assign
+            | Token::And
+            | Token::Or
+            | Token::Not
+            | Token::Plus
+            | Token::Minus
+            | Token::Star
+            | Token::Slash
+            | Token::Percent
+            | Token::Pipeline
+            | Token::QuestionDot
+            | Token::Coalesce => Category::Operator,
+        }
+    }
+}
+
+/// Split `source` into classified tokens, comments included, in source
+/// order, for syntax highlighting. Never fails: characters the lexer
+/// rejects are left out and scanning carries on after them.
+pub fn tokenize(source: &str) -> Vec<(Token, Span, Category)> {
+    let mut lexer = Lexer::new(source.to_string());
+    let mut tokens = Vec::new();
+    loop {
+        let before = lexer.position;
+        match lexer.next_spanned_token() {
+            Ok((Token::Eof, _)) => break,
+            Ok((token, span)) => {
+                let category = Category::of(&token);
+                tokens.push((token, span, category));
+            }
+            // Errors always consume input, but guard against looping anyway
+            Err(_) if lexer.position == before => break,
+            Err(_) => {}
+        }
+    }
+
+    for comment in lexer.comments() {
+        tokens.push((Token::Comment(comment.text.clone()), comment.span, Category::Comment));
+    }
+    tokens.sort_by_key(|(_, span, _)| span.offset);
+    tokens
+}
+
+/// Whether `source` stops partway through: inside an unclosed parenthesis,
+/// bracket, brace, string or block comment. The REPL keeps reading lines
+/// until its input is complete.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut lexer = Lexer::new(source.to_string());
+    let mut depth = 0isize;
+    loop {
+        let before = lexer.position;
+        match lexer.next_token() {
+            Ok(Token::Eof) => return depth > 0,
+            Ok(Token::LeftParen | Token::LeftBracket | Token::LeftBrace) => depth += 1,
+            Ok(Token::RightParen | Token::RightBracket | Token::RightBrace) => depth -= 1,
+            Ok(_) => {}
+            Err(Error::Lexer(e))
+                if matches!(
+                    e.message.as_str(),
+                    "Unclosed string literal"
+                        | "Unexpected end of string"
+                        | "Unterminated multi-line comment"
+                ) =>
+            {
+                return true
+            }
+            Err(_) if lexer.position == before => return false,
+            Err(_) => {}
+        }
+    }
+}
+
+/// A comment skipped by the lexer, kept so tools like the formatter can
+/// put it back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// Full comment text including the `//` or `/* */` delimiters
+    pub text: String,
+    pub span: Span,
 }
 
 pub struct Lexer {
     input: String,
+    // Character offset of `current_char`, used for spans
     position: usize,
+    // Byte offset of `current_char`, used to step through and slice `input`
+    byte_position: usize,
     current_char: Option<char>,
     line: usize,
     column: usize,
+    // Start of the token currently being scanned
+    token_line: usize,
+    token_column: usize,
+    token_start: usize,
+    token_start_byte: usize,
+    comments: Vec<Comment>,
 }
 
 impl Lexer {
@@ -76,17 +349,94 @@ impl Lexer {
         let mut lexer = Lexer {
             input,
             position: 0,
+            byte_position: 0,
             current_char: None,
             line: 1,
-            column: 0,
+            column: 1,
+            token_line: 1,
+            token_column: 1,
+            token_start: 0,
+            token_start_byte: 0,
+            comments: Vec::new(),
         };
         lexer.current_char = lexer.input.chars().next();
         lexer
     }
 
+    /// Start lexing `input` from the middle, at a character offset whose
+    /// line and column are already known. Spans stay relative to the start of
+    /// `input`.
+    pub fn resume_at(input: String, offset: usize, line: usize, column: usize) -> Self {
+        let mut lexer = Lexer::new(input);
+        lexer.position = offset;
+        lexer.byte_position = lexer
+            .input
+            .char_indices()
+            .nth(offset)
+            .map_or(lexer.input.len(), |(byte, _)| byte);
+        lexer.line = line;
+        lexer.column = column;
+        lexer.current_char = lexer.input[lexer.byte_position..].chars().next();
+        lexer
+    }
+
+    /// Comments skipped so far, in source order.
+    pub fn comments(&self) -> &[Comment] {
+        &self.comments
+    }
+
     pub fn next_token(&mut self) -> Result<Token> {
-        self.skip_whitespace();
+        self.next_spanned_token().map(|(token, _)| token)
+    }
+
+    /// Scan the next token together with the span it occupies in the source.
+    pub fn next_spanned_token(&mut self) -> Result<(Token, Span)> {
+        let token = self.scan_token()?;
+        let length = self.position - self.token_start;
+        Ok((
+            token,
+            Span::new(self.token_start, self.token_line, self.token_column, length),
+        ))
+    }
 
+    fn scan_token(&mut self) -> Result<Token> {
+        // Comments are skipped in a loop, not by recursing, so any number
+        // of them in a row can't overflow the stack
+        loop {
+            self.skip_whitespace();
+            self.token_line = self.line;
+            self.token_column = self.column;
+            self.token_start = self.position;
+            self.token_start_byte = self.byte_position;
+            if !self.skip_comment()? {
+                return self.scan_non_comment();
+            }
+        }
+    }
+
+    /// Skip the comment starting at the current character, recording it,
+    /// if there is one. Returns whether there was.
+    fn skip_comment(&mut self) -> Result<bool> {
+        let rest = &self.input[self.byte_position..];
+        // A `#!` line at the very start lets scripts run directly; keep it as
+        // a comment so the formatter writes it back out
+        if rest.starts_with("//") || (self.position == 0 && rest.starts_with("#!")) {
+            while self.current_char.is_some() && self.current_char != Some('\n') {
+                self.advance();
+            }
+        } else if rest.starts_with("/*") {
+            // Multi-line comment - skip until */
+            self.advance();
+            self.advance();
+            self.skip_multiline_comment()?;
+        } else {
+            return Ok(false);
+        }
+        self.record_comment();
+        Ok(true)
+    }
+
+    fn scan_non_comment(&mut self) -> Result<Token> {
         match self.current_char {
             None => Ok(Token::Eof),
             Some(ch) => match ch {
@@ -106,18 +456,44 @@ impl Lexer {
                     self.advance();
                     Ok(Token::RightBrace)
                 }
+                '[' => {
+                    self.advance();
+                    Ok(Token::LeftBracket)
+                }
+                ']' => {
+                    self.advance();
+                    Ok(Token::RightBracket)
+                }
                 ':' => {
                     self.advance();
                     Ok(Token::Colon)
                 }
                 '.' => {
                     self.advance();
-                    Ok(Token::Dot)
+                    if self.current_char != Some('.') {
+                        return Ok(Token::Dot);
+                    }
+                    self.advance();
+                    if self.current_char == Some('.') {
+                        self.advance();
+                        Ok(Token::Ellipsis)
+                    } else {
+                        Err(Error::Lexer(LexerError {
+                            message: "Unexpected '..', did you mean '...'?".to_string(),
+                            line: self.token_line,
+                            column: self.token_column,
+                            length: self.position - self.token_start,
+                        }))
+                    }
                 }
                 ',' => {
                     self.advance();
                     Ok(Token::Comma)
                 }
+                ';' => {
+                    self.advance();
+                    Ok(Token::Semicolon)
+                }
                 '+' => {
                     self.advance();
                     Ok(Token::Plus)
@@ -137,21 +513,7 @@ impl Lexer {
                 }
                 '/' => {
                     self.advance();
-                    if self.current_char == Some('/') {
-                        // Line comment - skip to end of line
-                        self.advance();
-                        while self.current_char.is_some() && self.current_char != Some('\n') {
-                            self.advance();
-                        }
-                        self.next_token()
-                    } else if self.current_char == Some('*') {
-                        // Multi-line comment - skip until */
-                        self.advance();
-                        self.skip_multiline_comment()?;
-                        self.next_token()
-                    } else {
-                        Ok(Token::Slash)
-                    }
+                    Ok(Token::Slash)
                 }
                 '%' => {
                     self.advance();
@@ -204,8 +566,9 @@ impl Lexer {
                     } else {
                         Err(Error::Lexer(LexerError {
                             message: "Unexpected character '&', did you mean '&&'?".to_string(),
-                            line: self.line,
-                            column: self.column,
+                            line: self.token_line,
+                            column: self.token_column,
+                            length: self.position - self.token_start,
                         }))
                     }
                 }
@@ -221,36 +584,76 @@ impl Lexer {
                         Err(Error::Lexer(LexerError {
                             message: "Unexpected character '|', did you mean '||' or '|>'?"
                                 .to_string(),
-                            line: self.line,
-                            column: self.column,
+                            line: self.token_line,
+                            column: self.token_column,
+                            length: self.position - self.token_start,
+                        }))
+                    }
+                }
+                '?' => {
+                    self.advance();
+                    if self.current_char == Some('.') {
+                        self.advance();
+                        Ok(Token::QuestionDot)
+                    } else if self.current_char == Some('?') {
+                        self.advance();
+                        Ok(Token::Coalesce)
+                    } else {
+                        Err(Error::Lexer(LexerError {
+                            message: "Unexpected character '?', did you mean '?.' or '??'?".to_string(),
+                            line: self.token_line,
+                            column: self.token_column,
+                            length: self.position - self.token_start,
                         }))
                     }
                 }
                 '"' => self.read_string(),
-                _ if ch.is_alphabetic() || ch == '_' => self.read_identifier(),
-                _ if ch.is_numeric() => self.read_number(),
-                _ => Err(Error::Lexer(LexerError {
-                    message: format!("Unexpected character '{}'", ch),
-                    line: self.line,
-                    column: self.column,
-                })),
+                '@' => self.read_temporal(),
+                _ if is_xid_start(ch) || ch == '_' => self.read_identifier(),
+                _ if ch.is_ascii_digit() => self.read_number(),
+                _ => {
+                    // Consume the character so callers can resume scanning
+                    self.advance();
+                    Err(Error::Lexer(LexerError {
+                        message: format!("Unexpected character '{}'", ch),
+                        line: self.token_line,
+                        column: self.token_column,
+                        length: self.position - self.token_start,
+                    }))
+                }
             },
         }
     }
 
     fn advance(&mut self) {
-        self.position += 1;
-        self.column += 1;
-
-        if self.position >= self.input.len() {
-            self.current_char = None;
+        let Some(ch) = self.current_char else {
+            return;
+        };
+        // Line/column always describe the position of `current_char`, and
+        // count characters, not bytes
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
         } else {
-            self.current_char = self.input.chars().nth(self.position);
-            if self.current_char == Some('\n') {
-                self.line += 1;
-                self.column = 0;
-            }
+            self.column += 1;
         }
+        self.position += 1;
+        self.byte_position += ch.len_utf8();
+        self.current_char = self.input[self.byte_position..].chars().next();
+    }
+
+    /// Source text of the token scanned so far.
+    fn token_text(&self) -> &str {
+        &self.input[self.token_start_byte..self.byte_position]
+    }
+
+    fn record_comment(&mut self) {
+        let length = self.position - self.token_start;
+        let text = self.token_text();
+        self.comments.push(Comment {
+            text: text.trim_end().to_string(),
+            span: Span::new(self.token_start, self.token_line, self.token_column, length),
+        });
     }
 
     fn skip_whitespace(&mut self) {
@@ -287,26 +690,32 @@ impl Lexer {
         if depth > 0 {
             Err(Error::Lexer(LexerError {
                 message: "Unterminated multi-line comment".to_string(),
-                line: self.line,
-                column: self.column,
+                line: self.token_line,
+                column: self.token_column,
+                length: self.position - self.token_start,
             }))
         } else {
             Ok(())
         }
     }
 
+    /// Identifiers follow UAX #31: a letter (or `_`) followed by letters,
+    /// marks, digits and connector punctuation. They are NFC-normalized, so
+    /// `é` means the same name whether typed precomposed or as `e` + accent.
     fn read_identifier(&mut self) -> Result<Token> {
-        let start = self.position;
-
         while let Some(ch) = self.current_char {
-            if ch.is_alphanumeric() || ch == '_' {
+            if is_xid_continue(ch) {
                 self.advance();
             } else {
                 break;
             }
         }
 
-        let identifier = &self.input[start..self.position];
+        let identifier = self.token_text();
+        // Keywords are all ASCII
+        if !identifier.is_ascii() {
+            return Ok(Token::Identifier(identifier.nfc().collect()));
+        }
 
         let token = match identifier {
             "value" => Token::Value,
@@ -322,6 +731,9 @@ impl Lexer {
             "in" => Token::In,
             "match" => Token::Match,
             "where" => Token::Where,
+            "import" => Token::Import,
+            "test" => Token::Test,
+            "data" => Token::Data,
             _ => Token::Identifier(identifier.to_string()),
         };
 
@@ -329,35 +741,85 @@ impl Lexer {
     }
 
     fn read_number(&mut self) -> Result<Token> {
-        let start = self.position;
-
-        while let Some(ch) = self.current_char {
-            if ch.is_numeric() {
-                self.advance();
-            } else {
-                break;
-            }
+        self.skip_digits();
+        // A point followed by a digit makes a decimal; otherwise it's a
+        // member access, as in `1.abs()`
+        let fraction = self.input[self.byte_position..].strip_prefix('.');
+        if fraction.is_some_and(|rest| rest.starts_with(|ch: char| ch.is_ascii_digit())) {
+            self.advance();
+            self.skip_digits();
+            let text = self.token_text();
+            return Decimal::parse(text).map(Token::Decimal).ok_or_else(|| {
+                Error::Lexer(LexerError {
+                    message: format!("Invalid decimal: {} (a decimal has at most {} places)", text, crate::decimal::MAX_PLACES),
+                    line: self.token_line,
+                    column: self.token_column,
+                    length: self.position - self.token_start,
+                })
+            });
         }
 
-        let number_str = &self.input[start..self.position];
+        let number_str = self.token_text();
         let number = number_str.parse::<i64>().map_err(|_| {
             Error::Lexer(LexerError {
                 message: format!("Invalid number: {}", number_str),
-                line: self.line,
-                column: self.column - number_str.len(),
+                line: self.token_line,
+                column: self.token_column,
+                length: self.position - self.token_start,
             })
         })?;
 
         Ok(Token::Integer(number))
     }
 
+    fn skip_digits(&mut self) {
+        while self.current_char.is_some_and(|ch| ch.is_ascii_digit()) {
+            self.advance();
+        }
+    }
+
+    /// An ISO-8601 date, time or duration after `@`: digits and dashes for
+    /// a date, digits and colons for a time, and `P` followed by digits and
+    /// designators for a duration.
+    fn read_temporal(&mut self) -> Result<Token> {
+        self.advance(); // Skip @
+        let start = self.byte_position;
+        let duration = self.current_char == Some('P');
+        let mut dashes = 0;
+        while let Some(ch) = self.current_char {
+            let part = if duration {
+                ch.is_ascii_digit() || ch.is_ascii_uppercase()
+            } else if ch == '-' && !self.input[start..self.byte_position].contains(':') {
+                // A date has two dashes; a third would be a subtraction
+                dashes += 1;
+                dashes <= 2
+            } else {
+                ch.is_ascii_digit() || ch == ':'
+            };
+            if !part {
+                break;
+            }
+            self.advance();
+        }
+
+        let text = &self.input[start..self.byte_position];
+        Temporal::parse(text).map(Token::Temporal).ok_or_else(|| {
+            Error::Lexer(LexerError {
+                message: format!("Invalid date, time or duration: @{} (expected e.g. @2024-01-31, @14:30 or @PT1H30M)", text),
+                line: self.token_line,
+                column: self.token_column,
+                length: self.position - self.token_start,
+            })
+        })
+    }
+
     fn read_string(&mut self) -> Result<Token> {
         self.advance(); // Skip opening quote
-        let start = self.position;
+        let start = self.byte_position;
 
         while let Some(ch) = self.current_char {
             if ch == '"' {
-                let string = self.input[start..self.position].to_string();
+                let string = self.input[start..self.byte_position].to_string();
                 self.advance(); // Skip closing quote
                 return Ok(Token::String(string));
             } else if ch == '\\' {
@@ -365,8 +827,9 @@ impl Lexer {
                 if self.current_char.is_none() {
                     return Err(Error::Lexer(LexerError {
                         message: "Unexpected end of string".to_string(),
-                        line: self.line,
-                        column: self.column,
+                        line: self.token_line,
+                        column: self.token_column,
+                        length: self.position - self.token_start,
                     }));
                 }
                 self.advance();
@@ -377,8 +840,9 @@ impl Lexer {
 
         Err(Error::Lexer(LexerError {
             message: "Unclosed string literal".to_string(),
-            line: self.line,
-            column: self.column,
+            line: self.token_line,
+            column: self.token_column,
+            length: self.position - self.token_start,
         }))
     }
 }
@@ -403,6 +867,38 @@ mod tests {
         assert_eq!(lexer.next_token().unwrap(), Token::Eof);
     }
 
+    #[test]
+    fn test_temporal_literals() {
+        let tokens = |source: &str| {
+            let mut lexer = Lexer::new(source.to_string());
+            let mut tokens = Vec::new();
+            loop {
+                match lexer.next_token().unwrap() {
+                    Token::Eof => return tokens,
+                    token => tokens.push(token.to_string()),
+                }
+            }
+        };
+        // A dash after a complete date or a time is a subtraction
+        assert_eq!(tokens("@2024-01-31-@P1W"), ["@2024-01-31", "-", "@P7D"]);
+        assert_eq!(tokens("@09:30-@PT90M"), ["@09:30:00", "-", "@PT1H30M"]);
+        assert!(Lexer::new("@2024-02-30".to_string()).next_token().is_err());
+        assert!(Lexer::new("@P1M".to_string()).next_token().is_err());
+    }
+
+    #[test]
+    fn test_decimal_literals() {
+        let mut lexer = Lexer::new("19.990 1.abs() 0.5".to_string());
+        assert_eq!(lexer.next_token().unwrap().to_string(), "19.990");
+        assert_eq!(lexer.next_token().unwrap(), Token::Integer(1));
+        assert_eq!(lexer.next_token().unwrap(), Token::Dot);
+        assert_eq!(lexer.next_token().unwrap(), Token::Identifier("abs".to_string()));
+        assert_eq!(lexer.next_token().unwrap(), Token::LeftParen);
+        assert_eq!(lexer.next_token().unwrap(), Token::RightParen);
+        assert!(matches!(lexer.next_token().unwrap(), Token::Decimal(_)));
+        assert!(Lexer::new(format!("0.{}", "1".repeat(29))).next_token().is_err());
+    }
+
     #[test]
     fn test_pipeline_vs_or() {
         let mut lexer = Lexer::new("a || b |> c".to_string());
@@ -473,4 +969,197 @@ mod tests {
         assert_eq!(lexer.next_token().unwrap(), Token::RightBrace);
         assert_eq!(lexer.next_token().unwrap(), Token::Eof);
     }
+
+    #[test]
+    fn test_token_spans() {
+        let mut lexer = Lexer::new("value Email(raw: String) {\n    validate: raw contains \"@\"\n}".to_string());
+
+        assert_eq!(lexer.next_spanned_token().unwrap(), (Token::Value, Span::new(0, 1, 1, 5)));
+        assert_eq!(
+            lexer.next_spanned_token().unwrap(),
+            (Token::Identifier("Email".to_string()), Span::new(6, 1, 7, 5))
+        );
+        for _ in 0..6 {
+            lexer.next_spanned_token().unwrap();
+        }
+        assert_eq!(lexer.next_spanned_token().unwrap(), (Token::Validate, Span::new(31, 2, 5, 8)));
+        assert_eq!(lexer.next_spanned_token().unwrap(), (Token::Colon, Span::new(39, 2, 13, 1)));
+        lexer.next_spanned_token().unwrap();
+        lexer.next_spanned_token().unwrap();
+        assert_eq!(
+            lexer.next_spanned_token().unwrap(),
+            (Token::String("@".to_string()), Span::new(54, 2, 28, 3))
+        );
+        assert_eq!(lexer.next_spanned_token().unwrap(), (Token::RightBrace, Span::new(58, 3, 1, 1)));
+    }
+
+    #[test]
+    fn test_spans_after_comments() {
+        let mut lexer = Lexer::new("// header\n/* block\n comment */ x".to_string());
+        assert_eq!(
+            lexer.next_spanned_token().unwrap(),
+            (Token::Identifier("x".to_string()), Span::new(31, 3, 13, 1))
+        );
+    }
+
+    #[test]
+    fn test_comments_are_recorded() {
+        let mut lexer = Lexer::new("// header\n/* block\n comment */ x".to_string());
+        lexer.next_token().unwrap();
+
+        let comments: Vec<_> = lexer
+            .comments()
+            .iter()
+            .map(|c| (c.text.as_str(), c.span))
+            .collect();
+        assert_eq!(
+            comments,
+            vec![
+                ("// header", Span::new(0, 1, 1, 9)),
+                ("/* block\n comment */", Span::new(10, 2, 1, 20)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_many_comments_in_a_row() {
+        let source = "// c\n/* c */\n".repeat(50_000) + "x";
+        let mut lexer = Lexer::new(source);
+        assert_eq!(lexer.next_token().unwrap(), Token::Identifier("x".to_string()));
+        assert_eq!(lexer.comments().len(), 100_000);
+    }
+
+    #[test]
+    fn test_shebang_line_is_skipped() {
+        let mut lexer = Lexer::new("#!/usr/bin/env relic\nx".to_string());
+        assert_eq!(
+            lexer.next_spanned_token().unwrap(),
+            (Token::Identifier("x".to_string()), Span::new(21, 2, 1, 1))
+        );
+        assert_eq!(lexer.comments()[0].text, "#!/usr/bin/env relic");
+
+        // Only the first line of the file can be a shebang
+        let mut lexer = Lexer::new("x\n#!".to_string());
+        lexer.next_token().unwrap();
+        assert!(lexer.next_token().is_err());
+    }
+
+    #[test]
+    fn test_tokenize_classifies_tokens() {
+        let tokens: Vec<_> = tokenize("fn f(x: Int) -> Bool { // check\n  x > 0 && true }")
+            .into_iter()
+            .map(|(token, _, category)| (token.to_string(), category))
+            .collect();
+
+        assert_eq!(
+            tokens,
+            vec![
+                ("fn".to_string(), Category::Keyword),
+                ("f".to_string(), Category::Identifier),
+                ("(".to_string(), Category::Punctuation),
+                ("x".to_string(), Category::Identifier),
+                (":".to_string(), Category::Punctuation),
+                ("Int".to_string(), Category::Type),
+                (")".to_string(), Category::Punctuation),
+                ("->".to_string(), Category::Operator),
+                ("Bool".to_string(), Category::Type),
+                ("{".to_string(), Category::Punctuation),
+                ("// check".to_string(), Category::Comment),
+                ("x".to_string(), Category::Identifier),
+                (">".to_string(), Category::Operator),
+                ("0".to_string(), Category::Literal),
+                ("&&".to_string(), Category::Operator),
+                ("true".to_string(), Category::Literal),
+                ("}".to_string(), Category::Punctuation),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_skips_invalid_characters() {
+        let tokens = tokenize("a $ b");
+        let spans: Vec<_> = tokens.iter().map(|(_, span, _)| span.offset).collect();
+        assert_eq!(spans, vec![0, 4]);
+        assert_eq!(tokenize("x \"open").len(), 1);
+    }
+
+    #[test]
+    fn test_multibyte_characters() {
+        let mut lexer = Lexer::new("// héllo 🦀\n\"naïve ☕\" x".to_string());
+        assert_eq!(
+            lexer.next_spanned_token().unwrap(),
+            (Token::String("naïve ☕".to_string()), Span::new(11, 2, 1, 9))
+        );
+        assert_eq!(
+            lexer.next_spanned_token().unwrap(),
+            (Token::Identifier("x".to_string()), Span::new(21, 2, 11, 1))
+        );
+        assert_eq!(lexer.comments()[0].text, "// héllo 🦀");
+
+        let mut lexer = Lexer::resume_at("\"é\" é1".to_string(), 4, 1, 5);
+        assert_eq!(lexer.next_token().unwrap(), Token::Identifier("é1".to_string()));
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let identifiers = |source: &str| -> Vec<Token> {
+            let mut lexer = Lexer::new(source.to_string());
+            std::iter::from_fn(|| match lexer.next_token().unwrap() {
+                Token::Eof => None,
+                token => Some(token),
+            })
+            .collect()
+        };
+
+        assert_eq!(
+            identifiers("Straße größe_2 日本語 ψυχή _x"),
+            ["Straße", "größe_2", "日本語", "ψυχή", "_x"]
+                .map(|name| Token::Identifier(name.to_string()))
+        );
+
+        // `e` followed by a combining acute accent is the same name as `é`
+        assert_eq!(identifiers("cafe\u{301}"), identifiers("caf\u{e9}"));
+        assert_eq!(identifiers("cafe\u{301}")[0], Token::Identifier("caf\u{e9}".to_string()));
+
+        // Spans count the characters as written
+        let mut lexer = Lexer::new("cafe\u{301} x".to_string());
+        lexer.next_token().unwrap();
+        assert_eq!(lexer.next_spanned_token().unwrap().1, Span::new(6, 1, 7, 1));
+    }
+
+    #[test]
+    fn test_identifiers_cannot_start_with_marks_or_symbols() {
+        assert!(Lexer::new("\u{301}x".to_string()).next_token().is_err());
+        assert!(Lexer::new("€uro".to_string()).next_token().is_err());
+    }
+
+    #[test]
+    fn test_only_ascii_digits_start_numbers() {
+        let mut lexer = Lexer::new("١٢".to_string());
+        assert!(lexer.next_token().is_err());
+    }
+
+    #[test]
+    fn test_lexer_error_position() {
+        let mut lexer = Lexer::new("a\n  b & c".to_string());
+        lexer.next_token().unwrap();
+        lexer.next_token().unwrap();
+        match lexer.next_token() {
+            Err(Error::Lexer(e)) => {
+                assert_eq!(e.line, 2);
+                assert_eq!(e.column, 5);
+            }
+            other => panic!("Expected lexer error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        for source in ["fn f(x: Int) -> Int {", "f(1,\n", "List[", "\"abc", "x /* note", "{ ( }"] {
+            assert!(is_incomplete(source), "{:?}", source);
+        }
+        for source in ["", "fn f(x: Int) -> Int { x }", "f(1))", "\"{\"", "// {", "1 $ 2"] {
+            assert!(!is_incomplete(source), "{:?}", source);
+        }
+    }
 }
@@ -1,15 +1,51 @@
+pub mod aggregate;
+pub mod artifact;
 pub mod ast;
+pub mod ast_json;
+pub mod builtin;
+pub mod cancel;
 pub mod compiler;
+pub mod completion;
+pub mod convert;
+pub mod coverage;
+pub mod decimal;
+pub mod diagnostic;
+pub mod dispatch;
+pub mod division;
+pub mod doc;
+pub mod engine;
 pub mod error;
 pub mod evaluator;
+pub mod explain;
+pub mod ffi;
+pub mod formatter;
+pub mod inspect;
+pub mod json;
+pub mod json_schema;
 pub mod lexer;
+pub mod limits;
 pub mod list;
+pub mod migrate;
+pub mod module;
+pub mod native;
 pub mod optimized_evaluator;
 pub mod parser;
+pub mod profile;
+pub mod proto;
+pub mod provenance;
 pub mod query;
 pub mod relation;
+pub mod reload;
+pub mod replay;
+pub mod rules;
 pub mod specialization;
+pub mod stable_hash;
 pub mod stdlib;
+pub mod storage;
+pub mod subscription;
+pub mod table;
+pub mod template;
+pub mod temporal;
 pub mod typechecker;
 pub mod types;
 pub mod value;
@@ -17,4 +53,5 @@ pub mod value;
 #[cfg(test)]
 mod test_value_equality;
 
+pub use engine::Engine;
 pub use error::{Error, Result};
@@ -0,0 +1,176 @@
+//! Bounds on what one evaluation may do, for hosts that run Relic code
+//! supplied by their users. Every limit is off by default.
+
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::EvalValue;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// Values constructed, each of which is kept as an instance
+    pub max_instances: Option<u64>,
+    /// Elements in any one list, such as `all(Email)`
+    pub max_list_len: Option<usize>,
+    /// Characters in any one string
+    pub max_string_len: Option<usize>,
+    /// Function calls in progress at once, to stop runaway recursion
+    /// before it overflows the stack
+    pub max_depth: Option<usize>,
+}
+
+impl EvalLimits {
+    /// Fail if `value` is a longer list or string than allowed.
+    pub(crate) fn check_value(&self, value: &EvalValue) -> Result<()> {
+        match (value, self.max_list_len, self.max_string_len) {
+            (EvalValue::List(items), Some(max), _) if items.len() > max => {
+                Err(exceeded(format!("a list of {} elements, more than the limit of {}", items.len(), max)))
+            }
+            (EvalValue::String(s), _, Some(max)) if s.chars().count() > max => Err(exceeded(format!(
+                "a string of {} characters, more than the limit of {}",
+                s.chars().count(),
+                max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if a string of `chars` characters, about to be built, would be
+    /// longer than allowed.
+    pub(crate) fn check_string_len(&self, chars: usize) -> Result<()> {
+        match self.max_string_len {
+            Some(max) if chars > max => Err(exceeded(format!(
+                "a string of {} characters, more than the limit of {}",
+                chars, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What the evaluations in progress have used of their limits. An engine
+/// shared between threads evaluates on each of them at once, so what each
+/// thread's evaluation has used is counted apart, in a thread local keyed by
+/// the `Usage` it belongs to.
+#[derive(Debug)]
+pub(crate) struct Usage {
+    id: u64,
+}
+
+/// One thread's evaluation with one registry.
+#[derive(Debug, Clone, Copy, Default)]
+struct Frame {
+    depth: usize,
+    instances: u64,
+}
+
+static NEXT_USAGE: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static FRAMES: RefCell<HashMap<u64, Frame>> = RefCell::new(HashMap::new());
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Usage { id: NEXT_USAGE.fetch_add(1, Ordering::Relaxed) }
+    }
+}
+
+impl Usage {
+    fn frame<T>(&self, f: impl FnOnce(&mut Frame) -> T) -> T {
+        FRAMES.with(|frames| f(frames.borrow_mut().entry(self.id).or_default()))
+    }
+
+    /// Start counting for a new evaluation on this thread.
+    pub(crate) fn begin(&self) {
+        self.frame(|frame| *frame = Frame::default());
+    }
+
+    /// Fail if creating one more instance would go over the limit.
+    pub(crate) fn check_instance(&self, limits: &EvalLimits) -> Result<()> {
+        let Some(max) = limits.max_instances else {
+            return Ok(());
+        };
+        if self.frame(|frame| frame.instances) >= max {
+            return Err(exceeded(format!("more than the limit of {} instances", max)));
+        }
+        Ok(())
+    }
+
+    /// Count an instance created by this thread's evaluation.
+    pub(crate) fn instance_created(&self) {
+        self.frame(|frame| frame.instances += 1);
+    }
+
+    /// Enter a function call, failing if that's deeper than allowed. The
+    /// call must `leave` when it returns.
+    pub(crate) fn enter(&self, limits: &EvalLimits) -> Result<()> {
+        let depth = self.frame(|frame| {
+            frame.depth += 1;
+            frame.depth
+        });
+        match limits.max_depth {
+            Some(max) if depth > max => {
+                self.leave();
+                Err(exceeded(format!("calls nested more than {} deep", max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn leave(&self) {
+        self.frame(|frame| frame.depth = frame.depth.saturating_sub(1));
+    }
+}
+
+impl Drop for Usage {
+    fn drop(&mut self) {
+        // Other threads' frames are left for their thread locals to drop
+        let _ = FRAMES.try_with(|frames| frames.borrow_mut().remove(&self.id));
+    }
+}
+
+fn exceeded(what: String) -> Error {
+    Error::Validation(ValidationError {
+        message: format!("Evaluation exceeded its limits: {}", what),
+        value_type: "limits".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_limits() {
+        let limits = EvalLimits {
+            max_instances: Some(2),
+            max_list_len: Some(3),
+            max_string_len: Some(10),
+            max_depth: Some(2),
+        };
+        let mut engine = Engine::new().with_limits(limits);
+        engine
+            .load(
+                "value Tally(n: Int) {}
+                 fn one(n: Int) -> Int { n + 1 }
+                 fn two(n: Int) -> Int { one(n) + 1 }
+                 fn three(n: Int) -> Int { two(n) + 1 }
+                 fn tallies(n: Int) -> Int { let a = Tally(n); let b = Tally(n + 1); let c = Tally(n + 2); n }",
+            )
+            .unwrap();
+        let exceeded = |result: Result<EvalValue>| result.unwrap_err().to_string();
+
+        assert_eq!(engine.eval("two(0)").unwrap(), EvalValue::Integer(2));
+        assert!(exceeded(engine.eval("three(0)")).contains("calls nested more than 2 deep"));
+        assert!(exceeded(engine.eval("tallies(1)")).contains("more than the limit of 2 instances"));
+        // The limit is per evaluation
+        engine.eval("Tally(10)").unwrap();
+        engine.eval("Tally(11)").unwrap();
+        assert!(exceeded(engine.eval("all(Tally)")).contains("a list of 4 elements"));
+        assert!(exceeded(engine.eval("\"hello world\".toUpperCase()")).contains("a string of 11 characters"));
+        assert_eq!(engine.eval("\"hello\"").unwrap(), EvalValue::String("hello".to_string()));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::stable_hash::stable_hash;
 use crate::value::ValueObject;
 use crate::error::Result;
 use std::any::Any;
@@ -42,7 +43,7 @@ impl List {
         self.items.get(index)
     }
 
-    pub fn iter(&self) -> std::slice::Iter<Arc<dyn ValueObject>> {
+    pub fn iter(&self) -> std::slice::Iter<'_, Arc<dyn ValueObject>> {
         self.items.iter()
     }
 
@@ -66,7 +67,7 @@ impl List {
     {
         let mapped_items: Vec<Arc<dyn ValueObject>> = self.items
             .iter()
-            .map(|item| mapper(item))
+            .map(mapper)
             .collect();
 
         // Note: mapping might change the element type, but for now we keep it simple
@@ -87,14 +88,14 @@ impl List {
     where
         F: Fn(&Arc<dyn ValueObject>) -> bool,
     {
-        self.items.iter().any(|item| predicate(item))
+        self.items.iter().any(predicate)
     }
 
     pub fn all<F>(&self, predicate: F) -> bool
     where
         F: Fn(&Arc<dyn ValueObject>) -> bool,
     {
-        self.items.iter().all(|item| predicate(item))
+        self.items.iter().all(predicate)
     }
 }
 
@@ -153,15 +154,14 @@ impl ValueObject for List {
     }
 
     fn hash_value(&self) -> u64 {
-        use std::collections::hash_map::DefaultHasher;
         use std::hash::{Hash, Hasher};
-        
-        let mut hasher = DefaultHasher::new();
-        self.element_type.hash(&mut hasher);
-        self.items.len().hash(&mut hasher);
-        for item in &self.items {
-            item.hash_value().hash(&mut hasher);
-        }
-        hasher.finish()
+
+        stable_hash(|hasher| {
+            self.element_type.hash(hasher);
+            hasher.write_usize(self.items.len());
+            for item in &self.items {
+                hasher.write_u64(item.hash_value());
+            }
+        })
     }
 }
\ No newline at end of file
@@ -1,13 +1,255 @@
-use relic::{compiler::Compiler, lexer::{Lexer, Token}, parser::Parser, typechecker::TypeChecker};
+use relic::{
+    ast::{Declaration, FunctionDeclaration, Program},
+    ast_json,
+    compiler::Compiler,
+    completion,
+    coverage,
+    diagnostic::{json_string, Diagnostic, Format, Renderer},
+    error::catch_panic,
+    evaluator::EvalValue,
+    explain,
+    doc::{self, DocFormat},
+    formatter,
+    inspect,
+    lexer::{self, Lexer, Token},
+    migrate,
+    reload,
+    module::{self, FileSystemResolver},
+    stdlib::ScriptEnvironment,
+    parser::Parser,
+    profile::Profile,
+    provenance::{self, Provenance},
+    replay::{Recorder, ReplayLog},
+    table,
+    typechecker::TypeChecker,
+    types::Type,
+    Engine,
+};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::{Hint, Hinter},
+    history::DefaultHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     env,
+    ffi::OsString,
     fs,
-    io::{self, Write},
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
 };
 
+/// Commands that control the REPL itself rather than run code.
+const COMMANDS: &[&str] = &[
+    ":help", ":exit", ":type", ":save", ":load", ":env", ":instances", ":forget", ":migrate", ":reset",
+    ":format", ":inspect", ":reload", ":ast", ":tokens",
+];
+
+/// What an input produced: printed as text, or as a JSON object per line
+/// for programs driving the REPL (`--json`).
+enum Outcome {
+    /// A declaration was defined; `kind` is "value type", "function" or
+    /// "method"
+    Defined { kind: &'static str, name: String },
+    /// A `data` block constructed `count` instances of `type_name`
+    Seeded { type_name: String, count: usize },
+    /// An expression's value and type
+    Value { value: EvalValue, ty: Type },
+    /// A top-level `let` bound `name` to a value of type `ty`
+    Bound { name: String, ty: Type },
+    /// An expression's type, from `:type`
+    Type { expression: String, ty: Type },
+    /// Whether a `test` declaration passed, from `relic test`
+    Test { name: String, passed: bool },
+    /// An expression's value and type, with what evaluating it took, from
+    /// `:time`
+    Timed {
+        value: EvalValue,
+        ty: Type,
+        elapsed: Duration,
+        profile: Profile,
+    },
+    /// Anything else a command has to say
+    Message(String),
+}
+
+impl Outcome {
+    /// The outcome as a replay log records it: as displayed, but without
+    /// how long `:time` took, which differs from run to run.
+    fn replay_text(&self) -> String {
+        match self {
+            Outcome::Timed { value, ty, .. } => Outcome::Value { value: value.clone(), ty: ty.clone() }.to_string(),
+            other => other.to_string(),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        match self {
+            Outcome::Defined { kind, name } => format!(
+                "{{\"kind\":\"defined\",\"declaration\":{},\"name\":{}}}",
+                json_string(kind),
+                json_string(name)
+            ),
+            Outcome::Seeded { type_name, count } => format!(
+                "{{\"kind\":\"seeded\",\"type\":{},\"count\":{}}}",
+                json_string(type_name),
+                count
+            ),
+            Outcome::Value { value, ty } => format!(
+                "{{\"kind\":\"value\",\"value\":{},\"type\":{}}}",
+                json_string(&value.to_string()),
+                json_string(&ty.to_string())
+            ),
+            Outcome::Bound { name, ty } => format!(
+                "{{\"kind\":\"bound\",\"name\":{},\"type\":{}}}",
+                json_string(name),
+                json_string(&ty.to_string())
+            ),
+            Outcome::Type { expression, ty } => format!(
+                "{{\"kind\":\"type\",\"expression\":{},\"type\":{}}}",
+                json_string(expression),
+                json_string(&ty.to_string())
+            ),
+            Outcome::Test { name, passed } => format!(
+                "{{\"kind\":\"test\",\"name\":{},\"passed\":{}}}",
+                json_string(name),
+                passed
+            ),
+            Outcome::Timed {
+                value,
+                ty,
+                elapsed,
+                profile,
+            } => format!(
+                "{{\"kind\":\"timed\",\"value\":{},\"type\":{},\"ms\":{:.3},\"dispatches\":{},\"cache_hits\":{},\"instances_created\":{}}}",
+                json_string(&value.to_string()),
+                json_string(&ty.to_string()),
+                elapsed.as_secs_f64() * 1000.0,
+                profile.dispatches,
+                profile.cache_hits,
+                profile.instances_created
+            ),
+            Outcome::Message(text) => format!("{{\"kind\":\"message\",\"text\":{}}}", json_string(text)),
+        }
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Defined { kind, name } => write!(f, "Defined {}: {}", kind, name),
+            Outcome::Seeded { type_name, count: n } => {
+                write!(f, "Seeded {}", count(*n, &format!("{} instance", type_name)))
+            }
+            Outcome::Value { value, ty } => write!(f, "→ {} : {}", value, ty),
+            Outcome::Bound { name, ty } => write!(f, "Bound {} : {}", name, ty),
+            Outcome::Type { expression, ty } => write!(f, "{} : {}", expression, ty),
+            Outcome::Test { name, passed } => {
+                write!(f, "test {} ... {}", name, if *passed { "ok" } else { "FAILED" })
+            }
+            Outcome::Timed {
+                value,
+                ty,
+                elapsed,
+                profile,
+            } => write!(
+                f,
+                "→ {} : {}\nTook {:.3} ms: {} dispatched ({} from the cache), {} created",
+                value,
+                ty,
+                elapsed.as_secs_f64() * 1000.0,
+                count(profile.dispatches as usize, "call"),
+                profile.cache_hits,
+                count(profile.instances_created as usize, "instance")
+            ),
+            Outcome::Message(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Line editor support for the REPL: tab completion from the declarations
+/// entered so far, and parameter help while typing a call's arguments.
+struct ReplHelper {
+    // Snapshot of the REPL's type checker, refreshed after each input
+    typechecker: TypeChecker,
+    // Every function overload, with its guards, refreshed with the type checker
+    functions: Vec<FunctionDeclaration>,
+    // Whether to dim hints
+    color: bool,
+}
+
+/// The signatures shown after the cursor while typing a call's arguments.
+/// Unlike a history hint, accepting it doesn't insert anything.
+struct SignatureHint(String);
+
+impl Hint for SignatureHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, candidates) = completion::complete(&self.typechecker, COMMANDS, line, pos);
+        let pairs = candidates
+            .into_iter()
+            .map(|c| Pair {
+                display: c.display,
+                replacement: c.replacement,
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = SignatureHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<SignatureHint> {
+        // Only at the end of the line, where there's room for it
+        if pos < line.len() {
+            return None;
+        }
+        completion::signature_hint(&self.typechecker, &self.functions, line, pos)
+            .map(|hint| SignatureHint(format!("  {}", hint)))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        if self.color {
+            Cow::Owned(format!("\x1b[2m{}\x1b[0m", hint))
+        } else {
+            Cow::Borrowed(hint)
+        }
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
 struct Repl {
     compiler: Compiler,
     typechecker: TypeChecker,
+    // Warnings from the last input, reported alongside its result
+    warnings: Vec<Diagnostic>,
+    // Everything defined so far, in order, for `:save`
+    session: Program,
+    // Where inputs come from, for the provenance of what they construct
+    origin: String,
 }
 
 impl Repl {
@@ -15,66 +257,669 @@ impl Repl {
         Self {
             compiler: Compiler::new(),
             typechecker: TypeChecker::new(),
+            warnings: Vec::new(),
+            session: Program {
+                imports: Vec::new(),
+                declarations: Vec::new(),
+                expressions: Vec::new(),
+                tests: Vec::new(),
+                data: Vec::new(),
+            },
+            origin: "<repl>".to_string(),
+        }
+    }
+
+    /// A session for running `filename`, whose imports are relative to it.
+    /// Standard input (`-`) imports relative to the working directory.
+    fn for_file(filename: &str, environment: ScriptEnvironment) -> Self {
+        let mut repl = Self::new();
+        let mut compiler = Compiler::new().with_script_environment(environment);
+        if filename != "-" {
+            let root = Path::new(filename).parent().unwrap_or(Path::new(""));
+            compiler = compiler.with_resolver(FileSystemResolver::new(root));
         }
+        repl.compiler = compiler;
+        repl.origin = origin(filename).to_string();
+        repl
     }
 
-    fn process_declaration(&mut self, input: &str) -> relic::Result<String> {
+    /// Start with the instances `previous` constructed, for
+    /// `run --watch --keep-instances`.
+    fn keep_instances(mut self, previous: &Repl) -> Self {
+        self.compiler = self.compiler.with_instances_from(previous.compiler.get_registry());
+        self
+    }
+
+    fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn process_declaration(&mut self, input: &str) -> Vec<relic::Result<Outcome>> {
         // Parse
         let lexer = Lexer::new(input.to_string());
-        let mut parser = Parser::new(lexer)?;
-        let program = parser.parse_program()?;
+        let program = Parser::new(lexer).and_then(|mut parser| {
+            let program = parser.parse_program();
+            self.warnings.extend_from_slice(parser.warnings());
+            program
+        });
+
+        match program {
+            Ok(program) => self.process_program(&program),
+            Err(e) => vec![Err(e)],
+        }
+    }
+
+    fn process_file(&mut self, contents: &str) -> Result<Vec<relic::Result<Outcome>>, Vec<relic::Error>> {
+        let program = self.parse_file(contents)?;
+        Ok(self.process_program(&program))
+    }
+
+    /// Parse and type check a file and the modules it imports, without
+    /// compiling or running anything.
+    fn check_file(&mut self, contents: &str) -> Result<(), Vec<relic::Error>> {
+        let program = self.parse_file(contents)?;
+        let modules = self.compiler.load_imports(&program).map_err(|e| vec![e])?;
+        for module in &modules {
+            self.typechecker
+                .check_program(&module.program)
+                .map_err(|e| vec![module::in_module(&module.name, e)])?;
+        }
+        let errors = self.typechecker.check_program_recovering(&program);
+        self.warnings.extend(coverage::check(&program, self.compiler.get_registry()));
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 
-        // Type check
-        self.typechecker.check_program(&program)?;
+    /// Parse a whole file, collecting every syntax error before giving up.
+    fn parse_file(&mut self, contents: &str) -> Result<Program, Vec<relic::Error>> {
+        let lexer = Lexer::new(contents.to_string());
+        let mut parser = Parser::new(lexer).map_err(|e| vec![e])?;
+        let (program, errors) = parser.parse_program_recovering();
+        self.warnings.extend_from_slice(parser.warnings());
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Define a program's declarations and run its script expressions.
+    /// Returns what each declaration and expression produced, in the order
+    /// they appear; a failure ends the list.
+    fn process_program(&mut self, program: &Program) -> Vec<relic::Result<Outcome>> {
+        provenance::from(self.origin.clone(), || self.run_program(program))
+    }
+
+    fn run_program(&mut self, program: &Program) -> Vec<relic::Result<Outcome>> {
+        let mut outcomes = Vec::new();
+        if let Err(e) = self.define_program(program) {
+            outcomes.push(Err(e));
+            return outcomes;
+        }
 
-        // Compile
-        self.compiler.compile_program(&program)?;
+        enum Item<'a> {
+            Declaration(&'a Declaration),
+            Data(&'a relic::ast::DataDeclaration),
+            Expression(&'a relic::ast::TopLevelExpression),
+        }
+        let declarations = program.declarations.iter().map(|decl| (decl.span().offset, Item::Declaration(decl)));
+        let data = program.data.iter().map(|data| (data.span.offset, Item::Data(data)));
+        let expressions = program.expressions.iter().map(|expr| (expr.span.offset, Item::Expression(expr)));
+        let mut items: Vec<_> = declarations.chain(data).chain(expressions).collect();
+        items.sort_by_key(|(offset, _)| *offset);
 
-        let mut result = String::new();
-        for decl in &program.declarations {
-            match decl {
-                relic::ast::Declaration::Value(v) => {
-                    result.push_str(&format!("Defined value type: {}\n", v.name));
+        // Script expressions run once everything they might call is defined
+        for (_, item) in items {
+            match item {
+                Item::Declaration(decl) => {
+                    let kind = match decl {
+                        relic::ast::Declaration::Value(_) => "value type",
+                        relic::ast::Declaration::Function(_) => "function",
+                        relic::ast::Declaration::Method(_) => "method",
+                    };
+                    outcomes.push(Ok(Outcome::Defined {
+                        kind,
+                        name: decl.name().to_string(),
+                    }));
                 }
-                relic::ast::Declaration::Function(f) => {
-                    result.push_str(&format!("Defined function: {}\n", f.name));
+                Item::Data(data) => outcomes.push(Ok(Outcome::Seeded {
+                    type_name: data.type_name.clone(),
+                    count: data.entries.len(),
+                })),
+                Item::Expression(expression) => {
+                    let describe = || formatter::format_expression(&expression.expression);
+                    let outcome =
+                        provenance::at(Some(expression.span.line), describe, || self.run_expression(expression));
+                    let failed = outcome.is_err();
+                    outcomes.push(outcome);
+                    if failed {
+                        break;
+                    }
                 }
-                relic::ast::Declaration::Method(m) => {
-                    result.push_str(&format!("Defined method: {}\n", m.name));
+            }
+        }
+        outcomes
+    }
+
+    /// Type check and compile a program's imports and declarations, and
+    /// type check its script expressions. A panic while doing so is
+    /// reported as an internal error, as it is at the prompt.
+    fn define_program(&mut self, program: &Program) -> relic::Result<()> {
+        catch_panic(|| {
+            // Imported modules come first, each after the modules it imports
+            let modules = self.compiler.load_imports(program)?;
+            for module in &modules {
+                self.typechecker
+                    .check_program(&module.program)
+                    .map_err(|e| module::in_module(&module.name, e))?;
+                self.compiler.compile_module(module)?;
+            }
+
+            // Type check
+            self.typechecker.check_program(program)?;
+
+            // Compile
+            self.compiler.compile_program(program)
+        })?;
+        self.warnings.extend(coverage::check(program, self.compiler.get_registry()));
+        self.session.imports.extend_from_slice(&program.imports);
+        self.session.declarations.extend_from_slice(&program.declarations);
+        self.session.tests.extend_from_slice(&program.tests);
+        Ok(())
+    }
+
+    /// A declaration or expression typed at the prompt.
+    fn process_input(&mut self, input: &str) -> Vec<relic::Result<Outcome>> {
+        // Determine if this is a declaration or expression
+        if ["value ", "fn ", "method ", "data ", "let "].iter().any(|keyword| input.starts_with(keyword)) {
+            return self.process_declaration(input);
+        }
+        // Try to parse as an expression first
+        let result = match self.process_expression(input) {
+            Ok(result) => Ok(result),
+            Err(_) => {
+                // If that fails and it looks like a construction, try that
+                if input.contains('(') && input.contains(')') {
+                    self.process_construction(input)
+                } else {
+                    Err(relic::Error::Parser(relic::error::ParserError {
+                        message: "Invalid syntax. Type ':help' for commands.".to_string(),
+                        line: 1,
+                        column: 1,
+                        length: 0,
+                    }))
                 }
             }
+        };
+        vec![result]
+    }
+
+    fn process_expression(&mut self, input: &str) -> relic::Result<Outcome> {
+        let expr = self.parse_expression(input)?;
+        self.evaluate(&expr)
+    }
+
+    /// Evaluate a script expression, binding its value if it's a `let`.
+    fn run_expression(&mut self, expression: &relic::ast::TopLevelExpression) -> relic::Result<Outcome> {
+        let outcome = self.evaluate(&expression.expression).map_err(|e| e.with_span(expression.span))?;
+        match (&expression.binding, outcome) {
+            (Some(name), Outcome::Value { value, ty }) => {
+                self.typechecker.declare_binding(name, ty.clone());
+                self.compiler.bind(name, value);
+                Ok(Outcome::Bound { name: name.clone(), ty })
+            }
+            (_, outcome) => Ok(outcome),
         }
+    }
+
+    /// Type check and evaluate an expression. A panic while doing so is
+    /// reported as an internal error, so `relic run` and `relic -e` fail
+    /// the way the prompt does rather than aborting.
+    fn evaluate(&mut self, expr: &relic::ast::Expression) -> relic::Result<Outcome> {
+        catch_panic(|| {
+            let expr_type = self.typechecker.check_script_expression(expr)?;
+            let result = self.compiler.evaluate_expression(expr)?;
+            Ok(Outcome::Value {
+                value: result,
+                ty: expr_type,
+            })
+        })
+    }
+
+    /// `:time expr`: evaluate an expression, measuring how long it takes
+    /// and counting the work it does.
+    fn time(&mut self, input: &str) -> relic::Result<Outcome> {
+        let expr = self.parse_expression(input)?;
+        let ty = self.typechecker.check_script_expression(&expr)?;
+        let before = self.compiler.get_registry().profile();
+        let start = Instant::now();
+        let value = self.compiler.evaluate_expression(&expr)?;
+        let elapsed = start.elapsed();
+        Ok(Outcome::Timed {
+            value,
+            ty,
+            elapsed,
+            profile: self.compiler.get_registry().profile() - before,
+        })
+    }
 
-        Ok(result)
+    /// `:type expr`: the type of an expression, without evaluating it.
+    fn type_of(&mut self, input: &str) -> relic::Result<Outcome> {
+        let expr = self.parse_expression(input)?;
+        let expr_type = self.typechecker.check_script_expression(&expr)?;
+        Ok(Outcome::Type {
+            expression: input.to_string(),
+            ty: expr_type,
+        })
     }
 
-    fn process_expression(&self, input: &str) -> relic::Result<String> {
+    /// `:dispatch f(args)`: how the call would be dispatched, without
+    /// making it.
+    fn dispatch(&mut self, input: &str) -> relic::Result<String> {
+        let call = self.parse_expression(input)?;
+        let explanation = explain::explain_call(&call, &mut self.typechecker, &self.compiler)?;
+        Ok(explanation.to_string())
+    }
+
+    /// `:inspect expr`: an expression's value, with its type, fields and
+    /// place in its type's instance pool.
+    fn inspect(&mut self, input: &str) -> relic::Result<String> {
+        let expr = self.parse_expression(input)?;
+        self.typechecker.check_script_expression(&expr)?;
+        let value = self.compiler.evaluate_expression(&expr)?;
+        Ok(inspect::inspect(&value, self.compiler.get_registry()).to_string())
+    }
+
+    /// `:ast input`: the syntax tree of declarations or expressions, as an
+    /// indented outline, without defining or evaluating anything.
+    fn ast(&self, input: &str) -> relic::Result<String> {
+        let program = Parser::new(Lexer::new(input.to_string()))?.parse_program()?;
+        Ok(ast_json::program_to_outline(&program).trim_end().to_string())
+    }
+
+    /// `:tokens input`: the tokens the lexer makes of `input`, comments
+    /// included, each with the line and column it starts at and its class.
+    fn tokens(&self, input: &str) -> relic::Result<String> {
+        // A character the lexer rejects is reported, as it would be when
+        // parsing, rather than skipped
+        let mut lexer = Lexer::new(input.to_string());
+        while lexer.next_token()? != Token::Eof {}
+        let lines: Vec<_> = lexer::tokenize(input)
+            .into_iter()
+            .map(|(token, span, category)| {
+                let position = format!("{}:{}", span.line, span.column);
+                format!("{:<7} {:<11} {}", position, format!("{:?}", category).to_lowercase(), token)
+            })
+            .collect();
+        if lines.is_empty() {
+            return Ok("No tokens".to_string());
+        }
+        Ok(lines.join("\n"))
+    }
+
+    /// Run a `:command`, or return None if there is no such command.
+    fn run_command(&mut self, command: &str, argument: &str) -> Option<relic::Result<Outcome>> {
+        let message = match command {
+            ":type" | ":t" => return Some(self.type_of(argument)),
+            ":time" => return Some(self.time(argument)),
+            ":save" => Ok(self.save(argument)),
+            ":env" => Ok(self.environment()),
+            ":instances" => self.instances(argument),
+            ":stats" => Ok(self.stats()),
+            ":dispatch" => self.dispatch(argument),
+            ":inspect" => self.inspect(argument),
+            ":ast" => self.ast(argument),
+            ":tokens" => self.tokens(argument),
+            ":reset" => {
+                let registry = self.compiler.get_registry();
+                let environment = registry.script_environment().clone();
+                let records_provenance = registry.records_provenance();
+                *self = Self::new();
+                let compiler = Compiler::new().with_script_environment(environment);
+                self.compiler = if records_provenance { compiler.with_provenance() } else { compiler };
+                Ok("Cleared every definition".to_string())
+            }
+            ":forget" => self.forget(argument),
+            ":migrate" => self.migrate(argument),
+            ":reload" => self.reload(argument),
+            _ => return None,
+        };
+        Some(message.map(Outcome::Message))
+    }
+
+    /// `:forget name`: remove a value type, with its instances, or every
+    /// overload of a function, so that it can be defined again.
+    fn forget(&mut self, name: &str) -> relic::Result<String> {
+        if name.is_empty() {
+            return Err(relic::Error::Type(relic::error::TypeError {
+                message: "Usage: :forget name".to_string(),
+                span: None,
+            }));
+        }
+        let checked = self.typechecker.forget(name);
+        let compiled = self.compiler.forget(name);
+        if !(checked || compiled) {
+            return Err(relic::Error::Type(relic::error::TypeError {
+                message: format!("Nothing named '{}' is defined", name),
+                span: None,
+            }));
+        }
+        self.session.declarations.retain(|declaration| declaration.name() != name);
+        Ok(format!("Forgot {}", name))
+    }
+
+    /// `:migrate value Type(...) { ... } [using function]`: replace a value
+    /// type's declaration, constructing its instances again under the new
+    /// one, after passing each parameter through `function` if given.
+    fn migrate(&mut self, argument: &str) -> relic::Result<String> {
+        let usage = || {
+            relic::Error::Type(relic::error::TypeError {
+                message: "Usage: :migrate value Type(...) { ... } [using function]".to_string(),
+                span: None,
+            })
+        };
+        let (source, mapping) = match argument.rfind('}') {
+            Some(end) => match argument[end + 1..].trim() {
+                "" => (&argument[..=end], None),
+                rest => match rest.strip_prefix("using ") {
+                    Some(function) => (&argument[..=end], Some(function.trim())),
+                    None => return Err(usage()),
+                },
+            },
+            None => return Err(usage()),
+        };
+        let lexer = Lexer::new(source.to_string());
+        let program = Parser::new(lexer)?.parse_program()?;
+        let declaration = match program.declarations.as_slice() {
+            [Declaration::Value(declaration)] => declaration,
+            _ => return Err(usage()),
+        };
+        let migration = migrate::migrate(&mut self.typechecker, &mut self.compiler, declaration, mapping)?;
+        for existing in &mut self.session.declarations {
+            if matches!(existing, Declaration::Value(old) if old.name == declaration.name) {
+                *existing = Declaration::Value(declaration.clone());
+            }
+        }
+        Ok(migration.to_string())
+    }
+
+    /// `:reload declarations`: replace functions and value types without
+    /// changing the types callers rely on.
+    fn reload(&mut self, source: &str) -> relic::Result<String> {
+        let lexer = Lexer::new(source.to_string());
+        let program = Parser::new(lexer)?.parse_program()?;
+        let reload = reload::reload(&mut self.typechecker, &mut self.compiler, &program)?;
+        self.session.declarations.retain(|existing| !reload.redefined.iter().any(|name| name == existing.name()));
+        self.session.declarations.extend(program.declarations);
+        Ok(reload.to_string())
+    }
+
+    /// `:env`: the value types and functions defined so far.
+    fn environment(&self) -> String {
+        let registry = self.compiler.get_registry();
+        let mut values: Vec<_> = registry.value_types().collect();
+        values.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut functions: Vec<_> = registry.functions().collect();
+        // Stable, so overloads stay in the order they were defined
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut out = String::new();
+        if !values.is_empty() {
+            out.push_str("Value types:\n");
+        }
+        for value in values {
+            let unit = value.parameter.unit.as_ref().map_or(String::new(), |unit| format!(" unit {:?}", unit));
+            out.push_str(&format!(
+                "  {}({}: {}{})\n",
+                value.name,
+                value.parameter.name,
+                formatter::format_type(&value.parameter.ty),
+                unit
+            ));
+            if let Some(validate) = &value.body.validate {
+                out.push_str(&format!("    validate: {}\n", formatter::format_expression(validate)));
+            }
+            if let Some(normalize) = &value.body.normalize {
+                out.push_str(&format!("    normalize: {}\n", formatter::format_expression(normalize)));
+            }
+            if let Some(unique) = value.body.unique {
+                out.push_str(&format!("    unique: {}\n", unique));
+            }
+            if let Some(display) = &value.body.display {
+                out.push_str(&format!("    display: {:?}\n", display));
+            }
+            if let Some(sensitive) = value.body.sensitive {
+                out.push_str(&format!("    sensitive: {}\n", sensitive));
+            }
+            if let Some(coerce) = value.body.coerce {
+                out.push_str(&format!("    coerce: {}\n", coerce));
+            }
+            if !value.body.convert.is_empty() {
+                out.push_str(&format!("    convert: {}\n", value.body.convert.join(", ")));
+            }
+        }
+        if !functions.is_empty() {
+            out.push_str("Functions:\n");
+        }
+        for function in functions {
+            let parameters: Vec<_> = function
+                .parameters
+                .iter()
+                .map(|p| {
+                    let guard = p.guard.as_ref().map_or(String::new(), |guard| {
+                        format!(" where {}", formatter::format_expression(guard))
+                    });
+                    format!("{}: {}{}", p.name, formatter::format_type(&p.ty), guard)
+                })
+                .collect();
+            out.push_str(&format!(
+                "  {}({}) -> {}\n",
+                function.name,
+                parameters.join(", "),
+                formatter::format_type(&function.return_type)
+            ));
+        }
+        let mut bindings: Vec<_> = self.typechecker.get_environment().bindings().iter().collect();
+        bindings.sort_by_key(|(name, _)| name.as_str());
+        if !bindings.is_empty() {
+            out.push_str("Bindings:\n");
+        }
+        for (name, ty) in bindings {
+            out.push_str(&format!("  {} : {}\n", name, formatter::format_type(ty)));
+        }
+
+        if out.is_empty() {
+            "Nothing is defined yet".to_string()
+        } else {
+            out.trim_end().to_string()
+        }
+    }
+
+    /// `:instances [Type]`: how many instances of each value type (or just
+    /// of `Type`) have been constructed, with the most recent few.
+    fn instances(&self, type_name: &str) -> relic::Result<String> {
+        const SAMPLE: usize = 5;
+
+        let registry = self.compiler.get_registry();
+        let mut names: Vec<_> = registry.value_types().map(|value| value.name.as_str()).collect();
+        names.sort();
+        if !type_name.is_empty() {
+            if !names.contains(&type_name) {
+                return Err(relic::Error::Type(relic::error::TypeError {
+                    message: format!("Unknown value type: {}", type_name),
+                    span: None,
+                }));
+            }
+            names = vec![type_name];
+        }
+
+        let mut out = String::new();
+        for name in names {
+            let count = registry.count_instances(name);
+            let noun = if count == 1 { "instance" } else { "instances" };
+            out.push_str(&format!("{}: {} {}", name, count, noun));
+            if count > SAMPLE {
+                out.push_str(&format!(", the last {}", SAMPLE));
+            }
+            out.push('\n');
+            for instance in registry.recent_instances(name, SAMPLE) {
+                out.push_str(&format!("  {}\n", instance));
+            }
+        }
+
+        if out.is_empty() {
+            Ok("No value types are defined yet".to_string())
+        } else {
+            Ok(out.trim_end().to_string())
+        }
+    }
+
+    /// `:stats`: how many instances of each value type there are and
+    /// roughly how much memory they take, the strings they share, and the
+    /// sizes of the function tables and the dispatch tables compiled.
+    fn stats(&self) -> String {
+        let stats = self.compiler.get_registry().stats();
+        let mut out = format!(
+            "{}, about {}\n",
+            count(stats.instances(), "instance"),
+            bytes(stats.approximate_bytes())
+        );
+        let width = stats.types.iter().map(|t| t.name.len()).max().unwrap_or(0);
+        for t in &stats.types {
+            out.push_str(&format!(
+                "  {:<width$}  {:>8}  {}\n",
+                t.name,
+                t.instances,
+                bytes(t.approximate_bytes)
+            ));
+        }
+        if stats.interned_strings > 0 {
+            out.push_str(&format!(
+                "  {} shared, {}\n",
+                count(stats.interned_strings, "distinct string"),
+                bytes(stats.interned_bytes)
+            ));
+        }
+        out.push_str(&format!(
+            "{}, {} ({} native); {} compiled",
+            count(stats.types.len(), "value type"),
+            count(stats.function_overloads, "function overload"),
+            stats.native_functions,
+            count(stats.dispatch_tables, "dispatch table")
+        ));
+        out
+    }
+
+    /// `:save file`: write every import and declaration entered so far.
+    fn save(&self, filename: &str) -> String {
+        // Each input's spans start at 0; number the items so the formatter
+        // keeps them in the order they were entered
+        let mut session = self.session.clone();
+        for (i, import) in session.imports.iter_mut().enumerate() {
+            import.span.offset = i;
+        }
+        let imports = session.imports.len();
+        for (i, declaration) in session.declarations.iter_mut().enumerate() {
+            declaration.span_mut().offset = imports + i;
+        }
+
+        match fs::write(filename, formatter::format_program(&session)) {
+            Ok(()) => {
+                let count = session.declarations.len();
+                let noun = if count == 1 { "declaration" } else { "declarations" };
+                format!("Saved {} {} to {}", count, noun, filename)
+            }
+            Err(e) => format!("Error writing file '{}': {}", filename, e),
+        }
+    }
+
+    /// `:load file`: run a file in this session, one declaration at a time
+    /// so that each is reported and a failure doesn't stop the rest.
+    /// Returns the file's contents, to report errors against, and the result
+    /// of each declaration followed by that of the file's expressions.
+    fn load(&mut self, filename: &str) -> Result<(String, Vec<relic::Result<Outcome>>), String> {
+        let contents = fs::read_to_string(filename)
+            .map_err(|e| format!("Error reading file '{}': {}", filename, e))?;
+        let program = match self.parse_file(&contents) {
+            Ok(program) => program,
+            Err(errors) => return Ok((contents, errors.into_iter().map(Err).collect())),
+        };
+
+        // What the file constructs came from the file
+        let origin = std::mem::replace(&mut self.origin, filename.to_string());
+        let mut results = Vec::new();
+        let imports = Program {
+            imports: program.imports.clone(),
+            declarations: Vec::new(),
+            expressions: Vec::new(),
+            tests: Vec::new(),
+            data: Vec::new(),
+        };
+        results.extend(self.process_program(&imports));
+        for declaration in &program.declarations {
+            let single = Program {
+                imports: Vec::new(),
+                declarations: vec![declaration.clone()],
+                expressions: Vec::new(),
+                tests: Vec::new(),
+                data: Vec::new(),
+            };
+            results.extend(self.process_program(&single));
+        }
+        for data in &program.data {
+            let single = Program {
+                imports: Vec::new(),
+                declarations: Vec::new(),
+                expressions: Vec::new(),
+                tests: Vec::new(),
+                data: vec![data.clone()],
+            };
+            results.extend(self.process_program(&single));
+        }
+        if !program.expressions.is_empty() {
+            let expressions = Program {
+                imports: Vec::new(),
+                declarations: Vec::new(),
+                expressions: program.expressions.clone(),
+                tests: Vec::new(),
+                data: Vec::new(),
+            };
+            results.extend(self.process_program(&expressions));
+        }
+        self.origin = origin;
+
+        Ok((contents, results))
+    }
+
+    /// Parse `input` as a single expression, with nothing after it.
+    fn parse_expression(&mut self, input: &str) -> relic::Result<relic::ast::Expression> {
         // Parse the expression
         let lexer = Lexer::new(input.to_string());
         let mut parser = Parser::new(lexer)?;
         
         // Try to parse as a single expression
         let expr = parser.parse_expression()?;
+        // The REPL may retry the same input, so keep only this attempt's warnings
+        self.warnings = parser.warnings().to_vec();
         
         // Ensure we consumed all tokens
         if parser.current_token != Token::Eof {
+            let span = parser.current_span();
             return Err(relic::Error::Parser(relic::error::ParserError {
                 message: format!("Unexpected token after expression: {:?}", parser.current_token),
-                line: 1,
-                column: 1,
+                line: span.line,
+                column: span.column,
+                length: span.length,
             }));
         }
-        
-        // Type check the expression
-        let expr_type = self.typechecker.check_expression(&expr)?;
-        
-        // Evaluate the expression
-        let result = self.compiler.evaluate_expression(&expr)?;
-        
-        Ok(format!("→ {} : {:?}", result, expr_type))
+
+        Ok(expr)
     }
 
-    fn process_construction(&self, input: &str) -> relic::Result<String> {
+    fn process_construction(&mut self, input: &str) -> relic::Result<Outcome> {
         // Simple pattern matching for value construction or function calls
         // Format: TypeName(value) or functionName(args)
         if let Some(paren_pos) = input.find('(') {
@@ -82,9 +927,8 @@ impl Repl {
                 let name = &input[..paren_pos];
                 let args_str = &input[paren_pos + 1..input.len() - 1];
 
-                // Check if it's a function or method call - use the expression evaluator instead
-                if self.compiler.get_registry().get_function(name).is_some() 
-                    || self.compiler.get_registry().get_methods(name).is_some() {
+                // Check if it's a function call - use the expression evaluator instead
+                if self.compiler.get_registry().get_function(name).is_some() {
                     return self.process_expression(input);
                 }
 
@@ -99,15 +943,17 @@ impl Repl {
                         Box::new(value_str.to_string())
                     };
 
-                match self.compiler.get_registry().construct(name, value) {
-                    Ok(_) => Ok(format!("✓ Created {} successfully", name)),
-                    Err(e) => Ok(format!("✗ Failed to create {}: {}", name, e)),
-                }
+                let message = match self.compiler.get_registry().construct(name, value) {
+                    Ok(_) => format!("✓ Created {} successfully", name),
+                    Err(e) => format!("✗ Failed to create {}: {}", name, e),
+                };
+                Ok(Outcome::Message(message))
             } else {
                 Err(relic::Error::Parser(relic::error::ParserError {
                     message: "Invalid construction syntax".to_string(),
                     line: 1,
                     column: 1,
+                    length: 0,
                 }))
             }
         } else {
@@ -115,105 +961,1521 @@ impl Repl {
                 message: "Expected value construction: TypeName(value)".to_string(),
                 line: 1,
                 column: 1,
+                length: 0,
             }))
         }
     }
 }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() > 1 {
-        // File mode
-        let filename = &args[1];
-        match fs::read_to_string(filename) {
-            Ok(contents) => {
-                let mut repl = Repl::new();
-                println!("Processing file: {}", filename);
-                
-                // Process the entire file as a program
-                match repl.process_declaration(&contents) {
-                    Ok(output) => {
-                        println!("{}", output);
-                        println!("\nFile processed successfully.");
+/// What the REPL did with an input, to print and to record.
+enum Ran {
+    /// Results, with the source their errors are located in and the file
+    /// it came from
+    Results {
+        source: String,
+        origin: Option<String>,
+        results: Vec<relic::Result<Outcome>>,
+    },
+    /// A problem with the input itself, such as an unknown command
+    Failed(String),
+}
+
+impl Ran {
+    /// What a replay log records of it.
+    fn replay_results(&self) -> Vec<String> {
+        match self {
+            Ran::Results { results, .. } => results
+                .iter()
+                .map(|result| match result {
+                    Ok(outcome) => outcome.replay_text(),
+                    Err(e) => format!("error: {}", e),
+                })
+                .collect(),
+            Ran::Failed(message) => vec![message.clone()],
+        }
+    }
+}
+
+/// Run an input typed at the prompt, other than `:exit`, `:help` and
+/// `:format`, which the prompt handles itself.
+fn run_input(repl: &mut Repl, input: &str) -> Ran {
+    let site = Provenance {
+        origin: repl.origin.clone(),
+        line: None,
+        expression: input.to_string(),
+    };
+    provenance::within(site, || run_attributed_input(repl, input))
+}
+
+fn run_attributed_input(repl: &mut Repl, input: &str) -> Ran {
+    if !input.starts_with(':') {
+        // A bug in Relic fails this input, not the session
+        let results = catch_panic(|| Ok(repl.process_input(input))).unwrap_or_else(|e| vec![Err(e)]);
+        return Ran::Results {
+            source: input.to_string(),
+            origin: None,
+            results,
+        };
+    }
+    let (command, argument) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    // Errors are located in the argument, so render them against it
+    let argument = argument.trim();
+    if command == ":load" {
+        return match repl.load(argument) {
+            Ok((contents, results)) => Ran::Results {
+                source: contents,
+                origin: Some(argument.to_string()),
+                results,
+            },
+            Err(message) => Ran::Failed(message),
+        };
+    }
+    // A bug in Relic fails the command, not the session
+    match catch_panic(|| Ok(repl.run_command(command, argument))).transpose() {
+        Some(result) => Ran::Results {
+            source: argument.to_string(),
+            origin: None,
+            results: vec![result.and_then(|r| r)],
+        },
+        None => Ran::Failed(format!("Unknown command '{}'. Type ':help' for commands.", command)),
+    }
+}
+
+/// Prints what REPL inputs produce. As text, results go to stdout and
+/// diagnostics to stderr in the `--diagnostics` format. As JSON, everything
+/// goes to stdout, one object per line with a `kind`, so that a program
+/// driving the REPL reads it all, in order, from one place.
+struct Printer {
+    output: Format,
+    diagnostics: Format,
+    renderer: Renderer,
+    /// Whether lists of values print as tables, rather than on one line
+    tables: bool,
+}
+
+impl Printer {
+    /// Print the results of an input, after any warnings it produced.
+    /// Returns the exit status for the first error among them, or 0.
+    fn outcomes(
+        &self,
+        warnings: Vec<Diagnostic>,
+        source: &str,
+        origin: Option<&str>,
+        results: Vec<relic::Result<Outcome>>,
+    ) -> i32 {
+        for warning in warnings {
+            self.diagnostic(&warning, source, origin);
+        }
+        let mut status = 0;
+        for result in results {
+            match result {
+                Ok(outcome) => match self.output {
+                    Format::Human => println!("{}", self.human(&outcome)),
+                    Format::Json => println!("{}", outcome.to_json()),
+                },
+                Err(e) => {
+                    self.diagnostic(&Diagnostic::from_error(&e), source, origin);
+                    if status == 0 {
+                        status = exit_code(&e);
+                    }
+                }
+            }
+        }
+        status
+    }
+
+    /// An outcome as text: a list of values as a table, when tables are on
+    /// and it has more than one row, a relation as a table when tables are
+    /// on, and anything else as it displays.
+    fn human(&self, outcome: &Outcome) -> String {
+        match outcome {
+            Outcome::Value { value: EvalValue::List(items), ty } if self.tables && items.len() > 1 => {
+                if let Some(table) = table::render(items, TABLE_ROWS) {
+                    return format!("→ {}\n{}", ty, table);
+                }
+            }
+            Outcome::Value { value: EvalValue::Relation(relation), ty } if self.tables => {
+                return format!("→ {}\n{}", ty, table::render_relation(relation, TABLE_ROWS));
+            }
+            _ => {}
+        }
+        outcome.to_string()
+    }
+
+    /// Print errors that stopped a file from being parsed or checked.
+    fn errors(&self, warnings: Vec<Diagnostic>, source: &str, origin: Option<&str>, errors: Vec<relic::Error>) -> i32 {
+        self.outcomes(warnings, source, origin, errors.into_iter().map(Err).collect())
+    }
+
+    fn diagnostic(&self, diagnostic: &Diagnostic, source: &str, origin: Option<&str>) {
+        match (self.output, self.diagnostics) {
+            (Format::Json, _) => println!(
+                "{{\"kind\":\"diagnostic\",\"diagnostic\":{}}}",
+                diagnostic.to_json(origin)
+            ),
+            (Format::Human, Format::Human) => eprint!("{}", self.renderer.render(diagnostic, source, origin)),
+            (Format::Human, Format::Json) => eprintln!("{}", diagnostic.to_json(origin)),
+        }
+    }
+
+    /// Something to tell the user that isn't the result of an input.
+    fn message(&self, text: &str) {
+        match self.output {
+            Format::Human => println!("{}", text),
+            Format::Json => println!("{}", Outcome::Message(text.to_string()).to_json()),
+        }
+    }
+
+    /// A problem with the input that isn't a diagnostic, such as an
+    /// unknown command.
+    fn error(&self, message: &str) {
+        match self.output {
+            Format::Human => eprintln!("{}", message),
+            Format::Json => println!("{{\"kind\":\"error\",\"message\":{}}}", json_string(message)),
+        }
+    }
+}
+
+/// The most rows of a list printed as a table.
+const TABLE_ROWS: usize = 20;
+
+/// `relic --help`
+const USAGE: &str = "\
+Usage: relic [COMMAND] [OPTIONS] [FILE...]
+
+Commands:
+  run FILE [ARG...]
+                   Run a file's declarations and expressions (the default given a FILE);
+                   the script reads ARGs with args() and variables with env(\"NAME\").
+                   Given a directory, runs every .relic file in it as one program
+  repl             Start an interactive session (the default without one)
+  check FILE...    Parse and type check files without running them
+  build FILE...    Compile files together, as one program, in the order they import each other
+  fmt FILE...      Format files in place
+  ast FILE         Print a file's syntax tree
+  test FILE...     Run the test declarations in files, each starting from the data blocks' instances
+  doc FILE         Print Markdown documentation for a file's value types and functions
+  schema FILE      Print a JSON Schema document for a file's value types, or with --proto,
+                   Protocol Buffers definitions
+  bench FILE       Time each stage of running a file
+  replay FILE      Run the inputs of a replay log again and show the results that differ
+  help             Show this help
+
+A FILE of `-` reads standard input.
+
+  relic -e SOURCE [FILE [ARG...]]
+                   Run SOURCE, after defining FILE's declarations, and print the value of
+                   each expression in it; exits 1 if the last one is false
+
+Options:
+  -e, --eval SOURCE       Run SOURCE instead of a file, as above
+  --json                  Print results, syntax trees and diagnostics as JSON, one object per line
+  --diagnostics=FORMAT    Print diagnostics as `human` text (the default) or `json`
+  --check                 With fmt, list the files that would change instead of changing them
+  --iterations=N          With bench, how many times to run the file (default 10)
+  --html                  With doc, print an HTML page instead of Markdown
+  --proto                 With schema, print a .proto file, noting what it can't express
+  --record=FILE           With repl, write each input and its results to FILE, for relic replay
+  --watch                 With run, run the file again whenever it or a module it imports changes
+  --keep-instances        With --watch, start each run with the instances constructed by the last
+  -h, --help              Show this help
+  -V, --version           Show the version
+
+Exit status: 0 on success; 1 when a file can't be read or written, fmt --check
+finds files to format, or a test fails; 2 for a bad command line; 3 for syntax errors; 4 for type
+errors; 5 for import errors; 6 for failed validation; 70 for a bug in Relic itself.
+";
+
+/// Exit status for a command line that can't be understood.
+const EXIT_USAGE: i32 = 2;
+
+/// Exit status for a file that failed with `error`, so that scripts and CI
+/// can tell what kind of problem it has.
+fn exit_code(error: &relic::Error) -> i32 {
+    match error {
+        relic::Error::Lexer(_) | relic::Error::Parser(_) => 3,
+        relic::Error::Type(_) => 4,
+        relic::Error::Module(_) => 5,
+        relic::Error::Validation(_) => 6,
+        relic::Error::Internal(_) => 70,
+        // Only a host cancels evaluations; as if interrupted
+        relic::Error::Cancelled => 130,
+    }
+}
+
+/// Flags given on the command line, and the files they apply to.
+struct Options {
+    /// How results are printed: `--json` for one JSON object per line
+    output: Format,
+    diagnostics: Format,
+    check: bool,
+    iterations: usize,
+    html: bool,
+    proto: bool,
+    /// Where the REPL writes a replay log, from `--record=FILE`
+    record: Option<String>,
+    /// Source given with `-e`, to run instead of a file
+    eval: Option<String>,
+    watch: bool,
+    keep_instances: bool,
+    help: bool,
+    version: bool,
+    files: Vec<String>,
+    /// What follows the file for `run`, passed to the script
+    arguments: Vec<String>,
+}
+
+impl Options {
+    /// With `script`, the first file is a script to run, and everything after
+    /// it is the script's arguments rather than options.
+    fn parse(args: impl Iterator<Item = String>, script: bool) -> Result<Self, String> {
+        let mut args = args.peekable();
+        let mut options = Options {
+            output: Format::Human,
+            diagnostics: Format::Human,
+            check: false,
+            iterations: 10,
+            html: false,
+            proto: false,
+            record: None,
+            eval: None,
+            watch: false,
+            keep_instances: false,
+            help: false,
+            version: false,
+            files: Vec::new(),
+            arguments: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                // Everything after `--` is a file, or for a script, an argument
+                "--" => {
+                    options.files.extend(args.by_ref());
+                    break;
+                }
+                "--json" => options.output = Format::Json,
+                "--check" => options.check = true,
+                "--html" => options.html = true,
+                "--proto" => options.proto = true,
+                "-e" | "--eval" => {
+                    options.eval = Some(args.next().ok_or_else(|| format!("{} needs SOURCE to run", arg))?);
+                }
+                "--watch" => options.watch = true,
+                "--keep-instances" => options.keep_instances = true,
+                "-h" | "--help" => options.help = true,
+                "-V" | "--version" => options.version = true,
+                _ if arg.starts_with("--diagnostics=") => {
+                    let name = &arg["--diagnostics=".len()..];
+                    options.diagnostics = Format::parse(name).ok_or_else(|| {
+                        format!("Unknown diagnostics format '{}' (expected 'human' or 'json')", name)
+                    })?;
+                }
+                _ if arg.starts_with("--record=") => {
+                    let file = &arg["--record=".len()..];
+                    if file.is_empty() {
+                        return Err("--record needs a FILE to write the replay log to".to_string());
                     }
-                    Err(e) => {
-                        eprintln!("Error: {}", e);
-                        std::process::exit(1);
+                    options.record = Some(file.to_string());
+                }
+                _ if arg.starts_with("--iterations=") => {
+                    let count = &arg["--iterations=".len()..];
+                    options.iterations = match count.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("--iterations needs a positive number, found '{}'", count)),
+                    };
+                }
+                // A lone `-` is standard input
+                _ if arg.starts_with('-') && arg != "-" => return Err(format!("Unknown option '{}'", arg)),
+                _ => {
+                    options.files.push(arg);
+                    if script {
+                        break;
                     }
                 }
             }
-            Err(e) => {
-                eprintln!("Error reading file '{}': {}", filename, e);
-                std::process::exit(1);
+        }
+        options.arguments.extend(args);
+        if script && options.files.len() > 1 {
+            options.arguments = options.files.split_off(1).into_iter().chain(options.arguments).collect();
+        }
+        Ok(options)
+    }
+
+    fn printer(&self) -> Printer {
+        Printer {
+            output: self.output,
+            diagnostics: self.diagnostics,
+            renderer: Renderer::from_env(),
+            tables: true,
+        }
+    }
+}
+
+/// Read a FILE argument; `-` is standard input.
+fn read_source(filename: &str) -> Result<String, i32> {
+    let contents = if filename == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(filename)
+    };
+    contents.map_err(|e| {
+        eprintln!("Error reading '{}': {}", origin(filename), e);
+        1
+    })
+}
+
+/// How a FILE argument is named in messages.
+fn origin(filename: &str) -> &str {
+    if filename == "-" {
+        "<stdin>"
+    } else {
+        filename
+    }
+}
+
+/// Where REPL history is kept between sessions: `$RELIC_HISTORY`, or
+/// `.relic_history` in the home directory. `var` looks up an environment
+/// variable, as `env::var_os` does.
+fn history_path(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    if let Some(path) = var("RELIC_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    var("HOME").map(|home| Path::new(&home).join(".relic_history"))
+}
+
+/// `relic run FILE`: define a file's declarations and run its expressions,
+/// printing each result as the REPL would.
+fn run_file(filename: &str, options: &Options) -> i32 {
+    if Path::new(filename).is_dir() {
+        return run_project(filename, options);
+    }
+    if options.watch {
+        return watch_file(filename, options);
+    }
+    run_once(filename, options, None).0
+}
+
+/// Run a file as `run_file` does, starting with the instances `previous`
+/// constructed. Returns the exit status, and the session the file ran in
+/// unless it couldn't be read.
+fn run_once(filename: &str, options: &Options, previous: Option<&Repl>) -> (i32, Option<Repl>) {
+    let contents = match read_source(filename) {
+        Ok(contents) => contents,
+        Err(status) => return (status, None),
+    };
+    let environment = ScriptEnvironment::from_process(options.arguments.clone());
+    let mut repl = Repl::for_file(filename, environment);
+    if let Some(previous) = previous {
+        repl = repl.keep_instances(previous);
+    }
+    let printer = options.printer();
+    if options.output == Format::Human {
+        println!("Processing file: {}", origin(filename));
+    }
+
+    let results = match repl.process_file(&contents) {
+        Ok(results) => results,
+        Err(errors) => errors.into_iter().map(Err).collect(),
+    };
+    let status = printer.outcomes(repl.take_warnings(), &contents, Some(origin(filename)), results);
+    if status == 0 && options.output == Format::Human {
+        println!("\nFile processed successfully.");
+    }
+    (status, Some(repl))
+}
+
+/// `relic -e SOURCE [FILE]`: define FILE's declarations, without running
+/// its expressions, then run SOURCE and print just the value of each of its
+/// expressions, for use in shell pipelines. The exit status is that of the
+/// first error, such as 6 for failed validation, or 1 if the last value is
+/// `false`.
+fn eval_source(source: &str, options: &Options) -> i32 {
+    let printer = options.printer();
+    let file = options.files.first().map(String::as_str);
+    let environment = ScriptEnvironment::from_process(options.arguments.clone());
+    let mut repl = Repl::for_file(file.unwrap_or("-"), environment);
+
+    if let Some(filename) = file {
+        let contents = match read_source(filename) {
+            Ok(contents) => contents,
+            Err(status) => return status,
+        };
+        let defined = repl.parse_file(&contents).and_then(|program| {
+            repl.define_program(&Program {
+                expressions: Vec::new(),
+                ..program
+            })
+            .map_err(|e| vec![e])
+        });
+        if let Err(errors) = defined {
+            return printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), errors);
+        }
+    }
+
+    let results = match repl.parse_file(source) {
+        Ok(program) => repl.process_program(&program),
+        Err(errors) => errors.into_iter().map(Err).collect(),
+    };
+    let last_false = matches!(
+        results.last(),
+        Some(Ok(Outcome::Value { value: EvalValue::Boolean(false), .. }))
+    );
+    let mut status = printer.outcomes(repl.take_warnings(), source, Some("-e"), Vec::new());
+    for result in results {
+        match result {
+            Ok(Outcome::Defined { .. }) => {}
+            Ok(Outcome::Value { value, .. }) if options.output == Format::Human => println!("{}", value),
+            result => {
+                let code = printer.outcomes(Vec::new(), source, Some("-e"), vec![result]);
+                status = if status == 0 { code } else { status };
+            }
+        }
+    }
+    match status {
+        0 if last_false => 1,
+        status => status,
+    }
+}
+
+/// How often `run --watch` looks for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// `relic run --watch FILE`: run a file, then run it again from scratch
+/// each time it or a module it imports changes, until interrupted. With
+/// `--keep-instances`, each run starts with the instances of the last.
+fn watch_file(filename: &str, options: &Options) -> i32 {
+    if filename == "-" {
+        eprintln!("relic run --watch needs a FILE to watch, not standard input");
+        return EXIT_USAGE;
+    }
+    let printer = options.printer();
+    let mut previous: Option<Repl> = None;
+    let mut watched = vec![PathBuf::from(filename)];
+    loop {
+        let (_, repl) = run_once(filename, options, previous.as_ref());
+        // A file that couldn't be read may be halfway through being saved;
+        // keep watching what the last run imported
+        if let Some(repl) = repl {
+            watched = std::iter::once(filename)
+                .chain(repl.compiler.modules())
+                .map(PathBuf::from)
+                .collect();
+            if options.keep_instances {
+                previous = Some(repl);
+            }
+        }
+        printer.message(&format!("\nWatching {} for changes (Ctrl-C to stop)", count(watched.len(), "file")));
+
+        let last_modified = modified_times(&watched);
+        while modified_times(&watched) == last_modified {
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+        printer.message(&format!("\n--- Running {} again ---\n", filename));
+    }
+}
+
+/// When each of `files` was last modified, or None for a file that can't
+/// be read.
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+}
+
+/// `relic check FILE...`: parse and type check each file, reporting every
+/// problem found, without compiling or running anything. The exit status is
+/// that of the first error.
+fn check_files(args: &[String], options: &Options) -> i32 {
+    let files = match expand_files(args) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("{}", message);
+            return EXIT_USAGE;
+        }
+    };
+    let printer = options.printer();
+    let mut status = 0;
+    let (mut failed, mut errors_found) = (0, 0);
+
+    for filename in &files {
+        let contents = match read_source(filename) {
+            Ok(contents) => contents,
+            Err(code) => {
+                failed += 1;
+                errors_found += 1;
+                status = if status == 0 { code } else { status };
+                continue;
+            }
+        };
+        let mut repl = Repl::for_file(filename, ScriptEnvironment::default());
+        let result = repl.check_file(&contents);
+        let warnings = repl.take_warnings();
+        let code = match result {
+            Ok(()) => printer.outcomes(warnings, &contents, Some(origin(filename)), Vec::new()),
+            Err(errors) => {
+                failed += 1;
+                errors_found += errors.len();
+                printer.errors(warnings, &contents, Some(origin(filename)), errors)
+            }
+        };
+        if status == 0 {
+            status = code;
+        }
+    }
+
+    if options.output == Format::Human {
+        if errors_found == 0 {
+            println!("Checked {}: no errors", count(files.len(), "file"));
+        } else {
+            println!(
+                "Checked {}: {} in {}",
+                count(files.len(), "file"),
+                count(errors_found, "error"),
+                count(failed, "file")
+            );
+        }
+    }
+    status
+}
+
+/// `relic test FILE...`: define each file's declarations, without running
+/// its script expressions, then run its `test` declarations in order. Each
+/// test starts with the instances of the file's `data` blocks, and those it
+/// constructs are discarded.
+fn test_files(args: &[String], options: &Options) -> i32 {
+    let files = match expand_files(args) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("{}", message);
+            return EXIT_USAGE;
+        }
+    };
+    let printer = options.printer();
+    let mut status = 0;
+    let (mut passed, mut failed) = (0, 0);
+
+    for filename in &files {
+        let contents = match read_source(filename) {
+            Ok(contents) => contents,
+            Err(code) => {
+                status = if status == 0 { code } else { status };
+                continue;
+            }
+        };
+        let mut repl = Repl::for_file(filename, ScriptEnvironment::default());
+        let program = match repl.parse_file(&contents) {
+            Ok(program) => program,
+            Err(errors) => {
+                let code = printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), errors);
+                status = if status == 0 { code } else { status };
+                continue;
+            }
+        };
+        if let Err(e) = repl.define_program(&program) {
+            let code = printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), vec![e]);
+            status = if status == 0 { code } else { status };
+            continue;
+        }
+        printer.outcomes(repl.take_warnings(), &contents, Some(origin(filename)), Vec::new());
+
+        for test in &program.tests {
+            let result = repl.compiler.run_test(test);
+            let outcome = Outcome::Test {
+                name: test.name.clone(),
+                passed: result.is_ok(),
+            };
+            printer.outcomes(Vec::new(), &contents, Some(origin(filename)), vec![Ok(outcome)]);
+            match result {
+                Ok(()) => passed += 1,
+                Err(e) => {
+                    failed += 1;
+                    printer.diagnostic(&Diagnostic::from_error(&e), &contents, Some(origin(filename)));
+                    status = if status == 0 { 1 } else { status };
+                }
+            }
+        }
+    }
+
+    printer.message(&format!(
+        "\nTest result: {} passed, {} failed in {}",
+        passed,
+        failed,
+        count(files.len(), "file")
+    ));
+    status
+}
+
+/// The files of a project, compiled into one session.
+struct Project {
+    repl: Repl,
+    /// Every file, after those it imports
+    modules: Vec<module::Module>,
+    /// The source of each file, by module name
+    sources: HashMap<String, String>,
+}
+
+impl Project {
+    /// Parse, type check and compile the files named by `args`, each as
+    /// `check` takes them, with the modules they import, into one session.
+    /// Diagnostics name the file they're in. Returns the exit status if
+    /// there were errors.
+    fn load(args: &[String], options: &Options) -> Result<Self, i32> {
+        let files = expand_files(args).map_err(|message| {
+            eprintln!("{}", message);
+            EXIT_USAGE
+        })?;
+        let printer = options.printer();
+        let environment = ScriptEnvironment::from_process(options.arguments.clone());
+        let mut repl = Repl::for_file("-", environment);
+        let mut roots = Vec::new();
+        let mut sources = HashMap::new();
+        let mut status = 0;
+
+        for filename in &files {
+            let contents = read_source(filename)?;
+            // Named as the resolver names the file when it's imported
+            let name = fs::canonicalize(filename).map_or_else(|_| filename.clone(), |path| path.to_string_lossy().into_owned());
+            match repl.parse_file(&contents) {
+                Ok(program) => roots.push(module::Module { name: name.clone(), program }),
+                Err(errors) => {
+                    let code = printer.errors(repl.take_warnings(), &contents, Some(display_name(&name)), errors);
+                    status = if status == 0 { code } else { status };
+                }
+            }
+            sources.insert(name, contents);
+        }
+        if status != 0 {
+            return Err(status);
+        }
+
+        let modules = module::load_project(&FileSystemResolver::default(), roots)
+            .map_err(|e| printer.errors(repl.take_warnings(), "", None, vec![e]))?;
+        let source = |sources: &HashMap<String, String>, name: &str| -> String {
+            sources.get(name).cloned().unwrap_or_default()
+        };
+        for (name, error) in module::duplicate_definitions(&modules) {
+            let code = printer.errors(Vec::new(), &source(&sources, &name), Some(display_name(&name)), vec![error]);
+            status = if status == 0 { code } else { status };
+        }
+        if status != 0 {
+            return Err(status);
+        }
+
+        for module in &modules {
+            let errors = repl.typechecker.check_program_recovering(&module.program);
+            let contents = source(&sources, &module.name);
+            if errors.is_empty() {
+                printer.outcomes(repl.take_warnings(), &contents, Some(display_name(&module.name)), Vec::new());
+            } else {
+                let code = printer.errors(repl.take_warnings(), &contents, Some(display_name(&module.name)), errors);
+                status = if status == 0 { code } else { status };
             }
         }
+        if status != 0 {
+            return Err(status);
+        }
+        for module in &modules {
+            if let Err(e) = repl.compiler.compile_module(module) {
+                let contents = source(&sources, &module.name);
+                return Err(printer.errors(Vec::new(), &contents, Some(display_name(&module.name)), vec![e]));
+            }
+        }
+
+        Ok(Self { repl, modules, sources })
+    }
+}
+
+/// A module name, which for a file is its absolute path, relative to the
+/// working directory if it's below it.
+fn display_name(name: &str) -> &str {
+    env::current_dir()
+        .ok()
+        .and_then(|dir| Path::new(name).strip_prefix(dir).ok())
+        .and_then(Path::to_str)
+        .unwrap_or(name)
+}
+
+/// `relic build FILE|DIR...`: compile files, and every `.relic` file in
+/// directories, into one set of declarations, as if one program, reporting
+/// any problem in the file it's in, including a value type or function
+/// defined in two files. Runs nothing.
+fn build_files(args: &[String], options: &Options) -> i32 {
+    let project = match Project::load(args, options) {
+        Ok(project) => project,
+        Err(status) => return status,
+    };
+    let declarations = project.modules.iter().flat_map(|module| &module.program.declarations);
+    let values = declarations.clone().filter(|d| matches!(d, Declaration::Value(_))).count();
+    let functions = declarations.count() - values;
+    options.printer().message(&format!(
+        "Built {}: {} and {}",
+        count(project.modules.len(), "file"),
+        count(values, "value type"),
+        count(functions, "function")
+    ));
+    0
+}
+
+/// `relic run DIR`: compile a project as `build` does, then run the script
+/// expressions of each file, each file after those it imports.
+fn run_project(dir: &str, options: &Options) -> i32 {
+    let Project { mut repl, modules, sources } = match Project::load(&[dir.to_string()], options) {
+        Ok(project) => project,
+        Err(status) => return status,
+    };
+    let printer = options.printer();
+    for module in &modules {
+        let results: Vec<_> = module
+            .program
+            .expressions
+            .iter()
+            .map(|expression| repl.run_expression(expression))
+            .collect();
+        let contents = sources.get(&module.name).map_or("", String::as_str);
+        let status = printer.outcomes(Vec::new(), contents, Some(display_name(&module.name)), results);
+        if status != 0 {
+            return status;
+        }
+    }
+    0
+}
+
+/// The files named by `check`, `build` and `test` arguments. Each is a file, a directory (for
+/// every `.relic` file below it) or a glob pattern such as
+/// `schemas/**/*.relic`, for shells that don't expand patterns themselves.
+fn expand_files(args: &[String]) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    for arg in args {
+        let pattern = if Path::new(arg).is_dir() {
+            format!("{}/**/*.relic", arg.trim_end_matches('/'))
+        } else if arg.contains(['*', '?', '[']) {
+            arg.clone()
+        } else {
+            files.push(arg.clone());
+            continue;
+        };
+
+        let paths = glob::glob(&pattern).map_err(|e| format!("Invalid pattern '{}': {}", arg, e))?;
+        let matched: Vec<_> = paths
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        if matched.is_empty() {
+            return Err(format!("No .relic files match '{}'", arg));
+        }
+        files.extend(matched);
+    }
+    Ok(files)
+}
+
+/// "1 file", "2 files"
+fn count(n: usize, noun: &str) -> String {
+    if n == 1 {
+        format!("1 {}", noun)
     } else {
-        // REPL mode
-        println!("Relic Language REPL v0.1.0");
-        println!("Type 'exit' to quit");
-        println!("Type 'help' for commands\n");
+        format!("{} {}s", n, noun)
+    }
+}
 
-        let mut repl = Repl::new();
+/// A size in bytes, in the largest unit it makes at least one of.
+fn bytes(n: usize) -> String {
+    match n {
+        0..=1023 => count(n, "byte"),
+        1024..=1048575 => format!("{:.1} KiB", n as f64 / 1024.0),
+        _ => format!("{:.1} MiB", n as f64 / 1048576.0),
+    }
+}
+
+/// `relic fmt [--check] FILE...`: rewrite files in canonical formatting.
+/// With `--check`, only list the files that would change. Standard input
+/// is formatted to standard output.
+fn format_files(files: &[String], options: &Options) -> i32 {
+    let printer = options.printer();
+    let mut status = 0;
+
+    for filename in files {
+        let contents = match read_source(filename) {
+            Ok(contents) => contents,
+            Err(code) => {
+                status = code;
+                continue;
+            }
+        };
+
+        let formatted = match formatter::format_source(&contents) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                status = printer.errors(Vec::new(), &contents, Some(origin(filename)), errors);
+                continue;
+            }
+        };
+
+        if filename == "-" && !options.check {
+            print!("{}", formatted);
+            continue;
+        }
+        if formatted == contents {
+            continue;
+        }
+        if options.check {
+            println!("Would reformat: {}", origin(filename));
+            status = 1;
+        } else if let Err(e) = fs::write(filename, &formatted) {
+            eprintln!("Error writing file '{}': {}", filename, e);
+            status = 1;
+        } else {
+            println!("Formatted: {}", filename);
+        }
+    }
+
+    status
+}
+
+/// `relic ast FILE`: print the syntax tree of a file, without checking or
+/// running it. With `--json`, as a single line of JSON.
+fn print_ast(filename: &str, options: &Options) -> i32 {
+    let contents = match read_source(filename) {
+        Ok(contents) => contents,
+        Err(status) => return status,
+    };
+
+    let mut repl = Repl::new();
+    match repl.parse_file(&contents) {
+        Ok(program) => {
+            match options.output {
+                Format::Human => println!("{:#?}", program),
+                Format::Json => println!("{}", ast_json::program_to_json(&program)),
+            }
+            0
+        }
+        Err(errors) => options.printer().errors(Vec::new(), &contents, Some(origin(filename)), errors),
+    }
+}
 
-        loop {
-        print!("relic> ");
-        io::stdout().flush().unwrap();
+/// `relic doc [--html] FILE`: print reference documentation for a file's
+/// value types and functions, as Markdown or with `--html` as a web page.
+/// The file must type check.
+fn print_docs(filename: &str, options: &Options) -> i32 {
+    let contents = match read_source(filename) {
+        Ok(contents) => contents,
+        Err(status) => return status,
+    };
+    let printer = options.printer();
+    let mut repl = Repl::for_file(filename, ScriptEnvironment::default());
+    let program = match repl.check_file(&contents).and_then(|()| repl.parse_file(&contents)) {
+        Ok(program) => program,
+        Err(errors) => return printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), errors),
+    };
 
-        let mut input = String::new();
-        match io::stdin().read_line(&mut input) {
-            Ok(0) => break, // EOF reached
-            Ok(_) => {},
+    let title = Path::new(origin(filename))
+        .file_stem()
+        .map_or_else(|| origin(filename).to_string(), |stem| stem.to_string_lossy().into_owned());
+    let format = if options.html { DocFormat::Html } else { DocFormat::Markdown };
+    print!("{}", doc::generate(&title, &contents, &program, format));
+    0
+}
+
+/// `relic schema [--proto] FILE`: print a JSON Schema document defining
+/// each value type a file declares, for other systems to check payloads
+/// with, or with `--proto` a `.proto` file for them to exchange data with.
+/// The file's declarations are defined, but its expressions aren't run.
+fn print_schema(filename: &str, options: &Options) -> i32 {
+    let contents = match read_source(filename) {
+        Ok(contents) => contents,
+        Err(status) => return status,
+    };
+    let printer = options.printer();
+    let mut repl = Repl::for_file(filename, ScriptEnvironment::default());
+    let program = match repl.parse_file(&contents) {
+        Ok(program) => program,
+        Err(errors) => return printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), errors),
+    };
+    let type_names: Vec<&str> = program
+        .declarations
+        .iter()
+        .filter(|declaration| matches!(declaration, Declaration::Value(_)))
+        .map(Declaration::name)
+        .collect();
+    if let Err(error) = repl.define_program(&program) {
+        return printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), vec![error]);
+    }
+    let registry = repl.compiler.get_registry();
+    let schema = if options.proto {
+        let proto = relic::proto::generate(registry, &type_names, &package(filename));
+        proto.map(|proto| proto.source.trim_end().to_string())
+    } else {
+        relic::json_schema::generate(registry, &type_names)
+    };
+    match schema {
+        Ok(schema) => {
+            println!("{}", schema);
+            0
+        }
+        Err(error) => printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), vec![error]),
+    }
+}
+
+// The package of a file's `.proto`: its name, as an identifier
+fn package(filename: &str) -> String {
+    let stem = Path::new(origin(filename)).file_stem().map(|stem| stem.to_string_lossy().into_owned());
+    let name: String = stem
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect();
+    match name.chars().next() {
+        Some(first) if first.is_ascii_alphabetic() => name,
+        _ => format!("relic{}", name),
+    }
+}
+
+/// `relic replay FILE`: run the inputs of a replay log again, in a fresh
+/// session of whatever recorded it, and show each whose results differ
+/// from the recorded ones. Exits 1 if any do.
+fn replay_file(filename: &str, options: &Options) -> i32 {
+    let contents = match read_source(filename) {
+        Ok(contents) => contents,
+        Err(status) => return status,
+    };
+    let printer = options.printer();
+    let log = match ReplayLog::parse(&contents) {
+        Ok(log) => log,
+        Err(error) => return printer.errors(Vec::new(), &contents, Some(origin(filename)), vec![error]),
+    };
+    let differences = match log.recorder {
+        Recorder::Repl => {
+            let mut repl = Repl::new();
+            repl.compiler = Compiler::new()
+                .with_script_environment(ScriptEnvironment::from_process(Vec::new()))
+                .with_provenance();
+            let mut replayed = ReplayLog::new(Recorder::Repl);
+            for entry in &log.entries {
+                replayed.record(&entry.input, run_input(&mut repl, &entry.input).replay_results());
+                repl.take_warnings();
+            }
+            log.diff(&replayed)
+        }
+        Recorder::Engine => match Engine::new().replay(&log) {
+            Ok(differences) => differences,
+            Err(error) => return printer.errors(Vec::new(), &contents, Some(origin(filename)), vec![error]),
+        },
+    };
+
+    for difference in &differences {
+        printer.message(difference.to_string().trim_end());
+    }
+    let summary = match differences.len() {
+        0 => "every result matched".to_string(),
+        n => format!("{} differed", count(n, "input")),
+    };
+    printer.message(&format!("Replayed {}: {}", count(log.entries.len(), "input"), summary));
+    i32::from(!differences.is_empty())
+}
+
+/// `relic bench FILE`: run a file `--iterations` times, each in a fresh
+/// session, and report the average time spent in each stage.
+fn bench(filename: &str, options: &Options) -> i32 {
+    let contents = match read_source(filename) {
+        Ok(contents) => contents,
+        Err(status) => return status,
+    };
+    let printer = options.printer();
+    let fail = |repl: &mut Repl, error| {
+        printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), vec![error])
+    };
+
+    let (mut parse, mut define, mut evaluate) = (Duration::ZERO, Duration::ZERO, Duration::ZERO);
+    for _ in 0..options.iterations {
+        let environment = ScriptEnvironment::from_process(options.arguments.clone());
+        let mut repl = Repl::for_file(filename, environment);
+
+        let start = Instant::now();
+        let program = match repl.parse_file(&contents) {
+            Ok(program) => program,
+            Err(errors) => return printer.errors(repl.take_warnings(), &contents, Some(origin(filename)), errors),
+        };
+        parse += start.elapsed();
+
+        let start = Instant::now();
+        if let Err(e) = repl.define_program(&program) {
+            return fail(&mut repl, e);
+        }
+        define += start.elapsed();
+
+        let start = Instant::now();
+        for expression in &program.expressions {
+            match repl.compiler.evaluate_expression(&expression.expression) {
+                Ok(value) => {
+                    if let Some(name) = &expression.binding {
+                        repl.compiler.bind(name, value);
+                    }
+                }
+                Err(e) => return fail(&mut repl, e.with_span(expression.span)),
+            }
+        }
+        evaluate += start.elapsed();
+    }
+
+    let runs = options.iterations as f64;
+    let stages = [
+        ("parse", parse),
+        ("compile", define),
+        ("evaluate", evaluate),
+        ("total", parse + define + evaluate),
+    ];
+    match options.output {
+        Format::Human => {
+            println!("{}: average of {} runs", origin(filename), options.iterations);
+            for (stage, time) in stages {
+                println!("  {:<10}{:>10.3} ms", stage, time.as_secs_f64() * 1000.0 / runs);
+            }
+        }
+        Format::Json => {
+            let times: Vec<_> = stages
+                .iter()
+                .map(|(stage, time)| format!("\"{}_ms\":{:.3}", stage, time.as_secs_f64() * 1000.0 / runs))
+                .collect();
+            println!(
+                "{{\"kind\":\"bench\",\"file\":{},\"iterations\":{},{}}}",
+                json_string(origin(filename)),
+                options.iterations,
+                times.join(",")
+            );
+        }
+    }
+    0
+}
+
+/// `relic repl`: read and run inputs until `:exit` or end of input.
+fn repl(options: &Options) -> i32 {
+    if options.output == Format::Human {
+        println!("Relic Language REPL v{}", env!("CARGO_PKG_VERSION"));
+        println!("Type ':exit' to quit");
+        println!("Type ':help' for commands\n");
+    }
+
+    let mut repl = Repl::new();
+    repl.compiler = Compiler::new()
+        .with_script_environment(ScriptEnvironment::from_process(Vec::new()))
+        .with_provenance();
+    let mut printer = options.printer();
+    let mut recording = options.record.clone().map(|path| (path, ReplayLog::new(Recorder::Repl)));
+    let mut editor = match Editor::<ReplHelper, DefaultHistory>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("Error starting the line editor: {}", e);
+            return 1;
+        }
+    };
+    let history = history_path(|name| env::var_os(name));
+    if let Some(path) = &history {
+        // There is no history file until the first session ends
+        let _ = editor.load_history(path);
+    }
+    // Lines read so far for an input that isn't complete yet
+    let mut pending = String::new();
+    let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let color = !no_color && io::stdout().is_terminal();
+
+    loop {
+        editor.set_helper(Some(ReplHelper {
+            typechecker: repl.typechecker.clone(),
+            functions: repl.compiler.get_registry().functions().cloned().collect(),
+            color,
+        }));
+        let prompt = if pending.is_empty() { "relic> " } else { "...> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C, or Ctrl-D partway through an input, abandons it
+            Err(ReadlineError::Interrupted) => {
+                pending.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) if !pending.is_empty() => {
+                pending.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
             Err(e) => {
                 eprintln!("Error reading input: {}", e);
                 break;
             }
-        }
+        };
 
+        // Keep reading while a declaration or expression is still open
+        pending.push_str(&line);
+        pending.push('\n');
+        if lexer::is_incomplete(&pending) {
+            continue;
+        }
+        let input = std::mem::take(&mut pending);
         let input = input.trim();
+        if !input.is_empty() {
+            // Multi-line inputs are recalled whole
+            let _ = editor.add_history_entry(input);
+        }
 
         match input {
-            "exit" => break,
-            "help" => {
+            ":exit" | "exit" => break,
+            ":help" | "help" => {
                 println!("Commands:");
                 println!("  value TypeName(param: Type) {{ ... }}     - Define a value type");
                 println!("  fn name(params) -> Type {{ ... }}         - Define a function");
                 println!("  method name(params) -> Type {{ ... }}     - Define a method");
                 println!("  TypeName(value)                           - Create a value instance");
                 println!("  functionName(args)                        - Call a function");
-                println!("  help                                      - Show this help");
-                println!("  exit                                      - Exit the REPL");
+                println!("  Unclosed (, [, {{ or strings continue on the next line; Ctrl-C cancels");
+                println!("  Up/Down recall earlier inputs, Ctrl-R searches them, Tab completes names");
+                println!("  Typing a call shows the signatures of its overloads after the cursor");
+                println!("  :type expr                                - Show the type of an expression without running it");
+                println!("  :time expr                                - Evaluate an expression, reporting time, dispatches and instances created");
+                println!("  :dispatch f(args)                         - Show which overload a call would run, and why");
+                println!("  :inspect expr                             - Show a value's type, fields, declaration, pool entry and provenance");
+                println!("  :tokens input                             - Show the tokens of an input, with positions and classes");
+                println!("  :ast input                                - Show the syntax tree of an input, indented");
+                println!("  :save file.relic                          - Write the declarations entered so far to a file");
+                println!("  :load file.relic                          - Run a file's declarations in this session");
+                println!("  :env                                      - List value types and functions");
+                println!("  :instances [Type]                         - Count instances of value types, with recent ones");
+                println!("  :stats                                    - Show instance counts, memory used and table sizes");
+                println!("  :forget name                              - Remove a value type or function, to redefine it");
+                println!("  :migrate value T(...) {{ ... }} [using f] - Redefine a value type, reconstructing its instances");
+                println!("  :reload declarations                      - Redefine functions or value types, keeping their types");
+                println!("  :reset                                    - Clear every definition");
+                println!("  :format table|raw|json                    - Print lists of values as tables, on one line, or as JSON");
+                println!("  :help                                     - Show this help");
+                println!("  :exit                                     - Exit the REPL");
             }
             "" => continue,
+            _ if input.split(char::is_whitespace).next() == Some(":format") => {
+                let argument = input[":format".len()..].trim();
+                match argument {
+                    "table" | "raw" => {
+                        printer.output = Format::Human;
+                        printer.tables = argument == "table";
+                    }
+                    _ => match Format::parse(argument) {
+                        Some(output) => printer.output = output,
+                        None => printer.error(&format!(
+                            "Unknown output format '{}' (expected 'table', 'raw', 'human' or 'json')",
+                            argument
+                        )),
+                    },
+                }
+            }
             _ => {
-                // Determine if this is a declaration or expression
-                let result = if input.starts_with("value ") || input.starts_with("fn ") || input.starts_with("method ") {
-                    repl.process_declaration(input)
-                } else {
-                    // Try to parse as an expression first
-                    match repl.process_expression(input) {
-                        Ok(result) => Ok(result),
-                        Err(_) => {
-                            // If that fails and it looks like a construction, try that
-                            if input.contains('(') && input.contains(')') {
-                                repl.process_construction(input)
-                            } else {
-                                Err(relic::Error::Parser(relic::error::ParserError {
-                                    message: "Invalid syntax. Type 'help' for commands.".to_string(),
-                                    line: 1,
-                                    column: 1,
-                                }))
-                            }
-                        }
+                let ran = run_input(&mut repl, input);
+                if let Some((path, log)) = &mut recording {
+                    log.record(input, ran.replay_results());
+                    // Written after every input, so that a crash leaves the log
+                    if let Err(e) = fs::write(&*path, log.to_string()) {
+                        printer.error(&format!("Error writing replay log '{}': {}", path, e));
                     }
-                };
-
-                match result {
-                    Ok(output) => println!("{}", output),
-                    Err(e) => eprintln!("Error: {}", e),
+                }
+                match ran {
+                    Ran::Results { source, origin, results } => {
+                        printer.outcomes(repl.take_warnings(), &source, origin.as_deref(), results);
+                    }
+                    Ran::Failed(message) => printer.error(&message),
                 }
             }
         }
     }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Could not save history to '{}': {}", path.display(), e);
+        }
+    }
+
+    0
+}
+
+/// The one FILE a command takes, or None after reporting that it's missing.
+fn single_file<'a>(command: &str, files: &'a [String]) -> Option<&'a str> {
+    match files {
+        [file] => Some(file),
+        _ => {
+            eprintln!("relic {} takes one FILE (or - for standard input); see relic --help", command);
+            None
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args().skip(1).peekable();
+    let command = match args.peek().map(String::as_str) {
+        Some(
+            "run" | "repl" | "check" | "build" | "fmt" | "test" | "ast" | "doc" | "schema" | "bench" | "replay" | "help",
+        ) => args.next(),
+        _ => None,
+    };
+    let script = matches!(command.as_deref(), None | Some("run"));
+    let options = match Options::parse(args, script) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}; see relic --help", message);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+    if options.version {
+        println!("relic {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    if options.help || command.as_deref() == Some("help") {
+        print!("{}", USAGE);
+        return;
+    }
+
+    if options.record.is_some() && !matches!(command.as_deref(), Some("repl") | None) {
+        eprintln!("--record only applies to the REPL; see relic --help");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if options.keep_instances && !options.watch {
+        eprintln!("--keep-instances only applies with run --watch; see relic --help");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    if let Some(source) = &options.eval {
+        if command.is_some() {
+            eprintln!("-e runs SOURCE instead of a command; see relic --help");
+            std::process::exit(EXIT_USAGE);
+        }
+        std::process::exit(eval_source(source, &options));
+    }
+
+    let files = &options.files;
+    let status = match command.as_deref() {
+        Some("repl") if files.is_empty() => repl(&options),
+        Some("repl") => {
+            eprintln!("relic repl doesn't take files; use :load in the session");
+            EXIT_USAGE
+        }
+        Some("check" | "build" | "fmt" | "test") if files.is_empty() => {
+            eprintln!("relic {} needs at least one FILE; see relic --help", command.as_deref().unwrap_or(""));
+            EXIT_USAGE
+        }
+        Some("check") => check_files(files, &options),
+        Some("build") => build_files(files, &options),
+        Some("fmt") => format_files(files, &options),
+        Some("test") => test_files(files, &options),
+        Some(command @ ("run" | "ast" | "doc" | "schema" | "bench" | "replay")) => match single_file(command, files) {
+            Some(file) if command == "run" => run_file(file, &options),
+            Some(file) if command == "ast" => print_ast(file, &options),
+            Some(file) if command == "doc" => print_docs(file, &options),
+            Some(file) if command == "schema" => print_schema(file, &options),
+            Some(file) if command == "replay" => replay_file(file, &options),
+            Some(file) => bench(file, &options),
+            None => EXIT_USAGE,
+        },
+        // `relic FILE` runs it; plain `relic` starts the REPL
+        _ => match files.first() {
+            None => repl(&options),
+            Some(file) => run_file(file, &options),
+        },
+    };
+    std::process::exit(status);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The outcomes of each input, in order, as `--json` prints them.
+    fn json(repl: &mut Repl, inputs: &[&str]) -> Vec<String> {
+        let outcomes = inputs.iter().flat_map(|input| repl.process_input(input));
+        outcomes.map(|outcome| outcome.unwrap().to_json()).collect()
+    }
+
+    #[test]
+    fn test_json_output() {
+        let options = Options::parse(["--json".to_string()].into_iter(), false).unwrap();
+        assert!(options.output == Format::Json);
+
+        let mut repl = Repl::new();
+        assert_eq!(
+            json(&mut repl, &["value Email(raw: String) {}", r#"Email("a@b")"#]),
+            vec![
+                r#"{"kind":"defined","declaration":"value type","name":"Email"}"#,
+                r#"{"kind":"value","value":"Email(a@b)","type":"Email"}"#,
+            ]
+        );
+        // Strings are escaped
+        let outcome = repl.run_command(":type", r#""say \"hi\"""#).unwrap().unwrap();
+        assert_eq!(outcome.to_json(), r#"{"kind":"type","expression":"\"say \\\"hi\\\"\"","type":"String"}"#);
+    }
+
+    #[test]
+    fn test_scripts_run_in_source_order() {
+        let mut repl = Repl::for_file("-", ScriptEnvironment::default());
+        let source = "value A(n: Int) { validate: n > 1 }\nA(f())\nfn f() -> Int { 2 }\nf()\nA(1)\nA(3)";
+        let results: Vec<_> = repl
+            .process_file(source)
+            .unwrap()
+            .into_iter()
+            .map(|result| result.map_or_else(|e| e.to_string(), |outcome| outcome.to_string()))
+            .collect();
+        // Expressions see every declaration, and the first failure stops the script
+        assert_eq!(results.len(), 5, "{:?}", results);
+        assert_eq!(results[..4], ["Defined value type: A", "→ A(2) : A", "Defined function: f", "→ 2 : Int"]);
+        assert!(results[4].starts_with("Validation error"), "{}", results[4]);
+    }
+
+    fn parse(args: &[&str], script: bool) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()), script)
+    }
+
+    #[test]
+    fn test_options() {
+        let options = parse(&["--diagnostics=json", "script.relic", "--verbose", "x"], true).unwrap();
+        assert!(options.diagnostics == Format::Json);
+        assert_eq!(options.files, ["script.relic"]);
+        assert_eq!(options.arguments, ["--verbose", "x"]);
+
+        let options = parse(&["-V", "-", "b.relic"], false).unwrap();
+        assert!(options.version);
+        assert_eq!(options.files, ["-", "b.relic"]);
+        assert_eq!(origin("-"), "<stdin>");
+
+        assert_eq!(parse(&["--nope"], false).err().unwrap(), "Unknown option '--nope'");
+        assert!(parse(&["--iterations=0"], false).is_err());
+        assert!(parse(&["--diagnostics=xml"], false).is_err());
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let mut repl = Repl::new();
+        repl.process_input("value Age(n: Int) { validate: n >= 0 }");
+        let mut status = |input: &str| repl.process_expression(input).map_or_else(|e| exit_code(&e), |_| 0);
+        assert_eq!(status("Age(1)"), 0);
+        assert_eq!(status("1 +"), 3);
+        assert_eq!(status("1 + \"a\""), 4);
+        assert_eq!(status("Age(0 - 1)"), 6);
+    }
+
+    #[test]
+    fn test_eval_source() {
+        let options = parse(&["-e", "1 + 1"], true).unwrap();
+        assert_eq!(options.eval.as_deref(), Some("1 + 1"));
+        assert_eq!(parse(&["-e"], true).err().unwrap(), "-e needs SOURCE to run");
+
+        // The status says whether the value was valid, for use in pipelines
+        let email = "value Email(raw: String) { validate: raw contains \"@\" }\n";
+        assert_eq!(eval_source("1 + 1", &options), 0);
+        assert_eq!(eval_source(&format!("{}Email(\"x@y.com\")", email), &options), 0);
+        assert_eq!(eval_source(&format!("{}Email(\"nope\")", email), &options), 6);
+        assert_eq!(eval_source("\"x@y.com\" contains \"!\"", &options), 1);
+        assert_eq!(eval_source("1 +", &options), 3);
+    }
+
+    #[test]
+    fn test_unmatched_patterns_are_usage_errors() {
+        let options = parse(&[], false).unwrap();
+        for pattern in ["no-such-directory/*.relic", "["] {
+            let args = [pattern.to_string()];
+            assert_eq!(check_files(&args, &options), EXIT_USAGE, "{}", pattern);
+            assert_eq!(test_files(&args, &options), EXIT_USAGE, "{}", pattern);
+            assert_eq!(Project::load(&args, &options).err(), Some(EXIT_USAGE), "{}", pattern);
+        }
+    }
+
+    /// A session with the declarations and data of `source` defined, as
+    /// `relic test` defines a file's, and the program parsed from it.
+    fn defined(source: &str, previous: Option<&Repl>) -> (Repl, Program) {
+        let mut repl = Repl::for_file("-", ScriptEnvironment::default());
+        if let Some(previous) = previous {
+            repl = repl.keep_instances(previous);
+        }
+        let program = repl.parse_file(source).unwrap();
+        repl.define_program(&program).unwrap();
+        (repl, program)
+    }
+
+    #[test]
+    fn test_recent_instances() {
+        let (repl, _) = defined("value Age(n: Int) { validate: n >= 0 }", None);
+        let registry = repl.compiler.get_registry();
+        for n in 1..=4i64 {
+            registry.construct("Age", Box::new(n)).unwrap();
+        }
+
+        assert_eq!(registry.count_instances("Age"), 4);
+        let recent: Vec<_> = registry
+            .recent_instances("Age", 2)
+            .iter()
+            .map(|instance| instance.to_string())
+            .collect();
+        assert_eq!(recent, vec!["Age(3)", "Age(4)"]);
+        assert!(registry.recent_instances("Missing", 2).is_empty());
+        assert_eq!(repl.instances("Age").unwrap(), "Age: 4 instances\n  Age(1)\n  Age(2)\n  Age(3)\n  Age(4)");
+    }
+
+    #[test]
+    fn test_keep_instances() {
+        let (first, _) = defined("value Age(n: Int) { validate: n >= 0 }", None);
+        for n in [5i64, 20, 40] {
+            first.compiler.get_registry().construct("Age", Box::new(n)).unwrap();
+        }
+
+        // Instances that fail the new validation are dropped
+        let (second, _) = defined("value Age(n: Int) { validate: n >= 18 }", Some(&first));
+        assert_eq!(second.compiler.get_registry().count_instances("Age"), 2);
+
+        // As are those whose parameter type changed
+        let (third, _) = defined("value Age(n: String) { validate: n != \"\" }", Some(&second));
+        assert_eq!(third.compiler.get_registry().count_instances("Age"), 0);
+    }
+
+    #[test]
+    fn test_run_test() {
+        let source = r#"
+            value Email(raw: String) { validate: raw contains "@" }
+            test "rejects a missing @" { assert(!isValid(Email, "foo")) }
+            test "starts empty" { Email("a@b"); assertEq(Email.count(), 1) }
+            test "wrong" { assertEq(Email.count(), 1) }
+            test "false" { Email.count() > 0 }
+        "#;
+        let (mut repl, program) = defined(source, None);
+        repl.compiler.get_registry().construct("Email", Box::new("x@y".to_string())).unwrap();
+
+        let results: Vec<_> = program.tests.iter().map(|test| repl.compiler.run_test(test)).collect();
+        assert!(results[0].is_ok());
+        assert!(results[1].is_ok());
+        assert_eq!(results[2].as_ref().unwrap_err().to_string(), "Validation error in assertion: Expected 1, found 0");
+        assert!(results[3].is_err());
+        // Instances from outside the tests are untouched
+        assert_eq!(repl.compiler.get_registry().count_instances("Email"), 1);
+    }
+
+    #[test]
+    fn test_tests_start_from_seed_data() {
+        let source = r#"
+            value Email(raw: String) { validate: raw contains "@" }
+            data Email { Email("a@b"), Email("c@d") }
+            test "seeded" { Email("e@f"); assertEq(Email.count(), 3) }
+            test "again" { assertEq(Email.count(), 2) }
+        "#;
+        let (mut repl, program) = defined(source, None);
+        assert_eq!(repl.compiler.get_registry().count_instances("Email"), 2);
+
+        for test in &program.tests {
+            repl.compiler.run_test(test).unwrap();
+        }
+        assert_eq!(repl.compiler.get_registry().count_instances("Email"), 2);
+    }
+
+    #[test]
+    fn test_history_path() {
+        let environment = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| OsString::from(value))
+        };
+        let both = environment(&[("RELIC_HISTORY", "/tmp/relic-history"), ("HOME", "/home/ada")]);
+        assert_eq!(history_path(both), Some(PathBuf::from("/tmp/relic-history")));
+        let home = environment(&[("HOME", "/home/ada")]);
+        assert_eq!(history_path(home), Some(PathBuf::from("/home/ada/.relic_history")));
+        assert_eq!(history_path(environment(&[])), None);
     }
 }
@@ -0,0 +1,139 @@
+//! Changing a value type without losing its instances. A migration
+//! exports the instances of the type, replaces its declaration, and
+//! constructs each instance again under the new one, optionally passing its
+//! parameter through a mapping function first. Instances that fail the new
+//! validation are reported rather than kept.
+
+use crate::ast::{Program, ValueDeclaration};
+use crate::compiler::Compiler;
+use crate::error::{Error, Result, TypeError};
+use crate::evaluator::{self, EvalValue};
+use crate::typechecker::TypeChecker;
+use std::fmt;
+
+/// What happened to the instances of a migrated type.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub type_name: String,
+    /// The instances under the new declaration
+    pub migrated: Vec<EvalValue>,
+    /// Parameters of the old instances that couldn't be mapped or failed the
+    /// new validation, with the reason
+    pub failed: Vec<(EvalValue, Error)>,
+}
+
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.migrated.len() + self.failed.len();
+        write!(f, "Migrated {} of {} {} instances", self.migrated.len(), total, self.type_name)?;
+        if !self.failed.is_empty() {
+            write!(f, "; {} dropped:", self.failed.len())?;
+            for (parameter, error) in &self.failed {
+                write!(f, "\n  {}({}): {}", self.type_name, parameter, error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replace the declaration of the value type `declaration` names with
+/// `declaration`, and construct its instances again under the new one. Each
+/// old instance's parameter is passed to the function `mapping`, if given,
+/// which must take the old parameter type and return the new one.
+///
+/// Nothing changes if the new declaration doesn't type check or no value
+/// type has its name.
+pub fn migrate(
+    typechecker: &mut TypeChecker,
+    compiler: &mut Compiler,
+    declaration: &ValueDeclaration,
+    mapping: Option<&str>,
+) -> Result<Migration> {
+    let name = &declaration.name;
+    let registry = compiler.get_registry();
+    let Some(old) = registry.constructors.get(name) else {
+        return Err(Error::Type(TypeError {
+            message: format!("No value type named '{}' to migrate", name),
+            span: Some(declaration.span),
+        }));
+    };
+    if let Some(mapping) = mapping {
+        if registry.get_functions(mapping).is_none() {
+            return Err(Error::Type(TypeError {
+                message: format!("Undefined mapping function: {}", mapping),
+                span: None,
+            }));
+        }
+    }
+    let old_parameter = old.declaration.parameter.name.clone();
+    let parameters: Vec<EvalValue> = evaluator::instance_values(name, registry)
+        .into_iter()
+        .filter_map(|instance| match instance {
+            EvalValue::Value { mut fields, .. } => fields.remove(&old_parameter),
+            _ => None,
+        })
+        .collect();
+
+    // Check the new declaration before anything is replaced
+    let program = Program {
+        imports: Vec::new(),
+        declarations: vec![crate::ast::Declaration::Value(declaration.clone())],
+        expressions: Vec::new(),
+        tests: Vec::new(),
+        data: Vec::new(),
+    };
+    let mut checker = typechecker.clone();
+    checker.forget(name);
+    checker.check_program(&program)?;
+    *typechecker = checker;
+    compiler.forget(name);
+    compiler.compile_program(&program)?;
+
+    let registry = compiler.get_registry();
+    let mut migration = Migration {
+        type_name: name.clone(),
+        migrated: Vec::new(),
+        failed: Vec::new(),
+    };
+    for parameter in parameters {
+        let mapped = match mapping {
+            Some(mapping) => evaluator::call(mapping, std::slice::from_ref(&parameter), registry),
+            None => Ok(parameter.clone()),
+        };
+        match mapped.and_then(|mapped| evaluator::construct(name, &mapped, registry)) {
+            Ok(value) => migration.migrated.push(value),
+            Err(e) => migration.failed.push((parameter, e)),
+        }
+    }
+    Ok(migration)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::evaluator::EvalValue;
+
+    #[test]
+    fn test_migrate() {
+        let mut engine = Engine::new();
+        engine
+            .load("value Code(raw: String) {}\nCode(\"ab\")\nCode(\"ABC\")\nCode(\"\")")
+            .unwrap();
+
+        // A declaration that doesn't type check changes nothing
+        assert!(engine.migrate("value Code(raw: String) { validate: raw }", None).is_err());
+        assert!(engine.migrate("value Other(raw: String) {}", None).is_err());
+        assert!(engine.migrate("value Code(raw: String) {}", Some("missing")).is_err());
+        assert_eq!(engine.get_instances("Code").unwrap().len(), 3);
+
+        let migration = engine
+            .migrate("value Code(raw: String) { validate: raw.length > 0 }", Some("uppercase"))
+            .unwrap();
+        assert_eq!(migration.migrated.len(), 2);
+        assert_eq!(migration.failed.len(), 1);
+        assert_eq!(migration.failed[0].0, EvalValue::String(String::new()));
+        assert!(migration.to_string().starts_with("Migrated 2 of 3 Code instances; 1 dropped:\n  Code(): "));
+
+        assert_eq!(engine.eval("Code.count()").unwrap(), EvalValue::Integer(2));
+    }
+}
@@ -0,0 +1,386 @@
+//! Resolution of `import` statements.
+//!
+//! The compiler doesn't read files itself: it asks a `ModuleResolver` for the
+//! source behind each import path. `FileSystemResolver` reads `.relic` files
+//! from disk; embedders can supply their own resolver (for example
+//! `MemoryResolver`) to serve modules from memory or a database.
+
+use crate::ast::{Declaration, Import, Program};
+use crate::error::{Error, ModuleError, Result, TypeError};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The source of a module, found by a `ModuleResolver`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleSource {
+    /// Identifies the module: two imports that resolve to the same name load
+    /// it only once. Also passed back as the importer of the module's own
+    /// imports.
+    pub name: String,
+    pub source: String,
+}
+
+/// A parsed module, ready to be type checked and compiled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub name: String,
+    pub program: Program,
+}
+
+/// Shared by the threads an `Engine` is used from, so it must be `Send`
+/// and `Sync`.
+pub trait ModuleResolver: Send + Sync {
+    /// Find the module that `path` refers to. `importer` is the name of the
+    /// module containing the import, or None for the program being compiled.
+    fn resolve(&self, path: &str, importer: Option<&str>) -> Result<ModuleSource>;
+}
+
+/// Resolves import paths to files: relative to the importing file, or to
+/// `root` for imports in the program being compiled.
+#[derive(Debug, Clone)]
+pub struct FileSystemResolver {
+    root: PathBuf,
+}
+
+impl FileSystemResolver {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl Default for FileSystemResolver {
+    fn default() -> Self {
+        Self::new(".")
+    }
+}
+
+impl ModuleResolver for FileSystemResolver {
+    fn resolve(&self, path: &str, importer: Option<&str>) -> Result<ModuleSource> {
+        let base = match importer {
+            Some(importer) => Path::new(importer).parent().unwrap_or(Path::new("")),
+            None => self.root.as_path(),
+        };
+        let file = base.join(path);
+        let source = std::fs::read_to_string(&file).map_err(|e| {
+            ModuleError::new(format!("Cannot read module '{}'", file.display())).with_source(e)
+        })?;
+        let name = file.canonicalize().unwrap_or(file);
+
+        Ok(ModuleSource {
+            name: name.to_string_lossy().into_owned(),
+            source,
+        })
+    }
+}
+
+/// Serves modules from a map of path to source. Paths are matched exactly,
+/// whichever module imports them.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryResolver {
+    modules: HashMap<String, String>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_module(mut self, path: impl Into<String>, source: impl Into<String>) -> Self {
+        self.modules.insert(path.into(), source.into());
+        self
+    }
+}
+
+impl ModuleResolver for MemoryResolver {
+    fn resolve(&self, path: &str, _importer: Option<&str>) -> Result<ModuleSource> {
+        match self.modules.get(path) {
+            Some(source) => Ok(ModuleSource {
+                name: path.to_string(),
+                source: source.clone(),
+            }),
+            None => Err(module_error(format!("No module named '{}'", path))),
+        }
+    }
+}
+
+/// Load every module that `program` imports, directly or through other
+/// modules, skipping those for which `loaded` returns true. Modules are
+/// returned in dependency order: each comes after the modules it imports.
+pub fn load_imports(
+    resolver: &dyn ModuleResolver,
+    program: &Program,
+    loaded: &dyn Fn(&str) -> bool,
+) -> Result<Vec<Module>> {
+    let mut loader = Loader {
+        resolver,
+        loaded,
+        roots: Vec::new(),
+        modules: Vec::new(),
+        stack: Vec::new(),
+    };
+    for import in &program.imports {
+        loader
+            .load(import, None)
+            .map_err(|e| e.with_span(import.span))?;
+    }
+    Ok(loader.modules)
+}
+
+/// Order the files of a project, already parsed as `roots`, and the modules
+/// they import so that each comes after the modules it imports. Roots are
+/// named as the resolver names modules, so that a root that another
+/// imports is only loaded once; unlike imported modules, roots may have
+/// script expressions. An import that fails is reported in the root that
+/// imports it.
+pub fn load_project(resolver: &dyn ModuleResolver, roots: Vec<Module>) -> Result<Vec<Module>> {
+    let mut loader = Loader {
+        resolver,
+        loaded: &|_| false,
+        roots,
+        modules: Vec::new(),
+        stack: Vec::new(),
+    };
+    while !loader.roots.is_empty() {
+        let root = loader.roots.remove(0);
+        loader.add(root).map_err(|(name, e)| in_module(&name, e))?;
+    }
+    Ok(loader.modules)
+}
+
+/// Errors for value types defined in more than one module of a project,
+/// and for functions defined in more than one with the same parameter types
+/// and no guards to choose between them. Each error is paired with the
+/// name of the module containing the later definition, which it locates.
+pub fn duplicate_definitions(modules: &[Module]) -> Vec<(String, Error)> {
+    // Where each value type and unguarded signature was first defined
+    let mut defined: HashMap<String, &str> = HashMap::new();
+    let mut errors = Vec::new();
+    for module in modules {
+        let mut seen = Vec::new();
+        for declaration in &module.program.declarations {
+            let (key, description) = match declaration {
+                Declaration::Value(value) => (value.name.clone(), format!("Value type '{}'", value.name)),
+                Declaration::Function(f) if f.parameters.iter().all(|p| p.guard.is_none()) => {
+                    let types: Vec<String> = f.parameters.iter().map(|p| p.ty.to_string()).collect();
+                    let signature = format!("{}({})", f.name, types.join(", "));
+                    (signature.clone(), format!("Function '{}'", signature))
+                }
+                _ => continue,
+            };
+            match defined.get(&key) {
+                Some(first) if *first != module.name => errors.push((
+                    module.name.clone(),
+                    Error::Type(TypeError {
+                        message: format!("{} is already defined in '{}'", description, first),
+                        span: Some(declaration.span()),
+                    }),
+                )),
+                _ => seen.push(key),
+            }
+        }
+        for key in seen {
+            defined.entry(key).or_insert(&module.name);
+        }
+    }
+    errors
+}
+
+struct Loader<'a> {
+    resolver: &'a dyn ModuleResolver,
+    loaded: &'a dyn Fn(&str) -> bool,
+    // Files of a project not loaded yet
+    roots: Vec<Module>,
+    modules: Vec<Module>,
+    // Names of the modules being loaded, to detect import cycles
+    stack: Vec<String>,
+}
+
+impl Loader<'_> {
+    fn load(&mut self, import: &Import, importer: Option<&str>) -> Result<()> {
+        let ModuleSource { name, source } = self.resolver.resolve(&import.path, importer)?;
+
+        if let Some(start) = self.stack.iter().position(|n| *n == name) {
+            let mut cycle = self.stack[start..].to_vec();
+            cycle.push(name);
+            return Err(module_error(format!("Import cycle: {}", cycle.join(" -> "))));
+        }
+        if (self.loaded)(&name) || self.modules.iter().any(|m| m.name == name) {
+            return Ok(());
+        }
+        if let Some(index) = self.roots.iter().position(|root| root.name == name) {
+            let root = self.roots.remove(index);
+            return self.add(root).map_err(|(_, e)| e);
+        }
+
+        let program = Parser::new(Lexer::new(source))
+            .and_then(|mut parser| parser.parse_program())
+            .map_err(|e| in_module(&name, e))?;
+        if !program.expressions.is_empty() {
+            return Err(module_error(format!(
+                "Module '{}' has top-level expressions; only declarations can be imported",
+                name
+            )));
+        }
+
+        self.stack.push(name.clone());
+        for import in &program.imports {
+            self.load(import, Some(&name))?;
+        }
+        self.stack.pop();
+
+        self.modules.push(Module { name, program });
+        Ok(())
+    }
+
+    /// Load the imports of a project file, then add it. Fails with the
+    /// error and the name of the module it should be reported in.
+    fn add(&mut self, root: Module) -> std::result::Result<(), (String, Error)> {
+        self.stack.push(root.name.clone());
+        for import in &root.program.imports {
+            self.load(import, Some(&root.name))
+                .map_err(|e| (root.name.clone(), e.with_span(import.span)))?;
+        }
+        self.stack.pop();
+        self.modules.push(root);
+        Ok(())
+    }
+}
+
+/// Report `error`, found in the module named `name`, from the file that
+/// imports it. Locations inside the module would point into the wrong file,
+/// so the error is kept as the cause rather than located.
+pub fn in_module(name: &str, error: Error) -> Error {
+    match error {
+        Error::Module(_) => error,
+        error => ModuleError::new(format!("In module '{}'", name)).with_source(error).into(),
+    }
+}
+
+fn module_error(message: String) -> Error {
+    ModuleError::new(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+
+    fn program(source: &str) -> Program {
+        Parser::new(Lexer::new(source.to_string()))
+            .and_then(|mut parser| parser.parse_program())
+            .unwrap()
+    }
+
+    fn names(modules: &[Module]) -> Vec<&str> {
+        modules.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_modules_load_in_dependency_order() {
+        let resolver = MemoryResolver::new()
+            .with_module("shapes", "import \"math\"\nfn area(w: Int) -> Int { square(w) }")
+            .with_module("math", "fn square(x: Int) -> Int { x * x }")
+            .with_module("io", "import \"math\"");
+
+        let modules = load_imports(
+            &resolver,
+            &program("import \"shapes\"; import \"io\"\narea(2)"),
+            &|_| false,
+        )
+        .unwrap();
+        assert_eq!(names(&modules), vec!["math", "shapes", "io"]);
+
+        let modules = load_imports(&resolver, &program("import \"shapes\""), &|n| n == "math").unwrap();
+        assert_eq!(names(&modules), vec!["shapes"]);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let resolver = MemoryResolver::new()
+            .with_module("a", "import \"b\"")
+            .with_module("b", "import \"a\"")
+            .with_module("broken", "fn f( -> Int { 1 }")
+            .with_module("script", "1 + 1");
+        let error = |source| match load_imports(&resolver, &program(source), &|_| false) {
+            Err(e @ Error::Module(_)) => {
+                let location = e.location().map(|(line, column, _)| (line, column));
+                (Diagnostic::from_error(&e).message, location)
+            }
+            other => panic!("Expected module error, got {:?}", other),
+        };
+
+        assert_eq!(error("\nimport \"missing\""), ("No module named 'missing'".to_string(), Some((2, 1))));
+        assert_eq!(error("import \"a\"").0, "Import cycle: a -> b -> a");
+        assert!(error("import \"broken\"").0.starts_with("In module 'broken': Parser error at 1:7"));
+        assert_eq!(
+            error("import \"script\"").0,
+            "Module 'script' has top-level expressions; only declarations can be imported"
+        );
+    }
+
+    #[test]
+    fn test_project() {
+        let resolver = MemoryResolver::new()
+            .with_module("math", "fn square(x: Int) -> Int { x * x }")
+            .with_module("shapes", "");
+        let root = |name: &str, source| Module {
+            name: name.to_string(),
+            program: program(source),
+        };
+
+        // A root imported by another is loaded once, as parsed, before it
+        let modules = load_project(
+            &resolver,
+            vec![root("app", "import \"shapes\"\nsquare(2)"), root("shapes", "import \"math\"")],
+        )
+        .unwrap();
+        assert_eq!(names(&modules), vec!["math", "shapes", "app"]);
+        assert_eq!(modules[1].program.imports.len(), 1);
+
+        let error = load_project(&resolver, vec![root("app", "import \"missing\"")]).unwrap_err();
+        assert_eq!(Diagnostic::from_error(&error).message, "No module named 'missing'");
+
+        let modules = [
+            root("a", "value Email(raw: String) {}\nfn f(x: Int) -> Int { x }"),
+            root("b", "fn f(x: Int where x > 0) -> Int { x }\nfn f(x: String) -> Int { 1 }"),
+            root("c", "value Email(raw: String) {}\nfn f(y: Int) -> Int { y }"),
+        ];
+        let duplicates: Vec<_> = duplicate_definitions(&modules)
+            .into_iter()
+            .map(|(name, e)| (name, Diagnostic::from_error(&e).message))
+            .collect();
+        assert_eq!(
+            duplicates,
+            vec![
+                ("c".to_string(), "Value type 'Email' is already defined in 'a'".to_string()),
+                ("c".to_string(), "Function 'f(Int)' is already defined in 'a'".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_system_resolver() {
+        let dir = std::env::temp_dir().join(format!("relic-modules-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/shapes.relic"), "import \"math.relic\"").unwrap();
+        std::fs::write(dir.join("lib/math.relic"), "fn square(x: Int) -> Int { x * x }").unwrap();
+
+        let resolver = FileSystemResolver::new(&dir);
+        let modules = load_imports(&resolver, &program("import \"lib/shapes.relic\""), &|_| false);
+        let missing = load_imports(&resolver, &program("import \"lib/nope.relic\""), &|_| false);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // The I/O error is kept as the cause
+        let cause = std::error::Error::source(&missing.unwrap_err())
+            .and_then(|cause| cause.downcast_ref::<std::io::Error>())
+            .map(|cause| cause.kind());
+        assert_eq!(cause, Some(std::io::ErrorKind::NotFound));
+
+        let modules = modules.unwrap();
+        assert_eq!(modules.len(), 2);
+        assert!(modules[0].name.ends_with("math.relic"));
+        assert!(modules[1].name.ends_with("shapes.relic"));
+    }
+}
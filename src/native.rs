@@ -0,0 +1,214 @@
+//! Functions written in Rust that Relic code calls like any other function.
+//! Each has a signature, so calls to it are type checked, and it takes part
+//! in multiple dispatch alongside overloads defined in Relic.
+
+use crate::ast::{Expression, FunctionDeclaration, Literal, ParameterWithGuard};
+use crate::convert::{FromEvalValue, IntoEvalValue};
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::EvalValue;
+use crate::lexer::Span;
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::fmt;
+use std::sync::Arc;
+
+/// The Rust side of a native function. It's given arguments that match the
+/// function's parameter types, and the registry it was called from.
+pub type NativeBody = Arc<dyn Fn(&[EvalValue], &ValueRegistry) -> Result<EvalValue> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct NativeFunction {
+    // The signature, with a placeholder body
+    declaration: FunctionDeclaration,
+    body: NativeBody,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.declaration.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NativeFunction {
+    /// A function taking `parameters`, as name and type pairs, and returning
+    /// `return_type`. For a closure over Rust types, use `from_fn` instead.
+    pub fn new(
+        name: &str,
+        parameters: &[(&str, Type)],
+        return_type: Type,
+        body: impl Fn(&[EvalValue], &ValueRegistry) -> Result<EvalValue> + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_body(name, parameters, return_type, Arc::new(body))
+    }
+
+    /// A function that calls `f`, with its signature taken from the types
+    /// of `f`'s parameters and result. Its parameters are named `arg1`,
+    /// `arg2` and so on. `f` may return a `Result` to report a failure.
+    pub fn from_fn<Args>(name: &str, f: impl IntoNativeFunction<Args>) -> Self {
+        let parameter_types = f.parameter_types();
+        let names: Vec<String> = (1..=parameter_types.len()).map(|i| format!("arg{}", i)).collect();
+        let parameters: Vec<(&str, Type)> = names.iter().map(String::as_str).zip(parameter_types).collect();
+        let return_type = f.return_type();
+        Self::with_body(name, &parameters, return_type, f.into_body())
+    }
+
+    fn with_body(name: &str, parameters: &[(&str, Type)], return_type: Type, body: NativeBody) -> Self {
+        let parameters = parameters
+            .iter()
+            .map(|(name, ty)| ParameterWithGuard {
+                name: name.to_string(),
+                ty: ty.clone(),
+                guard: None,
+            })
+            .collect();
+        Self {
+            declaration: FunctionDeclaration {
+                name: name.to_string(),
+                parameters,
+                return_type,
+                // Never evaluated; calls go to `body`
+                body: Expression::Literal(Literal::String("built-in".to_string())),
+                span: Span::default(),
+            },
+            body,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.declaration.name
+    }
+
+    /// The function's signature, as a declaration whose body is a
+    /// placeholder.
+    pub fn declaration(&self) -> &FunctionDeclaration {
+        &self.declaration
+    }
+
+    pub fn call(&self, args: &[EvalValue], registry: &ValueRegistry) -> Result<EvalValue> {
+        (self.body)(args, registry)
+    }
+
+    /// Whether this is the native body of `declaration`: they have the same
+    /// name and parameter types.
+    pub fn implements(&self, declaration: &FunctionDeclaration) -> bool {
+        self.declaration.name == declaration.name
+            && self.declaration.parameters.len() == declaration.parameters.len()
+            && self
+                .declaration
+                .parameters
+                .iter()
+                .zip(&declaration.parameters)
+                .all(|(a, b)| a.ty == b.ty)
+    }
+}
+
+/// A Rust closure that can be called from Relic: one whose parameters are
+/// `FromEvalValue` and whose result is `IntoEvalValue`, or a `Result` of
+/// one. `Args` is the tuple of parameter types, which tells the
+/// implementations for each number of parameters apart.
+pub trait IntoNativeFunction<Args> {
+    fn parameter_types(&self) -> Vec<Type>;
+    fn return_type(&self) -> Type;
+    fn into_body(self) -> NativeBody;
+}
+
+/// What a native closure can return: a value, or a `Result` of one.
+pub trait NativeReturn {
+    fn return_type() -> Type;
+    fn into_result(self) -> Result<EvalValue>;
+}
+
+impl<T: IntoEvalValue> NativeReturn for T {
+    fn return_type() -> Type {
+        T::relic_type()
+    }
+
+    fn into_result(self) -> Result<EvalValue> {
+        Ok(self.into_eval_value())
+    }
+}
+
+impl<T: IntoEvalValue> NativeReturn for Result<T> {
+    fn return_type() -> Type {
+        T::relic_type()
+    }
+
+    fn into_result(self) -> Result<EvalValue> {
+        self.map(IntoEvalValue::into_eval_value)
+    }
+}
+
+macro_rules! impl_into_native_function {
+    ($count:expr $(, $arg:ident)*) => {
+        impl<F, R, $($arg,)*> IntoNativeFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: NativeReturn,
+            $($arg: FromEvalValue,)*
+        {
+            fn parameter_types(&self) -> Vec<Type> {
+                vec![$($arg::relic_type()),*]
+            }
+
+            fn return_type(&self) -> Type {
+                R::return_type()
+            }
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_body(self) -> NativeBody {
+                Arc::new(move |args: &[EvalValue], _: &ValueRegistry| {
+                    if args.len() != $count {
+                        return Err(arity_error($count, args.len()));
+                    }
+                    let mut args = args.iter().cloned();
+                    $(let $arg = $arg::from_eval_value(args.next().ok_or_else(|| arity_error($count, 0))?)?;)*
+                    self($($arg),*).into_result()
+                })
+            }
+        }
+    };
+}
+
+impl_into_native_function!(0);
+impl_into_native_function!(1, A);
+impl_into_native_function!(2, A, B);
+impl_into_native_function!(3, A, B, C);
+impl_into_native_function!(4, A, B, C, D);
+
+fn arity_error(expected: usize, found: usize) -> Error {
+    Error::Validation(ValidationError {
+        message: format!("Native function expects {} arguments, got {}", expected, found),
+        value_type: "function".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_fn() {
+        let registry = ValueRegistry::new();
+        let repeat = NativeFunction::from_fn("repeat", |s: String, n: i64| s.repeat(n as usize));
+        let parameters: Vec<_> = repeat.declaration().parameters.iter().map(|p| p.ty.clone()).collect();
+        assert_eq!(parameters, vec![Type::String, Type::Int]);
+        assert_eq!(repeat.declaration().return_type, Type::String);
+        let args = [EvalValue::String("ab".to_string()), EvalValue::Integer(2)];
+        assert_eq!(repeat.call(&args, &registry).unwrap(), EvalValue::String("abab".to_string()));
+
+        let half = NativeFunction::from_fn("half", |n: i64| -> Result<i64> {
+            if n % 2 == 0 {
+                Ok(n / 2)
+            } else {
+                Err(Error::Validation(ValidationError {
+                    message: format!("{} is odd", n),
+                    value_type: "Int".to_string(),
+                }))
+            }
+        });
+        assert!(half.call(&[EvalValue::Integer(3)], &registry).is_err());
+        assert!(half.call(&[], &registry).is_err());
+    }
+}
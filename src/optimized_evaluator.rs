@@ -1,6 +1,6 @@
 use crate::ast::*;
-use crate::error::{Error, Result, ValidationError};
-use crate::evaluator::{EvalValue, evaluate_expression};
+use crate::error::Result;
+use crate::evaluator::{binary_operation, evaluate_expression, EvalValue};
 use crate::specialization::SpecializationCache;
 use crate::types::Type;
 use crate::value::ValueRegistry;
@@ -15,6 +15,12 @@ pub fn evaluate_expression_optimized(
     type_env: &HashMap<String, Type>,
 ) -> Result<EvalValue> {
     match expr {
+        Expression::FunctionCall(_, args) if args.iter().any(|arg| matches!(arg, Expression::Lambda(..))) => {
+            // A function passed to a builtin such as `exists` or `where` is
+            // evaluated once per row, not as an argument
+            evaluate_expression(expr, context, registry)
+        }
+
         Expression::FunctionCall(name, args) => {
             // Evaluate arguments first
             let mut arg_values = Vec::new();
@@ -34,6 +40,9 @@ pub fn evaluate_expression_optimized(
                 // Use the pre-computed best function directly
                 if let Some(functions) = registry.get_functions(name) {
                     if let Some(func) = functions.get(specialized_idx) {
+                        if let Some(native) = registry.native(func) {
+                            return native.call(&arg_values, registry);
+                        }
                         // Fast path: directly call the specialized function
                         let mut func_context = HashMap::new();
                         for (param, value) in func.parameters.iter().zip(arg_values.iter()) {
@@ -72,6 +81,9 @@ pub fn evaluate_expression_optimized(
             if let Some(specialized_idx) = specialization_cache.get_specialization(method_name, &all_types) {
                 if let Some(functions) = registry.get_functions(method_name) {
                     if let Some(func) = functions.get(specialized_idx) {
+                        if let Some(native) = registry.native(func) {
+                            return native.call(&arg_values, registry);
+                        }
                         let mut func_context = HashMap::new();
                         for (param, value) in func.parameters.iter().zip(arg_values.iter()) {
                             func_context.insert(param.name.clone(), value.clone());
@@ -83,18 +95,16 @@ pub fn evaluate_expression_optimized(
                 }
             }
 
-            // Fall back to dynamic dispatch
-            let mut call_args = args.to_vec();
-            call_args.insert(0, *receiver.clone());
-            let function_call = Expression::FunctionCall(method_name.clone(), call_args);
-            evaluate_expression(&function_call, context, registry)
+            // Fall back to resolving the method as the evaluator does,
+            // which may find a built-in method rather than a function
+            evaluate_expression(expr, context, registry)
         }
         
         // For other expression types, recurse with optimization
         Expression::Binary(op, left, right) => {
             let left_val = evaluate_expression_optimized(left, context, registry, specialization_cache, type_env)?;
             let right_val = evaluate_expression_optimized(right, context, registry, specialization_cache, type_env)?;
-            evaluate_binary_op(op, left_val, right_val)
+            binary_operation(op, left_val, right_val, registry)
         }
         
         Expression::Let(name, binding, body) => {
@@ -128,51 +138,6 @@ fn evaluate_function_call(
     evaluate_expression(&function_call, context, registry)
 }
 
-/// Evaluate binary operations
-fn evaluate_binary_op(op: &BinaryOp, left: EvalValue, right: EvalValue) -> Result<EvalValue> {
-    match (op, left, right) {
-        (BinaryOp::Add, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-            Ok(EvalValue::Integer(l + r))
-        }
-        (BinaryOp::Subtract, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-            Ok(EvalValue::Integer(l - r))
-        }
-        (BinaryOp::Multiply, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-            Ok(EvalValue::Integer(l * r))
-        }
-        (BinaryOp::Divide, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-            if r != 0 {
-                Ok(EvalValue::Integer(l / r))
-            } else {
-                Err(Error::Validation(ValidationError {
-                    message: "Division by zero".to_string(),
-                    value_type: "".to_string(),
-                }))
-            }
-        }
-        (BinaryOp::Modulo, EvalValue::Integer(l), EvalValue::Integer(r)) => {
-            if r != 0 {
-                Ok(EvalValue::Integer(l % r))
-            } else {
-                Err(Error::Validation(ValidationError {
-                    message: "Modulo by zero".to_string(),
-                    value_type: "".to_string(),
-                }))
-            }
-        }
-        (BinaryOp::And, EvalValue::Boolean(l), EvalValue::Boolean(r)) => {
-            Ok(EvalValue::Boolean(l && r))
-        }
-        (BinaryOp::Or, EvalValue::Boolean(l), EvalValue::Boolean(r)) => {
-            Ok(EvalValue::Boolean(l || r))
-        }
-        _ => Err(Error::Validation(ValidationError {
-            message: "Type mismatch in binary operation".to_string(),
-            value_type: "".to_string(),
-        })),
-    }
-}
-
 /// Infer type from runtime value
 fn type_from_value(value: &EvalValue) -> Type {
     match value {
@@ -182,6 +147,11 @@ fn type_from_value(value: &EvalValue) -> Type {
         EvalValue::Value { type_name, .. } => Type::Value(type_name.clone()),
         EvalValue::Type(_) => Type::Type,
         EvalValue::List(_) => Type::List(Box::new(Type::Any)), // TODO: Infer element type
+        EvalValue::Option(_) => Type::Option(Box::new(Type::Any)),
+        EvalValue::Relation(_) => Type::Relation,
+        EvalValue::Temporal(t) => t.type_of(),
+        EvalValue::Decimal(_) => Type::Decimal,
+        EvalValue::Query(_) => Type::Query(Box::new(Type::Any)),
     }
 }
 
@@ -222,12 +192,17 @@ mod tests {
     fn test_optimized_function_call() {
         // This is a placeholder test - in a real implementation,
         // we would test that specialized calls are faster than dynamic dispatch
-        let _cache = SpecializationCache::new();
-        let _registry = ValueRegistry::new();
-        let _context: HashMap<String, EvalValue> = HashMap::new();
-        let _type_env: HashMap<String, Type> = HashMap::new();
-        
-        // Would test optimization here
-        assert!(true);
+        let cache = SpecializationCache::new();
+        let registry = ValueRegistry::new();
+        let context: HashMap<String, EvalValue> = HashMap::new();
+        let type_env: HashMap<String, Type> = HashMap::new();
+
+        let expr = Expression::Binary(
+            BinaryOp::Add,
+            Box::new(Expression::Literal(Literal::Integer(2))),
+            Box::new(Expression::Literal(Literal::Integer(3))),
+        );
+        let result = evaluate_expression_optimized(&expr, &context, &registry, &cache, &type_env);
+        assert!(matches!(result, Ok(EvalValue::Integer(5))));
     }
 }
\ No newline at end of file
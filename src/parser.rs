@@ -1,87 +1,315 @@
 use crate::ast::*;
+use crate::diagnostic::{Diagnostic, Severity};
 use crate::error::{Error, ParserError, Result};
-use crate::lexer::{Lexer, Token};
+use crate::formatter::{comparison_token, format_expression};
+use crate::lexer::{Lexer, Span, Token};
+use crate::template::Template;
 use crate::types::Type;
 
+/// Default limit on expression nesting. Each level costs several stack
+/// frames, so this keeps even an unoptimized build well within the 2 MiB
+/// stack of a spawned thread, while being far beyond anything written by hand.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+// The clauses of a value body that are names rather than keywords
+const VALUE_CLAUSES: [&str; 4] = ["display", "sensitive", "coerce", "convert"];
+
 pub struct Parser {
     lexer: Lexer,
     pub current_token: Token,
-    line: usize,
-    column: usize,
+    current_span: Span,
+    // Span of the most recently consumed token, used to close declaration spans
+    previous_span: Span,
+    // Errors recorded while recovering, in source order
+    diagnostics: Vec<Error>,
+    // Suspicious but valid code
+    warnings: Vec<Diagnostic>,
+    // Set while parsing a whole program, where some errors can be recorded
+    // and parsed past instead of stopping at them
+    recovering: bool,
+    // Current and maximum expression nesting, to bound recursion
+    depth: usize,
+    max_depth: usize,
+    // Number of `{` consumed minus `}` consumed, used to find synchronization points
+    brace_depth: usize,
+    // Incremented on every attempt to advance, so recovery can detect lack of progress
+    advance_count: usize,
 }
 
 impl Parser {
     pub fn new(mut lexer: Lexer) -> Result<Self> {
-        let current_token = lexer.next_token()?;
+        let (current_token, current_span) = lexer.next_spanned_token()?;
         Ok(Parser {
             lexer,
             current_token,
-            line: 1,
-            column: 1,
+            current_span,
+            previous_span: Span::default(),
+            diagnostics: Vec::new(),
+            warnings: Vec::new(),
+            recovering: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            brace_depth: 0,
+            advance_count: 0,
         })
     }
 
+    /// Limit how deeply expressions may nest before parsing fails.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Location of the token the parser is currently looking at.
+    pub fn current_span(&self) -> Span {
+        self.current_span
+    }
+
+    /// Warnings found so far, in source order.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
     pub fn parse_program(&mut self) -> Result<Program> {
+        let (program, mut errors) = self.parse_program_recovering();
+        if errors.is_empty() {
+            Ok(program)
+        } else {
+            Err(errors.remove(0))
+        }
+    }
+
+    /// Parse a whole program without stopping at the first syntax error.
+    ///
+    /// After an error the parser skips ahead to the next synchronization point
+    /// (the start of a declaration, or the next clause/closing brace inside a
+    /// value body) and keeps going, so every error in the file is reported in
+    /// one pass. Declarations that parsed cleanly are still returned.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "parse", level = "debug", skip_all))]
+    pub fn parse_program_recovering(&mut self) -> (Program, Vec<Error>) {
+        let mut imports = Vec::new();
         let mut declarations = Vec::new();
+        let mut expressions = Vec::new();
+        let mut tests = Vec::new();
+        let mut data = Vec::new();
+        self.recovering = true;
 
         while self.current_token != Token::Eof {
-            declarations.push(self.parse_declaration()?);
+            let start = self.advance_count;
+            self.brace_depth = 0;
+            let parsed = if self.current_token == Token::Import {
+                self.parse_import().map(|i| imports.push(i))
+            } else if self.current_token == Token::Test {
+                self.parse_test().map(|t| tests.push(t))
+            } else if self.current_token == Token::Data {
+                self.parse_data().map(|d| data.push(d))
+            } else if self.at_declaration_boundary() {
+                self.parse_declaration().map(|d| declarations.push(d))
+            } else {
+                self.parse_top_level_expression().map(|e| expressions.push(e))
+            };
+            match parsed {
+                Ok(()) => {}
+                Err(e) => {
+                    self.diagnostics.push(e);
+                    if self.advance_count == start {
+                        // Nothing was consumed; skip the offending token
+                        self.skip_token();
+                    }
+                    self.synchronize_declaration();
+                }
+            }
+        }
+
+        self.recovering = false;
+        let mut errors = std::mem::take(&mut self.diagnostics);
+        // Lexer errors hit while skipping can be recorded before the parser
+        // error that triggered the skip
+        errors.sort_by_key(|e| match e {
+            Error::Lexer(e) => (e.line, e.column),
+            Error::Parser(e) => (e.line, e.column),
+            _ => (0, 0),
+        });
+
+        (Program { imports, declarations, expressions, tests, data }, errors)
+    }
+
+    /// Parse `import "path"`, with an optional `;` after it.
+    fn parse_import(&mut self) -> Result<Import> {
+        let start = self.current_span;
+        self.expect(Token::Import)?;
+        let path = match &self.current_token {
+            Token::String(path) => path.clone(),
+            _ => {
+                return Err(self.error(format!(
+                    "Expected a quoted path after 'import', as in import \"shapes.relic\", found {:?}",
+                    self.current_token
+                )))
+            }
+        };
+        self.advance()?;
+        let span = start.to(self.previous_span);
+        if self.current_token == Token::Semicolon {
+            self.advance()?;
+        }
+        Ok(Import { path, span })
+    }
+
+    /// Parse `test "name" { body }`.
+    fn parse_test(&mut self) -> Result<TestDeclaration> {
+        let start = self.current_span;
+        self.expect(Token::Test)?;
+        let name = match &self.current_token {
+            Token::String(name) => name.clone(),
+            _ => {
+                return Err(self.error(format!(
+                    "Expected a quoted name after 'test', as in test \"emails need an @\" {{ ... }}, found {:?}",
+                    self.current_token
+                )))
+            }
+        };
+        self.advance()?;
+        self.expect(Token::LeftBrace)?;
+        let body = self.parse_block()?;
+        self.expect(Token::RightBrace)?;
+        Ok(TestDeclaration {
+            name,
+            body,
+            span: start.to(self.previous_span),
+        })
+    }
+
+    /// Parse `data Type { Type(...), ... }`, with optional commas between
+    /// the entries.
+    fn parse_data(&mut self) -> Result<DataDeclaration> {
+        let start = self.current_span;
+        self.expect(Token::Data)?;
+        let type_name = match &self.current_token {
+            Token::Identifier(name) => name.clone(),
+            _ => {
+                return Err(self.error(format!(
+                    "Expected a value type name after 'data', as in data User {{ User(\"alice\") }}, found {:?}",
+                    self.current_token
+                )))
+            }
+        };
+        self.advance()?;
+        self.expect(Token::LeftBrace)?;
+
+        let mut entries = Vec::new();
+        while self.current_token != Token::RightBrace && self.current_token != Token::Eof {
+            let entry_start = self.current_span;
+            let expression = self.parse_expression()?;
+            entries.push(TopLevelExpression {
+                expression,
+                span: entry_start.to(self.previous_span),
+                binding: None,
+            });
+            if self.current_token == Token::Comma {
+                self.advance()?;
+            }
         }
 
-        Ok(Program { declarations })
+        self.expect(Token::RightBrace)?;
+        Ok(DataDeclaration {
+            type_name,
+            entries,
+            span: start.to(self.previous_span),
+        })
+    }
+
+    /// Parse a script expression at the top level of a file. A `;` may follow
+    /// it, to keep it apart from a next expression that could otherwise
+    /// continue it. `let name = value` without `in` binds the value for the
+    /// expressions after it.
+    fn parse_top_level_expression(&mut self) -> Result<TopLevelExpression> {
+        let start = self.current_span;
+        let mut binding = None;
+        let expression = if self.current_token == Token::Let {
+            self.advance()?;
+            let name = self.expect_identifier("variable name")?;
+            self.expect(Token::Assign)?;
+            let value = self.parse_expression()?;
+            if self.current_token == Token::In {
+                self.advance()?;
+                let body = self.parse_expression()?;
+                Expression::Let(name, Box::new(value), Box::new(body))
+            } else {
+                binding = Some(name);
+                value
+            }
+        } else {
+            self.parse_expression()?
+        };
+        let span = start.to(self.previous_span);
+        if self.current_token == Token::Semicolon {
+            self.advance()?;
+        }
+        Ok(TopLevelExpression { expression, span, binding })
+    }
+
+    /// Skip tokens until the start of the next declaration.
+    fn synchronize_declaration(&mut self) {
+        while !self.at_declaration_boundary() {
+            self.skip_token();
+        }
+    }
+
+    /// Skip tokens until the next clause keyword or the closing brace of the
+    /// value body opened at `depth`. Returns false if a declaration boundary
+    /// was reached first, meaning the body itself is unterminated.
+    fn synchronize_value_clause(&mut self, depth: usize) -> bool {
+        loop {
+            if self.at_declaration_boundary() {
+                return false;
+            }
+            if self.brace_depth == depth
+                && (matches!(
+                    self.current_token,
+                    Token::Validate | Token::Normalize | Token::Unique | Token::RightBrace
+                ) || matches!(&self.current_token, Token::Identifier(name) if VALUE_CLAUSES.contains(&name.as_str())))
+            {
+                return true;
+            }
+            self.skip_token();
+        }
+    }
+
+    fn at_declaration_boundary(&self) -> bool {
+        // `value`, `fn`, `method`, `import`, `test` and `data` can only start
+        // a declaration
+        matches!(
+            self.current_token,
+            Token::Value | Token::Fn | Token::Method | Token::Import | Token::Test | Token::Data | Token::Eof
+        )
+    }
+
+    /// Advance during recovery, recording rather than propagating lexer errors.
+    fn skip_token(&mut self) {
+        if let Err(e) = self.advance() {
+            self.diagnostics.push(e);
+        }
     }
 
     fn parse_declaration(&mut self) -> Result<Declaration> {
         match &self.current_token {
             Token::Value => Ok(Declaration::Value(self.parse_value_declaration()?)),
             Token::Fn => Ok(Declaration::Function(self.parse_function_declaration()?)),
-            Token::Method => {
-                // Treat 'method' as an alias for 'fn' - parse it as a function
-                self.advance()?; // consume 'method' token
-                let name = self.expect_identifier()?;
-                self.expect(Token::LeftParen)?;
-                
-                let mut parameters = Vec::new();
-                while self.current_token != Token::RightParen {
-                    // Support parameter guards for unified syntax
-                    parameters.push(self.parse_parameter_with_guard()?);
-                    if self.current_token == Token::Comma {
-                        self.advance()?;
-                    } else if self.current_token != Token::RightParen {
-                        return Err(Error::Parser(ParserError {
-                            message: "Expected ',' or ')' after parameter".to_string(),
-                            line: self.line,
-                            column: self.column,
-                        }));
-                    }
-                }
-                
-                self.expect(Token::RightParen)?;
-                self.expect(Token::ReturnArrow)?;
-                let return_type = self.parse_type()?;
-                self.expect(Token::LeftBrace)?;
-                let body = self.parse_expression()?;
-                self.expect(Token::RightBrace)?;
-                
-                Ok(Declaration::Function(FunctionDeclaration {
-                    name,
-                    parameters,
-                    return_type,
-                    body,
-                }))
-            },
-            _ => Err(Error::Parser(ParserError {
-                message: format!("Expected 'value', 'fn', or 'method' keyword, found {:?}", self.current_token),
-                line: self.line,
-                column: self.column,
-            })),
+            // 'method' is an alias for 'fn': a method is parsed as one and
+            // declared as the function it is
+            Token::Method => Ok(Declaration::Function(self.parse_method_declaration()?.into())),
+            _ => Err(self.error(format!(
+                "Expected 'value', 'fn', or 'method' keyword, found {:?}",
+                self.current_token
+            ))),
         }
     }
 
     fn parse_value_declaration(&mut self) -> Result<ValueDeclaration> {
+        let start = self.current_span;
         self.expect(Token::Value)?;
 
-        let name = self.expect_identifier()?;
+        let name = self.expect_identifier("value type name")?;
 
         self.expect(Token::LeftParen)?;
         let parameter = self.parse_parameter()?;
@@ -95,34 +323,20 @@ impl Parser {
             name,
             parameter,
             body,
+            span: start.to(self.previous_span),
         })
     }
 
     fn parse_function_declaration(&mut self) -> Result<FunctionDeclaration> {
+        let start = self.current_span;
         self.expect(Token::Fn)?;
-        let name = self.expect_identifier()?;
+        let name = self.expect_identifier("function name")?;
         self.expect(Token::LeftParen)?;
-        
-        let mut parameters = Vec::new();
-        while self.current_token != Token::RightParen {
-            // Support parameter guards for unified syntax
-            parameters.push(self.parse_parameter_with_guard()?);
-            if self.current_token == Token::Comma {
-                self.advance()?;
-            } else if self.current_token != Token::RightParen {
-                return Err(Error::Parser(ParserError {
-                    message: "Expected ',' or ')' after parameter".to_string(),
-                    line: self.line,
-                    column: self.column,
-                }));
-            }
-        }
-        
-        self.expect(Token::RightParen)?;
+        let parameters = self.parse_parameters()?;
         self.expect(Token::ReturnArrow)?;
         let return_type = self.parse_type()?;
         self.expect(Token::LeftBrace)?;
-        let body = self.parse_expression()?;
+        let body = self.parse_block()?;
         self.expect(Token::RightBrace)?;
         
         Ok(FunctionDeclaration {
@@ -130,54 +344,66 @@ impl Parser {
             parameters,
             return_type,
             body,
+            span: start.to(self.previous_span),
         })
     }
 
     fn parse_method_declaration(&mut self) -> Result<MethodDeclaration> {
+        let start = self.current_span;
         self.expect(Token::Method)?;
-        let name = self.expect_identifier()?;
+        let name = self.expect_identifier("method name")?;
         self.expect(Token::LeftParen)?;
-        
-        let mut parameters = Vec::new();
-        while self.current_token != Token::RightParen {
-            parameters.push(self.parse_parameter_with_guard()?);
-            if self.current_token == Token::Comma {
-                self.advance()?;
-            } else if self.current_token != Token::RightParen {
-                return Err(Error::Parser(ParserError {
-                    message: "Expected ',' or ')' after parameter".to_string(),
-                    line: self.line,
-                    column: self.column,
-                }));
-            }
-        }
-        
-        self.expect(Token::RightParen)?;
+        let parameters = self.parse_parameters()?;
         self.expect(Token::ReturnArrow)?;
         let return_type = self.parse_type()?;
         self.expect(Token::LeftBrace)?;
-        let body = self.parse_expression()?;
+        let body = self.parse_block()?;
         self.expect(Token::RightBrace)?;
-        
+
         Ok(MethodDeclaration {
             name,
             parameters,
             return_type,
             body,
+            span: start.to(self.previous_span),
         })
     }
 
-
     fn parse_parameter(&mut self) -> Result<Parameter> {
-        let name = self.expect_identifier()?;
+        let name = self.expect_identifier("parameter name")?;
         self.expect(Token::Colon)?;
         let ty = self.parse_type()?;
+        let unit = if self.current_token == Token::Identifier("unit".to_string()) {
+            self.advance()?;
+            match self.current_token.clone() {
+                Token::String(unit) => {
+                    self.advance()?;
+                    Some(unit)
+                }
+                token => return Err(self.error(format!("Expected the unit's name in quotes, found {:?}", token))),
+            }
+        } else {
+            None
+        };
+
+        Ok(Parameter { name, ty, unit })
+    }
 
-        Ok(Parameter { name, ty })
+    /// Parse a parameter list up to and including the closing parenthesis.
+    fn parse_parameters(&mut self) -> Result<Vec<ParameterWithGuard>> {
+        let mut parameters = Vec::new();
+        while self.current_token != Token::RightParen {
+            // Support parameter guards for unified syntax
+            parameters.push(self.parse_parameter_with_guard()?);
+            self.list_separator("parameter", |token| matches!(token, Token::Identifier(_)))?;
+        }
+
+        self.expect(Token::RightParen)?;
+        Ok(parameters)
     }
 
     fn parse_parameter_with_guard(&mut self) -> Result<ParameterWithGuard> {
-        let name = self.expect_identifier()?;
+        let name = self.expect_identifier("parameter name")?;
         self.expect(Token::Colon)?;
         let ty = self.parse_type()?;
         
@@ -191,78 +417,238 @@ impl Parser {
         Ok(ParameterWithGuard { name, ty, guard })
     }
 
+    /// Parse a type: a name, optionally followed by type arguments in
+    /// square brackets, as in `List[Int]`.
     fn parse_type(&mut self) -> Result<Type> {
-        match &self.current_token {
-            Token::Identifier(name) => {
-                let ty = match name.as_str() {
-                    "String" => Type::String,
-                    "Int" => Type::Int,
-                    "Bool" => Type::Bool,
-                    "Any" => Type::Any,
-                    "Type" => Type::Type,
-                    _ => Type::Value(name.clone()),
-                };
-                self.advance()?;
-                Ok(ty)
+        let start = self.current_span;
+        let name = match &self.current_token {
+            Token::Identifier(name) => name.clone(),
+            token if is_keyword(token) => {
+                return Err(self.error(format!("'{}' is a reserved keyword, not a type", token)))
+            }
+            _ => return Err(self.error(format!("Expected type name, found {:?}", self.current_token))),
+        };
+        self.advance()?;
+
+        match self.current_token {
+            Token::LeftBracket => {
+                let arguments = self.parse_type_arguments()?;
+                self.generic_type(&name, arguments, start)
+            }
+            Token::Less => Err(self.error(format!(
+                "Type arguments go in square brackets, as in {}[...]",
+                name
+            ))),
+            _ => Ok(match name.as_str() {
+                "String" => Type::String,
+                "Int" => Type::Int,
+                "Bool" => Type::Bool,
+                "Any" => Type::Any,
+                "Type" => Type::Type,
+                "Relation" => Type::Relation,
+                "Date" => Type::Date,
+                "Time" => Type::Time,
+                "Duration" => Type::Duration,
+                "Decimal" => Type::Decimal,
+                "List" | "Option" | "Query" => {
+                    return Err(self.error_at(
+                        start,
+                        format!("'{0}' needs an element type, as in {0}[Int]", name),
+                    ))
+                }
+                _ => Type::Value(name),
+            }),
+        }
+    }
+
+    /// Parse `[T, ...]` after a type name, up to and including the `]`.
+    fn parse_type_arguments(&mut self) -> Result<Vec<Type>> {
+        let open = self.current_span;
+        self.advance()?;
+
+        let mut arguments = Vec::new();
+        while self.current_token != Token::RightBracket {
+            arguments.push(self.nested(Self::parse_type)?);
+            match self.current_token {
+                Token::Comma => self.advance()?,
+                Token::RightBracket => {}
+                _ => {
+                    return Err(self.error(format!(
+                        "Expected ']' to close the '[' at {}:{}, found {:?}",
+                        open.line, open.column, self.current_token
+                    )))
+                }
             }
-            _ => Err(Error::Parser(ParserError {
-                message: format!("Expected type name, found {:?}", self.current_token),
-                line: self.line,
-                column: self.column,
-            })),
+        }
+
+        self.expect(Token::RightBracket)?;
+        Ok(arguments)
+    }
+
+    /// The type `name[arguments]`. Only `List`, `Option` and `Query` take
+    /// type arguments so far.
+    fn generic_type(&self, name: &str, mut arguments: Vec<Type>, span: Span) -> Result<Type> {
+        match name {
+            "List" if arguments.len() == 1 => Ok(Type::List(Box::new(arguments.remove(0)))),
+            "Option" if arguments.len() == 1 => Ok(Type::Option(Box::new(arguments.remove(0)))),
+            "Query" if arguments.len() == 1 => Ok(Type::Query(Box::new(arguments.remove(0)))),
+            "List" | "Option" | "Query" => Err(self.error_at(
+                span,
+                format!("'{}' takes 1 type argument, found {}", name, arguments.len()),
+            )),
+            _ => Err(self.error_at(
+                span,
+                format!("'{}' does not take type arguments; only List, Option and Query do", name),
+            )),
         }
     }
 
     fn parse_value_body(&mut self) -> Result<ValueBody> {
-        let mut validate = None;
-        let mut normalize = None;
-        let mut unique = None;
+        let mut body = ValueBody {
+            validate: None,
+            normalize: None,
+            unique: None,
+            display: None,
+            sensitive: None,
+            coerce: None,
+            convert: Vec::new(),
+        };
+        let depth = self.brace_depth;
 
         while self.current_token != Token::RightBrace {
-            match &self.current_token {
-                Token::Validate => {
-                    self.advance()?;
-                    self.expect(Token::Colon)?;
-                    validate = Some(self.parse_expression()?);
+            let start = self.advance_count;
+            if let Err(e) = self.parse_value_clause(&mut body) {
+                // Recover at the next clause so later mistakes in the body are reported too
+                if self.advance_count == start && !self.at_declaration_boundary() {
+                    self.skip_token();
                 }
-                Token::Normalize => {
-                    self.advance()?;
-                    self.expect(Token::Colon)?;
-                    normalize = Some(self.parse_expression()?);
+                if !self.synchronize_value_clause(depth) {
+                    return Err(e);
                 }
-                Token::Unique => {
-                    self.advance()?;
-                    self.expect(Token::Colon)?;
-                    unique = Some(self.parse_boolean()?);
+                self.diagnostics.push(e);
+            }
+        }
+
+        Ok(body)
+    }
+
+    fn parse_value_clause(&mut self, body: &mut ValueBody) -> Result<()> {
+        match &self.current_token {
+            Token::Validate => {
+                self.advance()?;
+                self.expect(Token::Colon)?;
+                body.validate = Some(self.parse_expression()?);
+            }
+            Token::Normalize => {
+                self.advance()?;
+                self.expect(Token::Colon)?;
+                body.normalize = Some(self.parse_expression()?);
+            }
+            Token::Unique => {
+                self.advance()?;
+                self.expect(Token::Colon)?;
+                body.unique = Some(self.parse_boolean()?);
+            }
+            // Not keywords, so that `display`, `sensitive`, `coerce` and
+            // `convert` can still name things
+            Token::Identifier(name) if name == "display" => {
+                self.advance()?;
+                self.expect(Token::Colon)?;
+                let Token::String(template) = self.current_token.clone() else {
+                    return Err(self.error(format!(
+                        "Expected a quoted template after 'display:', as in display: \"#{{amount}} USD\", found {:?}",
+                        self.current_token
+                    )));
+                };
+                if let Err(message) = Template::parse(&template) {
+                    return Err(self.error(format!("Invalid display template: {}", message)));
                 }
-                _ => {
-                    return Err(Error::Parser(ParserError {
-                        message: format!(
-                            "Expected 'validate', 'normalize', or 'unique', found {:?}",
-                            self.current_token
-                        ),
-                        line: self.line,
-                        column: self.column,
-                    }))
+                self.advance()?;
+                body.display = Some(template);
+            }
+            Token::Identifier(name) if name == "sensitive" => {
+                self.advance()?;
+                self.expect(Token::Colon)?;
+                body.sensitive = Some(self.parse_boolean()?);
+            }
+            Token::Identifier(name) if name == "coerce" => {
+                self.advance()?;
+                self.expect(Token::Colon)?;
+                body.coerce = Some(self.parse_boolean()?);
+            }
+            Token::Identifier(name) if name == "convert" => {
+                self.advance()?;
+                self.expect(Token::Colon)?;
+                body.convert.push(self.expect_identifier("conversion function")?);
+                while self.current_token == Token::Comma {
+                    self.advance()?;
+                    body.convert.push(self.expect_identifier("conversion function")?);
                 }
             }
+            _ => {
+                return Err(self.error(format!(
+                    "Expected 'validate', 'normalize', 'unique', 'display', 'sensitive', 'coerce' or 'convert', \
+                     found {:?}",
+                    self.current_token
+                )))
+            }
         }
-
-        Ok(ValueBody {
-            validate,
-            normalize,
-            unique,
-        })
+        Ok(())
     }
 
     pub fn parse_expression(&mut self) -> Result<Expression> {
-        self.parse_pipeline_expression()
+        if self.depth == 0 {
+            return self.parse_outermost_expression();
+        }
+        self.nested(Self::parse_pipeline_expression)
+    }
+
+    /// Parse an expression that is not part of another, and bound the depth
+    /// of its whole tree. Each chain is bounded as it is parsed, but a chain
+    /// of chains, such as `a * b + c * d`, can still nest deeper than one,
+    /// and checking and evaluating the tree recurse.
+    fn parse_outermost_expression(&mut self) -> Result<Expression> {
+        let start = self.current_span;
+        let expr = self.nested(Self::parse_pipeline_expression)?;
+        if expr.deeper_than(self.max_depth) {
+            return Err(self.error_at(start, self.too_deep()));
+        }
+        Ok(expr)
+    }
+
+    /// Run a parsing function one nesting level deeper, failing with a parse
+    /// error rather than overflowing the stack on pathologically nested input.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= self.max_depth {
+            return Err(self.error(self.too_deep()));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Count one more link of a chain such as `a + b + c` or `x?.y?.z`.
+    /// Chains are built in loops rather than by recursing, but their trees
+    /// are as deep as they are long.
+    fn link(&self, links: &mut usize) -> Result<()> {
+        *links += 1;
+        if self.depth + *links > self.max_depth {
+            return Err(self.error(self.too_deep()));
+        }
+        Ok(())
+    }
+
+    fn too_deep(&self) -> String {
+        format!("Expression is nested too deeply (the limit is {} levels)", self.max_depth)
     }
 
     fn parse_pipeline_expression(&mut self) -> Result<Expression> {
         let mut left = self.parse_or_expression()?;
+        let mut links = 0;
 
         while self.current_token == Token::Pipeline {
+            self.link(&mut links)?;
             self.advance()?;
             let right = self.parse_or_expression()?;
             left = Expression::Pipeline(Box::new(left), Box::new(right));
@@ -273,8 +659,10 @@ impl Parser {
 
     fn parse_or_expression(&mut self) -> Result<Expression> {
         let mut left = self.parse_and_expression()?;
+        let mut links = 0;
 
         while self.current_token == Token::Or {
+            self.link(&mut links)?;
             self.advance()?;
             let right = self.parse_and_expression()?;
             left = Expression::Binary(BinaryOp::Or, Box::new(left), Box::new(right));
@@ -285,8 +673,10 @@ impl Parser {
 
     fn parse_and_expression(&mut self) -> Result<Expression> {
         let mut left = self.parse_comparison_expression()?;
+        let mut links = 0;
 
         while self.current_token == Token::And {
+            self.link(&mut links)?;
             self.advance()?;
             let right = self.parse_comparison_expression()?;
             left = Expression::Binary(BinaryOp::And, Box::new(left), Box::new(right));
@@ -296,7 +686,9 @@ impl Parser {
     }
 
     fn parse_comparison_expression(&mut self) -> Result<Expression> {
-        let mut left = self.parse_additive_expression()?;
+        let start = self.current_span;
+        let mut left = self.parse_coalesce_expression()?;
+        let mut comparisons = 0;
 
         loop {
             let op = match &self.current_token {
@@ -310,16 +702,49 @@ impl Parser {
                 _ => break,
             };
 
+            self.link(&mut comparisons)?;
             self.advance()?;
-            let right = self.parse_additive_expression()?;
+            let right = self.parse_coalesce_expression()?;
+            // `a < b == c` compares the boolean `a < b` with `c`, which is
+            // rarely what was meant. An explicitly parenthesized left side
+            // comes back from the primary level and does not count. One
+            // warning, at the start of the chain, is enough.
+            if comparisons == 2 {
+                let span = start.to(self.previous_span);
+                let inner = format_expression(&left);
+                let rest = format!(" {} {}", comparison_token(&op), format_expression(&right));
+                self.warnings.push(Diagnostic {
+                    severity: Severity::Warning,
+                    code: "W0101",
+                    message: format!(
+                        "Comparison chain `{}{}` parses as `({}){}`; \
+                         add parentheses to make the grouping explicit",
+                        inner, rest, inner, rest
+                    ),
+                    location: Some((span.line, span.column, span.length)),
+                });
+            }
             left = Expression::Comparison(op, Box::new(left), Box::new(right));
         }
 
         Ok(left)
     }
 
+    /// `??` binds tighter than comparisons and looser than arithmetic, and
+    /// groups to the right: `a ?? b ?? 0 > 1` is `(a ?? (b ?? 0)) > 1`.
+    fn parse_coalesce_expression(&mut self) -> Result<Expression> {
+        let left = self.parse_additive_expression()?;
+        if self.current_token != Token::Coalesce {
+            return Ok(left);
+        }
+        self.advance()?;
+        let right = self.nested(Self::parse_coalesce_expression)?;
+        Ok(Expression::Coalesce(Box::new(left), Box::new(right)))
+    }
+
     fn parse_additive_expression(&mut self) -> Result<Expression> {
         let mut left = self.parse_multiplicative_expression()?;
+        let mut links = 0;
 
         loop {
             let op = match &self.current_token {
@@ -328,6 +753,7 @@ impl Parser {
                 _ => break,
             };
 
+            self.link(&mut links)?;
             self.advance()?;
             let right = self.parse_multiplicative_expression()?;
             left = Expression::Binary(op, Box::new(left), Box::new(right));
@@ -338,6 +764,7 @@ impl Parser {
 
     fn parse_multiplicative_expression(&mut self) -> Result<Expression> {
         let mut left = self.parse_unary_expression()?;
+        let mut links = 0;
 
         loop {
             let op = match &self.current_token {
@@ -347,6 +774,7 @@ impl Parser {
                 _ => break,
             };
 
+            self.link(&mut links)?;
             self.advance()?;
             let right = self.parse_unary_expression()?;
             left = Expression::Binary(op, Box::new(left), Box::new(right));
@@ -356,104 +784,101 @@ impl Parser {
     }
 
     fn parse_unary_expression(&mut self) -> Result<Expression> {
-        match &self.current_token {
-            Token::Not => {
-                self.advance()?;
-                let expr = self.parse_unary_expression()?;
-                Ok(Expression::Unary(UnaryOp::Not, Box::new(expr)))
-            }
-            Token::Minus => {
-                self.advance()?;
-                let expr = self.parse_unary_expression()?;
-                Ok(Expression::Unary(UnaryOp::Minus, Box::new(expr)))
-            }
-            _ => self.parse_postfix_expression(),
-        }
+        let op = match &self.current_token {
+            Token::Not => UnaryOp::Not,
+            Token::Minus => UnaryOp::Minus,
+            _ => return self.parse_postfix_expression(),
+        };
+        self.advance()?;
+        let expr = self.nested(Self::parse_unary_expression)?;
+        Ok(Expression::Unary(op, Box::new(expr)))
     }
 
     fn parse_postfix_expression(&mut self) -> Result<Expression> {
         let mut expr = self.parse_primary_expression()?;
+        let mut links = 0;
 
         loop {
-            match &self.current_token {
-                Token::Dot => {
+            if matches!(self.current_token, Token::Dot | Token::QuestionDot | Token::Not) {
+                self.link(&mut links)?;
+            }
+            let optional = match self.current_token {
+                Token::Dot => false,
+                Token::QuestionDot => true,
+                // `option!` unwraps the option
+                Token::Not => {
                     self.advance()?;
-                    // Allow query keywords as method names
-                    let member = match &self.current_token {
-                        Token::Identifier(name) => {
-                            let n = name.clone();
-                            self.advance()?;
-                            n
-                        }
-                        Token::Where => {
-                            self.advance()?;
-                            "where".to_string()
-                        }
-                        _ => return Err(Error::Parser(ParserError {
-                            message: format!("Expected method name after '.', found {:?}", self.current_token),
-                            line: self.line,
-                            column: self.column,
-                        })),
-                    };
-
-                    if self.current_token == Token::LeftParen {
-                        self.advance()?;
-                        let mut args = Vec::new();
-
-                        if self.current_token != Token::RightParen {
-                            loop {
-                                args.push(self.parse_expression()?);
-                                if self.current_token == Token::Comma {
-                                    self.advance()?;
-                                } else {
-                                    break;
-                                }
-                            }
-                        }
-
-                        self.expect(Token::RightParen)?;
-                        // Treat all method calls uniformly
-                        expr = Expression::MethodCall(Box::new(expr), member, args);
-                    } else {
-                        expr = Expression::MemberAccess(Box::new(expr), member);
-                    }
+                    expr = Expression::Unwrap(Box::new(expr));
+                    continue;
                 }
                 _ => break,
-            }
+            };
+            expr = self.parse_member(expr, optional)?;
         }
 
         Ok(expr)
     }
 
-    fn parse_primary_expression(&mut self) -> Result<Expression> {
-        match &self.current_token.clone() {
-            Token::Let => {
+    /// Parse the member after the `.` or `?.` at the current token, and the
+    /// arguments if it is a method called on `expr`.
+    fn parse_member(&mut self, expr: Expression, optional: bool) -> Result<Expression> {
+        self.advance()?;
+        // Allow query keywords as method names
+        let member = match &self.current_token {
+            Token::Identifier(name) => {
+                let n = name.clone();
                 self.advance()?;
-                let name = self.expect_identifier()?;
-                self.expect(Token::Assign)?;
-                let value = self.parse_expression()?;
-                self.expect(Token::In)?;
-                let body = self.parse_expression()?;
-                Ok(Expression::Let(name, Box::new(value), Box::new(body)))
+                n
             }
-            Token::Match => {
+            Token::Where => {
                 self.advance()?;
-                let expr = self.parse_expression()?;
-                self.expect(Token::LeftBrace)?;
-                
-                let mut arms = Vec::new();
-                while self.current_token != Token::RightBrace && self.current_token != Token::Eof {
-                    arms.push(self.parse_match_arm()?);
-                    
-                    // Optional comma between arms
-                    if self.current_token == Token::Comma {
-                        self.advance()?;
-                    }
-                }
-                
-                self.expect(Token::RightBrace)?;
-                Ok(Expression::Match(Box::new(expr), arms))
+                "where".to_string()
             }
+            _ => return Err(self.error(format!(
+                "Expected method name after '{}', found {:?}",
+                if optional { "?." } else { "." },
+                self.current_token
+            ))),
+        };
+
+        let args = if self.current_token == Token::LeftParen {
+            Some(self.parse_method_arguments(&member)?)
+        } else {
+            None
+        };
+        Ok(match (optional, args) {
+            (true, args) => Expression::OptionalAccess(Box::new(expr), member, args),
+            // Treat all method calls uniformly
+            (false, Some(args)) => Expression::MethodCall(Box::new(expr), member, args),
+            (false, None) => Expression::MemberAccess(Box::new(expr), member),
+        })
+    }
+
+    /// Parse the arguments of a method call, starting at its `(`.
+    fn parse_method_arguments(&mut self, member: &str) -> Result<Vec<Expression>> {
+        let open = self.current_span;
+        self.advance()?;
+        let mut args = Vec::new();
+        for arg in self.parse_arguments()? {
+            if arg.label.is_some() {
+                return Err(self.error_at(
+                    open,
+                    format!(
+                        "Labelled arguments are not supported in method calls; \
+                         call {}(...) instead",
+                        member
+                    ),
+                ));
+            }
+            args.push(arg.value);
+        }
+        Ok(args)
+    }
+
+    fn parse_primary_expression(&mut self) -> Result<Expression> {
+        match &self.current_token.clone() {
+            Token::Let => self.parse_let_expression(),
+            Token::Match => self.parse_match_expression(),
             Token::String(s) => {
                 self.advance()?;
                 Ok(Expression::Literal(Literal::String(s.clone())))
@@ -462,6 +887,14 @@ impl Parser {
                 self.advance()?;
                 Ok(Expression::Literal(Literal::Integer(*n)))
             }
+            Token::Temporal(t) => {
+                self.advance()?;
+                Ok(Expression::Literal(Literal::Temporal(*t)))
+            }
+            Token::Decimal(d) => {
+                self.advance()?;
+                Ok(Expression::Literal(Literal::Decimal(*d)))
+            }
             Token::True => {
                 self.advance()?;
                 Ok(Expression::Literal(Literal::Boolean(true)))
@@ -474,57 +907,290 @@ impl Parser {
                 let func_name = name.clone();
                 self.advance()?;
                 
+                if func_name == "Relation" && self.current_token == Token::LeftParen {
+                    return self.parse_relation();
+                }
+
                 // Check if this is a function call
                 if self.current_token == Token::LeftParen {
                     self.advance()?;
-                    let mut args = Vec::new();
-                    
-                    if self.current_token != Token::RightParen {
-                        loop {
-                            args.push(self.parse_expression()?);
-                            if self.current_token == Token::Comma {
-                                self.advance()?;
-                            } else {
-                                break;
-                            }
-                        }
+                    let args = self.parse_arguments()?;
+                    if args.iter().any(|arg| arg.label.is_some()) {
+                        Ok(Expression::NamedCall(func_name, args))
+                    } else {
+                        let args = args.into_iter().map(|arg| arg.value).collect();
+                        Ok(Expression::FunctionCall(func_name, args))
+                    }
+                } else if self.current_token == Token::Arrow {
+                    // A lambda, `x => body`, whose body extends as far as it can
+                    self.advance()?;
+                    let body = self.parse_expression()?;
+                    Ok(Expression::Lambda(func_name, Box::new(body)))
+                } else {
+                    Ok(Expression::Identifier(func_name))
+                }
+            }
+            Token::LeftParen => {
+                self.advance()?;
+                let expr = self.parse_expression()?;
+                self.expect(Token::RightParen)?;
+                Ok(expr)
+            }
+            Token::LeftBracket => self.parse_comprehension(),
+            // The query step `where(rows, x => p)`; elsewhere `where` starts
+            // a guard
+            Token::Where => {
+                let start = self.current_span;
+                self.advance()?;
+                if self.current_token != Token::LeftParen {
+                    return Err(self.error_at(start, "'where' is a reserved keyword and cannot be used as a name here".to_string()));
+                }
+                self.advance()?;
+                let args = self.parse_arguments()?;
+                Ok(Expression::FunctionCall("where".to_string(), args.into_iter().map(|arg| arg.value).collect()))
+            }
+            token if is_keyword(token) => Err(self.error(format!(
+                "'{}' is a reserved keyword and cannot be used as a name here",
+                token
+            ))),
+            _ => Err(self.error(format!(
+                "Unexpected token in expression: {:?}",
+                self.current_token
+            ))),
+        }
+    }
+
+    /// Parse a comprehension, `[element for name in source if condition]`,
+    /// the `if` part optional. `for` and `if` are only words here, not
+    /// keywords, so they stay usable as names elsewhere.
+    fn parse_comprehension(&mut self) -> Result<Expression> {
+        self.expect(Token::LeftBracket)?;
+        let element = self.parse_expression()?;
+        self.expect_word("for")?;
+        let name = self.expect_identifier("variable name")?;
+        self.expect(Token::In)?;
+        let source = self.parse_expression()?;
+        let condition = if self.current_token == Token::Identifier("if".to_string()) {
+            self.advance()?;
+            Some(Box::new(self.parse_expression()?))
+        } else {
+            None
+        };
+        self.expect(Token::RightBracket)?;
+        Ok(Expression::Comprehension(Box::new(element), name, Box::new(source), condition))
+    }
+
+    /// Parse a relation literal after `Relation`: its schema, `(name: Type,
+    /// ...)`, then optionally its rows, `{ (value, ...), ... }`, each with a
+    /// value per field in schema order. Commas between rows are optional.
+    fn parse_relation(&mut self) -> Result<Expression> {
+        self.expect(Token::LeftParen)?;
+        let mut fields = Vec::new();
+        while self.current_token != Token::RightParen {
+            let name = self.expect_identifier("field name")?;
+            self.expect(Token::Colon)?;
+            let ty = self.parse_type()?;
+            fields.push(Parameter { name, ty, unit: None });
+            if self.current_token != Token::Comma {
+                break;
+            }
+            self.advance()?;
+        }
+        self.expect(Token::RightParen)?;
+
+        let mut rows = Vec::new();
+        if self.current_token == Token::LeftBrace {
+            self.advance()?;
+            while self.current_token != Token::RightBrace && self.current_token != Token::Eof {
+                self.expect(Token::LeftParen)?;
+                let mut row = Vec::new();
+                while self.current_token != Token::RightParen {
+                    row.push(self.parse_expression()?);
+                    if self.current_token != Token::Comma {
+                        break;
+                    }
+                    self.advance()?;
+                }
+                self.expect(Token::RightParen)?;
+                rows.push(row);
+                if self.current_token == Token::Comma {
+                    self.advance()?;
+                }
+            }
+            self.expect(Token::RightBrace)?;
+        }
+        Ok(Expression::Relation(fields, rows))
+    }
+
+    fn parse_let_expression(&mut self) -> Result<Expression> {
+        self.advance()?;
+        let name = self.expect_identifier("variable name")?;
+        self.expect(Token::Assign)?;
+        let value = self.parse_expression()?;
+        self.expect(Token::In)?;
+        let body = self.parse_expression()?;
+        Ok(Expression::Let(name, Box::new(value), Box::new(body)))
+    }
+
+    fn parse_match_expression(&mut self) -> Result<Expression> {
+        self.advance()?;
+        let expr = self.parse_expression()?;
+        self.expect(Token::LeftBrace)?;
+
+        let mut arms = Vec::new();
+        while self.current_token != Token::RightBrace && self.current_token != Token::Eof {
+            arms.push(self.parse_match_arm()?);
+
+            // Optional comma between arms
+            if self.current_token == Token::Comma {
+                self.advance()?;
+            }
+        }
+
+        self.expect(Token::RightBrace)?;
+        Ok(Expression::Match(Box::new(expr), arms))
+    }
+
+    /// Parse the contents of a function body: `;`-terminated statements
+    /// followed by the result expression. A body without statements is just
+    /// its expression.
+    fn parse_block(&mut self) -> Result<Expression> {
+        let mut statements = Vec::new();
+
+        loop {
+            let expr = if self.current_token == Token::Let {
+                self.advance()?;
+                let name = self.expect_identifier("variable name")?;
+                self.expect(Token::Assign)?;
+                let value = self.parse_expression()?;
+                match self.current_token {
+                    Token::Semicolon => {
+                        self.advance()?;
+                        statements.push(Statement::Let(name, value));
+                        self.expect_block_continues()?;
+                        continue;
+                    }
+                    Token::In => {
+                        self.advance()?;
+                        let body = self.parse_expression()?;
+                        Expression::Let(name, Box::new(value), Box::new(body))
+                    }
+                    _ => {
+                        return Err(self.error(format!(
+                            "Expected ';' or 'in' after let binding, found {:?}",
+                            self.current_token
+                        )))
+                    }
+                }
+            } else {
+                self.parse_expression()?
+            };
+
+            if self.current_token == Token::Semicolon {
+                self.advance()?;
+                statements.push(Statement::Expression(expr));
+                self.expect_block_continues()?;
+                continue;
+            }
+
+            return Ok(if statements.is_empty() {
+                expr
+            } else {
+                Expression::Block(statements, Box::new(expr))
+            });
+        }
+    }
+
+    fn expect_block_continues(&self) -> Result<()> {
+        if self.current_token == Token::RightBrace {
+            Err(self.error(
+                "Expected an expression after ';': a block must end with its result".to_string(),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Parse call arguments up to and including the closing parenthesis.
+    /// Arguments may be labelled (`name: value`), but positional arguments
+    /// must come first.
+    fn parse_arguments(&mut self) -> Result<Vec<Argument>> {
+        let mut args: Vec<Argument> = Vec::new();
+
+        while self.current_token != Token::RightParen {
+            let start = self.current_span;
+            let value = self.parse_expression()?;
+            let argument = match value {
+                // `name:` can only be a label, since no expression is followed by a colon
+                Expression::Identifier(label) if self.current_token == Token::Colon => {
+                    self.advance()?;
+                    if args.iter().any(|arg| arg.label.as_deref() == Some(label.as_str())) {
+                        return Err(self.error_at(
+                            start,
+                            format!("Argument '{}' given more than once", label),
+                        ));
+                    }
+                    Argument {
+                        label: Some(label),
+                        value: self.parse_expression()?,
                     }
-                    
-                    self.expect(Token::RightParen)?;
-                    Ok(Expression::FunctionCall(func_name, args))
-                } else {
-                    Ok(Expression::Identifier(func_name))
                 }
-            }
-            Token::LeftParen => {
-                self.advance()?;
-                let expr = self.parse_expression()?;
-                self.expect(Token::RightParen)?;
-                Ok(expr)
-            }
-            _ => Err(Error::Parser(ParserError {
-                message: format!("Unexpected token in expression: {:?}", self.current_token),
-                line: self.line,
-                column: self.column,
-            })),
+                value => {
+                    if args.iter().any(|arg| arg.label.is_some()) {
+                        return Err(self.error_at(
+                            start,
+                            "Positional arguments must come before labelled arguments".to_string(),
+                        ));
+                    }
+                    Argument { label: None, value }
+                }
+            };
+            args.push(argument);
+            self.list_separator("argument", starts_expression)?;
         }
+
+        self.expect(Token::RightParen)?;
+        Ok(args)
     }
 
     fn parse_match_arm(&mut self) -> Result<MatchArm> {
-        // Parse pattern: ValueType(binding)
-        let constructor = self.expect_identifier()?;
-        self.expect(Token::LeftParen)?;
-        let binding = self.expect_identifier()?;
-        self.expect(Token::RightParen)?;
-        
+        let pattern = if self.current_token == Token::LeftBracket {
+            self.parse_list_pattern()?
+        } else {
+            // Parse pattern: ValueType(binding)
+            let constructor = self.expect_identifier("value type name")?;
+            self.expect(Token::LeftParen)?;
+            let binding = self.expect_identifier("variable name")?;
+            self.expect(Token::RightParen)?;
+            Pattern::Constructor(constructor, binding)
+        };
+
         self.expect(Token::Arrow)?;
-        
+
         let body = self.parse_expression()?;
-        
-        Ok(MatchArm {
-            pattern: Pattern::Constructor(constructor, binding),
-            body,
-        })
+
+        Ok(MatchArm { pattern, body })
+    }
+
+    // `[]`, `[a, b]` or `[head, ...rest]`, the rest binding last
+    fn parse_list_pattern(&mut self) -> Result<Pattern> {
+        self.expect(Token::LeftBracket)?;
+        let mut elements = Vec::new();
+        let mut rest = None;
+        while self.current_token != Token::RightBracket && rest.is_none() {
+            if self.current_token == Token::Ellipsis {
+                self.advance()?;
+                rest = Some(self.expect_identifier("name for the rest of the list")?);
+            } else {
+                elements.push(self.expect_identifier("variable name")?);
+            }
+            if self.current_token != Token::Comma {
+                break;
+            }
+            self.advance()?;
+        }
+        self.expect(Token::RightBracket)?;
+        Ok(Pattern::List(elements, rest))
     }
 
     fn parse_boolean(&mut self) -> Result<bool> {
@@ -537,11 +1203,32 @@ impl Parser {
                 self.advance()?;
                 Ok(false)
             }
-            _ => Err(Error::Parser(ParserError {
-                message: format!("Expected boolean value, found {:?}", self.current_token),
-                line: self.line,
-                column: self.column,
-            })),
+            _ => Err(self.error(format!("Expected boolean value, found {:?}", self.current_token))),
+        }
+    }
+
+    /// Consume the `,` after an item of a parenthesized list. A trailing comma
+    /// before the `)` is fine. When the next token could start another item,
+    /// the comma was most likely forgotten: while recovering, that is
+    /// reported and parsing carries on as if the comma were there.
+    fn list_separator(&mut self, item: &str, starts_item: fn(&Token) -> bool) -> Result<()> {
+        if self.current_token == Token::Comma {
+            return self.advance();
+        }
+        if self.current_token == Token::RightParen {
+            return Ok(());
+        }
+
+        let message = format!("Expected ',' or ')' after {}, found {:?}", item, self.current_token);
+        if !starts_item(&self.current_token) {
+            return Err(self.error(message));
+        }
+        let error = self.error(format!("{}; did you forget a comma?", message));
+        if self.recovering {
+            self.diagnostics.push(error);
+            Ok(())
+        } else {
+            Err(error)
         }
     }
 
@@ -550,32 +1237,188 @@ impl Parser {
             self.advance()?;
             Ok(())
         } else {
-            Err(Error::Parser(ParserError {
-                message: format!("Expected {:?}, found {:?}", expected, self.current_token),
-                line: self.line,
-                column: self.column,
-            }))
+            Err(self.error(format!("Expected {:?}, found {:?}", expected, self.current_token)))
         }
     }
 
-    fn expect_identifier(&mut self) -> Result<String> {
+    /// Consume `word`, a name that only has a meaning where it's expected.
+    fn expect_word(&mut self, word: &str) -> Result<()> {
+        if self.current_token != Token::Identifier(word.to_string()) {
+            return Err(self.error(format!("Expected '{}', found {:?}", word, self.current_token)));
+        }
+        self.advance()
+    }
+
+    /// Consume a name, described as `what` in errors.
+    fn expect_identifier(&mut self, what: &str) -> Result<String> {
         match &self.current_token.clone() {
             Token::Identifier(name) => {
                 self.advance()?;
                 Ok(name.clone())
             }
-            _ => Err(Error::Parser(ParserError {
-                message: format!("Expected identifier, found {:?}", self.current_token),
-                line: self.line,
-                column: self.column,
-            })),
+            token if is_keyword(token) => Err(self.error(format!(
+                "'{}' is a reserved keyword and cannot be used as a {}; rename it",
+                token, what
+            ))),
+            _ => Err(self.error(format!("Expected {}, found {:?}", what, self.current_token))),
         }
     }
 
     fn advance(&mut self) -> Result<()> {
-        self.current_token = self.lexer.next_token()?;
+        self.advance_count += 1;
+        let (token, span) = self.lexer.next_spanned_token()?;
+        match self.current_token {
+            Token::LeftBrace => self.brace_depth += 1,
+            Token::RightBrace => self.brace_depth = self.brace_depth.saturating_sub(1),
+            _ => {}
+        }
+        self.current_token = token;
+        self.previous_span = std::mem::replace(&mut self.current_span, span);
         Ok(())
     }
+
+    /// Build a parser error located at the current token.
+    fn error(&self, message: String) -> Error {
+        self.error_at(self.current_span, message)
+    }
+
+    fn error_at(&self, span: Span, message: String) -> Error {
+        Error::Parser(ParserError {
+            message,
+            line: span.line,
+            column: span.column,
+            length: span.length,
+        })
+    }
+}
+
+/// A change to the source text, in character offsets: the old text in
+/// `start..old_end` was replaced by new text ending at `new_end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edit {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+impl Edit {
+    fn delta(&self) -> isize {
+        self.new_end as isize - self.old_end as isize
+    }
+}
+
+/// Reparse `source` after `edit`, reusing the declarations of `previous`
+/// (the parse of the text before the edit) wherever possible.
+///
+/// When the edit falls inside a single declaration only that declaration is
+/// reparsed; declarations after it keep their trees and only have their spans
+/// moved. Anything else (edits between or across declarations, or an edit
+/// that changes where a declaration ends) falls back to a full parse.
+pub fn parse_incremental(previous: &Program, source: &str, edit: Edit) -> Result<Program> {
+    match reparse_declaration(previous, source, edit) {
+        Some(program) => Ok(program),
+        None => Parser::new(Lexer::new(source.to_string()))?.parse_program(),
+    }
+}
+
+fn reparse_declaration(previous: &Program, source: &str, edit: Edit) -> Option<Program> {
+    let index = previous.declarations.iter().position(|d| {
+        let span = d.span();
+        span.offset <= edit.start && edit.old_end <= span.end()
+    })?;
+    let old_span = previous.declarations[index].span();
+    let new_end = (old_span.end() as isize + edit.delta()) as usize;
+
+    let lexer = Lexer::resume_at(
+        source.to_string(),
+        old_span.offset,
+        old_span.line,
+        old_span.column,
+    );
+    let mut parser = Parser::new(lexer).ok()?;
+    let declaration = parser.parse_declaration().ok()?;
+    if !parser.diagnostics.is_empty() || declaration.span().end() != new_end {
+        return None;
+    }
+
+    let mut declarations = previous.declarations.clone();
+    declarations[index] = declaration;
+
+    // Later declarations and script expressions are unchanged but may have
+    // moved, possibly onto other lines
+    let line_starts = line_starts(source);
+    let mut imports = previous.imports.clone();
+    let mut expressions = previous.expressions.clone();
+    let mut tests = previous.tests.clone();
+    let mut data = previous.data.clone();
+    let later_imports = imports
+        .iter_mut()
+        .map(|i| &mut i.span)
+        .filter(|span| span.offset > old_span.offset);
+    let later_declarations = declarations[index + 1..].iter_mut().map(Declaration::span_mut);
+    let later_expressions = expressions
+        .iter_mut()
+        .map(|e| &mut e.span)
+        .filter(|span| span.offset > old_span.offset);
+    let later_tests = tests
+        .iter_mut()
+        .map(|t| &mut t.span)
+        .filter(|span| span.offset > old_span.offset);
+    let later_data = data
+        .iter_mut()
+        .filter(|d| d.span.offset > old_span.offset)
+        .flat_map(|d| std::iter::once(&mut d.span).chain(d.entries.iter_mut().map(|e| &mut e.span)));
+    for span in later_declarations
+        .chain(later_imports)
+        .chain(later_expressions)
+        .chain(later_tests)
+        .chain(later_data)
+    {
+        span.offset = (span.offset as isize + edit.delta()) as usize;
+        let line = line_starts.partition_point(|&start| start <= span.offset);
+        span.line = line;
+        span.column = span.offset - line_starts[line - 1] + 1;
+    }
+
+    Some(Program { imports, declarations, expressions, tests, data })
+}
+
+/// Whether `token` is a word reserved by the language, which can't be used
+/// as a name.
+fn is_keyword(token: &Token) -> bool {
+    !matches!(token, Token::Identifier(_)) && token.to_string().starts_with(char::is_alphabetic)
+}
+
+/// Whether `token` can be the first token of an expression.
+fn starts_expression(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Identifier(_)
+            | Token::String(_)
+            | Token::Integer(_)
+            | Token::Temporal(_)
+            | Token::Decimal(_)
+            | Token::True
+            | Token::False
+            | Token::Let
+            | Token::Match
+            | Token::Not
+            | Token::LeftParen
+            | Token::LeftBracket
+    )
+}
+
+/// Character offset of the first character of each line.
+fn line_starts(source: &str) -> Vec<usize> {
+    let mut starts = vec![0];
+    starts.extend(
+        source
+            .chars()
+            .enumerate()
+            .filter(|&(_, c)| c == '\n')
+            .map(|(i, _)| i + 1),
+    );
+    starts
 }
 
 #[cfg(test)]
@@ -803,6 +1646,7 @@ mod tests {
                                 assert_eq!(name, "Status");
                                 assert_eq!(binding, "code");
                             }
+                            pattern => panic!("Expected constructor pattern, got {:?}", pattern),
                         }
                         
                         // Check the arm body
@@ -827,4 +1671,597 @@ mod tests {
 
     // Relation tests removed - using Type-as-Relation model now
 
+    fn parse_error(input: &str) -> ParserError {
+        let lexer = Lexer::new(input.to_string());
+        let result = Parser::new(lexer).and_then(|mut parser| parser.parse_program());
+        match result {
+            Err(Error::Parser(e)) => e,
+            other => panic!("Expected parser error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_position_on_later_line() {
+        let error = parse_error("value Email(raw: String) {\n    validate: raw contains \"@\"\n}\n\nfn broken(x: Int) Int { x }");
+        assert_eq!(error.line, 5);
+        assert_eq!(error.column, 19);
+        assert!(error.message.contains("Expected ReturnArrow"));
+    }
+
+    #[test]
+    fn test_error_position_inside_body() {
+        let error = parse_error("fn f(x: Int) -> Int {\n    x +\n}");
+        assert_eq!(error.line, 3);
+        assert_eq!(error.column, 1);
+    }
+
+    #[test]
+    fn test_error_position_in_value_body() {
+        let error = parse_error("value Age(n: Int) {\n  validate: n > 0\n  bogus: true\n}");
+        assert_eq!(error.line, 3);
+        assert_eq!(error.column, 3);
+    }
+
+    fn parse_recovering(input: &str) -> (Program, Vec<Error>) {
+        let lexer = Lexer::new(input.to_string());
+        let mut parser = Parser::new(lexer).unwrap();
+        parser.parse_program_recovering()
+    }
+
+    fn error_lines(errors: &[Error]) -> Vec<usize> {
+        errors
+            .iter()
+            .map(|e| match e {
+                Error::Parser(e) => e.line,
+                Error::Lexer(e) => e.line,
+                other => panic!("Unexpected error kind: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recovery_reports_all_declaration_errors() {
+        let (program, errors) = parse_recovering(
+            "fn a(x Int) -> Int { x }\n\
+             fn b(x: Int) -> Int { x + 1 }\n\
+             fn c(x: Int) Int { x }\n\
+             value D(s: String) { validate: s contains \"@\" }",
+        );
+
+        assert_eq!(error_lines(&errors), vec![1, 3]);
+        let names: Vec<_> = program
+            .declarations
+            .iter()
+            .map(|d| match d {
+                Declaration::Function(f) => f.name.clone(),
+                Declaration::Value(v) => v.name.clone(),
+                Declaration::Method(m) => m.name.clone(),
+            })
+            .collect();
+        assert_eq!(names, vec!["b", "D"]);
+    }
+
+    #[test]
+    fn test_recovery_inside_value_body() {
+        let (program, errors) = parse_recovering(
+            "value A(x: Int) {\n    validate: x >\n    normalize: x\n    bogus: 1\n    unique: true\n}",
+        );
+
+        assert_eq!(error_lines(&errors), vec![3, 4]);
+        assert_eq!(program.declarations.len(), 1);
+        match &program.declarations[0] {
+            Declaration::Value(v) => assert_eq!(v.body.unique, Some(true)),
+            _ => panic!("Expected value declaration"),
+        }
+    }
+
+    #[test]
+    fn test_recovery_respects_nested_braces() {
+        // The error is inside a match arm; recovery must not treat the
+        // match's closing brace as the end of the value body
+        let (program, errors) = parse_recovering(
+            "value A(s: Status) {\n    validate: match s { Status(c) => c > }\n    unique: true\n}\n\
+             fn ok(x: Int) -> Int { x }",
+        );
+
+        assert_eq!(error_lines(&errors), vec![2]);
+        assert_eq!(program.declarations.len(), 2);
+    }
+
+    #[test]
+    fn test_unterminated_body_stops_at_next_declaration() {
+        let (program, errors) = parse_recovering(
+            "value A(n: Int) {\n    validate: n > 0\n\nfn after(x: Int) -> Int { x }",
+        );
+
+        assert_eq!(error_lines(&errors), vec![4]);
+        assert_eq!(program.declarations.len(), 1);
+        assert!(matches!(&program.declarations[0], Declaration::Function(f) if f.name == "after"));
+    }
+
+    #[test]
+    fn test_recovery_records_lexer_errors() {
+        let (program, errors) = parse_recovering("fn f(x: Int) -> Int { x $ 1 }\nfn g(x: Int) -> Int { x }");
+
+        assert!(matches!(errors[0], Error::Lexer(_)));
+        assert_eq!(program.declarations.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_program_returns_first_error() {
+        let error = parse_error("fn a(x Int) -> Int { x }\nfn c(x: Int) Int { x }");
+        assert_eq!(error.line, 1);
+    }
+
+    #[test]
+    fn test_trailing_commas() {
+        let (program, errors) = parse_recovering(
+            "fn f(a: Int, b: Int,) -> Int { g(a, b,) + a.max(b,) }\nmethod m(x: Int,) -> Int { h(x: x,) }",
+        );
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        match &program.declarations[0] {
+            Declaration::Function(f) => {
+                assert_eq!(f.parameters.len(), 2);
+                assert_eq!(format_expression(&f.body), "g(a, b) + a.max(b)");
+            }
+            other => panic!("Expected function, got {:?}", other),
+        }
+        assert!(parse_expr("f(,)").is_err());
+    }
+
+    #[test]
+    fn test_missing_comma_is_reported_and_parsed_past() {
+        let (program, errors) = parse_recovering("fn f(a: Int b: Int) -> Int { g(a b, \"c\" 1) }");
+
+        let messages: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                Error::Parser(e) => (e.column, e.message.as_str()),
+                other => panic!("Unexpected error kind: {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (13, "Expected ',' or ')' after parameter, found Identifier(\"b\"); did you forget a comma?"),
+                (34, "Expected ',' or ')' after argument, found Identifier(\"b\"); did you forget a comma?"),
+                (41, "Expected ',' or ')' after argument, found Integer(1); did you forget a comma?"),
+            ]
+        );
+        match &program.declarations[0] {
+            Declaration::Function(f) => {
+                assert_eq!(f.parameters.len(), 2);
+                assert_eq!(format_expression(&f.body), "g(a, b, \"c\", 1)");
+            }
+            other => panic!("Expected function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_comma_fails_outside_recovery() {
+        let error = parse_expr("f(a b)").unwrap_err().to_string();
+        assert!(error.contains("did you forget a comma?"), "{}", error);
+
+        let error = parse_expr("f(a }").unwrap_err().to_string();
+        assert!(error.contains("Expected ',' or ')' after argument, found RightBrace"), "{}", error);
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        assert!(parse_expr(&nested(DEFAULT_MAX_DEPTH - 1)).is_ok());
+        let error = parse_expr(&nested(DEFAULT_MAX_DEPTH)).unwrap_err().to_string();
+        assert!(error.contains("nested too deeply"), "{}", error);
+        assert!(parse_expr(&format!("{}x", "!".repeat(100_000))).is_err());
+
+        // Chains built in loops count too
+        let chain = |length: usize, op: &str| format!("x{}", op.repeat(length));
+        assert!(parse_expr(&chain(DEFAULT_MAX_DEPTH - 1, " + 1")).is_ok());
+        for op in [" + 1", " * 1", " && y", " == y", "?.y", ".y", " |> f", " ?? y"] {
+            let error = parse_expr(&chain(DEFAULT_MAX_DEPTH, op)).unwrap_err().to_string();
+            assert!(error.contains("nested too deeply"), "{}: {}", op, error);
+        }
+        assert!(parse_expr(&format!("f({})", chain(100_000, " + 1"))).is_err());
+
+        let mut parser = Parser::new(Lexer::new(nested(20))).unwrap().with_max_depth(10);
+        assert!(parser.parse_expression().is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit_recovers_at_next_declaration() {
+        let source = format!(
+            "fn deep(x: Int) -> Int {{ {} }}\nfn ok(x: Int) -> Int {{ x }}",
+            "-".repeat(10_000) + "x"
+        );
+        let (program, errors) = parse_recovering(&source);
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(program.declarations.len(), 1);
+    }
+
+    #[test]
+    fn test_keywords_used_as_names() {
+        let cases = [
+            ("fn f(where: Int) -> Int { 1 }", "'where' is a reserved keyword and cannot be used as a parameter name; rename it"),
+            ("value match(x: Int) {}", "'match' is a reserved keyword and cannot be used as a value type name; rename it"),
+            ("fn in(x: Int) -> Int { x }", "'in' is a reserved keyword and cannot be used as a function name; rename it"),
+            ("fn f(x: Int) -> Int { let unique = x in unique }", "'unique' is a reserved keyword and cannot be used as a variable name; rename it"),
+            ("fn f(x: value) -> Int { x }", "'value' is a reserved keyword, not a type"),
+            ("fn f(x: Int) -> Int { x + validate }", "'validate' is a reserved keyword and cannot be used as a name here"),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(parse_error(source).message, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_top_level_expressions() {
+        let (program, errors) = parse_recovering(
+            "#!/usr/bin/env relic\nfn double(x: Int) -> Int { x * 2 }\ndouble(4)\nx; -1",
+        );
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(program.declarations.len(), 1);
+        let spans: Vec<_> = program
+            .expressions
+            .iter()
+            .map(|e| (e.span.line, e.span.column, e.span.length))
+            .collect();
+        assert_eq!(spans, vec![(3, 1, 9), (4, 1, 1), (4, 4, 2)]);
+        assert!(matches!(program.expressions[2].expression, Expression::Unary(UnaryOp::Minus, _)));
+    }
+
+    #[test]
+    fn test_imports() {
+        let (program, errors) = parse_recovering(
+            "import \"shapes.relic\";\nfn f(x: Int) -> Int { x }\nimport \"lib/math.relic\"\nf(1)\nimport shapes",
+        );
+
+        let imports: Vec<_> = program
+            .imports
+            .iter()
+            .map(|i| (i.path.as_str(), i.span.line, i.span.length))
+            .collect();
+        assert_eq!(imports, vec![("shapes.relic", 1, 21), ("lib/math.relic", 3, 23)]);
+        assert_eq!((program.declarations.len(), program.expressions.len()), (1, 1));
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains(
+            "Expected a quoted path after 'import', as in import \"shapes.relic\", found Identifier(\"shapes\")"
+        ));
+    }
+
+    #[test]
+    fn test_data_blocks() {
+        let (program, errors) = parse_recovering(
+            "data User {\n    User(\"alice\"),\n    User(\"bob\")\n}\ndata { User(\"carol\") }",
+        );
+
+        assert_eq!(program.data.len(), 1);
+        let entries: Vec<_> = program.data[0].entries.iter().map(|e| (e.span.line, e.span.length)).collect();
+        assert_eq!(program.data[0].type_name, "User");
+        assert_eq!(entries, vec![(2, 13), (3, 11)]);
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains("Expected a value type name after 'data'"));
+    }
+
+    #[test]
+    fn test_generic_types() {
+        let program = parse("fn f(xs: List[List[Email]], n: List[Int,]) -> List[String] { xs }");
+        let Declaration::Function(f) = &program.declarations[0] else { panic!() };
+
+        let list = |ty| Type::List(Box::new(ty));
+        assert_eq!(f.parameters[0].ty, list(list(Type::Value("Email".to_string()))));
+        assert_eq!(f.parameters[1].ty, list(Type::Int));
+        assert_eq!(f.return_type, list(Type::String));
+    }
+
+    #[test]
+    fn test_generic_type_errors() {
+        let error = parse_error("fn f(xs: List[Int) -> Int { 1 }");
+        assert_eq!(error.message, "Expected ']' to close the '[' at 1:14, found RightParen");
+
+        let error = parse_error("fn f(xs: List[List[Int]) -> Int { 1 }");
+        assert_eq!(error.message, "Expected ']' to close the '[' at 1:14, found RightParen");
+
+        let error = parse_error("fn f(xs: List) -> Int { 1 }");
+        assert_eq!((error.column, error.message.as_str()), (10, "'List' needs an element type, as in List[Int]"));
+
+        let error = parse_error("fn f(xs: List[Int, String]) -> Int { 1 }");
+        assert_eq!(error.message, "'List' takes 1 type argument, found 2");
+
+        let error = parse_error("fn f(x: Email[Int]) -> Int { 1 }");
+        assert_eq!(error.message, "'Email' does not take type arguments; only List, Option and Query do");
+
+        let error = parse_error("fn f(xs: List<String>) -> Int { 1 }");
+        assert_eq!(error.message, "Type arguments go in square brackets, as in List[...]");
+    }
+
+    fn parse_expr(input: &str) -> Result<Expression> {
+        Parser::new(Lexer::new(input.to_string()))?.parse_expression()
+    }
+
+    #[test]
+    fn test_labelled_arguments() {
+        let expr = parse_expr("createUser(\"bob\", age: 30)").unwrap();
+        assert_eq!(
+            expr,
+            Expression::NamedCall(
+                "createUser".to_string(),
+                vec![
+                    Argument {
+                        label: None,
+                        value: Expression::Literal(Literal::String("bob".to_string())),
+                    },
+                    Argument {
+                        label: Some("age".to_string()),
+                        value: Expression::Literal(Literal::Integer(30)),
+                    },
+                ]
+            )
+        );
+
+        // Without labels the call stays positional
+        assert!(matches!(parse_expr("f(a, b)").unwrap(), Expression::FunctionCall(..)));
+    }
+
+    #[test]
+    fn test_labelled_argument_errors() {
+        let message = |input: &str| match parse_expr(input) {
+            Err(Error::Parser(e)) => (e.message, e.column),
+            other => panic!("Expected parser error, got {:?}", other),
+        };
+
+        assert_eq!(
+            message("f(a: 1, 2)"),
+            ("Positional arguments must come before labelled arguments".to_string(), 9)
+        );
+        assert_eq!(message("f(a: 1, a: 2)"), ("Argument 'a' given more than once".to_string(), 9));
+        assert!(message("x.f(a: 1)").0.starts_with("Labelled arguments are not supported"));
+    }
+
+    #[test]
+    fn test_block_body() {
+        let program = parse("fn f(x: Int) -> Int { let a = x + 1; let b = a * 2; a + b }");
+        let Declaration::Function(f) = &program.declarations[0] else {
+            panic!("Expected function");
+        };
+
+        match &f.body {
+            Expression::Block(statements, result) => {
+                assert!(matches!(
+                    &statements[..],
+                    [Statement::Let(a, _), Statement::Let(b, _)] if a == "a" && b == "b"
+                ));
+                assert!(matches!(**result, Expression::Binary(BinaryOp::Add, _, _)));
+            }
+            other => panic!("Expected block, got {:?}", other),
+        }
+
+        // `let ... in` still works inside a body, and a single expression stays unwrapped
+        let program = parse("fn g(x: Int) -> Int { let a = x in a }");
+        let Declaration::Function(g) = &program.declarations[0] else {
+            panic!("Expected function");
+        };
+        assert!(matches!(g.body, Expression::Let(..)));
+    }
+
+    #[test]
+    fn test_block_errors() {
+        let error = parse_error("fn f(x: Int) -> Int { let a = x; }");
+        assert_eq!((error.line, error.column), (1, 34));
+        assert!(error.message.contains("block must end with its result"));
+
+        let error = parse_error("fn f(x: Int) -> Int { let a = x }");
+        assert!(error.message.starts_with("Expected ';' or 'in' after let binding"));
+    }
+
+    /// Fully parenthesized rendering of binary structure, to spell out precedence.
+    fn grouping(expr: &Expression) -> String {
+        match expr {
+            Expression::Binary(_, l, r)
+            | Expression::Comparison(_, l, r)
+            | Expression::Pipeline(l, r)
+            | Expression::Coalesce(l, r) => {
+                let text = format_expression(expr);
+                let right = format_expression(r);
+                // The operator is the last word before the right operand; the
+                // left operand may have been parenthesized by the formatter.
+                let op = text[..text.len() - right.len()].split_whitespace().last().unwrap();
+                format!("({} {} {})", grouping(l), op, grouping(r))
+            }
+            Expression::Unary(_, operand) => {
+                let text = format_expression(expr);
+                format!("({}{})", &text[..1], grouping(operand))
+            }
+            _ => format_expression(expr),
+        }
+    }
+
+    #[test]
+    fn test_precedence_table() {
+        // Loosest to tightest: |>, ||, &&, comparisons, ??, + -, * / %, unary,
+        // member access and `!`. Every binary level but `??` is left-associative.
+        let cases = [
+            ("a |> f || b", "(a |> (f || b))"),
+            ("a || b && c", "(a || (b && c))"),
+            ("a && b == c", "(a && (b == c))"),
+            ("a == b + c", "(a == (b + c))"),
+            ("x contains \"@\" && y", "((x contains \"@\") && y)"),
+            ("a + b * c", "(a + (b * c))"),
+            ("a - b - c", "((a - b) - c)"),
+            ("a / b % c", "((a / b) % c)"),
+            ("-a * b", "((-a) * b)"),
+            ("!a.b", "(!a.b)"),
+            ("a |> f |> g", "((a |> f) |> g)"),
+            ("a < b == c", "((a < b) == c)"),
+            ("a ?? b ?? c", "(a ?? (b ?? c))"),
+            ("a ?? b > c + d", "((a ?? b) > (c + d))"),
+            ("a ?? b + c", "(a ?? (b + c))"),
+            ("!a?.b!", "(!a?.b!)"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(grouping(&parse_expr(input).unwrap()), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_comparison_chain_warning() {
+        let mut parser = Parser::new(Lexer::new("x > 0 && a < b == c".to_string())).unwrap();
+        parser.parse_expression().unwrap();
+
+        let warnings = parser.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert_eq!(warnings[0].code, "W0101");
+        assert_eq!(warnings[0].location, Some((1, 10, 10)));
+        assert!(warnings[0].message.contains("parses as `(a < b) == c`"));
+
+        let mut parser = Parser::new(Lexer::new("a < b == c == d".to_string())).unwrap();
+        parser.parse_expression().unwrap();
+        assert_eq!(parser.warnings().len(), 1);
+    }
+
+    #[test]
+    fn test_parenthesized_comparison_does_not_warn() {
+        for input in ["(a < b) == c", "a < (b == c)", "a < b && b < c"] {
+            let mut parser = Parser::new(Lexer::new(input.to_string())).unwrap();
+            parser.parse_expression().unwrap();
+            assert!(parser.warnings().is_empty(), "{}", input);
+        }
+    }
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::new(source.to_string()))
+            .and_then(|mut parser| parser.parse_program())
+            .unwrap()
+    }
+
+    fn reparse(old: &str, new: &str, edit: Edit) -> Program {
+        let full = parse(new);
+        let incremental = parse_incremental(&parse(old), new, edit).unwrap();
+
+        assert_eq!(incremental, full);
+        incremental
+    }
+
+    #[test]
+    fn test_incremental_edit_inside_declaration() {
+        let old = "fn a(x: Int) -> Int { x }\nfn b(y: Int) -> Int { y }\nfn c(z: Int) -> Int { z }";
+        let new = "fn a(x: Int) -> Int { x }\nfn b(y: Int) -> Int {\n  y + 100\n}\nfn c(z: Int) -> Int { z }";
+        let edit = Edit { start: 47, old_end: 50, new_end: 58 };
+
+        assert!(reparse_declaration(&parse(old), new, edit).is_some());
+        let program = reparse(old, new, edit);
+        assert_eq!(program.declarations[2].span().line, 5);
+    }
+
+    #[test]
+    fn test_incremental_falls_back_across_declarations() {
+        let old = "fn a(x: Int) -> Int { x }\nfn b(y: Int) -> Int { y }";
+        let new = "fn a(x: Int) -> Int { y }";
+        let edit = Edit { start: 22, old_end: 48, new_end: 22 };
+
+        assert!(reparse_declaration(&parse(old), new, edit).is_none());
+        reparse(old, new, edit);
+    }
+
+    #[test]
+    fn test_incremental_falls_back_when_declaration_is_split() {
+        let old = "fn a(x: Int) -> Int { x }\nfn b(y: Int) -> Int { y }";
+        let new = "fn a(x: Int) -> Int { x } fn n(q: Int) -> Int { q }\nfn b(y: Int) -> Int { y }";
+        let edit = Edit { start: 25, old_end: 25, new_end: 51 };
+
+        assert!(reparse_declaration(&parse(old), new, edit).is_none());
+        assert_eq!(reparse(old, new, edit).declarations.len(), 3);
+    }
+
+    #[test]
+    fn test_incremental_moves_script_expressions() {
+        let old = "f(1)\nfn f(x: Int) -> Int { x }\nf(2)";
+        let new = "f(1)\nfn f(x: Int) -> Int {\n  x + 1\n}\nf(2)";
+        let edit = Edit { start: 26, old_end: 27, new_end: 35 };
+
+        let program = reparse(old, new, edit);
+        assert_eq!(program.expressions[0].span.line, 1);
+        assert_eq!(program.expressions[1].span.line, 5);
+    }
+
+    #[test]
+    fn test_incremental_reports_syntax_errors() {
+        let old = "fn a(x: Int) -> Int { x }";
+        let new = "fn a(x Int) -> Int { x }";
+        let previous = parse(old);
+
+        assert!(parse_incremental(&previous, new, Edit { start: 6, old_end: 7, new_end: 6 }).is_err());
+    }
+}
+
+/// Property tests feeding generated source to the lexer and parser: whatever
+/// the input, they must return rather than panic or overflow the stack.
+#[cfg(test)]
+mod fuzz {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Fragments of Relic source, so generated programs get past the lexer and
+    // reach deep into the parser instead of failing on the first character
+    const FRAGMENTS: &[&str] = &[
+        "value", "fn", "method", "validate", "normalize", "unique", "let", "in", "match", "where",
+        "test", "true", "false", "contains", "x", "Email", "Int", "String", "42", "\"s\"", "(", ")", "{",
+        "}", "[", "]", "List", ":", ".", ",", ";", "=", "==", "!=", "<", ">", "<=", ">=", "&&", "||", "!", "+", "-",
+        "*", "/", "%", "|>", "=>", "->", "//", "/*", "*/", "#!", "\n", "é", "\"",
+    ];
+
+    fn fragments() -> impl Strategy<Value = String> {
+        prop::collection::vec(prop::sample::select(FRAGMENTS), 0..200).prop_map(|parts| parts.join(" "))
+    }
+
+    /// Lex, parse and type check `source`, which must not panic or overflow
+    /// the stack however malformed or deeply nested it is.
+    fn parse_all(source: &str) {
+        for _ in crate::lexer::tokenize(source) {}
+        if let Ok(mut parser) = Parser::new(Lexer::new(source.to_string())) {
+            let (program, _) = parser.parse_program_recovering();
+            let _ = crate::typechecker::TypeChecker::new().check_program_recovering(&program);
+        }
+        if let Ok(mut parser) = Parser::new(Lexer::new(source.to_string())) {
+            let _ = parser.parse_expression();
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn parser_never_panics_on_fragments(source in fragments()) {
+            parse_all(&source);
+        }
+
+        #[test]
+        fn parser_never_panics_on_arbitrary_text(source in "\\PC*") {
+            parse_all(&source);
+        }
+
+        #[test]
+        fn parser_never_panics_on_deep_nesting(
+            open in prop::sample::select(&["(", "!", "-", "let x = ", "match "][..]),
+            depth in 0..2_000usize,
+        ) {
+            parse_all(&format!("fn f(x: Int) -> Int {{ {}x }}", open.repeat(depth)));
+        }
+
+        #[test]
+        fn checker_never_overflows_on_long_chains(
+            op in prop::sample::select(&[" + 1", " * x", " && x", " == x", " |> f", "?.x", ".x", ".f()"][..]),
+            length in 0..2_000usize,
+            parens in 0..64usize,
+        ) {
+            let chain = format!("x{}", op.repeat(length));
+            parse_all(&format!("fn f(x: Int) -> Int {{ {}{}{} }}", "(".repeat(parens), chain, ")".repeat(parens)));
+        }
+    }
 }
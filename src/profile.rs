@@ -0,0 +1,90 @@
+//! Counts of the work the evaluator does, kept by each `ValueRegistry` for
+//! finding out why an evaluation is slow, as the REPL's `:time` does.
+
+use std::ops::Sub;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The running counts. Evaluation only has shared access to the registry,
+/// so they're atomic.
+#[derive(Debug, Default)]
+pub struct Counters {
+    dispatches: AtomicU64,
+    cache_hits: AtomicU64,
+    instances_created: AtomicU64,
+}
+
+impl Counters {
+    /// A call chose among several overloads, from a dispatch table
+    /// compiled earlier or the dispatch cache if `cached`.
+    pub(crate) fn dispatch(&self, cached: bool) {
+        self.dispatches.fetch_add(1, Ordering::Relaxed);
+        if cached {
+            self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn instance_created(&self) {
+        self.instances_created.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Profile {
+        Profile {
+            dispatches: self.dispatches.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            instances_created: self.instances_created.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The counts at some point, or, subtracting an earlier snapshot, what
+/// happened in between.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Calls to a function with several overloads, which dispatch had to
+    /// choose between
+    pub dispatches: u64,
+    /// Those of the dispatches answered by a dispatch table compiled by an
+    /// earlier call, or by the dispatch cache
+    pub cache_hits: u64,
+    /// Values constructed and recorded as instances
+    pub instances_created: u64,
+}
+
+impl Sub for Profile {
+    type Output = Profile;
+
+    fn sub(self, earlier: Profile) -> Profile {
+        Profile {
+            dispatches: self.dispatches - earlier.dispatches,
+            cache_hits: self.cache_hits - earlier.cache_hits,
+            instances_created: self.instances_created - earlier.instances_created,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_profile() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value Tally(n: Int) { validate: n >= 0 }
+                 fn profiledKind(n: Int) -> String { \"int\" }
+                 fn profiledKind(s: String) -> String { \"string\" }",
+            )
+            .unwrap();
+        let before = engine.registry().profile();
+        engine.eval("profiledKind(1)").unwrap();
+        engine.eval("profiledKind(2)").unwrap();
+        engine.eval("Tally(3)").unwrap();
+        assert!(engine.eval("Tally(-3)").is_err());
+
+        let profile = engine.registry().profile() - before;
+        assert_eq!(profile.dispatches, 2);
+        assert_eq!(profile.cache_hits, 1);
+        assert_eq!(profile.instances_created, 1);
+    }
+}
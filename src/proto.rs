@@ -0,0 +1,179 @@
+//! Protocol Buffers (proto3) definitions for value types, so that other
+//! services can exchange data of the same shape as Relic's. A value type is
+//! a message with its parameter as its one field, numbered 1, which in the
+//! protobuf JSON mapping is the object `fromJson` reads: `value Age(n: Int)`
+//! is `message Age { int64 n = 1; }`, and a parameter of another value type
+//! is a field of that type's message.
+//!
+//! Protobuf has no constraints, so what the messages can't say is reported:
+//! each type's validation and normalization, types sent as text, and
+//! parameters left out, such as a Relation, whose columns are only known at
+//! run time. The report is also written as comments above each message.
+
+use crate::error::{Error, Result, TypeError};
+use crate::formatter::{format_expression, format_type};
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::collections::BTreeMap;
+
+/// A `.proto` file, and what it leaves unsaid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtoFile {
+    pub source: String,
+    pub report: Vec<Unmapped>,
+}
+
+/// Something about a value type that its message doesn't express.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unmapped {
+    pub type_name: String,
+    pub note: String,
+}
+
+/// A proto3 file in `package` with a message for each of the value types
+/// `type_names` and for the value types their parameters refer to.
+pub fn generate(registry: &ValueRegistry, type_names: &[&str], package: &str) -> Result<ProtoFile> {
+    let mut messages = BTreeMap::new();
+    let mut pending: Vec<String> = type_names.iter().map(|name| name.to_string()).collect();
+    while let Some(type_name) = pending.pop() {
+        if messages.contains_key(&type_name) {
+            continue;
+        }
+        let constructor = registry.constructors.get(&type_name).ok_or_else(|| {
+            Error::Type(TypeError {
+                message: format!("Unknown value type: {}", type_name),
+                span: None,
+            })
+        })?;
+        let declaration = &constructor.declaration;
+        let parameter = &declaration.parameter;
+        let mut notes = Vec::new();
+        if let Some(validate) = &declaration.body.validate {
+            notes.push(format!("validate: {} is only checked by Relic", format_expression(validate)));
+        }
+        if let Some(normalize) = &declaration.body.normalize {
+            notes.push(format!("normalize: {} is only applied by Relic", format_expression(normalize)));
+        }
+        let field = match field_type(&parameter.ty) {
+            Ok((field_type, note)) => {
+                notes.extend(note);
+                pending.extend(referenced(&parameter.ty));
+                // A sensitive parameter is redacted where protobuf prints messages
+                let options = if declaration.body.sensitive == Some(true) { " [debug_redact = true]" } else { "" };
+                Some(format!("  {} {} = 1{};\n", field_type, parameter.name, options))
+            }
+            Err(reason) => {
+                notes.push(format!("{} is left out: {}", parameter.name, reason));
+                None
+            }
+        };
+        messages.insert(type_name, (notes, field));
+    }
+
+    let mut source = format!("syntax = \"proto3\";\n\npackage {};\n", package);
+    let mut report = Vec::new();
+    for (type_name, (notes, field)) in messages {
+        source.push('\n');
+        for note in &notes {
+            source.push_str(&format!("// {}\n", note));
+        }
+        source.push_str(&format!("message {} {{\n{}}}\n", type_name, field.unwrap_or_default()));
+        report.extend(notes.into_iter().map(|note| Unmapped { type_name: type_name.clone(), note }));
+    }
+    Ok(ProtoFile { source, report })
+}
+
+// The field type of a parameter of type `ty`, with a note on what it
+// doesn't keep, or why there is none
+fn field_type(ty: &Type) -> std::result::Result<(String, Option<String>), String> {
+    let scalar = |name: &str| Ok((name.to_string(), None));
+    let text = |example: &str| {
+        let note = format!("{} is sent as text, such as \"{}\"", format_type(ty), example);
+        Ok(("string".to_string(), Some(note)))
+    };
+    match ty {
+        Type::String => scalar("string"),
+        Type::Int => scalar("int64"),
+        Type::Bool => scalar("bool"),
+        Type::Value(name) => scalar(name),
+        // Text, so that no digits are lost to floating point
+        Type::Decimal => text("19.99"),
+        Type::Date => text("2024-01-31"),
+        Type::Time => text("09:30:00"),
+        Type::Duration => text("PT1H30M"),
+        Type::List(element) => match element.as_ref() {
+            Type::List(_) | Type::Option(_) => {
+                Err(format!("a repeated field can't hold a {}", format_type(element)))
+            }
+            element => {
+                let (element, note) = field_type(element)?;
+                Ok((format!("repeated {}", element), note))
+            }
+        },
+        Type::Option(element) => match element.as_ref() {
+            Type::Option(_) => Err("protobuf has no optional optional field".to_string()),
+            Type::List(_) => {
+                let (field, note) = field_type(element)?;
+                let none = format!("None and an empty {} are both sent as no items", format_type(element));
+                Ok((field, Some(note.map_or(none.clone(), |note| format!("{}; {}", note, none)))))
+            }
+            element => {
+                let (element, note) = field_type(element)?;
+                Ok((format!("optional {}", element), note))
+            }
+        },
+        Type::Relation => Err("a Relation's columns are only known at run time".to_string()),
+        other => Err(format!("a {} can't be sent", format_type(other))),
+    }
+}
+
+// The value types `ty` refers to
+fn referenced(ty: &Type) -> Vec<String> {
+    match ty {
+        Type::Value(name) => vec![name.clone()],
+        Type::List(element) | Type::Option(element) => referenced(element),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_proto() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value Email(raw: String) { validate: raw contains \"@\" normalize: raw.toLowerCase() }
+                 value Secret(raw: String) { sensitive: true }
+                 value Price(amount: Decimal) {}
+                 value Member(emails: List[Email]) {}
+                 value Nickname(name: Option[String]) {}
+                 value Report(rows: Relation) {}",
+            )
+            .unwrap();
+
+        let proto = engine.proto(&["Member", "Secret", "Price", "Nickname"], "shop").unwrap();
+        assert_eq!(
+            proto.source,
+            "syntax = \"proto3\";\n\npackage shop;\n\n\
+             // validate: raw contains \"@\" is only checked by Relic\n\
+             // normalize: raw.toLowerCase() is only applied by Relic\n\
+             message Email {\n  string raw = 1;\n}\n\n\
+             message Member {\n  repeated Email emails = 1;\n}\n\n\
+             message Nickname {\n  optional string name = 1;\n}\n\n\
+             // Decimal is sent as text, such as \"19.99\"\n\
+             message Price {\n  string amount = 1;\n}\n\n\
+             message Secret {\n  string raw = 1 [debug_redact = true];\n}\n"
+        );
+        assert_eq!(proto.report.len(), 3);
+        assert_eq!(proto.report[2].type_name, "Price");
+
+        let report = engine.proto(&["Report"], "shop").unwrap();
+        assert!(report.source.contains("message Report {\n}"), "{}", report.source);
+        assert_eq!(report.report[0].note, "rows is left out: a Relation's columns are only known at run time");
+
+        assert!(engine.proto(&["Missing"], "shop").is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! Where instances came from. A registry that records provenance (see
+//! `ValueRegistry::set_provenance`) keeps, with each instance it
+//! constructs, the expression that was running and where it was written:
+//! a file and line, the REPL, or the line of Rust that called the
+//! `Engine`. `provenance(x)` lists it for the instances equal to `x`, and
+//! `:inspect` shows it, so that data that looks wrong can be traced back
+//! to the code that let it in.
+//!
+//! Whoever runs code says where it is with `from`, around everything it
+//! runs, and `at`, around each expression, or with `within` at once.
+//! Without a `from`, `at` does nothing, so code that doesn't record
+//! provenance doesn't pay for describing expressions.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Arc;
+
+thread_local! {
+    // What is running on this thread, innermost last
+    static SITES: RefCell<Vec<Arc<Provenance>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The code that constructed an instance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// A file, `<repl>`, or for a host's call, the Rust file and line that
+    /// made it, such as `load at src/main.rs:12`
+    pub origin: String,
+    /// The expression's line in its source, when it has one
+    pub line: Option<usize>,
+    /// The top-level expression, call or `data` entry that was running
+    pub expression: String,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}, line {}: {}", self.origin, line, self.expression),
+            None if self.expression.is_empty() => write!(f, "{}", self.origin),
+            None => write!(f, "{}: {}", self.origin, self.expression),
+        }
+    }
+}
+
+/// Run `f` with what it constructs attributed to `origin`, until `at`
+/// names the expression.
+pub fn from<T>(origin: impl Into<String>, f: impl FnOnce() -> T) -> T {
+    within(
+        Provenance {
+            origin: origin.into(),
+            line: None,
+            expression: String::new(),
+        },
+        f,
+    )
+}
+
+/// Run `f` with what it constructs attributed to `expression`, at `line` of
+/// the source `from` named.
+pub fn at<T>(line: Option<usize>, expression: impl FnOnce() -> String, f: impl FnOnce() -> T) -> T {
+    let Some(outer) = current() else {
+        return f();
+    };
+    within(
+        Provenance {
+            origin: outer.origin.clone(),
+            line,
+            expression: expression(),
+        },
+        f,
+    )
+}
+
+/// What is running on this thread, if anyone said.
+pub fn current() -> Option<Arc<Provenance>> {
+    SITES.with(|sites| sites.borrow().last().cloned())
+}
+
+/// Run `f` with what it constructs attributed to `site`.
+pub fn within<T>(site: Provenance, f: impl FnOnce() -> T) -> T {
+    // Popped on the way out, even by a panic, which an Engine catches
+    struct Pop;
+    impl Drop for Pop {
+        fn drop(&mut self) {
+            SITES.with(|sites| sites.borrow_mut().pop());
+        }
+    }
+    SITES.with(|sites| sites.borrow_mut().push(Arc::new(site)));
+    let _pop = Pop;
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_provenance() {
+        let mut engine = Engine::new().with_provenance();
+        let source = "value Email(raw: String) { validate: raw contains \"@\" }\n\nEmail(\"a@b\")";
+        engine.load(source).unwrap();
+        let loaded = line!() - 1;
+        engine.eval("Email(\"c@d\")").unwrap();
+        engine.eval("Email(\"a@b\")").unwrap();
+        let evaluated = line!() - 1;
+
+        let sites = engine.eval_as::<Vec<String>>("provenance(Email(\"a@b\"))").unwrap();
+        assert_eq!(sites.len(), 3, "{:?}", sites);
+        assert_eq!(sites[0], format!("load at {}:{}, line 3: Email(\"a@b\")", file!(), loaded));
+        assert_eq!(sites[1], format!("eval at {}:{}: Email(\"a@b\")", file!(), evaluated));
+        // The call to provenance constructs its own argument
+        assert!(sites[2].starts_with("eval at"), "{}", sites[2]);
+
+        let inspected = engine.eval_as::<String>("inspect(Email(\"c@d\"))").unwrap();
+        let created = format!("\n  created: eval at {}:{}: Email(\"c@d\")", file!(), evaluated - 1);
+        assert!(inspected.contains(&created), "{}", inspected);
+
+        // Without provenance, nothing is recorded
+        let mut engine = Engine::new();
+        engine.load("value Age(n: Int) {}\nAge(1)").unwrap();
+        assert_eq!(engine.eval_as::<Vec<String>>("provenance(Age(1))").unwrap(), Vec::<String>::new());
+        assert!(engine.eval("provenance(1)").is_err());
+        assert!(current().is_none());
+    }
+}
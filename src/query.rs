@@ -1,7 +1,448 @@
-use crate::error::Result;
+//! Queries: `User |> where(u => u.age > 18) |> select(u => u.name)`. A
+//! `where` or `select` doesn't visit any rows; it gives a `Query`, which
+//! says where the rows come from and what to do with each. The rows are
+//! only visited when the query is materialized, counted or quantified
+//! over, or when it's returned from an evaluation, and then one at a time,
+//! through every step, without a list between the steps.
+//! A value type's instances come from its store, which runs the steps at
+//! the start of the query that it can; see `storage`.
+//!
+//! `sortBy` and the window steps, `running`, `rank`, `lag` and `lead`, need
+//! every row before they can give the first, so the rows reaching one of
+//! them are collected, and what comes out of it is visited one at a time
+//! again: `Sale |> sortBy(s => s.day) |> running(s => s.amount)`.
+//!
+//! Below that are helpers for relations, which aren't queries yet.
+
+use crate::ast::Expression;
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::{evaluate_expression, EvalValue};
+use crate::formatter::format_expression;
 use crate::relation::{Relation, Schema};
-use crate::value::ValueObject;
+use crate::storage::{InstanceStore, Pushdown};
+use crate::types::Type;
+use crate::value::{ValueObject, ValueRegistry};
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Rows to be visited, and what to do with each, when they're wanted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    source: Source,
+    steps: Vec<Step>,
+}
+
+/// Where a query's rows come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// The instances of a value type that evaluation can see, as of when
+    /// the query is run
+    Type(String),
+    /// The elements of a list
+    List(Arc<Vec<EvalValue>>),
+}
+
+/// What a query does with each row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// `where(x => p)`: keep the rows `p` holds for
+    Where(Function),
+    /// `select(x => e)`: replace each row with `e`
+    Select(Function),
+    /// `sortBy(x => k)`: the rows in order of `k`, least first, rows with
+    /// the same `k` staying in the order they came
+    SortBy(Function),
+    /// `running(x => n)` and the other window steps: replace each row with
+    /// what the window gives for it, in the order the rows came
+    Window(Window, Function),
+}
+
+/// What a window step gives for each row, from the rows around it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// `running(x => n)`: the total of the Int `n` over this row and every
+    /// row before it
+    Running,
+    /// `rank(x => k)`: 1, plus the number of rows with a lesser `k`, so
+    /// rows with the same `k` share a rank and the next rank is skipped
+    Rank,
+    /// `lag(x => e)`: `e` of the row before, or None for the first row
+    Lag,
+    /// `lead(x => e)`: `e` of the row after, or None for the last row
+    Lead,
+}
+
+/// `parameter => body`, with the variables in scope where it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub parameter: String,
+    pub body: Expression,
+    pub context: Arc<HashMap<String, EvalValue>>,
+}
+
+impl Query {
+    /// A query of `rows`: a value type, a list, or another query.
+    pub fn of(rows: EvalValue, step: &str) -> Result<Query> {
+        let source = match rows {
+            EvalValue::Type(type_name) => Source::Type(type_name),
+            EvalValue::List(items) => Source::List(Arc::new(items)),
+            EvalValue::Query(query) => return Ok(query),
+            other => {
+                return Err(failure(format!(
+                    "The first argument of {} must be a value type, a list or a query, not {}",
+                    step, other
+                )))
+            }
+        };
+        Ok(Query { source, steps: Vec::new() })
+    }
+
+    /// This query, then `step`.
+    pub fn then(mut self, step: Step) -> Query {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn source(&self) -> &Source {
+        &self.source
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Run the query, collecting its rows.
+    pub fn materialize(&self, registry: &ValueRegistry) -> Result<Vec<EvalValue>> {
+        let mut rows = Vec::new();
+        self.for_each(registry, |row| {
+            rows.push(row);
+            Ok(true)
+        })?;
+        Ok(rows)
+    }
+
+    /// Run the query, counting its rows without keeping them.
+    pub fn count(&self, registry: &ValueRegistry) -> Result<usize> {
+        let mut count = 0;
+        self.for_each(registry, |_| {
+            count += 1;
+            Ok(true)
+        })?;
+        Ok(count)
+    }
+
+    /// Run the query, passing each row that comes out of its steps to
+    /// `visit`, until it returns false.
+    pub fn for_each(&self, registry: &ValueRegistry, visit: impl FnMut(EvalValue) -> Result<bool>) -> Result<()> {
+        // Up to a step that needs every row, rows are streamed; what it
+        // gives is then streamed through the steps after it
+        let mut rows = None;
+        let mut steps = self.steps.as_slice();
+        while let Some(at) = steps.iter().position(Step::needs_every_row) {
+            let mut collected = Vec::new();
+            self.stream(rows.take(), &steps[..at], registry, |row| {
+                collected.push(row);
+                Ok(true)
+            })?;
+            rows = Some(steps[at].rearrange(collected, registry)?);
+            steps = &steps[at + 1..];
+        }
+        self.stream(rows, steps, registry, visit)
+    }
+
+    // Pass each of `rows`, or of the source if there are none yet, through
+    // `steps` that each take one row at a time
+    fn stream(
+        &self,
+        rows: Option<Vec<EvalValue>>,
+        steps: &[Step],
+        registry: &ValueRegistry,
+        mut visit: impl FnMut(EvalValue) -> Result<bool>,
+    ) -> Result<()> {
+        // The store runs what it can of the steps on a value type's
+        // instances, and the rest are run here on the rows it gives
+        let (pushdown, steps) = match (&rows, &self.source) {
+            (None, Source::Type(type_name)) => {
+                let (pushdown, pushed) = Pushdown::plan(type_name, steps, registry.capabilities(), registry);
+                (pushdown, &steps[pushed..])
+            }
+            _ => (Pushdown::default(), steps),
+        };
+        // Each step's scope is made once, and its parameter rebound per row
+        let mut scopes: Vec<HashMap<String, EvalValue>> =
+            steps.iter().map(|step| (*step.function().context).clone()).collect();
+        let mut run = |row: EvalValue| -> Result<bool> {
+            match apply(steps, row, &mut scopes, registry)? {
+                Some(row) => visit(row),
+                None => Ok(true),
+            }
+        };
+        match (rows, &self.source) {
+            (Some(rows), _) => {
+                for row in rows {
+                    if !run(row)? {
+                        break;
+                    }
+                }
+            }
+            (None, Source::Type(type_name)) => registry.scan(type_name, &pushdown, &mut run)?,
+            (None, Source::List(items)) => {
+                for item in items.iter() {
+                    if !run(item.clone())? {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// The row after every step, or None if a `where` drops it
+fn apply(
+    steps: &[Step],
+    mut row: EvalValue,
+    scopes: &mut [HashMap<String, EvalValue>],
+    registry: &ValueRegistry,
+) -> Result<Option<EvalValue>> {
+    for (step, scope) in steps.iter().zip(scopes) {
+        let function = step.function();
+        scope.insert(function.parameter.clone(), row);
+        let result = evaluate_expression(&function.body, scope, registry)?;
+        row = match step {
+            Step::Where(_) => match result {
+                EvalValue::Boolean(true) => scope.remove(&function.parameter).unwrap_or(result),
+                EvalValue::Boolean(false) => return Ok(None),
+                other => {
+                    return Err(failure(format!("The predicate of where evaluated to {}, not a Bool", other)))
+                }
+            },
+            _ => result,
+        };
+    }
+    Ok(Some(row))
+}
+
+impl Step {
+    /// The step `name(rows, function)` adds, if `name` is a step.
+    pub fn named(name: &str, function: Function) -> Option<Step> {
+        match name {
+            "where" => Some(Step::Where(function)),
+            "select" => Some(Step::Select(function)),
+            "sortBy" => Some(Step::SortBy(function)),
+            _ => Window::of_step(name).map(|window| Step::Window(window, function)),
+        }
+    }
+
+    pub fn function(&self) -> &Function {
+        match self {
+            Step::Where(function) | Step::Select(function) | Step::SortBy(function) | Step::Window(_, function) => {
+                function
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::Where(_) => "where",
+            Step::Select(_) => "select",
+            Step::SortBy(_) => "sortBy",
+            Step::Window(window, _) => window.name(),
+        }
+    }
+
+    /// Whether the step needs every row before it can give its first.
+    pub fn needs_every_row(&self) -> bool {
+        matches!(self, Step::SortBy(_) | Step::Window(..))
+    }
+
+    // What a step that needs every row gives for `rows`
+    fn rearrange(&self, rows: Vec<EvalValue>, registry: &ValueRegistry) -> Result<Vec<EvalValue>> {
+        let function = self.function();
+        let mut scope = (*function.context).clone();
+        let mut values = Vec::with_capacity(rows.len());
+        for row in &rows {
+            scope.insert(function.parameter.clone(), row.clone());
+            values.push(evaluate_expression(&function.body, &scope, registry)?);
+        }
+        match self {
+            Step::SortBy(_) => {
+                let mut order: Vec<usize> = (0..rows.len()).collect();
+                sort_by_key(&mut order, &values, self.name())?;
+                let mut rows: Vec<Option<EvalValue>> = rows.into_iter().map(Some).collect();
+                Ok(order.into_iter().filter_map(|index| rows[index].take()).collect())
+            }
+            Step::Window(window, _) => window.over(values),
+            Step::Where(_) | Step::Select(_) => Ok(rows),
+        }
+    }
+}
+
+impl Window {
+    /// The window a query step called `name` computes, if any.
+    pub fn of_step(name: &str) -> Option<Self> {
+        match name {
+            "running" => Some(Window::Running),
+            "rank" => Some(Window::Rank),
+            "lag" => Some(Window::Lag),
+            "lead" => Some(Window::Lead),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Window::Running => "running",
+            Window::Rank => "rank",
+            Window::Lag => "lag",
+            Window::Lead => "lead",
+        }
+    }
+
+    /// The types the function passed to the window may return, or None if
+    /// it may return any.
+    pub fn accepts(self) -> Option<&'static [Type]> {
+        match self {
+            Window::Running => Some(&[Type::Int]),
+            Window::Rank => Some(SORT_KEYS),
+            Window::Lag | Window::Lead => None,
+        }
+    }
+
+    /// The type of each row the window gives, given the type its function
+    /// returns.
+    pub fn result_type(self, function: &Type) -> Type {
+        match self {
+            Window::Running | Window::Rank => Type::Int,
+            Window::Lag | Window::Lead => Type::Option(Box::new(function.clone())),
+        }
+    }
+
+    // What the window gives for each row, from the function's value for
+    // every row, in order
+    fn over(self, values: Vec<EvalValue>) -> Result<Vec<EvalValue>> {
+        match self {
+            Window::Running => {
+                let mut total = 0i64;
+                values
+                    .into_iter()
+                    .map(|value| match value {
+                        EvalValue::Integer(n) => {
+                            total = total
+                                .checked_add(n)
+                                .ok_or_else(|| failure("The running total overflows an Int".to_string()))?;
+                            Ok(EvalValue::Integer(total))
+                        }
+                        other => Err(failure(format!("The function passed to running returned {}, not an Int", other))),
+                    })
+                    .collect()
+            }
+            Window::Rank => {
+                let mut order: Vec<usize> = (0..values.len()).collect();
+                sort_by_key(&mut order, &values, self.name())?;
+                let mut ranks = vec![EvalValue::Integer(0); values.len()];
+                for (position, &index) in order.iter().enumerate() {
+                    let rank = match position.checked_sub(1).map(|previous| order[previous]) {
+                        Some(previous) if values[previous] == values[index] => ranks[previous].clone(),
+                        _ => EvalValue::Integer(position as i64 + 1),
+                    };
+                    ranks[index] = rank;
+                }
+                Ok(ranks)
+            }
+            Window::Lag => {
+                let mut previous = None;
+                Ok(values
+                    .into_iter()
+                    .map(|value| EvalValue::Option(previous.replace(value).map(Box::new)))
+                    .collect())
+            }
+            Window::Lead => {
+                let mut next = None;
+                let mut leads: Vec<EvalValue> = values
+                    .into_iter()
+                    .rev()
+                    .map(|value| EvalValue::Option(next.replace(value).map(Box::new)))
+                    .collect();
+                leads.reverse();
+                Ok(leads)
+            }
+        }
+    }
+}
+
+/// The types `sortBy` and `rank` can order rows by.
+pub const SORT_KEYS: &[Type] = &[Type::Int, Type::String];
+
+// Order the indices of `keys` by the key at each, keeping ties in order
+fn sort_by_key(order: &mut [usize], keys: &[EvalValue], step: &str) -> Result<()> {
+    let mut unordered = None;
+    order.sort_by(|&l, &r| match (&keys[l], &keys[r]) {
+        (EvalValue::Integer(l), EvalValue::Integer(r)) => l.cmp(r),
+        (EvalValue::String(l), EvalValue::String(r)) => l.cmp(r),
+        (l, r) => {
+            unordered.get_or_insert_with(|| format!("{} cannot order {} and {}", step, l, r));
+            Ordering::Equal
+        }
+    });
+    match unordered {
+        Some(message) => Err(failure(message)),
+        None => Ok(()),
+    }
+}
+
+/// Evaluate a step, such as `where(rows, x => p)` or `select(rows, x => e)`:
+/// a query of `rows` with the step added, which visits none of them yet.
+pub fn evaluate_step(
+    name: &str,
+    rows: &Expression,
+    parameter: &str,
+    body: &Expression,
+    context: &HashMap<String, EvalValue>,
+    registry: &ValueRegistry,
+) -> Result<EvalValue> {
+    let query = Query::of(evaluate_expression(rows, context, registry)?, name)?;
+    let function = Function {
+        parameter: parameter.to_string(),
+        body: body.clone(),
+        context: Arc::new(context.clone()),
+    };
+    let step = Step::named(name, function).ok_or_else(|| failure(format!("{} is not a query step", name)))?;
+    Ok(EvalValue::Query(query.then(step)))
+}
+
+/// `value` with the query run, if it is one: for the host, or the REPL, to
+/// see rows rather than a description of them.
+pub fn materialized(value: EvalValue, registry: &ValueRegistry) -> Result<EvalValue> {
+    match value {
+        EvalValue::Query(query) => Ok(EvalValue::List(query.materialize(registry)?)),
+        other => Ok(other),
+    }
+}
+
+// As it would be written: `User |> where(u => u.age > 18)`
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Source::Type(type_name) => write!(f, "{}", type_name)?,
+            Source::List(items) => write!(f, "<a list of {}>", items.len())?,
+        }
+        for step in &self.steps {
+            let function = step.function();
+            write!(f, " |> {}({} => {})", step.name(), function.parameter, format_expression(&function.body))?;
+        }
+        Ok(())
+    }
+}
+
+fn failure(message: String) -> Error {
+    Error::Validation(ValidationError {
+        message,
+        value_type: "query".to_string(),
+    })
+}
 
 /// Filter rows in a relation based on a predicate
 pub fn where_clause(
@@ -56,7 +497,7 @@ pub fn select(
         for field_name in &fields {
             if let Some(value) = row.get(field_name) {
                 // Clone the value
-                new_row.insert(field_name.clone(), clone_value_object(value));
+                new_row.insert(field_name.clone(), clone_value_object(value.as_ref()));
             }
         }
         projected = projected.add_row(new_row)?;
@@ -66,7 +507,7 @@ pub fn select(
 }
 
 /// Helper to clone a ValueObject (since they don't implement Clone directly)
-fn clone_value_object(value: &Box<dyn ValueObject>) -> Box<dyn ValueObject> {
+fn clone_value_object(_value: &dyn ValueObject) -> Box<dyn ValueObject> {
     // This is a simplified implementation
     // In a real system, we'd need a proper cloning mechanism
     // For now, we'll create a string representation and parse it back
@@ -100,4 +541,130 @@ pub fn limit(relation: &Relation, n: usize) -> Result<Relation> {
 /// Count the number of rows in a relation
 pub fn count(relation: &Relation) -> usize {
     relation.rows().len()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::evaluator::EvalValue;
+    use crate::types::Type;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_lazy_queries() {
+        let visited = Arc::new(AtomicUsize::new(0));
+        let seen = Arc::clone(&visited);
+        let mut engine = Engine::new();
+        engine.register_fn("seen", move |n: i64| {
+            seen.fetch_add(1, Ordering::SeqCst);
+            n
+        });
+        engine.load("value Age(n: Int) {}\nAge(3)\nAge(20)\nAge(40)").unwrap();
+        let ints = |ns: &[i64]| EvalValue::List(ns.iter().copied().map(EvalValue::Integer).collect());
+
+        // Composing a query visits no rows
+        assert_eq!(engine.eval("let q = Age |> where(a => seen(a.n) > 10) in 1").unwrap(), EvalValue::Integer(1));
+        assert_eq!(visited.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            engine.type_of("Age |> where(a => a.n > 10) |> select(a => a.n)").unwrap(),
+            Type::Query(Box::new(Type::Int))
+        );
+
+        // Returned from an evaluation, it is run once, through both steps
+        assert_eq!(engine.eval("Age |> where(a => seen(a.n) > 10) |> select(a => a.n * 2)").unwrap(), ints(&[40, 80]));
+        assert_eq!(visited.load(Ordering::SeqCst), 3);
+
+        assert_eq!(engine.eval("Age.where(a => a.n > 10).count()").unwrap(), EvalValue::Integer(2));
+        assert_eq!(engine.eval("materialize(Age |> select(a => a.n))").unwrap(), ints(&[3, 20, 40]));
+        assert_eq!(engine.eval("all(Age) |> select(a => a.n)").unwrap(), ints(&[3, 20, 40]));
+        // A quantifier stops at the first row that decides it
+        visited.store(0, Ordering::SeqCst);
+        assert_eq!(engine.eval("exists(Age |> select(a => seen(a.n)), n => n > 10)").unwrap(), EvalValue::Boolean(true));
+        assert_eq!(visited.load(Ordering::SeqCst), 2);
+
+        // Queries can be passed to functions, still unrun
+        engine.load("fn size(q: Query[Age]) -> Int { q.count() }").unwrap();
+        assert_eq!(engine.eval("Age.where(a => a.n > 10) |> size").unwrap(), EvalValue::Integer(2));
+        let ages = engine.eval("let q = Age |> where(a => a.n > 10) in q").unwrap();
+        assert_eq!(ages.to_string(), "[Age(20), Age(40)]");
+        let error = engine.eval("Age |> where(a => a.n)").unwrap_err();
+        assert!(error.to_string().contains("predicate of where"), "{}", error);
+        assert!(engine.eval("where").is_err());
+        assert!(engine.eval("where(5, n => true)").unwrap_err().to_string().contains("a list or a query"));
+    }
+
+    #[test]
+    fn test_comprehensions() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value User(email: String) {}
+                 User(\"ada@example.com\")
+                 User(\"bob@example.org\")
+                 fn work() -> List[String] { [u.email for u in User if u.email contains \".com\"] }",
+            )
+            .unwrap();
+        let strings = |ss: &[&str]| EvalValue::List(ss.iter().map(|s| EvalValue::String(s.to_string())).collect());
+
+        // Sugar for where and select, materialized into a list
+        assert_eq!(engine.eval("work()").unwrap(), strings(&["ada@example.com"]));
+        assert_eq!(
+            engine.eval("[u.email for u in all(User)]").unwrap(),
+            strings(&["ada@example.com", "bob@example.org"])
+        );
+        assert_eq!(
+            engine.type_of("[u.email.length > 3 for u in User if u.email contains \"ada\"]").unwrap(),
+            Type::List(Box::new(Type::Bool))
+        );
+        // Iterating all(User) or User.all() sees Users, whose fields compare
+        for source in ["all(User)", "User.all()"] {
+            let comprehension = format!("[u.email for u in {} if u.email == \"bob@example.org\"]", source);
+            assert_eq!(engine.eval(&comprehension).unwrap(), strings(&["bob@example.org"]));
+            let query = format!("{}.where(u => u.email == \"ada@example.com\").count()", source);
+            assert_eq!(engine.eval(&query).unwrap(), EvalValue::Integer(1));
+        }
+        assert_eq!(engine.type_of("all(User)").unwrap(), Type::List(Box::new(Type::Value("User".to_string()))));
+        let lengths = engine.eval("[n * 2 for n in [u.email.length for u in User] if n > 0]").unwrap();
+        assert_eq!(lengths.to_string(), "[30, 30]");
+
+        // `for` and `if` are still names outside a comprehension
+        assert_eq!(engine.eval("let for = 1 in for + 1").unwrap(), EvalValue::Integer(2));
+        let error = engine.type_of("[u.email for u in User if u.email]").unwrap_err();
+        assert!(error.to_string().contains("predicate of where"), "{}", error);
+        assert!(engine.eval("[u.email in User]").is_err());
+    }
+
+    #[test]
+    fn test_window_steps() {
+        let mut engine = Engine::new();
+        engine.load("value Sale(amount: Int) {}\nSale(5)\nSale(10)\nSale(5)").unwrap();
+        let ints = |ns: &[i64]| EvalValue::List(ns.iter().copied().map(EvalValue::Integer).collect());
+        let sorted = "Sale |> sortBy(s => s.amount)";
+
+        assert_eq!(engine.eval(&format!("{} |> select(s => s.amount)", sorted)).unwrap(), ints(&[5, 5, 10]));
+        assert_eq!(engine.eval(&format!("{} |> running(s => s.amount)", sorted)).unwrap(), ints(&[5, 10, 20]));
+        // Rows with the same key share a rank, and the next is skipped
+        assert_eq!(engine.eval("Sale |> rank(s => s.amount)").unwrap(), ints(&[1, 3, 1]));
+        assert_eq!(engine.eval("Sale |> rank(s => 0 - s.amount)").unwrap(), ints(&[2, 1, 2]));
+        let lag = engine.eval(&format!("{} |> lag(s => s.amount)", sorted)).unwrap();
+        assert_eq!(lag.to_string(), "[None, Some(5), Some(5)]");
+        let lead = engine.eval("Sale.lead(s => s.amount)").unwrap();
+        assert_eq!(lead.to_string(), "[Some(10), Some(5), None]");
+
+        // Steps after a window are streamed as usual
+        assert_eq!(
+            engine.eval(&format!("({} |> running(s => s.amount) |> where(t => t > 5)).count()", sorted)).unwrap(),
+            EvalValue::Integer(2)
+        );
+        assert_eq!(engine.eval("Sale.where(s => s.amount > 5).running(s => s.amount)").unwrap(), ints(&[10]));
+        assert_eq!(
+            engine.type_of("Sale |> lag(s => s.amount)").unwrap(),
+            Type::Query(Box::new(Type::Option(Box::new(Type::Int))))
+        );
+        assert_eq!(engine.type_of("Sale |> rank(s => s.amount)").unwrap(), Type::Query(Box::new(Type::Int)));
+
+        let error = engine.eval("Sale |> running(s => s.amount > 1)").unwrap_err();
+        assert!(error.to_string().contains("function passed to running"), "{}", error);
+        assert!(engine.eval("Sale |> sortBy(s => s)").is_err());
+    }
+}
@@ -1,4 +1,6 @@
 use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::EvalValue;
+use crate::stable_hash::{self, stable_hash};
 use crate::value::ValueObject;
 use std::any::Any;
 use std::collections::HashMap;
@@ -135,6 +137,132 @@ impl Relation {
     }
 }
 
+/// Relations written in Relic hold `EvalValue`s as their cells.
+impl Relation {
+    /// This relation with a row added whose values are `values`, one per
+    /// field in schema order, each of the field's type.
+    pub fn add_values(&self, values: Vec<EvalValue>) -> Result<Relation> {
+        if values.len() != self.schema.fields.len() {
+            return Err(Error::Validation(ValidationError {
+                message: format!(
+                    "A row of this relation needs {} values, one per field, but {} were given",
+                    self.schema.fields.len(),
+                    values.len()
+                ),
+                value_type: "Relation".to_string(),
+            }));
+        }
+        let mut row: HashMap<String, Box<dyn ValueObject>> = HashMap::new();
+        for ((field, type_name), value) in self.schema.fields.iter().zip(values) {
+            if !fits(type_name, &value) {
+                return Err(Error::Validation(ValidationError {
+                    message: format!("Field '{}' of the relation is {}, not {}", field, type_name, value),
+                    value_type: "Relation".to_string(),
+                }));
+            }
+            row.insert(field.clone(), Box::new(value));
+        }
+        self.add_row(row)
+    }
+
+    /// The values of each row, in schema order.
+    pub fn cells(&self) -> Vec<Vec<EvalValue>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                self.schema
+                    .fields
+                    .iter()
+                    .map(|(field, _)| row.get(field).map_or(EvalValue::Option(None), |value| cell(value.as_ref())))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Each row as a `Row` value, with a field per column.
+    pub fn row_values(&self) -> Vec<EvalValue> {
+        self.rows
+            .iter()
+            .map(|row| EvalValue::Value {
+                type_name: "Row".to_string(),
+                fields: row.iter().map(|(field, value)| (field.clone(), cell(value.as_ref()))).collect(),
+                display: None,
+                sensitive: false,
+            })
+            .collect()
+    }
+}
+
+/// A cell's value. Cells added from Relic are `EvalValue`s; any other
+/// value object is shown as text.
+fn cell(value: &dyn ValueObject) -> EvalValue {
+    match value.as_any().downcast_ref::<EvalValue>() {
+        Some(value) => value.clone(),
+        None => EvalValue::String(value.to_string()),
+    }
+}
+
+/// Whether `value` belongs in a field whose type is written `type_name`.
+/// The element types of lists and options aren't checked.
+fn fits(type_name: &str, value: &EvalValue) -> bool {
+    let found = match value {
+        EvalValue::String(_) => "String",
+        EvalValue::Integer(_) => "Int",
+        EvalValue::Boolean(_) => "Bool",
+        EvalValue::Value { type_name, .. } => type_name,
+        EvalValue::Type(_) => "Type",
+        EvalValue::List(_) => "List",
+        EvalValue::Option(_) => "Option",
+        EvalValue::Relation(_) => "Relation",
+        EvalValue::Temporal(t) => t.type_name(),
+        EvalValue::Decimal(_) => "Decimal",
+        EvalValue::Query(_) => "Query",
+    };
+    type_name == "Any" || type_name == found || type_name.strip_prefix(found).is_some_and(|rest| rest.starts_with('['))
+}
+
+impl ValueObject for EvalValue {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn normalize(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            EvalValue::String(_) => "String",
+            EvalValue::Integer(_) => "Int",
+            EvalValue::Boolean(_) => "Bool",
+            EvalValue::Value { .. } => "Value",
+            EvalValue::Type(_) => "Type",
+            EvalValue::List(_) => "List",
+            EvalValue::Option(_) => "Option",
+            EvalValue::Temporal(t) => t.type_name(),
+            EvalValue::Decimal(_) => "Decimal",
+            EvalValue::Relation(_) => "Relation",
+            EvalValue::Query(_) => "Query",
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn equals(&self, other: &dyn ValueObject) -> bool {
+        other.as_any().downcast_ref::<EvalValue>() == Some(self)
+    }
+
+    fn hash_value(&self) -> u64 {
+        stable_hash(|hasher| stable_hash::write_value(hasher, self))
+    }
+}
+
 impl ValueObject for Relation {
     fn validate(&self) -> Result<()> {
         // Relations are always valid after construction
@@ -160,24 +288,35 @@ impl ValueObject for Relation {
     
     fn equals(&self, other: &dyn ValueObject) -> bool {
         if let Some(other_rel) = other.as_any().downcast_ref::<Relation>() {
-            // Compare schemas and rows
-            self.schema == other_rel.schema &&
-            self.rows.len() == other_rel.rows.len()
-            // TODO: Proper row comparison
+            // Same schema, and the same rows in the same order
+            self.schema == other_rel.schema
+                && self.rows.len() == other_rel.rows.len()
+                && self.rows.iter().zip(&other_rel.rows).all(|(row, other_row)| {
+                    row.len() == other_row.len()
+                        && row.iter().all(|(field, value)| {
+                            other_row.get(field).is_some_and(|other| self.values_equal(value.as_ref(), other.as_ref()))
+                        })
+                })
         } else {
             false
         }
     }
     
     fn hash_value(&self) -> u64 {
-        let mut hasher = std::collections::hash_map::DefaultHasher::new();
-        // Hash schema and row count
-        for (name, typ) in &self.schema.fields {
-            name.hash(&mut hasher);
-            typ.hash(&mut hasher);
-        }
-        self.rows.len().hash(&mut hasher);
-        hasher.finish()
+        // Schema and row count
+        stable_hash(|hasher| {
+            for (name, typ) in &self.schema.fields {
+                hasher.write_text(name);
+                typ.hash(hasher);
+            }
+            hasher.write_usize(self.rows.len());
+        })
+    }
+}
+
+impl PartialEq for Relation {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other)
     }
 }
 
@@ -191,4 +330,66 @@ impl std::fmt::Debug for Relation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Relation(schema: {:?}, {} rows)", self.schema, self.rows.len())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_relations_in_relic() {
+        let mut engine = Engine::new();
+        let EvalValue::Relation(people) = engine
+            .eval("Relation(name: String, age: Int) { (\"bob\", 30), (\"amy\", 41) }")
+            .unwrap()
+        else {
+            panic!("expected a relation");
+        };
+        assert_eq!(
+            people.cells(),
+            [
+                vec![EvalValue::String("bob".to_string()), EvalValue::Integer(30)],
+                vec![EvalValue::String("amy".to_string()), EvalValue::Integer(41)],
+            ]
+        );
+        assert_eq!(
+            EvalValue::Relation(people.clone()).to_string(),
+            "Relation(name: String, age: Int) { (\"bob\", 30), (\"amy\", 41) }"
+        );
+
+        // Adding a row makes a new relation
+        engine
+            .load("fn withCy(r: Relation) -> Relation { r.addRow(\"cy\", 7) }\nfn size(r: Relation) -> Int { r.rows().length() }")
+            .unwrap();
+        assert_eq!(
+            engine.eval("let r = Relation(name: String, age: Int) in size(withCy(withCy(r))) + size(r)").unwrap(),
+            EvalValue::Integer(2)
+        );
+        assert_eq!(
+            engine.eval("Relation(id: Int, tag: String).schema()").unwrap(),
+            EvalValue::List(vec![
+                EvalValue::String("id: Int".to_string()),
+                EvalValue::String("tag: String".to_string()),
+            ])
+        );
+
+        let error = engine.eval("Relation(name: String) { (\"a\"), (1) }").unwrap_err();
+        assert!(error.to_string().contains("field 'name' of row 2: expected `String`, found `Int`"), "{}", error);
+        assert!(engine.eval("Relation(name: String) { (\"a\", 1) }").is_err());
+        assert!(engine.eval("Relation(a: Int, a: Int)").is_err());
+        let error = engine.eval("Relation(id: Int).addRow(\"x\")").unwrap_err();
+        assert!(error.to_string().contains("Field 'id' of the relation is Int, not x"), "{}", error);
+        assert!(engine.eval("Relation(id: Int).addRow(1, 2)").is_err());
+    }
+
+    #[test]
+    fn test_equality_compares_rows() {
+        let schema = Schema {
+            fields: vec![("n".to_string(), "Int".to_string())],
+        };
+        let one = Relation::new(schema.clone()).add_values(vec![EvalValue::Integer(1)]).unwrap();
+        let two = Relation::new(schema).add_values(vec![EvalValue::Integer(2)]).unwrap();
+        assert_ne!(one, two);
+        assert_eq!(one, one.clone());
+    }
+}
@@ -0,0 +1,256 @@
+//! Redefining functions and value types while a program runs. A reload
+//! replaces a declaration only if the code already calling it can't tell
+//! the difference in types: a function must keep its overloads' parameter
+//! and return types, and a value type its parameter, while guards, bodies,
+//! validation and normalization may change. Anything else is rejected with
+//! what changed, and nothing is replaced.
+//!
+//! Instances of a redefined value type are kept, except those that fail its
+//! new validation, as when a file is run again with `--keep-instances`. The
+//! cached dispatches and specializations of a redefined function are
+//! dropped, so that the next call resolves against the new overloads.
+
+use crate::ast::{Declaration, Program};
+use crate::compiler::Compiler;
+use crate::error::{Error, Result, TypeError};
+use crate::evaluator;
+use crate::formatter::format_type;
+use crate::typechecker::TypeChecker;
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// What a reload replaced and defined.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reload {
+    /// The functions and value types replaced
+    pub redefined: Vec<String>,
+    /// The functions and value types defined for the first time
+    pub added: Vec<String>,
+    /// How many instances of each redefined value type failed its new
+    /// validation and were dropped
+    pub dropped: Vec<(String, usize)>,
+}
+
+impl fmt::Display for Reload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if !self.redefined.is_empty() {
+            parts.push(format!("Redefined {}", self.redefined.join(", ")));
+        }
+        if !self.added.is_empty() {
+            parts.push(format!("defined {}", self.added.join(", ")));
+        }
+        for (type_name, count) in &self.dropped {
+            parts.push(format!("dropped {} {} instances that fail its new validation", count, type_name));
+        }
+        match parts.is_empty() {
+            true => write!(f, "Nothing to reload"),
+            false => {
+                let text = parts.join("; ");
+                write!(f, "{}{}", text[..1].to_uppercase(), &text[1..])
+            }
+        }
+    }
+}
+
+/// Replace the functions and value types `program` declares that are
+/// already defined, and define the others. `program` may only declare.
+///
+/// Nothing changes if a redefinition is incompatible, a function is built
+/// in, or the program doesn't type check.
+pub fn reload(typechecker: &mut TypeChecker, compiler: &mut Compiler, program: &Program) -> Result<Reload> {
+    if !program.expressions.is_empty()
+        || !program.tests.is_empty()
+        || !program.data.is_empty()
+        || !program.imports.is_empty()
+    {
+        return Err(invalid("A reload takes only value type and function declarations".to_string()));
+    }
+
+    let registry = compiler.get_registry();
+    let mut reload = Reload::default();
+    let mut incompatible = Vec::new();
+    let mut functions: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for declaration in &program.declarations {
+        match declaration {
+            Declaration::Value(value) => {
+                let Some(old) = registry.constructors.get(&value.name) else {
+                    reload.added.push(value.name.clone());
+                    continue;
+                };
+                let old = &old.declaration.parameter;
+                let new = &value.parameter;
+                if old.name != new.name || old.ty != new.ty {
+                    incompatible.push(format!(
+                        "value {}:\n  - {}({}: {})\n  + {}({}: {})",
+                        value.name,
+                        value.name,
+                        old.name,
+                        format_type(&old.ty),
+                        value.name,
+                        new.name,
+                        format_type(&new.ty)
+                    ));
+                }
+                reload.redefined.push(value.name.clone());
+            }
+            Declaration::Function(function) => {
+                let parameters = function.parameters.iter().map(|p| &p.ty);
+                let signature = signature(&function.name, parameters, &function.return_type);
+                functions.entry(&function.name).or_default().push(signature);
+            }
+            Declaration::Method(method) => {
+                let parameters = method.parameters.iter().map(|p| &p.ty);
+                let signature = signature(&method.name, parameters, &method.return_type);
+                functions.entry(&method.name).or_default().push(signature);
+            }
+        }
+    }
+    for (name, mut new) in functions {
+        let Some(overloads) = registry.get_functions(name) else {
+            reload.added.push(name.to_string());
+            continue;
+        };
+        if overloads.iter().any(|overload| registry.native(overload).is_some()) {
+            return Err(invalid(format!("Function '{}' is built in and can't be redefined", name)));
+        }
+        let mut old: Vec<String> = overloads
+            .iter()
+            .map(|overload| {
+                let parameters = overload.parameters.iter().map(|p| &p.ty);
+                signature(name, parameters, &overload.return_type)
+            })
+            .collect();
+        old.sort();
+        new.sort();
+        if old != new {
+            let mut diff = format!("fn {}:", name);
+            for removed in old.iter().filter(|signature| !new.contains(signature)) {
+                diff.push_str(&format!("\n  - {}", removed));
+            }
+            for added in new.iter().filter(|signature| !old.contains(signature)) {
+                diff.push_str(&format!("\n  + {}", added));
+            }
+            if old.len() != new.len() {
+                diff.push_str(&format!("\n  {} overloads became {}", old.len(), new.len()));
+            }
+            incompatible.push(diff);
+        }
+        reload.redefined.push(name.to_string());
+    }
+    if !incompatible.is_empty() {
+        return Err(invalid(format!(
+            "Incompatible redefinition; the types callers rely on changed:\n{}",
+            incompatible.join("\n")
+        )));
+    }
+
+    // Check the new declarations before anything is replaced
+    let mut checker = typechecker.clone();
+    for name in &reload.redefined {
+        checker.forget(name);
+    }
+    checker.check_program(program)?;
+    *typechecker = checker;
+
+    let counts: Vec<(String, usize)> = reload
+        .redefined
+        .iter()
+        .filter(|name| registry.constructors.contains_key(*name))
+        .map(|name| (name.clone(), instance_count(name, registry)))
+        .collect();
+    for name in &reload.redefined {
+        // A value type is replaced in place, keeping its instances
+        if !compiler.get_registry().constructors.contains_key(name) {
+            compiler.forget(name);
+        }
+    }
+    compiler.compile_program(program)?;
+
+    let registry = compiler.get_registry();
+    for (name, before) in counts {
+        let after = instance_count(&name, registry);
+        if after < before {
+            reload.dropped.push((name, before - after));
+        }
+    }
+    Ok(reload)
+}
+
+// How a caller sees an overload: `area(Circle) -> Int`
+fn signature<'a>(name: &str, parameters: impl Iterator<Item = &'a Type>, return_type: &Type) -> String {
+    let parameters: Vec<String> = parameters.map(format_type).collect();
+    format!("{}({}) -> {}", name, parameters.join(", "), format_type(return_type))
+}
+
+fn instance_count(type_name: &str, registry: &ValueRegistry) -> usize {
+    evaluator::instance_values(type_name, registry).len()
+}
+
+fn invalid(message: String) -> Error {
+    Error::Type(TypeError { message, span: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::evaluator::EvalValue;
+
+    #[test]
+    fn test_reload() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value Age(n: Int) { validate: n >= 0 }
+                 fn describe(a: Age) -> String { \"age\" }
+                 fn twice(n: Int) -> Int { n * 2 }
+                 Age(3)
+                 Age(30)",
+            )
+            .unwrap();
+        assert_eq!(engine.eval("describe(Age(3))").unwrap(), EvalValue::String("age".to_string()));
+
+        let reload = engine
+            .reload(
+                "value Age(n: Int) { validate: n >= 0 && n < 20 }
+                 fn describe(a: Age) -> String { \"young\" }
+                 fn half(n: Int) -> Int { n / 2 }",
+            )
+            .unwrap();
+        assert_eq!(reload.redefined, ["Age", "describe"]);
+        assert_eq!(reload.added, ["half"]);
+        assert_eq!(reload.dropped, [("Age".to_string(), 1)]);
+        assert_eq!(
+            reload.to_string(),
+            "Redefined Age, describe; defined half; dropped 1 Age instances that fail its new validation"
+        );
+        // The cached dispatch of describe resolves to the new body
+        assert_eq!(engine.eval("describe(Age(3))").unwrap(), EvalValue::String("young".to_string()));
+        assert!(engine.eval("Age(30)").is_err());
+        assert_eq!(engine.eval("half(twice(4))").unwrap(), EvalValue::Integer(4));
+
+        // Incompatible redefinitions change nothing
+        let error = engine
+            .reload(
+                "fn describe(a: Age) -> Int { 1 }
+                 value Age(years: Int) {}",
+            )
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("value Age:\n  - Age(n: Int)\n  + Age(years: Int)"), "{}", error);
+        assert!(
+            error.contains("fn describe:\n  - describe(Age) -> String\n  + describe(Age) -> Int"),
+            "{}",
+            error
+        );
+        assert_eq!(engine.eval("describe(Age(3))").unwrap(), EvalValue::String("young".to_string()));
+
+        // A body that doesn't type check changes nothing either
+        assert!(engine.reload("fn twice(n: Int) -> Int { \"two\" }").is_err());
+        assert_eq!(engine.eval("twice(2)").unwrap(), EvalValue::Integer(4));
+        assert!(engine.reload("twice(2)").is_err());
+        assert!(engine.reload("fn assert(condition: Bool) -> Bool { true }").is_err());
+    }
+}
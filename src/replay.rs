@@ -0,0 +1,228 @@
+//! Replay logs: every input of a REPL session or an `Engine`, with what it
+//! produced, written so that `relic replay` can run the inputs again in a
+//! fresh session and show where the results differ. A log attached to a bug
+//! report reproduces it, and shows whether a fix changed anything else.
+//!
+//! A log is text. Its first line says what ran the inputs, each input's
+//! lines start with `> `, each of its results with `= `, a result's further
+//! lines with `| `, and a blank line ends the entry:
+//!
+//! ```text
+//! # relic replay log: repl
+//! > value Age(n: Int) { validate: n >= 0 }
+//! = Defined value type: Age
+//!
+//! > Age(-1)
+//! = error: Validation error in Age: Validation failed
+//! ```
+
+use crate::error::{Error, Result, ValidationError};
+use std::fmt;
+
+const HEADER: &str = "# relic replay log: ";
+
+/// What ran a log's inputs, and so what must run them again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recorder {
+    /// The REPL, whose inputs include `:commands`
+    Repl,
+    /// `Engine::load` and `Engine::eval`
+    Engine,
+}
+
+impl Recorder {
+    pub fn name(self) -> &'static str {
+        match self {
+            Recorder::Repl => "repl",
+            Recorder::Engine => "engine",
+        }
+    }
+}
+
+/// The inputs of a session, in order, each with its results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayLog {
+    pub recorder: Recorder,
+    pub entries: Vec<Entry>,
+}
+
+/// One input and what it produced: values, messages and errors, as text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub input: String,
+    pub results: Vec<String>,
+}
+
+impl ReplayLog {
+    pub fn new(recorder: Recorder) -> Self {
+        ReplayLog {
+            recorder,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, input: &str, results: Vec<String>) {
+        self.entries.push(Entry {
+            input: input.to_string(),
+            results,
+        });
+    }
+
+    /// Read a log written by `Display`.
+    pub fn parse(text: &str) -> Result<ReplayLog> {
+        let mut lines = text.lines().enumerate();
+        let recorder = match lines.next().and_then(|(_, line)| line.strip_prefix(HEADER)) {
+            Some("repl") => Recorder::Repl,
+            Some("engine") => Recorder::Engine,
+            _ => return Err(invalid(1, "expected '# relic replay log: repl' or '# relic replay log: engine'")),
+        };
+        let mut log = ReplayLog::new(recorder);
+        // The entry being read
+        let mut input: Vec<&str> = Vec::new();
+        let mut results: Vec<String> = Vec::new();
+        for (index, line) in lines {
+            let marker = line.get(..1).unwrap_or_default();
+            let rest = line[marker.len()..].strip_prefix(' ').unwrap_or(&line[marker.len()..]);
+            match marker {
+                "" if !input.is_empty() => {
+                    log.record(&input.join("\n"), std::mem::take(&mut results));
+                    input.clear();
+                }
+                "" => {}
+                ">" if results.is_empty() => input.push(rest),
+                "=" if !input.is_empty() => results.push(rest.to_string()),
+                "|" if !results.is_empty() => {
+                    let result = results.last_mut().unwrap();
+                    result.push('\n');
+                    result.push_str(rest);
+                }
+                _ => return Err(invalid(index + 1, "expected '> ' before an input or '= ' before a result")),
+            }
+        }
+        if !input.is_empty() {
+            log.record(&input.join("\n"), results);
+        }
+        Ok(log)
+    }
+
+    /// The entries of `replayed` whose results differ from those of this
+    /// log, as a replay of its inputs produced them.
+    pub fn diff(&self, replayed: &ReplayLog) -> Vec<Difference> {
+        self.entries
+            .iter()
+            .zip(&replayed.entries)
+            .enumerate()
+            .filter(|(_, (recorded, replayed))| recorded.results != replayed.results)
+            .map(|(index, (recorded, replayed))| Difference {
+                number: index + 1,
+                input: recorded.input.clone(),
+                recorded: recorded.results.clone(),
+                replayed: replayed.results.clone(),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for ReplayLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}{}", HEADER, self.recorder.name())?;
+        for (index, entry) in self.entries.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            for line in entry.input.split('\n') {
+                writeln!(f, "> {}", line)?;
+            }
+            for result in &entry.results {
+                let mut lines = result.split('\n');
+                writeln!(f, "= {}", lines.next().unwrap_or_default())?;
+                for line in lines {
+                    writeln!(f, "| {}", line)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An input whose results changed when it was replayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Which input it is, counting from 1
+    pub number: usize,
+    pub input: String,
+    pub recorded: Vec<String>,
+    pub replayed: Vec<String>,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Input {} differs: {}", self.number, self.input)?;
+        for result in &self.recorded {
+            writeln!(f, "  - {}", result)?;
+        }
+        for result in &self.replayed {
+            writeln!(f, "  + {}", result)?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(line: usize, message: &str) -> Error {
+    Error::Validation(ValidationError {
+        message: format!("Invalid replay log at line {}: {}", line, message),
+        value_type: "replay log".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_replay_log() {
+        let mut log = ReplayLog::new(Recorder::Repl);
+        log.record("fn f(n: Int) -> Int {\n\n  n\n}", vec!["Defined function: f".to_string()]);
+        log.record(":stats", vec!["Age: 1\nEmail: 2".to_string(), "error: oops".to_string()]);
+        log.record(":format json", Vec::new());
+        let text = log.to_string();
+        assert_eq!(
+            text,
+            "# relic replay log: repl\n\
+             > fn f(n: Int) -> Int {\n> \n>   n\n> }\n= Defined function: f\n\n\
+             > :stats\n= Age: 1\n| Email: 2\n= error: oops\n\n\
+             > :format json\n"
+        );
+        assert_eq!(ReplayLog::parse(&text).unwrap(), log);
+
+        assert!(ReplayLog::parse("> 1 + 1\n").unwrap_err().to_string().contains("line 1"));
+        let error = ReplayLog::parse("# relic replay log: repl\n> 1\n= 1\n> 2\n").unwrap_err();
+        assert!(error.to_string().contains("line 4"), "{}", error);
+    }
+
+    #[test]
+    fn test_engine_replay() {
+        let mut engine = Engine::new().with_replay_log();
+        engine.load("value Age(n: Int) { validate: n >= 0 }\nAge(3)").unwrap();
+        engine.eval("Age(-1)").unwrap_err();
+        engine.eval("Age(3).n + 1").unwrap();
+        let log = engine.replay_log().unwrap();
+        assert_eq!(log.entries.len(), 3);
+        assert_eq!(log.entries[0].results, ["Age(3)"]);
+        assert_eq!(log.entries[1].results, ["error: Validation error in Age: Validation failed"]);
+        assert_eq!(log.entries[2].results, ["4"]);
+
+        // A fresh engine reproduces the session
+        assert_eq!(Engine::new().replay(&log).unwrap(), Vec::new());
+
+        let mut changed = ReplayLog::parse(&log.to_string()).unwrap();
+        changed.entries[2].results = vec!["5".to_string()];
+        let differences = Engine::new().replay(&changed).unwrap();
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].to_string(), "Input 3 differs: Age(3).n + 1\n  - 5\n  + 4\n");
+
+        assert!(Engine::new().replay(&ReplayLog::new(Recorder::Repl)).is_err());
+        assert!(Engine::new().replay_log().is_none());
+    }
+}
@@ -0,0 +1,363 @@
+//! The rules a value type's `validate:` places on its parameter, read off
+//! the validation expression, for a UI to check input the way Relic will
+//! before it's sent: `value Username(raw: String) { validate: raw.length >=
+//! 3 && raw.length <= 20 && raw contains "_" }` gives a length of 3 to 20
+//! and a required `_`, which `html_attributes` turns into `minlength`,
+//! `maxlength` and `pattern`.
+//!
+//! The validation is split at each `&&`. A part that compares the
+//! parameter, or its length, with an Int literal, or asks whether it
+//! contains a String literal, is a rule; any other part is kept as Relic
+//! text in `unchecked`, for Relic to check when the value is constructed.
+//! A call of a function with one overload, such as the prelude's
+//! `between(n, 1, 10)` or `nonEmpty(raw)`, is read as its body with the
+//! arguments in place of its parameters.
+
+use crate::ast::{BinaryOp, ComparisonOp, Expression, Literal, UnaryOp, ValueDeclaration};
+use crate::diagnostic::json_string;
+use crate::formatter::{format_expression, format_type};
+use crate::types::Type;
+use crate::value::ValueRegistry;
+use std::collections::HashMap;
+
+// How deeply calls are followed, so that a recursive function is left
+// unchecked rather than followed forever
+const MAX_CALL_DEPTH: usize = 8;
+
+// What each variable in scope stands for, in terms of the validation: the
+// parameter itself, or the argument a followed call's parameter was given
+type Arguments = HashMap<String, Expression>;
+
+/// What the validation of a value type asks of its parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationRules {
+    pub type_name: String,
+    pub parameter: String,
+    pub parameter_type: Type,
+    /// The parts of the validation that are rules, in the order written
+    pub rules: Vec<Rule>,
+    /// The parts that aren't, formatted as Relic
+    pub unchecked: Vec<String>,
+    /// Whether the type has a `normalize:`, whose result is what's
+    /// validated, so that input breaking a rule may still be accepted once
+    /// normalized
+    pub normalized: bool,
+}
+
+/// One rule on the parameter. Bounds are inclusive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// A String at least this many characters long
+    MinLength(i64),
+    /// A String at most this many characters long
+    MaxLength(i64),
+    /// An Int at least this
+    Min(i64),
+    /// An Int at most this
+    Max(i64),
+    /// A String containing this
+    Contains(String),
+}
+
+impl ValidationRules {
+    /// The rules of the value type `declaration` declares, following calls
+    /// to the functions of `registry`.
+    pub fn of(declaration: &ValueDeclaration, registry: &ValueRegistry) -> ValidationRules {
+        let parameter = &declaration.parameter;
+        let mut rules = ValidationRules {
+            type_name: declaration.name.clone(),
+            parameter: parameter.name.clone(),
+            parameter_type: parameter.ty.clone(),
+            rules: Vec::new(),
+            unchecked: Vec::new(),
+            normalized: declaration.body.normalize.is_some(),
+        };
+        if let Some(validate) = &declaration.body.validate {
+            let scope = Arguments::from([(parameter.name.clone(), Expression::Identifier(parameter.name.clone()))]);
+            let unchecked = rules.add(validate, &scope, registry, 0);
+            rules.unchecked = unchecked;
+        }
+        rules
+    }
+
+    // Add the rules of `part`, returning the parts that aren't rules
+    fn add(&mut self, part: &Expression, arguments: &Arguments, registry: &ValueRegistry, depth: usize) -> Vec<String> {
+        match part {
+            Expression::Binary(BinaryOp::And, left, right) => {
+                let mut unchecked = self.add(left, arguments, registry, depth);
+                unchecked.extend(self.add(right, arguments, registry, depth));
+                unchecked
+            }
+            Expression::Literal(Literal::Boolean(true)) => Vec::new(),
+            Expression::FunctionCall(name, args) if depth < MAX_CALL_DEPTH => {
+                let function = match registry.get_functions(name).map(Vec::as_slice) {
+                    Some([function])
+                        if function.parameters.len() == args.len()
+                            && function.parameters.iter().all(|parameter| parameter.guard.is_none()) =>
+                    {
+                        function
+                    }
+                    _ => return vec![format_expression(part)],
+                };
+                let inner: Arguments = function
+                    .parameters
+                    .iter()
+                    .zip(args)
+                    .filter_map(|(parameter, arg)| Some((parameter.name.clone(), resolved(arg, arguments)?)))
+                    .collect();
+                // The rules of the body hold for the call; if any of it isn't
+                // a rule, the call as written is left to Relic
+                if self.add(&function.body, &inner, registry, depth + 1).is_empty() {
+                    Vec::new()
+                } else {
+                    vec![format_expression(part)]
+                }
+            }
+            _ => match self.rules_of(part, arguments) {
+                Some(rules) => {
+                    self.rules.extend(rules);
+                    Vec::new()
+                }
+                None => vec![format_expression(part)],
+            },
+        }
+    }
+
+    // The rules `part` amounts to, if it's one the module doc lists
+    fn rules_of(&self, part: &Expression, arguments: &Arguments) -> Option<Vec<Rule>> {
+        let Expression::Comparison(op, left, right) = part else {
+            return None;
+        };
+        let (left, right) = (resolved(left, arguments)?, resolved(right, arguments)?);
+        if let (ComparisonOp::Contains, Type::String, Expression::Literal(Literal::String(text))) =
+            (op, &self.parameter_type, &right)
+        {
+            return self.is_parameter(&left).then(|| vec![Rule::Contains(text.clone())]);
+        }
+        // With the parameter on the left, `5 < n` read as `n > 5`
+        let (op, subject, bound) = match (integer(&left), integer(&right)) {
+            (None, Some(bound)) => (op.clone(), &left, bound),
+            (Some(bound), None) => (flipped(op)?, &right, bound),
+            _ => return None,
+        };
+        let length = match &self.parameter_type {
+            Type::String if self.is_length(subject, arguments) => true,
+            Type::Int if self.is_parameter(subject) => false,
+            _ => return None,
+        };
+        let at_least = |n| if length { Rule::MinLength(n) } else { Rule::Min(n) };
+        let at_most = |n| if length { Rule::MaxLength(n) } else { Rule::Max(n) };
+        Some(match op {
+            ComparisonOp::GreaterEqual => vec![at_least(bound)],
+            ComparisonOp::Greater => vec![at_least(bound.checked_add(1)?)],
+            ComparisonOp::LessEqual => vec![at_most(bound)],
+            ComparisonOp::Less => vec![at_most(bound.checked_sub(1)?)],
+            ComparisonOp::Equal => vec![at_least(bound), at_most(bound)],
+            ComparisonOp::NotEqual | ComparisonOp::Contains => return None,
+        })
+    }
+
+    // Whether `expr`, resolved, is the parameter
+    fn is_parameter(&self, expr: &Expression) -> bool {
+        matches!(expr, Expression::Identifier(name) if *name == self.parameter)
+    }
+
+    // `raw.length`, `raw.length()` or `length(raw)`
+    fn is_length(&self, expr: &Expression, arguments: &Arguments) -> bool {
+        let is_parameter = |object: &Expression| resolved(object, arguments).is_some_and(|o| self.is_parameter(&o));
+        match expr {
+            Expression::MemberAccess(object, member) => member == "length" && is_parameter(object),
+            Expression::MethodCall(object, method, args) => method == "length" && args.is_empty() && is_parameter(object),
+            Expression::FunctionCall(name, args) => name == "length" && matches!(args.as_slice(), [arg] if is_parameter(arg)),
+            _ => false,
+        }
+    }
+
+    /// The tightest of each kind of bound, as (least, greatest): the length
+    /// of a String, or an Int itself.
+    pub fn bounds(&self) -> (Option<i64>, Option<i64>) {
+        let mut least = None;
+        let mut greatest = None;
+        for rule in &self.rules {
+            match rule {
+                Rule::MinLength(n) | Rule::Min(n) => least = least.max(Some(*n)),
+                Rule::MaxLength(n) | Rule::Max(n) => greatest = Some(greatest.map_or(*n, |g: i64| g.min(*n))),
+                Rule::Contains(_) => {}
+            }
+        }
+        (least, greatest)
+    }
+
+    /// The strings the parameter must contain.
+    pub fn required_substrings(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().filter_map(|rule| match rule {
+            Rule::Contains(text) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// The attributes of an HTML `<input>` that checks the rules:
+    /// `required`, `type`, `minlength` and `maxlength` or `min` and `max`,
+    /// and a `pattern` for the substrings, in that order.
+    pub fn html_attributes(&self) -> Vec<(String, String)> {
+        let mut attributes = vec![("required".to_string(), String::new())];
+        let (least, greatest) = self.bounds();
+        let (kind, low, high) = match self.parameter_type {
+            Type::Int => ("number", "min", "max"),
+            _ => ("text", "minlength", "maxlength"),
+        };
+        attributes.push(("type".to_string(), kind.to_string()));
+        if let Some(least) = least {
+            attributes.push((low.to_string(), least.to_string()));
+        }
+        if let Some(greatest) = greatest {
+            attributes.push((high.to_string(), greatest.to_string()));
+        }
+        let substrings: Vec<String> = self.required_substrings().map(escape_pattern).collect();
+        // A pattern must match the whole input, so each substring is looked
+        // for ahead of matching anything
+        let pattern = match substrings.as_slice() {
+            [] => None,
+            [one] => Some(format!(".*{}.*", one)),
+            many => Some(format!("{}.*", many.iter().map(|s| format!("(?=.*{})", s)).collect::<String>())),
+        };
+        if let Some(pattern) = pattern {
+            attributes.push(("pattern".to_string(), pattern));
+        }
+        attributes
+    }
+
+    /// The rules as a single line of JSON: the type, its parameter and
+    /// their type, each rule as an object with a `kind`, the unchecked
+    /// parts and whether the type normalizes.
+    pub fn to_json(&self) -> String {
+        let rules: Vec<String> = self
+            .rules
+            .iter()
+            .map(|rule| match rule {
+                Rule::Contains(text) => format!("{{\"kind\":\"contains\",\"value\":{}}}", json_string(text)),
+                Rule::MinLength(n) => format!("{{\"kind\":\"minLength\",\"value\":{}}}", n),
+                Rule::MaxLength(n) => format!("{{\"kind\":\"maxLength\",\"value\":{}}}", n),
+                Rule::Min(n) => format!("{{\"kind\":\"min\",\"value\":{}}}", n),
+                Rule::Max(n) => format!("{{\"kind\":\"max\",\"value\":{}}}", n),
+            })
+            .collect();
+        let unchecked: Vec<String> = self.unchecked.iter().map(|part| json_string(part)).collect();
+        format!(
+            "{{\"type\":{},\"parameter\":{},\"parameterType\":{},\"rules\":[{}],\"unchecked\":[{}],\"normalized\":{}}}",
+            json_string(&self.type_name),
+            json_string(&self.parameter),
+            json_string(&format_type(&self.parameter_type)),
+            rules.join(","),
+            unchecked.join(","),
+            self.normalized
+        )
+    }
+}
+
+// `expr`, with a variable replaced by what it stands for, or None if it
+// isn't in scope
+fn resolved(expr: &Expression, arguments: &Arguments) -> Option<Expression> {
+    match expr {
+        Expression::Identifier(name) => arguments.get(name).cloned(),
+        _ => Some(expr.clone()),
+    }
+}
+
+// An Int literal, negated or not
+fn integer(expr: &Expression) -> Option<i64> {
+    match expr {
+        Expression::Literal(Literal::Integer(n)) => Some(*n),
+        Expression::Unary(UnaryOp::Minus, operand) => integer(operand)?.checked_neg(),
+        _ => None,
+    }
+}
+
+// The comparison with its sides swapped
+fn flipped(op: &ComparisonOp) -> Option<ComparisonOp> {
+    Some(match op {
+        ComparisonOp::Less => ComparisonOp::Greater,
+        ComparisonOp::Greater => ComparisonOp::Less,
+        ComparisonOp::LessEqual => ComparisonOp::GreaterEqual,
+        ComparisonOp::GreaterEqual => ComparisonOp::LessEqual,
+        ComparisonOp::Equal => ComparisonOp::Equal,
+        ComparisonOp::NotEqual | ComparisonOp::Contains => return None,
+    })
+}
+
+// `text` matched literally by a JavaScript regular expression, as HTML
+// patterns and JSON Schema patterns are
+pub(crate) fn escape_pattern(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "^$\\.*+?()[]{}|/".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    #[test]
+    fn test_rules_of_validation() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value Username(raw: String) { validate: raw.length >= 3 && 20 >= raw.length && raw contains \"_\" }
+                 value Percent(n: Int) { validate: n > -1 && n <= 100 && n != 50 }
+                 value Code(raw: String) {
+                     validate: raw contains \"a.b\" && raw contains \"-\" && raw.length == 4
+                     normalize: raw.trim()
+                 }",
+            )
+            .unwrap();
+
+        let username = engine.validation_rules("Username").unwrap();
+        assert_eq!(
+            username.rules,
+            [Rule::MinLength(3), Rule::MaxLength(20), Rule::Contains("_".to_string())]
+        );
+        assert!(username.unchecked.is_empty() && !username.normalized);
+        let attributes = |rules: &ValidationRules| {
+            rules.html_attributes().into_iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>()
+        };
+        assert_eq!(attributes(&username), ["required=", "type=text", "minlength=3", "maxlength=20", "pattern=.*_.*"]);
+
+        // What can't be checked by a form is left to Relic
+        let percent = engine.validation_rules("Percent").unwrap();
+        assert_eq!(percent.rules, [Rule::Min(0), Rule::Max(100)]);
+        assert_eq!(percent.unchecked, ["n != 50"]);
+        assert_eq!(attributes(&percent), ["required=", "type=number", "min=0", "max=100"]);
+        assert_eq!(
+            percent.to_json(),
+            "{\"type\":\"Percent\",\"parameter\":\"n\",\"parameterType\":\"Int\",\"rules\":[{\"kind\":\"min\",\"value\":0},\
+             {\"kind\":\"max\",\"value\":100}],\"unchecked\":[\"n != 50\"],\"normalized\":false}"
+        );
+
+        let code = engine.validation_rules("Code").unwrap();
+        assert_eq!(code.bounds(), (Some(4), Some(4)));
+        assert!(code.normalized);
+        assert_eq!(attributes(&code).last().unwrap(), "pattern=(?=.*a\\.b)(?=.*-).*");
+
+        // Calls are followed into the prelude's functions and the program's
+        engine
+            .load(
+                "fn isEven(n: Int) -> Bool { n % 2 == 0 }
+                 fn small(n: Int) -> Bool { between(n, 1, 10) }
+                 value Handle(name: String) { validate: nonEmpty(name) && maxLength(name, 8) }
+                 value Score(n: Int) { validate: small(n) && isEven(n) }",
+            )
+            .unwrap();
+        assert_eq!(engine.validation_rules("Handle").unwrap().rules, [Rule::MinLength(1), Rule::MaxLength(8)]);
+        let score = engine.validation_rules("Score").unwrap();
+        assert_eq!(score.rules, [Rule::Min(1), Rule::Max(10)]);
+        assert_eq!(score.unchecked, ["isEven(n)"]);
+
+        assert!(engine.validation_rules("Missing").is_err());
+    }
+}
@@ -17,6 +17,12 @@ pub struct SpecializationCache {
     cache: HashMap<(String, Vec<Type>), usize>,
 }
 
+impl Default for SpecializationCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SpecializationCache {
     pub fn new() -> Self {
         Self {
@@ -89,7 +95,7 @@ impl SpecializationCache {
         }
 
         // Sort by specificity (highest first)
-        candidates.sort_by(|a, b| b.1.cmp(&a.1));
+        candidates.sort_by_key(|c| std::cmp::Reverse(c.1));
 
         // Check for ambiguity
         if candidates.len() >= 2 && candidates[0].1 == candidates[1].1 {
@@ -118,6 +124,31 @@ impl SpecializationCache {
         let key = (function_name.to_string(), arg_types.to_vec());
         self.cache.get(&key).copied()
     }
+
+    /// The cached specializations of calls to any of `function_names`.
+    pub fn specializations_of(&self, function_names: &[&str]) -> Vec<SpecializedCall> {
+        self.cache
+            .iter()
+            .filter(|((name, _), _)| function_names.contains(&name.as_str()))
+            .map(|((name, arg_types), &target_function)| SpecializedCall {
+                function_name: name.clone(),
+                arg_types: arg_types.clone(),
+                target_function,
+            })
+            .collect()
+    }
+
+    /// Cache a specialization found before, such as one saved with a
+    /// compiled program.
+    pub fn insert(&mut self, call: SpecializedCall) {
+        self.cache.insert((call.function_name, call.arg_types), call.target_function);
+    }
+
+    /// Drop the cached specializations of `function_name`, whose
+    /// implementations have changed.
+    pub fn forget(&mut self, function_name: &str) {
+        self.cache.retain(|(name, _), _| name != function_name);
+    }
 }
 
 /// Check if two types are compatible (source type can be passed to parameter type)
@@ -141,7 +172,19 @@ fn types_compatible(param_type: &Type, arg_type: &Type) -> bool {
 /// Calculate type specificity score (higher is more specific)
 fn type_specificity(ty: &Type) -> u32 {
     match ty {
-        Type::Int | Type::String | Type::Bool | Type::Value(_) | Type::Type | Type::List(_) => 3,
+        Type::Int
+        | Type::String
+        | Type::Bool
+        | Type::Value(_)
+        | Type::Type
+        | Type::List(_)
+        | Type::Option(_)
+        | Type::Query(_)
+        | Type::Relation
+        | Type::Date
+        | Type::Time
+        | Type::Duration
+        | Type::Decimal => 3,
         Type::Any => 1,
         Type::Unknown => 0,
     }
@@ -168,13 +211,19 @@ pub fn specialize_function_calls(
 
             // All types must be known for specialization
             if arg_types.iter().all(|t| !matches!(t, Type::Unknown)) {
-                if let Some(spec) = specialization_cache.try_specialize(name, &arg_types, registry) {
+                if let Some(_spec) = specialization_cache.try_specialize(name, &arg_types, registry) {
                     // In a real implementation, we would transform this into a specialized call
                     // For now, we just cache the specialization for the evaluator to use
                     // The evaluator can check the cache before doing dynamic dispatch
                 }
             }
         }
+        Expression::NamedCall(_, args) => {
+            // Labels decide the overload, so only the arguments are specialized
+            for arg in args.iter_mut() {
+                specialize_function_calls(&mut arg.value, type_env, specialization_cache, registry);
+            }
+        }
         Expression::MethodCall(receiver, method_name, args) => {
             // Specialize receiver and arguments
             specialize_function_calls(receiver, type_env, specialization_cache, registry);
@@ -209,18 +258,64 @@ pub fn specialize_function_calls(
             new_env.insert(name.clone(), ty);
             specialize_function_calls(body, &new_env, specialization_cache, registry);
         }
+        Expression::Block(statements, result) => {
+            let mut block_env = type_env.clone();
+            for statement in statements.iter_mut() {
+                match statement {
+                    Statement::Let(name, value) => {
+                        specialize_function_calls(value, &block_env, specialization_cache, registry);
+                        let ty = infer_expression_type(value, &block_env);
+                        block_env.insert(name.clone(), ty);
+                    }
+                    Statement::Expression(expr) => {
+                        specialize_function_calls(expr, &block_env, specialization_cache, registry);
+                    }
+                }
+            }
+            specialize_function_calls(result, &block_env, specialization_cache, registry);
+        }
         Expression::Pipeline(expr, right) => {
             specialize_function_calls(expr, type_env, specialization_cache, registry);
             specialize_function_calls(right, type_env, specialization_cache, registry);
         }
-        Expression::Match(expr, branches) => {
+        Expression::Match(expr, _branches) => {
             specialize_function_calls(expr, type_env, specialization_cache, registry);
             // Note: branches are not mutable here, would need different approach for real implementation
         }
         // No ValueConstruction variant in current AST
-        Expression::MemberAccess(expr, _) => {
+        Expression::MemberAccess(expr, _) | Expression::Unwrap(expr) => {
             specialize_function_calls(expr, type_env, specialization_cache, registry);
         }
+        Expression::OptionalAccess(option, _, args) => {
+            specialize_function_calls(option, type_env, specialization_cache, registry);
+            for arg in args.iter_mut().flatten() {
+                specialize_function_calls(arg, type_env, specialization_cache, registry);
+            }
+        }
+        Expression::Coalesce(option, default) => {
+            specialize_function_calls(option, type_env, specialization_cache, registry);
+            specialize_function_calls(default, type_env, specialization_cache, registry);
+        }
+        Expression::Relation(_, rows) => {
+            for cell in rows.iter_mut().flatten() {
+                specialize_function_calls(cell, type_env, specialization_cache, registry);
+            }
+        }
+        Expression::Lambda(parameter, body) => {
+            // The parameter's type comes from the builtin the lambda is passed to
+            let mut lambda_env = type_env.clone();
+            lambda_env.insert(parameter.clone(), Type::Unknown);
+            specialize_function_calls(body, &lambda_env, specialization_cache, registry);
+        }
+        Expression::Comprehension(element, name, source, condition) => {
+            specialize_function_calls(source, type_env, specialization_cache, registry);
+            let mut row_env = type_env.clone();
+            row_env.insert(name.clone(), Type::Unknown);
+            specialize_function_calls(element, &row_env, specialization_cache, registry);
+            if let Some(condition) = condition {
+                specialize_function_calls(condition, &row_env, specialization_cache, registry);
+            }
+        }
         Expression::Literal(_) | Expression::Identifier(_) | Expression::TypeLiteral(_) => {
             // No function calls to specialize
         }
@@ -278,6 +373,7 @@ mod tests {
             ],
             return_type: Type::Int,
             body: Expression::Literal(Literal::Integer(0)), // Dummy body
+            span: crate::lexer::Span::default(),
         };
         registry.register_function(func);
 
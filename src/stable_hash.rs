@@ -0,0 +1,296 @@
+//! Hashes that stay the same from one process to the next, on every
+//! platform and with every version of Rust, so that they can be stored: in
+//! a persisted index, or to find duplicates across restarts.
+//! `std::collections::hash_map::DefaultHasher` promises none of this.
+//!
+//! `StableHasher` is SipHash-2-4 with fixed keys. It writes integers
+//! little-endian and `usize`s as 64 bits, and `write_text` gives strings a
+//! length prefix, so what is hashed doesn't depend on the platform or on
+//! how the standard library hashes a `str`. `ValueObject::hash_value` is
+//! made with it.
+
+use crate::evaluator::EvalValue;
+use crate::value::ValueObject;
+use std::hash::{Hash, Hasher};
+
+/// The version of the hashes `StableHasher` makes. It changes whenever the
+/// hash of any value would, so that a stored hash with another version is
+/// known to be stale.
+pub const HASH_VERSION: u32 = 1;
+
+// "relic-stable-v1!", as two little-endian words
+const KEY0: u64 = 0x7473_2d63_696c_6572;
+const KEY1: u64 = 0x2131_762d_656c_6261;
+
+/// SipHash-2-4 with fixed keys.
+#[derive(Debug, Clone)]
+pub struct StableHasher {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+    // Bytes written that don't yet fill a word
+    tail: u64,
+    tail_len: usize,
+    length: u64,
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StableHasher {
+    pub fn new() -> Self {
+        Self::with_keys(KEY0, KEY1)
+    }
+
+    fn with_keys(k0: u64, k1: u64) -> Self {
+        StableHasher {
+            v0: k0 ^ 0x736f_6d65_7073_6575,
+            v1: k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: k0 ^ 0x6c79_6765_6e65_7261,
+            v3: k1 ^ 0x7465_6462_7974_6573,
+            tail: 0,
+            tail_len: 0,
+            length: 0,
+        }
+    }
+
+    /// Write `text` with its length before it, so that ("ab", "c") and
+    /// ("a", "bc") hash differently.
+    pub fn write_text(&mut self, text: &str) {
+        self.write_u64(text.len() as u64);
+        self.write(text.as_bytes());
+    }
+
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, word: u64) {
+        self.v3 ^= word;
+        self.round();
+        self.round();
+        self.v0 ^= word;
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.length = self.length.wrapping_add(bytes.len() as u64);
+        for &byte in bytes {
+            self.tail |= u64::from(byte) << (8 * self.tail_len);
+            self.tail_len += 1;
+            if self.tail_len == 8 {
+                let word = self.tail;
+                self.compress(word);
+                self.tail = 0;
+                self.tail_len = 0;
+            }
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = self.clone();
+        let word = state.tail | ((self.length & 0xff) << 56);
+        state.compress(word);
+        state.v2 ^= 0xff;
+        for _ in 0..4 {
+            state.round();
+        }
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+
+    // Integers are written little-endian whatever the platform, and sizes
+    // as 64 bits whatever its word size
+    fn write_u8(&mut self, n: u8) {
+        self.write(&[n]);
+    }
+
+    fn write_u16(&mut self, n: u16) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        self.write(&n.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+
+    fn write_i8(&mut self, n: i8) {
+        self.write_u8(n as u8);
+    }
+
+    fn write_i16(&mut self, n: i16) {
+        self.write_u16(n as u16);
+    }
+
+    fn write_i32(&mut self, n: i32) {
+        self.write_u32(n as u32);
+    }
+
+    fn write_i64(&mut self, n: i64) {
+        self.write_u64(n as u64);
+    }
+
+    fn write_i128(&mut self, n: i128) {
+        self.write_u128(n as u128);
+    }
+
+    fn write_isize(&mut self, n: isize) {
+        self.write_u64(n as i64 as u64);
+    }
+}
+
+/// The stable hash of what `write` writes.
+pub fn stable_hash(write: impl FnOnce(&mut StableHasher)) -> u64 {
+    let mut hasher = StableHasher::new();
+    write(&mut hasher);
+    hasher.finish()
+}
+
+/// Write `value` to `hasher`. Values equal by `==` are written alike: a
+/// value of a value type by its type and fields, whatever its display text.
+pub fn write_value(hasher: &mut StableHasher, value: &EvalValue) {
+    match value {
+        EvalValue::String(s) => {
+            hasher.write_u8(0);
+            hasher.write_text(s);
+        }
+        EvalValue::Integer(n) => {
+            hasher.write_u8(1);
+            hasher.write_i64(*n);
+        }
+        EvalValue::Boolean(b) => {
+            hasher.write_u8(2);
+            hasher.write_u8(u8::from(*b));
+        }
+        EvalValue::Value { type_name, fields, .. } => {
+            hasher.write_u8(3);
+            hasher.write_text(type_name);
+            let mut fields: Vec<_> = fields.iter().collect();
+            fields.sort_by(|a, b| a.0.cmp(b.0));
+            hasher.write_usize(fields.len());
+            for (name, field) in fields {
+                hasher.write_text(name);
+                write_value(hasher, field);
+            }
+        }
+        EvalValue::Type(name) => {
+            hasher.write_u8(4);
+            hasher.write_text(name);
+        }
+        EvalValue::List(items) => {
+            hasher.write_u8(5);
+            hasher.write_usize(items.len());
+            for item in items {
+                write_value(hasher, item);
+            }
+        }
+        EvalValue::Option(inner) => {
+            hasher.write_u8(6);
+            match inner {
+                Some(inner) => {
+                    hasher.write_u8(1);
+                    write_value(hasher, inner);
+                }
+                None => hasher.write_u8(0),
+            }
+        }
+        EvalValue::Relation(relation) => {
+            hasher.write_u8(7);
+            hasher.write_u64(relation.hash_value());
+        }
+        EvalValue::Temporal(t) => {
+            hasher.write_u8(8);
+            t.hash(hasher);
+        }
+        EvalValue::Decimal(d) => {
+            hasher.write_u8(9);
+            d.hash(hasher);
+        }
+        // By what it would run, not the rows it would give
+        EvalValue::Query(query) => {
+            hasher.write_u8(10);
+            hasher.write_text(&query.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+
+    #[test]
+    fn test_siphash_reference_vectors() {
+        // From the SipHash paper: key 00..0f, messages 00..(n-1)
+        let key0 = u64::from_le_bytes([0, 1, 2, 3, 4, 5, 6, 7]);
+        let key1 = u64::from_le_bytes([8, 9, 10, 11, 12, 13, 14, 15]);
+        let hash = |n: u8| {
+            let mut hasher = StableHasher::with_keys(key0, key1);
+            hasher.write(&(0..n).collect::<Vec<u8>>());
+            hasher.finish()
+        };
+        assert_eq!(hash(0), 0x726f_db47_dd0e_0e31);
+        assert_eq!(hash(1), 0x74f8_39c5_93dc_67fd);
+        assert_eq!(hash(15), 0xa129_ca61_49be_45e5);
+
+        // Written in pieces, the bytes hash the same
+        let mut hasher = StableHasher::with_keys(key0, key1);
+        hasher.write(&[0, 1, 2]);
+        hasher.write(&[3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]);
+        assert_eq!(hasher.finish(), hash(15));
+    }
+
+    #[test]
+    fn test_hashes_are_fixed() {
+        // These must not change unless HASH_VERSION does
+        assert_eq!(stable_hash(|h| h.write_text("relic")), 0x7859_eaa4_ea7b_9fbe);
+        assert_eq!(stable_hash(|h| write_value(h, &EvalValue::Integer(42))), 0x9d29_622f_9adf_8905);
+    }
+
+    #[test]
+    fn test_value_hashes() {
+        let mut engine = Engine::new();
+        engine
+            .load("value Email(raw: String) { normalize: raw.toLowerCase() }\nvalue Tag(label: String) {}")
+            .unwrap();
+        let hash = |engine: &mut Engine, source: &str| stable_hash(|h| write_value(h, &engine.eval(source).unwrap()));
+        assert_eq!(hash(&mut engine, "Email(\"A@B\")"), hash(&mut engine, "Email(\"a@b\")"));
+        assert_ne!(hash(&mut engine, "Email(\"a@b\")"), hash(&mut engine, "Tag(\"a@b\")"));
+        assert_ne!(hash(&mut engine, "Email(\"a@b\")"), hash(&mut engine, "Email(\"a@c\")"));
+
+        // Instances in the pool hash by their parameter, not their type alone
+        let instances = engine.registry().get_all_instances("Email");
+        assert_eq!(instances[0].hash_value(), instances[1].hash_value());
+        assert!(instances[0].equals(instances[1].as_ref()));
+        let other = engine.registry().get_all_instances("Tag");
+        engine.eval("Email(\"x@y\")").unwrap();
+        let last = engine.registry().get_all_instances("Email").pop().unwrap();
+        assert_ne!(instances[0].hash_value(), last.hash_value());
+        assert!(!instances[0].equals(last.as_ref()));
+        assert!(!instances[0].equals(other[0].as_ref()));
+    }
+}
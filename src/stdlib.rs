@@ -1,28 +1,160 @@
-use crate::ast::*;
+use crate::ast::{Declaration, FunctionDeclaration};
+use crate::error::{Error, ValidationError};
+use crate::evaluator::{self, visible_instances, EvalValue};
+use crate::inspect;
+use crate::lexer::Lexer;
+use crate::native::NativeFunction;
+use crate::parser::Parser;
 use crate::types::Type;
 use crate::value::ValueRegistry;
+use std::collections::HashMap;
 
-/// Register all standard library functions
+/// What a script can see of the program running it, through `args()` and
+/// `env("NAME")`. Empty unless the host fills it in, so embedded Relic code
+/// only sees what it's given; the `relic` command passes its arguments and
+/// the process environment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptEnvironment {
+    pub arguments: Vec<String>,
+    pub variables: HashMap<String, String>,
+}
+
+impl ScriptEnvironment {
+    /// `arguments`, with the variables of the current process.
+    pub fn from_process(arguments: Vec<String>) -> Self {
+        Self {
+            arguments,
+            variables: std::env::vars().collect(),
+        }
+    }
+}
+
+/// Register all standard library functions: the natives, and the prelude
 pub fn register_stdlib(registry: &mut ValueRegistry) {
-    // Register the single built-in function: all(t: Type) -> List[t]
-    // This is the ONLY built-in needed for the Type-as-Relation model
-    register_all_function(registry);
+    for native in native_functions() {
+        registry.register_native(native);
+    }
+    for function in prelude() {
+        registry.register_prelude_function(function);
+    }
 }
 
-/// Register the all(t: Type) -> List[t] built-in function
-/// This is the foundational function for the Type-as-Relation model
-fn register_all_function(registry: &mut ValueRegistry) {
-    let all_function = FunctionDeclaration {
-        name: "all".to_string(),
-        parameters: vec![ParameterWithGuard {
-            name: "t".to_string(),
-            ty: Type::Type,
-            guard: None,
-        }],
-        return_type: Type::List(Box::new(Type::Any)), // List of elements of the type
-        // The body is not used for built-ins - they are handled specially in the evaluator
-        body: Expression::Literal(Literal::String("built-in".to_string())),
+lazy_static::lazy_static! {
+    static ref PRELUDE: Vec<FunctionDeclaration> = {
+        let program = Parser::new(Lexer::new(include_str!("prelude.relic").to_string()))
+            .and_then(|mut parser| parser.parse_program())
+            .expect("the prelude parses");
+        program
+            .declarations
+            .into_iter()
+            .filter_map(|declaration| match declaration {
+                Declaration::Function(function) => Some(function),
+                _ => None,
+            })
+            .collect()
     };
-    
-    registry.register_function(all_function);
-}
\ No newline at end of file
+}
+
+/// The functions of the prelude, `prelude.relic`: helpers such as
+/// `isPositive`, `between` and `lowercase` that are written in Relic rather
+/// than built in. A program's own function with the same name and parameter
+/// types replaces the prelude's.
+pub fn prelude() -> Vec<FunctionDeclaration> {
+    PRELUDE.clone()
+}
+
+/// The standard library's functions, which are written in Rust:
+/// - all(t: Type) -> List[t], the foundation of the Type-as-Relation model
+/// - args() -> List[String] and env(name: String) -> Option[String], which
+///   read the registry's `ScriptEnvironment`
+/// - assert(condition: Bool) -> Bool and assertEq(actual: Any, expected: Any)
+///   -> Bool, which fail unless their check holds, for `test` declarations
+/// - isValid(t: Type, value: Any) -> Bool, whether `value` passes the
+///   validation of the value type `t`, without constructing anything
+/// - inspect(x: Any) -> String, a report of `x`'s type, fields, declaration
+///   and place in its type's instance pool, for debugging
+pub fn native_functions() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("all", &[("t", Type::Type)], Type::List(Box::new(Type::Any)), |args, registry| {
+            match args {
+                [EvalValue::Type(type_name)] => Ok(EvalValue::List(visible_instances(type_name, registry))),
+                _ => Err(Error::Validation(ValidationError {
+                    message: "all() expects a Type argument".to_string(),
+                    value_type: "function".to_string(),
+                })),
+            }
+        }),
+        NativeFunction::new("args", &[], Type::List(Box::new(Type::String)), |_, registry| {
+            let arguments = &registry.script_environment().arguments;
+            Ok(EvalValue::List(arguments.iter().cloned().map(EvalValue::String).collect()))
+        }),
+        NativeFunction::new(
+            "env",
+            &[("name", Type::String)],
+            Type::Option(Box::new(Type::String)),
+            |args, registry| {
+                let value = match args {
+                    [EvalValue::String(name)] => registry.script_environment().variables.get(name),
+                    _ => None,
+                };
+                Ok(EvalValue::Option(value.map(|v| Box::new(EvalValue::String(v.clone())))))
+            },
+        ),
+        NativeFunction::new("assert", &[("condition", Type::Bool)], Type::Bool, |args, _| match args {
+            [EvalValue::Boolean(true)] => Ok(EvalValue::Boolean(true)),
+            _ => Err(assertion_error("Assertion failed".to_string())),
+        }),
+        NativeFunction::new(
+            "assertEq",
+            &[("actual", Type::Any), ("expected", Type::Any)],
+            Type::Bool,
+            |args, _| match args {
+                [actual, expected] if actual == expected => Ok(EvalValue::Boolean(true)),
+                [actual, expected] => Err(assertion_error(format!("Expected {}, found {}", expected, actual))),
+                _ => Err(assertion_error("assertEq() expects two arguments".to_string())),
+            },
+        ),
+        NativeFunction::new(
+            "isValid",
+            &[("t", Type::Type), ("value", Type::Any)],
+            Type::Bool,
+            |args, registry| match args {
+                [EvalValue::Type(type_name), value] if registry.constructors.contains_key(type_name) => {
+                    Ok(EvalValue::Boolean(evaluator::validate(type_name, value, registry).is_ok()))
+                }
+                _ => Err(Error::Validation(ValidationError {
+                    message: "isValid() expects a value type and a value".to_string(),
+                    value_type: "function".to_string(),
+                })),
+            },
+        ),
+        NativeFunction::new(
+            "provenance",
+            &[("x", Type::Any)],
+            Type::List(Box::new(Type::String)),
+            |args, registry| match args {
+                [value @ EvalValue::Value { .. }] => Ok(EvalValue::List(
+                    registry.provenance(value).iter().map(|site| EvalValue::String(site.to_string())).collect(),
+                )),
+                _ => Err(Error::Validation(ValidationError {
+                    message: "provenance() expects a value of a value type".to_string(),
+                    value_type: "function".to_string(),
+                })),
+            },
+        ),
+        NativeFunction::new("inspect", &[("x", Type::Any)], Type::String, |args, registry| match args {
+            [value] => Ok(EvalValue::String(inspect::inspect(value, registry).to_string())),
+            _ => Err(Error::Validation(ValidationError {
+                message: "inspect() expects one argument".to_string(),
+                value_type: "function".to_string(),
+            })),
+        }),
+    ]
+}
+
+fn assertion_error(message: String) -> Error {
+    Error::Validation(ValidationError {
+        message,
+        value_type: "assertion".to_string(),
+    })
+}
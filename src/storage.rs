@@ -0,0 +1,256 @@
+//! Where the instances of value types are kept, and how much of a query the
+//! keeper runs itself. A query of a value type, `User |> where(u => u.age >
+//! 18) |> select(u => u.name)`, asks its `InstanceStore` for the store's
+//! `Capabilities`, and hands the store the steps at its start that they
+//! cover as a `Pushdown`: `where` steps comparing the type's field to a
+//! constant, and a `select` of the field after them. The query runs the
+//! rest of its steps on the rows the store gives.
+//!
+//! A persistent store would translate a pushdown into its own queries, so
+//! that rows it filters out are never loaded. The in-memory `ValueRegistry`
+//! is the only store so far; it runs a pushdown on each instance directly,
+//! without evaluating the steps' functions.
+
+use crate::ast::{ComparisonOp, Expression};
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::{compare, evaluate_expression, instance_value, EvalValue};
+use crate::query::{Function, Step};
+use crate::value::ValueRegistry;
+
+/// What an `InstanceStore` can run of a query itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Keeping only the instances that pass a `Filter`
+    pub filter: bool,
+    /// Giving the field of each instance instead of the instance
+    pub project: bool,
+}
+
+/// `field op value`: a `where` step a store can run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub field: String,
+    pub op: ComparisonOp,
+    pub value: EvalValue,
+}
+
+/// The steps at the start of a query that its store runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pushdown {
+    /// The filters an instance must all pass
+    pub filters: Vec<Filter>,
+    /// The field each row is, if not the whole instance
+    pub projection: Option<String>,
+}
+
+/// Keeps the instances of value types, for queries to read.
+pub trait InstanceStore {
+    /// What the store can run of a query itself.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Pass each instance of `type_name` that evaluation can see and that
+    /// passes `pushdown`'s filters, projected as it says, to `visit`, until
+    /// it returns false. `pushdown` only asks for what the store's
+    /// capabilities include.
+    fn scan(&self, type_name: &str, pushdown: &Pushdown, visit: &mut dyn FnMut(EvalValue) -> Result<bool>)
+        -> Result<()>;
+}
+
+impl Pushdown {
+    /// What a store with `capabilities` can run of `steps`, from the first,
+    /// on the instances of `type_name`, and how many steps that is.
+    pub fn plan(
+        type_name: &str,
+        steps: &[Step],
+        capabilities: Capabilities,
+        registry: &ValueRegistry,
+    ) -> (Pushdown, usize) {
+        let mut pushdown = Pushdown::default();
+        let Some(constructor) = registry.constructors.get(type_name) else {
+            return (pushdown, 0);
+        };
+        let field = &constructor.declaration.parameter.name;
+        let mut pushed = 0;
+        for step in steps {
+            match step {
+                Step::Where(function) if capabilities.filter => match filter(function, field, registry) {
+                    Some(filter) => pushdown.filters.push(filter),
+                    None => break,
+                },
+                // Nothing is pushed after a projection
+                Step::Select(function) if capabilities.project && is_field(&function.body, function, field) => {
+                    pushdown.projection = Some(field.clone());
+                    pushed += 1;
+                    break;
+                }
+                _ => break,
+            }
+            pushed += 1;
+        }
+        (pushdown, pushed)
+    }
+
+    /// Whether `instance`, of the value type `type_name`, passes every
+    /// filter.
+    pub fn admits(&self, type_name: &str, instance: &EvalValue) -> Result<bool> {
+        for filter in &self.filters {
+            let value = field_of(type_name, instance, &filter.field)?;
+            if !compare(&filter.op, value, filter.value.clone())? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl InstanceStore for ValueRegistry {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities { filter: true, project: true }
+    }
+
+    fn scan(
+        &self,
+        type_name: &str,
+        pushdown: &Pushdown,
+        visit: &mut dyn FnMut(EvalValue) -> Result<bool>,
+    ) -> Result<()> {
+        for instance in self.snapshot(type_name).iter() {
+            let row = instance_value(type_name, instance.as_ref(), self);
+            if !self.is_visible(type_name, &row) || !pushdown.admits(type_name, &row)? {
+                continue;
+            }
+            let row = match &pushdown.projection {
+                Some(field) => field_of(type_name, &row, field)?,
+                None => row,
+            };
+            if !visit(row)? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// `x.field op c` or `c op x.field`, where `x` is the function's parameter
+// and `c` a literal or a variable the function captured, as a filter
+fn filter(function: &Function, field: &str, registry: &ValueRegistry) -> Option<Filter> {
+    let Expression::Comparison(op, left, right) = &function.body else {
+        return None;
+    };
+    let (op, constant) = if is_field(left, function, field) {
+        (op.clone(), right)
+    } else if is_field(right, function, field) {
+        (flipped(op)?, left)
+    } else {
+        return None;
+    };
+    let value = match &**constant {
+        Expression::Literal(_) => evaluate_expression(constant, &function.context, registry).ok()?,
+        Expression::Identifier(name) if *name != function.parameter => function.context.get(name)?.clone(),
+        _ => return None,
+    };
+    Some(Filter { field: field.to_string(), op, value })
+}
+
+// Whether `expression` is `x.field`, with `x` the function's parameter
+fn is_field(expression: &Expression, function: &Function, field: &str) -> bool {
+    matches!(
+        expression,
+        Expression::MemberAccess(row, member)
+            if member == field && matches!(&**row, Expression::Identifier(name) if *name == function.parameter)
+    )
+}
+
+// The comparison with its operands swapped: `a < b` is `b > a`
+fn flipped(op: &ComparisonOp) -> Option<ComparisonOp> {
+    match op {
+        ComparisonOp::Equal => Some(ComparisonOp::Equal),
+        ComparisonOp::NotEqual => Some(ComparisonOp::NotEqual),
+        ComparisonOp::Less => Some(ComparisonOp::Greater),
+        ComparisonOp::Greater => Some(ComparisonOp::Less),
+        ComparisonOp::LessEqual => Some(ComparisonOp::GreaterEqual),
+        ComparisonOp::GreaterEqual => Some(ComparisonOp::LessEqual),
+        ComparisonOp::Contains => None,
+    }
+}
+
+// The field of an instance, failing as `x.field` would if it's missing
+fn field_of(type_name: &str, instance: &EvalValue, field: &str) -> Result<EvalValue> {
+    match instance {
+        EvalValue::Value { fields, .. } => fields.get(field).cloned(),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        Error::Validation(ValidationError {
+            message: format!("Value type '{}' has no member '{}'", type_name, field),
+            value_type: type_name.to_string(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::sync::Arc;
+
+    fn function(body: &str, context: &[(&str, EvalValue)]) -> Function {
+        let mut parser = Parser::new(Lexer::new(body.to_string())).unwrap();
+        Function {
+            parameter: "a".to_string(),
+            body: parser.parse_expression().unwrap(),
+            context: Arc::new(context.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()),
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut engine = Engine::new();
+        engine.load("value Age(n: Int) {}").unwrap();
+        let registry = engine.registry();
+        let steps = [
+            Step::Where(function("a.n > 18", &[])),
+            Step::Where(function("minimum <= a.n", &[("minimum", EvalValue::Integer(21))])),
+            Step::Select(function("a.n", &[])),
+            Step::Where(function("a > 30", &[])),
+        ];
+
+        let (pushdown, pushed) = Pushdown::plan("Age", &steps, registry.capabilities(), registry);
+        assert_eq!(pushed, 3);
+        assert_eq!(
+            pushdown.filters,
+            vec![
+                Filter { field: "n".to_string(), op: ComparisonOp::Greater, value: EvalValue::Integer(18) },
+                Filter { field: "n".to_string(), op: ComparisonOp::GreaterEqual, value: EvalValue::Integer(21) },
+            ]
+        );
+        assert_eq!(pushdown.projection.as_deref(), Some("n"));
+
+        // Only what the store says it can run is pushed
+        let filters = Capabilities { filter: true, project: false };
+        assert_eq!(Pushdown::plan("Age", &steps, filters, registry).1, 2);
+        assert_eq!(Pushdown::plan("Age", &steps, Capabilities::default(), registry), (Pushdown::default(), 0));
+
+        // Nor is anything after a step that can't be
+        let steps = [Step::Where(function("a.n * 2 > 18", &[])), Step::Where(function("a.n > 18", &[]))];
+        assert_eq!(Pushdown::plan("Age", &steps, registry.capabilities(), registry).1, 0);
+        let unknown = [Step::Where(function("a.n > other", &[]))];
+        assert_eq!(Pushdown::plan("Age", &unknown, registry.capabilities(), registry).1, 0);
+    }
+
+    #[test]
+    fn test_pushed_queries() {
+        let mut engine = Engine::new();
+        engine.load("value Age(n: Int) {}\nAge(5)\nAge(20)\nAge(40)").unwrap();
+        let ages = |engine: &Engine, source: &str| engine.eval(source).unwrap().to_string();
+
+        assert_eq!(ages(&engine, "Age |> where(a => a.n > 10) |> select(a => a.n)"), "[20, 40]");
+        assert_eq!(ages(&engine, "Age |> where(a => 30 > a.n) |> where(a => a.n * 2 > 20)"), "[Age(20)]");
+        assert!(engine.eval("Age |> where(a => a.n > \"ten\")").is_err());
+
+        engine.set_visibility(|_, age| age.to_string() != "Age(40)");
+        assert_eq!(ages(&engine, "Age |> where(a => a.n > 10) |> select(a => a.n)"), "[20]");
+    }
+}
@@ -0,0 +1,266 @@
+//! Telling the host when instances come and go. `ValueRegistry::subscribe`
+//! calls a function with each `Change` to a value type's instances as it
+//! happens, which is enough to keep a cache or a materialized view in step
+//! with the instance pool. `ValueRegistry::changes` queues the changes on a
+//! `ChangeStream` instead, for a host to take them when it is ready, or to
+//! await them from async code.
+
+use crate::evaluator::EvalValue;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::task::{Context, Poll, Waker};
+
+/// Something that happened to an instance of a value type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The instance was constructed and added to its type's instances.
+    Constructed(EvalValue),
+    /// The instance was dropped: its type was removed, or declared again
+    /// with a validation the instance no longer passes.
+    Retracted(EvalValue),
+}
+
+impl Change {
+    /// The instance that was constructed or retracted.
+    pub fn value(&self) -> &EvalValue {
+        match self {
+            Change::Constructed(value) | Change::Retracted(value) => value,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Constructed(value) => write!(f, "+ {}", value),
+            Change::Retracted(value) => write!(f, "- {}", value),
+        }
+    }
+}
+
+/// Called with each change to the instances of the type it subscribed to.
+pub type Subscriber = Arc<dyn Fn(&Change) + Send + Sync>;
+
+/// Names a subscription, to end it with `ValueRegistry::unsubscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// The subscribers of each value type, by type name.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    next: AtomicU64,
+    subscribers: RwLock<HashMap<String, Vec<(SubscriptionId, Subscriber)>>>,
+}
+
+impl Subscriptions {
+    pub(crate) fn add(&self, type_name: &str, subscriber: Subscriber) -> SubscriptionId {
+        let id = SubscriptionId(self.next.fetch_add(1, Ordering::Relaxed));
+        if let Ok(mut subscribers) = self.subscribers.write() {
+            subscribers.entry(type_name.to_string()).or_default().push((id, subscriber));
+        }
+        id
+    }
+
+    pub(crate) fn remove(&self, id: SubscriptionId) -> bool {
+        let Ok(mut subscribers) = self.subscribers.write() else {
+            return false;
+        };
+        for list in subscribers.values_mut() {
+            if let Some(index) = list.iter().position(|(other, _)| *other == id) {
+                list.remove(index);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether anything would hear of a change to `type_name`, so that the
+    /// change need not be built if not.
+    pub(crate) fn is_watched(&self, type_name: &str) -> bool {
+        self.subscribers
+            .read()
+            .is_ok_and(|subscribers| subscribers.get(type_name).is_some_and(|list| !list.is_empty()))
+    }
+
+    /// Tell the subscribers of `type_name` about `change`. They are called
+    /// after the lock is released, so one may subscribe or unsubscribe.
+    pub(crate) fn notify(&self, type_name: &str, change: &Change) {
+        let subscribers: Vec<Subscriber> = match self.subscribers.read() {
+            Ok(subscribers) => subscribers
+                .get(type_name)
+                .map(|list| list.iter().map(|(_, subscriber)| Arc::clone(subscriber)).collect())
+                .unwrap_or_default(),
+            Err(_) => return,
+        };
+        for subscriber in subscribers {
+            subscriber(change);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    changes: VecDeque<Change>,
+    waker: Option<Waker>,
+}
+
+/// The changes to a value type's instances, queued in the order they
+/// happened until they are taken. Take them with `try_recv`, or await
+/// `recv()` from async code; a change wakes the task waiting for it.
+/// Once the stream is dropped, its subscription does nothing, but stays
+/// registered until `unsubscribe` is called with its `id`.
+pub struct ChangeStream {
+    id: SubscriptionId,
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl ChangeStream {
+    /// A stream, and the subscriber that feeds it, to be registered under `id`.
+    pub(crate) fn new(id: impl FnOnce(Subscriber) -> SubscriptionId) -> Self {
+        let queue = Arc::new(Mutex::new(Queue::default()));
+        let feed: Weak<Mutex<Queue>> = Arc::downgrade(&queue);
+        let id = id(Arc::new(move |change: &Change| {
+            if let Some(Ok(mut queue)) = feed.upgrade().as_deref().map(Mutex::lock) {
+                queue.changes.push_back(change.clone());
+                if let Some(waker) = queue.waker.take() {
+                    waker.wake();
+                }
+            }
+        }));
+        ChangeStream { id, queue }
+    }
+
+    pub fn id(&self) -> SubscriptionId {
+        self.id
+    }
+
+    /// The oldest change not yet taken, or None if there isn't one.
+    pub fn try_recv(&mut self) -> Option<Change> {
+        self.queue.lock().ok()?.changes.pop_front()
+    }
+
+    /// Wait for the next change.
+    pub fn recv(&mut self) -> Recv<'_> {
+        Recv { stream: self }
+    }
+}
+
+/// The future returned by `ChangeStream::recv`.
+pub struct Recv<'a> {
+    stream: &'a mut ChangeStream,
+}
+
+impl Future for Recv<'_> {
+    type Output = Change;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Change> {
+        let Ok(mut queue) = self.stream.queue.lock() else {
+            return Poll::Pending;
+        };
+        match queue.changes.pop_front() {
+            Some(change) => Poll::Ready(change),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+    use std::task::Wake;
+    use std::thread::{self, Thread};
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    #[derive(Default)]
+    struct Woken(std::sync::atomic::AtomicBool);
+
+    impl Wake for Woken {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Just enough of an executor to await one future on this thread
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut engine = Engine::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let id = engine.subscribe("User", move |change| log.lock().unwrap().push(change.to_string()));
+
+        // Instances of data blocks are constructed as the program loads
+        engine
+            .load("value User(name: String) { validate: name != \"\" }\ndata User { User(\"ann\") }")
+            .unwrap();
+        engine.eval("User(\"bob\")").unwrap();
+        assert!(engine.eval("User(\"\")").is_err());
+        engine.load("value Tag(label: String) {}").unwrap();
+        engine.eval("Tag(\"x\")").unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["+ User(ann)", "+ User(bob)"]);
+
+        engine.forget("User");
+        assert_eq!(seen.lock().unwrap().len(), 4);
+        assert!(seen.lock().unwrap().contains(&"- User(bob)".to_string()));
+
+        assert!(engine.unsubscribe(id));
+        assert!(!engine.unsubscribe(id));
+        engine.load("value User(name: String) {}").unwrap();
+        engine.eval("User(\"cy\")").unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_change_stream() {
+        let mut engine = Engine::new();
+        engine.load("value Point(x: Int) {}").unwrap();
+        let mut changes = engine.changes("Point");
+        assert_eq!(changes.try_recv(), None);
+
+        engine.eval("Point(1)").unwrap();
+        engine.eval("Point(2)").unwrap();
+        assert_eq!(block_on(changes.recv()).to_string(), "+ Point(1)");
+        assert_eq!(changes.try_recv().map(|change| change.to_string()), Some("+ Point(2)".to_string()));
+
+        // A task waiting for a change is woken when it happens
+        let woken = Arc::new(Woken::default());
+        let waker = Waker::from(Arc::clone(&woken));
+        let mut cx = Context::from_waker(&waker);
+        let mut recv = changes.recv();
+        assert!(Pin::new(&mut recv).poll(&mut cx).is_pending());
+        engine.eval("Point(3)").unwrap();
+        assert!(woken.0.load(Ordering::Relaxed));
+        match Pin::new(&mut recv).poll(&mut cx) {
+            Poll::Ready(Change::Constructed(point)) => assert_eq!(point.to_string(), "Point(3)"),
+            other => panic!("expected Point(3), found {:?}", other.map(|change| change.to_string())),
+        }
+
+        assert!(engine.unsubscribe(changes.id()));
+    }
+}
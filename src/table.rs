@@ -0,0 +1,209 @@
+//! Lists of values, and relations, laid out as text tables, one row per
+//! value and one column per field, for the REPL to print instead of a long
+//! comma-separated line.
+
+use crate::evaluator::{EvalValue, REDACTED};
+use crate::relation::Relation;
+
+/// `values` as a table, showing at most `max_rows` of them. Returns None
+/// unless they're all values of one value type, the only lists with
+/// fields to make columns of.
+pub fn render(values: &[EvalValue], max_rows: usize) -> Option<String> {
+    let EvalValue::Value { type_name, .. } = values.first()? else {
+        return None;
+    };
+    let mut columns: Vec<&str> = Vec::new();
+    for value in values {
+        match value {
+            EvalValue::Value { type_name: name, fields, .. } if name == type_name => {
+                for field in fields.keys() {
+                    if !columns.contains(&field.as_str()) {
+                        columns.push(field);
+                    }
+                }
+            }
+            _ => return None,
+        }
+    }
+    columns.sort_unstable();
+
+    let shown = &values[..values.len().min(max_rows)];
+    let rows: Vec<Vec<Cell>> = shown
+        .iter()
+        .map(|value| {
+            let EvalValue::Value { fields, sensitive, .. } = value else {
+                unreachable!("checked above");
+            };
+            if *sensitive {
+                return columns.iter().map(|_| Cell::redacted()).collect();
+            }
+            columns.iter().map(|column| Cell::new(fields.get(*column))).collect()
+        })
+        .collect();
+    let noun = if values.len() == 1 { "row" } else { "rows" };
+    let footer = if shown.len() < values.len() {
+        format!("({} {} of {}, first {} shown)", values.len(), noun, type_name, shown.len())
+    } else {
+        format!("({} {} of {})", values.len(), noun, type_name)
+    };
+    Some(layout(&columns, &rows, &footer))
+}
+
+/// `relation` as a table, with its columns in schema order, showing at
+/// most `max_rows` of its rows.
+pub fn render_relation(relation: &Relation, max_rows: usize) -> String {
+    let columns: Vec<&str> = relation.schema().fields.iter().map(|(name, _)| name.as_str()).collect();
+    let cells = relation.cells();
+    let rows: Vec<Vec<Cell>> = cells
+        .iter()
+        .take(max_rows)
+        .map(|row| row.iter().map(|value| Cell::new(Some(value))).collect())
+        .collect();
+    let noun = if cells.len() == 1 { "row" } else { "rows" };
+    let footer = if rows.len() < cells.len() {
+        format!("({} {}, first {} shown)", cells.len(), noun, rows.len())
+    } else {
+        format!("({} {})", cells.len(), noun)
+    };
+    layout(&columns, &rows, &footer)
+}
+
+/// A header of `columns`, a rule, then `rows` in columns as wide as their
+/// widest cell, then `footer`.
+fn layout(columns: &[&str], rows: &[Vec<Cell>], footer: &str) -> String {
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .map(|row| row[i].width())
+                .chain(std::iter::once(column.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let mut out = String::new();
+    let header: Vec<String> = columns
+        .iter()
+        .zip(&widths)
+        .map(|(column, width)| format!(" {:<width$} ", column))
+        .collect();
+    out.push_str(header.join("|").trim_end());
+    out.push('\n');
+    let rule: Vec<String> = widths.iter().map(|width| "-".repeat(width + 2)).collect();
+    out.push_str(&rule.join("+"));
+    out.push('\n');
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| {
+                if cell.numeric {
+                    format!(" {:>width$} ", cell.text)
+                } else {
+                    format!(" {:<width$} ", cell.text)
+                }
+            })
+            .collect();
+        out.push_str(cells.join("|").trim_end());
+        out.push('\n');
+    }
+
+    out.push_str(footer);
+    out
+}
+
+struct Cell {
+    text: String,
+    // Numbers are right-aligned
+    numeric: bool,
+}
+
+impl Cell {
+    fn new(value: Option<&EvalValue>) -> Self {
+        Self {
+            text: value.map(ToString::to_string).unwrap_or_default(),
+            numeric: matches!(value, Some(EvalValue::Integer(_))),
+        }
+    }
+
+    fn redacted() -> Self {
+        Self {
+            text: REDACTED.to_string(),
+            numeric: false,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.text.chars().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn person(name: &str, age: i64) -> EvalValue {
+        let mut fields = HashMap::new();
+        fields.insert("name".to_string(), EvalValue::String(name.to_string()));
+        fields.insert("age".to_string(), EvalValue::Integer(age));
+        EvalValue::Value {
+            type_name: "Person".to_string(),
+            fields,
+            display: None,
+            sensitive: false,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let people = [person("Ada", 36), person("Grace", 105), person("Bo", 7)];
+        assert_eq!(
+            render(&people, 10).unwrap(),
+            concat!(
+                " age | name\n",
+                "-----+-------\n",
+                "  36 | Ada\n",
+                " 105 | Grace\n",
+                "   7 | Bo\n",
+                "(3 rows of Person)",
+            )
+        );
+        assert_eq!(
+            render(&people, 1).unwrap(),
+            " age | name\n-----+------\n  36 | Ada\n(3 rows of Person, first 1 shown)"
+        );
+    }
+
+    #[test]
+    fn test_render_relation() {
+        let relation = Relation::new(crate::relation::Schema {
+            fields: vec![("name".to_string(), "String".to_string()), ("age".to_string(), "Int".to_string())],
+        });
+        assert_eq!(render_relation(&relation, 10), " name | age\n------+-----\n(0 rows)");
+        let relation = relation
+            .add_values(vec![EvalValue::String("Ada".to_string()), EvalValue::Integer(36)])
+            .unwrap()
+            .add_values(vec![EvalValue::String("Grace".to_string()), EvalValue::Integer(105)])
+            .unwrap();
+        assert_eq!(
+            render_relation(&relation, 1),
+            " name | age\n------+-----\n Ada  |  36\n(2 rows, first 1 shown)"
+        );
+    }
+
+    #[test]
+    fn test_only_values_of_one_type() {
+        assert_eq!(render(&[], 10), None);
+        assert_eq!(render(&[EvalValue::Integer(1)], 10), None);
+        let other = EvalValue::Value {
+            type_name: "Pet".to_string(),
+            fields: HashMap::new(),
+            display: None,
+            sensitive: false,
+        };
+        assert_eq!(render(&[person("Ada", 36), other], 10), None);
+    }
+}
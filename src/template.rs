@@ -0,0 +1,146 @@
+//! Display templates: the text of a value type's `display:` clause, in
+//! which each `#{expression}` stands for the expression's value. The
+//! expressions see the type's parameter, so
+//! `value Celsius(degrees: Int) { display: "#{degrees} °C" }` shows
+//! `Celsius(21)` as `21 °C`.
+
+use crate::ast::Expression;
+use crate::error::Result;
+use crate::evaluator::{evaluate_expression, EvalValue};
+use crate::lexer::{Lexer, Token};
+use crate::parser::Parser;
+use crate::value::ValueRegistry;
+use std::collections::HashMap;
+
+/// A template, split into its text and the expressions between it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    Text(String),
+    /// A `#{...}`, shown as the value it evaluates to
+    Expression(Expression),
+}
+
+impl Template {
+    /// Split `text` into text and `#{...}` expressions, or say why it can't
+    /// be. A placeholder ends at the `}` that matches its `{`.
+    pub fn parse(text: &str) -> std::result::Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("#{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let source = &rest[start + 2..];
+            let mut depth = 0;
+            let end = source
+                .char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' if depth == 0 => return true,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    false
+                })
+                .map(|(index, _)| index)
+                .ok_or_else(|| format!("'#{{' at {} has no closing '}}'", start))?;
+            parts.push(Part::Expression(parse_placeholder(&source[..end])?));
+            rest = &source[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+        Ok(Template { parts })
+    }
+
+    /// The expressions of the template's placeholders.
+    pub fn expressions(&self) -> impl Iterator<Item = &Expression> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Expression(expr) => Some(expr),
+            Part::Text(_) => None,
+        })
+    }
+
+    /// The template filled in, with `parameter` bound to `value`. Strings
+    /// are shown as they are; other values as they display.
+    pub fn render(&self, parameter: &str, value: &EvalValue, registry: &ValueRegistry) -> Result<String> {
+        let mut context = HashMap::new();
+        context.insert(parameter.to_string(), value.clone());
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Expression(expr) => match evaluate_expression(expr, &context, registry)? {
+                    EvalValue::String(s) => out.push_str(&s),
+                    other => out.push_str(&other.to_string()),
+                },
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn parse_placeholder(source: &str) -> std::result::Result<Expression, String> {
+    if source.trim().is_empty() {
+        return Err("'#{}' needs an expression between the braces".to_string());
+    }
+    let describe = |e: crate::error::Error| format!("in '#{{{}}}': {}", source, e);
+    let mut parser = Parser::new(Lexer::new(source.to_string())).map_err(describe)?;
+    let expr = parser.parse_expression().map_err(describe)?;
+    if parser.current_token != Token::Eof {
+        return Err(format!("in '#{{{}}}': expected one expression", source));
+    }
+    Ok(expr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+
+    #[test]
+    fn test_parse() {
+        let template = Template::parse("#{a} and #{b.length}!").unwrap();
+        assert_eq!(template.parts.len(), 4);
+        assert_eq!(template.parts[1], Part::Text(" and ".to_string()));
+        assert_eq!(template.expressions().count(), 2);
+        assert_eq!(Template::parse("plain").unwrap().parts, [Part::Text("plain".to_string())]);
+
+        assert!(Template::parse("#{a").unwrap_err().contains("no closing"));
+        assert!(Template::parse("#{}").unwrap_err().contains("needs an expression"));
+        assert!(Template::parse("#{a b}").unwrap_err().contains("one expression"));
+    }
+
+    #[test]
+    fn test_display_clause() {
+        let mut engine = Engine::new();
+        engine
+            .load(
+                "value ApiKey(key: String) {\n\
+                 validate: key.trim() != \"\"\n\
+                 display: \"ApiKey(#{key.length} characters)\"\n\
+                 }\n\
+                 value Count(n: Int) { display: \"#{n} items\" }",
+            )
+            .unwrap();
+        assert_eq!(engine.eval("ApiKey(\"0123456789\")").unwrap().to_string(), "ApiKey(10 characters)");
+        assert_eq!(engine.eval("Count(3)").unwrap().to_string(), "3 items");
+        // Instances read back from the pool display the same way
+        assert_eq!(engine.eval("all(Count)").unwrap().to_string(), "[3 items]");
+        // The display text doesn't take part in equality
+        let EvalValue::Value { type_name, fields, .. } = engine.eval("Count(3)").unwrap() else {
+            panic!("expected a Count");
+        };
+        assert_eq!(engine.eval("Count(3)").unwrap(), EvalValue::Value { type_name, fields, display: None, sensitive: false });
+        assert_eq!(engine.eval("ApiKey(\"0123456789\").key").unwrap().to_string(), "0123456789");
+
+        let error = engine.load("value Bad(n: Int) { display: \"#{m}\" }").unwrap_err();
+        assert!(error.to_string().contains("Undefined identifier: m"), "{}", error);
+    }
+}
@@ -0,0 +1,498 @@
+//! Dates, times of day and durations. Each has an ISO-8601 literal written
+//! after `@`: `@2024-01-31`, `@14:30` or `@14:30:15`, and `@P90D` or
+//! `@PT1H30M`. Dates are days of the proleptic Gregorian calendar, with no
+//! time zone; times are seconds since midnight; durations are a signed
+//! number of seconds, so they have no months or years, whose length varies.
+//!
+//! A date moves by whole days (`@2024-01-31 + @P30D`), a time moves around
+//! the clock (`@23:00 + @PT2H` is `01:00:00`), and subtracting one date or
+//! time from another gives the duration between them, so that a rule such
+//! as "the end is within 90 days of the start" is `end - start <= @P90D`.
+
+use crate::ast::{BinaryOp, ComparisonOp};
+use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::EvalValue;
+use crate::types::Type;
+use std::fmt;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// A date, a time of day or a duration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Temporal {
+    Date(Date),
+    Time(Time),
+    Duration(Duration),
+}
+
+/// A calendar date, as the number of days since 1970-01-01.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    days: i64,
+}
+
+/// A time of day, as the number of seconds since midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Time {
+    seconds: i64,
+}
+
+/// A length of time in seconds, which may be negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration {
+    seconds: i64,
+}
+
+impl Temporal {
+    /// The value of an ISO-8601 literal, without its `@`.
+    pub fn parse(text: &str) -> Option<Temporal> {
+        if text.starts_with('P') || text.starts_with("-P") {
+            Duration::parse(text).map(Temporal::Duration)
+        } else if text.contains(':') {
+            Time::parse(text).map(Temporal::Time)
+        } else {
+            Date::parse(text).map(Temporal::Date)
+        }
+    }
+
+    /// The name of the value's type, as written in source.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Temporal::Date(_) => "Date",
+            Temporal::Time(_) => "Time",
+            Temporal::Duration(_) => "Duration",
+        }
+    }
+
+    pub fn type_of(&self) -> Type {
+        match self {
+            Temporal::Date(_) => Type::Date,
+            Temporal::Time(_) => Type::Time,
+            Temporal::Duration(_) => Type::Duration,
+        }
+    }
+
+    /// The result of the built-in method `name`, such as `year()`.
+    pub fn accessor(&self, name: &str) -> Result<i64> {
+        let result = match (self, name) {
+            (Temporal::Date(date), "year") => date.year(),
+            (Temporal::Date(date), "month") => date.month(),
+            (Temporal::Date(date), "day") => date.day(),
+            (Temporal::Date(date), "dayOfWeek") => date.day_of_week(),
+            (Temporal::Time(time), "hour") => time.seconds / 3600,
+            (Temporal::Time(time), "minute") => time.seconds / 60 % 60,
+            (Temporal::Time(time), "second") => time.seconds % 60,
+            (Temporal::Duration(duration), "days") => duration.seconds / SECONDS_PER_DAY,
+            (Temporal::Duration(duration), "hours") => duration.seconds / 3600,
+            (Temporal::Duration(duration), "minutes") => duration.seconds / 60,
+            (Temporal::Duration(duration), "seconds") => duration.seconds,
+            _ => {
+                return Err(Error::Validation(ValidationError {
+                    message: format!("{} has no method '{}'", self.type_of(), name),
+                    value_type: self.type_of().to_string(),
+                }))
+            }
+        };
+        Ok(result)
+    }
+}
+
+impl fmt::Display for Temporal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Temporal::Date(date) => write!(f, "{}", date),
+            Temporal::Time(time) => write!(f, "{}", time),
+            Temporal::Duration(duration) => write!(f, "{}", duration),
+        }
+    }
+}
+
+impl Date {
+    /// The date with this year, month and day, if there is one.
+    pub fn from_ymd(year: i64, month: i64, day: i64) -> Option<Date> {
+        if !(0..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+            return None;
+        }
+        // Days from civil, counting years from March so that the leap day
+        // comes last
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let year_of_era = y.rem_euclid(400);
+        let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        Some(Date { days: era * 146_097 + day_of_era - 719_468 })
+    }
+
+    /// `YYYY-MM-DD`.
+    pub fn parse(text: &str) -> Option<Date> {
+        let mut parts = text.split('-');
+        let year = fixed_digits(parts.next()?, 4)?;
+        let month = fixed_digits(parts.next()?, 2)?;
+        let day = fixed_digits(parts.next()?, 2)?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Date::from_ymd(year, month, day)
+    }
+
+    fn civil(&self) -> (i64, i64, i64) {
+        let z = self.days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+
+    pub fn year(&self) -> i64 {
+        self.civil().0
+    }
+
+    pub fn month(&self) -> i64 {
+        self.civil().1
+    }
+
+    pub fn day(&self) -> i64 {
+        self.civil().2
+    }
+
+    /// 1 for Monday through 7 for Sunday, as in ISO-8601.
+    pub fn day_of_week(&self) -> i64 {
+        // 1970-01-01 was a Thursday
+        (self.days + 3).rem_euclid(7) + 1
+    }
+
+    fn plus_days(&self, days: i64) -> Option<Date> {
+        let date = Date { days: self.days.checked_add(days)? };
+        (0..=9999).contains(&date.year()).then_some(date)
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.civil();
+        write!(f, "{:04}-{:02}-{:02}", year, month, day)
+    }
+}
+
+impl Time {
+    pub fn from_hms(hour: i64, minute: i64, second: i64) -> Option<Time> {
+        if !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0..60).contains(&second) {
+            return None;
+        }
+        Some(Time { seconds: hour * 3600 + minute * 60 + second })
+    }
+
+    /// `HH:MM` or `HH:MM:SS`.
+    pub fn parse(text: &str) -> Option<Time> {
+        let mut parts = text.split(':');
+        let hour = fixed_digits(parts.next()?, 2)?;
+        let minute = fixed_digits(parts.next()?, 2)?;
+        let second = match parts.next() {
+            Some(second) => fixed_digits(second, 2)?,
+            None => 0,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Time::from_hms(hour, minute, second)
+    }
+
+    /// The time `seconds` later, around the clock.
+    fn plus_seconds(&self, seconds: i64) -> Time {
+        Time { seconds: (self.seconds + seconds.rem_euclid(SECONDS_PER_DAY)) % SECONDS_PER_DAY }
+    }
+}
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.seconds / 3600, self.seconds / 60 % 60, self.seconds % 60)
+    }
+}
+
+impl Duration {
+    pub fn from_seconds(seconds: i64) -> Duration {
+        Duration { seconds }
+    }
+
+    pub fn seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    /// `P[nW][nD][T[nH][nM][nS]]`, optionally negated with a leading `-`.
+    /// Years and months are refused, having no fixed length.
+    pub fn parse(text: &str) -> Option<Duration> {
+        let (sign, text) = match text.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, text),
+        };
+        let text = text.strip_prefix('P')?;
+        let (date_part, time_part) = match text.split_once('T') {
+            Some((date_part, time_part)) if !time_part.is_empty() => (date_part, Some(time_part)),
+            Some(_) => return None,
+            None => (text, None),
+        };
+
+        let mut seconds: i64 = 0;
+        let mut components = 0;
+        let units = [
+            (date_part, &[('W', 7 * SECONDS_PER_DAY), ('D', SECONDS_PER_DAY)][..]),
+            (time_part.unwrap_or(""), &[('H', 3600), ('M', 60), ('S', 1)][..]),
+        ];
+        for (mut part, units) in units {
+            for &(designator, scale) in units {
+                if let Some((number, rest)) = part.split_once(designator) {
+                    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+                        return None;
+                    }
+                    let amount: i64 = number.parse().ok()?;
+                    seconds = seconds.checked_add(amount.checked_mul(scale)?)?;
+                    components += 1;
+                    part = rest;
+                }
+            }
+            if !part.is_empty() {
+                return None;
+            }
+        }
+        (components > 0).then_some(Duration { seconds: sign * seconds })
+    }
+}
+
+impl fmt::Display for Duration {
+    /// The ISO-8601 form, in days, hours, minutes and seconds
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.seconds == 0 {
+            return write!(f, "PT0S");
+        }
+        if self.seconds < 0 {
+            write!(f, "-")?;
+        }
+        let total = self.seconds.unsigned_abs();
+        let days = total / SECONDS_PER_DAY as u64;
+        let rest = total % SECONDS_PER_DAY as u64;
+        write!(f, "P")?;
+        if days > 0 {
+            write!(f, "{}D", days)?;
+        }
+        if rest > 0 {
+            write!(f, "T")?;
+            for (amount, designator) in [(rest / 3600, 'H'), (rest / 60 % 60, 'M'), (rest % 60, 'S')] {
+                if amount > 0 {
+                    write!(f, "{}{}", amount, designator)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The type of `left op right` when either side is a date, a time or a
+/// duration, or None if the operator doesn't apply to them.
+pub fn operator_type(op: &BinaryOp, left: &Type, right: &Type) -> Option<Type> {
+    match (op, left, right) {
+        (BinaryOp::Add, Type::Date, Type::Duration)
+        | (BinaryOp::Add, Type::Duration, Type::Date)
+        | (BinaryOp::Subtract, Type::Date, Type::Duration) => Some(Type::Date),
+        (BinaryOp::Add, Type::Time, Type::Duration)
+        | (BinaryOp::Add, Type::Duration, Type::Time)
+        | (BinaryOp::Subtract, Type::Time, Type::Duration) => Some(Type::Time),
+        (BinaryOp::Add | BinaryOp::Subtract, Type::Duration, Type::Duration)
+        | (BinaryOp::Subtract, Type::Date, Type::Date)
+        | (BinaryOp::Subtract, Type::Time, Type::Time)
+        | (BinaryOp::Multiply, Type::Duration, Type::Int)
+        | (BinaryOp::Multiply, Type::Int, Type::Duration) => Some(Type::Duration),
+        _ => None,
+    }
+}
+
+pub fn is_temporal_type(ty: &Type) -> bool {
+    matches!(ty, Type::Date | Type::Time | Type::Duration)
+}
+
+/// `left op right` for two dates, two times or two durations.
+pub fn compare(op: &ComparisonOp, left: &Temporal, right: &Temporal) -> Result<bool> {
+    let ordering = match (left, right) {
+        (Temporal::Date(l), Temporal::Date(r)) => l.cmp(r),
+        (Temporal::Time(l), Temporal::Time(r)) => l.cmp(r),
+        (Temporal::Duration(l), Temporal::Duration(r)) => l.cmp(r),
+        _ => return Err(mismatch("comparison")),
+    };
+    match op {
+        ComparisonOp::Equal => Ok(ordering.is_eq()),
+        ComparisonOp::NotEqual => Ok(ordering.is_ne()),
+        ComparisonOp::Less => Ok(ordering.is_lt()),
+        ComparisonOp::Greater => Ok(ordering.is_gt()),
+        ComparisonOp::LessEqual => Ok(ordering.is_le()),
+        ComparisonOp::GreaterEqual => Ok(ordering.is_ge()),
+        ComparisonOp::Contains => Err(mismatch("comparison")),
+    }
+}
+
+/// `left op right`, where either side is a date, a time or a duration.
+pub fn arithmetic(op: &BinaryOp, left: &EvalValue, right: &EvalValue) -> Result<EvalValue> {
+    use Temporal::{Date as D, Duration as P, Time as T};
+    let result = match (op, left, right) {
+        (BinaryOp::Add, EvalValue::Temporal(D(date)), EvalValue::Temporal(P(duration)))
+        | (BinaryOp::Add, EvalValue::Temporal(P(duration)), EvalValue::Temporal(D(date))) => {
+            move_date(date, duration.seconds)?
+        }
+        (BinaryOp::Subtract, EvalValue::Temporal(D(date)), EvalValue::Temporal(P(duration))) => {
+            move_date(date, duration.seconds.checked_neg().ok_or_else(overflow)?)?
+        }
+        (BinaryOp::Add, EvalValue::Temporal(T(time)), EvalValue::Temporal(P(duration)))
+        | (BinaryOp::Add, EvalValue::Temporal(P(duration)), EvalValue::Temporal(T(time))) => {
+            T(time.plus_seconds(duration.seconds))
+        }
+        (BinaryOp::Subtract, EvalValue::Temporal(T(time)), EvalValue::Temporal(P(duration))) => {
+            T(time.plus_seconds(-duration.seconds.rem_euclid(SECONDS_PER_DAY)))
+        }
+        (BinaryOp::Add, EvalValue::Temporal(P(l)), EvalValue::Temporal(P(r))) => {
+            P(Duration { seconds: l.seconds.checked_add(r.seconds).ok_or_else(overflow)? })
+        }
+        (BinaryOp::Subtract, EvalValue::Temporal(P(l)), EvalValue::Temporal(P(r))) => {
+            P(Duration { seconds: l.seconds.checked_sub(r.seconds).ok_or_else(overflow)? })
+        }
+        (BinaryOp::Subtract, EvalValue::Temporal(D(l)), EvalValue::Temporal(D(r))) => {
+            P(Duration { seconds: (l.days - r.days) * SECONDS_PER_DAY })
+        }
+        (BinaryOp::Subtract, EvalValue::Temporal(T(l)), EvalValue::Temporal(T(r))) => {
+            P(Duration { seconds: l.seconds - r.seconds })
+        }
+        (BinaryOp::Multiply, EvalValue::Temporal(P(duration)), EvalValue::Integer(n))
+        | (BinaryOp::Multiply, EvalValue::Integer(n), EvalValue::Temporal(P(duration))) => {
+            P(Duration { seconds: duration.seconds.checked_mul(*n).ok_or_else(overflow)? })
+        }
+        _ => return Err(mismatch("binary operation")),
+    };
+    Ok(EvalValue::Temporal(result))
+}
+
+/// `date` moved by `seconds`, which must make whole days.
+fn move_date(date: &Date, seconds: i64) -> Result<Temporal> {
+    if seconds % SECONDS_PER_DAY != 0 {
+        return Err(Error::Validation(ValidationError {
+            message: format!("A date can only move by whole days, not {}", Duration { seconds }),
+            value_type: "Date".to_string(),
+        }));
+    }
+    let moved = date.plus_days(seconds / SECONDS_PER_DAY).ok_or_else(|| {
+        Error::Validation(ValidationError {
+            message: "The date is outside the years 0000 to 9999".to_string(),
+            value_type: "Date".to_string(),
+        })
+    })?;
+    Ok(Temporal::Date(moved))
+}
+
+// As the evaluator reports operands of the wrong types
+fn mismatch(operation: &str) -> Error {
+    Error::Validation(ValidationError {
+        message: format!("Type mismatch in {}", operation),
+        value_type: "".to_string(),
+    })
+}
+
+fn overflow() -> Error {
+    Error::Validation(ValidationError {
+        message: "The duration is too long".to_string(),
+        value_type: "Duration".to_string(),
+    })
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// `text` as a number, if it is exactly `width` ASCII digits.
+fn fixed_digits(text: &str, width: usize) -> Option<i64> {
+    if text.len() != width || !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> Date {
+        Date::parse(text).unwrap()
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        for text in ["1970-01-01", "2024-02-29", "0001-03-01", "9999-12-31", "1969-12-31"] {
+            assert_eq!(date(text).to_string(), text);
+        }
+        for text in ["2023-02-29", "2024-13-01", "2024-1-01", "24-01-01", "2024-01-01-01"] {
+            assert_eq!(Date::parse(text), None, "{}", text);
+        }
+        assert_eq!(Time::parse("09:05").unwrap().to_string(), "09:05:00");
+        assert_eq!(Time::parse("24:00"), None);
+
+        let durations = [("P90D", "P90D"), ("PT90M", "PT1H30M"), ("P1W", "P7D"), ("-P1DT2S", "-P1DT2S"), ("PT0S", "PT0S")];
+        for (text, canonical) in durations {
+            assert_eq!(Duration::parse(text).unwrap().to_string(), canonical);
+        }
+        for text in ["P", "PT", "P1M", "P1Y", "PT1D", "P1H", "P-1D", "P1D2"] {
+            assert_eq!(Duration::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_calendar() {
+        assert_eq!(date("1970-01-01").days, 0);
+        assert_eq!(date("2000-03-01").days - date("2000-02-28").days, 2);
+        assert_eq!(date("1900-03-01").days - date("1900-02-28").days, 1);
+        // 2024-01-31 was a Wednesday
+        assert_eq!(date("2024-01-31").day_of_week(), 3);
+        assert_eq!(date("2024-02-04").day_of_week(), 7);
+        let d = date("2024-07-15");
+        assert_eq!((d.year(), d.month(), d.day()), (2024, 7, 15));
+    }
+
+    #[test]
+    fn test_temporals_in_relic() {
+        use crate::Engine;
+
+        let mut engine = Engine::new();
+        let eval = |engine: &mut Engine, source: &str| engine.eval(source).map(|value| value.to_string());
+        assert_eq!(eval(&mut engine, "@2024-01-31 + @P30D").unwrap(), "2024-03-01");
+        assert_eq!(eval(&mut engine, "@2024-03-01 - @2024-01-31").unwrap(), "P30D");
+        assert_eq!(eval(&mut engine, "@23:30 + @PT45M").unwrap(), "00:15:00");
+        assert_eq!(eval(&mut engine, "@00:15 - @PT45M").unwrap(), "23:30:00");
+        assert_eq!(eval(&mut engine, "@PT20M * 3 - @PT1H").unwrap(), "PT0S");
+        assert_eq!(eval(&mut engine, "-@P1D").unwrap(), "-P1D");
+        assert_eq!(eval(&mut engine, "@2024-01-31.dayOfWeek()").unwrap(), "3");
+        assert_eq!(eval(&mut engine, "@2024-01-31 < @2024-02-01 && @09:00 >= @08:59:59").unwrap(), "true");
+        assert!(eval(&mut engine, "@2024-01-31 + @PT1H").unwrap_err().to_string().contains("whole days"));
+        assert!(eval(&mut engine, "@9999-12-31 + @P1D").is_err());
+
+        // Comparing or combining different kinds is a type error
+        assert!(matches!(engine.eval("@2024-01-31 + @2024-01-31"), Err(Error::Type(_))));
+        assert!(matches!(engine.eval("@2024-01-31 < @12:00"), Err(Error::Type(_))));
+        assert!(matches!(engine.eval("@2024-02-30"), Err(Error::Lexer(_))));
+
+        engine
+            .load(
+                "value Booking(start: Date) { validate: start.dayOfWeek() <= 5 }\n\
+                 fn withinQuarter(start: Date, end: Date) -> Bool { end - start <= @P90D }",
+            )
+            .unwrap();
+        assert_eq!(eval(&mut engine, "Booking(@2024-01-31).start.year()").unwrap(), "2024");
+        assert!(engine.eval("Booking(@2024-02-03)").is_err());
+        assert_eq!(eval(&mut engine, "withinQuarter(@2024-01-01, @2024-03-31)").unwrap(), "true");
+        assert_eq!(eval(&mut engine, "withinQuarter(@2024-01-01, @2024-04-01)").unwrap(), "false");
+        assert_eq!(engine.get_instances("Booking").unwrap()[0].to_string(), "Booking(2024-01-31)");
+    }
+}
@@ -64,4 +64,4 @@ mod tests {
         let email = EmailAddress::from("test@example.com".to_string()).unwrap();
         assert_eq!(format!("{}", email), "EmailAddress(test@example.com)");
     }
-}
\ No newline at end of file
+}
@@ -1,4 +1,6 @@
+use crate::ast::{ParameterWithGuard, ValueDeclaration};
 use std::collections::HashMap;
+use std::fmt;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
@@ -12,6 +14,72 @@ pub enum Type {
     Type,
     // Minimal List type for relational operations
     List(Box<Type>),
+    // A value that may be missing, such as an unset environment variable
+    Option(Box<Type>),
+    // Rows with named fields; the schema is only known at run time
+    Relation,
+    // A calendar date, a time of day and a length of time
+    Date,
+    Time,
+    Duration,
+    // A fixed-point decimal number
+    Decimal,
+    // Rows of the element type, visited when the query is run
+    Query(Box<Type>),
+}
+
+impl Type {
+    /// Whether a value of type `actual` can be used where `self` is expected.
+    /// `Any` stands for a type that is not known statically, such as the
+    /// elements of `all(t)`, and is compatible with every type.
+    pub fn accepts(&self, actual: &Type) -> bool {
+        match (self, actual) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::List(expected), Type::List(actual)) => expected.accepts(actual),
+            (Type::Option(expected), Type::Option(actual)) => expected.accepts(actual),
+            (Type::Query(expected), Type::Query(actual)) => expected.accepts(actual),
+            _ => self == actual,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    /// The type as it is written in source, e.g. `List[Email]`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::String => write!(f, "String"),
+            Type::Int => write!(f, "Int"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Value(name) => write!(f, "{}", name),
+            Type::Any => write!(f, "Any"),
+            Type::Unknown => write!(f, "Unknown"),
+            Type::Type => write!(f, "Type"),
+            Type::List(element) => write!(f, "List[{}]", element),
+            Type::Option(element) => write!(f, "Option[{}]", element),
+            Type::Relation => write!(f, "Relation"),
+            Type::Date => write!(f, "Date"),
+            Type::Time => write!(f, "Time"),
+            Type::Duration => write!(f, "Duration"),
+            Type::Decimal => write!(f, "Decimal"),
+            Type::Query(element) => write!(f, "Query[{}]", element),
+        }
+    }
+}
+
+/// Types of a parameter or argument list, written `(Int, String)`.
+pub struct ParameterList<'a>(pub &'a [Type]);
+
+impl fmt::Display for ParameterList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, ty) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", ty)?;
+        }
+        write!(f, ")")
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -19,27 +87,72 @@ pub struct TypeEnvironment {
     values: HashMap<String, ValueType>,
     // Unified storage: all functions can have multiple implementations
     functions: HashMap<String, Vec<FunctionType>>,
+    // Types of the values bound by top-level `let`s
+    bindings: HashMap<String, Type>,
+    // Units of the measures functions return, by name and parameter types
+    return_units: HashMap<(String, Vec<Type>), String>,
+    // The value type each function registered with `convert:` constructs
+    conversion_targets: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct FunctionType {
     pub name: String,
+    pub parameter_names: Vec<String>,
     pub parameter_types: Vec<Type>,
     pub return_type: Type,
 }
 
-#[derive(Debug, Clone)]
-pub struct MethodSignature {
-    pub parameter_types: Vec<Type>,
-    pub return_type: Type,
-    pub guards: Vec<Option<String>>,
+impl fmt::Display for FunctionType {
+    /// The signature without names, e.g. `(Int, String) -> Bool`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {}", ParameterList(&self.parameter_types), self.return_type)
+    }
+}
+
+impl FunctionType {
+    /// The signature of a function declared with `parameters`.
+    pub fn of(name: &str, parameters: &[ParameterWithGuard], return_type: &Type) -> Self {
+        Self {
+            name: name.to_string(),
+            parameter_names: parameters.iter().map(|p| p.name.clone()).collect(),
+            parameter_types: parameters.iter().map(|p| p.ty.clone()).collect(),
+            return_type: return_type.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ValueType {
     pub name: String,
+    pub parameter_name: String,
     pub parameter_type: Type,
+    /// What the parameter measures, such as "km"
+    pub unit: Option<String>,
+    /// The functions registered to construct it from another unit
+    pub conversions: Vec<String>,
     pub constraints: Constraints,
+    /// Whether a bare parameter is accepted where the type is expected
+    pub coerce: bool,
+}
+
+impl ValueType {
+    /// The value type `declaration` declares.
+    pub fn of(declaration: &ValueDeclaration) -> Self {
+        Self {
+            name: declaration.name.clone(),
+            parameter_name: declaration.parameter.name.clone(),
+            parameter_type: declaration.parameter.ty.clone(),
+            unit: declaration.parameter.unit.clone(),
+            conversions: declaration.body.convert.clone(),
+            constraints: Constraints {
+                validate: declaration.body.validate.as_ref().map(|_| "custom".to_string()),
+                normalize: declaration.body.normalize.as_ref().map(|_| "custom".to_string()),
+                unique: declaration.body.unique.unwrap_or(false),
+            },
+            coerce: declaration.body.coerce.unwrap_or(false),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -49,11 +162,20 @@ pub struct Constraints {
     pub unique: bool,
 }
 
+impl Default for TypeEnvironment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TypeEnvironment {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
             functions: HashMap::new(),
+            bindings: HashMap::new(),
+            return_units: HashMap::new(),
+            conversion_targets: HashMap::new(),
         }
     }
 
@@ -65,13 +187,41 @@ impl TypeEnvironment {
         self.values.get(name)
     }
 
-    pub fn define_function(&mut self, name: String, parameter_types: Vec<Type>, return_type: Type) {
+    /// Bind `name` at the top level to a value of type `ty`, replacing an
+    /// earlier binding of the name.
+    pub fn define_binding(&mut self, name: String, ty: Type) {
+        self.bindings.insert(name, ty);
+    }
+
+    /// The names bound at the top level, with the types of their values.
+    pub fn bindings(&self) -> &HashMap<String, Type> {
+        &self.bindings
+    }
+
+    /// Every value type defined so far, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &ValueType> {
+        self.values.values()
+    }
+
+    /// Every overload of every function defined so far, in no particular order.
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionType> {
+        self.functions.values().flatten()
+    }
+
+    pub fn define_function(
+        &mut self,
+        name: String,
+        parameter_names: Vec<String>,
+        parameter_types: Vec<Type>,
+        return_type: Type,
+    ) {
         let function_type = FunctionType {
             name: name.clone(),
+            parameter_names,
             parameter_types,
             return_type,
         };
-        self.functions.entry(name).or_insert_with(Vec::new).push(function_type);
+        self.functions.entry(name).or_default().push(function_type);
     }
 
     pub fn get_function(&self, name: &str) -> Option<&FunctionType> {
@@ -85,26 +235,56 @@ impl TypeEnvironment {
         })
     }
     
-    pub fn get_functions(&self, name: &str) -> Option<&Vec<FunctionType>> {
-        self.functions.get(name)
-    }
-    
-    // Methods are now just functions with multiple implementations
-    pub fn define_method(&mut self, name: String, signature: MethodSignature) {
-        let function_type = FunctionType {
-            name: name.clone(),
-            parameter_types: signature.parameter_types,
-            return_type: signature.return_type,
+    /// Record the unit of the measure the overload of `name` taking
+    /// `parameter_types` returns, or that it returns no measure.
+    pub fn define_return_unit(&mut self, name: String, parameter_types: Vec<Type>, unit: Option<String>) {
+        match unit {
+            Some(unit) => self.return_units.insert((name, parameter_types), unit),
+            None => self.return_units.remove(&(name, parameter_types)),
         };
-        self.functions.entry(name).or_insert_with(Vec::new).push(function_type);
     }
-    
-    pub fn get_methods(&self, _name: &str) -> Option<&Vec<MethodSignature>> {
-        // For backward compatibility during transition
-        None
+
+    /// The unit of the measure the overload of `name` taking
+    /// `parameter_types` returns, if it's known.
+    pub fn return_unit(&self, name: &str, parameter_types: &[Type]) -> Option<&String> {
+        self.return_units.get(&(name.to_string(), parameter_types.to_vec()))
+    }
+
+    /// Record that `function` is registered with `convert:` to construct
+    /// the value type `target`.
+    pub fn declare_conversion(&mut self, function: String, target: String) {
+        self.conversion_targets.insert(function, target);
+    }
+
+    /// The value type `function` is registered to construct, if any.
+    pub fn conversion_target(&self, function: &str) -> Option<&String> {
+        self.conversion_targets.get(function)
+    }
+
+    pub fn get_functions(&self, name: &str) -> Option<&Vec<FunctionType>> {
+        self.functions.get(name)
     }
     
     pub fn is_type_name(&self, name: &str) -> bool {
         self.values.contains_key(name)
     }
+
+    /// Remove the overload of the function `name` that takes
+    /// `parameter_types`, if there is one.
+    pub fn remove_overload(&mut self, name: &str, parameter_types: &[Type]) {
+        if let Some(overloads) = self.functions.get_mut(name) {
+            overloads.retain(|overload| overload.parameter_types != parameter_types);
+        }
+        self.return_units.remove(&(name.to_string(), parameter_types.to_vec()));
+    }
+
+    /// Remove the value type or every overload of the function called
+    /// `name`. Returns false if nothing had that name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let value = self.values.remove(name).is_some();
+        let functions = self.functions.remove(name).is_some();
+        self.return_units.retain(|(function, _), _| function != name);
+        self.conversion_targets.retain(|_, target| target != name);
+        value || functions
+    }
 }
@@ -1,11 +1,30 @@
 use crate::ast::{ValueDeclaration, FunctionDeclaration, MethodDeclaration};
+use crate::builtin::{BuiltinMethod, BuiltinTable};
+use crate::cancel::CancellationToken;
+use crate::dispatch::{Dispatch, DispatchCache, DispatchTable, DispatchTables};
+use crate::division::Division;
+use crate::limits::{EvalLimits, Usage};
 use crate::error::{Error, Result, ValidationError};
+use crate::evaluator::{self, EvalValue};
+use crate::native::NativeFunction;
+use crate::profile::{Counters, Profile};
+use crate::provenance::{self, Provenance};
+use crate::stdlib::ScriptEnvironment;
+use crate::decimal::Decimal;
+use crate::temporal::Temporal;
+use crate::template::Template;
+use crate::stable_hash::{self, stable_hash};
+use crate::subscription::{Change, ChangeStream, Subscriber, SubscriptionId, Subscriptions};
 use std::any::Any;
-use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
+use crate::types::Type;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 use std::fmt::{Debug, Display};
 use std::sync::{Arc, RwLock};
 
+/// An instance of a value type. `hash_value` must agree with `equals`, and
+/// is made with a `StableHasher`, so that the same value hashes the same in
+/// every process (for as long as `stable_hash::HASH_VERSION` is the same).
 pub trait ValueObject: Any + Send + Sync + Debug + Display {
     fn validate(&self) -> Result<()>;
     fn normalize(&mut self) -> Result<()>;
@@ -16,19 +35,198 @@ pub trait ValueObject: Any + Send + Sync + Debug + Display {
     fn hash_value(&self) -> u64;
 }
 
+pub type Validator = Box<dyn Fn(&(dyn Any + Send + Sync)) -> Result<()> + Send + Sync>;
+pub type Normalizer = Box<dyn Fn(&mut (dyn Any + Send + Sync)) -> Result<()> + Send + Sync>;
+
+/// Decides whether evaluation can see an instance, given its type's name
+/// and the instance; see `ValueRegistry::set_visibility`.
+pub type Visibility = Arc<dyn Fn(&str, &EvalValue) -> bool + Send + Sync>;
+
+/// The instances of one value type at one moment, in the order they were
+/// constructed. Constructions after the snapshot was taken don't change it.
+pub type Snapshot = Arc<Vec<Arc<dyn ValueObject>>>;
+
+/// The instances of one value type. Readers take a snapshot by cloning the
+/// `Arc`, holding the lock only for that; a writer copies the instances
+/// first only if a snapshot of them is still in use.
+type Pool = Arc<RwLock<Snapshot>>;
+
+/// Instance pools for Type-as-Relation, keyed by type name. Each type's
+/// pool has its own lock, so constructing values of different types from
+/// different threads doesn't contend; the map of pools is only locked for
+/// writing when a type gets its first instance or is removed.
+#[derive(Default)]
+struct InstanceStore {
+    pools: RwLock<HashMap<String, Pool>>,
+}
+
+impl InstanceStore {
+    /// The pool of `type_name`, if it has had an instance.
+    fn pool(&self, type_name: &str) -> Option<Pool> {
+        self.pools.read().ok()?.get(type_name).map(Arc::clone)
+    }
+
+    /// The pool of `type_name`, made empty if it hasn't had an instance.
+    fn pool_or_default(&self, type_name: &str) -> Option<Pool> {
+        if let Some(pool) = self.pool(type_name) {
+            return Some(pool);
+        }
+        let mut pools = self.pools.write().ok()?;
+        Some(Arc::clone(pools.entry(type_name.to_string()).or_default()))
+    }
+
+    /// The instances of `type_name` as they are now, oldest first.
+    fn snapshot(&self, type_name: &str) -> Snapshot {
+        self.pool(type_name)
+            .and_then(|pool| pool.read().ok().map(|instances| Arc::clone(&instances)))
+            .unwrap_or_default()
+    }
+
+    /// Call `f` with the instances of `type_name` to change, making its pool
+    /// if `create` and it has none.
+    fn write<R>(
+        &self,
+        type_name: &str,
+        create: bool,
+        f: impl FnOnce(&mut Vec<Arc<dyn ValueObject>>) -> R,
+    ) -> Option<R> {
+        let pool = if create { self.pool_or_default(type_name)? } else { self.pool(type_name)? };
+        let mut instances = pool.write().ok()?;
+        Some(f(Arc::make_mut(&mut instances)))
+    }
+
+    /// Take the pool of `type_name` out of the store, returning its instances.
+    fn remove(&self, type_name: &str) -> Vec<Arc<dyn ValueObject>> {
+        let removed = self.pools.write().ok().and_then(|mut pools| pools.remove(type_name));
+        removed
+            .and_then(|pool| pool.write().ok().map(|mut instances| std::mem::take(Arc::make_mut(&mut instances))))
+            .unwrap_or_default()
+    }
+
+    /// The names of the types with pools.
+    fn type_names(&self) -> Vec<String> {
+        self.pools.read().map(|pools| pools.keys().cloned().collect()).unwrap_or_default()
+    }
+}
+
+/// The string parameters of instances, one copy of each distinct string,
+/// shared by every instance that wraps it. Imports repeat the same few
+/// strings (country codes, currencies, statuses) across many instances, so
+/// memory grows with the distinct strings rather than the instances.
+#[derive(Default)]
+struct Interner {
+    strings: RwLock<HashSet<Arc<str>>>,
+}
+
+impl Interner {
+    /// The shared copy of `text`, made if this is its first use.
+    fn intern(&self, text: String) -> Arc<str> {
+        if let Some(shared) = self.strings.read().ok().and_then(|strings| strings.get(text.as_str()).cloned()) {
+            return shared;
+        }
+        let Ok(mut strings) = self.strings.write() else {
+            return Arc::from(text);
+        };
+        // Another thread may have added it meanwhile
+        if let Some(shared) = strings.get(text.as_str()) {
+            return Arc::clone(shared);
+        }
+        let shared: Arc<str> = Arc::from(text);
+        strings.insert(Arc::clone(&shared));
+        shared
+    }
+
+    /// Share `text`, which an instance from another registry already holds.
+    fn adopt(&self, text: &Arc<str>) {
+        if let Ok(mut strings) = self.strings.write() {
+            if !strings.contains(&**text) {
+                strings.insert(Arc::clone(text));
+            }
+        }
+    }
+
+    /// Forget the strings no instance holds any more.
+    fn prune(&mut self) {
+        if let Ok(strings) = self.strings.get_mut() {
+            strings.retain(|text| Arc::strong_count(text) > 1);
+        }
+    }
+
+    /// How many distinct strings there are, and their bytes.
+    fn stats(&self) -> (usize, usize) {
+        self.strings.read().map_or((0, 0), |strings| {
+            (strings.len(), strings.iter().map(|text| text.len()).sum())
+        })
+    }
+}
+
+/// What `ValueRegistry::construct_bulk` made of its inputs.
+#[derive(Debug)]
+pub struct BulkConstruction<T> {
+    /// The instances constructed, in the order of their inputs.
+    pub instances: Vec<T>,
+    /// Why each of the other inputs was rejected.
+    pub errors: Vec<BulkError>,
+}
+
+/// An input `construct_bulk` rejected, by its index among the inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkError {
+    pub index: usize,
+    pub message: String,
+}
+
 pub struct ValueConstructor {
     pub declaration: ValueDeclaration,
-    pub validator: Box<dyn Fn(&(dyn Any + Send + Sync)) -> Result<()> + Send + Sync>,
-    pub normalizer: Option<Box<dyn Fn(&mut (dyn Any + Send + Sync)) -> Result<()> + Send + Sync>>,
+    pub validator: Validator,
+    pub normalizer: Option<Normalizer>,
+    /// The declaration's `display:` template, parsed
+    pub display: Option<Template>,
 }
 
 pub struct ValueRegistry {
     pub(crate) constructors: HashMap<String, ValueConstructor>,
     // Unified storage: all functions can have multiple implementations
     functions: HashMap<String, Vec<FunctionDeclaration>>,
+    // Rust bodies of the functions that have them, by name
+    natives: HashMap<String, Vec<NativeFunction>>,
+    // Methods of the built-in types, such as `trim()` on a String
+    builtins: BuiltinTable,
+    // Signatures of the prelude's functions that haven't been replaced
+    prelude: HashSet<(String, Vec<Type>)>,
     // Type-as-Relation: Track all instances by type name
     // Using strong references to keep instances indefinitely
-    instances: Arc<RwLock<HashMap<String, Vec<Arc<dyn ValueObject>>>>>,
+    instances: InstanceStore,
+    // Arguments and variables for `args()` and `env()`
+    script_environment: ScriptEnvironment,
+    // Values bound by top-level `let`s, which every evaluation sees
+    bindings: HashMap<String, EvalValue>,
+    counters: Counters,
+    // Checked by the evaluator, to stop when another thread cancels
+    cancellation: CancellationToken,
+    limits: EvalLimits,
+    usage: Usage,
+    // Which instances queries see; all of them when None
+    visibility: Option<Visibility>,
+    // Who to tell when instances are constructed or retracted
+    subscriptions: Subscriptions,
+    // Whether instances keep the code that constructed them
+    records_provenance: bool,
+    // The string parameters of instances, each stored once
+    strings: Interner,
+    dispatch: Dispatch,
+    // Compiled at each function's first call, and dropped when its
+    // overloads change
+    dispatch_tables: DispatchTables,
+    // The overloads `Dispatch::Scan` resolved, for functions without guards
+    dispatch_cache: DispatchCache,
+    division: Division,
+}
+
+impl Default for ValueRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ValueRegistry {
@@ -36,18 +234,273 @@ impl ValueRegistry {
         Self {
             constructors: HashMap::new(),
             functions: HashMap::new(),
-            instances: Arc::new(RwLock::new(HashMap::new())),
+            natives: HashMap::new(),
+            builtins: BuiltinTable::standard(),
+            prelude: HashSet::new(),
+            instances: InstanceStore::default(),
+            script_environment: ScriptEnvironment::default(),
+            bindings: HashMap::new(),
+            counters: Counters::default(),
+            cancellation: CancellationToken::new(),
+            limits: EvalLimits::default(),
+            usage: Usage::default(),
+            visibility: None,
+            subscriptions: Subscriptions::default(),
+            records_provenance: false,
+            strings: Interner::default(),
+            dispatch: Dispatch::default(),
+            dispatch_tables: DispatchTables::default(),
+            dispatch_cache: DispatchCache::default(),
+            division: Division::default(),
         }
     }
 
+    /// Keep, with each instance constructed from now on, the code that
+    /// constructed it; see `provenance`.
+    pub fn set_provenance(&mut self, records: bool) {
+        self.records_provenance = records;
+    }
+
+    pub fn records_provenance(&self) -> bool {
+        self.records_provenance
+    }
+
+    /// Choose how calls pick among overloads; see `Dispatch`.
+    pub fn set_dispatch(&mut self, dispatch: Dispatch) {
+        self.dispatch = dispatch;
+    }
+
+    pub fn dispatch(&self) -> Dispatch {
+        self.dispatch
+    }
+
+    /// Choose how `/` and `%` round negative `Int`s; see `Division`.
+    pub fn set_division(&mut self, division: Division) {
+        self.division = division;
+    }
+
+    pub fn division(&self) -> Division {
+        self.division
+    }
+
+    /// The dispatch table of `overloads`, the overloads of `name`, and
+    /// whether it was compiled before this call.
+    pub(crate) fn dispatch_table(&self, name: &str, overloads: &[FunctionDeclaration]) -> (Arc<DispatchTable>, bool) {
+        self.dispatch_tables.get(name, overloads)
+    }
+
+    pub(crate) fn dispatch_cache(&self) -> &DispatchCache {
+        &self.dispatch_cache
+    }
+
+    /// Drop the dispatch table and the cached dispatches of `name`, whose
+    /// overloads changed.
+    pub(crate) fn forget_dispatch(&mut self, name: &str) {
+        self.dispatch_tables.forget(name);
+        self.dispatch_cache.forget(name);
+    }
+
+    /// Where each instance equal to `value` was constructed, oldest first.
+    /// Instances constructed while provenance wasn't recorded are left out.
+    pub fn provenance(&self, value: &EvalValue) -> Vec<Arc<Provenance>> {
+        let EvalValue::Value { type_name, .. } = value else {
+            return Vec::new();
+        };
+        self.snapshot(type_name)
+            .iter()
+            .filter_map(|instance| instance.as_any().downcast_ref::<GenericValueObject>())
+            .filter(|instance| evaluator::instance_value(type_name, *instance, self) == *value)
+            .filter_map(|instance| instance.provenance.clone())
+            .collect()
+    }
+
+    pub fn limits(&self) -> &EvalLimits {
+        &self.limits
+    }
+
+    /// Bound what each evaluation may do; see `EvalLimits`.
+    pub fn set_limits(&mut self, limits: EvalLimits) {
+        self.limits = limits;
+    }
+
+    /// Start an evaluation on this thread, so that its limits count from
+    /// here. Evaluations on other threads count on their own.
+    pub fn begin_evaluation(&self) {
+        self.usage.begin();
+    }
+
+    pub(crate) fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    /// The token that stops evaluation with this registry when cancelled.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Counts of the work evaluation has done with this registry so far.
+    pub fn profile(&self) -> Profile {
+        self.counters.snapshot()
+    }
+
+    pub(crate) fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    /// Hide the instances `visible` returns false for from evaluation:
+    /// `T.all()`, `T.count()`, `all(t)`, aggregates and quantifiers only see
+    /// the others. The host's own lookups, such as `get_all_instances`,
+    /// still see them all. None shows every instance.
+    pub fn set_visibility(&mut self, visibility: Option<Visibility>) {
+        self.visibility = visibility;
+    }
+
+    pub fn has_visibility(&self) -> bool {
+        self.visibility.is_some()
+    }
+
+    /// Whether evaluation can see `instance`, of the value type `type_name`.
+    pub fn is_visible(&self, type_name: &str, instance: &EvalValue) -> bool {
+        self.visibility.as_ref().is_none_or(|visible| visible(type_name, instance))
+    }
+
+    /// Call `subscriber` with each change to the instances of the value type
+    /// `type_name` from now on: as each is constructed, and as each is
+    /// retracted, when the type is removed or declared again with a
+    /// validation the instance fails. The type need not be declared yet.
+    pub fn subscribe(&self, type_name: &str, subscriber: Subscriber) -> SubscriptionId {
+        self.subscriptions.add(type_name, subscriber)
+    }
+
+    /// Queue the changes to the instances of `type_name` on a stream, to be
+    /// taken or awaited; see `subscribe`.
+    pub fn changes(&self, type_name: &str) -> ChangeStream {
+        ChangeStream::new(|subscriber| self.subscribe(type_name, subscriber))
+    }
+
+    /// End a subscription. Returns false if it had already ended.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        self.subscriptions.remove(id)
+    }
+
+    /// Tell the subscribers of `type_name` that `instances` were retracted.
+    fn retracted(&self, type_name: &str, instances: &[Arc<dyn ValueObject>]) {
+        if instances.is_empty() || !self.subscriptions.is_watched(type_name) {
+            return;
+        }
+        for instance in instances {
+            let value = evaluator::instance_value(type_name, instance.as_ref(), self);
+            self.subscriptions.notify(type_name, &Change::Retracted(value));
+        }
+    }
+
+    pub fn script_environment(&self) -> &ScriptEnvironment {
+        &self.script_environment
+    }
+
+    pub fn set_script_environment(&mut self, environment: ScriptEnvironment) {
+        self.script_environment = environment;
+    }
+
+    /// The values bound by top-level `let`s, by name.
+    pub fn bindings(&self) -> &HashMap<String, EvalValue> {
+        &self.bindings
+    }
+
+    /// Bind `name` to `value` for the evaluations after this one, replacing
+    /// an earlier binding of the name.
+    pub fn bind(&mut self, name: &str, value: EvalValue) {
+        self.bindings.insert(name.to_string(), value);
+    }
+
     pub fn register(&mut self, name: String, constructor: ValueConstructor) {
+        // Instances kept from an earlier run must still be valid values of
+        // the type as it is now declared
+        let mut dropped = Vec::new();
+        self.instances.write(&name, false, |kept| {
+            kept.retain(|instance| {
+                let valid = still_valid(&constructor, instance.as_ref());
+                if !valid {
+                    dropped.push(Arc::clone(instance));
+                }
+                valid
+            })
+        });
+        // Described while the declaration they were made with is in place
+        self.retracted(&name, &dropped);
         self.constructors.insert(name, constructor);
+        if !dropped.is_empty() {
+            drop(dropped);
+            self.strings.prune();
+        }
+    }
+
+    /// Start with the instances constructed in `previous`, so that a program
+    /// can be run again without losing them. When a type is registered, its
+    /// instances that no longer pass its validation are dropped.
+    pub fn keep_instances(&mut self, previous: &ValueRegistry) {
+        for type_name in previous.instances.type_names() {
+            let kept = previous.instances.snapshot(&type_name);
+            for instance in kept.iter() {
+                if let Some(text) = shared_text(instance.as_ref()) {
+                    self.strings.adopt(text);
+                }
+            }
+            self.instances.write(&type_name, true, |instances| instances.extend(kept.iter().cloned()));
+        }
+    }
+
+    /// Run `f` against an empty instance store, then put back the instances
+    /// there were before. Whatever `f` constructs is discarded.
+    pub fn with_fresh_instances<T>(&mut self, f: impl FnOnce(&ValueRegistry) -> T) -> T {
+        let saved = std::mem::take(&mut self.instances);
+        // Nor does anyone hear of it
+        let subscriptions = std::mem::take(&mut self.subscriptions);
+        let result = f(self);
+        self.instances = saved;
+        self.subscriptions = subscriptions;
+        result
     }
 
     pub fn register_function(&mut self, func_decl: FunctionDeclaration) {
-        self.functions.entry(func_decl.name.clone())
-            .or_insert_with(Vec::new)
-            .push(func_decl);
+        self.forget_dispatch(&func_decl.name);
+        let overloads = self.functions.entry(func_decl.name.clone()).or_default();
+        // A function of the program replaces the prelude's with its signature
+        let signature = (func_decl.name.clone(), parameter_types(&func_decl));
+        if self.prelude.remove(&signature) {
+            overloads.retain(|existing| parameter_types(existing) != signature.1);
+        }
+        overloads.push(func_decl);
+    }
+
+    /// Register a function of the stdlib prelude, which a later function
+    /// with the same name and parameter types replaces.
+    pub fn register_prelude_function(&mut self, func_decl: FunctionDeclaration) {
+        self.register_function(func_decl.clone());
+        self.prelude.insert((func_decl.name.clone(), parameter_types(&func_decl)));
+    }
+
+    /// Register a function implemented in Rust. It's dispatched to like any
+    /// other overload of its name.
+    pub fn register_native(&mut self, native: NativeFunction) {
+        self.register_function(native.declaration().clone());
+        self.natives.entry(native.name().to_string()).or_default().push(native);
+    }
+
+    /// Add a method to a built-in type, replacing the one of the same name
+    /// and number of parameters.
+    pub fn register_builtin(&mut self, method: BuiltinMethod) {
+        self.builtins.define(method);
+    }
+
+    /// The methods of the built-in types.
+    pub fn builtins(&self) -> &BuiltinTable {
+        &self.builtins
+    }
+
+    /// The Rust implementation of `function`, if it has one.
+    pub fn native(&self, function: &FunctionDeclaration) -> Option<&NativeFunction> {
+        self.natives.get(&function.name)?.iter().find(|native| native.implements(function))
     }
 
     pub fn get_function(&self, name: &str) -> Option<&FunctionDeclaration> {
@@ -65,20 +518,10 @@ impl ValueRegistry {
         self.functions.get(name)
     }
     
+    /// Register a method as the function overload it is, so that calls
+    /// dispatch to it like any other.
     pub fn register_method(&mut self, method_decl: MethodDeclaration) {
-        // Convert method to function for unified storage
-        let func_decl = FunctionDeclaration {
-            name: method_decl.name.clone(),
-            parameters: method_decl.parameters.clone(),
-            return_type: method_decl.return_type,
-            body: method_decl.body,
-        };
-        self.register_function(func_decl);
-    }
-    
-    pub fn get_methods(&self, _name: &str) -> Option<&Vec<MethodDeclaration>> {
-        // For backward compatibility during transition
-        None
+        self.register_function(method_decl.into());
     }
 
     pub fn execute_function(&self, name: &str, args: Vec<Box<dyn Any + Send + Sync>>) -> Result<Box<dyn Any + Send + Sync>> {
@@ -107,7 +550,80 @@ impl ValueRegistry {
         })?;
 
         // Validate the input
-        (constructor.validator)(&*input)?;
+        (constructor.validator)(&*input).inspect_err(|_e| {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(value_type = type_name, error = %_e, "validation failed");
+        })?;
+
+        self.admit(type_name, input)
+    }
+
+    /// Construct a value of `type_name` from each of `inputs`, as
+    /// `construct` does, validating every input even when some fail. The
+    /// result has the instances of the inputs that passed, in input order,
+    /// and an error for each that didn't, with its index. Fails only if
+    /// there is no such type.
+    pub fn construct_bulk(
+        &self,
+        type_name: &str,
+        inputs: Vec<Box<dyn Any + Send + Sync>>,
+    ) -> Result<BulkConstruction<Arc<dyn ValueObject>>> {
+        self.construct_many(type_name, inputs, false)
+    }
+
+    /// `construct_bulk`, with the inputs validated on as many threads as
+    /// the machine runs at once. Instances are still recorded in input order.
+    pub fn construct_bulk_parallel(
+        &self,
+        type_name: &str,
+        inputs: Vec<Box<dyn Any + Send + Sync>>,
+    ) -> Result<BulkConstruction<Arc<dyn ValueObject>>> {
+        self.construct_many(type_name, inputs, true)
+    }
+
+    fn construct_many(
+        &self,
+        type_name: &str,
+        inputs: Vec<Box<dyn Any + Send + Sync>>,
+        parallel: bool,
+    ) -> Result<BulkConstruction<Arc<dyn ValueObject>>> {
+        let constructor = self.constructors.get(type_name).ok_or_else(|| {
+            Error::Validation(ValidationError {
+                message: format!("Unknown value type: {}", type_name),
+                value_type: type_name.to_string(),
+            })
+        })?;
+
+        let validate = |chunk: &[Box<dyn Any + Send + Sync>]| -> Vec<Result<()>> {
+            chunk.iter().map(|input| (constructor.validator)(&**input)).collect()
+        };
+        let threads = std::thread::available_parallelism().map_or(1, usize::from);
+        let verdicts = if parallel && threads > 1 && inputs.len() > 1 {
+            let chunk_size = inputs.len().div_ceil(threads);
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = inputs.chunks(chunk_size).map(|chunk| scope.spawn(|| validate(chunk))).collect();
+                handles
+                    .into_iter()
+                    .flat_map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                    .collect::<Vec<_>>()
+            })
+        } else {
+            validate(&inputs)
+        };
+
+        let mut bulk = BulkConstruction { instances: Vec::new(), errors: Vec::new() };
+        for (index, (input, verdict)) in inputs.into_iter().zip(verdicts).enumerate() {
+            match verdict.and_then(|()| self.admit(type_name, input)) {
+                Ok(instance) => bulk.instances.push(instance),
+                Err(error) => bulk.errors.push(BulkError { index, message: error.to_string() }),
+            }
+        }
+        Ok(bulk)
+    }
+
+    /// Record a validated input as a new instance of `type_name`.
+    fn admit(&self, type_name: &str, input: Box<dyn Any + Send + Sync>) -> Result<Arc<dyn ValueObject>> {
+        self.usage.check_instance(&self.limits)?;
 
         // Create the value object
         let value = self.create_value_object(type_name, input)?;
@@ -115,31 +631,110 @@ impl ValueRegistry {
 
         // Register the instance for Type-as-Relation
         self.register_instance(type_name, value_arc.clone());
+        if self.subscriptions.is_watched(type_name) {
+            let value = evaluator::instance_value(type_name, value_arc.as_ref(), self);
+            self.subscriptions.notify(type_name, &Change::Constructed(value));
+        }
 
         Ok(value_arc)
     }
 
     fn register_instance(&self, type_name: &str, instance: Arc<dyn ValueObject>) {
-        if let Ok(mut instances) = self.instances.write() {
-            instances.entry(type_name.to_string())
-                .or_insert_with(Vec::new)
-                .push(instance);
-        }
+        // Only this type's pool is locked while the instance is added
+        self.instances.write(type_name, true, |relation| {
+            relation.push(instance);
+            self.counters.instance_created();
+            self.usage.instance_created();
+            #[cfg(feature = "tracing")]
+            tracing::trace!(value_type = type_name, instances = relation.len(), "instance recorded");
+        });
     }
 
     // Type-as-Relation query methods
     pub fn get_all_instances(&self, type_name: &str) -> Vec<Arc<dyn ValueObject>> {
-        if let Ok(instances) = self.instances.read() {
-            if let Some(type_instances) = instances.get(type_name) {
-                // Simply return a clone of the Arc references
-                return type_instances.clone();
-            }
-        }
-        Vec::new()
+        self.snapshot(type_name).to_vec()
+    }
+
+    /// The instances of a type as they are now, oldest first, without
+    /// copying them. Queries read from a snapshot, so one that runs for a
+    /// while neither waits for constructions of the type nor holds them up,
+    /// and doesn't see the instances they add.
+    pub fn snapshot(&self, type_name: &str) -> Snapshot {
+        self.instances.snapshot(type_name)
     }
 
     pub fn count_instances(&self, type_name: &str) -> usize {
-        self.get_all_instances(type_name).len()
+        self.snapshot(type_name).len()
+    }
+
+    /// The last `n` instances of a type, oldest first.
+    pub fn recent_instances(&self, type_name: &str, n: usize) -> Vec<Arc<dyn ValueObject>> {
+        let all = self.snapshot(type_name);
+        all[all.len().saturating_sub(n)..].to_vec()
+    }
+
+    /// Remove the value type called `name`, with its instances, or every
+    /// overload of the function called `name`. Returns false if nothing had
+    /// that name.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let removed = if self.constructors.contains_key(name) {
+            self.instances.remove(name)
+        } else {
+            Vec::new()
+        };
+        self.retracted(name, &removed);
+        if !removed.is_empty() {
+            drop(removed);
+            self.strings.prune();
+        }
+        let value = self.constructors.remove(name).is_some();
+        self.forget_dispatch(name);
+        let functions = self.functions.remove(name).is_some();
+        self.natives.remove(name);
+        value || functions
+    }
+
+    /// What the registry holds, for keeping an eye on the instance store,
+    /// which grows with every value constructed.
+    pub fn stats(&self) -> RegistryStats {
+        let mut types: Vec<TypeStats> = self
+            .constructors
+            .keys()
+            .map(|name| TypeStats {
+                name: name.clone(),
+                instances: 0,
+                approximate_bytes: 0,
+            })
+            .collect();
+        for stats in &mut types {
+            let relation = self.snapshot(&stats.name);
+            stats.instances = relation.len();
+            stats.approximate_bytes = relation.capacity() * std::mem::size_of::<Arc<dyn ValueObject>>()
+                + relation.iter().map(|instance| approximate_size(&**instance)).sum::<usize>();
+        }
+        types.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let (interned_strings, interned_bytes) = self.strings.stats();
+        RegistryStats {
+            types,
+            interned_strings,
+            interned_bytes,
+            function_overloads: self.functions.values().map(Vec::len).sum(),
+            native_functions: self.natives.values().map(Vec::len).sum(),
+            dispatch_cache_entries: self.dispatch_cache.len(),
+            dispatch_tables: self.dispatch_tables.len(),
+        }
+    }
+
+    /// Declarations of every value type, in no particular order.
+    pub fn value_types(&self) -> impl Iterator<Item = &ValueDeclaration> {
+        self.constructors.values().map(|constructor| &constructor.declaration)
+    }
+
+    /// Every overload of every function, in no particular order. Overloads
+    /// of one function are in the order they were defined.
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionDeclaration> {
+        self.functions.values().flatten()
     }
 
     fn create_value_object(
@@ -147,24 +742,174 @@ impl ValueRegistry {
         type_name: &str,
         input: Box<dyn Any + Send + Sync>,
     ) -> Result<Box<dyn ValueObject>> {
+        // Equal strings share one copy
+        let input = match input.downcast::<String>() {
+            Ok(text) => Box::new(self.strings.intern(*text)),
+            Err(input) => input,
+        };
         // This would be expanded to create specific value object types
         // For now, we'll create a generic implementation
         Ok(Box::new(GenericValueObject {
             type_name: type_name.to_string(),
             data: input,
+            sensitive: evaluator::is_sensitive(type_name, self),
+            provenance: if self.records_provenance { provenance::current() } else { None },
         }))
     }
 }
 
+/// Bytes taken by an instance on the heap: the reference count, the object
+/// and what its parameter points to.
+fn approximate_size(instance: &dyn ValueObject) -> usize {
+    // Strong and weak counts
+    let header = 2 * std::mem::size_of::<usize>();
+    let Some(generic) = instance.as_any().downcast_ref::<GenericValueObject>() else {
+        return header + std::mem::size_of_val(instance);
+    };
+    // An interned string is counted once, in `RegistryStats::interned_bytes`
+    let data = match generic.data.downcast_ref::<String>() {
+        Some(s) => std::mem::size_of::<String>() + s.capacity(),
+        None => std::mem::size_of_val(&*generic.data),
+    };
+    header + std::mem::size_of::<GenericValueObject>() + generic.type_name.capacity() + data
+}
+
+/// What a `ValueRegistry` holds; see `ValueRegistry::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryStats {
+    /// Every value type, by name
+    pub types: Vec<TypeStats>,
+    /// Distinct strings the instances' string parameters share
+    pub interned_strings: usize,
+    /// The bytes of those strings, stored once however many instances
+    /// hold them
+    pub interned_bytes: usize,
+    /// Overloads of every function, including native ones
+    pub function_overloads: usize,
+    pub native_functions: usize,
+    /// Argument types resolved to an overload and kept for later calls by
+    /// `Dispatch::Scan`
+    pub dispatch_cache_entries: usize,
+    /// Functions whose dispatch tables have been compiled
+    pub dispatch_tables: usize,
+}
+
+impl RegistryStats {
+    pub fn instances(&self) -> usize {
+        self.types.iter().map(|t| t.instances).sum()
+    }
+
+    pub fn approximate_bytes(&self) -> usize {
+        self.types.iter().map(|t| t.approximate_bytes).sum::<usize>() + self.interned_bytes
+    }
+}
+
+/// The instances of one value type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeStats {
+    pub name: String,
+    pub instances: usize,
+    /// Heap memory the instances take, roughly
+    pub approximate_bytes: usize,
+}
+
+fn parameter_types(function: &FunctionDeclaration) -> Vec<Type> {
+    function.parameters.iter().map(|p| p.ty.clone()).collect()
+}
+
+/// Whether `instance`, constructed for an earlier declaration of a type,
+/// has the parameter type and passes the validation of `constructor`.
+fn still_valid(constructor: &ValueConstructor, instance: &dyn ValueObject) -> bool {
+    let Some(generic) = instance.as_any().downcast_ref::<GenericValueObject>() else {
+        return true;
+    };
+    // Validators take a String
+    let text = shared_text(instance).map(|text| text.to_string());
+    let data = match &text {
+        Some(text) => text as &(dyn Any + Send + Sync),
+        None => &*generic.data,
+    };
+    let same_type = match constructor.declaration.parameter.ty {
+        crate::types::Type::String => data.is::<String>(),
+        crate::types::Type::Int => data.is::<i64>(),
+        crate::types::Type::Bool => data.is::<bool>(),
+        ref ty @ (crate::types::Type::Date | crate::types::Type::Time | crate::types::Type::Duration) => {
+            data.downcast_ref::<Temporal>().is_some_and(|t| t.type_of() == *ty)
+        }
+        crate::types::Type::Decimal => data.is::<Decimal>(),
+        ref ty @ crate::types::Type::Value(_) => {
+            data.downcast_ref::<EvalValue>().is_some_and(|value| evaluator::matches_type(ty, value))
+        }
+        _ => true,
+    };
+    same_type && (constructor.validator)(data).is_ok()
+}
+
+/// The string an instance wraps, interned by its registry.
+fn shared_text(instance: &dyn ValueObject) -> Option<&Arc<str>> {
+    instance.as_any().downcast_ref::<GenericValueObject>()?.data.downcast_ref::<Arc<str>>()
+}
+
+/// The string `data` is, whether interned or not.
+pub(crate) fn text(data: &(dyn Any + Send + Sync)) -> Option<&str> {
+    match data.downcast_ref::<Arc<str>>() {
+        Some(text) => Some(text),
+        None => data.downcast_ref::<String>().map(String::as_str),
+    }
+}
+
+/// A constructor's input, which is a String, i64, bool, Temporal, Decimal
+/// or, for a parameter of another value type, a list or an option, an
+/// EvalValue, as the value it was made from. An instance holds a String
+/// interned, as an `Arc<str>`.
+fn parameter_value(data: &(dyn Any + Send + Sync)) -> Option<EvalValue> {
+    if let Some(s) = text(data) {
+        Some(EvalValue::String(s.to_string()))
+    } else if let Some(n) = data.downcast_ref::<i64>() {
+        Some(EvalValue::Integer(*n))
+    } else if let Some(b) = data.downcast_ref::<bool>() {
+        Some(EvalValue::Boolean(*b))
+    } else if let Some(t) = data.downcast_ref::<Temporal>() {
+        Some(EvalValue::Temporal(*t))
+    } else if let Some(d) = data.downcast_ref::<Decimal>() {
+        Some(EvalValue::Decimal(*d))
+    } else {
+        data.downcast_ref::<EvalValue>().cloned()
+    }
+}
+
 #[derive(Debug)]
 pub struct GenericValueObject {
     pub type_name: String,
     pub data: Box<dyn Any + Send + Sync>,
+    /// Whether the type is `sensitive`, so that `data` isn't shown
+    pub sensitive: bool,
+    /// The code that constructed it, if its registry records provenance
+    pub provenance: Option<Arc<Provenance>>,
 }
 
 impl Display for GenericValueObject {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}({:?})", self.type_name, self.data)
+        if self.sensitive {
+            return write!(f, "{}({})", self.type_name, evaluator::REDACTED);
+        }
+        // Constructors are given the parameter as a String, i64, bool,
+        // Temporal, Decimal or EvalValue
+        if let Some(s) = text(&*self.data) {
+            write!(f, "{}({:?})", self.type_name, s)
+        } else if let Some(n) = self.data.downcast_ref::<i64>() {
+            write!(f, "{}({})", self.type_name, n)
+        } else if let Some(b) = self.data.downcast_ref::<bool>() {
+            write!(f, "{}({})", self.type_name, b)
+        } else if let Some(t) = self.data.downcast_ref::<Temporal>() {
+            write!(f, "{}({})", self.type_name, t)
+        } else if let Some(d) = self.data.downcast_ref::<Decimal>() {
+            write!(f, "{}({})", self.type_name, d)
+        } else if let Some(value) = self.data.downcast_ref::<EvalValue>() {
+            write!(f, "{}({})", self.type_name, value)
+        } else {
+            write!(f, "{}({:?})", self.type_name, self.data)
+        }
     }
 }
 
@@ -192,23 +937,21 @@ impl ValueObject for GenericValueObject {
     }
 
     fn equals(&self, other: &dyn ValueObject) -> bool {
-        // First check if types match
-        if self.type_name() != other.type_name() {
+        let Some(other) = other.as_any().downcast_ref::<GenericValueObject>() else {
             return false;
-        }
-
-        // For now, we compare the debug representation
-        // In a real implementation, we'd have type-specific comparison
-        format!("{:?}", self.data) == format!("{:?}", other.as_any())
+        };
+        self.type_name == other.type_name && parameter_value(&*self.data) == parameter_value(&*other.data)
     }
 
     fn hash_value(&self) -> u64 {
-        use std::collections::hash_map::DefaultHasher;
-        let mut hasher = DefaultHasher::new();
-        self.type_name.hash(&mut hasher);
-        // Hash the debug representation for now
-        format!("{:?}", self.data).hash(&mut hasher);
-        hasher.finish()
+        stable_hash(|hasher| {
+            hasher.write_text(&self.type_name);
+            match parameter_value(&*self.data) {
+                Some(value) => stable_hash::write_value(hasher, &value),
+                // Not one of the parameter types Relic constructs values from
+                None => hasher.write_text(&format!("{:?}", self.data)),
+            }
+        })
     }
 }
 
@@ -282,9 +1025,9 @@ impl ValueObject for EmailAddress {
     }
 
     fn hash_value(&self) -> u64 {
-        use std::collections::hash_map::DefaultHasher;
-        let mut hasher = DefaultHasher::new();
-        self.hash(&mut hasher);
-        hasher.finish()
+        stable_hash(|hasher| {
+            hasher.write_text("EmailAddress");
+            hasher.write_text(&self.value);
+        })
     }
 }
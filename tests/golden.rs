@@ -0,0 +1,106 @@
+//! Golden-file conformance tests for the language.
+//!
+//! Each `tests/cases/NAME.relic` is run with `relic run`, and what it prints
+//! is compared with `tests/cases/NAME.out`: its standard output, then its
+//! standard error, then `exit status: N` if it failed. To add a case, write
+//! the program and run
+//!
+//!     RELIC_BLESS=1 cargo test --test golden
+//!
+//! to record its output, then check the `.out` file is what it should be.
+//! `cargo test --test golden -- NAME` runs only the cases whose names
+//! contain NAME.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+fn main() -> ExitCode {
+    let cases = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("cases");
+    let bless = std::env::var_os("RELIC_BLESS").is_some();
+    let filters: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with('-')).collect();
+
+    let mut programs: Vec<PathBuf> = fs::read_dir(&cases)
+        .expect("tests/cases exists")
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "relic"))
+        .collect();
+    programs.sort();
+
+    let mut failed = Vec::new();
+    let mut ran = 0;
+    for program in &programs {
+        let name = program.file_stem().unwrap().to_string_lossy().into_owned();
+        if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+            continue;
+        }
+        ran += 1;
+        let actual = run(&cases, program);
+        let golden = program.with_extension("out");
+        if bless {
+            fs::write(&golden, &actual).expect("golden file is writable");
+            println!("case {} ... blessed", name);
+            continue;
+        }
+        match fs::read_to_string(&golden) {
+            Ok(expected) if expected == actual => println!("case {} ... ok", name),
+            Ok(expected) => {
+                println!("case {} ... FAILED\n{}", name, diff(&expected, &actual));
+                failed.push(name);
+            }
+            Err(_) => {
+                println!("case {} ... FAILED (no {}; run with RELIC_BLESS=1 to record it)", name, golden.display());
+                failed.push(name);
+            }
+        }
+    }
+
+    println!("\ngolden result: {} passed, {} failed", ran - failed.len(), failed.len());
+    if failed.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        println!("failed cases: {}", failed.join(", "));
+        ExitCode::FAILURE
+    }
+}
+
+/// What running `program` prints, in the form of a golden file. Programs
+/// run from the cases directory, so paths in diagnostics are just the file
+/// name.
+fn run(cases: &Path, program: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_relic"))
+        .arg("run")
+        .arg(program.file_name().unwrap())
+        .current_dir(cases)
+        .env("NO_COLOR", "1")
+        .output()
+        .expect("relic runs");
+    let mut actual = String::from_utf8_lossy(&output.stdout).into_owned();
+    actual.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        let status = output.status.code().map_or("signal".to_string(), |code| code.to_string());
+        actual.push_str(&format!("exit status: {}\n", status));
+    }
+    actual
+}
+
+/// The lines that differ between `expected` and `actual`, marked `-` and
+/// `+`, with the line number of each pair.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut out = String::new();
+    for line in 0..expected.len().max(actual.len()) {
+        let (old, new) = (expected.get(line), actual.get(line));
+        if old != new {
+            out.push_str(&format!("  line {}:\n", line + 1));
+            if let Some(old) = old {
+                out.push_str(&format!("  - {}\n", old));
+            }
+            if let Some(new) = new {
+                out.push_str(&format!("  + {}\n", new));
+            }
+        }
+    }
+    out
+}